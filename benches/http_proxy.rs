@@ -0,0 +1,124 @@
+// 经进程内代理转发一个带常见浏览器头部的 GET，测量单请求耗时与每请求的堆分配次数：
+//   cargo bench --bench http_proxy
+// 配合 `make stress-http STRESS_SELFTEST=1` 观察高并发下的延迟分布
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+// 以 jemalloc/mimalloc 编译时库已经设置了全局分配器，不再计数
+#[cfg(not(any(feature = "jemalloc", feature = "mimalloc")))]
+mod counting {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    pub(crate) struct Counting;
+
+    pub(crate) static ALLOCS: AtomicU64 = AtomicU64::new(0);
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            ALLOCS.fetch_add(1, Ordering::Relaxed);
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            ALLOCS.fetch_add(1, Ordering::Relaxed);
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: Counting = Counting;
+}
+
+const LOOPBACK: &str = if cfg!(target_os = "linux") { "lo" } else { "lo0" };
+
+// 读完请求头后回一个固定的小应答并关闭
+fn spawn_origin() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        for s in listener.incoming() {
+            let Ok(mut s) = s else { continue };
+            let mut buf = [0u8; 8192];
+            let mut seen = Vec::new();
+            while !seen.windows(4).any(|w| w == b"\r\n\r\n") {
+                match s.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => seen.extend_from_slice(&buf[..n]),
+                }
+            }
+            let _ = s.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok");
+        }
+    });
+    port
+}
+
+fn spawn_proxy() -> u16 {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let args: Vec<String> = ["iface-proxy", "--iface", LOOPBACK, "--listen", &format!("127.0.0.1:{}", port)].iter().map(|s| s.to_string()).collect();
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let _ = rt.block_on(iface_proxy::run_cli(args));
+    });
+    for _ in 0..100 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() { return port; }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    panic!("proxy did not start on port {}", port);
+}
+
+fn request(proxy: u16, req: &[u8]) {
+    let mut s = TcpStream::connect(("127.0.0.1", proxy)).unwrap();
+    s.write_all(req).unwrap();
+    let mut resp = Vec::with_capacity(256);
+    s.read_to_end(&mut resp).unwrap();
+    assert!(resp.starts_with(b"HTTP/1.1 200"), "{:?}", String::from_utf8_lossy(&resp));
+}
+
+fn bench(c: &mut Criterion) {
+    let origin = spawn_origin();
+    let proxy = spawn_proxy();
+    let req = format!(
+        "GET http://127.0.0.1:{port}/assets/app.js?v=3 HTTP/1.1\r\n\
+         Host: 127.0.0.1:{port}\r\n\
+         User-Agent: Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0\r\n\
+         Accept: */*\r\n\
+         Accept-Language: en-US,en;q=0.5\r\n\
+         Accept-Encoding: identity\r\n\
+         Referer: http://127.0.0.1:{port}/\r\n\
+         Cookie: session=0123456789abcdef; theme=dark; consent=1\r\n\
+         Sec-Fetch-Dest: script\r\n\
+         Sec-Fetch-Mode: no-cors\r\n\
+         Sec-Fetch-Site: same-origin\r\n\
+         Cache-Control: no-cache\r\n\
+         Pragma: no-cache\r\n\
+         DNT: 1\r\n\
+         Proxy-Connection: keep-alive\r\n\
+         Connection: close\r\n\r\n",
+        port = origin
+    );
+
+    // 预热后统计整个进程（客户端、代理、源站）每请求的分配次数
+    for _ in 0..50 { request(proxy, req.as_bytes()); }
+    #[cfg(not(any(feature = "jemalloc", feature = "mimalloc")))]
+    {
+        use std::sync::atomic::Ordering;
+        let n = 500;
+        let before = counting::ALLOCS.load(Ordering::Relaxed);
+        for _ in 0..n { request(proxy, req.as_bytes()); }
+        let per = (counting::ALLOCS.load(Ordering::Relaxed) - before) as f64 / n as f64;
+        println!("heap allocations per proxied GET: {:.1}", per);
+    }
+
+    c.bench_function("http_proxy GET 15 headers", |b| b.iter(|| request(proxy, req.as_bytes())));
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
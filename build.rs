@@ -1,10 +1,46 @@
+use std::process::Command;
+
 fn main() {
     // Prefer explicit IFACE_PROXY_VERSION, then CI tag, then Cargo package version
     let version = std::env::var("IFACE_PROXY_VERSION")
         .or_else(|_| std::env::var("GITHUB_REF_NAME"))
         .unwrap_or_else(|_| env!("CARGO_PKG_VERSION").to_string());
 
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short=9", "HEAD"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    // SOURCE_DATE_EPOCH keeps reproducible builds stable
+    let secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+
     println!("cargo:rustc-env=IFACE_PROXY_VERSION={}", version);
+    println!("cargo:rustc-env=IFACE_PROXY_GIT_HASH={}", git_hash);
+    println!("cargo:rustc-env=IFACE_PROXY_BUILD_DATE={}", civil_date(secs));
 }
 
-
+// days since 1970-01-01 -> YYYY-MM-DD (Howard Hinnant's civil_from_days)
+fn civil_date(secs: u64) -> String {
+    let z = (secs / 86400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
@@ -0,0 +1,251 @@
+use anyhow::Result;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::logsink::Stream;
+use crate::util::{json_string, log_error, log_info, log_throttled};
+
+// --access-log：每个会话结束时追加一行 JSON（时间、会话 ID、协议、客户端、用户、目标、网卡、字节数、时长、是否出错）。
+// 日期变化后的第一次写入时把当前文件改名为 PATH.YYYY-MM-DD 并重新打开，旧文件在后台 gzip 成 .gz，
+// 再按 --access-log-max-days 与 --access-log-max-size 删除最旧的归档。目标按 --log-redact 处理
+pub(crate) struct AccessLogConfig {
+    pub(crate) path: String,
+    // 归档保留的天数，0 为不按天数删除
+    pub(crate) max_days: u32,
+    // 归档合计大小上限，0 为不限
+    pub(crate) max_size: u64,
+}
+
+type Date = (i32, u32, u32);
+
+struct Writer {
+    path: PathBuf,
+    file: Option<File>,
+    // 当前文件中记录所属的日期
+    date: Date,
+}
+
+static CONFIG: OnceLock<AccessLogConfig> = OnceLock::new();
+static WRITER: OnceLock<Mutex<Writer>> = OnceLock::new();
+
+fn open(path: &Path) -> std::io::Result<File> {
+    std::fs::OpenOptions::new().create(true).append(true).open(path)
+}
+
+// 已有的文件按最后修改时间定日期，重启后跨天的记录也会按时归档
+pub(crate) fn install(cfg: AccessLogConfig) -> Result<()> {
+    let path = PathBuf::from(&cfg.path);
+    let file = open(&path).map_err(|e| anyhow::anyhow!("open access log {}: {}", cfg.path, e))?;
+    let modified = file.metadata().and_then(|m| m.modified()).ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok());
+    let date = modified.map_or_else(crate::util::local_date, |d| crate::util::local_date_at(d.as_secs()));
+    let _ = WRITER.set(Mutex::new(Writer { path, file: Some(file), date }));
+    let _ = CONFIG.set(cfg);
+    // 上次运行留下的归档也按保留策略清理
+    if let Some(cfg) = CONFIG.get() { std::thread::spawn(move || prune(cfg, crate::util::local_date())); }
+    Ok(())
+}
+
+// 会话结束时由 session 调用
+pub(crate) struct Entry<'a> {
+    pub(crate) id: u64,
+    pub(crate) kind: &'a str,
+    pub(crate) protocol: &'a str,
+    pub(crate) peer: &'a str,
+    pub(crate) user: Option<&'a str>,
+    pub(crate) target: &'a str,
+    pub(crate) iface: &'a str,
+    pub(crate) up: u64,
+    pub(crate) down: u64,
+    pub(crate) duration: Duration,
+    pub(crate) failed: bool,
+}
+
+impl Entry<'_> {
+    fn to_json(&self, now: Duration) -> String {
+        let opt = |s: &str| if s.is_empty() { String::from("null") } else { json_string(s) };
+        format!(
+            "{{\"ts\":{}.{:03},\"id\":{},\"kind\":{},\"protocol\":{},\"peer\":{},\"user\":{},\"target\":{},\"iface\":{},\"up\":{},\"down\":{},\"duration_ms\":{},\"failed\":{}}}\n",
+            now.as_secs(),
+            now.subsec_millis(),
+            self.id,
+            json_string(self.kind),
+            json_string(self.protocol),
+            json_string(self.peer),
+            opt(self.user.unwrap_or("")),
+            opt(&crate::addr::redact_authority(self.target)),
+            opt(self.iface),
+            self.up,
+            self.down,
+            self.duration.as_millis(),
+            self.failed
+        )
+    }
+}
+
+// 写入文件，并送到 --access-log-sink（出错的会话为 warning，其余为 info）
+pub(crate) fn record(e: &Entry<'_>) {
+    let sink = crate::logsink::configured(Stream::Access);
+    let file = CONFIG.get().zip(WRITER.get());
+    if !sink && file.is_none() { return; }
+    let line = e.to_json(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default());
+    if sink && !crate::logsink::send(Stream::Access, if e.failed { crate::logsink::WARNING } else { crate::logsink::INFO }, line.trim_end()) {
+        print!("{}", line);
+    }
+    let Some((cfg, w)) = file else { return };
+    let today = crate::util::local_date();
+    let mut w = w.lock().unwrap_or_else(|e| e.into_inner());
+    if w.date != today {
+        if let Some(rotated) = w.rotate(today) { std::thread::spawn(move || archive(&rotated, cfg, today)); }
+    }
+    let Some(file) = w.file.as_mut() else { return };
+    if let Err(e) = file.write_all(line.as_bytes()) {
+        log_throttled(|| log_error(format!("access log: write to {} failed: {}", w.path.display(), e)));
+    }
+}
+
+impl Writer {
+    // 改名为 PATH.YYYY-MM-DD（已存在时加 -N）并重新打开，返回改名后的路径
+    fn rotate(&mut self, today: Date) -> Option<PathBuf> {
+        let (y, m, d) = self.date;
+        self.date = today;
+        let name = format!("{}.{:04}-{:02}-{:02}", self.path.display(), y, m, d);
+        let mut dest = PathBuf::from(&name);
+        let mut n = 1;
+        while dest.exists() || PathBuf::from(format!("{}.gz", dest.display())).exists() {
+            dest = PathBuf::from(format!("{}-{}", name, n));
+            n += 1;
+        }
+        self.file = None;
+        let rotated = match std::fs::rename(&self.path, &dest) {
+            Ok(()) => Some(dest),
+            Err(e) => {
+                log_error(format!("access log: rotate {} failed, keep appending: {}", self.path.display(), e));
+                None
+            }
+        };
+        match open(&self.path) {
+            Ok(f) => self.file = Some(f),
+            Err(e) => log_error(format!("access log: reopen {} failed: {}", self.path.display(), e)),
+        }
+        rotated
+    }
+}
+
+// 压缩刚改名的文件，再按保留策略删除旧归档
+fn archive(rotated: &Path, cfg: &AccessLogConfig, today: Date) {
+    match gzip(rotated) {
+        Ok(gz) => log_info(format!("access log: rotated to {}", gz.display())),
+        Err(e) => log_error(format!("access log: compress {} failed, keeping it uncompressed: {}", rotated.display(), e)),
+    }
+    prune(cfg, today);
+}
+
+fn gzip(path: &Path) -> Result<PathBuf> {
+    let gz = PathBuf::from(format!("{}.gz", path.display()));
+    let tmp = PathBuf::from(format!("{}.gz.tmp", path.display()));
+    let mut enc = flate2::write::GzEncoder::new(File::create(&tmp)?, flate2::Compression::default());
+    std::io::copy(&mut File::open(path)?, &mut enc)?;
+    enc.finish()?.sync_all()?;
+    std::fs::rename(&tmp, &gz)?;
+    std::fs::remove_file(path)?;
+    Ok(gz)
+}
+
+// 自 1970-01-01 起的天数
+fn days(y: i32, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y as i64 - 1 } else { y as i64 };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m as i64 + 9) % 12) + 2) / 5 + d as i64 - 1;
+    era * 146097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719468
+}
+
+// 归档名中的日期：PATH.YYYY-MM-DD[-N][.gz]
+fn archive_date(base: &str, name: &str) -> Option<Date> {
+    let rest = name.strip_prefix(base)?.strip_prefix('.')?;
+    let date = rest.get(..10)?;
+    let suffix = &rest[10..];
+    if !(suffix.is_empty() || suffix.starts_with('-') || suffix.starts_with(".gz")) || suffix.ends_with(".tmp") { return None; }
+    let mut it = date.split('-');
+    let (y, m, d) = (it.next()?.parse().ok()?, it.next()?.parse().ok()?, it.next()?.parse().ok()?);
+    Some((y, m, d))
+}
+
+fn prune(cfg: &AccessLogConfig, today: Date) {
+    let path = Path::new(&cfg.path);
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let Some(base) = path.file_name().and_then(|n| n.to_str()) else { return };
+    let Ok(rd) = std::fs::read_dir(dir) else { return };
+    let mut archives: Vec<(Date, String, u64)> = rd
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            let date = archive_date(base, &name)?;
+            Some((date, name, e.metadata().map(|m| m.len()).unwrap_or(0)))
+        })
+        .collect();
+    archives.sort();
+    let today = days(today.0, today.1, today.2);
+    let mut total: u64 = archives.iter().map(|a| a.2).sum();
+    let mut removed = 0;
+    for ((y, m, d), name, size) in &archives {
+        let expired = cfg.max_days > 0 && today - days(*y, *m, *d) > cfg.max_days as i64;
+        if !expired && (cfg.max_size == 0 || total <= cfg.max_size) { continue; }
+        match std::fs::remove_file(dir.join(name)) {
+            Ok(()) => {
+                total -= size;
+                removed += 1;
+            }
+            Err(e) => log_error(format!("access log: remove {} failed: {}", name, e)),
+        }
+    }
+    if removed > 0 { log_info(format!("access log: removed {} old archive(s)", removed)); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn json_lines_are_escaped() {
+        let e = Entry { id: 7, kind: "http", protocol: "connect", peer: "127.0.0.1:5000", user: Some("a\"b"), target: "example.com:443", iface: "", up: 1, down: 2, duration: Duration::from_millis(1500), failed: true };
+        let line = e.to_json(Duration::from_millis(1_700_000_000_123));
+        assert_eq!(
+            line,
+            "{\"ts\":1700000000.123,\"id\":7,\"kind\":\"http\",\"protocol\":\"connect\",\"peer\":\"127.0.0.1:5000\",\"user\":\"a\\\"b\",\"target\":\"example.com:443\",\"iface\":null,\"up\":1,\"down\":2,\"duration_ms\":1500,\"failed\":true}\n"
+        );
+    }
+
+    #[test]
+    fn rotates_compresses_and_prunes() {
+        let dir = std::env::temp_dir().join(format!("iface-proxy-access-log-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+        let mut w = Writer { path: path.clone(), file: Some(open(&path).unwrap()), date: (2026, 3, 9) };
+        w.file.as_mut().unwrap().write_all(b"{\"id\":1}\n").unwrap();
+        let rotated = w.rotate((2026, 3, 10)).unwrap();
+        assert_eq!(rotated, dir.join("access.log.2026-03-09"));
+        w.file.as_mut().unwrap().write_all(b"{\"id\":2}\n").unwrap();
+
+        // 更早的归档：一个超出天数，一个在天数内但会因总大小被删
+        std::fs::write(dir.join("access.log.2026-02-01.gz"), vec![0u8; 10]).unwrap();
+        std::fs::write(dir.join("access.log.2026-03-05.gz"), vec![0u8; 4000]).unwrap();
+        std::fs::write(dir.join("other.log.2020-01-01.gz"), b"x").unwrap();
+        let cfg = AccessLogConfig { path: path.display().to_string(), max_days: 30, max_size: 1000 };
+        archive(&rotated, &cfg, (2026, 3, 10));
+
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(File::open(dir.join("access.log.2026-03-09.gz")).unwrap()).read_to_string(&mut text).unwrap();
+        assert_eq!(text, "{\"id\":1}\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"id\":2}\n");
+        let mut left: Vec<String> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap()).collect();
+        left.sort();
+        assert_eq!(left, ["access.log", "access.log.2026-03-09.gz", "other.log.2020-01-01.gz"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
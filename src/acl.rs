@@ -0,0 +1,60 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::util::{log_info, log_throttled};
+
+// 访问控制规则命中的统一入口：来源白名单 (allow= / --allow-client) 与目标黑名单 (--deny-dest)。
+// --acl-audit 时只记录日志和计数，不拒绝连接，用于先拿真实流量验证规则
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AclRule {
+    ClientAllow,
+    DestDeny,
+}
+
+impl AclRule {
+    const ALL: [AclRule; 2] = [AclRule::ClientAllow, AclRule::DestDeny];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::ClientAllow => "client_allow",
+            Self::DestDeny => "dest_deny",
+        }
+    }
+}
+
+static AUDIT: AtomicBool = AtomicBool::new(false);
+static MATCHES: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+
+pub(crate) fn set_audit(on: bool) {
+    AUDIT.store(on, Ordering::Relaxed);
+}
+
+pub(crate) fn audit() -> bool {
+    AUDIT.load(Ordering::Relaxed)
+}
+
+// 记录一次命中（来源不在白名单 / 目标在黑名单），返回是否需要拒绝
+pub(crate) fn hit(rule: AclRule, addr: IpAddr) -> bool {
+    MATCHES[rule as usize].fetch_add(1, Ordering::Relaxed);
+    if !audit() { return true; }
+    match rule {
+        AclRule::ClientAllow => log_throttled(|| log_info(format!("acl audit: would deny connection from {} (not in allow list)", addr))),
+        AclRule::DestDeny => log_throttled(|| log_info(format!("acl audit: would deny destination {} (in deny list)", crate::addr::redact_host(&addr.to_string())))),
+    }
+    false
+}
+
+// Prometheus 文本格式
+pub(crate) fn render_metrics() -> String {
+    let mut out = String::from("# HELP iface_proxy_acl_matches_total Connections matching an ACL rule (denied, or only logged in audit mode).\n");
+    out.push_str("# TYPE iface_proxy_acl_matches_total counter\n");
+    for rule in AclRule::ALL {
+        out.push_str(&format!(
+            "iface_proxy_acl_matches_total{{rule=\"{}\",enforced=\"{}\"}} {}\n",
+            rule.name(),
+            !audit(),
+            MATCHES[rule as usize].load(Ordering::Relaxed)
+        ));
+    }
+    out
+}
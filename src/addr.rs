@@ -0,0 +1,301 @@
+use anyhow::Result;
+use std::borrow::Cow;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::OnceLock;
+use tokio::io::AsyncRead;
+
+use crate::error::ProxyError;
+
+// 代理请求的目标地址，HTTP CONNECT/Host 的 authority 与 SOCKS5、Shadowsocks 的 ATYP 编码共用：
+// 以域名形式给出的 IP 字面量（含带方括号的 IPv6，如 SOCKS5 ATYP=0x03 的 "[::1]"）一律按 IP 处理，
+// 日志、规则与出站连接看到的都是同一种写法
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum TargetAddr {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+
+const ATYP_V4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_V6: u8 = 0x04;
+
+impl TargetAddr {
+    // 域名不做检查，来自协议解析的用 domain()
+    pub(crate) fn new(host: &str, port: u16) -> Self {
+        match host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host).parse::<IpAddr>() {
+            Ok(ip) => TargetAddr::Ip(SocketAddr::new(ip, port)),
+            Err(_) => TargetAddr::Domain(host.to_string(), port),
+        }
+    }
+
+    // 客户端给出的域名：非空、UTF-8、不含控制字符与空白
+    pub(crate) fn domain(name: &[u8], port: u16) -> Result<Self, String> {
+        let name = std::str::from_utf8(name).map_err(|_| String::from("target host is not valid UTF-8"))?;
+        if name.is_empty() { return Err(String::from("empty target host")); }
+        if name.bytes().any(|b| b.is_ascii_control() || b == b' ') { return Err(format!("invalid target host {:?}", name)); }
+        Ok(Self::new(name, port))
+    }
+
+    // HOST[:PORT] 或 [IPv6][:PORT]，见 uri::parse_authority
+    pub(crate) fn parse_authority(s: &str, default_port: u16) -> Result<Self, String> {
+        crate::uri::parse_authority(s, default_port).map(|(host, port)| Self::new(&host, port))
+    }
+
+    // SOCKS 风格的 ATYP(1) ADDR PORT(2)，返回地址与占用的字节数
+    pub(crate) fn decode(buf: &[u8]) -> Result<(Self, usize)> {
+        let short = || anyhow::anyhow!("truncated address");
+        let atyp = *buf.first().ok_or_else(short)?;
+        let len = match atyp {
+            ATYP_V4 => 4,
+            ATYP_V6 => 16,
+            ATYP_DOMAIN => 1 + *buf.get(1).ok_or_else(short)? as usize,
+            _ => anyhow::bail!("unsupported ATYP 0x{:02x}", atyp),
+        };
+        let b = buf.get(1..1 + len + 2).ok_or_else(short)?;
+        let port = u16::from_be_bytes([b[len], b[len + 1]]);
+        let addr = match atyp {
+            ATYP_V4 => TargetAddr::Ip(SocketAddr::new(Ipv4Addr::new(b[0], b[1], b[2], b[3]).into(), port)),
+            ATYP_V6 => TargetAddr::Ip(SocketAddr::new(Ipv6Addr::from(<[u8; 16]>::try_from(&b[..16])?).into(), port)),
+            _ => Self::domain(&b[1..len], port).map_err(|e| anyhow::anyhow!(e))?,
+        };
+        Ok((addr, 1 + len + 2))
+    }
+
+    // SOCKS5 请求中的地址：按 ATYP 读出所需的字节再 decode
+    pub(crate) async fn read<S: AsyncRead + Unpin>(stream: &mut S, atyp: u8, read_timeout_ms: u64) -> Result<Self> {
+        let read = crate::socks5::read_exact_into;
+        let mut buf = vec![atyp];
+        let len = match atyp {
+            ATYP_V4 => 4,
+            ATYP_V6 => 16,
+            ATYP_DOMAIN => {
+                let mut l = [0u8; 1];
+                read(stream, &mut l, read_timeout_ms).await?;
+                buf.push(l[0]);
+                l[0] as usize
+            }
+            _ => anyhow::bail!(ProxyError::Handshake(format!("unsupported ATYP 0x{:02x}", atyp))),
+        };
+        let start = buf.len();
+        buf.resize(start + len + 2, 0);
+        read(stream, &mut buf[start..], read_timeout_ms).await?;
+        Self::decode(&buf).map(|(a, _)| a).map_err(|e| ProxyError::Handshake(e.to_string()).into())
+    }
+
+    pub(crate) fn encode(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(22);
+        match self {
+            TargetAddr::Ip(SocketAddr::V4(a)) => {
+                out.push(ATYP_V4);
+                out.extend_from_slice(&a.ip().octets());
+            }
+            TargetAddr::Ip(SocketAddr::V6(a)) => {
+                out.push(ATYP_V6);
+                out.extend_from_slice(&a.ip().octets());
+            }
+            TargetAddr::Domain(host, _) => {
+                if host.is_empty() || host.len() > 255 { anyhow::bail!("invalid target host length {}", host.len()); }
+                out.push(ATYP_DOMAIN);
+                out.push(host.len() as u8);
+                out.extend_from_slice(host.as_bytes());
+            }
+        }
+        out.extend_from_slice(&self.port().to_be_bytes());
+        Ok(out)
+    }
+
+    // 交给 Dialer 的主机名，IPv6 不带方括号
+    pub(crate) fn host(&self) -> String {
+        match self {
+            TargetAddr::Ip(a) => a.ip().to_string(),
+            TargetAddr::Domain(h, _) => h.clone(),
+        }
+    }
+
+    pub(crate) fn port(&self) -> u16 {
+        match self {
+            TargetAddr::Ip(a) => a.port(),
+            TargetAddr::Domain(_, p) => *p,
+        }
+    }
+
+    // 写日志用，按 --log-redact 处理主机部分
+    pub(crate) fn redacted(&self) -> String {
+        redact(&self.host(), self.port())
+    }
+}
+
+// --log-redact：日志（含会话错误）中的目标主机名与 IP 改为散列或截断，HTTP 路径一并隐去，
+// 供须保留日志但不能留下访问记录的场合。客户端地址、管理接口（/sessions、/hosts）以及
+// --capture-dir、--record-dir、--dump-http 写出的文件不受影响
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Redact {
+    Off,
+    // 按进程启动时随机生成的密钥散列：同一次运行中同一目标的写法相同，可以关联，重启后不同
+    Hash,
+    // 只保留注册域名（最后两段）、IPv4 的 /24 或 IPv6 的 /48
+    Truncate,
+}
+
+impl Redact {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "hash" => Ok(Self::Hash),
+            "truncate" => Ok(Self::Truncate),
+            _ => anyhow::bail!("invalid log redaction {:?} (expected off, hash or truncate)", s),
+        }
+    }
+}
+
+static REDACT: AtomicU8 = AtomicU8::new(0);
+
+pub(crate) fn set_redact(mode: Redact) {
+    REDACT.store(mode as u8, Ordering::Relaxed);
+}
+
+fn redact_mode() -> Redact {
+    match REDACT.load(Ordering::Relaxed) {
+        1 => Redact::Hash,
+        2 => Redact::Truncate,
+        _ => Redact::Off,
+    }
+}
+
+fn hash_host(host: &str) -> String {
+    static KEY: OnceLock<std::collections::hash_map::RandomState> = OnceLock::new();
+    format!("h-{:012x}", KEY.get_or_init(Default::default).hash_one(host.to_ascii_lowercase()) >> 16)
+}
+
+fn truncate_host(host: &str) -> String {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(v4)) => {
+            let o = v4.octets();
+            format!("{}.{}.{}.0/24", o[0], o[1], o[2])
+        }
+        Ok(IpAddr::V6(v6)) => {
+            let s = v6.segments();
+            format!("{}/48", Ipv6Addr::new(s[0], s[1], s[2], 0, 0, 0, 0, 0))
+        }
+        Err(_) => {
+            let host = host.trim_end_matches('.');
+            let labels: Vec<&str> = host.rsplitn(3, '.').collect();
+            if labels.len() < 3 { host.to_string() } else { format!("*.{}.{}", labels[1], labels[0]) }
+        }
+    }
+}
+
+// 主机名或 IP（IPv6 可带方括号）
+pub(crate) fn redact_host(host: &str) -> Cow<'_, str> {
+    let bare = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+    match redact_mode() {
+        Redact::Off => Cow::Borrowed(host),
+        Redact::Hash => Cow::Owned(hash_host(bare)),
+        Redact::Truncate => Cow::Owned(truncate_host(bare)),
+    }
+}
+
+// HOST:PORT，IPv6 与截断后的 IPv6 前缀带方括号
+pub(crate) fn redact(host: &str, port: u16) -> String {
+    let h = redact_host(host);
+    if h.contains(':') && !h.starts_with('[') { format!("[{}]:{}", h, port) } else { format!("{}:{}", h, port) }
+}
+
+pub(crate) fn redact_socket(addr: SocketAddr) -> String {
+    redact(&addr.ip().to_string(), addr.port())
+}
+
+// HOST[:PORT] 形式的 authority，端口省略时保持省略
+pub(crate) fn redact_authority(authority: &str) -> String {
+    if redact_mode() == Redact::Off { return authority.to_string(); }
+    match crate::uri::parse_authority(authority, 0) {
+        Ok((host, 0)) => redact_host(&host).into_owned(),
+        Ok((host, port)) => redact(&host, port),
+        Err(_) => redact_host(authority).into_owned(),
+    }
+}
+
+// 请求路径与查询串可能同样敏感，启用时一律隐去
+pub(crate) fn redact_path(path: &str) -> &str {
+    if redact_mode() == Redact::Off { path } else { "/..." }
+}
+
+// HOST:PORT，IPv6 带方括号
+impl std::fmt::Display for TargetAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TargetAddr::Ip(a) => write!(f, "{}", a),
+            TargetAddr::Domain(h, p) => write!(f, "{}:{}", h, p),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_target_from_every_protocol() {
+        let v6 = TargetAddr::Ip("[2001:db8::1]:443".parse().unwrap());
+        // HTTP CONNECT、SOCKS5 ATYP=0x04、ATYP=0x03 带或不带方括号的字面量
+        let mut atyp4 = vec![ATYP_V6];
+        atyp4.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        atyp4.extend_from_slice(&443u16.to_be_bytes());
+        let domain = |h: &str| [&[ATYP_DOMAIN, h.len() as u8][..], h.as_bytes(), &443u16.to_be_bytes()].concat();
+        assert_eq!(TargetAddr::parse_authority("[2001:db8::1]:443", 80).unwrap(), v6);
+        assert_eq!(TargetAddr::decode(&atyp4).unwrap(), (v6.clone(), 19));
+        assert_eq!(TargetAddr::decode(&domain("[2001:db8::1]")).unwrap().0, v6);
+        assert_eq!(TargetAddr::decode(&domain("2001:db8::1")).unwrap().0, v6);
+        assert_eq!(TargetAddr::decode(&domain("10.0.0.1")).unwrap().0, TargetAddr::Ip("10.0.0.1:443".parse().unwrap()));
+        assert_eq!((v6.host(), v6.to_string()), (String::from("2001:db8::1"), String::from("[2001:db8::1]:443")));
+        // 字面量按 IP 编码，往返不变
+        assert_eq!(TargetAddr::new("[2001:db8::1]", 443).encode().unwrap(), atyp4);
+        for t in [v6, TargetAddr::new("example.com", 80), TargetAddr::new("192.0.2.1", 8080)] {
+            let buf = t.encode().unwrap();
+            assert_eq!(TargetAddr::decode(&buf).unwrap(), (t, buf.len()));
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        assert!(TargetAddr::decode(&[ATYP_V6, 0, 0]).is_err());
+        assert!(TargetAddr::decode(&[ATYP_DOMAIN, 0, 0, 80]).is_err());
+        assert!(TargetAddr::decode(&[ATYP_DOMAIN, 3, b'a', b'\n', b'b', 0, 80]).is_err());
+        assert!(TargetAddr::decode(&[ATYP_DOMAIN, 2, 0xff, 0xfe, 0, 80]).is_err());
+        assert!(TargetAddr::decode(&[0x02, 0, 0, 0, 0, 0, 80]).is_err());
+        assert!(TargetAddr::parse_authority("2001:db8::1:443", 80).is_err());
+        assert!(TargetAddr::new(&"a".repeat(256), 80).encode().is_err());
+    }
+
+    #[test]
+    fn redaction_modes() {
+        assert_eq!(truncate_host("api.eu.example.com"), "*.example.com");
+        assert_eq!(truncate_host("example.com."), "example.com");
+        assert_eq!(truncate_host("192.0.2.77"), "192.0.2.0/24");
+        assert_eq!(truncate_host("2001:db8:1:2::5"), "2001:db8:1::/48");
+        let h = hash_host("Example.COM");
+        assert!(h.starts_with("h-") && h.len() == 14, "{}", h);
+        assert_eq!(h, hash_host("example.com"));
+        assert_ne!(h, hash_host("example.org"));
+        assert!(Redact::parse("hash").is_ok() && Redact::parse("md5").is_err());
+        // 未开启时原样输出（不改全局设置，以免影响并行的其他测试）
+        assert_eq!((redact("[::1]", 443), redact("::1", 443)), (String::from("[::1]:443"), String::from("[::1]:443")));
+        assert_eq!(redact_authority("example.com:8080"), "example.com:8080");
+    }
+
+    #[tokio::test]
+    async fn reads_socks5_request_addresses() {
+        let (mut a, mut b) = tokio::io::duplex(64);
+        let mut buf = TargetAddr::new("::1", 8080).encode().unwrap();
+        buf.extend(TargetAddr::new("example.com", 443).encode().unwrap());
+        tokio::io::AsyncWriteExt::write_all(&mut a, &buf).await.unwrap();
+        let mut atyp = [0u8; 1];
+        crate::socks5::read_exact_into(&mut b, &mut atyp, 1000).await.unwrap();
+        assert_eq!(TargetAddr::read(&mut b, atyp[0], 1000).await.unwrap().to_string(), "[::1]:8080");
+        crate::socks5::read_exact_into(&mut b, &mut atyp, 1000).await.unwrap();
+        assert_eq!(TargetAddr::read(&mut b, atyp[0], 1000).await.unwrap(), TargetAddr::Domain(String::from("example.com"), 443));
+        assert!(TargetAddr::read(&mut b, 0x02, 1000).await.is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{timeout, Duration};
+
+use crate::listener::{AcceptBackoff, Accepted, BoundListener, ListenerSettings};
+use crate::util::{log_throttled, log_info, log_error};
+
+const ADMIN_READ_TIMEOUT_MS: u64 = 5000;
+
+async fn read_request_head<S: AsyncRead + Unpin>(stream: &mut S) -> Result<String> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut tmp = [0u8; 1024];
+    loop {
+        let n = stream.read(&mut tmp).await?;
+        if n == 0 { anyhow::bail!("admin client closed before headers"); }
+        buf.extend_from_slice(&tmp[..n]);
+        if buf.windows(4).any(|w| w == b"\r\n\r\n") { break; }
+        if buf.len() > 8 * 1024 { anyhow::bail!("admin request too large"); }
+    }
+    Ok(String::from_utf8_lossy(&buf).to_string())
+}
+
+fn query_param<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+    query.split('&').find_map(|kv| kv.split_once('=').filter(|(k, _)| *k == key).map(|(_, v)| v))
+}
+
+// unix: 请求是否来自 Unix socket 上的管理监听（访问受文件权限约束）
+fn route(method: &str, path: &str, unix: bool) -> (&'static str, String) {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    // 写操作要求 POST，以免被浏览器预取等误触发
+    if path == "/users/reset" {
+        if method != "POST" { return ("405 Method Not Allowed", String::from("use POST\n")); }
+        let n = crate::quota::reset(query_param(query, "user"));
+        return ("200 OK", format!("reset usage of {} user(s)\n", n));
+    }
+    if path == "/rules/reload" {
+        if method != "POST" { return ("405 Method Not Allowed", String::from("use POST\n")); }
+        return match crate::rules::reload() {
+            Ok(summary) => ("200 OK", format!("reloaded: {}\n", summary)),
+            Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+        };
+    }
+    if path == "/bans/unban" {
+        if method != "POST" { return ("405 Method Not Allowed", String::from("use POST\n")); }
+        let ip = match query_param(query, "ip").map(|v| v.parse::<std::net::IpAddr>()) {
+            Some(Ok(ip)) => Some(ip),
+            Some(Err(_)) => return ("400 Bad Request", String::from("invalid ip\n")),
+            None => None,
+        };
+        let n = crate::ban::unban(ip);
+        log_info(format!("admin: lifted {} ban(s){}", n, ip.map(|ip| format!(" for {}", ip)).unwrap_or_default()));
+        return ("200 OK", format!("unbanned {} address(es)\n", n));
+    }
+    if let Some(action) = path.strip_prefix("/listeners/").filter(|a| *a == "pause" || *a == "resume") {
+        if method != "POST" { return ("405 Method Not Allowed", String::from("use POST\n")); }
+        let Some(which) = query_param(query, "listener") else { return ("400 Bad Request", String::from("listener= is required (KIND=ADDR, ADDR or all)\n")) };
+        let which = crate::uri::percent_decode(which).unwrap_or_else(|_| which.to_string());
+        return match crate::listener::set_paused(&which, action == "pause") {
+            Ok(changed) => {
+                if !changed.is_empty() { log_info(format!("admin: {}d {}", action, changed.join(", "))); }
+                ("200 OK", format!("{}d {} listener(s)\n", action, changed.len()))
+            }
+            Err(e) => ("404 Not Found", format!("{}\n", e)),
+        };
+    }
+    if path == "/sessions/kill" {
+        if method != "POST" { return ("405 Method Not Allowed", String::from("use POST\n")); }
+        let Some(id) = query_param(query, "id").and_then(|v| v.parse::<u64>().ok()) else { return ("400 Bad Request", String::from("id= is required\n")) };
+        if !crate::session::kill(id) { return ("404 Not Found", format!("no session {}\n", id)); }
+        log_info(format!("admin: killed session #{}", id));
+        return ("200 OK", format!("killed session {}\n", id));
+    }
+    if let Some(action) = path.strip_prefix("/tokens").filter(|_| method == "POST") {
+        // 签发的令牌即代理凭据，TCP 上的管理接口没有认证，只允许经 Unix socket 操作
+        if !unix { return ("403 Forbidden", String::from("token endpoints require a unix-socket admin listener\n")); }
+        return token_action(action, query);
+    }
+    if method != "GET" {
+        return ("405 Method Not Allowed", String::from("only GET is supported\n"));
+    }
+    match path {
+        "/" => ("200 OK", String::from("endpoints:\n  /version  version and build info\n  /metrics  Prometheus metrics\n  /hosts    per-destination traffic (?top=N)\n  /users    per-user traffic and quota usage\n  /users/reset  POST, reset usage (?user=NAME, default all)\n  /sessions active sessions (id, peer, target, idle time, bytes)\n  /sessions/kill  POST ?id=ID, close a session\n  /listeners  listeners and whether they are accepting\n  /listeners/pause  POST ?listener=KIND=ADDR|ADDR|all, stop accepting (existing sessions continue)\n  /listeners/resume  POST ?listener=KIND=ADDR|ADDR|all\n  /probes   per-interface connect latency and loss\n  /egress-groups  egress group members, active and total connections\n  /rules    routing rules in evaluation order\n  /rules/reload  POST, re-read --rules-file and rule sets\n  /tokens   bearer tokens (id, user, expiry); POST ?user=NAME[&ttl=SECS] to issue (unix socket only)\n  /tokens/revoke  POST ?id=ID\n  /tokens/rotate  POST ?id=ID[&grace=SECS], issue a replacement and expire the old token\n  /bans     source IPs with recent auth failures and active bans\n  /bans/unban  POST, lift bans (?ip=IP, default all)\n  /heap     allocator heap statistics\n")),
+        "/version" => ("200 OK", format!(
+            "version: {}\ngit: {}\nbuilt: {}\n",
+            crate::build_info::VERSION,
+            crate::build_info::GIT_HASH,
+            crate::build_info::BUILD_DATE
+        )),
+        "/metrics" => ("200 OK", crate::metrics::render()),
+        "/hosts" => ("200 OK", crate::stats::render_top(query_param(query, "top").and_then(|v| v.parse().ok()).unwrap_or(usize::MAX))),
+        "/users" => ("200 OK", crate::quota::render()),
+        "/sessions" => ("200 OK", crate::session::render()),
+        "/listeners" => ("200 OK", crate::listener::render_listeners()),
+        "/probes" => ("200 OK", crate::probe::render()),
+        "/egress-groups" => ("200 OK", crate::balance::render()),
+        "/tokens" => ("200 OK", crate::token::render()),
+        "/bans" => ("200 OK", crate::ban::render()),
+        "/rules" => match crate::rules::installed() {
+            Some(rules) => ("200 OK", rules.render()),
+            None => ("404 Not Found", String::from("no rules configured\n")),
+        },
+        "/heap" => match crate::alloc::heap_stats() {
+            Ok(body) => ("200 OK", body),
+            Err(e) => ("501 Not Implemented", format!("{}\n", e)),
+        },
+        _ => ("404 Not Found", String::from("not found\n")),
+    }
+}
+
+// POST /tokens、/tokens/revoke、/tokens/rotate；新令牌的明文只在这里返回一次
+fn token_action(action: &str, query: &str) -> (&'static str, String) {
+    let param = |key| query_param(query, key).map(|v| crate::uri::percent_decode(v).unwrap_or_else(|_| v.to_string()));
+    let secs = |key, default| match param(key) {
+        Some(v) => v.parse::<u64>().map_err(|_| format!("invalid {} {:?}\n", key, v)),
+        None => Ok(default),
+    };
+    let issued = |res: Result<crate::token::Issued>| match res {
+        Ok(t) => {
+            log_info(format!("admin: issued token {} for {} (expires in {}s)", t.id, t.user, t.expires.saturating_sub(crate::token::now())));
+            ("200 OK", format!("token: {}\nid: {}\nuser: {}\nexpires: {}\n", t.token, t.id, t.user, t.expires))
+        }
+        Err(e) => ("400 Bad Request", format!("{}\n", e)),
+    };
+    match (action, param("user"), param("id")) {
+        ("", Some(user), _) => match secs("ttl", crate::token::DEFAULT_TTL_SECS) {
+            Ok(ttl) => issued(crate::token::issue(&user, ttl)),
+            Err(e) => ("400 Bad Request", e),
+        },
+        ("/revoke", _, Some(id)) => match crate::token::revoke(&id) {
+            Ok(true) => {
+                log_info(format!("admin: revoked token {}", id));
+                ("200 OK", format!("revoked {}\n", id))
+            }
+            Ok(false) => ("404 Not Found", format!("no token {:?}\n", id)),
+            Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+        },
+        ("/rotate", _, Some(id)) => match secs("grace", crate::token::DEFAULT_GRACE_SECS) {
+            Ok(grace) => issued(crate::token::rotate(&id, grace)),
+            Err(e) => ("400 Bad Request", e),
+        },
+        ("", None, _) => ("400 Bad Request", String::from("user= is required\n")),
+        ("/revoke" | "/rotate", _, None) => ("400 Bad Request", String::from("id= is required\n")),
+        _ => ("404 Not Found", String::from("not found\n")),
+    }
+}
+
+async fn handle_admin<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, unix: bool) -> Result<()> {
+    let head = timeout(Duration::from_millis(ADMIN_READ_TIMEOUT_MS), read_request_head(&mut stream)).await??;
+    let mut parts = head.split("\r\n").next().unwrap_or("").split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("/");
+    let (status, body) = route(method, path, unix);
+    let resp = format!(
+        "HTTP/1.1 {}\r\nServer: {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        crate::build_info::AGENT,
+        body.len(),
+        body
+    );
+    stream.write_all(resp.as_bytes()).await?;
+    Ok(())
+}
+
+fn spawn_admin<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(stream: S, unix: bool) {
+    tokio::spawn(async move {
+        if let Err(e) = handle_admin(stream, unix).await {
+            log_error(format!("admin handler error: {}", e));
+        }
+    });
+}
+
+pub async fn run_admin(listener: BoundListener, s: Arc<ListenerSettings>) -> Result<()> {
+    let listen = listener.local_desc();
+    log_info(format!("Admin API listening on {}", listen));
+    let mut backoff = AcceptBackoff::new();
+    loop {
+        let accepted = backoff.accept(&listen, || listener.accept()).await;
+        match accepted {
+            Accepted::Tcp(stream, peer_addr) => {
+                if !s.allows(peer_addr.ip()) {
+                    log_throttled(|| log_info(format!("denied connection from {} to {} (not in allow list)", peer_addr, listen)));
+                    continue;
+                }
+                spawn_admin(stream, false);
+            }
+            Accepted::Unix(stream) => spawn_admin(stream, true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_writes_require_unix_listener() {
+        let (status, _) = route("POST", "/tokens?user=alice", false);
+        assert_eq!(status, "403 Forbidden");
+        let (status, _) = route("POST", "/tokens/revoke?id=00", false);
+        assert_eq!(status, "403 Forbidden");
+        // 只读列表不受影响
+        assert_eq!(route("GET", "/tokens", false).0, "200 OK");
+    }
+}
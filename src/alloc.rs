@@ -0,0 +1,46 @@
+use anyhow::Result;
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+pub(crate) fn allocator_name() -> &'static str {
+    if cfg!(feature = "jemalloc") {
+        "jemalloc"
+    } else if cfg!(feature = "mimalloc") {
+        "mimalloc"
+    } else {
+        "system"
+    }
+}
+
+pub(crate) fn alloc_stats_enabled() -> bool {
+    cfg!(feature = "alloc-stats")
+}
+
+// jemalloc 全局计数的快照（字节）。排查长时间运行后的缓慢增长时，拿 `resident` 与 RSS 比较
+#[cfg(feature = "alloc-stats")]
+pub(crate) fn heap_stats() -> Result<String> {
+    use tikv_jemalloc_ctl::{epoch, stats};
+    let e = |e: tikv_jemalloc_ctl::Error| anyhow::anyhow!("jemalloc ctl: {}", e);
+    // 统计值在推进 epoch 之前是缓存的
+    epoch::advance().map_err(e)?;
+    Ok(format!(
+        "allocator jemalloc\nallocated {}\nactive {}\nresident {}\nmapped {}\nretained {}\nmetadata {}\n",
+        stats::allocated::read().map_err(e)?,
+        stats::active::read().map_err(e)?,
+        stats::resident::read().map_err(e)?,
+        stats::mapped::read().map_err(e)?,
+        stats::retained::read().map_err(e)?,
+        stats::metadata::read().map_err(e)?,
+    ))
+}
+
+#[cfg(not(feature = "alloc-stats"))]
+pub(crate) fn heap_stats() -> Result<String> {
+    anyhow::bail!("heap stats unavailable: built without the alloc-stats feature (allocator: {})", allocator_name())
+}
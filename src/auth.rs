@@ -0,0 +1,345 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::util::{log_error, log_throttled};
+
+// 外部命令与 webhook 的超时
+const EXTERNAL_TIMEOUT_MS: u64 = 5000;
+// htpasswd/命令/webhook 校验通过的结果缓存时间，避免每个连接都做 bcrypt 或起进程；吊销最多滞后这么久
+const CACHE_SECS: u64 = 60;
+
+pub(crate) type AuthFuture<'a> = Pin<Box<dyn Future<Output = Result<bool>> + Send + 'a>>;
+
+// 代理认证后端：HTTP Basic 与 SOCKS5 用户名/密码子协商都经它校验。
+// Ok(false) 为凭据不对，Err 为后端本身不可用（记录日志并按拒绝处理）
+pub(crate) trait Authenticator: Send + Sync {
+    fn check<'a>(&'a self, user: &'a str, pass: &'a str) -> AuthFuture<'a>;
+    // 启动摘要用
+    fn describe(&self) -> String;
+}
+
+pub(crate) async fn verify(auth: &dyn Authenticator, user: &str, pass: &str) -> bool {
+    match auth.check(user, pass).await {
+        Ok(ok) => ok,
+        Err(e) => {
+            log_throttled(|| log_error(format!("auth backend {}: {}", auth.describe(), e)));
+            false
+        }
+    }
+}
+
+// 监听的 user=/pass= 与 --socks5-user/--socks5-pass；只设了其中一项时任何凭据都不通过
+pub(crate) struct StaticAuth {
+    user: Option<String>,
+    pass: Option<String>,
+}
+
+impl StaticAuth {
+    pub(crate) fn new(user: Option<String>, pass: Option<String>) -> Self {
+        Self { user, pass }
+    }
+}
+
+impl Authenticator for StaticAuth {
+    fn check<'a>(&'a self, user: &'a str, pass: &'a str) -> AuthFuture<'a> {
+        let ok = self.user.as_deref() == Some(user) && self.pass.as_deref() == Some(pass);
+        Box::pin(async move { Ok(ok) })
+    }
+
+    fn describe(&self) -> String {
+        String::from("static")
+    }
+}
+
+// --auth 的取值：htpasswd:PATH、command:CMD、webhook:URL
+#[derive(Clone, Debug)]
+pub(crate) enum AuthBackend {
+    Htpasswd(String),
+    Command(String),
+    Webhook(String),
+}
+
+impl AuthBackend {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (kind, arg) = s.split_once(':').ok_or_else(|| anyhow::anyhow!("invalid auth backend {:?} (expected htpasswd:PATH, command:CMD or webhook:URL)", s))?;
+        if arg.is_empty() { anyhow::bail!("auth backend {:?} is missing its argument", s); }
+        match kind {
+            "htpasswd" => Ok(Self::Htpasswd(arg.to_string())),
+            "command" => Ok(Self::Command(arg.to_string())),
+            "webhook" => {
+                let uri = crate::uri::parse_absolute(arg).map_err(|e| anyhow::anyhow!("auth webhook {:?}: {}", arg, e))?;
+                if uri.scheme != "http" { anyhow::bail!("auth webhook {:?}: only http:// is supported", arg); }
+                Ok(Self::Webhook(arg.to_string()))
+            }
+            _ => anyhow::bail!("unknown auth backend {:?} (expected htpasswd, command or webhook)", kind),
+        }
+    }
+
+    pub(crate) fn build(&self) -> Result<Arc<dyn Authenticator>> {
+        let inner: Box<dyn Authenticator> = match self {
+            Self::Htpasswd(path) => Box::new(Htpasswd::load(path)?),
+            Self::Command(cmd) => Box::new(CommandAuth { cmd: cmd.clone() }),
+            Self::Webhook(url) => Box::new(WebhookAuth { url: url.clone() }),
+        };
+        Ok(Arc::new(Cached { inner, ok: Mutex::new(HashMap::new()) }))
+    }
+}
+
+// 只缓存通过的结果，键为 blake3(user \0 pass)，不在内存里留明文密码
+struct Cached {
+    inner: Box<dyn Authenticator>,
+    ok: Mutex<HashMap<[u8; 32], Instant>>,
+}
+
+impl Authenticator for Cached {
+    fn check<'a>(&'a self, user: &'a str, pass: &'a str) -> AuthFuture<'a> {
+        Box::pin(async move {
+            let mut h = blake3::Hasher::new();
+            h.update(user.as_bytes());
+            h.update(&[0]);
+            h.update(pass.as_bytes());
+            let key = *h.finalize().as_bytes();
+            let ttl = Duration::from_secs(CACHE_SECS);
+            {
+                let mut ok = self.ok.lock().unwrap_or_else(|e| e.into_inner());
+                ok.retain(|_, at| at.elapsed() < ttl);
+                if ok.contains_key(&key) { return Ok(true); }
+            }
+            let res = self.inner.check(user, pass).await?;
+            if res { self.ok.lock().unwrap_or_else(|e| e.into_inner()).insert(key, Instant::now()); }
+            Ok(res)
+        })
+    }
+
+    fn describe(&self) -> String {
+        self.inner.describe()
+    }
+}
+
+// Apache htpasswd 文件：bcrypt ($2y$)、apr1 MD5（htpasswd 默认）、{SHA}、$1$/$5$/$6$ 与 crypt；
+// 文件修改后下次校验时重新读取
+// 用户名 -> 哈希
+type Entries = Arc<HashMap<String, String>>;
+
+struct Htpasswd {
+    path: String,
+    // 读取时文件的修改时间与内容
+    state: Mutex<(Option<SystemTime>, Entries)>,
+}
+
+impl Htpasswd {
+    fn load(path: &str) -> Result<Self> {
+        let h = Self { path: path.to_string(), state: Mutex::new((None, Arc::new(HashMap::new()))) };
+        h.entries()?;
+        Ok(h)
+    }
+
+    fn entries(&self) -> Result<Entries> {
+        let mtime = std::fs::metadata(&self.path).and_then(|m| m.modified()).map_err(|e| anyhow::anyhow!("htpasswd {}: {}", self.path, e))?;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.0 != Some(mtime) {
+            let body = std::fs::read_to_string(&self.path).map_err(|e| anyhow::anyhow!("htpasswd {}: {}", self.path, e))?;
+            let entries = body
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .filter_map(|l| l.split_once(':'))
+                .map(|(u, h)| (u.to_string(), h.to_string()))
+                .collect();
+            *state = (Some(mtime), Arc::new(entries));
+        }
+        Ok(state.1.clone())
+    }
+}
+
+impl Authenticator for Htpasswd {
+    fn check<'a>(&'a self, user: &'a str, pass: &'a str) -> AuthFuture<'a> {
+        Box::pin(async move {
+            let Some(hash) = self.entries()?.get(user).cloned() else { return Ok(false) };
+            // bcrypt 较慢，放到阻塞线程池
+            let pass = pass.to_string();
+            Ok(tokio::task::spawn_blocking(move || verify_htpasswd_hash(&pass, &hash)).await?)
+        })
+    }
+
+    fn describe(&self) -> String {
+        format!("htpasswd:{}", self.path)
+    }
+}
+
+fn verify_htpasswd_hash(pass: &str, hash: &str) -> bool {
+    if let Some(sha) = hash.strip_prefix("{SHA}") {
+        use base64::Engine;
+        let digest = sha1_smol::Sha1::from(pass.as_bytes()).digest().bytes();
+        return base64::engine::general_purpose::STANDARD.encode(digest) == sha;
+    }
+    if let Some(rest) = hash.strip_prefix("$apr1$") {
+        let salt = rest.split('$').next().unwrap_or("");
+        return apr1_crypt(pass.as_bytes(), salt.as_bytes()) == hash;
+    }
+    pwhash::unix::verify(pass, hash)
+}
+
+// Apache 的 MD5 变体：与 md5crypt ($1$) 相同，只是 magic 换成 $apr1$
+fn apr1_crypt(pass: &[u8], salt: &[u8]) -> String {
+    use md5::{Digest, Md5};
+    const MAGIC: &[u8] = b"$apr1$";
+    let salt = &salt[..salt.len().min(8)];
+    let alt = Md5::new().chain(pass).chain(salt).chain(pass).finalize();
+    let mut ctx = Md5::new().chain(pass).chain(MAGIC).chain(salt);
+    let mut left = pass.len();
+    while left > 0 {
+        let n = left.min(16);
+        ctx.update(&alt[..n]);
+        left -= n;
+    }
+    let mut i = pass.len();
+    while i > 0 {
+        if i & 1 == 1 { ctx.update([0u8]); } else { ctx.update(&pass[..1]); }
+        i >>= 1;
+    }
+    let mut fin = ctx.finalize();
+    for round in 0..1000 {
+        let mut c = Md5::new();
+        if round & 1 == 1 { c.update(pass); } else { c.update(fin); }
+        if round % 3 != 0 { c.update(salt); }
+        if round % 7 != 0 { c.update(pass); }
+        if round & 1 == 1 { c.update(fin); } else { c.update(pass); }
+        fin = c.finalize();
+    }
+    const ITOA64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+    let mut out = String::from_utf8_lossy(MAGIC).into_owned();
+    out.push_str(&String::from_utf8_lossy(salt));
+    out.push('$');
+    let mut push = |mut v: u32, n: usize| {
+        for _ in 0..n {
+            out.push(ITOA64[(v & 0x3f) as usize] as char);
+            v >>= 6;
+        }
+    };
+    for &(a, b, c) in &[(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        push((fin[a] as u32) << 16 | (fin[b] as u32) << 8 | fin[c] as u32, 4);
+    }
+    push(fin[11] as u32, 2);
+    out
+}
+
+// 外部命令：经 `sh -c` 运行，标准输入依次为用户名、密码各一行（不出现在命令行和环境变量里），退出码 0 为通过
+struct CommandAuth {
+    cmd: String,
+}
+
+impl Authenticator for CommandAuth {
+    fn check<'a>(&'a self, user: &'a str, pass: &'a str) -> AuthFuture<'a> {
+        Box::pin(async move {
+            if user.contains('\n') || pass.contains('\n') { return Ok(false); }
+            let mut child = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(&self.cmd)
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::null())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| anyhow::anyhow!("spawn failed: {}", e))?;
+            let mut stdin = child.stdin.take().ok_or_else(|| anyhow::anyhow!("no stdin"))?;
+            let run = async {
+                // 命令可能不读标准输入就退出，写失败不算错误
+                let _ = stdin.write_all(format!("{}\n{}\n", user, pass).as_bytes()).await;
+                drop(stdin);
+                child.wait().await
+            };
+            let status = tokio::time::timeout(Duration::from_millis(EXTERNAL_TIMEOUT_MS), run)
+                .await
+                .map_err(|_| anyhow::anyhow!("timed out after {}ms", EXTERNAL_TIMEOUT_MS))??;
+            Ok(status.success())
+        })
+    }
+
+    fn describe(&self) -> String {
+        format!("command:{}", self.cmd)
+    }
+}
+
+// HTTP webhook：POST {"user":...,"pass":...}，2xx 为通过，401/403 为拒绝，其他状态视为后端错误
+struct WebhookAuth {
+    url: String,
+}
+
+impl Authenticator for WebhookAuth {
+    fn check<'a>(&'a self, user: &'a str, pass: &'a str) -> AuthFuture<'a> {
+        Box::pin(async move {
+            let uri = crate::uri::parse_absolute(&self.url).map_err(|e| anyhow::anyhow!(e))?;
+            let body = format!("{{\"user\":{},\"pass\":{}}}", crate::util::json_string(user), crate::util::json_string(pass));
+            let req = format!(
+                "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                uri.path,
+                crate::uri::format_authority(&uri.host, uri.port, 80),
+                crate::build_info::AGENT,
+                body.len(),
+                body
+            );
+            let exchange = async {
+                let mut s = tokio::net::TcpStream::connect((uri.host.as_str(), uri.port)).await?;
+                s.write_all(req.as_bytes()).await?;
+                let mut head = Vec::new();
+                let mut buf = [0u8; 512];
+                while !head.windows(2).any(|w| w == b"\r\n") && head.len() < 4096 {
+                    let n = s.read(&mut buf).await?;
+                    if n == 0 { break; }
+                    head.extend_from_slice(&buf[..n]);
+                }
+                Ok::<_, anyhow::Error>(head)
+            };
+            let head = tokio::time::timeout(Duration::from_millis(EXTERNAL_TIMEOUT_MS), exchange)
+                .await
+                .map_err(|_| anyhow::anyhow!("timed out after {}ms", EXTERNAL_TIMEOUT_MS))??;
+            let line = String::from_utf8_lossy(&head);
+            let status: u16 = line.split_whitespace().nth(1).and_then(|s| s.parse().ok()).ok_or_else(|| anyhow::anyhow!("invalid response"))?;
+            match status {
+                200..=299 => Ok(true),
+                401 | 403 => Ok(false),
+                _ => anyhow::bail!("unexpected status {}", status),
+            }
+        })
+    }
+
+    fn describe(&self) -> String {
+        format!("webhook:{}", self.url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn htpasswd_hash_formats() {
+        assert_eq!(apr1_crypt(b"secret", b"r31Xx1y2"), "$apr1$r31Xx1y2$Hftt4k2o9hj4fB3tmxRkT.");
+        for hash in ["$apr1$r31Xx1y2$Hftt4k2o9hj4fB3tmxRkT.", "{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=", "$1$abc$iCQ2D3nhptRYi27fDYv2s1"] {
+            assert!(verify_htpasswd_hash("secret", hash), "{}", hash);
+            assert!(!verify_htpasswd_hash("Secret", hash), "{}", hash);
+        }
+    }
+
+    #[test]
+    fn parse_backends() {
+        assert!(matches!(AuthBackend::parse("htpasswd:/etc/proxy.htpasswd").unwrap(), AuthBackend::Htpasswd(p) if p == "/etc/proxy.htpasswd"));
+        assert!(matches!(AuthBackend::parse("command:/usr/local/bin/check --strict").unwrap(), AuthBackend::Command(_)));
+        assert!(AuthBackend::parse("webhook:http://127.0.0.1:9000/auth").is_ok());
+        assert!(AuthBackend::parse("webhook:https://auth.example.com/").is_err());
+        assert!(AuthBackend::parse("ldap:foo").is_err());
+        assert!(AuthBackend::parse("htpasswd:").is_err());
+    }
+
+    #[tokio::test]
+    async fn command_backend_reads_credentials_from_stdin() {
+        let auth = CommandAuth { cmd: String::from("read u; read p; [ \"$u\" = alice ] && [ \"$p\" = 'pa ss' ]") };
+        assert!(auth.check("alice", "pa ss").await.unwrap());
+        assert!(!auth.check("alice", "nope").await.unwrap());
+    }
+}
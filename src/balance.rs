@@ -0,0 +1,465 @@
+use anyhow::Result;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::util::{log_info, log_throttled};
+
+// --egress-group：把几块网卡合成一个出口组，组名可用在任何填网卡名的地方（--iface、监听的 iface=、规则的 iface:、上游的 ?iface=），
+// 每个新连接按策略选一块成员网卡，如 Wi-Fi 与 USB 共享网络叠加带宽
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Policy {
+    RoundRobin,
+    // 活动连接数 / 权重最小的成员
+    LeastConns,
+    // 按权重（带宽）平滑加权轮询
+    Weighted,
+    // 全部走最近一次测速最快的成员
+    UrlTest,
+}
+
+impl Policy {
+    fn parse(s: &str) -> Result<Self> {
+        match s {
+            "rr" | "round-robin" => Ok(Self::RoundRobin),
+            "least" | "least-conns" => Ok(Self::LeastConns),
+            "weighted" => Ok(Self::Weighted),
+            "url-test" => Ok(Self::UrlTest),
+            _ => anyhow::bail!("unknown egress group policy {:?} (expected rr, least, weighted or url-test)", s),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::RoundRobin => "rr",
+            Self::LeastConns => "least",
+            Self::Weighted => "weighted",
+            Self::UrlTest => "url-test",
+        }
+    }
+}
+
+// 粘滞：同一目标主机（及客户端）总是分到同一成员，避免按来源 IP 绑定会话的服务（网银、登录态）因换出口断开
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Sticky {
+    Off,
+    Host,
+    HostClient,
+}
+
+impl Sticky {
+    fn parse(s: &str) -> Result<Self> {
+        match s {
+            "host" => Ok(Self::Host),
+            "host+client" => Ok(Self::HostClient),
+            _ => anyhow::bail!("unknown sticky mode {:?} (expected host or host+client)", s),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Off => "",
+            Self::Host => "host",
+            Self::HostClient => "host+client",
+        }
+    }
+}
+
+// NAME=IF[:WEIGHT],IF[:WEIGHT][,policy=rr|least|weighted|url-test][,sticky=host|host+client]；
+// WEIGHT 为带宽（如 Mbit/s，只看比例），默认 1；给了权重又没写 policy 时按 weighted
+#[derive(Clone, Debug)]
+pub(crate) struct GroupSpec {
+    pub(crate) name: String,
+    policy: Policy,
+    sticky: Sticky,
+    members: Vec<(String, u32)>,
+}
+
+impl GroupSpec {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (name, list) = s.split_once('=').ok_or_else(|| anyhow::anyhow!("invalid egress group {:?} (expected NAME=IF[:WEIGHT],IF[:WEIGHT][,policy=P])", s))?;
+        let (mut policy, mut sticky, mut members) = (None, Sticky::Off, Vec::new());
+        for tok in list.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            if let Some(p) = tok.strip_prefix("policy=") {
+                policy = Some(Policy::parse(p)?);
+                continue;
+            }
+            if let Some(m) = tok.strip_prefix("sticky=") {
+                sticky = Sticky::parse(m)?;
+                continue;
+            }
+            let (iface, weight) = match tok.split_once(':') {
+                Some((i, w)) => match w.parse::<u32>() {
+                    Ok(w) if w > 0 => (i, Some(w)),
+                    _ => anyhow::bail!("invalid weight {:?} in egress group {:?}", w, s),
+                },
+                None => (tok, None),
+            };
+            members.push((iface.to_string(), weight));
+        }
+        if name.trim().is_empty() || members.len() < 2 { anyhow::bail!("egress group {:?} needs a name and at least two interfaces", s); }
+        let weighted = members.iter().any(|(_, w)| w.is_some());
+        let policy = policy.unwrap_or(if weighted { Policy::Weighted } else { Policy::RoundRobin });
+        // 粘滞按权重做一致性哈希，与看实时状态的策略不能同时使用
+        if sticky != Sticky::Off && matches!(policy, Policy::LeastConns | Policy::UrlTest) {
+            anyhow::bail!("egress group {:?}: sticky= only works with policy=rr or weighted", s);
+        }
+        Ok(Self { name: name.trim().to_string(), policy, sticky, members: members.into_iter().map(|(i, w)| (i, w.unwrap_or(1))).collect() })
+    }
+
+    pub(crate) fn members(&self) -> impl Iterator<Item = &str> {
+        self.members.iter().map(|(i, _)| i.as_str())
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        let members: Vec<String> = self.members.iter().map(|(i, w)| format!("{}:{}", i, w)).collect();
+        format!("{}={} ({})", self.name, members.join(","), self.mode())
+    }
+
+    fn mode(&self) -> String {
+        match self.sticky {
+            Sticky::Off => self.policy.name().to_string(),
+            s => format!("{} sticky={}", self.policy.name(), s.name()),
+        }
+    }
+}
+
+struct Member {
+    iface: String,
+    weight: u32,
+    active: AtomicUsize,
+    picked: AtomicUsize,
+    // url-test 最近一次测得的首字节耗时，NO_DELAY 表示未测或失败
+    delay_ms: AtomicU64,
+}
+
+const NO_DELAY: u64 = u64::MAX;
+
+struct Group {
+    spec: GroupSpec,
+    members: Vec<Member>,
+    next: AtomicUsize,
+    // 平滑加权轮询的当前值
+    current: Mutex<Vec<i64>>,
+    // url-test 当前选中的成员
+    selected: AtomicUsize,
+}
+
+impl Group {
+    fn new(spec: GroupSpec) -> Self {
+        let members: Vec<Member> = spec.members.iter().map(|(i, w)| Member {
+            iface: i.clone(),
+            weight: *w,
+            active: AtomicUsize::new(0),
+            picked: AtomicUsize::new(0),
+            delay_ms: AtomicU64::new(NO_DELAY),
+        }).collect();
+        Self { current: Mutex::new(vec![0; members.len()]), members, next: AtomicUsize::new(0), selected: AtomicUsize::new(0), spec }
+    }
+
+    // 加权 rendezvous 哈希：每个成员得分 weight / -ln(u)，u 由 key 与成员名哈希得到，按得分从高到低排列；
+    // 成员增减只影响原本落在该成员上的目标
+    fn rank(&self, key: &str) -> Vec<usize> {
+        let score = |m: &Member| {
+            let mut h = blake3::Hasher::new();
+            h.update(key.as_bytes());
+            h.update(&[0]);
+            h.update(m.iface.as_bytes());
+            let bits = u64::from_le_bytes(h.finalize().as_bytes()[..8].try_into().unwrap_or_default()) >> 11;
+            let u = (bits + 1) as f64 / ((1u64 << 53) + 1) as f64;
+            m.weight as f64 / -u.ln()
+        };
+        let scores: Vec<f64> = self.members.iter().map(score).collect();
+        let mut order: Vec<usize> = (0..self.members.len()).collect();
+        order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]).then(a.cmp(&b)));
+        order
+    }
+
+    fn sticky_key(&self, host: &str) -> Option<String> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        match self.spec.sticky {
+            Sticky::Off => None,
+            Sticky::Host => Some(host),
+            Sticky::HostClient => {
+                let peer = crate::session::client().map(|c| c.peer).unwrap_or_default();
+                let client = peer.parse::<std::net::SocketAddr>().map(|a| a.ip().to_string()).unwrap_or(peer);
+                Some(format!("{}|{}", host, client))
+            }
+        }
+    }
+
+    // `host` 为出站目标，粘滞组据此选择；重试（attempt > 0）时依次换到排名靠后的成员
+    fn pick(&self, host: &str, attempt: usize) -> &Member {
+        let n = self.members.len();
+        let i = match self.sticky_key(host) {
+            Some(key) => self.rank(&key)[attempt % n],
+            None => self.pick_index(n),
+        };
+        let m = &self.members[i];
+        m.picked.fetch_add(1, Ordering::Relaxed);
+        m
+    }
+
+    fn pick_index(&self, n: usize) -> usize {
+        match self.spec.policy {
+            Policy::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % n,
+            // 比较 active/weight 时交叉相乘避免浮点；从轮询位置开始找，使相同负载时依次分配
+            Policy::LeastConns => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                let load = |m: &Member| (m.active.load(Ordering::Relaxed) as u64, m.weight as u64);
+                (0..n).map(|k| (start + k) % n).min_by(|&a, &b| {
+                    let ((la, wa), (lb, wb)) = (load(&self.members[a]), load(&self.members[b]));
+                    (la * wb).cmp(&(lb * wa))
+                }).unwrap_or(0)
+            }
+            Policy::Weighted => {
+                let mut cur = self.current.lock().unwrap_or_else(|e| e.into_inner());
+                let total: i64 = self.members.iter().map(|m| m.weight as i64).sum();
+                cur.iter_mut().zip(&self.members).for_each(|(c, m)| *c += m.weight as i64);
+                let best = (0..n).max_by_key(|&k| (cur[k], std::cmp::Reverse(k))).unwrap_or(0);
+                cur[best] -= total;
+                best
+            }
+            Policy::UrlTest => self.selected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// 滞后：新的最快成员须比当前成员快出 tolerance 毫秒才切换，当前成员测速失败时直接切换；都失败时保持不变
+fn choose(current: usize, delays: &[Option<u64>], tolerance: u64) -> usize {
+    let Some((best, best_ms)) = delays.iter().enumerate().filter_map(|(i, d)| d.map(|d| (i, d))).min_by_key(|(_, d)| *d) else {
+        return current;
+    };
+    match delays.get(current).copied().flatten() {
+        Some(cur_ms) if best_ms + tolerance >= cur_ms => current,
+        _ => best,
+    }
+}
+
+static GROUPS: OnceLock<Vec<Group>> = OnceLock::new();
+
+pub(crate) fn install(specs: Vec<GroupSpec>) {
+    let _ = GROUPS.set(specs.into_iter().map(Group::new).collect());
+}
+
+fn group(name: &str) -> Option<&'static Group> {
+    GROUPS.get()?.iter().find(|g| g.spec.name == name)
+}
+
+pub(crate) fn members(name: &str) -> Option<Vec<&'static str>> {
+    Some(group(name)?.members.iter().map(|m| m.iface.as_str()).collect())
+}
+
+// 组名换成本次选中的成员网卡；不计入活动连接数（UDP、上游连接等不经 DirectDialer 的出站）
+pub(crate) fn resolve<'a>(iface: &'a str, host: &str, attempt: usize) -> &'a str {
+    match group(iface) {
+        Some(g) => &g.pick(host, attempt).iface,
+        None => iface,
+    }
+}
+
+// 选成员并计入活动连接数，返回的 Lease 随连接释放
+pub(crate) fn lease(iface: &str, host: &str, attempt: usize) -> Option<(&'static str, Lease)> {
+    let m = group(iface)?.pick(host, attempt);
+    m.active.fetch_add(1, Ordering::Relaxed);
+    Some((&m.iface, Lease(m)))
+}
+
+pub(crate) struct Lease(&'static Member);
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// 持有 Lease 的出站连接
+pub(crate) struct Leased<S> {
+    pub(crate) inner: S,
+    pub(crate) _lease: Lease,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Leased<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Leased<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+pub(crate) struct UrlTest {
+    pub(crate) url: String,
+    pub(crate) interval: Duration,
+    pub(crate) tolerance_ms: u64,
+}
+
+const TEST_TIMEOUT_SECS: u64 = 5;
+
+pub(crate) fn has_url_test() -> bool {
+    GROUPS.get().is_some_and(|gs| gs.iter().any(|g| g.spec.policy == Policy::UrlTest))
+}
+
+// 从建连开始计时到收到应答的第一个字节，包含解析、TCP 与 TLS 握手
+async fn ttfb(url: &crate::ruleset::Url, iface: &str) -> Result<u64> {
+    let started = Instant::now();
+    let fut = async {
+        let mut s = url.connect(iface).await?;
+        let req = format!("GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: {}\r\nConnection: close\r\n\r\n", url.path, url.host, crate::build_info::AGENT);
+        s.write_all(req.as_bytes()).await?;
+        let mut b = [0u8; 1];
+        if s.read(&mut b).await? == 0 { anyhow::bail!("connection closed without a response"); }
+        Ok(started.elapsed().as_millis() as u64)
+    };
+    tokio::time::timeout(Duration::from_secs(TEST_TIMEOUT_SECS), fut).await.map_err(|_| anyhow::anyhow!("timed out after {}s", TEST_TIMEOUT_SECS))?
+}
+
+// url-test 组定时经各成员测速，新连接走当前最快的成员
+pub(crate) async fn run_url_test(cfg: UrlTest) {
+    let url = match crate::ruleset::Url::parse(&cfg.url) {
+        Ok(u) => std::sync::Arc::new(u),
+        Err(e) => return crate::util::log_error(format!("url-test: {}", e)),
+    };
+    let mut ticker = tokio::time::interval(cfg.interval.max(Duration::from_secs(1)));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let groups: Vec<&'static Group> = GROUPS.get().into_iter().flatten().filter(|g| g.spec.policy == Policy::UrlTest).collect();
+    loop {
+        ticker.tick().await;
+        // 所有组的成员并发测速
+        let mut round = Vec::new();
+        for (gi, g) in groups.iter().enumerate() {
+            for (mi, m) in g.members.iter().enumerate() {
+                let url = url.clone();
+                round.push(tokio::spawn(async move { (gi, mi, ttfb(&url, &m.iface).await) }));
+            }
+        }
+        for h in round {
+            let Ok((gi, mi, res)) = h.await else { continue };
+            let m = &groups[gi].members[mi];
+            match res {
+                Ok(ms) => m.delay_ms.store(ms, Ordering::Relaxed),
+                Err(e) => {
+                    log_throttled(|| log_info(format!("url-test {} via {} failed: {}", cfg.url, m.iface, e)));
+                    m.delay_ms.store(NO_DELAY, Ordering::Relaxed);
+                }
+            }
+        }
+        for g in &groups {
+            let delays: Vec<Option<u64>> = g.members.iter().map(|m| Some(m.delay_ms.load(Ordering::Relaxed)).filter(|d| *d != NO_DELAY)).collect();
+            let cur = g.selected.load(Ordering::Relaxed);
+            let next = choose(cur, &delays, cfg.tolerance_ms);
+            if next != cur {
+                let ms = |d: Option<u64>| d.map(|d| format!("{}ms", d)).unwrap_or_else(|| String::from("failed"));
+                log_info(format!(
+                    "egress group {}: switching to {} ({}, was {} {})",
+                    g.spec.name, g.members[next].iface, ms(delays[next]), g.members[cur].iface, ms(delays[cur])
+                ));
+                g.selected.store(next, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// 管理接口 /egress-groups；url-test 组当前选中的成员标 *
+pub(crate) fn render() -> String {
+    let mut out = format!("{:<12} {:<26} {:<12} {:>6} {:>8} {:>10} {:>9}\n", "group", "policy", "iface", "weight", "active", "picked", "delay_ms");
+    for g in GROUPS.get().into_iter().flatten() {
+        for (i, m) in g.members.iter().enumerate() {
+            let selected = g.spec.policy == Policy::UrlTest && g.selected.load(Ordering::Relaxed) == i;
+            let delay = match m.delay_ms.load(Ordering::Relaxed) {
+                NO_DELAY => String::from("-"),
+                d => d.to_string(),
+            };
+            out.push_str(&format!(
+                "{:<12} {:<26} {:<12} {:>6} {:>8} {:>10} {:>9}\n",
+                g.spec.name,
+                g.spec.mode(),
+                format!("{}{}", m.iface, if selected { "*" } else { "" }),
+                m.weight,
+                m.active.load(Ordering::Relaxed),
+                m.picked.load(Ordering::Relaxed),
+                delay
+            ));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn picks(g: &Group, n: usize) -> Vec<&str> {
+        (0..n).map(|_| g.pick("example.com", 0).iface.as_str()).collect()
+    }
+
+    #[test]
+    fn policies_distribute_connections() {
+        let rr = Group::new(GroupSpec::parse("bond=wlan0,usb0").unwrap());
+        assert_eq!(picks(&rr, 4), ["wlan0", "usb0", "wlan0", "usb0"]);
+
+        let w = Group::new(GroupSpec::parse("bond=wlan0:3,usb0:1").unwrap());
+        assert_eq!(w.spec.policy, Policy::Weighted);
+        assert_eq!(picks(&w, 8), ["wlan0", "wlan0", "usb0", "wlan0", "wlan0", "wlan0", "usb0", "wlan0"]);
+
+        let least = Group::new(GroupSpec::parse("bond=wlan0:2,usb0:1,policy=least").unwrap());
+        // wlan0 权重为 2，可承担两倍的活动连接
+        least.members[0].active.store(2, Ordering::Relaxed);
+        least.members[1].active.store(1, Ordering::Relaxed);
+        assert_eq!(least.pick("", 0).iface, "wlan0");
+        least.members[0].active.store(3, Ordering::Relaxed);
+        assert_eq!(least.pick("", 0).iface, "usb0");
+
+        let fastest = Group::new(GroupSpec::parse("bond=wlan0,usb0,policy=url-test").unwrap());
+        fastest.selected.store(1, Ordering::Relaxed);
+        assert_eq!(picks(&fastest, 2), ["usb0", "usb0"]);
+
+        assert!(GroupSpec::parse("bond=wlan0").is_err());
+        assert!(GroupSpec::parse("bond=wlan0:0,usb0").is_err());
+        assert!(GroupSpec::parse("bond=wlan0,usb0,policy=random").is_err());
+    }
+
+    #[test]
+    fn sticky_groups_keep_a_host_on_one_member() {
+        let g = Group::new(GroupSpec::parse("bond=wlan0:3,usb0:1,eth1:1,sticky=host").unwrap());
+        let hosts: Vec<String> = (0..400).map(|i| format!("site{}.example", i)).collect();
+        let first: Vec<&str> = hosts.iter().map(|h| g.pick(h, 0).iface.as_str()).collect();
+        // 同一主机每次都分到同一成员，大小写与末尾的点不影响
+        assert!(hosts.iter().zip(&first).all(|(h, m)| g.pick(h, 0).iface == *m));
+        assert_eq!(g.pick("SITE7.example.", 0).iface, first[7]);
+        // 重试换到另一个成员
+        assert!(hosts.iter().zip(&first).all(|(h, m)| g.pick(h, 1).iface != *m));
+        // 按权重分布：wlan0 约占 3/5
+        let wlan = first.iter().filter(|m| **m == "wlan0").count();
+        assert!((200..280).contains(&wlan), "{}", wlan);
+        // 去掉一个成员只移动原本落在它上面的主机
+        let fewer = Group::new(GroupSpec::parse("bond=wlan0:3,usb0:1,sticky=host").unwrap());
+        assert!(hosts.iter().zip(&first).filter(|(_, m)| **m != "eth1").all(|(h, m)| fewer.pick(h, 0).iface == *m));
+        assert!(GroupSpec::parse("bond=wlan0,usb0,policy=least,sticky=host").is_err());
+    }
+
+    #[test]
+    fn url_test_switches_with_hysteresis() {
+        // 快出不到 tolerance 不切换
+        assert_eq!(choose(0, &[Some(100), Some(70)], 50), 0);
+        assert_eq!(choose(0, &[Some(100), Some(40)], 50), 1);
+        // 当前成员失败时切到最快的可用成员；全部失败时不动
+        assert_eq!(choose(0, &[None, Some(300), Some(200)], 50), 2);
+        assert_eq!(choose(1, &[None, None], 50), 1);
+    }
+}
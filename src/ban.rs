@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::util::{log_info, log_throttled};
+
+// 认证失败计数与临时封禁（类似 fail2ban）：同一来源 IP 在 window 内认证失败达到 threshold 次后，
+// 封禁 ban 时长，期间 HTTP、SOCKS5、混合端口与 WebSocket 隧道监听在 accept 后直接关闭它的连接。
+// 回环地址不计数，避免本机配置错误的应用把自己封掉
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BanConfig {
+    pub(crate) threshold: u32,
+    pub(crate) window: Duration,
+    pub(crate) ban: Duration,
+}
+
+// 表项上限：大量不同来源的失败（如扫描）不会无限占用内存，超出时丢弃最早的非封禁项
+const MAX_ENTRIES: usize = 65536;
+
+struct Entry {
+    // 当前计数窗口的起点与其中的失败次数
+    since: Instant,
+    failures: u32,
+    banned_until: Option<Instant>,
+    // 累计封禁次数，/bans 展示用
+    bans: u32,
+}
+
+#[derive(Default)]
+struct Table {
+    entries: HashMap<IpAddr, Entry>,
+}
+
+impl Table {
+    // 返回本次是否触发封禁
+    fn fail(&mut self, cfg: &BanConfig, ip: IpAddr, now: Instant) -> bool {
+        if self.entries.len() >= MAX_ENTRIES && !self.entries.contains_key(&ip) { self.prune(cfg, now); }
+        let e = self.entries.entry(ip).or_insert(Entry { since: now, failures: 0, banned_until: None, bans: 0 });
+        if now.duration_since(e.since) >= cfg.window {
+            e.since = now;
+            e.failures = 0;
+        }
+        e.failures += 1;
+        if e.failures < cfg.threshold || e.banned_until.is_some_and(|t| t > now) { return false; }
+        e.banned_until = Some(now + cfg.ban);
+        e.bans += 1;
+        e.failures = 0;
+        true
+    }
+
+    fn banned(&self, ip: IpAddr, now: Instant) -> bool {
+        self.entries.get(&ip).and_then(|e| e.banned_until).is_some_and(|t| t > now)
+    }
+
+    fn prune(&mut self, cfg: &BanConfig, now: Instant) {
+        self.entries.retain(|_, e| e.banned_until.is_some_and(|t| t > now) || now.duration_since(e.since) < cfg.window);
+        while self.entries.len() >= MAX_ENTRIES {
+            let Some(oldest) = self.entries.iter().filter(|(_, e)| e.banned_until.is_none_or(|t| t <= now)).min_by_key(|(_, e)| e.since).map(|(ip, _)| *ip) else { break };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    // None 为全部；返回解除的封禁数
+    fn unban(&mut self, ip: Option<IpAddr>, now: Instant) -> usize {
+        let mut n = 0;
+        for (_, e) in self.entries.iter_mut().filter(|(k, _)| ip.is_none_or(|ip| **k == ip)) {
+            if e.banned_until.take().is_some_and(|t| t > now) { n += 1; }
+            e.failures = 0;
+        }
+        n
+    }
+}
+
+static CONFIG: OnceLock<BanConfig> = OnceLock::new();
+
+fn table() -> &'static Mutex<Table> {
+    static TABLE: OnceLock<Mutex<Table>> = OnceLock::new();
+    TABLE.get_or_init(|| Mutex::new(Table::default()))
+}
+
+// threshold 为 0 时不启用
+pub(crate) fn install(cfg: BanConfig) {
+    if cfg.threshold > 0 { let _ = CONFIG.set(cfg); }
+}
+
+// 当前会话的客户端认证失败时调用（凭据错误，不含未带凭据的首次请求）
+pub(crate) fn record_failure() {
+    let Some(cfg) = CONFIG.get() else { return };
+    let Some(ip) = crate::session::peer_ip().filter(|ip| !ip.is_loopback()) else { return };
+    if table().lock().unwrap_or_else(|e| e.into_inner()).fail(cfg, ip, Instant::now()) {
+        log_info(format!("auth: banning {} for {}s after {} failed attempts within {}s", ip, cfg.ban.as_secs(), cfg.threshold, cfg.window.as_secs()));
+    }
+}
+
+// accept 循环用：封禁中的来源直接关闭连接
+pub(crate) fn banned(ip: IpAddr, listen: &str) -> bool {
+    if CONFIG.get().is_none() { return false; }
+    let banned = table().lock().unwrap_or_else(|e| e.into_inner()).banned(ip, Instant::now());
+    if banned { log_throttled(|| log_info(format!("denied connection from {} to {} (banned after repeated auth failures)", ip, listen))); }
+    banned
+}
+
+pub(crate) fn unban(ip: Option<IpAddr>) -> usize {
+    table().lock().unwrap_or_else(|e| e.into_inner()).unban(ip, Instant::now())
+}
+
+pub(crate) fn render() -> String {
+    let Some(cfg) = CONFIG.get() else { return String::from("auth failure banning is disabled\n") };
+    let now = Instant::now();
+    let mut t = table().lock().unwrap_or_else(|e| e.into_inner());
+    t.prune(cfg, now);
+    let mut rows: Vec<_> = t.entries.iter().collect();
+    rows.sort_by_key(|(ip, _)| **ip);
+    let mut out = format!("{:<40} {:>8} {:>6} {}\n", "ip", "failures", "bans", "banned_for");
+    for (ip, e) in rows {
+        let banned = match e.banned_until.filter(|t| *t > now) {
+            Some(t) => format!("{}s", (t - now).as_secs()),
+            None => String::from("-"),
+        };
+        out.push_str(&format!("{:<40} {:>8} {:>6} {}\n", ip.to_string(), e.failures, e.bans, banned));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bans_after_threshold_within_window() {
+        let cfg = BanConfig { threshold: 3, window: Duration::from_secs(60), ban: Duration::from_secs(300) };
+        let ip: IpAddr = "192.0.2.7".parse().unwrap();
+        let t0 = Instant::now();
+        let mut t = Table::default();
+        // 窗口过期后重新计数
+        assert!(!t.fail(&cfg, ip, t0) && !t.fail(&cfg, ip, t0 + Duration::from_secs(1)));
+        assert!(!t.fail(&cfg, ip, t0 + Duration::from_secs(61)));
+        assert!(!t.banned(ip, t0 + Duration::from_secs(61)));
+        assert!(!t.fail(&cfg, ip, t0 + Duration::from_secs(62)));
+        assert!(t.fail(&cfg, ip, t0 + Duration::from_secs(63)));
+        assert!(t.banned(ip, t0 + Duration::from_secs(100)) && !t.banned("192.0.2.8".parse().unwrap(), t0));
+        assert!(!t.banned(ip, t0 + Duration::from_secs(363)));
+
+        let t1 = t0 + Duration::from_secs(400);
+        assert!(!t.fail(&cfg, ip, t1) && !t.fail(&cfg, ip, t1) && t.fail(&cfg, ip, t1));
+        assert_eq!((t.entries[&ip].bans, t.unban(Some(ip), t1)), (2, 1));
+        assert!(!t.banned(ip, t1));
+    }
+}
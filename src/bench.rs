@@ -0,0 +1,140 @@
+use anyhow::Result;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::Duration;
+
+use crate::cli::BenchArgs;
+use crate::ruleset::Url;
+
+// bench：不经监听，直接用内部的出站连接逐个经各网卡下载同一地址，比较建连、首字节耗时与吞吐，帮助选择路由
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+
+struct Sample {
+    // 解析、TCP 建连与 TLS 握手
+    connect_ms: u64,
+    // 发出请求到收到应答头
+    ttfb_ms: u64,
+    body: u64,
+    // 从收到应答头到下载结束
+    transfer: Duration,
+    // 达到 --max-secs 或 --max-bytes 后提前停止
+    capped: bool,
+}
+
+impl Sample {
+    fn mbps(&self) -> f64 {
+        mbps(self.body, self.transfer)
+    }
+}
+
+fn mbps(bytes: u64, d: Duration) -> f64 {
+    let secs = d.as_secs_f64();
+    if secs <= 0.0 { 0.0 } else { bytes as f64 * 8.0 / secs / 1e6 }
+}
+
+// 读到应答头结束，只接受 200；返回头之后已读到的正文
+async fn read_head<S: tokio::io::AsyncRead + Unpin>(s: &mut S) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    let mut tmp = [0u8; 4096];
+    loop {
+        let n = s.read(&mut tmp).await?;
+        if n == 0 { anyhow::bail!("connection closed before response headers"); }
+        buf.extend_from_slice(&tmp[..n]);
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&buf[..end]);
+            let status = head.lines().next().unwrap_or("");
+            if status.split_whitespace().nth(1) != Some("200") { anyhow::bail!("got {:?}", status); }
+            return Ok(buf.split_off(end + 4));
+        }
+        if buf.len() > 64 * 1024 { anyhow::bail!("response headers too large"); }
+    }
+}
+
+async fn download(url: &Url, iface: &str, args: &BenchArgs) -> Result<Sample> {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(args.max_secs);
+    let started = Instant::now();
+    let connect_timeout = Duration::from_secs(CONNECT_TIMEOUT_SECS.min(args.max_secs));
+    let mut s = tokio::time::timeout(connect_timeout, url.connect(iface)).await.map_err(|_| anyhow::anyhow!("connect timed out after {}s", connect_timeout.as_secs()))??;
+    let connect_ms = started.elapsed().as_millis() as u64;
+    // HTTP/1.0 请求，应答不会是 chunked
+    let req = format!("GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: {}\r\nConnection: close\r\n\r\n", url.path, url.host, crate::build_info::AGENT);
+    let sent = Instant::now();
+    s.write_all(req.as_bytes()).await?;
+    let first = tokio::time::timeout_at(deadline, read_head(&mut s)).await.map_err(|_| anyhow::anyhow!("no response before --max-secs"))??;
+    let ttfb_ms = sent.elapsed().as_millis() as u64;
+    let body_start = Instant::now();
+    let max = args.max_bytes.unwrap_or(u64::MAX);
+    let mut body = (first.len() as u64).min(max);
+    let mut buf = vec![0u8; 64 * 1024];
+    let capped = loop {
+        if body >= max { break true; }
+        let want = buf.len().min((max - body).try_into().unwrap_or(usize::MAX));
+        match tokio::time::timeout_at(deadline, s.read(&mut buf[..want])).await {
+            Err(_) => break true,
+            Ok(Ok(0)) => break false,
+            Ok(Ok(n)) => body += n as u64,
+            Ok(Err(e)) => return Err(anyhow::anyhow!("download failed after {} bytes: {}", body, e)),
+        }
+    };
+    Ok(Sample { connect_ms, ttfb_ms, body, transfer: body_start.elapsed(), capped })
+}
+
+fn size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1 << 30 => format!("{:.1}GiB", b as f64 / (1u64 << 30) as f64),
+        b if b >= 1 << 20 => format!("{:.1}MiB", b as f64 / (1u64 << 20) as f64),
+        b if b >= 1 << 10 => format!("{:.1}KiB", b as f64 / 1024.0),
+        b => format!("{}B", b),
+    }
+}
+
+pub(crate) async fn run_bench(args: BenchArgs) -> Result<()> {
+    let url = Url::parse(&args.url)?;
+    println!("bench {} via {} ({} round(s), up to {}s each)", args.url, args.ifaces.join(", "), args.rounds, args.max_secs);
+    // 每块网卡的成功样本
+    let mut results: Vec<(String, Vec<Sample>, usize)> = Vec::new();
+    for iface in &args.ifaces {
+        let (mut ok, mut failed) = (Vec::new(), 0);
+        for round in 1..=args.rounds {
+            match download(&url, iface, &args).await {
+                Ok(s) => {
+                    println!(
+                        "  {:<12} #{} connect {}ms, first byte {}ms, {} in {:.2}s, {:.1} Mbit/s{}",
+                        iface, round, s.connect_ms, s.ttfb_ms, size(s.body), s.transfer.as_secs_f64(), s.mbps(), if s.capped { " (capped)" } else { "" }
+                    );
+                    ok.push(s);
+                }
+                Err(e) => {
+                    println!("  {:<12} #{} failed: {}", iface, round, e);
+                    failed += 1;
+                }
+            }
+        }
+        results.push((iface.clone(), ok, failed));
+    }
+
+    println!("\n{:<12} {:>10} {:>8} {:>10} {:>10} {:>7}", "iface", "connect_ms", "ttfb_ms", "bytes", "Mbit/s", "failed");
+    let mut best: Option<(&str, f64)> = None;
+    for (iface, ok, failed) in &results {
+        if ok.is_empty() {
+            println!("{:<12} {:>10} {:>8} {:>10} {:>10} {:>7}", iface, "-", "-", "-", "-", failed);
+            continue;
+        }
+        let n = ok.len() as u64;
+        let bytes: u64 = ok.iter().map(|s| s.body).sum();
+        let rate = mbps(bytes, ok.iter().map(|s| s.transfer).sum());
+        println!(
+            "{:<12} {:>10} {:>8} {:>10} {:>10.1} {:>7}",
+            iface,
+            ok.iter().map(|s| s.connect_ms).sum::<u64>() / n,
+            ok.iter().map(|s| s.ttfb_ms).sum::<u64>() / n,
+            size(bytes),
+            rate,
+            failed
+        );
+        if best.is_none_or(|(_, r)| rate > r) { best = Some((iface, rate)); }
+    }
+    let Some((fastest, rate)) = best else { anyhow::bail!("all downloads failed") };
+    if results.len() > 1 { println!("\nfastest: {} ({:.1} Mbit/s)", fastest, rate); }
+    Ok(())
+}
@@ -0,0 +1,121 @@
+use std::time::{Duration, Instant};
+
+use iface_proxy::Recording;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+struct Args {
+    file: String,
+    target: String,
+    // send every client frame immediately instead of at its recorded offset
+    no_timing: bool,
+    // stop reading once the proxy has been silent this long after the last client frame
+    idle_ms: u64,
+    show: bool,
+}
+
+fn parse_args() -> Args {
+    let mut file = None;
+    let mut target = String::from("127.0.0.1:7890");
+    let mut no_timing = false;
+    let mut idle_ms: u64 = 2000;
+    let mut show = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--target" { if let Some(v) = args.next() { target = v; } }
+        else if let Some(v) = arg.strip_prefix("--target=") { target = v.to_string(); }
+        else if arg == "--no-timing" { no_timing = true; }
+        else if arg == "--idle-ms" { if let Some(v) = args.next() { idle_ms = v.parse().unwrap_or(idle_ms); } }
+        else if let Some(v) = arg.strip_prefix("--idle-ms=") { idle_ms = v.parse().unwrap_or(idle_ms); }
+        else if arg == "--show" { show = true; }
+        else if arg == "-h" || arg == "--help" { print_help_and_exit(); }
+        else { file = Some(arg); }
+    }
+    let Some(file) = file else { print_help_and_exit() };
+    Args { file, target, no_timing, idle_ms, show }
+}
+
+fn print_help_and_exit() -> ! {
+    eprintln!("replay - re-send the client side of a session recorded with --record-dir\n\nUsage: replay [options] FILE.rec\n\nOptions:\n  --target ADDR:PORT       Proxy address (default 127.0.0.1:7890)\n  --no-timing              Send all client data at once instead of at the recorded offsets\n  --idle-ms N              After the last client frame, stop once the proxy is silent for N ms (default 2000)\n  --show                   Print what the proxy sent back\n");
+    std::process::exit(0)
+}
+
+// Printable rendering of a byte range for the report.
+fn escape(bytes: &[u8]) -> String {
+    bytes.escape_ascii().to_string()
+}
+
+// First offset where `got` and `want` differ, or None when one is a prefix of the other.
+fn divergence(got: &[u8], want: &[u8]) -> Option<usize> {
+    got.iter().zip(want).position(|(a, b)| a != b)
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> anyhow::Result<()> {
+    let args = parse_args();
+    let bytes = std::fs::read(&args.file).map_err(|e| anyhow::anyhow!("cannot read {}: {}", args.file, e))?;
+    let rec = Recording::parse(&bytes).map_err(|e| anyhow::anyhow!("{}: {}", args.file, e))?;
+    eprintln!(
+        "{}: {} session from {} to {}{}",
+        args.file,
+        rec.kind,
+        rec.peer,
+        if rec.target.is_empty() { "(no target)" } else { rec.target.as_str() },
+        if rec.truncated { " (truncated)" } else { "" }
+    );
+
+    let stream = TcpStream::connect(&args.target).await.map_err(|e| anyhow::anyhow!("cannot connect to {}: {}", args.target, e))?;
+    let (mut rd, mut wr) = stream.into_split();
+    let (done_tx, mut done_rx) = tokio::sync::watch::channel(false);
+    let started = Instant::now();
+
+    let send = async {
+        let mut sent = 0usize;
+        for f in rec.frames.iter().filter(|f| f.from_client) {
+            if !args.no_timing {
+                tokio::time::sleep_until((started + Duration::from_millis(f.at_ms as u64)).into()).await;
+            }
+            wr.write_all(&f.data).await?;
+            sent += f.data.len();
+        }
+        let _ = done_tx.send(true);
+        Ok::<_, anyhow::Error>(sent)
+    };
+    let recv = async {
+        let mut got = Vec::new();
+        let mut buf = vec![0u8; 16 * 1024];
+        loop {
+            let n = if *done_rx.borrow() {
+                match tokio::time::timeout(Duration::from_millis(args.idle_ms), rd.read(&mut buf)).await {
+                    Ok(r) => r?,
+                    Err(_) => break,
+                }
+            } else {
+                tokio::select! {
+                    r = rd.read(&mut buf) => r?,
+                    _ = done_rx.changed() => continue,
+                }
+            };
+            if n == 0 { break; }
+            got.extend_from_slice(&buf[..n]);
+        }
+        Ok::<_, anyhow::Error>(got)
+    };
+    let (sent, got) = tokio::try_join!(send, recv)?;
+
+    let want = rec.stream(false);
+    println!("sent {} bytes, received {} bytes (recorded {}) in {:?}", sent, got.len(), want.len(), started.elapsed());
+    if args.show { println!("{}", escape(&got)); }
+    match divergence(&got, &want) {
+        Some(at) => {
+            let from = at.saturating_sub(32);
+            println!("responses differ at byte {}:\n  recorded: {}\n  replayed: {}", at, escape(&want[from..(at + 32).min(want.len())]), escape(&got[from..(at + 32).min(got.len())]));
+        }
+        None if got.len() == want.len() => println!("responses identical"),
+        // a truncated recording only holds a prefix of the response
+        None if rec.truncated && got.len() > want.len() => println!("responses identical up to the end of the recording"),
+        None => println!("responses agree for {} bytes, then the {} one ends", got.len().min(want.len()), if got.len() < want.len() { "replayed" } else { "recorded" }),
+    }
+    Ok(())
+}
@@ -1,14 +1,35 @@
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::net::{TcpListener, TcpStream};
 
 #[derive(Clone, Copy, Debug)]
-enum Mode { Http, Connect, Idle }
+enum Mode { Http, Connect, Idle, Socks5 }
 
-fn parse_args() -> (String, Mode, String, usize, u64) {
+impl Mode {
+    fn name(self) -> &'static str {
+        match self { Mode::Http => "http", Mode::Connect => "connect", Mode::Idle => "idle", Mode::Socks5 => "socks5" }
+    }
+}
+
+struct Args {
+    target: String,
+    mode: Mode,
+    payload: String,
+    conns: usize,
+    duration_secs: u64,
+    user: Option<String>,
+    pass: Option<String>,
+    // connect/socks5: bytes echoed through each tunnel (0 = old behaviour)
+    size: usize,
+    json: Option<String>,
+    // spin up a local HTTP/TCP echo origin and point the payload at it
+    selftest: bool,
+}
+
+fn parse_args() -> Args {
     // defaults
     let mut target = String::from("127.0.0.1:7890");
     let mut mode = Mode::Http;
@@ -16,6 +37,11 @@ fn parse_args() -> (String, Mode, String, usize, u64) {
     let mut payload = String::from("http://example.com/");
     let mut conns: usize = 500;
     let mut duration_secs: u64 = 60;
+    let mut user = None;
+    let mut pass = None;
+    let mut size: usize = 0;
+    let mut json = None;
+    let mut selftest = false;
 
     let mut args = std::env::args().skip(1);
     while let Some(arg) = args.next() {
@@ -29,19 +55,74 @@ fn parse_args() -> (String, Mode, String, usize, u64) {
         else if let Some(v) = arg.strip_prefix("--conns=") { conns = v.parse().unwrap_or(conns); }
         else if arg == "--duration-secs" { if let Some(v) = args.next() { duration_secs = v.parse().unwrap_or(duration_secs); } }
         else if let Some(v) = arg.strip_prefix("--duration-secs=") { duration_secs = v.parse().unwrap_or(duration_secs); }
+        else if arg == "--user" { user = args.next(); }
+        else if let Some(v) = arg.strip_prefix("--user=") { user = Some(v.to_string()); }
+        else if arg == "--pass" { pass = args.next(); }
+        else if let Some(v) = arg.strip_prefix("--pass=") { pass = Some(v.to_string()); }
+        else if arg == "--size" { if let Some(v) = args.next() { size = parse_size(&v).unwrap_or(size); } }
+        else if let Some(v) = arg.strip_prefix("--size=") { size = parse_size(v).unwrap_or(size); }
+        else if arg == "--json" { json = args.next(); }
+        else if let Some(v) = arg.strip_prefix("--json=") { json = Some(v.to_string()); }
+        else if arg == "--selftest" { selftest = true; }
         else if arg == "-h" || arg == "--help" { print_help_and_exit(); }
     }
-    (target, mode, payload, conns, duration_secs)
+    Args { target, mode, payload, conns, duration_secs, user, pass, size, json, selftest }
 }
 
-fn parse_mode(s: &str) -> Mode { match s { "http" => Mode::Http, "connect" => Mode::Connect, "idle" => Mode::Idle, _ => Mode::Http } }
+fn parse_mode(s: &str) -> Mode { match s { "http" => Mode::Http, "connect" => Mode::Connect, "idle" => Mode::Idle, "socks5" => Mode::Socks5, _ => Mode::Http } }
+
+// 4096, 64k, 1m
+fn parse_size(s: &str) -> Option<usize> {
+    let s = s.trim().to_ascii_lowercase();
+    let (num, mult) = match s.chars().last()? {
+        'k' => (&s[..s.len() - 1], 1024),
+        'm' => (&s[..s.len() - 1], 1024 * 1024),
+        _ => (s.as_str(), 1),
+    };
+    num.parse::<usize>().ok().map(|n| n * mult)
+}
 
 fn print_help_and_exit() -> ! {
-    eprintln!("stress - simple HTTP proxy stress tool\n\nOptions:\n  --target ADDR:PORT       Proxy address (default 127.0.0.1:7890)\n  --mode http|connect|idle Mode: http absolute-URI GET; connect sends CONNECT then closes; idle opens TCP and does nothing\n  --payload STR            http: URI (default http://example.com/); connect: host:port (default example.com:443)\n  --conns N                Concurrent connections (default 500)\n  --duration-secs S        Test duration in seconds (default 60)\n");
+    eprintln!("stress - simple HTTP proxy stress tool\n\nOptions:\n  --target ADDR:PORT       Proxy address (default 127.0.0.1:7890)\n  --mode http|connect|idle|socks5\n                           Mode: http absolute-URI GET; connect sends CONNECT then closes; idle opens TCP and does nothing;\n                           socks5 does the SOCKS5 handshake + CONNECT, then an HTTP/1.0 GET through the tunnel\n  --payload STR            http: URI (default http://example.com/); connect/socks5: host:port (default example.com:443 / example.com:80)\n  --user U --pass P        socks5: username/password auth (RFC 1929)\n  --size N[k|m]            connect/socks5: send N bytes through each tunnel and read them back (target must echo)\n  --json PATH              Write final results (latency percentiles, throughput) as JSON\n  --selftest               Start a local HTTP/TCP echo origin on 127.0.0.1 and use it instead of --payload\n                           (the proxy must be able to reach loopback, e.g. run it with -i lo;\n                           connect mode also needs --connect-ports '*' since the origin uses a random port)\n  --conns N                Concurrent connections (default 500)\n  --duration-secs S        Test duration in seconds (default 60)\n");
     std::process::exit(0)
 }
 
-async fn worker_http(target: &str, uri: &str) -> anyhow::Result<()> {
+// What one successful iteration moved; the iteration itself is timed by the caller.
+#[derive(Default)]
+struct Sample {
+    bytes: u64,
+    // socks5: TCP connect + greeting/auth + CONNECT reply
+    handshake: Option<Duration>,
+}
+
+// Writes `size` bytes and reads the same amount back concurrently, so large sizes can't deadlock on full buffers.
+async fn echo_transfer(stream: &mut TcpStream, size: usize) -> anyhow::Result<u64> {
+    let (mut rd, mut wr) = stream.split();
+    let send = async {
+        let chunk = vec![0x5Au8; 16 * 1024];
+        let mut left = size;
+        while left > 0 {
+            let n = left.min(chunk.len());
+            wr.write_all(&chunk[..n]).await?;
+            left -= n;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    let recv = async {
+        let mut buf = vec![0u8; 16 * 1024];
+        let mut got = 0usize;
+        while got < size {
+            let n = rd.read(&mut buf).await?;
+            if n == 0 { anyhow::bail!("tunnel closed after {} of {} echoed bytes", got, size); }
+            got += n;
+        }
+        Ok(())
+    };
+    tokio::try_join!(send, recv)?;
+    Ok(size as u64 * 2)
+}
+
+async fn worker_http(target: &str, uri: &str) -> anyhow::Result<Sample> {
     let mut stream = TcpStream::connect(target).await?;
     let host = uri.strip_prefix("http://").and_then(|r| r.split('/').next()).unwrap_or("");
     let req = format!(
@@ -50,40 +131,206 @@ async fn worker_http(target: &str, uri: &str) -> anyhow::Result<()> {
     );
     stream.write_all(req.as_bytes()).await?;
     let mut buf = [0u8; 1024];
-    let _ = stream.read(&mut buf).await; // best-effort
-    Ok(())
+    let n = stream.read(&mut buf).await.unwrap_or(0); // best-effort
+    Ok(Sample { bytes: (req.len() + n) as u64, handshake: None })
 }
 
-async fn worker_connect(target: &str, authority: &str) -> anyhow::Result<()> {
+async fn worker_connect(target: &str, authority: &str, size: usize) -> anyhow::Result<Sample> {
     let mut stream = TcpStream::connect(target).await?;
     let req = format!(
         "CONNECT {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
         authority, authority
     );
     stream.write_all(req.as_bytes()).await?;
-    // read a small response then close
-    let mut buf = [0u8; 128];
-    let _ = stream.read(&mut buf).await;
-    Ok(())
+    if size == 0 {
+        // read a small response then close
+        let mut buf = [0u8; 128];
+        let _ = stream.read(&mut buf).await;
+        return Ok(Sample::default());
+    }
+    let mut head = Vec::new();
+    let mut b = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut b).await? == 0 { anyhow::bail!("proxy closed before CONNECT response"); }
+        head.push(b[0]);
+    }
+    if !head.starts_with(b"HTTP/1.1 200") && !head.starts_with(b"HTTP/1.0 200") { anyhow::bail!("CONNECT refused"); }
+    let bytes = echo_transfer(&mut stream, size).await?;
+    Ok(Sample { bytes, handshake: None })
+}
+
+async fn worker_socks5(target: &str, authority: &str, auth: Option<(&str, &str)>, size: usize) -> anyhow::Result<Sample> {
+    let started = Instant::now();
+    let (host, port) = authority.rsplit_once(':').ok_or_else(|| anyhow::anyhow!("payload must be host:port"))?;
+    let port: u16 = port.parse()?;
+    let mut stream = TcpStream::connect(target).await?;
+    stream.write_all(&[0x05, 0x01, if auth.is_some() { 0x02 } else { 0x00 }]).await?;
+    let mut rep = [0u8; 2];
+    stream.read_exact(&mut rep).await?;
+    if rep[1] == 0xFF { anyhow::bail!("no acceptable auth method"); }
+    if let Some((u, p)) = auth {
+        let mut msg = vec![0x01, u.len() as u8];
+        msg.extend_from_slice(u.as_bytes());
+        msg.push(p.len() as u8);
+        msg.extend_from_slice(p.as_bytes());
+        stream.write_all(&msg).await?;
+        stream.read_exact(&mut rep).await?;
+        if rep[1] != 0x00 { anyhow::bail!("auth failed"); }
+    }
+    let mut req = vec![0x05, 0x01, 0x00, 0x03, host.len() as u8];
+    req.extend_from_slice(host.as_bytes());
+    req.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&req).await?;
+    let mut reply = [0u8; 10];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0x00 { anyhow::bail!("CONNECT failed (REP={:#04x})", reply[1]); }
+    let handshake = Some(started.elapsed());
+
+    if size > 0 {
+        return Ok(Sample { bytes: echo_transfer(&mut stream, size).await?, handshake });
+    }
+    stream.write_all(format!("GET / HTTP/1.0\r\nHost: {}\r\n\r\n", host).as_bytes()).await?;
+    let mut buf = vec![0u8; 16 * 1024];
+    let mut bytes = 0u64;
+    loop {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 { break; }
+        bytes += n as u64;
+    }
+    Ok(Sample { bytes, handshake })
 }
 
-async fn worker_idle(target: &str) -> anyhow::Result<()> {
+async fn worker_idle(target: &str) -> anyhow::Result<Sample> {
     // open and keep a short idle to exercise server read timeout
     let _stream = TcpStream::connect(target).await?;
     tokio::time::sleep(Duration::from_millis(200)).await;
+    Ok(Sample::default())
+}
+
+const ORIGIN_BODY: usize = 1024;
+
+// Local origin for --selftest: requests that start with an HTTP method get a small fixed response,
+// anything else is echoed back until the client closes.
+async fn run_origin(listener: TcpListener) {
+    loop {
+        let Ok((mut conn, _)) = listener.accept().await else { continue };
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 16 * 1024];
+            let mut n = match conn.read(&mut buf).await { Ok(0) | Err(_) => return, Ok(n) => n };
+            let is_http = [&b"GET "[..], b"HEAD ", b"POST ", b"PUT "].iter().any(|m| buf[..n].starts_with(m));
+            if !is_http {
+                loop {
+                    if conn.write_all(&buf[..n]).await.is_err() { return; }
+                    n = match conn.read(&mut buf).await { Ok(0) | Err(_) => return, Ok(n) => n };
+                }
+            }
+            let mut head = buf[..n].to_vec();
+            while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                match conn.read(&mut buf).await { Ok(0) | Err(_) => return, Ok(n) => head.extend_from_slice(&buf[..n]) }
+            }
+            let resp = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", ORIGIN_BODY, "x".repeat(ORIGIN_BODY));
+            let _ = conn.write_all(resp.as_bytes()).await;
+            let _ = conn.shutdown().await;
+        });
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    ok: AtomicU64,
+    err: AtomicU64,
+    bytes: AtomicU64,
+    hs_us: AtomicU64,
+    hs_count: AtomicU64,
+    // per-iteration latency of successful runs, in microseconds
+    latencies: Mutex<Vec<u64>>,
+}
+
+impl Stats {
+    fn record(&self, elapsed: Duration, s: Sample) {
+        self.ok.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(s.bytes, Ordering::Relaxed);
+        if let Some(hs) = s.handshake {
+            self.hs_us.fetch_add(hs.as_micros() as u64, Ordering::Relaxed);
+            self.hs_count.fetch_add(1, Ordering::Relaxed);
+        }
+        self.latencies.lock().unwrap_or_else(|e| e.into_inner()).push(elapsed.as_micros() as u64);
+    }
+
+    fn handshake_avg_ms(&self) -> Option<f64> {
+        let n = self.hs_count.load(Ordering::Relaxed);
+        (n > 0).then(|| self.hs_us.load(Ordering::Relaxed) as f64 / n as f64 / 1000.0)
+    }
+}
+
+struct Percentiles { p50: f64, p95: f64, p99: f64, max: f64, mean: f64 }
+
+// Nearest-rank percentiles in milliseconds; `samples` is sorted in place.
+fn percentiles(samples: &mut [u64]) -> Option<Percentiles> {
+    if samples.is_empty() { return None; }
+    samples.sort_unstable();
+    let at = |p: f64| {
+        let rank = ((p / 100.0) * samples.len() as f64).ceil() as usize;
+        samples[rank.clamp(1, samples.len()) - 1] as f64 / 1000.0
+    };
+    let mean = samples.iter().sum::<u64>() as f64 / samples.len() as f64 / 1000.0;
+    Some(Percentiles { p50: at(50.0), p95: at(95.0), p99: at(99.0), max: samples[samples.len() - 1] as f64 / 1000.0, mean })
+}
+
+fn write_json(path: &str, args: &Args, elapsed: Duration, stats: &Stats, pct: Option<&Percentiles>) -> anyhow::Result<()> {
+    let secs = elapsed.as_secs_f64();
+    let bytes = stats.bytes.load(Ordering::Relaxed);
+    let ok = stats.ok.load(Ordering::Relaxed);
+    let num = |v: Option<f64>| v.map(|v| format!("{:.3}", v)).unwrap_or_else(|| String::from("null"));
+    let json = format!(
+        "{{\n  \"mode\": \"{}\",\n  \"target\": \"{}\",\n  \"payload\": \"{}\",\n  \"conns\": {},\n  \"size\": {},\n  \"duration_secs\": {:.3},\n  \"ok\": {},\n  \"err\": {},\n  \"ops_per_sec\": {:.3},\n  \"bytes\": {},\n  \"mb_per_sec\": {:.3},\n  \"latency_ms\": {{ \"p50\": {}, \"p95\": {}, \"p99\": {}, \"max\": {}, \"mean\": {} }},\n  \"handshake_avg_ms\": {}\n}}\n",
+        args.mode.name(),
+        args.target.replace('"', "\\\""),
+        args.payload.replace('"', "\\\""),
+        args.conns,
+        args.size,
+        secs,
+        ok,
+        stats.err.load(Ordering::Relaxed),
+        ok as f64 / secs,
+        bytes,
+        bytes as f64 / secs / 1e6,
+        num(pct.map(|p| p.p50)),
+        num(pct.map(|p| p.p95)),
+        num(pct.map(|p| p.p99)),
+        num(pct.map(|p| p.max)),
+        num(pct.map(|p| p.mean)),
+        num(stats.handshake_avg_ms()),
+    );
+    std::fs::write(path, json)?;
     Ok(())
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let (target, mode, payload, conns, duration_secs) = parse_args();
-    let payload = if matches!(mode, Mode::Connect) {
-        if payload.is_empty() || !payload.contains(':') { "example.com:443".to_string() } else { payload }
-    } else { payload };
+    let mut args = parse_args();
+    if args.selftest {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let origin = listener.local_addr()?;
+        tokio::spawn(run_origin(listener));
+        args.payload = match args.mode {
+            Mode::Http => format!("http://{}/", origin),
+            _ => origin.to_string(),
+        };
+        eprintln!("selftest origin listening on {} (payload {})", origin, args.payload);
+    }
+    args.payload = match args.mode {
+        Mode::Connect if args.payload.is_empty() || !args.payload.contains(':') => "example.com:443".to_string(),
+        Mode::Socks5 if args.payload.is_empty() || !args.payload.contains(':') => "example.com:80".to_string(),
+        _ => args.payload,
+    };
+    let Args { mode, conns, duration_secs, size, .. } = args;
+    let target = args.target.clone();
+    let payload = args.payload.clone();
+    let auth = Arc::new(args.user.clone().zip(args.pass.clone()));
 
     let stop = Arc::new(AtomicBool::new(false));
-    let success = Arc::new(AtomicU64::new(0));
-    let failures = Arc::new(AtomicU64::new(0));
+    let stats = Arc::new(Stats::default());
 
     let start = Instant::now();
     let stop_clone = stop.clone();
@@ -92,19 +339,27 @@ async fn main() -> anyhow::Result<()> {
         stop_clone.store(true, Ordering::SeqCst);
     });
 
-    // stats ticker
-    let s_succ = success.clone();
-    let s_fail = failures.clone();
+    // stats ticker: counts plus latency percentiles / throughput of the last second
+    let s = stats.clone();
     tokio::spawn(async move {
-        let mut prev_s = 0u64; let mut prev_f = 0u64;
+        let mut prev_s = 0u64; let mut prev_f = 0u64; let mut prev_b = 0u64; let mut seen = 0usize;
         loop {
             tokio::time::sleep(Duration::from_secs(1)).await;
-            let s = s_succ.load(Ordering::Relaxed);
-            let f = s_fail.load(Ordering::Relaxed);
-            let ds = s - prev_s; let df = f - prev_f; prev_s = s; prev_f = f;
+            let ok = s.ok.load(Ordering::Relaxed);
+            let f = s.err.load(Ordering::Relaxed);
+            let b = s.bytes.load(Ordering::Relaxed);
+            let ds = ok - prev_s; let df = f - prev_f; let db = b - prev_b; prev_s = ok; prev_f = f; prev_b = b;
+            let mut window: Vec<u64> = {
+                let lat = s.latencies.lock().unwrap_or_else(|e| e.into_inner());
+                let w = lat[seen..].to_vec();
+                seen = lat.len();
+                w
+            };
+            let lat = percentiles(&mut window).map(|p| format!(" p50={:.2}ms p99={:.2}ms", p.p50, p.p99)).unwrap_or_default();
+            let hs = s.handshake_avg_ms().map(|v| format!(" handshake_avg_ms={:.2}", v)).unwrap_or_default();
             eprintln!(
-                "[{:?}] +ok={} +err={} total_ok={} total_err={}",
-                start.elapsed(), ds, df, s, f
+                "[{:?}] +ok={} +err={} total_ok={} total_err={}{} {:.2}MB/s{}",
+                start.elapsed(), ds, df, ok, f, lat, db as f64 / 1e6, hs
             );
         }
     });
@@ -114,26 +369,42 @@ async fn main() -> anyhow::Result<()> {
         let target_c = target.clone();
         let payload_c = payload.clone();
         let stop_c = stop.clone();
-        let succ_c = success.clone();
-        let fail_c = failures.clone();
+        let stats_c = stats.clone();
+        let auth_c = auth.clone();
         tasks.push(tokio::spawn(async move {
             while !stop_c.load(Ordering::Relaxed) {
+                let started = Instant::now();
                 let res = match mode {
                     Mode::Http => worker_http(&target_c, &payload_c).await,
-                    Mode::Connect => worker_connect(&target_c, &payload_c).await,
+                    Mode::Connect => worker_connect(&target_c, &payload_c, size).await,
                     Mode::Idle => worker_idle(&target_c).await,
+                    Mode::Socks5 => {
+                        let auth = auth_c.as_ref().as_ref().map(|(u, p)| (u.as_str(), p.as_str()));
+                        worker_socks5(&target_c, &payload_c, auth, size).await
+                    }
                 };
                 match res {
-                    Ok(_) => { succ_c.fetch_add(1, Ordering::Relaxed); }
-                    Err(_) => { fail_c.fetch_add(1, Ordering::Relaxed); }
+                    Ok(sample) => stats_c.record(started.elapsed(), sample),
+                    Err(_) => { stats_c.err.fetch_add(1, Ordering::Relaxed); }
                 }
             }
         }));
     }
 
     for t in tasks { let _ = t.await; }
-    eprintln!("Finished in {:?}. ok={} err={}", start.elapsed(), success.load(Ordering::Relaxed), failures.load(Ordering::Relaxed));
+    let elapsed = start.elapsed();
+    let mut all = std::mem::take(&mut *stats.latencies.lock().unwrap_or_else(|e| e.into_inner()));
+    let pct = percentiles(&mut all);
+    let bytes = stats.bytes.load(Ordering::Relaxed);
+    eprintln!("Finished in {:?}. ok={} err={}", elapsed, stats.ok.load(Ordering::Relaxed), stats.err.load(Ordering::Relaxed));
+    if let Some(p) = &pct {
+        eprintln!("latency: p50={:.2}ms p95={:.2}ms p99={:.2}ms max={:.2}ms mean={:.2}ms", p.p50, p.p95, p.p99, p.max, p.mean);
+    }
+    eprintln!("throughput: {:.2}MB/s ({} bytes){}", bytes as f64 / elapsed.as_secs_f64() / 1e6, bytes,
+        stats.handshake_avg_ms().map(|v| format!(" handshake_avg_ms={:.2}", v)).unwrap_or_default());
+    if let Some(path) = &args.json {
+        write_json(path, &args, elapsed, &stats, pct.as_ref())?;
+        eprintln!("results written to {}", path);
+    }
     Ok(())
 }
-
-
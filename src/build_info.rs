@@ -0,0 +1,17 @@
+// 编译期由 build.rs 注入的版本信息
+pub(crate) const VERSION: &str = env!("IFACE_PROXY_VERSION");
+pub(crate) const GIT_HASH: &str = env!("IFACE_PROXY_GIT_HASH");
+pub(crate) const BUILD_DATE: &str = env!("IFACE_PROXY_BUILD_DATE");
+
+// `iface-proxy --version` 的输出，如 `0.1.0 (git 1a2b3c4d5, built 2024-05-01)`
+pub(crate) const LONG_VERSION: &str = concat!(
+    env!("IFACE_PROXY_VERSION"),
+    " (git ",
+    env!("IFACE_PROXY_GIT_HASH"),
+    ", built ",
+    env!("IFACE_PROXY_BUILD_DATE"),
+    ")"
+);
+
+// Proxy-Agent / Server 头的取值
+pub(crate) const AGENT: &str = concat!("iface-proxy/", env!("IFACE_PROXY_VERSION"));
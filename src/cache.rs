@@ -0,0 +1,742 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::response::{Event, Framer};
+use crate::util::{log_error, log_info, log_throttled};
+
+// 明文 HTTP GET 应答缓存（RFC 7234 的常用子集）：按 Cache-Control/Expires 计算新鲜度，
+// 过期后带 If-None-Match/If-Modified-Since 向目标验证，304 时用缓存内容应答，减少经计费网卡的重复下载。
+// 内存按总大小 LRU 淘汰；配置目录时同时写入磁盘，重启后仍可命中
+pub(crate) struct CacheConfig {
+    pub(crate) memory: u64,
+    pub(crate) max_object: u64,
+    pub(crate) dir: Option<PathBuf>,
+    pub(crate) disk: u64,
+}
+
+struct Store {
+    cfg: CacheConfig,
+    // 值为条目与最近使用序号
+    entries: HashMap<String, (Arc<Entry>, u64)>,
+    bytes: u64,
+    tick: u64,
+    // 配置目录时的磁盘写入线程
+    disk: Option<std::sync::mpsc::Sender<DiskOp>>,
+}
+
+// 写盘与删除交给单独的线程按顺序执行，不在 STORE 锁内做文件 I/O
+enum DiskOp {
+    Write(PathBuf, Vec<u8>),
+    Remove(PathBuf),
+}
+
+// 写盘线程维护的目录用量：启动时扫描一次，之后按写入与删除增减，超出上限时按修改时间删除最旧的条目
+struct Disk {
+    limit: u64,
+    // 文件 -> (修改时间, 大小)
+    files: HashMap<PathBuf, (SystemTime, u64)>,
+    total: u64,
+}
+
+static STORE: OnceLock<Mutex<Store>> = OnceLock::new();
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+static REVALIDATED: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+static BYPASSED: AtomicU64 = AtomicU64::new(0);
+// 由缓存提供、无需再从目标下载的 body 字节
+static SAVED: AtomicU64 = AtomicU64::new(0);
+
+const DISK_MAGIC: &str = "iface-proxy-cache/1";
+const STORABLE_STATUS: [u16; 5] = [200, 203, 301, 404, 410];
+// 转发缓存内容时不带上的逐跳头部，Content-Length 与 Age 按实际重新生成
+const HOP_BY_HOP: [&str; 10] = [
+    "connection", "keep-alive", "proxy-connection", "transfer-encoding", "te", "trailer", "upgrade",
+    "proxy-authenticate", "content-length", "age",
+];
+
+pub(crate) fn install(cfg: CacheConfig) {
+    let disk = cfg.dir.clone().map(|dir| {
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            log_error(format!("cache: cannot create {}: {}", dir.display(), e));
+        }
+        let (tx, rx) = std::sync::mpsc::channel();
+        let limit = cfg.disk;
+        std::thread::spawn(move || {
+            let mut disk = Disk::scan(&dir, limit);
+            for op in rx { disk.apply(op); }
+        });
+        tx
+    });
+    let _ = STORE.set(Mutex::new(Store { cfg, entries: HashMap::new(), bytes: 0, tick: 0, disk }));
+}
+
+pub(crate) fn enabled() -> bool {
+    STORE.get().is_some()
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// IMF-fixdate，如 `Sun, 06 Nov 1994 08:49:37 GMT`；其他旧格式按无效处理（Expires 无效即已过期）
+fn parse_http_date(s: &str) -> Option<u64> {
+    let mut parts = s.split_whitespace();
+    parts.next()?.strip_suffix(',')?;
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"].iter().position(|m| m.eq_ignore_ascii_case(month))? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+    let mut hms = parts.next()?.split(':').map(|v| v.parse::<u64>().ok());
+    let (h, m, sec) = (hms.next()??, hms.next()??, hms.next()??);
+    if parts.next() != Some("GMT") || !(1..=31).contains(&day) || year < 1970 || h > 23 || m > 59 || sec > 60 { return None; }
+    // 公历日期到 1970-01-01 起的天数（Howard Hinnant 的 days_from_civil）
+    let (y, mp) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = (era * 146097 + doe).checked_sub(719468)?;
+    Some(days * 86400 + h * 3600 + m * 60 + sec)
+}
+
+// `max-age=60, no-cache, private="Set-Cookie"` -> [(max-age, Some(60)), (no-cache, None), ...]
+fn directives(values: &[&str]) -> Vec<(String, Option<String>)> {
+    values
+        .iter()
+        .flat_map(|v| v.split(','))
+        .filter_map(|d| {
+            let (name, value) = match d.split_once('=') {
+                Some((n, v)) => (n, Some(v.trim().trim_matches('"').to_string())),
+                None => (d, None),
+            };
+            let name = name.trim().to_ascii_lowercase();
+            (!name.is_empty()).then_some((name, value))
+        })
+        .collect()
+}
+
+fn directive_secs(d: &[(String, Option<String>)], name: &str) -> Option<u64> {
+    d.iter().find(|(n, _)| n == name).and_then(|(_, v)| v.as_deref()?.parse().ok())
+}
+
+fn has_directive(d: &[(String, Option<String>)], name: &str) -> bool {
+    d.iter().any(|(n, _)| n == name)
+}
+
+fn request_header<'a>(headers: &'a [(String, String)], name: &str) -> Vec<&'a str> {
+    headers.iter().filter(|(n, _)| n.trim().eq_ignore_ascii_case(name)).map(|(_, v)| v.trim()).collect()
+}
+
+fn head_lines(head: &str) -> impl Iterator<Item = (&str, &str)> {
+    head.split("\r\n").skip(1).filter_map(|l| l.split_once(':')).map(|(n, v)| (n.trim(), v.trim()))
+}
+
+fn head_header<'a>(head: &'a str, name: &str) -> Vec<&'a str> {
+    head_lines(head).filter(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v).collect()
+}
+
+pub(crate) struct Entry {
+    // 状态行与端到端头部，每行以 CRLF 结尾，不含结尾空行
+    head: String,
+    body: Vec<u8>,
+    // Vary 列出的请求头及存储时请求中的取值
+    vary: Vec<(String, Option<String>)>,
+    // 存储或最近一次验证的时刻，以及当时的 Age
+    stored: u64,
+    initial_age: u64,
+    lifetime: u64,
+    // 应答带 no-cache：每次使用前都要验证
+    no_cache: bool,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Entry {
+    // 从目标应答头判断能否存储，body 之后再填；`request` 为发往目标的请求头
+    fn from_head(head: &str, request: &[(String, String)], stored: u64) -> Option<Self> {
+        let status: u16 = head.split_whitespace().nth(1)?.parse().ok()?;
+        if !STORABLE_STATUS.contains(&status) { return None; }
+        let cc = directives(&head_header(head, "cache-control"));
+        if has_directive(&cc, "no-store") || has_directive(&cc, "private") { return None; }
+        // 带 Set-Cookie 的应答因人而异，共享缓存不存
+        if !head_header(head, "set-cookie").is_empty() { return None; }
+        let authorized = !request_header(request, "authorization").is_empty();
+        if authorized && !["public", "s-maxage", "must-revalidate"].iter().any(|d| has_directive(&cc, d)) { return None; }
+        let mut vary = Vec::new();
+        for name in head_header(head, "vary").iter().flat_map(|v| v.split(',')) {
+            let name = name.trim().to_ascii_lowercase();
+            if name == "*" { return None; }
+            if name.is_empty() { continue; }
+            let value = request_header(request, &name).join(", ");
+            vary.push((name, (!value.is_empty()).then_some(value)));
+        }
+        let first = |name: &str| head_header(head, name).first().map(|v| v.to_string());
+        let date = first("date").and_then(|d| parse_http_date(&d));
+        let last_modified = first("last-modified");
+        let lifetime = directive_secs(&cc, "s-maxage")
+            .or_else(|| directive_secs(&cc, "max-age"))
+            .or_else(|| first("expires").map(|e| parse_http_date(&e).unwrap_or(0).saturating_sub(date.unwrap_or(stored))))
+            .unwrap_or_else(|| {
+                // 没有明确期限：按 Last-Modified 距今的 10% 估算，最多一天
+                let lm = last_modified.as_deref().and_then(parse_http_date);
+                lm.map_or(0, |lm| (date.unwrap_or(stored).saturating_sub(lm) / 10).min(86400))
+            });
+        let etag = first("etag");
+        if lifetime == 0 && etag.is_none() && last_modified.is_none() { return None; }
+        let age: u64 = first("age").and_then(|a| a.parse().ok()).unwrap_or(0);
+        let kept: String = head
+            .split("\r\n")
+            .enumerate()
+            .filter(|(i, l)| *i == 0 || (!l.is_empty() && l.split_once(':').is_some_and(|(n, _)| !HOP_BY_HOP.contains(&n.trim().to_ascii_lowercase().as_str()))))
+            .map(|(_, l)| format!("{}\r\n", l))
+            .collect();
+        let no_cache = has_directive(&cc, "no-cache") || head_header(head, "pragma").iter().any(|p| p.eq_ignore_ascii_case("no-cache"));
+        Some(Self {
+            head: kept,
+            body: Vec::new(),
+            vary,
+            stored,
+            initial_age: age.max(date.map_or(0, |d| stored.saturating_sub(d))),
+            lifetime,
+            no_cache,
+            etag,
+            last_modified,
+        })
+    }
+
+    pub(crate) fn status(&self) -> &str {
+        self.head.split_whitespace().nth(1).unwrap_or("")
+    }
+
+    pub(crate) fn body_len(&self) -> usize {
+        self.body.len()
+    }
+
+    fn size(&self) -> u64 {
+        (self.head.len() + self.body.len()) as u64
+    }
+
+    fn age(&self) -> u64 {
+        self.initial_age + now().saturating_sub(self.stored)
+    }
+
+    fn fresh(&self) -> bool {
+        !self.no_cache && self.age() < self.lifetime
+    }
+
+    fn vary_matches(&self, request: &[(String, String)]) -> bool {
+        self.vary.iter().all(|(name, stored)| {
+            let value = request_header(request, name).join(", ");
+            stored.as_deref().unwrap_or("") == value
+        })
+    }
+
+    // 完整应答；`close` 时声明连接随后关闭
+    pub(crate) fn response(&self, close: bool) -> Vec<u8> {
+        let mut out = format!("{}Age: {}\r\nContent-Length: {}\r\n", self.head, self.age(), self.body.len());
+        if close { out.push_str("Connection: close\r\n"); }
+        out.push_str("\r\n");
+        let mut out = out.into_bytes();
+        out.extend_from_slice(&self.body);
+        out
+    }
+
+    // 304 带来的新头部覆盖同名旧头部后重新计算新鲜度；304 表示不再可缓存时返回 None
+    fn refreshed(&self, head304: &str, request: &[(String, String)]) -> Option<Self> {
+        let mut merged: Vec<String> = self.head.trim_end_matches("\r\n").split("\r\n").map(String::from).collect();
+        let updates: Vec<(&str, &str)> = head_lines(head304).filter(|(n, _)| !HOP_BY_HOP.contains(&n.to_ascii_lowercase().as_str())).collect();
+        merged.retain(|l| l.split_once(':').is_none_or(|(n, _)| !updates.iter().any(|(u, _)| u.eq_ignore_ascii_case(n.trim()))));
+        merged.extend(updates.iter().map(|(n, v)| format!("{}: {}", n, v)));
+        let mut entry = Self::from_head(&format!("{}\r\n\r\n", merged.join("\r\n")), request, now())?;
+        entry.body = self.body.clone();
+        Some(entry)
+    }
+
+    fn encode(&self, key: &str) -> Vec<u8> {
+        let mut out = format!(
+            "{}\n{}\n{} {} {} {} {} {} {}\n",
+            DISK_MAGIC, key, self.stored, self.initial_age, self.lifetime, self.no_cache as u8, self.head.len(), self.body.len(), self.vary.len()
+        );
+        for (name, value) in &self.vary {
+            match value {
+                Some(v) => out.push_str(&format!("{}\t{}\n", name, v)),
+                None => out.push_str(&format!("{}\n", name)),
+            }
+        }
+        let mut out = out.into_bytes();
+        out.extend_from_slice(self.head.as_bytes());
+        out.extend_from_slice(&self.body);
+        out
+    }
+
+    fn decode(key: &str, data: &[u8]) -> Option<Self> {
+        let mut rest = data;
+        let mut line = || {
+            let pos = rest.iter().position(|&b| b == b'\n')?;
+            let l = std::str::from_utf8(&rest[..pos]).ok()?;
+            rest = &rest[pos + 1..];
+            Some(l)
+        };
+        if line()? != DISK_MAGIC || line()? != key { return None; }
+        let meta: Vec<u64> = line()?.split(' ').map(|v| v.parse().ok()).collect::<Option<_>>()?;
+        let [stored, initial_age, lifetime, no_cache, head_len, body_len, vary_len] = meta[..] else { return None };
+        let mut vary = Vec::new();
+        for _ in 0..vary_len {
+            let l = line()?;
+            vary.push(match l.split_once('\t') {
+                Some((n, v)) => (n.to_string(), Some(v.to_string())),
+                None => (l.to_string(), None),
+            });
+        }
+        if rest.len() as u64 != head_len + body_len { return None; }
+        let head = std::str::from_utf8(&rest[..head_len as usize]).ok()?.to_string();
+        let first = |name: &str| head_header(&head, name).first().map(|v| v.to_string());
+        let (etag, last_modified) = (first("etag"), first("last-modified"));
+        Some(Self { body: rest[head_len as usize..].to_vec(), head, vary, stored, initial_age, lifetime, no_cache: no_cache != 0, etag, last_modified })
+    }
+}
+
+impl Disk {
+    fn scan(dir: &std::path::Path, limit: u64) -> Self {
+        let mut disk = Self { limit, files: HashMap::new(), total: 0 };
+        for e in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+            let path = e.path();
+            if path.extension().is_none_or(|x| x != "cache") { continue; }
+            let Ok(meta) = e.metadata() else { continue };
+            disk.total += meta.len();
+            disk.files.insert(path, (meta.modified().unwrap_or(UNIX_EPOCH), meta.len()));
+        }
+        disk
+    }
+
+    fn apply(&mut self, op: DiskOp) {
+        match op {
+            DiskOp::Write(path, data) => {
+                // 先写临时文件再改名
+                let tmp = path.with_extension("tmp");
+                if let Err(e) = std::fs::write(&tmp, &data).and_then(|_| std::fs::rename(&tmp, &path)) {
+                    log_error(format!("cache: cannot write {}: {}", path.display(), e));
+                    return;
+                }
+                self.forget(&path);
+                self.total += data.len() as u64;
+                self.files.insert(path.clone(), (SystemTime::now(), data.len() as u64));
+                self.prune(&path);
+            }
+            DiskOp::Remove(path) => {
+                let _ = std::fs::remove_file(&path);
+                self.forget(&path);
+            }
+        }
+    }
+
+    fn forget(&mut self, path: &std::path::Path) {
+        if let Some((_, len)) = self.files.remove(path) { self.total -= len; }
+    }
+
+    fn prune(&mut self, keep: &std::path::Path) {
+        if self.total <= self.limit { return; }
+        let mut files: Vec<(SystemTime, PathBuf)> = self.files.iter().filter(|(p, _)| p.as_path() != keep).map(|(p, (t, _))| (*t, p.clone())).collect();
+        files.sort();
+        for (_, file) in files {
+            if self.total <= self.limit { break; }
+            match std::fs::remove_file(&file) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {}
+                _ => self.forget(&file),
+            }
+        }
+    }
+}
+
+impl Store {
+    // 只查内存；磁盘上的条目由 `load` 在锁外读取
+    fn get(&mut self, key: &str) -> Option<Arc<Entry>> {
+        self.tick += 1;
+        let (entry, used) = self.entries.get_mut(key)?;
+        *used = self.tick;
+        Some(entry.clone())
+    }
+
+    fn insert(&mut self, key: &str, entry: Entry) {
+        if entry.size() > self.cfg.max_object.min(self.cfg.memory) { return; }
+        if let (Some(disk), Some(path)) = (&self.disk, self.disk_path(key)) {
+            let _ = disk.send(DiskOp::Write(path, entry.encode(key)));
+        }
+        self.insert_memory(key, Arc::new(entry));
+    }
+
+    fn insert_memory(&mut self, key: &str, entry: Arc<Entry>) {
+        self.tick += 1;
+        self.bytes += entry.size();
+        if let Some((old, _)) = self.entries.insert(key.to_string(), (entry, self.tick)) {
+            self.bytes -= old.size();
+        }
+        while self.bytes > self.cfg.memory {
+            let Some(oldest) = self.entries.iter().min_by_key(|(_, (_, used))| *used).map(|(k, _)| k.clone()) else { break };
+            if let Some((e, _)) = self.entries.remove(&oldest) { self.bytes -= e.size(); }
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((e, _)) = self.entries.remove(key) { self.bytes -= e.size(); }
+        if let (Some(disk), Some(path)) = (&self.disk, self.disk_path(key)) { let _ = disk.send(DiskOp::Remove(path)); }
+    }
+
+    fn disk_path(&self, key: &str) -> Option<PathBuf> {
+        let dir = self.cfg.dir.as_ref()?;
+        Some(dir.join(format!("{}.cache", blake3::hash(key.as_bytes()).to_hex())))
+    }
+}
+
+pub(crate) enum Lookup {
+    // 未启用缓存或请求不适合缓存
+    Bypass,
+    Miss,
+    Hit(Arc<Entry>),
+    // 已过期，需带验证头向目标确认
+    Revalidate(Arc<Entry>),
+}
+
+// `restricted` 为经 --deny-dest 限制的监听：各自的条目分开存放，避免用不受限监听取回的内容应答受限的客户端
+pub(crate) fn key(authority: &str, path: &str, restricted: bool) -> String {
+    format!("{}http://{}{}", if restricted { "~" } else { "" }, authority.to_ascii_lowercase(), path)
+}
+
+// 写日志用，见 --log-redact
+fn redact_key(key: &str) -> String {
+    let (prefix, rest) = key.split_at(key.find("http://").map_or(0, |i| i + "http://".len()));
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    format!("{}{}{}", prefix, crate::addr::redact_authority(authority), crate::addr::redact_path(path))
+}
+
+// 内存中没有时在阻塞线程池里读磁盘，读到后放回内存
+async fn load(store: &'static Mutex<Store>, key: &str) -> Option<Arc<Entry>> {
+    let path = store.lock().unwrap_or_else(|e| e.into_inner()).disk_path(key)?;
+    let k = key.to_string();
+    let entry = tokio::task::spawn_blocking(move || Entry::decode(&k, &std::fs::read(path).ok()?)).await.ok()??;
+    let entry = Arc::new(entry);
+    store.lock().unwrap_or_else(|e| e.into_inner()).insert_memory(key, entry.clone());
+    Some(entry)
+}
+
+// `headers` 为发往目标的请求头（不含请求行）
+pub(crate) async fn lookup(key: &str, method: &str, headers: &[(String, String)]) -> Lookup {
+    let Some(store) = STORE.get() else { return Lookup::Bypass };
+    if !method.eq_ignore_ascii_case("GET") {
+        // 可能修改资源的请求使对应条目失效
+        if ["POST", "PUT", "DELETE", "PATCH"].iter().any(|m| m.eq_ignore_ascii_case(method)) {
+            store.lock().unwrap_or_else(|e| e.into_inner()).remove(key);
+        }
+        BYPASSED.fetch_add(1, Ordering::Relaxed);
+        return Lookup::Bypass;
+    }
+    let cc = directives(&request_header(headers, "cache-control"));
+    if has_directive(&cc, "no-store") || !request_header(headers, "range").is_empty() {
+        BYPASSED.fetch_add(1, Ordering::Relaxed);
+        return Lookup::Bypass;
+    }
+    let cached = store.lock().unwrap_or_else(|e| e.into_inner()).get(key);
+    let entry = match cached {
+        Some(entry) => Some(entry),
+        None => load(store, key).await,
+    };
+    let entry = entry.filter(|e| e.vary_matches(headers));
+    let Some(entry) = entry else {
+        MISSES.fetch_add(1, Ordering::Relaxed);
+        return Lookup::Miss;
+    };
+    let no_cache = has_directive(&cc, "no-cache") || request_header(headers, "pragma").iter().any(|p| p.eq_ignore_ascii_case("no-cache"));
+    let too_old = directive_secs(&cc, "max-age").is_some_and(|max| entry.age() > max);
+    if entry.fresh() && !no_cache && !too_old {
+        HITS.fetch_add(1, Ordering::Relaxed);
+        SAVED.fetch_add(entry.body.len() as u64, Ordering::Relaxed);
+        return Lookup::Hit(entry);
+    }
+    // 客户端自带条件请求时由它与目标协商，这里不再追加验证头
+    let conditional = ["if-none-match", "if-modified-since"].iter().any(|h| !request_header(headers, h).is_empty());
+    if (entry.etag.is_some() || entry.last_modified.is_some()) && !conditional {
+        return Lookup::Revalidate(entry);
+    }
+    MISSES.fetch_add(1, Ordering::Relaxed);
+    Lookup::Miss
+}
+
+// 向目标验证过期条目
+pub(crate) fn add_validators(entry: &Entry, headers: &mut Vec<(String, String)>) {
+    if let Some(etag) = &entry.etag {
+        headers.push((String::from("If-None-Match"), etag.clone()));
+    }
+    if let Some(lm) = &entry.last_modified {
+        headers.push((String::from("If-Modified-Since"), lm.clone()));
+    }
+}
+
+// 本连接第一个应答的存储任务
+pub(crate) struct Pending {
+    key: String,
+    request: Vec<(String, String)>,
+    revalidating: Option<Arc<Entry>>,
+    entry: Option<Entry>,
+    // 已用缓存内容替换了目标的 304
+    replaced: bool,
+    done: bool,
+}
+
+impl Pending {
+    pub(crate) fn new(key: String, request: &[(String, String)], revalidating: Option<Arc<Entry>>) -> Self {
+        Self { key, request: request.to_vec(), revalidating, entry: None, replaced: false, done: false }
+    }
+
+    fn on_event(&mut self, out: &mut Vec<u8>, event: Event) {
+        if self.done {
+            match event {
+                Event::Head { head: data, .. } | Event::Data(data) | Event::Framing(data) | Event::Raw(data) => out.extend_from_slice(data),
+                Event::End(_) => {}
+            }
+            return;
+        }
+        let Some(store) = STORE.get() else { return };
+        match event {
+            Event::Head { head, .. } => {
+                let text = String::from_utf8_lossy(head);
+                let status = text.split_whitespace().nth(1).unwrap_or("");
+                if status.starts_with('1') {
+                    out.extend_from_slice(head);
+                    return;
+                }
+                if let (Some(old), "304") = (&self.revalidating, status) {
+                    let mut store = store.lock().unwrap_or_else(|e| e.into_inner());
+                    match old.refreshed(&text, &self.request) {
+                        Some(entry) => {
+                            out.extend_from_slice(&entry.response(false));
+                            store.insert(&self.key, entry);
+                        }
+                        None => {
+                            out.extend_from_slice(&old.response(false));
+                            store.remove(&self.key);
+                        }
+                    }
+                    self.replaced = true;
+                    REVALIDATED.fetch_add(1, Ordering::Relaxed);
+                    SAVED.fetch_add(old.body.len() as u64, Ordering::Relaxed);
+                    log_throttled(|| log_info(format!("cache: revalidated {}", redact_key(&self.key))));
+                    return;
+                }
+                if self.revalidating.is_some() { MISSES.fetch_add(1, Ordering::Relaxed); }
+                out.extend_from_slice(head);
+                self.entry = Entry::from_head(&text, &self.request, now());
+                if self.entry.is_none() && self.revalidating.is_some() {
+                    store.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.key);
+                }
+            }
+            Event::Data(data) => {
+                out.extend_from_slice(data);
+                let max = store.lock().unwrap_or_else(|e| e.into_inner()).cfg.max_object;
+                if let Some(entry) = &mut self.entry {
+                    if (entry.body.len() + data.len()) as u64 > max {
+                        self.entry = None;
+                    } else {
+                        entry.body.extend_from_slice(data);
+                    }
+                }
+            }
+            Event::Framing(data) | Event::Raw(data) => out.extend_from_slice(data),
+            Event::End(t) => {
+                self.done = true;
+                if let Some(entry) = self.entry.take().filter(|_| t.complete && !self.replaced) {
+                    store.lock().unwrap_or_else(|e| e.into_inner()).insert(&self.key, entry);
+                }
+            }
+        }
+    }
+}
+
+// 包在客户端连接外：记录本连接第一个应答并在完整收到后存入缓存，验证通过（304）时改为发出缓存内容；
+// 之后的应答以及 `pending` 为 None 时原样透传
+pub(crate) struct Fill<S> {
+    inner: S,
+    framer: Framer,
+    pending: Option<Pending>,
+    out: Vec<u8>,
+    finished: bool,
+}
+
+impl<S> Fill<S> {
+    pub(crate) fn new(inner: S, first_method: &str, pending: Option<Pending>) -> Self {
+        Self { inner, framer: Framer::new(first_method), pending, out: Vec::new(), finished: false }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Fill<S> {
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while !self.out.is_empty() {
+            let n = std::task::ready!(Pin::new(&mut self.inner).poll_write(cx, &self.out))?;
+            if n == 0 { return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into())); }
+            self.out.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Fill<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Fill<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        std::task::ready!(this.poll_drain(cx))?;
+        let Some(pending) = this.pending.as_mut().filter(|p| !p.done) else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+        let out = &mut this.out;
+        this.framer.feed(buf, &mut |e| pending.on_event(out, e));
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) { return Poll::Ready(Err(e)); }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        std::task::ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        // 读到关闭为止的 body 在此结束
+        if !this.finished {
+            this.finished = true;
+            if let Some(pending) = this.pending.as_mut().filter(|p| !p.done) {
+                let out = &mut this.out;
+                this.framer.finish(&mut |e| pending.on_event(out, e));
+            }
+        }
+        std::task::ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+pub(crate) fn render_metrics() -> String {
+    let Some(store) = STORE.get() else { return String::new() };
+    let mut out = String::from("# HELP iface_proxy_cache_requests_total Plain HTTP requests seen by the response cache, by result.\n");
+    out.push_str("# TYPE iface_proxy_cache_requests_total counter\n");
+    for (result, n) in [("hit", &HITS), ("revalidated", &REVALIDATED), ("miss", &MISSES), ("bypass", &BYPASSED)] {
+        out.push_str(&format!("iface_proxy_cache_requests_total{{result=\"{}\"}} {}\n", result, n.load(Ordering::Relaxed)));
+    }
+    out.push_str("# HELP iface_proxy_cache_saved_bytes_total Response body bytes served from the cache instead of the origin.\n");
+    out.push_str("# TYPE iface_proxy_cache_saved_bytes_total counter\n");
+    out.push_str(&format!("iface_proxy_cache_saved_bytes_total {}\n", SAVED.load(Ordering::Relaxed)));
+    let (entries, bytes) = {
+        let s = store.lock().unwrap_or_else(|e| e.into_inner());
+        (s.entries.len(), s.bytes)
+    };
+    out.push_str("# HELP iface_proxy_cache_memory_bytes Bytes held by the in-memory response cache.\n");
+    out.push_str("# TYPE iface_proxy_cache_memory_bytes gauge\n");
+    out.push_str(&format!("iface_proxy_cache_memory_bytes {}\n", bytes));
+    out.push_str("# HELP iface_proxy_cache_entries Responses held by the in-memory response cache.\n");
+    out.push_str("# TYPE iface_proxy_cache_entries gauge\n");
+    out.push_str(&format!("iface_proxy_cache_entries {}\n", entries));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+        headers.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect()
+    }
+
+    fn head(lines: &[&str]) -> String {
+        format!("HTTP/1.1 200 OK\r\n{}\r\n\r\n", lines.iter().map(|l| format!("{}\r\n", l)).collect::<String>().trim_end_matches("\r\n"))
+    }
+
+    #[test]
+    fn http_dates() {
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784111777));
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(parse_http_date("Tue, 29 Feb 2028 12:00:00 GMT"), Some(1835438400));
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("0"), None);
+    }
+
+    #[test]
+    fn freshness_lifetime() {
+        let date = "Date: Sun, 06 Nov 1994 08:49:37 GMT";
+        let lifetime = |lines: &[&str]| Entry::from_head(&head(lines), &[], 784111777).map(|e| e.lifetime);
+        assert_eq!(lifetime(&["Cache-Control: max-age=60, s-maxage=120"]), Some(120));
+        assert_eq!(lifetime(&[date, "Expires: Sun, 06 Nov 1994 09:49:37 GMT"]), Some(3600));
+        // 无效的 Expires 视为已过期，没有验证器则不存
+        assert_eq!(lifetime(&[date, "Expires: 0"]), None);
+        assert_eq!(lifetime(&[date, "Expires: 0", "ETag: \"v1\""]), Some(0));
+        // 启发式：Last-Modified 距 Date 的 10%
+        assert_eq!(lifetime(&[date, "Last-Modified: Sun, 06 Nov 1994 06:49:37 GMT"]), Some(720));
+        assert_eq!(lifetime(&["Cache-Control: max-age=60, no-store"]), None);
+        assert_eq!(lifetime(&["Cache-Control: private, max-age=60"]), None);
+        assert_eq!(lifetime(&["Cache-Control: max-age=60", "Set-Cookie: a=b"]), None);
+        assert_eq!(lifetime(&["Cache-Control: max-age=60", "Vary: *"]), None);
+        let auth = req(&[("Authorization", "Basic eDp5")]);
+        assert!(Entry::from_head(&head(&["Cache-Control: max-age=60"]), &auth, 0).is_none());
+        assert!(Entry::from_head(&head(&["Cache-Control: public, max-age=60"]), &auth, 0).is_some());
+    }
+
+    #[test]
+    fn stored_response_and_vary() {
+        let h = head(&["Cache-Control: max-age=60", "Vary: Accept-Language", "Connection: keep-alive", "Content-Length: 2", "ETag: \"v1\""]);
+        let mut e = Entry::from_head(&h, &req(&[("Accept-Language", "en")]), now()).unwrap();
+        e.body = b"hi".to_vec();
+        let resp = String::from_utf8(e.response(true)).unwrap();
+        assert_eq!(resp, "HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nVary: Accept-Language\r\nETag: \"v1\"\r\nAge: 0\r\nContent-Length: 2\r\nConnection: close\r\n\r\nhi");
+        assert!(e.fresh());
+        assert!(e.vary_matches(&req(&[("accept-language", "en")])));
+        assert!(!e.vary_matches(&req(&[("Accept-Language", "de")])));
+        assert!(!e.vary_matches(&[]));
+
+        let decoded = Entry::decode("k", &e.encode("k")).unwrap();
+        assert_eq!((decoded.head.as_str(), decoded.body.as_slice(), decoded.vary.clone()), (e.head.as_str(), e.body.as_slice(), e.vary.clone()));
+        assert_eq!(decoded.etag.as_deref(), Some("\"v1\""));
+        assert!(Entry::decode("other", &e.encode("k")).is_none());
+    }
+
+    #[test]
+    fn revalidation_merges_headers() {
+        let h = head(&["Cache-Control: no-cache", "ETag: \"v1\"", "X-Old: 1"]);
+        let mut e = Entry::from_head(&h, &[], now()).unwrap();
+        e.body = b"body".to_vec();
+        assert!(!e.fresh());
+        let r = e.refreshed("HTTP/1.1 304 Not Modified\r\nCache-Control: max-age=30\r\nETag: \"v1\"\r\n\r\n", &[]).unwrap();
+        assert!(r.fresh() && r.lifetime == 30 && r.body == b"body");
+        assert!(r.head.starts_with("HTTP/1.1 200 OK\r\n") && r.head.contains("X-Old: 1\r\n") && !r.head.contains("no-cache"));
+        assert!(e.refreshed("HTTP/1.1 304 Not Modified\r\nCache-Control: no-store\r\n\r\n", &[]).is_none());
+    }
+
+    #[test]
+    fn disk_usage_is_tracked_without_rescanning() {
+        let dir = std::env::temp_dir().join(format!("iface-proxy-cache-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("old.cache"), [0u8; 40]).unwrap();
+        std::fs::write(dir.join("other.tmp"), [0u8; 40]).unwrap();
+        let mut disk = Disk::scan(&dir, 100);
+        assert_eq!(disk.total, 40);
+        // 超出上限时先删最旧的，刚写入的保留
+        disk.apply(DiskOp::Write(dir.join("a.cache"), vec![1; 50]));
+        disk.apply(DiskOp::Write(dir.join("b.cache"), vec![2; 30]));
+        assert_eq!(disk.total, 80);
+        assert!(!dir.join("old.cache").exists() && dir.join("a.cache").exists());
+        disk.apply(DiskOp::Write(dir.join("a.cache"), vec![1; 10]));
+        assert_eq!(disk.total, 40);
+        disk.apply(DiskOp::Remove(dir.join("b.cache")));
+        assert_eq!((disk.total, dir.join("b.cache").exists()), (10, false));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
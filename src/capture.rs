@@ -0,0 +1,224 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::Ipv4Addr;
+use std::pin::Pin;
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::dialer::OutboundStream;
+use crate::util::{log_error, log_info};
+
+// 会话抓包：把转发的明文流写成 PCAP（LINKTYPE_RAW，合成 IPv4/TCP 头），便于在 Wireshark 中查看。
+// 客户端固定为 10.0.0.1:<随机端口>，服务端为 10.0.0.2:<目标端口>。
+pub(crate) struct CaptureConfig {
+    pub(crate) dir: String,
+    // 目标主机后缀过滤，空表示全部
+    pub(crate) hosts: Vec<String>,
+    // 同时抓取 CONNECT/SOCKS 隧道（通常是 TLS 密文）
+    pub(crate) tunnels: bool,
+}
+
+static CONFIG: OnceLock<CaptureConfig> = OnceLock::new();
+
+pub(crate) fn install(cfg: CaptureConfig) {
+    let _ = CONFIG.set(cfg);
+}
+
+const CLIENT_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const SERVER_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const LINKTYPE_RAW: u32 = 101;
+const MAX_SEGMENT: usize = 16 * 1024;
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+fn host_matches(hosts: &[String], host: &str) -> bool {
+    hosts.is_empty() || hosts.iter().any(|s| crate::util::host_has_suffix(host, s))
+}
+
+// 根据配置决定是否包一层抓包；`plaintext` 为 false 表示隧道
+pub(crate) fn maybe_wrap(stream: OutboundStream, host: &str, port: u16, plaintext: bool) -> OutboundStream {
+    let Some(cfg) = CONFIG.get() else { return stream };
+    if (!plaintext && !cfg.tunnels) || !host_matches(&cfg.hosts, host) { return stream; }
+    match PcapWriter::create(cfg, host, port) {
+        Ok(w) => Box::new(Captured { inner: stream, pcap: Some(w) }),
+        Err(e) => {
+            log_error(format!("capture: failed to open pcap for {}: {}", crate::addr::redact(host, port), e));
+            stream
+        }
+    }
+}
+
+fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in chunks {
+        let mut it = chunk.chunks_exact(2);
+        for w in &mut it { sum += u16::from_be_bytes([w[0], w[1]]) as u32; }
+        if let [b] = it.remainder() { sum += (*b as u32) << 8; }
+    }
+    while sum >> 16 != 0 { sum = (sum & 0xffff) + (sum >> 16); }
+    !(sum as u16)
+}
+
+struct PcapWriter {
+    out: BufWriter<File>,
+    client_port: u16,
+    server_port: u16,
+    client_seq: u32,
+    server_seq: u32,
+    ip_id: u16,
+}
+
+impl PcapWriter {
+    fn create(cfg: &CaptureConfig, host: &str, port: u16) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&cfg.dir)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let safe_host: String = host.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' }).collect();
+        let path = std::path::Path::new(&cfg.dir).join(format!("{}-{}-{}.pcap", now.as_millis(), safe_host, port));
+        let mut out = BufWriter::new(File::create(&path)?);
+        // global header: magic, v2.4, thiszone, sigfigs, snaplen, network
+        out.write_all(&0xa1b2c3d4u32.to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&4u16.to_le_bytes())?;
+        out.write_all(&0i32.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(&65535u32.to_le_bytes())?;
+        out.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+        // 文件名含目标主机，开启 --log-redact 时日志中只写目录
+        let shown = if crate::addr::redact_host(host) == host { path.display().to_string() } else { cfg.dir.clone() };
+        log_info(format!("capture: {} -> {}", crate::addr::redact(host, port), shown));
+        let client_port = 40000 + (now.subsec_nanos() % 20000) as u16;
+        let mut w = Self { out, client_port, server_port: port, client_seq: 1000, server_seq: 5000, ip_id: 1 };
+        // 合成三次握手
+        w.packet(true, SYN, &[])?;
+        w.client_seq = w.client_seq.wrapping_add(1);
+        w.packet(false, SYN | ACK, &[])?;
+        w.server_seq = w.server_seq.wrapping_add(1);
+        w.packet(true, ACK, &[])?;
+        Ok(w)
+    }
+
+    fn packet(&mut self, from_client: bool, flags: u8, payload: &[u8]) -> std::io::Result<()> {
+        let (src, dst, sport, dport, seq, ack) = if from_client {
+            (CLIENT_IP, SERVER_IP, self.client_port, self.server_port, self.client_seq, self.server_seq)
+        } else {
+            (SERVER_IP, CLIENT_IP, self.server_port, self.client_port, self.server_seq, self.client_seq)
+        };
+        let ack = if flags & ACK != 0 { ack } else { 0 };
+        let mut tcp = [0u8; 20];
+        tcp[0..2].copy_from_slice(&sport.to_be_bytes());
+        tcp[2..4].copy_from_slice(&dport.to_be_bytes());
+        tcp[4..8].copy_from_slice(&seq.to_be_bytes());
+        tcp[8..12].copy_from_slice(&ack.to_be_bytes());
+        tcp[12] = 5 << 4;
+        tcp[13] = flags;
+        tcp[14..16].copy_from_slice(&65535u16.to_be_bytes());
+        let tcp_len = (20 + payload.len()) as u16;
+        let mut pseudo = [0u8; 12];
+        pseudo[0..4].copy_from_slice(&src.octets());
+        pseudo[4..8].copy_from_slice(&dst.octets());
+        pseudo[9] = 6;
+        pseudo[10..12].copy_from_slice(&tcp_len.to_be_bytes());
+        let csum = checksum(&[&pseudo, &tcp, payload]);
+        tcp[16..18].copy_from_slice(&csum.to_be_bytes());
+
+        let mut ip = [0u8; 20];
+        ip[0] = 0x45;
+        ip[2..4].copy_from_slice(&(20 + tcp_len).to_be_bytes());
+        ip[4..6].copy_from_slice(&self.ip_id.to_be_bytes());
+        ip[6] = 0x40; // DF
+        ip[8] = 64;
+        ip[9] = 6;
+        ip[12..16].copy_from_slice(&src.octets());
+        ip[16..20].copy_from_slice(&dst.octets());
+        let csum = checksum(&[&ip]);
+        ip[10..12].copy_from_slice(&csum.to_be_bytes());
+        self.ip_id = self.ip_id.wrapping_add(1);
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let len = (40 + payload.len()) as u32;
+        self.out.write_all(&(now.as_secs() as u32).to_le_bytes())?;
+        self.out.write_all(&now.subsec_micros().to_le_bytes())?;
+        self.out.write_all(&len.to_le_bytes())?;
+        self.out.write_all(&len.to_le_bytes())?;
+        self.out.write_all(&ip)?;
+        self.out.write_all(&tcp)?;
+        self.out.write_all(payload)?;
+        Ok(())
+    }
+
+    fn data(&mut self, from_client: bool, data: &[u8]) -> std::io::Result<()> {
+        for chunk in data.chunks(MAX_SEGMENT) {
+            self.packet(from_client, PSH | ACK, chunk)?;
+            let seq = if from_client { &mut self.client_seq } else { &mut self.server_seq };
+            *seq = seq.wrapping_add(chunk.len() as u32);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.packet(true, FIN | ACK, &[])?;
+        self.client_seq = self.client_seq.wrapping_add(1);
+        self.packet(false, FIN | ACK, &[])?;
+        self.server_seq = self.server_seq.wrapping_add(1);
+        self.packet(true, ACK, &[])?;
+        self.out.flush()
+    }
+}
+
+// 出站连接的写入记为客户端 -> 服务端，读出记为服务端 -> 客户端
+struct Captured {
+    inner: OutboundStream,
+    pcap: Option<PcapWriter>,
+}
+
+impl Captured {
+    fn record(&mut self, from_client: bool, data: &[u8]) {
+        if let Some(w) = &mut self.pcap {
+            if let Err(e) = w.data(from_client, data) {
+                log_error(format!("capture: write failed, stopping capture: {}", e));
+                self.pcap = None;
+            }
+        }
+    }
+}
+
+impl Drop for Captured {
+    fn drop(&mut self) {
+        if let Some(w) = &mut self.pcap {
+            let _ = w.finish();
+        }
+    }
+}
+
+impl AsyncRead for Captured {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if buf.filled().len() > before {
+            let data = buf.filled()[before..].to_vec();
+            self.record(false, &data);
+        }
+        res
+    }
+}
+
+impl AsyncWrite for Captured {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res { self.record(true, &buf[..n]); }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,288 @@
+use anyhow::Result;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+use crate::dialer::OutboundStream;
+use crate::error::ProxyError;
+use crate::util::{log_info, log_throttled};
+
+// 故障注入（开发调试用）：对匹配的直连目标注入建连与收包延迟、限速、随机断开和域名解析失败，
+// 让应用开发者经代理在指定网卡上模拟差网络。只作用于 DirectDialer 的直连出站
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ChaosRule {
+    suffix: String,
+    // 只作用于经该网卡的出站，None 为全部
+    iface: Option<String>,
+    // 建连前、以及每次从目标读到数据后的延迟
+    latency_ms: u64,
+    // 延迟在 [latency, latency + jitter] 中随机
+    jitter_ms: u64,
+    // 每个方向的速率上限（字节/秒）
+    rate: Option<u64>,
+    // 连接在传输前 RESET_WINDOW 字节内的随机位置被重置的概率
+    reset: f64,
+    // 域名解析失败的概率（目标为 IP 时不适用）
+    dns_fail: f64,
+}
+
+// reset 的断开位置在连接的前 64 KiB 内
+const RESET_WINDOW: u64 = 64 * 1024;
+// 单次读写的上限，限速时另按每 100ms 的份额切分，使速率平滑
+const MAX_CHUNK: usize = 16 * 1024;
+
+fn probability(key: &str, v: &str) -> Result<f64> {
+    let p: f64 = v.parse().map_err(|_| anyhow::anyhow!("invalid {} {:?} (expected a probability such as 0.1)", key, v))?;
+    if !(0.0..=1.0).contains(&p) { anyhow::bail!("{} {:?} is not between 0 and 1", key, v); }
+    Ok(p)
+}
+
+impl ChaosRule {
+    // SUFFIX,key=value,...，如 `example.com,latency=300,jitter=100,rate=64KiB,reset=0.1,dns-fail=0.2,iface=en0`
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let mut parts = s.split(',').map(str::trim);
+        let suffix = parts.next().unwrap_or("").trim_start_matches('.').to_ascii_lowercase();
+        if suffix.is_empty() || suffix.contains('=') { anyhow::bail!("chaos rule {:?} must start with a host suffix (or *)", s); }
+        let mut rule = Self { suffix, iface: None, latency_ms: 0, jitter_ms: 0, rate: None, reset: 0.0, dns_fail: 0.0 };
+        for part in parts.filter(|p| !p.is_empty()) {
+            let (key, v) = part.split_once('=').ok_or_else(|| anyhow::anyhow!("invalid chaos option {:?} (expected key=value)", part))?;
+            let ms = || v.trim_end_matches("ms").parse::<u64>().map_err(|_| anyhow::anyhow!("invalid {} {:?} (expected milliseconds)", key, v));
+            match key {
+                "latency" => rule.latency_ms = ms()?,
+                "jitter" => rule.jitter_ms = ms()?,
+                "rate" => {
+                    let rate = crate::quota::parse_size(v.trim_end_matches("/s"))?;
+                    if rate == 0 { anyhow::bail!("chaos rate must be positive"); }
+                    rule.rate = Some(rate);
+                }
+                "reset" => rule.reset = probability(key, v)?,
+                "dns-fail" => rule.dns_fail = probability(key, v)?,
+                "iface" => rule.iface = Some(v.to_string()),
+                _ => anyhow::bail!("unknown chaos option {:?} (expected latency, jitter, rate, reset, dns-fail or iface)", key),
+            }
+        }
+        Ok(rule)
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        let mut out = self.suffix.clone();
+        if let Some(iface) = &self.iface { out.push_str(&format!(" via {}", iface)); }
+        if self.latency_ms > 0 || self.jitter_ms > 0 { out.push_str(&format!(" latency={}ms+{}ms", self.latency_ms, self.jitter_ms)); }
+        if let Some(rate) = self.rate { out.push_str(&format!(" rate={}B/s", rate)); }
+        if self.reset > 0.0 { out.push_str(&format!(" reset={}", self.reset)); }
+        if self.dns_fail > 0.0 { out.push_str(&format!(" dns-fail={}", self.dns_fail)); }
+        out
+    }
+
+    fn delay(&self) -> Duration {
+        let jitter = if self.jitter_ms > 0 { random() % (self.jitter_ms + 1) } else { 0 };
+        Duration::from_millis(self.latency_ms + jitter)
+    }
+
+    // 建连之前：按概率让域名解析失败，并加上一次延迟
+    pub(crate) async fn before_connect(&self, host: &str) -> Result<()> {
+        if self.dns_fail > 0.0 && host.parse::<std::net::IpAddr>().is_err() && chance(self.dns_fail) {
+            INJECTED[0].fetch_add(1, Ordering::Relaxed);
+            log_throttled(|| log_info(format!("chaos: failing DNS lookup of {}", crate::addr::redact_host(host))));
+            return Err(ProxyError::Dns { host: host.to_string(), reason: String::from("injected failure (--chaos)") }.into());
+        }
+        let delay = self.delay();
+        if !delay.is_zero() { tokio::time::sleep(delay).await; }
+        Ok(())
+    }
+}
+
+static RULES: OnceLock<Vec<ChaosRule>> = OnceLock::new();
+// 指标：注入的 dns_fail、reset 次数
+static INJECTED: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+
+pub(crate) fn install(rules: Vec<ChaosRule>) {
+    let _ = RULES.set(rules);
+}
+
+// 按声明顺序取第一条匹配目标与出口网卡的规则
+pub(crate) fn rule_for(host: &str, iface: &str) -> Option<&'static ChaosRule> {
+    RULES.get()?.iter().find(|r| crate::util::host_has_suffix(host, &r.suffix) && r.iface.as_deref().is_none_or(|i| i == iface))
+}
+
+fn random() -> u64 {
+    let mut b = [0u8; 8];
+    let _ = getrandom::getrandom(&mut b);
+    u64::from_ne_bytes(b)
+}
+
+fn chance(p: f64) -> bool {
+    (random() >> 11) as f64 / (1u64 << 53) as f64 <= p && p > 0.0
+}
+
+pub(crate) fn wrap(inner: OutboundStream, rule: &'static ChaosRule) -> OutboundStream {
+    let reset_after = chance(rule.reset).then(|| random() % RESET_WINDOW);
+    let now = Instant::now();
+    Box::new(Chaotic { inner, rule, held: Vec::new(), held_pos: 0, read_timer: None, write_timer: None, next_read: now, next_write: now, reset_after, broken: false })
+}
+
+struct Chaotic {
+    inner: OutboundStream,
+    rule: &'static ChaosRule,
+    // 已从目标读到、延迟结束后才交给调用方的数据
+    held: Vec<u8>,
+    held_pos: usize,
+    read_timer: Option<Pin<Box<Sleep>>>,
+    write_timer: Option<Pin<Box<Sleep>>>,
+    // 限速：下一次允许读/写的时间
+    next_read: Instant,
+    next_write: Instant,
+    // 还能传输多少字节后重置连接
+    reset_after: Option<u64>,
+    // 已重置：之后的读写都失败
+    broken: bool,
+}
+
+impl Chaotic {
+    fn chunk(&self, want: usize) -> usize {
+        let mut n = want.min(MAX_CHUNK);
+        if let Some(rate) = self.rule.rate { n = n.min((rate / 10).max(1) as usize); }
+        if let Some(left) = self.reset_after { n = n.min(left.max(1) as usize); }
+        n
+    }
+
+    // 记一次传输：推后该方向的下一次允许时间，并扣减断开前的剩余字节
+    fn spend(&mut self, n: usize, read: bool) {
+        if let Some(rate) = self.rule.rate {
+            let next = if read { &mut self.next_read } else { &mut self.next_write };
+            *next = (*next).max(Instant::now()) + Duration::from_nanos(n as u64 * 1_000_000_000 / rate);
+        }
+        if let Some(left) = &mut self.reset_after { *left = left.saturating_sub(n as u64); }
+    }
+
+    fn check_reset(&mut self) -> std::io::Result<()> {
+        if self.reset_after == Some(0) {
+            self.reset_after = None;
+            INJECTED[1].fetch_add(1, Ordering::Relaxed);
+            log_throttled(|| log_info("chaos: resetting connection"));
+            self.broken = true;
+        }
+        if self.broken { return Err(std::io::ErrorKind::ConnectionReset.into()); }
+        Ok(())
+    }
+}
+
+fn poll_timer(timer: &mut Option<Pin<Box<Sleep>>>, cx: &mut Context<'_>) -> Poll<()> {
+    if let Some(t) = timer {
+        ready!(t.as_mut().poll(cx));
+        *timer = None;
+    }
+    Poll::Ready(())
+}
+
+impl AsyncRead for Chaotic {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            ready!(poll_timer(&mut this.read_timer, cx));
+            if this.held_pos < this.held.len() {
+                let n = buf.remaining().min(this.held.len() - this.held_pos);
+                buf.put_slice(&this.held[this.held_pos..this.held_pos + n]);
+                this.held_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            this.check_reset()?;
+            if this.next_read > Instant::now() {
+                this.read_timer = Some(Box::pin(tokio::time::sleep_until(this.next_read)));
+                continue;
+            }
+            let cap = this.chunk(buf.remaining());
+            this.held.resize(cap, 0);
+            let mut rb = ReadBuf::new(&mut this.held);
+            let res = Pin::new(&mut this.inner).poll_read(cx, &mut rb);
+            let n = rb.filled().len();
+            this.held.truncate(n);
+            this.held_pos = 0;
+            ready!(res)?;
+            if n == 0 { return Poll::Ready(Ok(())); }
+            this.spend(n, true);
+            let delay = this.rule.delay();
+            if !delay.is_zero() { this.read_timer = Some(Box::pin(tokio::time::sleep(delay))); }
+        }
+    }
+}
+
+impl AsyncWrite for Chaotic {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = &mut *self;
+        loop {
+            ready!(poll_timer(&mut this.write_timer, cx));
+            this.check_reset()?;
+            if this.next_write <= Instant::now() { break; }
+            this.write_timer = Some(Box::pin(tokio::time::sleep_until(this.next_write)));
+        }
+        let cap = this.chunk(buf.len());
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..cap]))?;
+        this.spend(n, false);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+pub(crate) fn render_metrics() -> String {
+    if RULES.get().is_none_or(|r| r.is_empty()) { return String::new(); }
+    let mut out = String::from("# HELP iface_proxy_chaos_injected_total Faults injected by --chaos rules.\n");
+    out.push_str("# TYPE iface_proxy_chaos_injected_total counter\n");
+    for (kind, n) in ["dns_fail", "reset"].iter().zip(&INJECTED) {
+        out.push_str(&format!("iface_proxy_chaos_injected_total{{kind=\"{}\"}} {}\n", kind, n.load(Ordering::Relaxed)));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn parses_rules() {
+        let r = ChaosRule::parse(".Example.com, latency=300ms, jitter=100, rate=64KiB/s, reset=0.1, dns-fail=1, iface=en0").unwrap();
+        assert_eq!((r.suffix.as_str(), r.iface.as_deref(), r.latency_ms, r.jitter_ms, r.rate), ("example.com", Some("en0"), 300, 100, Some(65536)));
+        assert_eq!((r.reset, r.dns_fail), (0.1, 1.0));
+        for bad in ["", "latency=10", "*,latency=soon", "*,reset=2", "*,rate=0", "*,loss=0.1", "*,latency"] {
+            assert!(ChaosRule::parse(bad).is_err(), "{:?} should be rejected", bad);
+        }
+    }
+
+    #[tokio::test]
+    async fn delays_limits_and_resets() {
+        static RULE: OnceLock<ChaosRule> = OnceLock::new();
+        let rule = RULE.get_or_init(|| ChaosRule::parse("*,latency=50,rate=1000").unwrap());
+        let (mut far, near) = tokio::io::duplex(4096);
+        let mut s = wrap(Box::new(near), rule);
+        far.write_all(&[7u8; 300]).await.unwrap();
+        let started = Instant::now();
+        let mut buf = vec![0u8; 300];
+        s.read_exact(&mut buf).await.unwrap();
+        // 每 100ms 的份额为 100 字节：分三次读取，各延迟 50ms，后两次还要等限速，最后一份在 250ms 后才交付
+        assert!(started.elapsed() >= Duration::from_millis(250), "{:?}", started.elapsed());
+
+        // 传输 10 字节后重置：已读到的数据照常交付，之后读写都失败
+        let (mut far, near) = tokio::io::duplex(4096);
+        let now = Instant::now();
+        let mut s = Chaotic { inner: Box::new(near), rule: &RESET_TEST, held: Vec::new(), held_pos: 0, read_timer: None, write_timer: None, next_read: now, next_write: now, reset_after: Some(10), broken: false };
+        assert_eq!(s.write(b"0123456789abc").await.unwrap(), 10);
+        far.write_all(b"reply").await.unwrap();
+        assert_eq!(s.read(&mut buf).await.unwrap_err().kind(), std::io::ErrorKind::ConnectionReset);
+        assert!(s.write(b"x").await.is_err());
+    }
+
+    static RESET_TEST: ChaosRule = ChaosRule { suffix: String::new(), iface: None, latency_ms: 0, jitter_ms: 0, rate: None, reset: 1.0, dns_fail: 0.0 };
+}
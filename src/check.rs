@@ -0,0 +1,171 @@
+use anyhow::Result;
+
+use crate::cli::RunArgs;
+use crate::listener::ListenerSpec;
+
+// check-config 的检查报告：逐项输出 ok/FAIL，最后有失败项则以非零状态退出
+pub(crate) struct Report {
+    pub(crate) failures: usize,
+}
+
+impl Report {
+    pub(crate) fn new() -> Self {
+        Self { failures: 0 }
+    }
+
+    pub(crate) fn item(&mut self, what: &str, res: Result<String>) {
+        match res {
+            Ok(detail) => println!("[ok]   {}: {}", what, detail),
+            Err(e) => {
+                self.failures += 1;
+                println!("[FAIL] {}: {}", what, e);
+            }
+        }
+    }
+}
+
+pub(crate) fn check_iface(name: &str) -> Result<String> {
+    // 出口组只要有一块成员网卡可用就能出站
+    if let Some(members) = crate::balance::members(name) {
+        let results: Vec<(&str, Result<String>)> = members.iter().map(|m| (*m, check_iface(m))).collect();
+        let detail: Vec<String> = results.iter().map(|(m, r)| match r {
+            Ok(s) => format!("{} {}", m, s),
+            Err(e) => format!("{} {}", m, e),
+        }).collect();
+        if results.iter().all(|(_, r)| r.is_err()) { anyhow::bail!("no usable member: {}", detail.join("; ")); }
+        return Ok(format!("group: {}", detail.join("; ")));
+    }
+    let info = crate::util::list_interfaces()?
+        .into_iter()
+        .find(|i| i.name == name)
+        .ok_or_else(|| anyhow::anyhow!("interface not found (see `iface-proxy list-ifaces`)"))?;
+    if !info.is_up { anyhow::bail!("interface is down"); }
+    if info.addrs.is_empty() { anyhow::bail!("interface has no addresses"); }
+    let addrs: Vec<String> = info.addrs.iter().map(|a| a.to_string()).collect();
+    Ok(format!("up [{}]", addrs.join(", ")))
+}
+
+async fn check_bind(spec: &ListenerSpec) -> Result<String> {
+    if let Some(path) = spec.unix_path() {
+        // 不在检查时创建 socket 文件，只确认目录存在且路径未被占用
+        let dir = std::path::Path::new(path).parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(std::path::Path::new("."));
+        if !dir.is_dir() { anyhow::bail!("directory {} does not exist", dir.display()); }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() { anyhow::bail!("{} is in use by another process", path); }
+        return Ok(format!("socket path usable (mode {:04o})", spec.mode.unwrap_or(0o660)));
+    }
+    if spec.kind.is_udp() {
+        let s = tokio::net::UdpSocket::bind(&spec.listen).await?;
+        return Ok(format!("bindable (udp {})", s.local_addr()?));
+    }
+    let l = tokio::net::TcpListener::bind(&spec.listen).await?;
+    Ok(format!("bindable ({})", l.local_addr()?))
+}
+
+async fn check_resolve(host: &str, port: u16) -> Result<String> {
+    let addrs: Vec<String> = crate::hosts::resolve(host, port).await?.iter().map(|a| a.to_string()).collect();
+    if addrs.is_empty() { anyhow::bail!("no addresses"); }
+    Ok(format!("resolves to {}", addrs.join(", ")))
+}
+
+// 在临时 socket 上试设 SO_MARK，确认平台与权限
+fn check_fwmark(mark: u32) -> Result<String> {
+    use std::os::fd::AsRawFd;
+    let sock = std::net::UdpSocket::bind("0.0.0.0:0")?;
+    crate::util::set_so_mark(sock.as_raw_fd(), mark)?;
+    Ok(String::from("settable"))
+}
+
+// 在 IPv4/IPv6 的临时 TCP 与 UDP socket 上试设 DSCP 与 --sockopt，提前发现平台不支持或取值被内核拒绝
+fn check_socket_marks(dscp: Option<u8>, opts: &[crate::util::RawSockOpt]) -> Result<String> {
+    use std::os::fd::AsRawFd;
+    for v6 in [false, true] {
+        let (tcp, udp) = match v6 {
+            false => (tokio::net::TcpSocket::new_v4()?, std::net::UdpSocket::bind("0.0.0.0:0")?),
+            // 没有 IPv6 的主机上只检查 IPv4
+            true => match (tokio::net::TcpSocket::new_v6(), std::net::UdpSocket::bind("[::]:0")) {
+                (Ok(t), Ok(u)) => (t, u),
+                _ => break,
+            },
+        };
+        crate::util::set_socket_marks(tcp.as_raw_fd(), v6, true, dscp, opts)?;
+        crate::util::set_socket_marks(udp.as_raw_fd(), v6, false, dscp, opts)?;
+    }
+    Ok(String::from("settable"))
+}
+
+pub(crate) async fn run_check(args: RunArgs) -> Result<()> {
+    let mut report = Report::new();
+    report.item("config", Ok(args.config.clone().unwrap_or_else(|| String::from("none (command line only)"))));
+
+    let specs = match args.listener_specs() {
+        Ok(specs) => specs,
+        Err(e) => {
+            report.item("listeners", Err(e));
+            Vec::new()
+        }
+    };
+    report.item("shadowsocks", args.ss_config(&specs).map(|ss| if ss.is_some() { String::from("configured") } else { String::from("not configured") }));
+    report.item("dns upstreams", args.dns_upstreams(&specs).map(|u| format!("{} configured", u.len())));
+    if args.fake_ip {
+        report.item("fake-ip", args.fake_ip_pool(&specs).map(|p| p.map(|p| p.describe()).unwrap_or_default()));
+    }
+    crate::balance::install(args.egress_groups.clone());
+    if crate::balance::has_url_test() {
+        report.item("egress test url", args.url_test().map(|t| t.url));
+    }
+    report.item(&format!("iface {}", args.iface), check_iface(&args.iface));
+    if let Some(path) = &args.token_file {
+        report.item(&format!("token file {}", path), crate::token::load(Some(path.clone())).map(|n| format!("{} active token(s)", n)));
+    }
+    if let Some(vrf) = &args.vrf {
+        report.item(&format!("vrf {}", vrf), crate::util::check_vrf(vrf, &args.iface));
+    }
+    if let Some(mark) = args.fwmark {
+        report.item(&format!("fwmark {:#x}", mark), check_fwmark(mark));
+    }
+    if args.dscp.is_some() || !args.sockopts.is_empty() {
+        let mut desc: Vec<String> = args.dscp.map(|d| format!("dscp {}", d)).into_iter().collect();
+        desc.extend(args.sockopts.iter().map(|o| o.describe()));
+        report.item(&format!("socket options {}", desc.join(" ")), check_socket_marks(args.dscp, &args.sockopts));
+    }
+    for spec in &specs {
+        report.item(&format!("listener {}", spec.describe()), check_bind(spec).await);
+        if spec.tls.enabled() {
+            report.item(&format!("listener {} tls", spec.listen), crate::tls::TlsServer::load(&spec.tls).map(|t| t.describe().to_string()));
+        }
+        if let Some(iface) = &spec.iface {
+            report.item(&format!("listener {} iface {}", spec.listen, iface), check_iface(iface));
+        }
+    }
+
+    match args.upstream_table() {
+        Ok(table) => report.item("upstreams", Ok(table.summary())),
+        Err(e) => report.item("upstreams", Err(e)),
+    }
+    if !args.hosts.is_empty() || !args.hosts_files.is_empty() {
+        report.item("hosts", crate::hosts::install(&args.hosts, &args.hosts_files));
+    }
+    if !args.rules.is_empty() || args.rules_file.is_some() || !args.rule_sets.is_empty() {
+        let upstreams: Vec<String> = args.upstreams.iter().map(|u| u.name.clone()).collect();
+        let rules = match crate::ruleset::Imports::load(args.rule_sets.clone(), args.geoip.clone(), args.rule_set_fetch()).await {
+            Ok(imports) => crate::rules::Rules::load(args.rules.clone(), args.rules_file.clone(), imports, upstreams),
+            Err(e) => Err(e),
+        };
+        report.item("rules", rules.map(|r| r.summary()));
+    }
+    if let Some(path) = &args.script {
+        report.item(&format!("script {}", path), crate::script::Engine::load(path).map(|_| String::from("loaded")));
+    }
+    for up in &args.upstreams {
+        report.item(&format!("upstream {} ({}:{})", up.name, up.host, up.port), check_resolve(&up.host, up.port).await);
+        if let Some(iface) = &up.iface {
+            report.item(&format!("upstream {} iface {}", up.name, iface), check_iface(iface));
+        }
+    }
+
+    if report.failures > 0 {
+        anyhow::bail!("{} check(s) failed", report.failures);
+    }
+    println!("config OK");
+    Ok(())
+}
@@ -0,0 +1,764 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use clap::{ArgAction, Args, Parser, Subcommand};
+
+use crate::decompress::DecompressRule;
+use crate::dump::DumpLevel;
+use crate::listener::{parse_listen_addr, parse_socket_mode, ListenerKind, ListenerSpec};
+use crate::quota::UserQuota;
+use crate::rewrite::HeaderRule;
+use crate::shadowsocks::SsConfig;
+use crate::upstream::{Upstream, UpstreamRule, UpstreamTable};
+use crate::util::Cidr;
+
+#[derive(Parser)]
+#[command(
+    name = "iface-proxy",
+    about = "本地 HTTP/HTTPS 与 SOCKS5 代理 (仅 HTTP/1.x)，出站连接绑定到指定网卡",
+    version = crate::build_info::LONG_VERSION,
+    disable_version_flag = true,
+    args_conflicts_with_subcommands = true,
+    args_override_self = true,
+    after_help = "示例:\n  iface-proxy init\n  iface-proxy --config ~/.config/iface-proxy/iface-proxy.conf\n  iface-proxy --iface en0 --socks5 --socks5-listen 127.0.0.1:1081\n  iface-proxy check-config --config iface-proxy.conf\n  iface-proxy self-test --iface en7\n  iface-proxy bench --iface en0 --iface en7 --url https://speed.example/100MB\n  iface-proxy list-ifaces"
+)]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
+    // 不带子命令时等同于 `run`
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+
+    /// 显示版本并退出
+    #[arg(short = 'v', long, action = ArgAction::Version)]
+    version: Option<bool>,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum Command {
+    /// 启动代理（默认）
+    #[command(args_override_self = true)]
+    Run(RunArgs),
+    /// 列出本机网卡及其地址
+    ListIfaces,
+    /// 只解析并校验配置，不启动监听
+    #[command(args_override_self = true)]
+    CheckConfig(RunArgs),
+    /// 管理系统服务（macOS launchd / Linux systemd）
+    #[command(subcommand)]
+    Service(ServiceCommand),
+    /// 交互式生成配置文件
+    Init,
+    /// 在临时端口上启动监听，经自身测试 HTTP GET / CONNECT / SOCKS5 并报告出口 IP
+    #[command(args_override_self = true)]
+    SelfTest(SelfTestArgs),
+    /// 不经代理，直接经各网卡下载同一地址，比较建连、首字节耗时与吞吐
+    Bench(BenchArgs),
+}
+
+#[derive(Args, Clone)]
+pub(crate) struct BenchArgs {
+    /// 参与比较的网卡 (可重复)
+    #[arg(short = 'i', long = "iface", value_name = "NAME", required = true)]
+    pub(crate) ifaces: Vec<String>,
+
+    /// 下载地址 (http:// 或 https://，应答须为 200)
+    #[arg(long, value_name = "URL")]
+    pub(crate) url: String,
+
+    /// 每块网卡下载的次数，结果取平均
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub(crate) rounds: u32,
+
+    /// 每次下载最多持续的秒数，到时停止并按已下载的量计算吞吐
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    pub(crate) max_secs: u64,
+
+    /// 每次下载最多读取的正文大小 (如 50MiB)
+    #[arg(long, value_name = "SIZE", value_parser = crate::quota::parse_size)]
+    pub(crate) max_bytes: Option<u64>,
+}
+
+#[derive(Args, Clone)]
+pub(crate) struct SelfTestArgs {
+    /// 回显出口 IP 的服务（仅 http://，响应正文应为 IP）
+    #[arg(long, value_name = "URL", default_value = "http://api.ipify.org/")]
+    pub(crate) echo_url: String,
+
+    /// CONNECT 测试使用的 TLS 主机
+    #[arg(long, value_name = "HOST:PORT", default_value = "www.cloudflare.com:443")]
+    pub(crate) tls_host: String,
+
+    #[command(flatten)]
+    pub(crate) run: RunArgs,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum ServiceCommand {
+    /// 注册并启动服务
+    Install {
+        /// 服务使用的配置文件
+        #[arg(short = 'c', long)]
+        config: String,
+    },
+    /// 停止并移除服务
+    Uninstall,
+    /// 查看服务状态
+    Status,
+}
+
+#[derive(Args, Clone)]
+pub(crate) struct RunArgs {
+    /// 从配置文件读取参数（命令行参数优先）
+    #[arg(short = 'c', long, value_name = "PATH")]
+    pub(crate) config: Option<String>,
+
+    /// 外发网卡名称
+    #[arg(short = 'i', long, value_name = "NAME", default_value = "en0")]
+    pub(crate) iface: String,
+
+    /// HTTP 代理监听地址 (HTTP/1.x，可重复以启动多个监听；也可为 unix:/path/to.sock)
+    #[arg(short = 'l', long, value_name = "ADDR:PORT", default_value = "127.0.0.1:7890", value_parser = parse_listen_addr)]
+    pub(crate) listen: Vec<String>,
+
+    /// 不启动默认的 HTTP 监听
+    #[arg(long)]
+    pub(crate) no_http: bool,
+
+    /// 追加监听 (KIND: http|socks5|mixed|admin|ss|dns|tcp-forward|udp-forward，可重复)；
+    /// 可带单独覆盖项: ?iface=IF&user=U&pass=P&allow=CIDR,CIDR；tcp-forward/udp-forward 需 target=HOST:PORT；
+    /// http 可加 tls-cert=PEM&tls-key=PEM 成为 HTTPS 代理，再加 client-ca=PEM 要求客户端证书 (client-auth=optional、client-id=cn|san)
+    #[arg(long = "listener", value_name = "KIND=ADDR:PORT[?OPTS]", value_parser = ListenerSpec::parse)]
+    pub(crate) listeners: Vec<ListenerSpec>,
+
+    /// 启用 SOCKS5 代理（同一端口兼容 SOCKS4/4a）
+    #[arg(long)]
+    pub(crate) socks5: bool,
+
+    /// SOCKS5 监听地址，与 --socks5 配合使用 (可重复)
+    #[arg(short = 'S', long, value_name = "ADDR:PORT", default_value = "127.0.0.1:7080", value_parser = parse_listen_addr)]
+    pub(crate) socks5_listen: Vec<String>,
+
+    /// SOCKS5 用户名（与 --socks5-pass 同时设置时启用认证）
+    #[arg(long, value_name = "USER")]
+    pub(crate) socks5_user: Option<String>,
+
+    /// SOCKS5 密码
+    #[arg(long, value_name = "PASS")]
+    pub(crate) socks5_pass: Option<String>,
+
+    /// 代理认证后端，作用于 HTTP、SOCKS5 与混合端口 (监听自身的 user=/pass= 优先)：htpasswd:PATH、command:CMD (标准输入为用户名与密码各一行，退出码 0 通过)、webhook:URL (POST JSON，2xx 通过)
+    #[arg(long = "auth", value_name = "BACKEND", value_parser = crate::auth::AuthBackend::parse)]
+    pub(crate) auth: Option<crate::auth::AuthBackend>,
+
+    /// 混合端口：同一端口自动识别 HTTP/SOCKS5/SOCKS4
+    #[arg(short = 'M', long, value_name = "ADDR:PORT", value_parser = parse_listen_addr)]
+    pub(crate) mixed_listen: Option<String>,
+
+    /// 启用管理接口 (HTTP，仅 GET；也可为 unix:/path/to.sock)
+    #[arg(long, value_name = "ADDR:PORT", value_parser = parse_listen_addr)]
+    pub(crate) admin_listen: Option<String>,
+
+    /// 启用 DNS 转发 (UDP)，查询经绑定网卡发往 --dns-upstream
+    #[arg(long, value_name = "ADDR:PORT", value_parser = parse_listen_addr)]
+    pub(crate) dns_listen: Option<String>,
+
+    /// DNS 转发的上游服务器 (IP[:PORT]，按顺序尝试，可重复)
+    #[arg(long = "dns-upstream", value_name = "IP[:PORT]", value_parser = crate::dns::parse_dns_upstream)]
+    pub(crate) dns_upstreams: Vec<std::net::SocketAddr>,
+
+    /// 静态主机映射 (可重复)，如 git.corp.example=10.0.0.2 或 *.corp.example=10.0.0.1，出站连接与 DNS 转发都先查它
+    #[arg(long = "host", value_name = "NAME=IP[,IP]", value_parser = crate::hosts::HostEntry::parse)]
+    pub(crate) hosts: Vec<crate::hosts::HostEntry>,
+
+    /// 同样先查的 hosts 文件 (可重复，如 /etc/hosts；DNS 转发的上游不会读它)，--host 优先
+    #[arg(long = "hosts-file", value_name = "PATH")]
+    pub(crate) hosts_files: Vec<String>,
+
+    /// 出口网卡只有 IPv6 (如蜂窝网络) 时，把 IPv4 目标嵌入该 NAT64 前缀后连接 (如 64:ff9b::/96)；auto 为按 RFC 7050 自动发现
+    #[arg(long, value_name = "PREFIX|auto", value_parser = crate::nat64::Nat64::parse)]
+    pub(crate) nat64_prefix: Option<crate::nat64::Nat64>,
+
+    /// Fake-IP：DNS 转发对 A 查询返回 --fake-ip-range 中的地址并记住对应域名，连接这些地址时换回域名再路由与解析 (需要 DNS 监听)
+    #[arg(long)]
+    pub(crate) fake_ip: bool,
+
+    /// Fake-IP 地址池 (IPv4 CIDR)
+    #[arg(long, value_name = "CIDR", value_parser = Cidr::parse, default_value = "198.18.0.0/15", requires = "fake_ip")]
+    pub(crate) fake_ip_range: Cidr,
+
+    /// 不使用 Fake-IP、照常转发给上游的域名后缀 (可重复，如 lan、ntp.org)
+    #[arg(long = "fake-ip-exclude", value_name = "SUFFIX", requires = "fake_ip")]
+    pub(crate) fake_ip_exclude: Vec<String>,
+
+    /// UDP 端口转发 (可重复)，如 0.0.0.0:51820=vpn.example.com:51820，经绑定网卡发往目标
+    #[arg(long = "udp-forward", value_name = "LISTEN=HOST:PORT[?OPTS]", value_parser = crate::listener::parse_udp_forward)]
+    pub(crate) udp_forwards: Vec<ListenerSpec>,
+
+    /// TCP 端口转发 (可重复)，如 127.0.0.1:5432=db.example.com:5432，经绑定网卡连接目标
+    #[arg(long = "tcp-forward", value_name = "LISTEN=HOST:PORT[?OPTS]", value_parser = crate::listener::parse_tcp_forward)]
+    pub(crate) tcp_forwards: Vec<ListenerSpec>,
+
+    /// UDP 转发映射的空闲回收时间（秒）
+    #[arg(long, value_name = "SECS", default_value_t = 60)]
+    pub(crate) udp_idle_secs: u64,
+
+    /// unix: 监听的 socket 文件权限 (八进制；单个监听可用 ?mode= 覆盖)
+    #[arg(long, value_name = "OCTAL", default_value = "0660", value_parser = parse_socket_mode)]
+    pub(crate) unix_socket_mode: u32,
+
+    /// Shadowsocks 加密方式
+    #[arg(long, value_name = "METHOD", default_value = "2022-blake3-aes-256-gcm")]
+    pub(crate) ss_method: String,
+
+    /// Shadowsocks 2022 密钥 (base64，配合 --listener ss=ADDR:PORT)
+    #[arg(long, value_name = "BASE64")]
+    pub(crate) ss_password: Option<String>,
+
+    /// 定义上游代理 (ss://METHOD:PASSWORD@HOST:PORT、ws(s)://[USER:PASS@]HOST:PORT[/PATH] 或 ssh://USER@HOST[:PORT]，均可带 ?iface=IF；可重复)
+    #[arg(long = "upstream", value_name = "NAME=URL", value_parser = Upstream::parse)]
+    pub(crate) upstreams: Vec<Upstream>,
+
+    /// 匹配域名后缀的目标经指定上游转发 (* 匹配全部，可重复)
+    #[arg(long = "upstream-rule", value_name = "SUFFIX=NAME", value_parser = UpstreamRule::parse)]
+    pub(crate) upstream_rules: Vec<UpstreamRule>,
+
+    /// 路由规则 (可重复)：[priority=N] 条件... => 动作，如 'suffix:example.com port:443 => upstream:remote'
+    #[arg(long = "rule", value_name = "RULE", value_parser = crate::rules::Rule::parse)]
+    pub(crate) rules: Vec<crate::rules::Rule>,
+
+    /// 路由规则文件 (每行一条，# 开头为注释)，收到 SIGHUP 或管理接口 POST /rules/reload 时重新读取
+    #[arg(long = "rules-file", value_name = "PATH")]
+    pub(crate) rules_file: Option<String>,
+
+    /// 导入 Clash/Surge 规则集 (可重复)：[priority=N] [interval=SECS] [iface=NAME] SOURCE [=> 动作]，SOURCE 为文件或 http(s):// 地址；不带动作时按每行的策略 (DIRECT、REJECT 或上游名)
+    #[arg(long = "rule-set", value_name = "RULESET", value_parser = crate::ruleset::RuleSetSpec::parse)]
+    pub(crate) rule_sets: Vec<crate::ruleset::RuleSetSpec>,
+
+    /// GEOIP 规则用的 CIDR 列表 (可重复)：国家/地区代码=文件或 http(s):// 地址，每行一个 CIDR
+    #[arg(long = "geoip", value_name = "CC=SOURCE", value_parser = crate::ruleset::GeoIpSpec::parse)]
+    pub(crate) geoip: Vec<crate::ruleset::GeoIpSpec>,
+
+    /// 远程规则集与 GeoIP 列表的重新拉取间隔（秒）
+    #[arg(long, value_name = "SECS", default_value_t = 86400)]
+    pub(crate) rule_set_interval_secs: u64,
+
+    /// 拉取远程规则集与 GeoIP 列表的出口网卡 (默认为 --iface)
+    #[arg(long, value_name = "NAME")]
+    pub(crate) rule_set_iface: Option<String>,
+
+    /// 远程规则集的内容与 ETag/Last-Modified 存入该目录，重启时拉取失败也能先用上次的内容
+    #[arg(long, value_name = "DIR")]
+    pub(crate) rule_set_cache_dir: Option<String>,
+
+    /// 规则重新加载后，去向变化 (改为拦截、所走规则被删除等) 的活动会话在 SECS 秒后关闭 (0 为立即；默认不关闭，只报告个数)
+    #[arg(long, value_name = "SECS")]
+    pub(crate) drain_on_reload: Option<u64>,
+
+    /// 路由脚本 (Lua，需以 lua feature 编译)：其中的 route(conn) 为每个出站连接返回去向，文件修改后自动重新加载
+    #[arg(long, value_name = "PATH")]
+    pub(crate) script: Option<String>,
+
+    /// 查找本机客户端的属主 UID 与所属进程，记入日志与 /sessions，并提供给脚本的 conn.uid/conn.process (规则含 uid:/process: 条件时自动开启)
+    #[arg(long)]
+    pub(crate) log_process: bool,
+
+    /// 非回环地址上的监听只接受这些来源 (CIDR，可重复；单个监听的 allow= 优先)
+    #[arg(long = "allow-client", value_name = "CIDR", value_parser = Cidr::parse)]
+    pub(crate) allow_clients: Vec<Cidr>,
+
+    /// 经非回环地址上的监听不允许访问的目标 (CIDR，可重复；默认为本机/内网/链路本地地址)
+    #[arg(long = "deny-dest", value_name = "CIDR", value_parser = Cidr::parse)]
+    pub(crate) deny_dests: Vec<Cidr>,
+
+    /// 不限制非回环监听可访问的目标
+    #[arg(long, conflicts_with = "deny_dests")]
+    pub(crate) no_deny_dest: bool,
+
+    /// 出站 TCP 连接的 MSS 上限 (如 1360、ppp0=1452；带网卡名的只用于该网卡，可重复)，避免 PPPoE/VPN 上路径 MTU 黑洞导致隧道卡住
+    #[arg(long = "tcp-mss", value_name = "[IFACE=]MSS", value_parser = crate::util::TcpMss::parse)]
+    pub(crate) tcp_mss: Vec<crate::util::TcpMss>,
+
+    /// 出站连接的本地端口范围 (如 40000-40999、ppp0=50000-50099；带网卡名的只用于该网卡，可重复)，用于只放行特定源端口的上游防火墙
+    #[arg(long = "source-ports", value_name = "[IFACE=]START-END", value_parser = crate::util::PortRange::parse)]
+    pub(crate) source_ports: Vec<crate::util::PortRange>,
+
+    /// 出站 socket 绑定到该 VRF 设备 (仅 Linux)，再绑定出口网卡的地址作源地址；出口网卡需已加入该 VRF
+    #[arg(long = "vrf", value_name = "DEV")]
+    pub(crate) vrf: Option<String>,
+
+    /// 出站 socket 的 SO_MARK (十进制或 0x 十六进制，仅 Linux，需 root 或 CAP_NET_ADMIN)，配合 ip rule fwmark 做策略路由
+    #[arg(long = "fwmark", value_name = "MARK", value_parser = crate::util::parse_fwmark)]
+    pub(crate) fwmark: Option<u32>,
+
+    /// 出站 socket 的 DSCP 标记 (0-63 或 CS0-CS7、AF11-AF43、EF)，写入 IPv4 的 TOS 与 IPv6 的 Traffic Class，供上游 QoS 设备分类
+    #[arg(long = "dscp", value_name = "DSCP", value_parser = crate::util::parse_dscp)]
+    pub(crate) dscp: Option<u8>,
+
+    /// 出站 socket 额外设置的整数选项 (可重复)，如 SO_PRIORITY=6、TCP_USER_TIMEOUT=30000；无内置名称的写 LEVEL:NAME=VALUE 数字形式，如 6:12=1 (TCP_QUICKACK)
+    #[arg(long = "sockopt", value_name = "[LEVEL:]NAME=VALUE", value_parser = crate::util::RawSockOpt::parse)]
+    pub(crate) sockopts: Vec<crate::util::RawSockOpt>,
+
+    /// 出站 TCP 保活 (秒)：off 或 IDLE[,INTERVAL[,COUNT]]，如 60,15,4；经 NAT 的长连接隧道空闲时不会被悄悄断开。监听可用 keepalive= 覆盖
+    #[arg(long = "tcp-keepalive", value_name = "off|IDLE[,INTERVAL[,COUNT]]", value_parser = crate::util::Keepalive::parse)]
+    pub(crate) tcp_keepalive: Option<crate::util::Keepalive>,
+
+    /// 出站连接启用 TCP Fast Open (仅 Linux，需 net.ipv4.tcp_fastopen 含客户端位)。监听可用 tfo=on|off 覆盖
+    #[arg(long = "tcp-fast-open")]
+    pub(crate) tcp_fast_open: bool,
+
+    /// 按目标主机后缀覆盖出站 TCP 选项 (可重复，先声明先匹配)，如 ssh.example.com=keepalive=30,10,3、example.com=tfo=on&keepalive=off
+    #[arg(long = "tcp-rule", value_name = "SUFFIX=OPTS", value_parser = crate::util::TcpRule::parse)]
+    pub(crate) tcp_rules: Vec<crate::util::TcpRule>,
+
+    /// 允许客户端按请求指定的出口网卡 (逗号分隔或重复)：HTTP 头 X-Iface-Proxy-Egress: en7，SOCKS5 用户名 user@en7
+    #[arg(long = "egress-allow", value_name = "IFACE", value_delimiter = ',')]
+    pub(crate) egress_allow: Vec<String>,
+
+    /// 出口组 (可重复)，如 bond=wlan0:300,usb0:100 或 bond=wlan0,usb0,policy=least：组名可代替任何网卡名，
+    /// 每个出站连接按策略 (rr 轮询、least 最少活动连接、weighted 按带宽权重) 选一块成员网卡；
+    /// 加 sticky=host (或 host+client) 时同一目标主机总是走同一块网卡
+    #[arg(long = "egress-group", value_name = "NAME=IF[:WEIGHT],IF[:WEIGHT]", value_parser = crate::balance::GroupSpec::parse)]
+    pub(crate) egress_groups: Vec<crate::balance::GroupSpec>,
+
+    /// policy=url-test 的出口组测速地址 (http:// 或 https://)，测量经各成员网卡收到应答首字节的耗时
+    #[arg(long, value_name = "URL", default_value = "http://www.gstatic.com/generate_204")]
+    pub(crate) egress_test_url: String,
+
+    /// url-test 测速间隔（秒）
+    #[arg(long, value_name = "SECS", default_value_t = 60)]
+    pub(crate) egress_test_interval_secs: u64,
+
+    /// url-test 切换成员的滞后：新的最快成员须比当前成员快出这么多毫秒才切换，避免来回跳
+    #[arg(long, value_name = "MS", default_value_t = 50)]
+    pub(crate) egress_test_tolerance_ms: u64,
+
+    /// 访问控制审计模式：allow/--allow-client/--deny-dest 命中时只记录日志与计数，不拒绝连接
+    #[arg(long)]
+    pub(crate) acl_audit: bool,
+
+    /// 最大并发连接数（所有监听共享；单个监听可用 ?max-conns=N 再加一层上限）
+    #[arg(long, value_name = "N", default_value_t = 10000, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub(crate) max_conns: usize,
+
+    /// 并发连接达到上限时，新连接最多排队等待的毫秒数；0 为立即丢弃
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    pub(crate) max_conns_wait_ms: u64,
+
+    /// 已接入但尚未读完请求头/握手的连接数上限（HTTP、混合端口与 WebSocket 隧道监听共享），超出时直接关闭新连接
+    #[arg(long, value_name = "N", default_value_t = 1024, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub(crate) max_handshakes: usize,
+
+    /// 单个来源 IP 已接入但尚未读完请求的连接数上限（回环来源不限），0 为不限制
+    #[arg(long, value_name = "N", default_value_t = 64)]
+    pub(crate) max_handshakes_per_ip: usize,
+
+    /// 读客户端请求头时的最低速率（字节/秒），收到首字节 1 秒后按平均速率检查，过慢则断开；0 为不检查
+    #[arg(long, value_name = "BYTES", default_value_t = 128)]
+    pub(crate) header_min_rate: u64,
+
+    /// 单个请求头部行的最大字节数（请求行不受限），超出时回 431
+    #[arg(long, value_name = "BYTES", default_value_t = crate::head::DEFAULT_MAX_LINE, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub(crate) max_header_line: usize,
+
+    /// 请求头部的最大个数，超出时回 431
+    #[arg(long, value_name = "N", default_value_t = crate::head::DEFAULT_MAX_FIELDS, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub(crate) max_headers: usize,
+
+    /// 握手/请求头读取超时（毫秒）
+    #[arg(long, value_name = "MS", default_value_t = 10000)]
+    pub(crate) read_timeout_ms: u64,
+
+    /// 会话总时长上限（毫秒）
+    #[arg(long, value_name = "MS", default_value_t = 600_000)]
+    pub(crate) session_timeout_ms: u64,
+
+    /// 会话超过该秒数没有收发数据即关闭（管理接口 /sessions 的 idle_s 列）；0 为不限制
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    pub(crate) session_idle_secs: u64,
+
+    /// 认证用户的流量配额，超出后拒绝新连接 (如 alice=10GiB/month、*=500MiB/day，可重复)
+    #[arg(long = "user-quota", value_name = "USER=SIZE/PERIOD", value_parser = UserQuota::parse)]
+    pub(crate) user_quotas: Vec<UserQuota>,
+
+    /// 管理接口签发的 Bearer 令牌（只存哈希）保存到该文件，重启后仍有效；不设置时令牌只在内存中
+    #[arg(long = "token-file", value_name = "PATH")]
+    pub(crate) token_file: Option<String>,
+
+    /// 同一来源 IP 在 --auth-ban-window-secs 内认证失败达到该次数后临时封禁 (HTTP/SOCKS5/混合端口/WebSocket 隧道，回环地址除外)；0 为关闭
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub(crate) auth_ban_threshold: u32,
+
+    /// 认证失败计数的时间窗口 (秒)
+    #[arg(long, value_name = "SECS", default_value_t = 600, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) auth_ban_window_secs: u64,
+
+    /// 封禁时长 (秒)，期间该来源的新连接在接入后直接关闭
+    #[arg(long, value_name = "SECS", default_value_t = 600, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) auth_ban_secs: u64,
+
+    /// 明文 HTTP 请求头改写 (ACTION: add|set|remove，如 *=remove:X-Forwarded-For，按顺序应用，可重复)
+    #[arg(long = "header-rule", value_name = "SUFFIX=ACTION:NAME[=VALUE]", value_parser = HeaderRule::parse)]
+    pub(crate) header_rules: Vec<HeaderRule>,
+
+    /// 明文 HTTP 应答解压 (MODE: decode 解压 gzip/deflate/br 后转发，strip 删除请求的 Accept-Encoding；默认 decode，可重复)
+    #[arg(long = "decompress", value_name = "SUFFIX[=MODE]", value_parser = DecompressRule::parse)]
+    pub(crate) decompress: Vec<DecompressRule>,
+
+    /// 启用明文 HTTP GET 应答缓存并设置内存上限 (如 64MiB)
+    #[arg(long, value_name = "SIZE", value_parser = crate::quota::parse_size)]
+    pub(crate) cache_size: Option<u64>,
+
+    /// 缓存同时写入该目录，重启后仍可命中
+    #[arg(long, value_name = "DIR", requires = "cache_size")]
+    pub(crate) cache_dir: Option<String>,
+
+    /// 磁盘缓存上限，超出时删除最旧的条目
+    #[arg(long, value_name = "SIZE", value_parser = crate::quota::parse_size, default_value = "1GiB")]
+    pub(crate) cache_disk_size: u64,
+
+    /// 单个应答 (头部加 body) 超过该大小时不缓存
+    #[arg(long, value_name = "SIZE", value_parser = crate::quota::parse_size, default_value = "8MiB")]
+    pub(crate) cache_max_object: u64,
+
+    /// 明文 HTTP 请求追加 Via 头 (带本实例标识，同时用于回环检测)
+    #[arg(long)]
+    pub(crate) add_via: bool,
+
+    /// 明文 HTTP 请求追加 RFC 7239 Forwarded 头 (含客户端地址)
+    #[arg(long)]
+    pub(crate) add_forwarded: bool,
+
+    /// 关闭 HTTP 请求的严格检查 (重复 Host、Content-Length/Transfer-Encoding 冲突、裸 CR/LF 等)，兼容不规范的客户端
+    #[arg(long)]
+    pub(crate) lenient: bool,
+
+    /// 明文 HTTP 的 GET/HEAD 在连接失败或目标未应答即断开时不重试 (默认换一个解析地址重试一次)
+    #[arg(long)]
+    pub(crate) no_retry: bool,
+
+    /// CONNECT 允许的目标端口 (PORT 或 LO-HI，逗号分隔或重复；`*` 为不限制)，其余端口返回 403
+    #[arg(long = "connect-ports", value_name = "PORTS", value_delimiter = ',', default_value = "443,8443", value_parser = crate::http_proxy::parse_port_range)]
+    pub(crate) connect_ports: Vec<(u16, u16)>,
+
+    /// 代理自身响应 (CONNECT 的 200、407 与错误页) 中 Proxy-Agent 头的取值 (默认 iface-proxy/版本号)
+    #[arg(long = "proxy-agent", value_name = "VALUE", value_parser = crate::http_proxy::parse_agent, conflicts_with = "no_proxy_agent")]
+    pub(crate) proxy_agent: Option<String>,
+
+    /// 代理自身响应中不发送 Proxy-Agent 头，隐藏代理软件标识
+    #[arg(long)]
+    pub(crate) no_proxy_agent: bool,
+
+    /// 追加到 CONNECT 的 200 Connection Established 响应中的头 (NAME: VALUE，可重复)
+    #[arg(long = "connect-header", value_name = "NAME: VALUE", value_parser = crate::http_proxy::parse_connect_header)]
+    pub(crate) connect_headers: Vec<(String, String)>,
+
+    /// 把明文 HTTP 会话写成 PCAP 文件到该目录 (调试用，默认关闭)
+    #[arg(long, value_name = "DIR")]
+    pub(crate) capture_dir: Option<String>,
+
+    /// 只抓取匹配该域名后缀的目标 (可重复)
+    #[arg(long = "capture-host", value_name = "SUFFIX", requires = "capture_dir")]
+    pub(crate) capture_hosts: Vec<String>,
+
+    /// 同时抓取 CONNECT/SOCKS 隧道 (通常为 TLS 密文)
+    #[arg(long, requires = "capture_dir")]
+    pub(crate) capture_tunnels: bool,
+
+    /// 故障注入 (测试用)：对匹配的直连目标注入延迟、限速、随机断开与解析失败，
+    /// 如 `example.com,latency=300,jitter=100,rate=64KiB,reset=0.1,dns-fail=0.2,iface=en0` (可重复，按顺序取第一条匹配)
+    #[arg(long = "chaos", value_name = "SUFFIX,OPTS", value_parser = crate::chaos::ChaosRule::parse)]
+    pub(crate) chaos: Vec<crate::chaos::ChaosRule>,
+
+    /// 录制 HTTP/SOCKS5 会话中客户端连接上的原始字节，会话出错时写入该目录，可用 replay 工具重放 (调试用，默认关闭)
+    #[arg(long, value_name = "DIR")]
+    pub(crate) record_dir: Option<String>,
+
+    /// 只保存目标匹配该域名后缀的会话 (可重复)
+    #[arg(long = "record-host", value_name = "SUFFIX", requires = "record_dir")]
+    pub(crate) record_hosts: Vec<String>,
+
+    /// 每个会话最多录制的字节数 (两个方向合计)
+    #[arg(long, value_name = "BYTES", default_value_t = 1 << 20, requires = "record_dir")]
+    pub(crate) record_max_bytes: usize,
+
+    /// 每个会话结束时向该文件追加一行 JSON 访问日志；跨天后改名为 PATH.YYYY-MM-DD 并在后台 gzip
+    #[arg(long, value_name = "PATH")]
+    pub(crate) access_log: Option<String>,
+
+    /// 访问日志归档保留的天数 (0 表示不按天数删除)
+    #[arg(long, value_name = "DAYS", default_value_t = 7, requires = "access_log")]
+    pub(crate) access_log_max_days: u32,
+
+    /// 访问日志归档合计大小上限，超出时从最旧的开始删除 (如 500MiB，默认不限)
+    #[arg(long, value_name = "SIZE", value_parser = crate::quota::parse_size, requires = "access_log")]
+    pub(crate) access_log_max_size: Option<u64>,
+
+    /// 访问日志另外送到的输出，取值同 --log-sink (console 为打印到标准输出)；可不配 --access-log 单独使用
+    #[arg(long, value_name = "SINK", value_parser = crate::logsink::SinkSpec::parse)]
+    pub(crate) access_log_sink: Option<crate::logsink::SinkSpec>,
+
+    /// 通过 mDNS/DNS-SD 在局域网上通告 HTTP (`_http-proxy._tcp`) 与 SOCKS5 (`_socks._tcp`) 监听，
+    /// 便于手机等设备自动发现 (只通告非回环地址上的监听)
+    #[arg(long)]
+    pub(crate) mdns: bool,
+
+    /// mDNS 通告的服务名 (默认 `iface-proxy on 主机名`)
+    #[arg(long, value_name = "NAME", requires = "mdns")]
+    pub(crate) mdns_name: Option<String>,
+
+    /// 只在这些网卡上通告 (可重复，默认所有已启用的非回环网卡)
+    #[arg(long = "mdns-iface", value_name = "IFACE", requires = "mdns")]
+    pub(crate) mdns_ifaces: Vec<String>,
+
+    /// 为非回环地址上的监听向网关请求同端口的映射 (NAT-PMP 或 UPnP)，定期续期，退出时删除
+    #[arg(long)]
+    pub(crate) port_map: bool,
+
+    /// 端口映射方式：auto (先试 NAT-PMP，不支持时改用 UPnP)、natpmp、upnp
+    #[arg(long, value_name = "METHOD", default_value = "auto", value_parser = crate::portmap::Method::parse, requires = "port_map")]
+    pub(crate) port_map_method: crate::portmap::Method,
+
+    /// 请求的映射租期 (秒)，租期过半时续期
+    #[arg(long, value_name = "SECS", default_value_t = 3600, value_parser = clap::value_parser!(u32).range(60..), requires = "port_map")]
+    pub(crate) port_map_lifetime: u32,
+
+    /// 网关地址 (默认取默认路由或监听地址所在网卡的网关)
+    #[arg(long, value_name = "IP", requires = "port_map")]
+    pub(crate) port_map_gateway: Option<std::net::Ipv4Addr>,
+
+    /// 把明文 HTTP 的请求/响应写入 --dump-http-file (headers: 请求行/状态行与头部；full: 另含 body)
+    #[arg(long, value_name = "LEVEL", value_parser = DumpLevel::parse)]
+    pub(crate) dump_http: Option<DumpLevel>,
+
+    /// --dump-http 的输出文件 (追加写入)
+    #[arg(long, value_name = "PATH", default_value = "iface-proxy-http.log")]
+    pub(crate) dump_http_file: String,
+
+    /// full 模式下每条消息最多记录的 body 字节数
+    #[arg(long, value_name = "BYTES", default_value_t = 4096)]
+    pub(crate) dump_http_body_max: usize,
+
+    /// 应用日志的输出：console (默认，终端)、syslog (本机 /dev/log)、syslog:unix:PATH、syslog:udp:HOST[:PORT] 或 journald
+    #[arg(long, value_name = "SINK", value_parser = crate::logsink::SinkSpec::parse)]
+    pub(crate) log_sink: Option<crate::logsink::SinkSpec>,
+
+    /// 日志中的目标主机名与 IP：off 原样，hash 按本次运行的随机密钥散列，truncate 只留注册域名、IPv4 /24 或 IPv6 /48 (两者都隐去 HTTP 路径)
+    #[arg(long, value_name = "MODE", value_parser = crate::addr::Redact::parse)]
+    pub(crate) log_redact: Option<crate::addr::Redact>,
+
+    /// 每隔 N 秒在日志中输出流量最多的目标主机 (0 为关闭)
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    pub(crate) stats_interval_secs: u64,
+
+    /// 定时把 /metrics 中的指标经 UDP 推送到 StatsD/DogStatsD (HOST:PORT)，用于没有 Prometheus 抓取的环境
+    #[arg(long, value_name = "HOST:PORT")]
+    pub(crate) statsd: Option<String>,
+
+    /// StatsD 指标名前缀
+    #[arg(long, value_name = "PREFIX", default_value = "iface_proxy", requires = "statsd")]
+    pub(crate) statsd_prefix: String,
+
+    /// 推送格式：dogstatsd (带标签，默认) 或 statsd (标签值拼进指标名)
+    #[arg(long, value_name = "FORMAT", default_value = "dogstatsd", value_parser = crate::statsd::Format::parse, requires = "statsd")]
+    pub(crate) statsd_format: crate::statsd::Format,
+
+    /// 附加到每个指标上的标签 KEY:VALUE (可重复，仅 dogstatsd；默认带上 iface:出口网卡)
+    #[arg(long = "statsd-tag", value_name = "KEY:VALUE", value_parser = crate::statsd::parse_tag, requires = "statsd")]
+    pub(crate) statsd_tags: Vec<(String, String)>,
+
+    /// 推送间隔 (秒)
+    #[arg(long, value_name = "SECS", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..), requires = "statsd")]
+    pub(crate) statsd_interval_secs: u64,
+
+    /// 每隔 N 秒在日志中输出一行运行概况：新建会话/秒、活动会话、上下行速率、出错比例 (0 为关闭)
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    pub(crate) summary_interval_secs: u64,
+
+    /// 出站连接所用的本地地址从网卡上消失 (DHCP 换地址、VPN 重连) 时如何处理这些会话：keep 只记日志，drain 宽限期后关闭，kill 立即关闭
+    #[arg(long, value_name = "POLICY", value_parser = crate::ifwatch::Policy::parse)]
+    pub(crate) on_iface_change: Option<crate::ifwatch::Policy>,
+
+    /// 检查网卡地址的间隔（秒）
+    #[arg(long, value_name = "SECS", default_value_t = 2, requires = "on_iface_change")]
+    pub(crate) iface_watch_secs: u64,
+
+    /// drain 策略的宽限期（秒）
+    #[arg(long, value_name = "SECS", default_value_t = 30, requires = "on_iface_change")]
+    pub(crate) iface_drain_secs: u64,
+
+    /// 出口探测目标 (可重复)，定时经各网卡 TCP 建连测量耗时与失败率
+    #[arg(long = "probe-target", value_name = "HOST:PORT")]
+    pub(crate) probe_targets: Vec<String>,
+
+    /// 参与探测的网卡 (逗号分隔或重复；默认为 --iface、各监听与上游的网卡及 --egress-allow)
+    #[arg(long = "probe-iface", value_name = "IFACE", value_delimiter = ',')]
+    pub(crate) probe_ifaces: Vec<String>,
+
+    /// 探测间隔（秒）
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    pub(crate) probe_interval_secs: u64,
+
+    /// 计算平均耗时与失败率的最近探测次数
+    #[arg(long, value_name = "N", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..=1000))]
+    pub(crate) probe_window: u64,
+
+    /// 定时输出时列出的主机数
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub(crate) stats_top: usize,
+
+    /// 启动时从该文件累加历史统计，退出时写回
+    #[arg(long, value_name = "PATH")]
+    pub(crate) stats_file: Option<String>,
+
+    /// 所有监听 bind 完成后写入该文件，每行 `KIND 实际地址`（端口为 0 时可据此获知系统分配的端口）
+    #[arg(long, value_name = "PATH")]
+    pub(crate) ready_file: Option<String>,
+
+    /// 以 root 启动时，在 bind 完成后切换到该用户运行
+    #[arg(long, value_name = "NAME")]
+    pub(crate) user: Option<String>,
+
+    /// 配合 --user 指定运行组 (默认: 用户的主组)
+    #[arg(long, value_name = "NAME", requires = "user")]
+    pub(crate) group: Option<String>,
+
+    /// 配合 --user，降权后保留 CAP_NET_RAW/CAP_NET_ADMIN (仅 Linux)
+    #[arg(long, requires = "user")]
+    pub(crate) keep_caps: bool,
+}
+
+impl Cli {
+    fn config_path(&self) -> Option<&str> {
+        match &self.command {
+            None => self.run.config.as_deref(),
+            Some(Command::Run(a)) | Some(Command::CheckConfig(a)) => a.config.as_deref(),
+            Some(Command::SelfTest(a)) => a.run.config.as_deref(),
+            Some(_) => None,
+        }
+    }
+}
+
+// 配置文件中的参数插在子命令名之后、其余命令行参数之前，后出现的同名参数覆盖前者
+pub(crate) fn parse(mut raw: Vec<String>) -> Result<Cli> {
+    let cli = Cli::parse_from(&raw);
+    let Some(path) = cli.config_path().map(str::to_string) else { return Ok(cli) };
+    let pos = match raw.get(1).map(String::as_str) {
+        Some("run") | Some("check-config") | Some("self-test") => 2,
+        _ => 1,
+    };
+    raw.splice(pos..pos, crate::config::load_config_args(&path)?);
+    match Cli::try_parse_from(&raw) {
+        Ok(cli) => Ok(cli),
+        Err(e) => {
+            let _ = e.print();
+            eprintln!("(while applying config {})", path);
+            std::process::exit(e.exit_code());
+        }
+    }
+}
+
+impl RunArgs {
+    // 所有监听统一由 ListenerSpec 列表描述
+    pub(crate) fn listener_specs(&self) -> Result<Vec<ListenerSpec>> {
+        let mut specs = Vec::new();
+        if !self.no_http {
+            specs.extend(self.listen.iter().map(|addr| ListenerSpec::new(ListenerKind::Http, addr.clone())));
+        }
+        if self.socks5 {
+            specs.extend(self.socks5_listen.iter().map(|addr| ListenerSpec::new(ListenerKind::Socks5, addr.clone())));
+        }
+        if let Some(addr) = &self.mixed_listen { specs.push(ListenerSpec::new(ListenerKind::Mixed, addr.clone())); }
+        if let Some(addr) = &self.admin_listen { specs.push(ListenerSpec::new(ListenerKind::Admin, addr.clone())); }
+        if let Some(addr) = &self.dns_listen { specs.push(ListenerSpec::new(ListenerKind::Dns, addr.clone())); }
+        specs.extend(self.tcp_forwards.iter().cloned());
+        specs.extend(self.udp_forwards.iter().cloned());
+        specs.extend(self.listeners.iter().cloned());
+        for spec in specs.iter_mut().filter(|s| s.unix_path().is_some()) {
+            spec.mode.get_or_insert(self.unix_socket_mode);
+        }
+        crate::listener::validate_specs(&specs)?;
+        Ok(specs)
+    }
+
+    pub(crate) fn ss_config(&self, specs: &[ListenerSpec]) -> Result<Option<Arc<SsConfig>>> {
+        let ss = match &self.ss_password {
+            Some(password) => Some(Arc::new(SsConfig::new(&self.ss_method, password)?)),
+            None => None,
+        };
+        if ss.is_none() && specs.iter().any(|s| s.kind == ListenerKind::Shadowsocks) {
+            anyhow::bail!("shadowsocks listener requires --ss-password");
+        }
+        Ok(ss)
+    }
+
+    // 未指定 --deny-dest 时使用内置的内网地址列表
+    pub(crate) fn deny_dest(&self) -> Vec<Cidr> {
+        if self.no_deny_dest {
+            Vec::new()
+        } else if self.deny_dests.is_empty() {
+            crate::util::default_deny_dest()
+        } else {
+            self.deny_dests.clone()
+        }
+    }
+
+    pub(crate) fn dns_upstreams(&self, specs: &[ListenerSpec]) -> Result<Arc<Vec<std::net::SocketAddr>>> {
+        if self.dns_upstreams.is_empty() && specs.iter().any(|s| s.kind == ListenerKind::Dns) {
+            anyhow::bail!("dns listener requires --dns-upstream");
+        }
+        Ok(Arc::new(self.dns_upstreams.clone()))
+    }
+
+    pub(crate) fn fake_ip_pool(&self, specs: &[ListenerSpec]) -> Result<Option<crate::fakeip::Pool>> {
+        if !self.fake_ip { return Ok(None); }
+        if !specs.iter().any(|s| s.kind == ListenerKind::Dns) { anyhow::bail!("--fake-ip requires a dns listener (--dns-listen)"); }
+        crate::fakeip::Pool::new(self.fake_ip_range, self.fake_ip_exclude.clone()).map(Some)
+    }
+
+    // 未指定 --probe-iface 时探测所有会用到的出口网卡
+    pub(crate) fn probe_ifaces(&self, specs: &[ListenerSpec]) -> Vec<String> {
+        if !self.probe_ifaces.is_empty() { return self.probe_ifaces.clone(); }
+        let mut out = vec![self.iface.clone()];
+        let extra = specs.iter().filter_map(|s| s.iface.clone())
+            .chain(self.upstreams.iter().filter_map(|u| u.iface.clone()))
+            .chain(self.egress_allow.iter().cloned());
+        for iface in extra {
+            if !out.contains(&iface) { out.push(iface); }
+        }
+        // 出口组探测各成员网卡
+        let mut expanded = Vec::new();
+        for iface in out {
+            let members: Vec<String> = match self.egress_groups.iter().find(|g| g.name == iface) {
+                Some(g) => g.members().map(String::from).collect(),
+                None => vec![iface],
+            };
+            for m in members {
+                if !expanded.contains(&m) { expanded.push(m); }
+            }
+        }
+        expanded
+    }
+
+    pub(crate) fn rule_set_fetch(&self) -> crate::ruleset::FetchOptions {
+        crate::ruleset::FetchOptions {
+            iface: self.rule_set_iface.clone().unwrap_or_else(|| self.iface.clone()),
+            interval: std::time::Duration::from_secs(self.rule_set_interval_secs.max(1)),
+            cache_dir: self.rule_set_cache_dir.as_ref().map(std::path::PathBuf::from),
+        }
+    }
+
+    pub(crate) fn url_test(&self) -> Result<crate::balance::UrlTest> {
+        crate::ruleset::Url::parse(&self.egress_test_url).map_err(|e| anyhow::anyhow!("--egress-test-url: {}", e))?;
+        Ok(crate::balance::UrlTest {
+            url: self.egress_test_url.clone(),
+            interval: std::time::Duration::from_secs(self.egress_test_interval_secs.max(1)),
+            tolerance_ms: self.egress_test_tolerance_ms,
+        })
+    }
+
+    pub(crate) fn upstream_table(&self) -> Result<UpstreamTable> {
+        UpstreamTable::new(self.upstreams.clone(), self.upstream_rules.clone())
+    }
+}
@@ -0,0 +1,35 @@
+use anyhow::Result;
+
+// 配置文件格式：每行一个 `key = value`，key 为去掉 `--` 前缀的长参数名；
+// 只有 key 的行表示开关参数（如 `socks5`），`#` 开头的行为注释。
+// 解析结果是一组等价的命令行参数，由 main 放在真实命令行参数之前。
+pub(crate) fn load_config_args(path: &str) -> Result<Vec<String>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read config {}: {}", path, e))?;
+    parse_config_args(&text)
+}
+
+pub(crate) fn parse_config_args(text: &str) -> Result<Vec<String>> {
+    let mut out = Vec::new();
+    for (idx, raw) in text.lines().enumerate() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        match line.split_once('=') {
+            Some((key, value)) => {
+                let key = key.trim();
+                if key.is_empty() { anyhow::bail!("config line {}: missing key", idx + 1); }
+                out.push(format!("--{}={}", key, unquote(value.trim())));
+            }
+            None => out.push(format!("--{}", line)),
+        }
+    }
+    Ok(out)
+}
+
+fn unquote(value: &str) -> &str {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    }
+}
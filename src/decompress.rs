@@ -0,0 +1,380 @@
+use anyhow::Result;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::response::{Event, Framer};
+
+// 明文 HTTP 应答的压缩处理，面向无法处理压缩内容的客户端：
+// decode 在转发前解压 gzip/deflate/br 应答（改为 chunked 发给客户端），strip 则删除请求的 Accept-Encoding
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Mode {
+    Decode,
+    Strip,
+}
+
+// SUFFIX[=MODE]，如 `*`、`legacy.example.com=strip`；SUFFIX 匹配目标主机及其子域名，`*` 匹配全部
+#[derive(Clone, Debug)]
+pub(crate) struct DecompressRule {
+    pub(crate) suffix: String,
+    pub(crate) mode: Mode,
+}
+
+impl DecompressRule {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (suffix, mode) = match s.split_once('=') {
+            Some((suffix, mode)) => (suffix, mode.trim()),
+            None => (s, "decode"),
+        };
+        let mode = match mode.to_ascii_lowercase().as_str() {
+            "decode" => Mode::Decode,
+            "strip" => Mode::Strip,
+            _ => anyhow::bail!("unknown decompress mode {:?} (expected decode or strip)", mode),
+        };
+        let suffix = suffix.trim().trim_start_matches('.').to_ascii_lowercase();
+        if suffix.is_empty() { anyhow::bail!("decompress rule {:?} is missing a host suffix", s); }
+        Ok(Self { suffix, mode })
+    }
+
+    fn matches_host(&self, host: &str) -> bool {
+        crate::util::host_has_suffix(host, &self.suffix)
+    }
+}
+
+static RULES: OnceLock<Vec<DecompressRule>> = OnceLock::new();
+
+pub(crate) fn install(rules: Vec<DecompressRule>) {
+    let _ = RULES.set(rules);
+}
+
+// 按声明顺序取第一条匹配的规则
+pub(crate) fn mode_for(host: &str) -> Option<Mode> {
+    RULES.get()?.iter().find(|r| r.matches_host(host)).map(|r| r.mode)
+}
+
+// strip 模式：删除发往目标的 Accept-Encoding，让目标返回未压缩内容
+pub(crate) fn strip_accept_encoding(headers: &mut Vec<(String, String)>) {
+    headers.retain(|(n, _)| !n.trim().eq_ignore_ascii_case("accept-encoding"));
+}
+
+enum Decoder {
+    Gzip(flate2::write::MultiGzDecoder<Vec<u8>>),
+    // zlib 封装还是裸 deflate 由第一个字节决定
+    Deflate,
+    Zlib(flate2::write::ZlibDecoder<Vec<u8>>),
+    RawDeflate(flate2::write::DeflateDecoder<Vec<u8>>),
+    Brotli(Box<brotli_decompressor::DecompressorWriter<Vec<u8>>>),
+}
+
+impl Decoder {
+    fn for_coding(coding: &str) -> Option<Self> {
+        match coding.to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Self::Gzip(flate2::write::MultiGzDecoder::new(Vec::new()))),
+            "deflate" => Some(Self::Deflate),
+            "br" => Some(Self::Brotli(Box::new(brotli_decompressor::DecompressorWriter::new(Vec::new(), 4096)))),
+            _ => None,
+        }
+    }
+
+    // 写入压缩数据，返回目前能解出的内容
+    fn write(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        if let Self::Deflate = self {
+            let Some(&first) = data.first() else { return Ok(Vec::new()) };
+            // RFC 1950：CMF 低 4 位为 8（deflate）且窗口不超过 32K
+            *self = if first & 0x0F == 8 && first >> 4 <= 7 {
+                Self::Zlib(flate2::write::ZlibDecoder::new(Vec::new()))
+            } else {
+                Self::RawDeflate(flate2::write::DeflateDecoder::new(Vec::new()))
+            };
+        }
+        let out = match self {
+            Self::Gzip(d) => { d.write_all(data)?; d.get_mut() }
+            Self::Zlib(d) => { d.write_all(data)?; d.get_mut() }
+            Self::RawDeflate(d) => { d.write_all(data)?; d.get_mut() }
+            Self::Brotli(d) => { d.write_all(data)?; d.get_mut() }
+            Self::Deflate => unreachable!(),
+        };
+        Ok(std::mem::take(out))
+    }
+
+    // body 结束：返回剩余内容，压缩流不完整时报错
+    fn finish(self) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip(d) => d.finish(),
+            Self::Zlib(d) => d.finish(),
+            Self::RawDeflate(d) => d.finish(),
+            Self::Deflate => Ok(Vec::new()),
+            Self::Brotli(d) => d.into_inner().map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "truncated brotli stream")),
+        }
+    }
+}
+
+fn push_chunk(out: &mut Vec<u8>, data: &[u8]) {
+    if data.is_empty() { return; }
+    out.extend_from_slice(format!("{:x}\r\n", data.len()).as_bytes());
+    out.extend_from_slice(data);
+    out.extend_from_slice(b"\r\n");
+}
+
+// 压缩的 HTTP/1.1 应答改写为未压缩的 chunked 应答：去掉 Content-Encoding/Content-Length/Transfer-Encoding；
+// 其余应答原样返回 None
+fn decoded_head(head: &[u8]) -> Option<(Vec<u8>, Decoder)> {
+    let text = std::str::from_utf8(head).ok()?;
+    let mut lines = text.trim_end_matches("\r\n").split("\r\n");
+    let status_line = lines.next()?;
+    if !status_line.starts_with("HTTP/1.1 ") { return None; }
+    let mut decoder = None;
+    let mut out = format!("{}\r\n", status_line);
+    for line in lines {
+        let name = line.split_once(':').map_or(line, |(n, _)| n).trim();
+        if name.eq_ignore_ascii_case("content-encoding") {
+            // 多层编码（如 `gzip, br`）不处理
+            if decoder.is_some() { return None; }
+            decoder = Some(Decoder::for_coding(line.split_once(':')?.1.trim())?);
+        } else if !name.eq_ignore_ascii_case("content-length") && !name.eq_ignore_ascii_case("transfer-encoding") {
+            out.push_str(line);
+            out.push_str("\r\n");
+        }
+    }
+    out.push_str("Transfer-Encoding: chunked\r\n\r\n");
+    Some((out.into_bytes(), decoder?))
+}
+
+#[derive(Default)]
+struct Rewriter {
+    decoder: Option<Decoder>,
+    // 已改写、尚未写给客户端的字节
+    out: Vec<u8>,
+    error: Option<std::io::Error>,
+}
+
+impl Rewriter {
+    fn on_event(&mut self, event: Event) {
+        match event {
+            Event::Head { head, has_body } => match decoded_head(head).filter(|_| has_body) {
+                Some((head, decoder)) => {
+                    self.out.extend_from_slice(&head);
+                    self.decoder = Some(decoder);
+                }
+                None => self.out.extend_from_slice(head),
+            },
+            Event::Data(data) => match &mut self.decoder {
+                Some(d) => match d.write(data) {
+                    Ok(plain) => push_chunk(&mut self.out, &plain),
+                    Err(e) => self.error = Some(e),
+                },
+                None => self.out.extend_from_slice(data),
+            },
+            // 解压时由我们重新分块，原始的分块格式丢弃
+            Event::Framing(data) => if self.decoder.is_none() { self.out.extend_from_slice(data) },
+            Event::Raw(data) => self.out.extend_from_slice(data),
+            Event::End(t) => {
+                let Some(d) = self.decoder.take() else { return };
+                // 上游 body 不完整时不写结束块，客户端同样能看出应答被截断
+                if !t.complete { return; }
+                match d.finish() {
+                    Ok(rest) => {
+                        push_chunk(&mut self.out, &rest);
+                        self.out.extend_from_slice(b"0\r\n\r\n");
+                    }
+                    Err(e) => self.error = Some(e),
+                }
+            }
+        }
+    }
+}
+
+// 包在客户端连接外：写往客户端的应答经此解压，读方向透传
+pub(crate) struct Decompress<S> {
+    inner: S,
+    framer: Framer,
+    rewriter: Rewriter,
+    // 未匹配 decode 规则时原样透传
+    enabled: bool,
+    finished: bool,
+}
+
+impl<S> Decompress<S> {
+    pub(crate) fn new(inner: S, first_method: &str, enabled: bool) -> Self {
+        Self { inner, framer: Framer::new(first_method), rewriter: Rewriter::default(), enabled, finished: false }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Decompress<S> {
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while !self.rewriter.out.is_empty() {
+            let n = std::task::ready!(Pin::new(&mut self.inner).poll_write(cx, &self.rewriter.out))?;
+            if n == 0 { return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into())); }
+            self.rewriter.out.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Decompress<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Decompress<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        if !this.enabled { return Pin::new(&mut this.inner).poll_write(cx, buf); }
+        // 上一批改写结果写完才接收新数据
+        std::task::ready!(this.poll_drain(cx))?;
+        let rewriter = &mut this.rewriter;
+        this.framer.feed(buf, &mut |e| rewriter.on_event(e));
+        if let Some(e) = this.rewriter.error.take() { return Poll::Ready(Err(e)); }
+        // 尽量立即写出；写不完的留到下次 write/flush
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) { return Poll::Ready(Err(e)); }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        std::task::ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        // 上游关闭：读到关闭为止的 body 在此结束
+        if this.enabled && !this.finished {
+            this.finished = true;
+            let rewriter = &mut this.rewriter;
+            this.framer.finish(&mut |e| rewriter.on_event(e));
+            if let Some(e) = this.rewriter.error.take() { return Poll::Ready(Err(e)); }
+        }
+        std::task::ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut e = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap()
+    }
+
+    async fn run(method: &str, chunks: &[&[u8]]) -> Vec<u8> {
+        let mut w = Decompress::new(Vec::new(), method, true);
+        for c in chunks {
+            w.write_all(c).await.unwrap();
+        }
+        w.shutdown().await.unwrap();
+        w.inner
+    }
+
+    fn response(headers: &str, body: &[u8]) -> Vec<u8> {
+        let mut r = format!("HTTP/1.1 200 OK\r\n{}\r\n", headers).into_bytes();
+        r.extend_from_slice(body);
+        r
+    }
+
+    #[test]
+    fn parse_rules() {
+        let r = DecompressRule::parse(".Example.com").unwrap();
+        assert_eq!((r.suffix.as_str(), r.mode), ("example.com", Mode::Decode));
+        assert_eq!(DecompressRule::parse("*=strip").unwrap().mode, Mode::Strip);
+        assert!(DecompressRule::parse("example.com=zip").is_err());
+        assert!(DecompressRule::parse("=strip").is_err());
+        assert!(r.matches_host("cdn.example.com") && !r.matches_host("badexample.com"));
+    }
+
+    #[tokio::test]
+    async fn gzip_with_content_length_becomes_chunked() {
+        let body = gzip(b"hello, plain world");
+        let resp = response(&format!("Content-Encoding: gzip\r\nContent-Length: {}\r\nETag: \"x\"\r\n", body.len()), &body);
+        // 逐字节写入，覆盖跨写入的头部与数据
+        let pieces: Vec<&[u8]> = resp.chunks(1).collect();
+        let out = run("GET", &pieces).await;
+        assert!(String::from_utf8_lossy(&out).starts_with("HTTP/1.1 200 OK\r\nETag: \"x\"\r\nTransfer-Encoding: chunked\r\n\r\n"), "{:?}", String::from_utf8_lossy(&out));
+        assert_eq!(dechunk(&out), "hello, plain world");
+    }
+
+    #[tokio::test]
+    async fn chunked_deflate_and_brotli() {
+        let mut z = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        z.write_all(b"zlib body").unwrap();
+        let z = z.finish().unwrap();
+        let mut chunked = format!("{:x}\r\n", 3).into_bytes();
+        chunked.extend_from_slice(&z[..3]);
+        chunked.extend_from_slice(format!("\r\n{:x}\r\n", z.len() - 3).as_bytes());
+        chunked.extend_from_slice(&z[3..]);
+        chunked.extend_from_slice(b"\r\n0\r\n\r\n");
+        let out = run("GET", &[&response("Content-Encoding: deflate\r\nTransfer-Encoding: chunked\r\n", &chunked)]).await;
+        assert_eq!(dechunk(&out), "zlib body");
+
+        let mut raw = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        raw.write_all(b"raw deflate").unwrap();
+        let raw = raw.finish().unwrap();
+        let out = run("GET", &[&response(&format!("Content-Encoding: deflate\r\nContent-Length: {}\r\n", raw.len()), &raw)]).await;
+        assert_eq!(dechunk(&out), "raw deflate");
+
+        // 手工构造的 br 流：一个未压缩 meta-block（"brotli"）加空的结束 meta-block
+        let br: &[u8] = &[0x50, 0x00, 0x10, b'b', b'r', b'o', b't', b'l', b'i', 0x03];
+        let out = run("GET", &[&response(&format!("Content-Encoding: br\r\nContent-Length: {}\r\n", br.len()), br)]).await;
+        assert_eq!(dechunk(&out), "brotli");
+    }
+
+    // 取出应答 body 并去掉分块格式
+    fn dechunk(resp: &[u8]) -> String {
+        let resp = std::str::from_utf8(resp).unwrap();
+        let (head, mut rest) = resp.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Transfer-Encoding: chunked") && !head.contains("Content-Encoding"), "{:?}", head);
+        let mut body = String::new();
+        loop {
+            let (size, after) = rest.split_once("\r\n").unwrap();
+            let size = usize::from_str_radix(size, 16).unwrap();
+            if size == 0 {
+                assert_eq!(after, "\r\n");
+                return body;
+            }
+            body.push_str(&after[..size]);
+            rest = &after[size + 2..];
+        }
+    }
+
+    #[tokio::test]
+    async fn untouched_responses_pass_through() {
+        let gz = gzip(b"x");
+        let cases: Vec<Vec<u8>> = vec![
+            response("Content-Length: 5\r\n", b"plain"),
+            // 未知编码与多层编码
+            response("Content-Encoding: zstd\r\nContent-Length: 1\r\n", b"z"),
+            response(&format!("Content-Encoding: gzip, br\r\nContent-Length: {}\r\n", gz.len()), &gz),
+            // HTTP/1.0 不能改为 chunked
+            [b"HTTP/1.0 200 OK\r\nContent-Encoding: gzip\r\n\r\n".as_slice(), &gz].concat(),
+            // 204 没有 body
+            b"HTTP/1.1 204 No Content\r\nContent-Encoding: gzip\r\n\r\n".to_vec(),
+        ];
+        for resp in cases {
+            assert_eq!(run("GET", &[&resp]).await, resp);
+        }
+        // HEAD 的应答没有 body
+        let head = response("Content-Encoding: gzip\r\nContent-Length: 20\r\n", b"");
+        assert_eq!(run("HEAD", &[&head]).await, head);
+    }
+
+    #[tokio::test]
+    async fn until_close_body_is_terminated_on_shutdown() {
+        let gz = gzip(b"streamed until close");
+        let out = run("GET", &[&response("Content-Encoding: gzip\r\nConnection: close\r\n", &gz)]).await;
+        assert_eq!(dechunk(&out), "streamed until close");
+    }
+
+    #[tokio::test]
+    async fn corrupt_stream_is_an_error() {
+        let mut w = Decompress::new(Vec::new(), "GET", true);
+        let resp = response("Content-Encoding: gzip\r\nContent-Length: 8\r\n", b"notgzip!");
+        assert!(w.write_all(&resp).await.is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use anyhow::Result;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::upstream::UpstreamDialer;
+use crate::util::{connect_outbound_attempt, Cidr};
+
+pub trait ProxyStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ProxyStream for T {}
+
+// 会话的出站一端：绑定网卡的直连 socket、经上游的隧道，或自定义 Dialer 返回的任意流
+pub type OutboundStream = Box<dyn ProxyStream>;
+
+// 一次出站连接请求
+pub struct DialRequest<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    // 本次会话选用的出口网卡（--iface、监听的 iface= 或按请求指定的网卡）
+    pub iface: &'a str,
+    // 重试序号：直连时从解析结果的第 attempt 个地址开始
+    pub attempt: usize,
+    deny: &'a [Cidr],
+}
+
+impl<'a> DialRequest<'a> {
+    pub(crate) fn new(host: &'a str, port: u16, iface: &'a str, deny: &'a [Cidr]) -> Self {
+        Self { host, port, iface, attempt: 0, deny }
+    }
+
+    pub(crate) fn with_attempt(self, attempt: usize) -> Self {
+        Self { attempt, ..self }
+    }
+
+    // 换一个目标或出口网卡（路由脚本改写后），保留重试序号与禁止访问的范围
+    pub(crate) fn retarget<'b>(&self, host: &'b str, port: u16, iface: &'b str) -> DialRequest<'b>
+    where
+        'a: 'b,
+    {
+        DialRequest { host, port, iface, attempt: self.attempt, deny: self.deny }
+    }
+
+    // 目标地址是否在该会话禁止访问的范围内（暴露在局域网的监听默认禁止内网地址）
+    pub fn denies(&self, ip: std::net::IpAddr) -> bool {
+        self.deny.iter().any(|c| c.contains(ip))
+    }
+}
+
+// 会话的出站上下文：所用 Dialer、默认出口网卡与该会话禁止访问的目标
+#[derive(Clone, Copy)]
+pub(crate) struct DialContext<'a> {
+    pub(crate) dialer: &'a dyn Dialer,
+    pub(crate) iface: &'a str,
+    pub(crate) deny: &'a [Cidr],
+}
+
+pub type DialFuture<'a> = Pin<Box<dyn Future<Output = Result<OutboundStream>> + Send + 'a>>;
+
+// 各协议处理器只经 Dialer 建立出站连接；默认为 UpstreamDialer::new(DirectDialer)，
+// 嵌入方可用 set_dialer 换成自己的出口逻辑
+pub trait Dialer: Send + Sync {
+    fn dial<'a>(&'a self, req: DialRequest<'a>) -> DialFuture<'a>;
+}
+
+// 经绑定网卡直连，--vrf、--fwmark、--dscp、--sockopt、--tcp-mss 与 set_prepare_socket 等出站 socket 设置都在这里生效
+pub struct DirectDialer;
+
+impl Dialer for DirectDialer {
+    fn dial<'a>(&'a self, req: DialRequest<'a>) -> DialFuture<'a> {
+        Box::pin(async move {
+            // 出口组在这里选定成员网卡，连接存续期间计入该成员的活动连接数
+            let (iface, lease) = match crate::balance::lease(req.iface, req.host, req.attempt) {
+                Some((member, lease)) => (member, Some(lease)),
+                None => (req.iface, None),
+            };
+            crate::session::set_target(req.host, req.port, iface);
+            let chaos = crate::chaos::rule_for(req.host, iface);
+            if let Some(rule) = chaos { rule.before_connect(req.host).await?; }
+            let stream = connect_outbound_attempt(req.host, req.port, iface, req.deny, req.attempt).await?;
+            let stream = match lease {
+                Some(lease) => Box::new(crate::balance::Leased { inner: stream, _lease: lease }) as OutboundStream,
+                None => Box::new(stream) as OutboundStream,
+            };
+            Ok(match chaos {
+                Some(rule) => crate::chaos::wrap(stream, rule),
+                None => stream,
+            })
+        })
+    }
+}
+
+static DIALER: OnceLock<Arc<dyn Dialer>> = OnceLock::new();
+
+// 替换所有监听使用的 Dialer；须在 run_cli 之前设置，只能设置一次。
+// 想保留 --upstream-rule 路由时用 UpstreamDialer::new(自定义直连) 包一层
+pub fn set_dialer(dialer: impl Dialer + 'static) -> Result<()> {
+    DIALER.set(Arc::new(dialer)).map_err(|_| anyhow::anyhow!("dialer is already set"))
+}
+
+// 配置了路由规则或脚本时由它们先做决定，--fake-ip 的地址在此之前换回域名
+pub(crate) fn installed() -> Arc<dyn Dialer> {
+    let inner = DIALER.get().cloned().unwrap_or_else(|| Arc::new(UpstreamDialer::new(DirectDialer)));
+    crate::fakeip::wrap(crate::router::wrap(inner))
+}
+
+// 测试用：每次 dial 建一对内存管道，对端原样回显，并记下拨号目标
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MemoryDialer {
+    pub(crate) dialed: std::sync::Mutex<Vec<String>>,
+}
+
+#[cfg(test)]
+impl Dialer for MemoryDialer {
+    fn dial<'a>(&'a self, req: DialRequest<'a>) -> DialFuture<'a> {
+        Box::pin(async move {
+            if let Ok(ip) = req.host.parse() {
+                if req.denies(ip) { return Err(crate::error::ProxyError::PolicyDenied(crate::error::Denied::Dest(ip)).into()); }
+            }
+            self.dialed.lock().unwrap().push(format!("{}:{}", req.host, req.port));
+            let (near, mut far) = tokio::io::duplex(64 * 1024);
+            tokio::spawn(async move {
+                let (mut r, mut w) = tokio::io::split(&mut far);
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+            });
+            Ok(Box::new(near) as OutboundStream)
+        })
+    }
+}
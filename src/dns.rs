@@ -0,0 +1,266 @@
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::time::{timeout, Duration};
+
+use crate::listener::ListenerSettings;
+use crate::util::{log_error, log_info, log_throttled, udp_socket_for};
+
+// 简单的 DNS 转发：每个 UDP 查询经绑定网卡发往上游，按顺序尝试，首个应答原样回给客户端
+const UPSTREAM_TIMEOUT_MS: u64 = 3000;
+const MAX_DNS_PACKET: usize = 4096;
+// 按 --host / --hosts-file 直接应答时的 TTL
+const LOCAL_TTL: u32 = 60;
+
+// ADDR[:PORT]，端口默认 53，如 1.1.1.1、[2606:4700::1111]:53
+pub(crate) fn parse_dns_upstream(s: &str) -> Result<SocketAddr> {
+    let s = s.trim();
+    if let Ok(sa) = s.parse::<SocketAddr>() { return Ok(sa); }
+    match s.trim_start_matches('[').trim_end_matches(']').parse::<std::net::IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, 53)),
+        Err(_) => anyhow::bail!("invalid DNS upstream {:?} (expected IP[:PORT])", s),
+    }
+}
+
+// 单个问题、IN 类 A/AAAA 查询且名字在静态映射中（或启用了 --fake-ip）时直接应答（没有该协议族的地址时应答为空），
+// 其余返回 None 交给上游
+fn local_answer(query: &[u8]) -> Option<Vec<u8>> {
+    // QR=0、OPCODE=0、QDCOUNT=1
+    if query.len() < 12 || query[2] & 0xf8 != 0 || query[4..6] != [0, 1] { return None; }
+    let mut pos = 12;
+    let mut labels = Vec::new();
+    loop {
+        let len = *query.get(pos)? as usize;
+        pos += 1;
+        if len == 0 { break; }
+        // 查询中的名字不应出现压缩指针
+        if len > 63 { return None; }
+        labels.push(std::str::from_utf8(query.get(pos..pos + len)?).ok()?);
+        pos += len;
+    }
+    let qtype = u16::from_be_bytes([*query.get(pos)?, *query.get(pos + 1)?]);
+    let qclass = u16::from_be_bytes([*query.get(pos + 2)?, *query.get(pos + 3)?]);
+    let question_end = pos + 4;
+    if qclass != 1 || !(qtype == 1 || qtype == 28) || labels.is_empty() { return None; }
+    let name = labels.join(".");
+    let (addrs, ttl) = match crate::hosts::lookup(&name) {
+        Some(addrs) => (addrs.to_vec(), LOCAL_TTL),
+        None => (vec![std::net::IpAddr::V4(crate::fakeip::installed()?.allocate(&name)?)], crate::fakeip::FAKE_TTL),
+    };
+    let answers: Vec<Vec<u8>> = addrs
+        .iter()
+        .filter_map(|ip| match (ip, qtype) {
+            (std::net::IpAddr::V4(v4), 1) => Some(v4.octets().to_vec()),
+            (std::net::IpAddr::V6(v6), 28) => Some(v6.octets().to_vec()),
+            _ => None,
+        })
+        .collect();
+    let mut resp = Vec::with_capacity(question_end + answers.len() * 28);
+    resp.extend_from_slice(&query[..2]);
+    // QR=1、AA=1、沿用 RD、RA=1
+    resp.extend_from_slice(&[0x84 | (query[2] & 0x01), 0x80, 0, 1]);
+    resp.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    resp.extend_from_slice(&[0, 0, 0, 0]);
+    resp.extend_from_slice(&query[12..question_end]);
+    for rdata in answers {
+        resp.extend_from_slice(&[0xc0, 0x0c]);
+        resp.extend_from_slice(&qtype.to_be_bytes());
+        resp.extend_from_slice(&[0, 1]);
+        resp.extend_from_slice(&ttl.to_be_bytes());
+        resp.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        resp.extend_from_slice(&rdata);
+    }
+    Some(resp)
+}
+
+async fn forward(query: &[u8], upstreams: &[SocketAddr], iface: &str) -> Result<Vec<u8>> {
+    let mut last_err = anyhow::anyhow!("no DNS upstreams configured");
+    for &up in upstreams {
+        let res = async {
+            let sock = udp_socket_for(up, iface)?;
+            sock.connect(up).await?;
+            sock.send(query).await?;
+            let mut buf = vec![0u8; MAX_DNS_PACKET];
+            loop {
+                let n = timeout(Duration::from_millis(UPSTREAM_TIMEOUT_MS), sock.recv(&mut buf))
+                    .await
+                    .map_err(|_| anyhow::anyhow!("DNS upstream {} timed out", up))??;
+                // 只接受与查询 ID 相同的应答
+                if n >= 2 && buf[..2] == query[..2] {
+                    buf.truncate(n);
+                    return Ok::<_, anyhow::Error>(buf);
+                }
+            }
+        }
+        .await;
+        match res {
+            Ok(resp) => return Ok(resp),
+            Err(e) => last_err = e,
+        }
+    }
+    Err(last_err)
+}
+
+// 查询 `name` 的 A 与 AAAA 记录（路由规则的 dns: 动作），IPv4 在前；两种查询都失败时返回 A 查询的错误
+pub(crate) async fn lookup(name: &str, server: SocketAddr, iface: &str) -> Result<Vec<std::net::IpAddr>> {
+    let (v4, v6) = tokio::join!(lookup_type(name, 1, server, iface), lookup_type(name, 28, server, iface));
+    let addrs: Vec<std::net::IpAddr> = match (v4, v6) {
+        (Err(e), Err(_)) => return Err(e),
+        (v4, v6) => v4.unwrap_or_default().into_iter().chain(v6.unwrap_or_default()).collect(),
+    };
+    if addrs.is_empty() { anyhow::bail!("{} has no A/AAAA records at {}", crate::addr::redact_host(name), server); }
+    Ok(addrs)
+}
+
+async fn lookup_type(name: &str, qtype: u16, server: SocketAddr, iface: &str) -> Result<Vec<std::net::IpAddr>> {
+    let mut id = [0u8; 2];
+    getrandom::getrandom(&mut id).map_err(|e| anyhow::anyhow!("random query id: {}", e))?;
+    let query = build_query(id, name, qtype)?;
+    parse_answers(&forward(&query, &[server], iface).await?, qtype)
+}
+
+// 带 RD 的单问题查询
+fn build_query(id: [u8; 2], name: &str, qtype: u16) -> Result<Vec<u8>> {
+    let mut q = vec![id[0], id[1], 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 { anyhow::bail!("invalid DNS name {:?}", name); }
+        q.push(label.len() as u8);
+        q.extend_from_slice(label.as_bytes());
+    }
+    q.push(0);
+    q.extend_from_slice(&qtype.to_be_bytes());
+    q.extend_from_slice(&[0, 1]);
+    Ok(q)
+}
+
+// 跳过（可能压缩的）名字，返回其后的位置
+fn skip_name(buf: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *buf.get(pos)? as usize;
+        if len & 0xc0 == 0xc0 { return Some(pos + 2); }
+        if len == 0 { return Some(pos + 1); }
+        pos += 1 + len;
+    }
+}
+
+// 应答中类型为 `qtype` 的地址（CNAME 链由服务器展开，这里只取地址记录）
+fn parse_answers(resp: &[u8], qtype: u16) -> Result<Vec<std::net::IpAddr>> {
+    let bad = || anyhow::anyhow!("malformed DNS response");
+    if resp.len() < 12 { return Err(bad()); }
+    match resp[3] & 0x0f {
+        0 => {}
+        3 => anyhow::bail!("no such domain"),
+        rcode => anyhow::bail!("DNS server returned rcode {}", rcode),
+    }
+    let u16_at = |p: usize| resp.get(p..p + 2).map(|b| u16::from_be_bytes([b[0], b[1]])).ok_or_else(bad);
+    let (qd, an) = (u16_at(4)?, u16_at(6)?);
+    let mut pos = 12;
+    for _ in 0..qd { pos = skip_name(resp, pos).ok_or_else(bad)? + 4; }
+    let mut out = Vec::new();
+    for _ in 0..an {
+        pos = skip_name(resp, pos).ok_or_else(bad)?;
+        let (rtype, rdlen) = (u16_at(pos)?, u16_at(pos + 8)? as usize);
+        let rdata = resp.get(pos + 10..pos + 10 + rdlen).ok_or_else(bad)?;
+        match (rtype, rdlen) {
+            (1, 4) if qtype == 1 => out.push(std::net::IpAddr::from(<[u8; 4]>::try_from(rdata).map_err(|_| bad())?)),
+            (28, 16) if qtype == 28 => out.push(std::net::IpAddr::from(<[u8; 16]>::try_from(rdata).map_err(|_| bad())?)),
+            _ => {}
+        }
+        pos += 10 + rdlen;
+    }
+    Ok(out)
+}
+
+pub async fn run_dns(sock: UdpSocket, upstreams: Arc<Vec<SocketAddr>>, s: Arc<ListenerSettings>) -> Result<()> {
+    let sock = Arc::new(sock);
+    let listen = sock.local_addr()?.to_string();
+    let ups: Vec<String> = upstreams.iter().map(|u| u.to_string()).collect();
+    log_info(format!("DNS forwarder listening on {} (udp), upstreams {}, bound to {}", listen, ups.join(","), s.iface));
+    let mut buf = vec![0u8; MAX_DNS_PACKET];
+    loop {
+        let (n, peer) = match sock.recv_from(&mut buf).await {
+            Ok(v) => v,
+            Err(e) => {
+                log_error(format!("DNS recv error: {}", e));
+                continue;
+            }
+        };
+        if !s.allows(peer.ip()) {
+            log_throttled(|| log_info(format!("denied DNS query from {} to {} (not in allow list)", peer, listen)));
+            continue;
+        }
+        // DNS 报文头至少 12 字节
+        if n < 12 { continue; }
+        let Some(permit) = s.limit.try_acquire() else {
+            log_throttled(|| log_info("too many concurrent DNS queries; dropping query"));
+            continue;
+        };
+        let query = buf[..n].to_vec();
+        let (sock, upstreams, s) = (sock.clone(), upstreams.clone(), s.clone());
+        tokio::spawn(async move {
+            let _permit = permit;
+            let res = match local_answer(&query) {
+                Some(resp) => Ok(resp),
+                None => forward(&query, &upstreams, &s.iface).await,
+            };
+            match res {
+                Ok(resp) => {
+                    if let Err(e) = sock.send_to(&resp, peer).await {
+                        log_error(format!("DNS reply to {} failed: {}", peer, e));
+                    }
+                }
+                Err(e) => log_throttled(|| log_info(format!("DNS query from {} failed: {}", peer, e))),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut q = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            q.push(label.len() as u8);
+            q.extend_from_slice(label.as_bytes());
+        }
+        q.push(0);
+        q.extend_from_slice(&qtype.to_be_bytes());
+        q.extend_from_slice(&[0, 1]);
+        q
+    }
+
+    #[test]
+    fn answers_static_hosts_locally() {
+        crate::hosts::install(&[crate::hosts::HostEntry::parse("*.dns-test.example=10.0.0.7,fd00::7").unwrap()], &[]).unwrap();
+        let q = query("db.dns-test.example", 1);
+        let resp = local_answer(&q).unwrap();
+        assert_eq!(&resp[..4], &[0x12, 0x34, 0x85, 0x80]);
+        assert_eq!(&resp[6..8], &[0, 1]);
+        assert_eq!(&resp[12..q.len()], &q[12..]);
+        assert_eq!(&resp[resp.len() - 4..], &[10, 0, 0, 7]);
+        let aaaa = local_answer(&query("db.dns-test.example", 28)).unwrap();
+        assert_eq!(&aaaa[aaaa.len() - 16..], &"fd00::7".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        // MX 与未映射的名字交给上游
+        assert!(local_answer(&query("db.dns-test.example", 15)).is_none());
+        assert!(local_answer(&query("other.example", 1)).is_none());
+    }
+
+    #[test]
+    fn parses_compressed_answers() {
+        let q = build_query([0xab, 0xcd], "www.example.com.", 1).unwrap();
+        assert_eq!(&q[12..], &query("www.example.com", 1)[12..]);
+        let mut resp = q.clone();
+        resp[2..8].copy_from_slice(&[0x81, 0x80, 0, 1, 0, 2]);
+        // CNAME 指向 web.example.com，再是它的 A 记录
+        resp.extend_from_slice(&[0xc0, 0x0c, 0, 5, 0, 1, 0, 0, 0, 60, 0, 6, 3, b'w', b'e', b'b', 0xc0, 0x10]);
+        resp.extend_from_slice(&[0xc0, 0x2d, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 0, 8]);
+        assert_eq!(parse_answers(&resp, 1).unwrap(), vec!["10.0.0.8".parse::<std::net::IpAddr>().unwrap()]);
+        assert!(parse_answers(&resp, 28).unwrap().is_empty());
+        resp[3] = 0x83;
+        assert!(parse_answers(&resp, 1).unwrap_err().to_string().contains("no such domain"));
+        assert!(build_query([0, 0], "a..b", 1).is_err());
+    }
+}
@@ -0,0 +1,349 @@
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::{Mutex, OnceLock};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::dialer::OutboundStream;
+use crate::util::{current_timestamp_prefix, log_error};
+
+// 明文 HTTP 事务日志：按会话解析经过出站连接的请求/响应，写入单独的文件。
+// headers 模式在头部读完时立即写出；full 模式在消息结束（或连接关闭）时连同 body 一起写出。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DumpLevel {
+    Headers,
+    Full,
+}
+
+impl DumpLevel {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "headers" => Ok(Self::Headers),
+            "full" => Ok(Self::Full),
+            _ => anyhow::bail!("invalid dump level {:?} (expected headers or full)", s),
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Headers => "headers",
+            Self::Full => "full",
+        }
+    }
+}
+
+struct Dumper {
+    level: DumpLevel,
+    body_max: usize,
+    out: Mutex<File>,
+}
+
+static DUMPER: OnceLock<Dumper> = OnceLock::new();
+
+// 以追加方式打开输出文件；打开失败时直接报错退出
+pub(crate) fn install(level: DumpLevel, path: &str, body_max: usize) -> Result<()> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("failed to open http dump file {}: {}", path, e))?;
+    let _ = DUMPER.set(Dumper { level, body_max, out: Mutex::new(file) });
+    Ok(())
+}
+
+fn write_entry(text: &str) {
+    let Some(d) = DUMPER.get() else { return };
+    let mut out = d.out.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(e) = out.write_all(text.as_bytes()) {
+        log_error(format!("http dump: write failed: {}", e));
+    }
+}
+
+// 仅用于明文 HTTP 路径；未开启 --dump-http 时原样返回
+pub(crate) fn maybe_wrap(stream: OutboundStream, host: &str, port: u16) -> OutboundStream {
+    let Some(d) = DUMPER.get() else { return stream };
+    // 与日志中的 [#ID] 相同
+    let session = crate::session::current().unwrap_or(0);
+    Box::new(Dumped {
+        inner: stream,
+        target: format!("{}:{}", host, port),
+        session,
+        level: d.level,
+        body_max: d.body_max,
+        requests: VecDeque::new(),
+        request: Direction::new(),
+        response: Direction::new(),
+    })
+}
+
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.split("\r\n").skip(1).find_map(|line| {
+        let (n, v) = line.split_once(':')?;
+        n.trim().eq_ignore_ascii_case(name).then(|| v.trim())
+    })
+}
+
+fn is_chunked(head: &str) -> bool {
+    header(head, "transfer-encoding").is_some_and(|v| v.to_ascii_lowercase().contains("chunked"))
+}
+
+fn content_length(head: &str) -> Option<u64> {
+    header(head, "content-length").and_then(|v| v.parse().ok())
+}
+
+// 分块编码的解析进度
+enum Chunk {
+    Size(Vec<u8>),
+    Data(u64),
+    DataEnd(u8),
+    Trailer(Vec<u8>),
+}
+
+enum Phase {
+    Head(Vec<u8>),
+    Length(u64),
+    Chunked(Chunk),
+    // 响应没有长度信息，body 持续到连接关闭
+    ToEof,
+    // 协议升级或无法解析，不再记录
+    Passthrough,
+}
+
+struct Direction {
+    phase: Phase,
+    head: Option<String>,
+    body: Vec<u8>,
+    body_len: u64,
+}
+
+impl Direction {
+    fn new() -> Self {
+        Self { phase: Phase::Head(Vec::new()), head: None, body: Vec::new(), body_len: 0 }
+    }
+}
+
+// 写入出站连接的是请求，读出的是响应
+struct Dumped {
+    inner: OutboundStream,
+    target: String,
+    session: u64,
+    level: DumpLevel,
+    body_max: usize,
+    // 尚未收到响应的请求方法，用于判断 HEAD 响应没有 body
+    requests: VecDeque<String>,
+    request: Direction,
+    response: Direction,
+}
+
+impl Dumped {
+    fn feed(&mut self, is_request: bool, mut data: &[u8]) {
+        while !data.is_empty() {
+            let dir = if is_request { &mut self.request } else { &mut self.response };
+            match &mut dir.phase {
+                Phase::Passthrough => return,
+                Phase::Head(buf) => {
+                    let old = buf.len();
+                    buf.extend_from_slice(data);
+                    let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+                        if buf.len() > 64 * 1024 { dir.phase = Phase::Passthrough; }
+                        return;
+                    };
+                    let head = String::from_utf8_lossy(&buf[..end + 4]).to_string();
+                    data = &data[end + 4 - old..];
+                    self.start_message(is_request, head);
+                }
+                Phase::Length(remaining) => {
+                    let n = (*remaining).min(data.len() as u64) as usize;
+                    *remaining -= n as u64;
+                    let done = *remaining == 0;
+                    self.body(is_request, &data[..n]);
+                    data = &data[n..];
+                    if done { self.end_body(is_request); }
+                }
+                Phase::Chunked(chunk) => {
+                    let (n, done) = advance_chunked(chunk, data);
+                    self.body(is_request, &data[..n]);
+                    data = &data[n..];
+                    if done { self.end_body(is_request); }
+                }
+                Phase::ToEof => {
+                    self.body(is_request, data);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn start_message(&mut self, is_request: bool, head: String) {
+        let phase = if is_request {
+            let method = head.split_whitespace().next().unwrap_or("").to_string();
+            self.requests.push_back(method);
+            if is_chunked(&head) {
+                Phase::Chunked(Chunk::Size(Vec::new()))
+            } else {
+                match content_length(&head) {
+                    Some(n) if n > 0 => Phase::Length(n),
+                    _ => Phase::Head(Vec::new()),
+                }
+            }
+        } else {
+            let status: u16 = head.split_whitespace().nth(1).and_then(|s| s.parse().ok()).unwrap_or(0);
+            // 1xx 是中间响应，不消耗对应的请求
+            let method = if (100..200).contains(&status) { None } else { self.requests.pop_front() };
+            if status == 101 {
+                // 升级后双向都不再是 HTTP
+                self.request.phase = Phase::Passthrough;
+                Phase::Passthrough
+            } else if (100..200).contains(&status) || status == 204 || status == 304 || method.as_deref() == Some("HEAD") {
+                Phase::Head(Vec::new())
+            } else if is_chunked(&head) {
+                Phase::Chunked(Chunk::Size(Vec::new()))
+            } else {
+                match content_length(&head) {
+                    Some(0) => Phase::Head(Vec::new()),
+                    Some(n) => Phase::Length(n),
+                    None => Phase::ToEof,
+                }
+            }
+        };
+        let complete = matches!(phase, Phase::Head(_) | Phase::Passthrough);
+        if self.level == DumpLevel::Headers {
+            write_entry(&self.render(is_request, &head, None));
+        }
+        let dir = if is_request { &mut self.request } else { &mut self.response };
+        dir.phase = phase;
+        dir.head = Some(head);
+        dir.body.clear();
+        dir.body_len = 0;
+        if complete { self.finish_message(is_request); }
+    }
+
+    fn body(&mut self, is_request: bool, data: &[u8]) {
+        let max = self.body_max;
+        let dir = if is_request { &mut self.request } else { &mut self.response };
+        dir.body_len += data.len() as u64;
+        if self.level == DumpLevel::Full && dir.body.len() < max {
+            let take = (max - dir.body.len()).min(data.len());
+            dir.body.extend_from_slice(&data[..take]);
+        }
+    }
+
+    fn end_body(&mut self, is_request: bool) {
+        let dir = if is_request { &mut self.request } else { &mut self.response };
+        dir.phase = Phase::Head(Vec::new());
+        self.finish_message(is_request);
+    }
+
+    fn finish_message(&mut self, is_request: bool) {
+        let dir = if is_request { &mut self.request } else { &mut self.response };
+        let Some(head) = dir.head.take() else { return };
+        let body = std::mem::take(&mut dir.body);
+        let body_len = dir.body_len;
+        if self.level == DumpLevel::Full {
+            write_entry(&self.render(is_request, &head, Some((&body, body_len))));
+        }
+    }
+
+    fn render(&self, is_request: bool, head: &str, body: Option<(&[u8], u64)>) -> String {
+        let mut out = format!(
+            "{} #{} {} {}\n{}",
+            current_timestamp_prefix(),
+            self.session,
+            self.target,
+            if is_request { ">>> request" } else { "<<< response" },
+            head.replace("\r\n", "\n")
+        );
+        if let Some((body, len)) = body {
+            if len > 0 {
+                out.push_str(&String::from_utf8_lossy(body));
+                if len > body.len() as u64 {
+                    out.push_str(&format!("\n... (truncated, {} bytes total)", len));
+                }
+                out.push_str("\n\n");
+            }
+        }
+        out
+    }
+}
+
+// 消耗分块编码的数据，返回 (消耗的字节数, 消息是否结束)
+fn advance_chunked(chunk: &mut Chunk, data: &[u8]) -> (usize, bool) {
+    let mut i = 0;
+    while i < data.len() {
+        match chunk {
+            Chunk::Size(line) => {
+                let b = data[i];
+                i += 1;
+                if b != b'\n' { line.push(b); continue; }
+                let text = String::from_utf8_lossy(line).to_string();
+                let size = text.split(';').next().unwrap_or("").trim();
+                *chunk = match u64::from_str_radix(size, 16) {
+                    Ok(0) => Chunk::Trailer(Vec::new()),
+                    Ok(n) => Chunk::Data(n),
+                    // 无法解析时按结束处理
+                    Err(_) => return (i, true),
+                };
+            }
+            Chunk::Data(remaining) => {
+                let n = (*remaining).min((data.len() - i) as u64);
+                *remaining -= n;
+                i += n as usize;
+                if *remaining == 0 { *chunk = Chunk::DataEnd(2); }
+            }
+            Chunk::DataEnd(left) => {
+                i += 1;
+                *left -= 1;
+                if *left == 0 { *chunk = Chunk::Size(Vec::new()); }
+            }
+            Chunk::Trailer(line) => {
+                let b = data[i];
+                i += 1;
+                if b != b'\n' { line.push(b); continue; }
+                if line.is_empty() || line == b"\r" { return (i, true); }
+                line.clear();
+            }
+        }
+    }
+    (i, false)
+}
+
+impl Drop for Dumped {
+    fn drop(&mut self) {
+        // 连接关闭时写出未结束的消息（如读到 EOF 为止的响应）
+        if self.level == DumpLevel::Full {
+            self.finish_message(true);
+            self.finish_message(false);
+        }
+    }
+}
+
+impl AsyncRead for Dumped {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if buf.filled().len() > before {
+            let data = buf.filled()[before..].to_vec();
+            self.feed(false, &data);
+        }
+        res
+    }
+}
+
+impl AsyncWrite for Dumped {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res { self.feed(true, &buf[..n]); }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::Result;
+use std::sync::OnceLock;
+
+use crate::error::{Denied, ProxyError};
+
+// 客户端按请求选择出口网卡：HTTP 请求带 `X-Iface-Proxy-Egress: en7` 头，SOCKS5 用户名写成 `user@en7`；
+// 只允许 --egress-allow 列出的网卡，未配置时一律拒绝
+pub(crate) const HEADER: &str = "X-Iface-Proxy-Egress";
+
+static ALLOW: OnceLock<Vec<String>> = OnceLock::new();
+
+pub(crate) fn install(allow: Vec<String>) {
+    let _ = ALLOW.set(allow);
+}
+
+pub(crate) fn enabled() -> bool {
+    ALLOW.get().is_some_and(|a| !a.is_empty())
+}
+
+pub(crate) fn check(iface: &str) -> Result<()> {
+    match ALLOW.get() {
+        Some(allow) if allow.iter().any(|a| a == iface) => Ok(()),
+        _ => Err(ProxyError::PolicyDenied(Denied::Egress(iface.to_string())).into()),
+    }
+}
+
+// 请求头中的出口网卡（取第一个）
+pub(crate) fn from_headers(head: &crate::head::RequestHead) -> Option<String> {
+    head.get(HEADER).map(String::from)
+}
+
+// `user@en7` -> (`user`, Some("en7"))；按最后一个 `@` 拆分
+pub(crate) fn split_username(u: &[u8]) -> (&[u8], Option<String>) {
+    match u.iter().rposition(|&b| b == b'@') {
+        Some(pos) if pos + 1 < u.len() => (&u[..pos], Some(String::from_utf8_lossy(&u[pos + 1..]).to_string())),
+        _ => (u, None),
+    }
+}
@@ -0,0 +1,163 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::addr::{redact_host, redact_socket};
+use crate::util::{log_error, log_info};
+
+// 核心路径（出站建连、代理握手、访问控制）的错误分类。错误仍经 anyhow 传递以便附加上下文，
+// 需要区分时用 ProxyError::find 取出：错误应答、是否重试、日志级别与 /metrics 计数都按分类决定，不再匹配错误文本
+#[derive(Debug)]
+pub(crate) enum ProxyError {
+    // 域名解析失败或没有可用地址
+    Dns { host: String, reason: String },
+    ConnectTimeout(SocketAddr),
+    ConnectRefused(SocketAddr),
+    // 出站 socket 绑定网卡或源地址失败
+    IfaceBind { iface: String, reason: String },
+    // 客户端的握手或请求头不合法、读取超时
+    Handshake(String),
+    // 请求头的某一行过长、头部过多或总长超限，HTTP 监听回 431
+    HeadersTooLarge(String),
+    PolicyDenied(Denied),
+    // 其余 I/O 错误（建连时的网络不可达、传输中的连接重置等）
+    Io(io::Error),
+}
+
+#[derive(Debug)]
+pub(crate) enum Denied {
+    // 目标地址命中 --deny-dest
+    Dest(IpAddr),
+    // 路由规则的 block
+    Route(String),
+    // 客户端指定的出口网卡不在 --egress-allow 中
+    Egress(String),
+    // 目标是本进程自己的监听地址
+    Loop(SocketAddr),
+}
+
+impl std::fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // 目标地址按 --log-redact 处理，会话错误会写进日志
+            Self::Dns { host, reason } => write!(f, "cannot resolve {}: {}", redact_host(host), reason),
+            Self::ConnectTimeout(addr) => write!(f, "connect to {} timed out", redact_socket(*addr)),
+            Self::ConnectRefused(addr) => write!(f, "connect to {} refused", redact_socket(*addr)),
+            Self::IfaceBind { iface, reason } => write!(f, "bind to {} failed: {}", iface, reason),
+            Self::Handshake(reason) | Self::HeadersTooLarge(reason) => f.write_str(reason),
+            Self::PolicyDenied(Denied::Dest(ip)) => write!(f, "destination {} is in the deny list", redact_host(&ip.to_string())),
+            Self::PolicyDenied(Denied::Route(target)) => write!(f, "{} blocked by route decision", target),
+            Self::PolicyDenied(Denied::Egress(iface)) => write!(f, "egress interface {:?} is not in --egress-allow", iface),
+            Self::PolicyDenied(Denied::Loop(addr)) => write!(f, "loop detected: {} is one of this proxy's listen addresses", redact_socket(*addr)),
+            Self::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ProxyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// 指标标签的顺序，与 ProxyError::index 对应；最后一项为未分类的错误
+const KINDS: [&str; 8] = ["dns", "connect_timeout", "connect_refused", "iface_bind", "handshake", "policy_denied", "io", "other"];
+
+static COUNTS: [AtomicU64; KINDS.len()] = [const { AtomicU64::new(0) }; KINDS.len()];
+
+impl ProxyError {
+    // 建连失败按 io::Error 的类型归类
+    pub(crate) fn connect(addr: SocketAddr, e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::TimedOut => Self::ConnectTimeout(addr),
+            io::ErrorKind::ConnectionRefused => Self::ConnectRefused(addr),
+            _ => Self::Io(e),
+        }
+    }
+
+    // 错误链中的第一个 ProxyError
+    pub(crate) fn find(e: &anyhow::Error) -> Option<&ProxyError> {
+        e.chain().find_map(|c| c.downcast_ref::<ProxyError>())
+    }
+
+    pub(crate) fn is_denied(e: &anyhow::Error) -> bool {
+        matches!(Self::find(e), Some(Self::PolicyDenied(_)))
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Self::Dns { .. } => 0,
+            Self::ConnectTimeout(_) => 1,
+            Self::ConnectRefused(_) => 2,
+            Self::IfaceBind { .. } => 3,
+            Self::Handshake(_) | Self::HeadersTooLarge(_) => 4,
+            Self::PolicyDenied(_) => 5,
+            Self::Io(_) => 6,
+        }
+    }
+}
+
+fn is_transient_io(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted | io::ErrorKind::TimedOut | io::ErrorKind::UnexpectedEof
+    )
+}
+
+// 对端断开、超时一类的网络抖动，只记 info
+pub(crate) fn is_transient(e: &anyhow::Error) -> bool {
+    match ProxyError::find(e) {
+        Some(ProxyError::ConnectTimeout(_)) => true,
+        Some(ProxyError::Io(ioe)) => is_transient_io(ioe),
+        Some(_) => false,
+        None => e.chain().filter_map(|c| c.downcast_ref::<io::Error>()).any(is_transient_io),
+    }
+}
+
+// 会话以错误结束时调用：按分类计数，暂时性错误记 info，其余记 error
+pub(crate) fn log_session_error(what: &str, e: &anyhow::Error) {
+    let i = ProxyError::find(e).map_or(KINDS.len() - 1, ProxyError::index);
+    COUNTS[i].fetch_add(1, Ordering::Relaxed);
+    if is_transient(e) {
+        log_info(format!("{} transient: {}", what, e));
+    } else {
+        log_error(format!("{} error: {}", what, e));
+    }
+}
+
+pub(crate) fn render_metrics() -> String {
+    let mut out = String::from("# HELP iface_proxy_session_errors_total Sessions that ended with an error, by error kind.\n");
+    out.push_str("# TYPE iface_proxy_session_errors_total counter\n");
+    for (kind, n) in KINDS.iter().zip(&COUNTS) {
+        out.push_str(&format!("iface_proxy_session_errors_total{{kind=\"{}\"}} {}\n", kind, n.load(Ordering::Relaxed)));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_through_context() {
+        let addr: SocketAddr = "192.0.2.1:443".parse().unwrap();
+        let refused = anyhow::Error::new(ProxyError::connect(addr, io::Error::from(io::ErrorKind::ConnectionRefused))).context("HTTP CONNECT");
+        assert!(matches!(ProxyError::find(&refused), Some(ProxyError::ConnectRefused(a)) if *a == addr));
+        assert!(!is_transient(&refused));
+
+        let timeout = anyhow::Error::new(ProxyError::connect(addr, io::Error::from(io::ErrorKind::TimedOut)));
+        assert_eq!(ProxyError::find(&timeout).map(|e| KINDS[e.index()]), Some("connect_timeout"));
+        assert!(is_transient(&timeout));
+
+        let denied = anyhow::Error::new(ProxyError::PolicyDenied(Denied::Dest("10.0.0.1".parse().unwrap())));
+        assert!(ProxyError::is_denied(&denied));
+        assert_eq!(denied.to_string(), "destination 10.0.0.1 is in the deny list");
+
+        // 未分类的 io::Error 仍按类型判断，不看错误文本
+        assert!(is_transient(&anyhow::Error::new(io::Error::from(io::ErrorKind::ConnectionReset))));
+        assert!(!is_transient(&anyhow::anyhow!("connection reset by a string")));
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::dialer::{DialFuture, DialRequest, Dialer};
+use crate::util::Cidr;
+
+// --fake-ip：DNS 转发对 A 查询从保留地址池中分配地址并记下对应的域名，
+// 出站连接的目标落在池中时换回域名，再交给路由规则与真实解析；客户端自己解析 DNS 时也能按域名分流
+
+// 池中地址的生命周期由重新分配决定，TTL 只需很短，客户端缓存过期后会再来查询
+pub(crate) const FAKE_TTL: u32 = 1;
+
+pub(crate) struct Pool {
+    range: Cidr,
+    base: u32,
+    // 可分配的地址为 base+1 .. base+size-1（跳过网络地址与广播地址）
+    size: u32,
+    // 这些域名后缀照常转发给上游，拿到真实地址
+    exclude: Vec<String>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    by_name: HashMap<String, u32>,
+    by_index: HashMap<u32, String>,
+    next: u32,
+}
+
+impl Pool {
+    pub(crate) fn new(range: Cidr, exclude: Vec<String>) -> Result<Self> {
+        let (lo, hi) = range.range();
+        // range() 在 IPv4 映射的 IPv6 空间里给出区间
+        let (Some(base), Some(last)) = (v4(lo), v4(hi)) else {
+            anyhow::bail!("--fake-ip-range must be an IPv4 CIDR, got {}", range);
+        };
+        let size = last - base + 1;
+        if size < 4 { anyhow::bail!("--fake-ip-range {} is too small", range); }
+        let exclude = exclude.iter().map(|s| s.trim().trim_start_matches("*.").trim_start_matches('.').trim_end_matches('.').to_ascii_lowercase()).collect();
+        Ok(Self { range, base, size, exclude, state: Mutex::new(State { next: 1, ..State::default() }) })
+    }
+
+    fn excluded(&self, name: &str) -> bool {
+        self.exclude.iter().any(|s| name == s || name.strip_suffix(s.as_str()).is_some_and(|p| p.ends_with('.')))
+    }
+
+    // 同一域名保持同一地址；地址池用完后按分配顺序回收最早的地址
+    pub(crate) fn allocate(&self, name: &str) -> Option<Ipv4Addr> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        if self.excluded(&name) { return None; }
+        let mut st = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(&i) = st.by_name.get(&name) { return Some(Ipv4Addr::from(self.base + i)); }
+        let i = st.next;
+        st.next = if i + 1 >= self.size - 1 { 1 } else { i + 1 };
+        if let Some(old) = st.by_index.insert(i, name.clone()) { st.by_name.remove(&old); }
+        st.by_name.insert(name, i);
+        Some(Ipv4Addr::from(self.base + i))
+    }
+
+    // Some(None) 表示地址在池中但没有对应的域名（如重启前分配的地址）
+    pub(crate) fn lookup(&self, ip: Ipv4Addr) -> Option<Option<String>> {
+        let i = u32::from(ip).checked_sub(self.base).filter(|i| *i < self.size)?;
+        Some(self.state.lock().unwrap_or_else(|e| e.into_inner()).by_index.get(&i).cloned())
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        let st = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        format!("range {}, {} of {} addresses in use, {} excluded suffix(es)", self.range, st.by_name.len(), self.size - 2, self.exclude.len())
+    }
+}
+
+fn v4(key: u128) -> Option<u32> {
+    std::net::Ipv6Addr::from(key).to_ipv4_mapped().map(u32::from)
+}
+
+static POOL: OnceLock<Pool> = OnceLock::new();
+
+pub(crate) fn install(pool: Pool) {
+    let _ = POOL.set(pool);
+}
+
+pub(crate) fn installed() -> Option<&'static Pool> {
+    POOL.get()
+}
+
+// 在 Dialer 链最外层（路由钩子之前）把池中的地址换回域名
+pub(crate) fn wrap(inner: Arc<dyn Dialer>) -> Arc<dyn Dialer> {
+    if POOL.get().is_some() { Arc::new(FakeIpDialer { inner }) } else { inner }
+}
+
+struct FakeIpDialer {
+    inner: Arc<dyn Dialer>,
+}
+
+impl Dialer for FakeIpDialer {
+    fn dial<'a>(&'a self, req: DialRequest<'a>) -> DialFuture<'a> {
+        Box::pin(async move {
+            let hit = match (POOL.get(), req.host.parse::<Ipv4Addr>()) {
+                (Some(pool), Ok(ip)) => pool.lookup(ip).map(|name| (ip, name)),
+                _ => None,
+            };
+            match hit {
+                None => self.inner.dial(req).await,
+                Some((_, Some(name))) => self.inner.dial(req.retarget(&name, req.port, req.iface)).await,
+                Some((ip, None)) => Err(anyhow::anyhow!("fake IP {} has no domain mapping (stale client DNS cache?)", ip)),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_recycles_and_maps_back() {
+        let pool = Pool::new(Cidr::parse("198.18.0.0/29").unwrap(), vec![String::from("*.lan.example")]).unwrap();
+        let a = pool.allocate("A.example.com.").unwrap();
+        assert_eq!(a, Ipv4Addr::new(198, 18, 0, 1));
+        assert_eq!(pool.allocate("a.example.com"), Some(a));
+        assert_eq!(pool.lookup(a), Some(Some(String::from("a.example.com"))));
+        assert_eq!(pool.allocate("nas.lan.example"), None);
+        assert_eq!(pool.lookup(Ipv4Addr::new(10, 0, 0, 1)), None);
+        assert_eq!(pool.lookup(Ipv4Addr::new(198, 18, 0, 6)), Some(None));
+        // 6 个可用地址用完后回收最早分配的
+        for i in 2..=6 { assert_eq!(pool.allocate(&format!("h{}.example", i)), Some(Ipv4Addr::new(198, 18, 0, i))); }
+        assert_eq!(pool.allocate("late.example"), Some(a));
+        assert_eq!(pool.lookup(a), Some(Some(String::from("late.example"))));
+        assert_ne!(pool.allocate("a.example.com"), Some(a));
+        assert!(Pool::new(Cidr::parse("fd00::/64").unwrap(), vec![]).is_err());
+    }
+}
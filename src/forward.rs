@@ -0,0 +1,179 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::time::{timeout, Duration, Instant};
+
+use crate::dialer::DialRequest;
+use crate::listener::{AcceptBackoff, ListenerSettings};
+use crate::stats::Metered;
+use crate::util::{log_error, log_info, log_throttled, udp_socket_for};
+
+// 目标 HOST:PORT（已由 parse_listen_addr 校验），IPv6 去掉方括号
+fn split_target(target: &str) -> (String, u16) {
+    match target.rsplit_once(':') {
+        Some((h, p)) => (h.trim_start_matches('[').trim_end_matches(']').to_string(), p.parse().unwrap_or(0)),
+        None => (target.to_string(), 0),
+    }
+}
+
+// TCP 端口转发：接受的连接原样转发到固定目标，与代理会话共用出站、超时与流量统计
+async fn handle_tcp_forward<S: AsyncRead + AsyncWrite + Unpin>(mut inbound: S, target: &str, s: &ListenerSettings) -> Result<()> {
+    let (host, port) = split_target(target);
+    let host = host.as_str();
+    let outbound = s.dialer.dial(DialRequest::new(host, port, &s.iface, &[])).await?;
+    let outbound = Metered::new(crate::capture::maybe_wrap(outbound, host, port, false));
+    let (c2s, s2c) = crate::stats::relay(&mut inbound, outbound, host, None, s.session_timeout_ms).await?;
+    log_throttled(|| log_info(format!("TCP forward finished {}:{} (c->s: {} bytes, s->c: {} bytes)", host, port, c2s, s2c)));
+    Ok(())
+}
+
+pub async fn run_tcp_forward(listener: TcpListener, target: String, s: Arc<ListenerSettings>) -> Result<()> {
+    let listen = listener.local_addr()?.to_string();
+    let target = Arc::new(target);
+    log_info(format!("TCP forwarder listening on {} -> {}, bound to {}", listen, target, s.iface));
+    let mut backoff = AcceptBackoff::new();
+    loop {
+        let (inbound, peer_addr) = backoff.accept(&listen, || listener.accept()).await;
+        if !s.allows(peer_addr.ip()) {
+            log_throttled(|| log_info(format!("denied connection from {} to {} (not in allow list)", peer_addr, listen)));
+            continue;
+        }
+        match s.limit.acquire().await {
+            Some(permit) => {
+                let (s, target) = (s.clone(), target.clone());
+                let who = crate::process::Peer::tcp(&inbound);
+                tokio::spawn(crate::session::run("tcp-forward", peer_addr.to_string(), s.sockopts, async move {
+                    let _permit = permit;
+                    log_throttled(|| log_info(format!("Incoming TCP forward from {} -> {} (iface: {})", peer_addr, target, s.iface)));
+                    crate::process::record(who).await;
+                    if let Err(e) = handle_tcp_forward(inbound, &target, &s).await {
+                        crate::error::log_session_error(&format!("TCP forward to {}", target), &e);
+                    }
+                }));
+            }
+            None => {
+                log_throttled(|| log_info("too many concurrent connections; dropping new TCP forward connection"));
+            }
+        }
+    }
+}
+
+// UDP 端口转发：每个客户端地址对应一个经绑定网卡连接到目标的 socket（类似 NAT 映射），
+// 双向都无数据超过空闲时间后回收
+const MAX_DATAGRAM: usize = 65535;
+
+struct Mapping {
+    out: UdpSocket,
+    last: Mutex<Instant>,
+    up: AtomicU64,
+}
+
+impl Mapping {
+    fn touch(&self) {
+        *self.last.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last.lock().unwrap_or_else(|e| e.into_inner()).elapsed()
+    }
+}
+
+type Mappings = Arc<Mutex<HashMap<SocketAddr, Arc<Mapping>>>>;
+
+// `target` 为 HOST:PORT，每个新映射建立时重新解析
+async fn open_mapping(target: &str, iface: &str) -> Result<UdpSocket> {
+    let addr = crate::hosts::resolve_target(target)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("{} resolved to no addresses", target))?;
+    let out = udp_socket_for(addr, iface)?;
+    out.connect(addr).await?;
+    Ok(out)
+}
+
+pub async fn run_udp_forward(sock: UdpSocket, target: String, idle_secs: u64, s: Arc<ListenerSettings>) -> Result<()> {
+    let sock = Arc::new(sock);
+    let listen = sock.local_addr()?.to_string();
+    let idle = Duration::from_secs(idle_secs.max(1));
+    log_info(format!("UDP forwarder listening on {} -> {}, bound to {}, idle timeout {}s", listen, target, s.iface, idle.as_secs()));
+    let mappings: Mappings = Arc::new(Mutex::new(HashMap::new()));
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    loop {
+        let (n, peer) = match sock.recv_from(&mut buf).await {
+            Ok(v) => v,
+            Err(e) => {
+                log_error(format!("UDP forward recv error on {}: {}", listen, e));
+                continue;
+            }
+        };
+        if !s.allows(peer.ip()) {
+            log_throttled(|| log_info(format!("denied UDP datagram from {} to {} (not in allow list)", peer, listen)));
+            continue;
+        }
+        let existing = mappings.lock().unwrap_or_else(|e| e.into_inner()).get(&peer).cloned();
+        let mapping = match existing {
+            Some(m) => m,
+            None => {
+                let Some(permit) = s.limit.try_acquire() else {
+                    log_throttled(|| log_info("too many UDP forward mappings; dropping datagram"));
+                    continue;
+                };
+                let out = match open_mapping(&target, &s.iface).await {
+                    Ok(out) => out,
+                    Err(e) => {
+                        log_throttled(|| log_info(format!("UDP forward {} -> {} failed: {}", peer, target, e)));
+                        continue;
+                    }
+                };
+                let m = Arc::new(Mapping { out, last: Mutex::new(Instant::now()), up: AtomicU64::new(0) });
+                mappings.lock().unwrap_or_else(|e| e.into_inner()).insert(peer, m.clone());
+                let (sock, mappings, m2, target, iface) = (sock.clone(), mappings.clone(), m.clone(), target.clone(), s.iface.clone());
+                tokio::spawn(crate::session::run("udp-forward", peer.to_string(), s.sockopts, async move {
+                    let _permit = permit;
+                    let (host, port) = split_target(&target);
+                    crate::session::set_target(&host, port, &iface);
+                    log_info(format!("UDP mapping {} -> {} via {}", peer, target, iface));
+                    let start = Instant::now();
+                    let mut down = 0u64;
+                    let mut rbuf = vec![0u8; MAX_DATAGRAM];
+                    loop {
+                        let wait = idle.saturating_sub(m2.idle_for());
+                        if wait.is_zero() { break; }
+                        match timeout(wait, m2.out.recv(&mut rbuf)).await {
+                            // 超时后重新计算：期间可能有上行数据刷新了活跃时间
+                            Err(_) => continue,
+                            Ok(Err(e)) => {
+                                log_throttled(|| log_info(format!("UDP mapping {} recv error: {}", peer, e)));
+                                break;
+                            }
+                            Ok(Ok(n)) => {
+                                m2.touch();
+                                down += n as u64;
+                                crate::stats::count_bytes(0, n as u64);
+                                if let Err(e) = sock.send_to(&rbuf[..n], peer).await {
+                                    log_error(format!("UDP reply to {} failed: {}", peer, e));
+                                }
+                            }
+                        }
+                    }
+                    mappings.lock().unwrap_or_else(|e| e.into_inner()).remove(&peer);
+                    let up = m2.up.load(Ordering::Relaxed);
+                    crate::stats::record(&host, up, down, start.elapsed());
+                    log_info(format!("UDP mapping {} expired (up {} bytes, down {} bytes)", peer, up, down));
+                }));
+                m
+            }
+        };
+        mapping.touch();
+        mapping.up.fetch_add(n as u64, Ordering::Relaxed);
+        crate::stats::count_bytes(n as u64, 0);
+        if let Err(e) = mapping.out.send(&buf[..n]).await {
+            log_throttled(|| log_info(format!("UDP forward {} -> {} send failed: {}", peer, target, e)));
+        }
+    }
+}
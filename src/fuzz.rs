@@ -0,0 +1,99 @@
+// cargo-fuzz 目标（见 fuzz/）的入口：把任意字节当作客户端发来的数据，交给 HTTP 代理与 SOCKS5/SOCKS4
+// 握手处理，跑在内存管道上，出站连接一律被拒绝。只要求不 panic、不溢出，处理结果本身不关心
+use std::cell::RefCell;
+
+use crate::auth::{Authenticator, StaticAuth};
+use crate::dialer::{DialContext, DialFuture, DialRequest, Dialer};
+use crate::error::ProxyError;
+
+// 握手走到建连这一步即可，拒绝连接时各协议都会回错误应答再返回
+struct RefuseDialer;
+
+impl Dialer for RefuseDialer {
+    fn dial<'a>(&'a self, req: DialRequest<'a>) -> DialFuture<'a> {
+        let addr = std::net::SocketAddr::from(([192, 0, 2, 1], req.port));
+        Box::pin(async move { Err(ProxyError::ConnectRefused(addr).into()) })
+    }
+}
+
+thread_local! {
+    static RUNTIME: RefCell<Option<tokio::runtime::Runtime>> = const { RefCell::new(None) };
+}
+
+// 客户端写完 `data` 就关闭，读取超时只在处理逻辑卡住时才会触发
+fn run<F: std::future::Future>(f: impl FnOnce(tokio::io::DuplexStream) -> F, data: &[u8]) {
+    RUNTIME.with(|rt| {
+        let mut rt = rt.borrow_mut();
+        let rt = rt.get_or_insert_with(|| tokio::runtime::Builder::new_current_thread().enable_all().build().expect("tokio runtime"));
+        rt.block_on(async {
+            let (mut client, server) = tokio::io::duplex(data.len().max(1));
+            tokio::io::AsyncWriteExt::write_all(&mut client, data).await.expect("duplex write");
+            drop(client);
+            f(server).await;
+        });
+    });
+}
+
+// 首字节的最低位决定是否要求认证（用户名 u、密码 p），其余字节是请求
+fn split(data: &[u8]) -> (Option<StaticAuth>, &[u8]) {
+    match data.split_first() {
+        Some((flags, rest)) if flags & 1 == 1 => (Some(StaticAuth::new(Some(String::from("u")), Some(String::from("p")))), rest),
+        Some((_, rest)) => (None, rest),
+        None => (None, data),
+    }
+}
+
+pub fn http_request(data: &[u8]) {
+    let (auth, data) = split(data);
+    run(|inbound| async move {
+        let ctx = DialContext { dialer: &RefuseDialer, iface: "lo", deny: &[] };
+        let _ = crate::http_proxy::handle_http_proxy(inbound, None, ctx, auth.as_ref().map(|a| a as &dyn Authenticator), None, 1000, 1000).await;
+    }, data);
+}
+
+pub fn socks5_handshake(data: &[u8]) {
+    let (auth, data) = split(data);
+    run(|inbound| async move {
+        let ctx = DialContext { dialer: &RefuseDialer, iface: "lo", deny: &[] };
+        let _ = crate::socks5::handle_socks5(inbound, ctx, auth.as_ref().map(|a| a as &dyn Authenticator), 1000, 1000).await;
+    }, data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 没有 cargo-fuzz 时的简化版：在合法请求上随机改写、截断、拼接，跑一遍两个入口
+    #[test]
+    fn mutated_requests_do_not_panic() {
+        let seeds: [&[u8]; 6] = [
+            b"\x00GET http://a.example/ HTTP/1.1\r\nHost: a.example\r\n\r\n",
+            b"\x01CONNECT a.example:443 HTTP/1.1\r\nHost: a.example:443\r\nProxy-Authorization: Basic dTpw\r\n\r\n",
+            b"\x00\x05\x01\x00\x05\x01\x00\x03\x09a.example\x01\xbb",
+            b"\x01\x05\x02\x00\x02\x01\x01u\x01p\x05\x01\x00\x01\xc0\x00\x02\x01\x00\x50",
+            b"\x00\x04\x01\x00\x50\x00\x00\x00\x01\x00a.example\x00",
+            b"\x00\x05\x01\x00\x05\x01\x00\x04\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x00\x50",
+        ];
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for i in 0..1500 {
+            let mut data = seeds[i % seeds.len()].to_vec();
+            for _ in 0..1 + next() % 4 {
+                let at = (next() as usize) % (data.len() + 1);
+                match next() % 4 {
+                    0 => data.truncate(at),
+                    1 if at < data.len() => data[at] = next() as u8,
+                    2 => data.splice(at..at, (0..next() % 16).map(|_| next() as u8)).for_each(drop),
+                    _ => data.extend_from_within(at..),
+                }
+            }
+            http_request(&data);
+            socks5_handshake(&data);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{Duration, Instant};
+
+use crate::error::ProxyError;
+
+// 慢速客户端（slowloris）防护：已接入但还没读完请求的连接受 --max-handshakes（全局）与
+// --max-handshakes-per-ip（单个来源）限制；读请求头时另有 --header-min-rate 最低速率，
+// 只连不发的空闲连接仍由 --read-timeout-ms 兜底
+static PER_IP_MAX: AtomicUsize = AtomicUsize::new(0);
+static MIN_RATE: AtomicU64 = AtomicU64::new(0);
+
+// 收到第一个字节后给的宽限期，之后按平均速率检查，每 RATE_CHECK 至少检查一次
+const RATE_GRACE: Duration = Duration::from_secs(1);
+const RATE_CHECK: Duration = Duration::from_millis(500);
+
+// 各原因丢弃的慢速客户端数，见 /metrics
+const REASONS: [&str; 4] = ["limit", "per_ip", "timeout", "min_rate"];
+static DROPPED: [AtomicU64; 4] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+fn dropped(reason: usize) {
+    DROPPED[reason].fetch_add(1, Ordering::Relaxed);
+}
+
+// per_ip 为 0 时不限制单个来源；min_rate（字节/秒）为 0 时不检查速率
+pub(crate) fn configure(per_ip: usize, min_rate: u64) {
+    PER_IP_MAX.store(per_ip, Ordering::Relaxed);
+    MIN_RATE.store(min_rate, Ordering::Relaxed);
+}
+
+fn pending() -> &'static Mutex<HashMap<IpAddr, usize>> {
+    static PENDING: OnceLock<Mutex<HashMap<IpAddr, usize>>> = OnceLock::new();
+    PENDING.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// 握手完成或会话结束时 drop，归还全局与该来源的名额
+pub(crate) struct Permit {
+    _global: OwnedSemaphorePermit,
+    ip: Option<IpAddr>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let Some(ip) = self.ip else { return };
+        let mut t = pending().lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(n) = t.get_mut(&ip) {
+            *n -= 1;
+            if *n == 0 { t.remove(&ip); }
+        }
+    }
+}
+
+// 不等待；unix socket 与回环来源不计单 IP 名额
+pub(crate) fn acquire(global: &Arc<Semaphore>, peer: Option<IpAddr>) -> Option<Permit> {
+    let Ok(permit) = global.clone().try_acquire_owned() else {
+        dropped(0);
+        return None;
+    };
+    let max = PER_IP_MAX.load(Ordering::Relaxed);
+    let Some(ip) = peer.filter(|ip| max > 0 && !ip.is_loopback()) else { return Some(Permit { _global: permit, ip: None }) };
+    let mut t = pending().lock().unwrap_or_else(|e| e.into_inner());
+    let n = t.entry(ip).or_insert(0);
+    if *n >= max {
+        dropped(1);
+        return None;
+    }
+    *n += 1;
+    Some(Permit { _global: permit, ip: Some(ip) })
+}
+
+// 读客户端请求头（HTTP 代理与 WebSocket 隧道），总时长不超过 read_timeout_ms，并检查最低速率与行长、头部数上限
+pub(crate) async fn read_headers<S: AsyncRead + Unpin>(stream: &mut S, read_timeout_ms: u64) -> Result<Vec<u8>> {
+    let deadline = Instant::now() + Duration::from_millis(read_timeout_ms);
+    read_paced(stream, deadline, MIN_RATE.load(Ordering::Relaxed), RATE_GRACE).await
+}
+
+async fn read_paced<S: AsyncRead + Unpin>(stream: &mut S, deadline: Instant, min_rate: u64, grace: Duration) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(4096);
+    let mut tmp = [0u8; 1024];
+    let mut first: Option<Instant> = None;
+    loop {
+        let wake = match first.filter(|_| min_rate > 0) {
+            Some(_) => deadline.min(Instant::now() + RATE_CHECK),
+            None => deadline,
+        };
+        // read 可取消：超时只是醒来做检查，已读到的数据不会丢
+        match tokio::time::timeout_at(wake, stream.read(&mut tmp)).await {
+            Ok(n) => {
+                let n = n?;
+                if n == 0 { anyhow::bail!(ProxyError::Handshake(String::from("client closed before headers"))); }
+                first.get_or_insert_with(Instant::now);
+                buf.extend_from_slice(&tmp[..n]);
+                crate::head::check_limits(&buf).map_err(ProxyError::HeadersTooLarge)?;
+                if buf.windows(4).any(|w| w == b"\r\n\r\n") { return Ok(buf); }
+                if buf.len() > 64 * 1024 { anyhow::bail!(ProxyError::HeadersTooLarge(String::from("headers too large"))); }
+            }
+            Err(_) if Instant::now() >= deadline => {
+                dropped(2);
+                anyhow::bail!(ProxyError::Handshake(String::from("timed out reading request headers")));
+            }
+            Err(_) => {}
+        }
+        let Some(t0) = first.filter(|_| min_rate > 0) else { continue };
+        let elapsed = t0.elapsed();
+        if elapsed >= grace && (buf.len() as u128) * 1000 < min_rate as u128 * elapsed.as_millis() {
+            dropped(3);
+            anyhow::bail!(ProxyError::Handshake(format!("client sent headers too slowly ({} bytes in {}ms)", buf.len(), elapsed.as_millis())));
+        }
+    }
+}
+
+pub(crate) fn render_metrics() -> String {
+    let mut out = String::from("# HELP iface_proxy_slow_clients_dropped_total Connections dropped before finishing their request, by reason.\n");
+    out.push_str("# TYPE iface_proxy_slow_clients_dropped_total counter\n");
+    for (reason, n) in REASONS.iter().zip(&DROPPED) {
+        out.push_str(&format!("iface_proxy_slow_clients_dropped_total{{reason=\"{}\"}} {}\n", reason, n.load(Ordering::Relaxed)));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn caps_pending_handshakes_per_ip() {
+        configure(2, 0);
+        let global = Arc::new(Semaphore::new(8));
+        let ip: IpAddr = "192.0.2.9".parse().unwrap();
+        let a = acquire(&global, Some(ip)).unwrap();
+        let _b = acquire(&global, Some(ip)).unwrap();
+        assert!(acquire(&global, Some(ip)).is_none());
+        // 其他来源与回环不受影响
+        assert!(acquire(&global, Some("192.0.2.10".parse().unwrap())).is_some());
+        let _lo: Vec<_> = (0..3).map(|_| acquire(&global, Some("127.0.0.1".parse().unwrap())).unwrap()).collect();
+        drop(a);
+        assert!(acquire(&global, Some(ip)).is_some());
+        configure(0, 0);
+    }
+
+    #[tokio::test]
+    async fn drops_clients_below_min_rate() {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").await.unwrap();
+        assert!(read_paced(&mut server, deadline, 1000, Duration::from_millis(50)).await.is_ok());
+
+        // 只发了几个字节就停住
+        client.write_all(b"GET / HT").await.unwrap();
+        let e = read_paced(&mut server, deadline, 1000, Duration::from_millis(50)).await.unwrap_err();
+        assert!(e.to_string().contains("too slowly"), "{}", e);
+        assert!(deadline > Instant::now() + Duration::from_secs(3));
+    }
+}
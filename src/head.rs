@@ -0,0 +1,141 @@
+use anyhow::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// --max-header-line / --max-headers：读请求头时逐行检查，读完之前就拒绝超长的行或过多的头部；
+// 请求行不受行长限制，整个请求头另有 64KB 上限
+pub(crate) const DEFAULT_MAX_LINE: usize = 8192;
+pub(crate) const DEFAULT_MAX_FIELDS: usize = 100;
+static MAX_LINE: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_LINE);
+static MAX_FIELDS: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_FIELDS);
+
+pub(crate) fn set_limits(max_line: usize, max_fields: usize) {
+    MAX_LINE.store(max_line, Ordering::Relaxed);
+    MAX_FIELDS.store(max_fields, Ordering::Relaxed);
+}
+
+// `buf` 为已收到的部分请求头，可以还没读到空行
+pub(crate) fn check_limits(buf: &[u8]) -> std::result::Result<(), String> {
+    check_limits_with(buf, MAX_LINE.load(Ordering::Relaxed), MAX_FIELDS.load(Ordering::Relaxed))
+}
+
+fn check_limits_with(buf: &[u8], max_line: usize, max_fields: usize) -> std::result::Result<(), String> {
+    // 第一段是请求行；空行之后是请求体，不检查
+    for (n, line) in buf.split(|&b| b == b'\n').skip(1).enumerate() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() { break; }
+        if line.len() > max_line { return Err(format!("header line longer than {} bytes", max_line)); }
+        if n >= max_fields { return Err(format!("more than {} header fields", max_fields)); }
+    }
+    Ok(())
+}
+
+// HTTP 请求头的只读视图：请求行与各头部都是原始文本上的切片，查找时逐行扫描，不为每行分配 String；
+// 头部通常只有十几行，重复扫描比先建表便宜
+pub(crate) struct RequestHead<'a> {
+    pub(crate) method: &'a str,
+    pub(crate) uri: &'a str,
+    pub(crate) version: &'a str,
+    // 请求行之后的头部文本（含结尾的空行）
+    fields: &'a str,
+}
+
+impl<'a> RequestHead<'a> {
+    // `text` 为到空行为止的完整请求头
+    pub(crate) fn parse(text: &'a str) -> Result<Self> {
+        let (line, fields) = text.split_once("\r\n").unwrap_or((text, ""));
+        let mut parts = line.split_whitespace();
+        let mut next = || parts.next().ok_or_else(|| anyhow::anyhow!("bad request line"));
+        Ok(Self { method: next()?, uri: next()?, version: next()?, fields })
+    }
+
+    // 请求行之后的非空行
+    pub(crate) fn lines(&self) -> impl Iterator<Item = &'a str> {
+        self.fields.split("\r\n").filter(|l| !l.is_empty())
+    }
+
+    // (名字, 去掉首尾空白的值)；没有冒号的行跳过
+    pub(crate) fn fields(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.lines().filter_map(|l| l.split_once(':')).map(|(n, v)| (n.trim(), v.trim()))
+    }
+
+    // 第一个同名头部的值，名字不区分大小写
+    pub(crate) fn get(&self, name: &str) -> Option<&'a str> {
+        self.fields().find_map(|(n, v)| n.eq_ignore_ascii_case(name).then_some(v))
+    }
+
+    // 逗号分隔的头部（可出现多次）中是否含有 `token`，不区分大小写
+    pub(crate) fn has_token(&self, name: &str, token: &str) -> bool {
+        self.fields().filter(|(n, _)| n.eq_ignore_ascii_case(name)).any(|(_, v)| v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+    }
+
+    // Upgrade: websocket 且 Connection 中含 upgrade
+    pub(crate) fn is_websocket_upgrade(&self) -> bool {
+        self.has_token("upgrade", "websocket") && self.has_token("connection", "upgrade")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_line_and_fields() {
+        let text = "GET http://example.com/a HTTP/1.1\r\nHost:  example.com \r\nConnection: keep-alive, Upgrade\r\nupgrade: WebSocket\r\nX-Bad\r\nhost: other\r\n\r\n";
+        let head = RequestHead::parse(text).unwrap();
+        assert_eq!((head.method, head.uri, head.version), ("GET", "http://example.com/a", "HTTP/1.1"));
+        assert_eq!(head.get("HOST"), Some("example.com"));
+        assert_eq!(head.lines().count(), 5);
+        assert_eq!(head.fields().count(), 4);
+        assert!(head.has_token("connection", "upgrade"));
+        assert!(head.is_websocket_upgrade());
+        assert_eq!(head.get("content-length"), None);
+        assert!(RequestHead::parse("GET /\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn limits_apply_to_partial_heads() {
+        let long = format!("GET / HTTP/1.1\r\nX-Long: {}", "a".repeat(40));
+        assert!(check_limits_with(long.as_bytes(), 48, 10).is_ok());
+        assert!(check_limits_with(long.as_bytes(), 40, 10).is_err());
+        // 请求行与请求体不计
+        let text = format!("GET /{} HTTP/1.1\r\nA: 1\r\nB: 2\r\n\r\n{}\r\nC: 3\r\n", "x".repeat(100), "y".repeat(100));
+        assert!(check_limits_with(text.as_bytes(), 16, 2).is_ok());
+        assert_eq!(check_limits_with(text.as_bytes(), 16, 1), Err(String::from("more than 1 header fields")));
+    }
+
+    // 简单的变异模糊测试：随机拼接请求头片段与任意字节，解析与检查都不能 panic，
+    // 通过检查的输入每行都在上限内，且其任意前缀也通过（边读边检查时不会误判）
+    #[test]
+    fn fuzz_parse_and_limits() {
+        const PIECES: [&[u8]; 14] = [
+            b"GET ", b"CONNECT ", b"http://a.example/", b"a.example:443", b" HTTP/1.1", b"\r\n", b"\n", b"\r",
+            b"Host: a", b"Upgrade: websocket", b":", b" ", b"\xff\xfe", b"Connection: upgrade, close",
+        ];
+        let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for _ in 0..5000 {
+            let mut buf = Vec::new();
+            for _ in 0..next() % 24 {
+                match next() % 4 {
+                    0 => buf.extend((0..next() % 64).map(|_| next() as u8)),
+                    1 => buf.extend(std::iter::repeat_n(b'a', (next() % 96) as usize)),
+                    _ => buf.extend_from_slice(PIECES[(next() % PIECES.len() as u64) as usize]),
+                }
+            }
+            let text = String::from_utf8_lossy(&buf);
+            if let Ok(head) = RequestHead::parse(&text) {
+                let _ = (head.get("host"), head.fields().count(), head.is_websocket_upgrade());
+            }
+            let (max_line, max_fields) = (1 + (next() % 80) as usize, (next() % 8) as usize);
+            if check_limits_with(&buf, max_line, max_fields).is_err() { continue; }
+            let end = buf.windows(2).position(|w| w == b"\n\n" || w == b"\n\r").unwrap_or(buf.len());
+            assert!(buf[..end].split(|&b| b == b'\n').skip(1).all(|l| l.strip_suffix(b"\r").unwrap_or(l).len() <= max_line), "{:?}", text);
+            assert!((0..buf.len()).all(|i| check_limits_with(&buf[..i], max_line, max_fields).is_ok()), "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+
+use crate::router::Resolve;
+
+// --host / --hosts-file：静态的主机名到地址映射，出站连接解析与 DNS 转发都先查这里，查不到再走系统解析或上游
+
+// NAME=IP[,IP...]，NAME 可以是 *.example.com（只匹配子域名）
+#[derive(Clone, Debug)]
+pub(crate) struct HostEntry {
+    name: String,
+    addrs: Vec<IpAddr>,
+}
+
+impl HostEntry {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (name, addrs) = s.split_once('=').ok_or_else(|| anyhow::anyhow!("invalid host entry {:?} (expected NAME=IP[,IP])", s))?;
+        let name = normalize(name);
+        if name.is_empty() || name == "*." { anyhow::bail!("host entry {:?} has no name", s); }
+        let addrs = addrs
+            .split(',')
+            .map(|a| a.trim().trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().map_err(|_| anyhow::anyhow!("invalid address {:?} in host entry {:?}", a, s)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { name, addrs })
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[derive(Default)]
+struct Table {
+    exact: HashMap<String, Vec<IpAddr>>,
+    // *.example.com 去掉 `*.` 后的后缀
+    wildcard: HashMap<String, Vec<IpAddr>>,
+}
+
+impl Table {
+    // 先出现的条目优先，同名的后续条目忽略（hosts 文件中同名多行则合并地址）
+    fn add(&mut self, name: &str, addrs: &[IpAddr], merge: bool) {
+        let map = match name.strip_prefix("*.") {
+            Some(_) => &mut self.wildcard,
+            None => &mut self.exact,
+        };
+        let key = name.trim_start_matches("*.").to_string();
+        match map.get_mut(&key) {
+            Some(list) if merge => list.extend(addrs.iter().filter(|a| !list.contains(a)).copied().collect::<Vec<_>>()),
+            Some(_) => {}
+            None => { map.insert(key, addrs.to_vec()); }
+        }
+    }
+
+    // 完全匹配优先，其次最长的通配后缀
+    fn lookup(&self, name: &str) -> Option<&[IpAddr]> {
+        let name = normalize(name);
+        if let Some(a) = self.exact.get(&name) { return Some(a); }
+        let mut rest = name.as_str();
+        while let Some((_, parent)) = rest.split_once('.') {
+            if let Some(a) = self.wildcard.get(parent) { return Some(a); }
+            rest = parent;
+        }
+        None
+    }
+
+    fn len(&self) -> usize {
+        self.exact.len() + self.wildcard.len()
+    }
+}
+
+// hosts 文件格式：`IP 名字 [别名...]`，# 之后为注释
+fn read_hosts_file(table: &mut Table, path: &str) -> Result<()> {
+    let text = std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("read {}: {}", path, e))?;
+    let mut file = Table::default();
+    for line in text.lines() {
+        let mut fields = line.split('#').next().unwrap_or("").split_whitespace();
+        let Some(ip) = fields.next() else { continue };
+        // 带 zone 的链路本地地址（fe80::1%lo0）无法用于出站连接，跳过
+        let Ok(ip) = ip.parse::<IpAddr>() else { continue };
+        for name in fields {
+            file.add(&normalize(name), &[ip], true);
+        }
+    }
+    for (name, addrs) in file.exact { table.add(&name, &addrs, false); }
+    for (suffix, addrs) in file.wildcard { table.add(&format!("*.{}", suffix), &addrs, false); }
+    Ok(())
+}
+
+static TABLE: OnceLock<Table> = OnceLock::new();
+
+// --host 条目优先于 hosts 文件，文件之间按给出的顺序；返回摘要
+pub(crate) fn install(entries: &[HostEntry], files: &[String]) -> Result<String> {
+    let table = build(entries, files)?;
+    let summary = format!("{} name(s) ({} --host, {} hosts file(s))", table.len(), entries.len(), files.len());
+    let _ = TABLE.set(table);
+    Ok(summary)
+}
+
+fn build(entries: &[HostEntry], files: &[String]) -> Result<Table> {
+    let mut table = Table::default();
+    for e in entries { table.add(&e.name, &e.addrs, false); }
+    for path in files { read_hosts_file(&mut table, path)?; }
+    Ok(table)
+}
+
+pub(crate) fn lookup(name: &str) -> Option<&'static [IpAddr]> {
+    TABLE.get()?.lookup(name)
+}
+
+tokio::task_local! {
+    // 路由规则的 resolve:/dns: 动作：(目标主机, 解析方式, 连接的出口网卡)
+    static OVERRIDE: (String, Resolve, String);
+}
+
+// 在 `fut` 内连接 `host` 时按 `resolve` 解析，优先于静态映射
+pub(crate) async fn with_override<F: std::future::Future>(host: &str, resolve: Option<Resolve>, iface: &str, fut: F) -> F::Output {
+    match resolve {
+        Some(r) => OVERRIDE.scope((host.to_string(), r, iface.to_string()), fut).await,
+        None => fut.await,
+    }
+}
+
+// 只作用于规则命中的那个主机名（不影响如上游服务器地址的解析）；IP 字面量目标只接受 resolve:
+fn override_for(host: &str) -> Option<(Resolve, String)> {
+    OVERRIDE
+        .try_with(|(h, r, iface)| h.eq_ignore_ascii_case(host).then(|| (r.clone(), iface.clone())))
+        .ok()
+        .flatten()
+        .filter(|(r, _)| matches!(r, Resolve::Addr(_)) || host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().is_err())
+}
+
+// 出站连接用的解析：规则指定的解析方式优先，其次静态映射，否则交给系统解析
+pub(crate) async fn resolve(host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+    match override_for(host) {
+        Some((Resolve::Addr(ip), _)) => return Ok(vec![SocketAddr::new(ip, port)]),
+        Some((Resolve::Server { addr, iface }, default)) => {
+            let ips = crate::dns::lookup(host, addr, iface.as_deref().unwrap_or(&default)).await.map_err(std::io::Error::other)?;
+            return Ok(ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect());
+        }
+        None => {}
+    }
+    match lookup(host) {
+        Some(addrs) => Ok(addrs.iter().map(|ip| SocketAddr::new(*ip, port)).collect()),
+        None => Ok(tokio::net::lookup_host((host, port)).await?.collect()),
+    }
+}
+
+// `target` 为 HOST:PORT
+pub(crate) async fn resolve_target(target: &str) -> std::io::Result<Vec<SocketAddr>> {
+    match crate::uri::parse_authority(target, 0) {
+        Ok((host, port)) if lookup(&host).is_some() => resolve(&host, port).await,
+        _ => Ok(tokio::net::lookup_host(target).await?.collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_files_and_wildcards() {
+        let path = std::env::temp_dir().join(format!("iface-proxy-hosts-{}", std::process::id()));
+        std::fs::write(&path, "127.0.0.1 localhost\n10.0.0.9 db.corp.example db # primary\n10.0.0.10 db.corp.example\nfe80::1%lo0 localhost\n10.9.9.9 git.corp.example\n").unwrap();
+        let entries: Vec<HostEntry> = ["git.corp.example=10.0.0.2,fd00::2", "*.corp.example=10.0.0.1", "*.svc.corp.example=10.1.0.1"]
+            .iter()
+            .map(|e| HostEntry::parse(e).unwrap())
+            .collect();
+        let table = build(&entries, &[path.to_string_lossy().into_owned()]).unwrap();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(table.lookup("GIT.corp.example."), Some(&[ip("10.0.0.2"), ip("fd00::2")][..]));
+        assert_eq!(table.lookup("db.corp.example"), Some(&[ip("10.0.0.9"), ip("10.0.0.10")][..]));
+        assert_eq!(table.lookup("wiki.corp.example"), Some(&[ip("10.0.0.1")][..]));
+        assert_eq!(table.lookup("api.svc.corp.example"), Some(&[ip("10.1.0.1")][..]));
+        assert_eq!(table.lookup("corp.example"), None);
+        assert_eq!(table.lookup("localhost"), Some(&[ip("127.0.0.1")][..]));
+        assert!(HostEntry::parse("x.example=not-an-ip").is_err());
+        assert!(HostEntry::parse("=10.0.0.1").is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,143 +1,739 @@
 use anyhow::Result;
-use std::sync::Arc;
-use tokio::io::{copy_bidirectional, AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Semaphore;
-use tokio::time::{sleep, timeout, Duration};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{timeout, Duration};
 
-use crate::util::{connect_outbound, log_throttled, log_info, log_error, is_transient_anyhow_error};
+use crate::addr::{redact_authority, redact_host, redact_path};
+use crate::auth::Authenticator;
+use crate::error::{Denied, ProxyError};
+use crate::listener::{AcceptBackoff, Accepted, BoundListener, ListenerSettings};
+use crate::response::{ResponseWatch, Transaction};
+use crate::stats::Metered;
+use crate::dialer::{DialContext, DialRequest, Dialer, OutboundStream};
+use crate::head::RequestHead;
+use crate::util::{log_throttled, log_info, Cidr};
 
-async fn read_http_headers(stream: &mut TcpStream) -> Result<Vec<u8>> {
+pub(crate) async fn read_http_headers<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>> {
     let mut buf = Vec::with_capacity(4096);
     let mut tmp = [0u8; 1024];
     loop {
         let n = stream.read(&mut tmp).await?;
-        if n == 0 { anyhow::bail!("client closed before headers"); }
+        if n == 0 { anyhow::bail!(ProxyError::Handshake(String::from("client closed before headers"))); }
         buf.extend_from_slice(&tmp[..n]);
         if buf.windows(4).any(|w| w == b"\r\n\r\n") { return Ok(buf); }
-        if buf.len() > 64 * 1024 { anyhow::bail!("headers too large"); }
+        if buf.len() > 64 * 1024 { anyhow::bail!(ProxyError::Handshake(String::from("headers too large"))); }
     }
 }
 
-fn split_headers_body(buf: &[u8]) -> Option<(usize, &[u8])> {
+pub(crate) fn split_headers_body(buf: &[u8]) -> Option<(usize, &[u8])> {
     for i in 0..buf.len().saturating_sub(3) {
         if &buf[i..i+4] == b"\r\n\r\n" { return Some((i+4, &buf[i+4..])); }
     }
     None
 }
 
-fn parse_request_line<'a>(headers: &'a str) -> anyhow::Result<(&'a str, &'a str, &'a str)> {
-    let mut lines = headers.split("\r\n");
-    let line = lines.next().unwrap_or("");
-    let mut parts = line.split_whitespace();
-    let method = parts.next().ok_or_else(|| anyhow::anyhow!("bad request line"))?;
-    let uri = parts.next().ok_or_else(|| anyhow::anyhow!("bad request line"))?;
-    let version = parts.next().ok_or_else(|| anyhow::anyhow!("bad request line"))?;
-    Ok((method, uri, version))
+// 读请求头时多读到的字节（POST body 开头、CONNECT 后紧跟的 TLS ClientHello 等）须在开始双向转发前
+// 原样写给上游，否则会丢失；普通请求与 CONNECT 统一走这里，以后支持 keep-alive 时下一个请求也从这里接续
+async fn forward_buffered<W: AsyncWrite + Unpin>(outbound: &mut W, buffered: &[u8]) -> Result<()> {
+    if !buffered.is_empty() {
+        outbound.write_all(buffered).await?;
+        outbound.flush().await?;
+    }
+    Ok(())
+}
+
+// --lenient 关闭下面的严格检查，恢复对不规范客户端的宽松解析
+static LENIENT: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn set_lenient(on: bool) {
+    LENIENT.store(on, Ordering::Relaxed);
 }
 
-fn parse_host_from_headers(headers: &str) -> Option<String> {
-    for line in headers.split("\r\n").skip(1) {
-        if let Some(rest) = line.strip_prefix("Host:") { return Some(rest.trim().to_string()); }
-        if let Some(rest) = line.strip_prefix("host:") { return Some(rest.trim().to_string()); }
+// 明文 GET/HEAD 在连接失败或目标未应答即断开时重试一次
+static RETRY: AtomicBool = AtomicBool::new(true);
+
+pub(crate) fn set_retry(on: bool) {
+    RETRY.store(on, Ordering::Relaxed);
+}
+
+// CONNECT 允许的目标端口（闭区间），未设置时只允许 443 与 8443
+static CONNECT_PORTS: OnceLock<Vec<(u16, u16)>> = OnceLock::new();
+
+pub(crate) fn set_connect_ports(ranges: Vec<(u16, u16)>) {
+    let _ = CONNECT_PORTS.set(ranges);
+}
+
+fn connect_port_allowed(port: u16) -> bool {
+    match CONNECT_PORTS.get() {
+        Some(ranges) => ranges.iter().any(|&(lo, hi)| (lo..=hi).contains(&port)),
+        None => port == 443 || port == 8443,
     }
-    None
 }
 
-async fn handle_http_proxy(mut inbound: TcpStream, iface: &str, read_timeout_ms: u64, session_timeout_ms: u64) -> Result<()> {
-    let raw = timeout(Duration::from_millis(read_timeout_ms), read_http_headers(&mut inbound)).await??;
-    let (header_end, body_start) = split_headers_body(&raw).ok_or_else(|| anyhow::anyhow!("bad headers"))?;
-    let headers_str = String::from_utf8_lossy(&raw[..header_end]).to_string();
-    let (method, uri, version) = parse_request_line(&headers_str)?;
+// 代理自身生成的响应（CONNECT 的 200、407 与错误页）中的 Proxy-Agent，None 为不发送；
+// `connect` 为追加到 200 Connection Established 中的头
+struct ResponseHeaders {
+    agent: Option<String>,
+    connect: Vec<(String, String)>,
+}
+
+impl Default for ResponseHeaders {
+    fn default() -> Self {
+        Self { agent: Some(String::from(crate::build_info::AGENT)), connect: Vec::new() }
+    }
+}
+
+impl ResponseHeaders {
+    // 以 CRLF 结尾，不发送时为空
+    fn agent_line(&self) -> String {
+        self.agent.as_deref().map_or(String::new(), |a| format!("Proxy-Agent: {}\r\n", a))
+    }
+
+    fn established(&self) -> String {
+        let mut resp = format!("HTTP/1.1 200 Connection Established\r\n{}", self.agent_line());
+        for (name, value) in &self.connect {
+            resp.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        resp.push_str("\r\n");
+        resp
+    }
+}
+
+static RESPONSE_HEADERS: OnceLock<ResponseHeaders> = OnceLock::new();
+
+pub(crate) fn set_response_headers(agent: Option<String>, connect: Vec<(String, String)>) {
+    let _ = RESPONSE_HEADERS.set(ResponseHeaders { agent, connect });
+}
+
+fn response_headers() -> &'static ResponseHeaders {
+    RESPONSE_HEADERS.get_or_init(ResponseHeaders::default)
+}
+
+// --proxy-agent 的取值：不能含 CR/LF 等控制字符
+pub(crate) fn parse_agent(s: &str) -> Result<String> {
+    let s = s.trim();
+    anyhow::ensure!(!s.is_empty(), "empty Proxy-Agent value (use --no-proxy-agent to omit the header)");
+    anyhow::ensure!(!s.chars().any(|c| c.is_control()), "invalid Proxy-Agent value {:?}: contains control characters", s);
+    Ok(s.to_string())
+}
+
+// --connect-header 的 `NAME: VALUE`；CONNECT 的 2xx 响应不能带消息体长度
+pub(crate) fn parse_connect_header(s: &str) -> Result<(String, String)> {
+    let (name, value) = s.split_once(':').ok_or_else(|| anyhow::anyhow!("invalid header {:?} (expected NAME: VALUE)", s))?;
+    let (name, value) = (name.trim(), value.trim());
+    let token = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    anyhow::ensure!(!name.is_empty() && name.chars().all(token), "invalid header name in {:?}", s);
+    anyhow::ensure!(!value.chars().any(|c| c.is_control() && c != '\t'), "invalid header value in {:?}: contains control characters", s);
+    for reserved in ["content-length", "transfer-encoding", "connection"] {
+        anyhow::ensure!(!name.eq_ignore_ascii_case(reserved), "header {} is not allowed in a CONNECT response", name);
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+// PORT、LO-HI，或 `*` 表示全部端口
+pub(crate) fn parse_port_range(s: &str) -> Result<(u16, u16)> {
+    let s = s.trim();
+    if s == "*" { return Ok((1, u16::MAX)); }
+    let (lo, hi) = s.split_once('-').unwrap_or((s, s));
+    match (lo.trim().parse::<u16>(), hi.trim().parse::<u16>()) {
+        (Ok(lo), Ok(hi)) if lo > 0 && lo <= hi => Ok((lo, hi)),
+        _ => anyhow::bail!("invalid port or port range {:?} (expected PORT, LO-HI or *)", s),
+    }
+}
+
+// 严格解析请求头，拒绝可能导致请求走私或目标歧义的写法；返回的错误作为 400 的原因
+fn check_request_head(raw: &[u8], head: &RequestHead) -> std::result::Result<(), String> {
+    let RequestHead { method, uri, version, .. } = *head;
+    if !version.starts_with("HTTP/1.") { return Err(format!("unsupported HTTP version {:?}", version)); }
+    for (i, &b) in raw.iter().enumerate() {
+        let bare_lf = b == b'\n' && (i == 0 || raw[i - 1] != b'\r');
+        let bare_cr = b == b'\r' && raw.get(i + 1) != Some(&b'\n');
+        if bare_lf || bare_cr { return Err(String::from("bare CR or LF in request head")); }
+    }
+    let mut host: Option<&str> = None;
+    let mut length: Option<&str> = None;
+    let mut transfer_encoding: Option<&str> = None;
+    for line in head.lines() {
+        if line.starts_with([' ', '\t']) { return Err(String::from("obsolete header line folding")); }
+        let Some((name, value)) = line.split_once(':') else { return Err(format!("malformed header line {:?}", line)) };
+        if name.is_empty() || name.contains([' ', '\t']) { return Err(format!("malformed header name {:?}", name)); }
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("host") {
+            if host.is_some() { return Err(String::from("multiple Host headers")); }
+            host = Some(value);
+        } else if name.eq_ignore_ascii_case("content-length") {
+            let valid = !value.is_empty() && value.bytes().all(|c| c.is_ascii_digit()) && length.is_none_or(|l| l == value);
+            if !valid { return Err(String::from("invalid or conflicting Content-Length")); }
+            length = Some(value);
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            if transfer_encoding.is_some() { return Err(String::from("multiple Transfer-Encoding headers")); }
+            transfer_encoding = Some(value);
+        }
+    }
+    if let Some(te) = transfer_encoding {
+        if length.is_some() { return Err(String::from("both Content-Length and Transfer-Encoding present")); }
+        if !te.rsplit(',').next().is_some_and(|t| t.trim().eq_ignore_ascii_case("chunked")) { return Err(format!("unsupported Transfer-Encoding {:?}", te.to_ascii_lowercase())); }
+    }
+    if method.eq_ignore_ascii_case("CONNECT") { return Ok(()); }
+    if uri.starts_with('/') {
+        if host.is_none() { return Err(String::from("missing Host header")); }
+        return Ok(());
+    }
+    let target = crate::uri::parse_absolute(uri)?;
+    if let Some(host) = host {
+        let matches = crate::uri::parse_authority(host, target.port_default())
+            .is_ok_and(|(h, p)| h.eq_ignore_ascii_case(&target.host) && p == target.port);
+        if !matches { return Err(format!("Host header {:?} does not match request URI", host)); }
+    }
+    Ok(())
+}
+
+// 代理自身生成的错误响应，正文为一行说明
+fn error_response(status: &str, reason: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\n{}Content-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+        status,
+        response_headers().agent_line(),
+        reason.len() + 1,
+        reason
+    )
+}
+
+// 出站连接失败时：目标是代理自身回 508，目标被禁止回 403，其余错误直接断开
+pub(crate) async fn dial<S: AsyncWrite + Unpin>(inbound: &mut S, dialer: &dyn Dialer, host: &str, port: u16, iface: &str, deny_dest: &[Cidr]) -> Result<OutboundStream> {
+    let res = dialer.dial(DialRequest::new(host, port, iface, deny_dest)).await;
+    if let Err(e) = &res {
+        report_dial_error(inbound, e).await?;
+    }
+    res
+}
+
+// 回环 508、目标被拒 403、建连超时 504，其余连接失败 502
+async fn report_dial_error<S: AsyncWrite + Unpin>(inbound: &mut S, e: &anyhow::Error) -> Result<()> {
+    let status = match ProxyError::find(e) {
+        Some(ProxyError::PolicyDenied(Denied::Loop(_))) => "508 Loop Detected",
+        Some(ProxyError::PolicyDenied(_)) => "403 Forbidden",
+        Some(ProxyError::ConnectTimeout(_)) => "504 Gateway Timeout",
+        _ => "502 Bad Gateway",
+    };
+    inbound.write_all(error_response(status, &e.to_string()).as_bytes()).await?;
+    Ok(())
+}
+
+// Proxy-Authorization: Basic base64(user:pass)；用户名不含 `:`，密码可以
+fn proxy_credentials(head: &RequestHead) -> Option<(String, String)> {
+    basic_credentials(head.get("proxy-authorization")?)
+}
+
+// 解出 `Basic` 认证头中的用户名与密码
+pub(crate) fn basic_credentials(value: &str) -> Option<(String, String)> {
+    use base64::Engine;
+    let mut parts = value.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("basic") => {
+            let decoded = String::from_utf8(base64::engine::general_purpose::STANDARD.decode(token).ok()?).ok()?;
+            let (user, pass) = decoded.split_once(':')?;
+            Some((user.to_string(), pass.to_string()))
+        }
+        _ => None,
+    }
+}
+
+// `identity` 为 TLS 握手时已校验的客户端证书对应的用户名，有它时不再要求 Basic 认证
+pub(crate) async fn handle_http_proxy<S: AsyncRead + AsyncWrite + Unpin>(mut inbound: S, peer: Option<SocketAddr>, ctx: DialContext<'_>, auth: Option<&dyn Authenticator>, identity: Option<String>, read_timeout_ms: u64, session_timeout_ms: u64) -> Result<()> {
+    let DialContext { dialer, iface, deny: deny_dest } = ctx;
+    let raw = match crate::handshake::read_headers(&mut inbound, read_timeout_ms).await {
+        Ok(raw) => raw,
+        Err(e) => {
+            if let Some(ProxyError::HeadersTooLarge(reason)) = ProxyError::find(&e) {
+                inbound.write_all(error_response("431 Request Header Fields Too Large", reason).as_bytes()).await?;
+            }
+            return Err(e);
+        }
+    };
+    crate::session::handshake_done();
+    let (header_end, body_start) = split_headers_body(&raw).ok_or_else(|| ProxyError::Handshake(String::from("bad headers")))?;
+    // 合法 UTF-8 时直接借用原始字节，不复制
+    let text = String::from_utf8_lossy(&raw[..header_end]);
+    let head = RequestHead::parse(&text).map_err(|e| ProxyError::Handshake(e.to_string()))?;
+    let RequestHead { method, uri, version, .. } = head;
+    if !LENIENT.load(Ordering::Relaxed) {
+        if let Err(reason) = check_request_head(&raw[..header_end], &head) {
+            inbound.write_all(error_response("400 Bad Request", &reason).as_bytes()).await?;
+            anyhow::bail!(ProxyError::Handshake(format!("rejected malformed request: {}", reason)));
+        }
+    }
+
+    if crate::loopguard::seen_in_headers(&head) {
+        inbound.write_all(error_response("508 Loop Detected", "request already passed through this proxy").as_bytes()).await?;
+        anyhow::bail!("loop detected: request carries this proxy's Via/Proxy-Agent");
+    }
+
+    let mut user = identity;
+    if let Some(auth) = auth.filter(|_| user.is_none()) {
+        let name = match proxy_credentials(&head) {
+            Some((u, p)) => crate::auth::verify(auth, &u, &p).await.then_some(u),
+            // 管理接口签发的 Bearer 令牌，作用于所有要求认证的 HTTP 监听
+            None => head.get("proxy-authorization").and_then(crate::token::bearer_user),
+        };
+        let Some(name) = name else {
+            // 浏览器等客户端先不带凭据试探，收到 407 后才带上，这种不计为失败
+            if head.get("proxy-authorization").is_some() { crate::ban::record_failure(); }
+            let resp = format!(
+                "HTTP/1.1 407 Proxy Authentication Required\r\n{}Proxy-Authenticate: Basic realm=\"iface-proxy\"\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                response_headers().agent_line()
+            );
+            inbound.write_all(resp.as_bytes()).await?;
+            anyhow::bail!("HTTP proxy authentication failed");
+        };
+        user = Some(name);
+    }
+    if let Some(name) = &user {
+        if let Err(e) = crate::quota::check(name) {
+            inbound.write_all(error_response("403 Forbidden", &e.to_string()).as_bytes()).await?;
+            return Err(e);
+        }
+        crate::session::set_user(name);
+    }
+
+    // 客户端指定的出口网卡，须在 --egress-allow 中
+    let egress = crate::egress::from_headers(&head);
+    if let Some(name) = &egress {
+        if let Err(e) = crate::egress::check(name) {
+            inbound.write_all(error_response("403 Forbidden", &e.to_string()).as_bytes()).await?;
+            return Err(e);
+        }
+    }
+    let iface = egress.as_deref().unwrap_or(iface);
 
     if method.eq_ignore_ascii_case("CONNECT") {
-        let mut hp = uri.split(':');
-        let host = hp.next().unwrap_or("");
-        let port: u16 = hp.next().unwrap_or("443").parse().unwrap_or(443);
-        log_throttled(|| log_info(format!("HTTP CONNECT -> {}:{} (iface: {})", host, port, iface)));
-        let mut outbound = connect_outbound(host, port, iface).await?;
-        inbound.write_all(b"HTTP/1.1 200 Connection Established\r\nProxy-Agent: iface-proxy\r\n\r\n").await?;
-        let (c2s, s2c) = timeout(Duration::from_millis(session_timeout_ms), copy_bidirectional(&mut inbound, &mut outbound)).await??;
-        log_throttled(|| log_info(format!("HTTP CONNECT finished {}:{} (c->s: {} bytes, s->c: {} bytes)", host, port, c2s, s2c)));
+        let target = match crate::addr::TargetAddr::parse_authority(uri, 443) {
+            Ok(v) => v,
+            Err(e) => {
+                let reason = format!("bad CONNECT target: {}", e);
+                inbound.write_all(error_response("400 Bad Request", &reason).as_bytes()).await?;
+                anyhow::bail!(ProxyError::Handshake(format!("rejected CONNECT: {}", reason)));
+            }
+        };
+        let port = target.port();
+        if !connect_port_allowed(port) {
+            let reason = format!("CONNECT to port {} is not allowed (see --connect-ports)", port);
+            inbound.write_all(error_response("403 Forbidden", &reason).as_bytes()).await?;
+            anyhow::bail!("rejected CONNECT to {}: port not allowed", target.redacted());
+        }
+        let host = target.host();
+        let host = host.as_str();
+        crate::session::set_protocol("connect");
+        // 客户端不等 200 就发出的 ClientHello 已在 body_start 中，路由脚本可据此看到 SNI
+        // 带 ECH 时明文 SNI 只是公开名字，不用于路由，目标为 IP 时按 cidr: 等条件匹配
+        if crate::router::enabled() {
+            match crate::router::parse_client_hello(body_start) {
+                Some(hello) if hello.ech => log_throttled(|| log_info(format!(
+                    "HTTP CONNECT {}: ClientHello uses ECH, outer SNI {} ignored for routing",
+                    target.redacted(),
+                    hello.sni.as_deref().map_or(std::borrow::Cow::Borrowed("-"), redact_host)
+                ))),
+                Some(crate::router::ClientHello { sni: Some(sni), .. }) => crate::session::set_sni(sni),
+                _ => {}
+            }
+        }
+        log_throttled(|| log_info(format!("HTTP CONNECT -> {} (iface: {})", target.redacted(), iface)));
+        let outbound = dial(&mut inbound, dialer, host, port, iface, deny_dest).await?;
+        let mut outbound = Metered::new(crate::capture::maybe_wrap(outbound, host, port, false));
+        inbound.write_all(response_headers().established().as_bytes()).await?;
+        forward_buffered(&mut outbound, body_start).await?;
+        let (c2s, s2c) = crate::stats::relay(&mut inbound, outbound, host, user.as_deref(), session_timeout_ms).await?;
+        log_throttled(|| log_info(format!("HTTP CONNECT finished {} (c->s: {} bytes, s->c: {} bytes)", target.redacted(), c2s, s2c)));
         return Ok(());
     }
 
-    let (mut host, mut port, path) = if let Some(rest) = uri.strip_prefix("http://") {
-        if let Some(pos) = rest.find('/') { (rest[..pos].to_string(), 80u16, rest[pos..].to_string()) } else { (rest.to_string(), 80u16, "/".to_string()) }
-    } else if uri.starts_with('/') {
-        (parse_host_from_headers(&headers_str).unwrap_or_default(), 80u16, uri.to_string())
+    // ws:// 绝对 URI 与 http:// 相同处理（握手本身就是一个 HTTP/1.1 请求）；origin-form 从 Host 头取目标
+    let target = if uri.starts_with('/') {
+        crate::uri::parse_authority(head.get("host").unwrap_or_default(), 80).map(|(host, port)| crate::uri::AbsoluteUri {
+            scheme: String::from("http"),
+            userinfo: None,
+            host,
+            port,
+            path: uri.to_string(),
+        })
     } else {
-        anyhow::bail!("unsupported URI for HTTP proxy");
+        crate::uri::parse_absolute(uri)
     };
-    if let Some((h, p)) = host.clone().split_once(':') { host = h.to_string(); port = p.parse().unwrap_or(80); }
-
-    log_throttled(|| log_info(format!("HTTP {} {} -> {}:{} (iface: {})", method, path, host, port, iface)));
-    let mut outbound = connect_outbound(&host, port, iface).await?;
-
-    let mut lines = headers_str.split("\r\n");
-    let _first = lines.next();
-    let mut rebuilt = String::new();
-    rebuilt.push_str(&format!("{} {} {}\r\n", method, path, version));
-    let mut has_host = false;
-    for line in lines {
-        if line.is_empty() { continue; }
-        let lower = line.to_ascii_lowercase();
-        if lower.starts_with("host:") { has_host = true; }
-        if lower.starts_with("proxy-connection:") || lower.starts_with("proxy-authorization:") { continue; }
-        rebuilt.push_str(line);
-        rebuilt.push_str("\r\n");
-    }
-    if !has_host { if port == 80 { rebuilt.push_str(&format!("Host: {}\r\n", host)); } else { rebuilt.push_str(&format!("Host: {}:{}\r\n", host, port)); } }
+    let crate::uri::AbsoluteUri { userinfo, host, port, path, .. } = match target {
+        Ok(t) => t,
+        Err(reason) => {
+            inbound.write_all(error_response("400 Bad Request", &reason).as_bytes()).await?;
+            anyhow::bail!(ProxyError::Handshake(format!("rejected request: {}", reason)));
+        }
+    };
+
+    crate::session::set_protocol("http");
+    let target = crate::addr::TargetAddr::new(&host, port);
+    log_throttled(|| log_info(format!("HTTP {} {} -> {} (iface: {})", method, redact_path(&path), target.redacted(), iface)));
+    let mut headers: Vec<(String, String)> = Vec::with_capacity(16);
+    for (name, value) in head.fields() {
+        if ["proxy-connection", "proxy-authorization", crate::egress::HEADER].iter().any(|h| name.eq_ignore_ascii_case(h)) { continue; }
+        headers.push((name.to_string(), value.to_string()));
+    }
+    if !headers.iter().any(|(n, _)| n.trim().eq_ignore_ascii_case("host")) {
+        headers.push((String::from("Host"), crate::uri::format_authority(&host, port, 80)));
+    }
+    // URI 中的 user:pass 转为发往目标的 Basic 认证（请求已带 Authorization 时以其为准）
+    if let Some(userinfo) = &userinfo {
+        if !headers.iter().any(|(n, _)| n.trim().eq_ignore_ascii_case("authorization")) {
+            use base64::Engine;
+            headers.push((String::from("Authorization"), format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(userinfo))));
+        }
+    }
+    let decompress = crate::decompress::mode_for(&host);
+    if decompress == Some(crate::decompress::Mode::Strip) {
+        crate::decompress::strip_accept_encoding(&mut headers);
+    }
+    crate::rewrite::apply(&mut headers, &host, port, peer);
+
+    // 缓存按发往目标的最终请求头判断；命中时不连接目标，应答后关闭连接
+    let authority = crate::uri::format_authority(&host, port, 80);
+    // 日志中的 authority 与路径，见 --log-redact
+    let shown = format!("{}{}", redact_authority(&authority), redact_path(&path));
+    let cache_key = crate::cache::key(&authority, &path, !deny_dest.is_empty());
+    let websocket = head.is_websocket_upgrade();
+    // 路由钩子按会话判断（用户、进程、时段等），拦截或改写目标的请求不经过缓存
+    let lookup = if websocket || (crate::cache::enabled() && !crate::router::cacheable(&host, port)) {
+        crate::cache::Lookup::Bypass
+    } else {
+        crate::cache::lookup(&cache_key, method, &headers).await
+    };
+    let pending = match lookup {
+        crate::cache::Lookup::Hit(entry) => {
+            let mut inbound = crate::decompress::Decompress::new(inbound, "GET", decompress == Some(crate::decompress::Mode::Decode));
+            inbound.write_all(&entry.response(true)).await?;
+            inbound.shutdown().await?;
+            log_throttled(|| log_info(format!("HTTP {} {} -> {} ({} body bytes, cache hit)", method, shown, entry.status(), entry.body_len())));
+            return Ok(());
+        }
+        crate::cache::Lookup::Revalidate(entry) => {
+            let pending = crate::cache::Pending::new(cache_key, &headers, Some(entry.clone()));
+            crate::cache::add_validators(&entry, &mut headers);
+            Some(pending)
+        }
+        crate::cache::Lookup::Miss => Some(crate::cache::Pending::new(cache_key, &headers, None)),
+        crate::cache::Lookup::Bypass => None,
+    };
+
+    let mut rebuilt = String::with_capacity(header_end + 64);
+    for part in [method, " ", &path, " ", version, "\r\n"] { rebuilt.push_str(part); }
+    for (name, value) in &headers {
+        for part in [name.as_str(), ": ", value, "\r\n"] { rebuilt.push_str(part); }
+    }
     rebuilt.push_str("\r\n");
 
-    outbound.write_all(rebuilt.as_bytes()).await?;
-    if !body_start.is_empty() { inbound.write_all(body_start).await?; }
-    let (c2s, s2c) = timeout(Duration::from_millis(session_timeout_ms), copy_bidirectional(&mut inbound, &mut outbound)).await??;
-    log_throttled(|| log_info(format!("HTTP finished {} {} (c->s: {} bytes, s->c: {} bytes)", method, host, c2s, s2c)));
+    // GET/HEAD 且请求头之后没有其他数据时可以安全重发：连接失败，或目标在应答前断开/重置时，
+    // 从下一个解析地址开始再试一次；先读到的第一段应答在 relay 前转给客户端
+    let retry = RETRY.load(Ordering::Relaxed) && !websocket && body_start.is_empty() && (method.eq_ignore_ascii_case("GET") || method.eq_ignore_ascii_case("HEAD"));
+    let mut attempt = 0;
+    let (mut outbound, first) = loop {
+        let outbound = match dialer.dial(DialRequest::new(&host, port, iface, deny_dest).with_attempt(attempt)).await {
+            Ok(o) => o,
+            Err(e) if retry && attempt == 0 && !ProxyError::is_denied(&e) => {
+                log_throttled(|| log_info(format!("HTTP {} {}: connect failed ({}), retrying", method, shown, e)));
+                attempt += 1;
+                continue;
+            }
+            Err(e) => {
+                report_dial_error(&mut inbound, &e).await?;
+                return Err(e);
+            }
+        };
+        let outbound = crate::capture::maybe_wrap(outbound, &host, port, true);
+        let mut outbound = Metered::new(crate::dump::maybe_wrap(outbound, &host, port));
+        if !retry {
+            outbound.write_all(rebuilt.as_bytes()).await?;
+            forward_buffered(&mut outbound, body_start).await?;
+            break (outbound, Vec::new());
+        }
+        // 等第一段应答按读超时计，之后的 relay 才开始会话超时，避免一个请求占用近两倍的会话时长
+        let mut first = vec![0u8; 16 * 1024];
+        let res = timeout(Duration::from_millis(read_timeout_ms), async {
+            outbound.write_all(rebuilt.as_bytes()).await?;
+            outbound.read(&mut first).await
+        })
+        .await;
+        let why = match res {
+            Ok(Ok(n)) if n > 0 => {
+                first.truncate(n);
+                break (outbound, first);
+            }
+            Ok(Ok(_)) => String::from("connection closed before any response"),
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => format!("timed out after {}ms", read_timeout_ms),
+        };
+        if attempt == 0 {
+            log_throttled(|| log_info(format!("HTTP {} {}: {}, retrying", method, shown, why)));
+            attempt += 1;
+            continue;
+        }
+        inbound.write_all(error_response("502 Bad Gateway", &format!("no response from {}: {}", authority, why)).as_bytes()).await?;
+        anyhow::bail!("no response from {}: {}", target.redacted(), why);
+    };
+    if websocket {
+        // 先转回上游的握手响应；101 之后连接不再是 HTTP，两端直接互传 WebSocket 帧
+        let resp = timeout(Duration::from_millis(read_timeout_ms), read_http_headers(&mut outbound)).await??;
+        inbound.write_all(&resp).await?;
+        let status = String::from_utf8_lossy(&resp).split_whitespace().nth(1).unwrap_or("").to_string();
+        if status == "101" {
+            log_throttled(|| log_info(format!("WebSocket upgraded {}{}", target.redacted(), redact_path(&path))));
+        } else {
+            log_throttled(|| log_info(format!("WebSocket upgrade to {} refused ({})", target.redacted(), status)));
+        }
+        let (c2s, s2c) = crate::stats::relay(&mut inbound, outbound, &host, user.as_deref(), session_timeout_ms).await?;
+        log_throttled(|| log_info(format!("HTTP finished {} {} (c->s: {} bytes, s->c: {} bytes)", method, redact_host(&host), c2s, s2c)));
+        return Ok(());
+    }
+    // 按应答记录状态码与 body 大小；同一连接上后续请求的请求行未解析，以 `(next request)` 代替
+    let mut request = Some(format!("{} {}", method, shown));
+    let on_done = |t: &Transaction| {
+        let req = request.take().unwrap_or_else(|| format!("(next request) {}", redact_authority(&authority)));
+        let partial = if t.complete { "" } else { ", incomplete" };
+        log_throttled(|| log_info(format!("HTTP {} -> {} ({} body bytes{})", req, t.status, t.body_bytes, partial)));
+    };
+    // 缓存与解压都在 ResponseWatch 之内，日志与统计按上游实际发送的字节计；缓存保存的是解压前的原始应答
+    let inbound = crate::decompress::Decompress::new(inbound, method, decompress == Some(crate::decompress::Mode::Decode));
+    let mut inbound = ResponseWatch::new(crate::cache::Fill::new(inbound, method, pending), method, on_done);
+    let res = async {
+        inbound.write_all(&first).await?;
+        crate::stats::relay(&mut inbound, outbound, &host, user.as_deref(), session_timeout_ms).await
+    }
+    .await;
+    inbound.finish();
+    let (c2s, s2c) = res?;
+    log_throttled(|| log_info(format!("HTTP finished {} {} (c->s: {} bytes, s->c: {} bytes)", method, redact_host(&host), c2s, s2c)));
     Ok(())
 }
 
-pub async fn run_http_proxy(iface: &str, listen: &str, sem: Arc<Semaphore>, read_timeout_ms: u64, session_timeout_ms: u64) -> Result<()> {
-    let listener = TcpListener::bind(listen).await?;
-    log_info(format!("HTTP proxy listening on {}, bound to {}", listen, iface));
-    let mut backoff_ms: u64 = 50;
+async fn spawn_session<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(inbound: S, peer: Option<SocketAddr>, who: crate::process::Peer, listen: &str, s: &Arc<ListenerSettings>) {
+    let Some(pending) = s.limit.handshake(peer.map(|p| p.ip())) else {
+        log_throttled(|| log_info("too many connections still in handshake; dropping new HTTP connection"));
+        return;
+    };
+    match s.limit.acquire().await {
+        Some(permit) => {
+            let (s, listen) = (s.clone(), listen.to_string());
+            let peer_desc = peer.map(|p| p.to_string()).unwrap_or_else(|| String::from("unix"));
+            tokio::spawn(crate::session::run("http", peer_desc, s.sockopts, async move {
+                let _permit = permit; // held for lifetime of task
+                crate::session::hold_handshake(pending);
+                match peer {
+                    Some(peer_addr) => log_throttled(|| log_info(format!(
+                        "Incoming TCP connection from {} -> listening on {} (iface: {})",
+                        peer_addr, listen, s.iface
+                    ))),
+                    None => log_throttled(|| log_info(format!("Incoming connection on {} (iface: {})", listen, s.iface))),
+                }
+                crate::process::record(who).await;
+                let res = match &s.tls {
+                    Some(tls) => match tls.accept(inbound, s.read_timeout_ms).await {
+                        Ok((inbound, identity)) => serve(inbound, peer, identity, &s).await,
+                        Err(e) => Err(e),
+                    },
+                    None => serve(inbound, peer, None, &s).await,
+                };
+                if let Err(e) = res {
+                    crate::error::log_session_error("TCP handler", &e);
+                }
+            }));
+        }
+        None => {
+            log_throttled(|| log_info("too many concurrent connections; dropping new HTTP connection"));
+            // inbound dropped here
+        }
+    }
+}
+
+// 录制的是 TLS 解密后的明文
+async fn serve<S: AsyncRead + AsyncWrite + Unpin>(inbound: S, peer: Option<SocketAddr>, identity: Option<String>, s: &ListenerSettings) -> Result<()> {
+    if let Some(name) = &identity { log_throttled(|| log_info(format!("TLS client certificate accepted for {}", name))); }
+    let inbound = crate::record::wrap(inbound);
+    handle_http_proxy(inbound, peer, s.dial_context(), s.auth.as_deref(), identity, s.read_timeout_ms, s.session_timeout_ms).await
+}
+
+pub async fn run_http_proxy(listener: BoundListener, s: Arc<ListenerSettings>) -> Result<()> {
+    let listen = listener.local_desc();
+    match &s.tls {
+        Some(tls) => log_info(format!("HTTPS proxy listening on {}, bound to {} ({})", listen, s.iface, tls.describe())),
+        None => log_info(format!("HTTP proxy listening on {}, bound to {}", listen, s.iface)),
+    }
+    let mut backoff = AcceptBackoff::new();
     loop {
-        let (inbound, peer_addr) = match listener.accept().await {
-            Ok(v) => {
-                backoff_ms = 50; // reset backoff on success
-                v
+        let accepted = backoff.accept(&listen, || listener.accept()).await;
+        match accepted {
+            Accepted::Tcp(inbound, peer_addr) => {
+                if !s.allows(peer_addr.ip()) {
+                    log_throttled(|| log_info(format!("denied connection from {} to {} (not in allow list)", peer_addr, listen)));
+                    continue;
+                }
+                if crate::ban::banned(peer_addr.ip(), &listen) { continue; }
+                let who = crate::process::Peer::tcp(&inbound);
+                spawn_session(inbound, Some(peer_addr), who, &listen, &s).await;
             }
-            Err(e) => {
-                log_error(format!("accept error: {}", e));
-                sleep(Duration::from_millis(backoff_ms)).await;
-                backoff_ms = (backoff_ms.saturating_mul(2)).min(1000);
-                continue;
+            Accepted::Unix(inbound) => {
+                let who = crate::process::Peer::unix(&inbound);
+                spawn_session(inbound, None, who, &listen, &s).await;
             }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialer::MemoryDialer;
+
+    #[tokio::test]
+    async fn connect_tunnel_goes_through_dialer() {
+        let dialer = MemoryDialer::default();
+        let (mut client, server) = tokio::io::duplex(4096);
+        let session = handle_http_proxy(server, None, DialContext { dialer: &dialer, iface: "lo", deny: &[] }, None, None, 1000, 5000);
+        let client = async {
+            client.write_all(b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n").await.unwrap();
+            let mut head = vec![0u8; 256];
+            let n = client.read(&mut head).await.unwrap();
+            assert!(head[..n].starts_with(b"HTTP/1.1 200 "), "{:?}", String::from_utf8_lossy(&head[..n]));
+            client.write_all(b"ping").await.unwrap();
+            let mut echo = [0u8; 4];
+            client.read_exact(&mut echo).await.unwrap();
+            assert_eq!(&echo, b"ping");
+            drop(client);
         };
-        let listen_for_log = listen.to_string();
-        log_throttled(|| log_info(format!(
-            "Incoming TCP connection from {} -> listening on {} (iface: {})",
-            peer_addr, listen_for_log, iface
-        )));
-        let iface_for_task = iface.to_string();
-        let sem_clone = sem.clone();
-        match sem_clone.try_acquire_owned() {
-            Ok(permit) => {
-                tokio::spawn(async move {
-                    let _permit = permit; // held for lifetime of task
-                    if let Err(e) = handle_http_proxy(inbound, &iface_for_task, read_timeout_ms, session_timeout_ms).await {
-                        if is_transient_anyhow_error(&e) {
-                            log_info(format!("TCP handler transient: {}", e));
-                        } else {
-                            log_error(format!("TCP handler error: {}", e));
-                        }
-                    }
-                });
+        let (res, ()) = tokio::join!(session, client);
+        res.unwrap();
+        assert_eq!(*dialer.dialed.lock().unwrap(), ["example.com:443"]);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_header_lines() {
+        let dialer = MemoryDialer::default();
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let session = handle_http_proxy(server, None, DialContext { dialer: &dialer, iface: "lo", deny: &[] }, None, None, 1000, 5000);
+        let client = async {
+            let req = format!("GET http://a.example/ HTTP/1.1\r\nX-Big: {}\r\n", "a".repeat(crate::head::DEFAULT_MAX_LINE));
+            client.write_all(req.as_bytes()).await.unwrap();
+            let mut resp = String::new();
+            client.read_to_string(&mut resp).await.unwrap();
+            resp
+        };
+        let (res, resp) = tokio::join!(session, client);
+        assert!(matches!(res.as_ref().map_err(ProxyError::find), Err(Some(ProxyError::HeadersTooLarge(_)))));
+        assert!(resp.starts_with("HTTP/1.1 431 "), "{}", resp);
+        assert!(dialer.dialed.lock().unwrap().is_empty());
+    }
+
+    // 目标收下请求却一直不应答：读超时后重试一次，再回 502 而不是直接断开
+    #[tokio::test]
+    async fn silent_origin_gets_502_after_read_timeout() {
+        struct Silent;
+        impl Dialer for Silent {
+            fn dial<'a>(&'a self, _: DialRequest<'a>) -> crate::dialer::DialFuture<'a> {
+                Box::pin(async {
+                    let (near, mut far) = tokio::io::duplex(64 * 1024);
+                    tokio::spawn(async move { let _ = tokio::io::copy(&mut far, &mut tokio::io::sink()).await; });
+                    Ok(Box::new(near) as OutboundStream)
+                })
+            }
+        }
+        let (mut client, server) = tokio::io::duplex(4096);
+        let session = handle_http_proxy(server, None, DialContext { dialer: &Silent, iface: "lo", deny: &[] }, None, None, 100, 60_000);
+        let client = async {
+            client.write_all(b"GET http://a.example/ HTTP/1.1\r\nHost: a.example\r\n\r\n").await.unwrap();
+            let mut resp = String::new();
+            client.read_to_string(&mut resp).await.unwrap();
+            resp
+        };
+        let (res, resp) = tokio::time::timeout(Duration::from_secs(5), async { tokio::join!(session, client) }).await.unwrap();
+        assert!(res.is_err());
+        assert!(resp.starts_with("HTTP/1.1 502 ") && resp.contains("timed out after 100ms"), "{}", resp);
+    }
+
+    // 放行时存入缓存的应答，在路由改为 block 后不能再用缓存应答
+    #[tokio::test]
+    async fn blocked_host_is_not_served_from_cache() {
+        use std::sync::atomic::AtomicBool;
+        static BLOCK: AtomicBool = AtomicBool::new(false);
+        const HOST: &str = "blocked.cache-test.example";
+        struct Toggle;
+        impl crate::router::Router for Toggle {
+            fn route(&self, q: &crate::router::Query<'_>) -> Result<crate::router::Decision> {
+                let block = q.host == HOST && BLOCK.load(Ordering::SeqCst);
+                Ok(crate::router::Decision::new(if block { crate::router::Route::Block } else { crate::router::Route::Default }))
             }
-            Err(_) => {
-                log_throttled(|| log_info("too many concurrent connections; dropping new HTTP connection"));
-                // inbound dropped here
+        }
+        // 读完请求头后回一个可缓存的应答
+        struct Origin;
+        impl Dialer for Origin {
+            fn dial<'a>(&'a self, _: DialRequest<'a>) -> crate::dialer::DialFuture<'a> {
+                Box::pin(async {
+                    let (near, mut far) = tokio::io::duplex(64 * 1024);
+                    tokio::spawn(async move {
+                        let _ = read_http_headers(&mut far).await;
+                        let _ = far.write_all(b"HTTP/1.1 200 OK\r\nCache-Control: max-age=600\r\nContent-Length: 2\r\n\r\nok").await;
+                    });
+                    Ok(Box::new(near) as OutboundStream)
+                })
             }
         }
+        crate::router::set_router(Toggle).unwrap();
+        crate::cache::install(crate::cache::CacheConfig { memory: 1 << 20, max_object: 1 << 16, dir: None, disk: 0 });
+        let dialer = crate::router::wrap(Arc::new(Origin));
+        let get = || async {
+            let (mut client, server) = tokio::io::duplex(4096);
+            let session = handle_http_proxy(server, None, DialContext { dialer: &*dialer, iface: "lo", deny: &[] }, None, None, 1000, 5000);
+            let client = async {
+                client.write_all(format!("GET http://{}/page HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", HOST, HOST).as_bytes()).await.unwrap();
+                client.shutdown().await.unwrap();
+                let mut resp = String::new();
+                client.read_to_string(&mut resp).await.unwrap();
+                resp
+            };
+            tokio::time::timeout(Duration::from_secs(5), async { tokio::join!(session, client) }).await.unwrap().1
+        };
+        assert!(get().await.ends_with("\r\n\r\nok"));
+        BLOCK.store(true, Ordering::SeqCst);
+        let resp = get().await;
+        assert!(resp.starts_with("HTTP/1.1 403 "), "{}", resp);
+    }
+
+    #[test]
+    fn strict_head_checks() {
+        let check = |text: &str| check_request_head(text.as_bytes(), &RequestHead::parse(text).unwrap());
+        assert_eq!(check("GET http://A.example/ HTTP/1.1\r\nHost: a.example\r\nContent-Length: 3\r\ncontent-length: 3\r\n\r\n"), Ok(()));
+        assert_eq!(check("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip, Chunked\r\n\r\n"), Ok(()));
+        for bad in [
+            "GET / HTTP/1.1\r\nHost: a\r\nhost: b\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\r\nContent-Length: +3\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked, gzip\r\n\r\n",
+            "GET / HTTP/1.1\r\nX: 1\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\r\n folded\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost : a\r\n\r\n",
+            "GET http://a.example/ HTTP/1.1\r\nHost: b.example\r\n\r\n",
+            "GET / HTTP/2.0\r\nHost: a\r\n\r\n",
+        ] {
+            assert!(check(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn connect_response_headers() {
+        assert_eq!(ResponseHeaders::default().established(), format!("HTTP/1.1 200 Connection Established\r\nProxy-Agent: {}\r\n\r\n", crate::build_info::AGENT));
+        let custom = ResponseHeaders { agent: None, connect: vec![parse_connect_header("X-Corp-Gateway:  edge-1 ").unwrap()] };
+        assert_eq!(custom.established(), "HTTP/1.1 200 Connection Established\r\nX-Corp-Gateway: edge-1\r\n\r\n");
+        assert_eq!(custom.agent_line(), "");
+        assert_eq!(parse_agent(" squid/6.1 ").unwrap(), "squid/6.1");
+        for bad in ["novalue", "Bad Name: x", "X-A: a\r\nX-B: b", "Content-Length: 0", ": x"] {
+            assert!(parse_connect_header(bad).is_err(), "{:?}", bad);
+        }
+        assert!(parse_agent("a\nb").is_err());
     }
 }
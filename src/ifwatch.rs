@@ -0,0 +1,97 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::util::{list_interfaces, log_error, log_info};
+
+// --on-iface-change：定时检查网卡地址，出站连接所用的本地地址消失（DHCP 换地址、VPN 重连、网卡断开）时
+// 按策略处理这些会话，避免它们挂到超时；新连接本来就会用新地址
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Policy {
+    // 只记录日志
+    Keep,
+    // 宽限期后仍在用旧地址的会话关闭
+    Drain,
+    // 立即关闭
+    Kill,
+}
+
+impl Policy {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "keep" => Ok(Self::Keep),
+            "drain" => Ok(Self::Drain),
+            "kill" | "kill-on-change" => Ok(Self::Kill),
+            _ => anyhow::bail!("invalid interface change policy {:?} (expected keep, drain or kill)", s),
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Keep => "keep",
+            Self::Drain => "drain",
+            Self::Kill => "kill",
+        }
+    }
+}
+
+// 处于 up 状态的网卡及其地址
+fn snapshot() -> BTreeMap<String, BTreeSet<IpAddr>> {
+    match list_interfaces() {
+        Ok(list) => list.into_iter().filter(|i| i.is_up).map(|i| (i.name, i.addrs.into_iter().collect())).collect(),
+        Err(e) => {
+            log_error(format!("iface watch: cannot list interfaces: {}", e));
+            BTreeMap::new()
+        }
+    }
+}
+
+fn present(snap: &BTreeMap<String, BTreeSet<IpAddr>>, ip: IpAddr) -> bool {
+    snap.values().any(|addrs| addrs.contains(&ip))
+}
+
+fn show(addrs: Option<&BTreeSet<IpAddr>>) -> String {
+    match addrs {
+        None => String::from("down"),
+        Some(a) if a.is_empty() => String::from("no addresses"),
+        Some(a) => a.iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join(","),
+    }
+}
+
+pub(crate) async fn watch(policy: Policy, interval: Duration, grace: Duration) {
+    let mut last = snapshot();
+    loop {
+        tokio::time::sleep(interval).await;
+        let now = snapshot();
+        if now == last { continue; }
+        let names: BTreeSet<&String> = last.keys().chain(now.keys()).collect();
+        for name in names {
+            let (before, after) = (last.get(name), now.get(name));
+            if before != after { log_info(format!("iface {}: {} -> {}", name, show(before), show(after))); }
+        }
+        let gone: BTreeSet<IpAddr> = last.values().flatten().copied().filter(|ip| !present(&now, *ip)).collect();
+        last = now;
+        if gone.is_empty() { continue; }
+        let affected = crate::session::count_where(|ip| gone.contains(&ip));
+        if affected == 0 { continue; }
+        let list = gone.iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join(",");
+        match policy {
+            Policy::Keep => log_info(format!("iface change: {} session(s) still use {} (keeping them)", affected, list)),
+            Policy::Kill => {
+                let n = crate::session::close_where(|ip| gone.contains(&ip));
+                log_info(format!("iface change: closed {} session(s) using {}", n, list));
+            }
+            Policy::Drain => {
+                log_info(format!("iface change: {} session(s) use {}, closing those still open in {}s", affected, list, grace.as_secs()));
+                tokio::spawn(async move {
+                    tokio::time::sleep(grace).await;
+                    // 期间地址可能又回来了（如 VPN 重连拿到同一地址），只关仍然消失的
+                    let snap = snapshot();
+                    let n = crate::session::close_where(|ip| gone.contains(&ip) && !present(&snap, ip));
+                    if n > 0 { log_info(format!("iface change: closed {} drained session(s) using {}", n, list)); }
+                });
+            }
+        }
+    }
+}
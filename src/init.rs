@@ -0,0 +1,167 @@
+use std::io::{self, BufRead, Write};
+use std::net::SocketAddr;
+
+use anyhow::Result;
+
+use crate::util::list_interfaces;
+
+fn prompt(question: &str, default: &str) -> Result<String> {
+    if default.is_empty() { print!("{}: ", question); } else { print!("{} [{}]: ", question, default); }
+    io::stdout().flush()?;
+    let mut line = String::new();
+    // EOF 时直接采用默认值，便于 `yes '' | iface-proxy init` 之类的非交互使用
+    if io::stdin().lock().read_line(&mut line)? == 0 { println!(); return Ok(default.to_string()); }
+    let answer = line.trim();
+    if answer.is_empty() { Ok(default.to_string()) } else { Ok(answer.to_string()) }
+}
+
+fn prompt_yes_no(question: &str, default: bool) -> Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    loop {
+        let answer = prompt(&format!("{} ({})", question, hint), "")?;
+        match answer.to_ascii_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("请输入 y 或 n"),
+        }
+    }
+}
+
+fn prompt_addr(question: &str, default: &str) -> Result<String> {
+    loop {
+        let answer = prompt(question, default)?;
+        if answer.parse::<SocketAddr>().is_ok() { return Ok(answer); }
+        println!("无效地址: {}（格式: IP:PORT）", answer);
+    }
+}
+
+fn prompt_iface() -> Result<String> {
+    let ifaces: Vec<_> = list_interfaces()?.into_iter().filter(|i| i.is_up && !i.is_loopback && !i.addrs.is_empty()).collect();
+    if ifaces.is_empty() {
+        println!("未检测到可用网卡，请手动输入。");
+        return prompt("出站网卡名称", "en0");
+    }
+    println!("检测到以下网卡:");
+    for (idx, iface) in ifaces.iter().enumerate() {
+        let addrs: Vec<String> = iface.addrs.iter().map(|a| a.to_string()).collect();
+        println!("  {}) {:<10} {}", idx + 1, iface.name, addrs.join(", "));
+    }
+    let default = ifaces.iter().position(|i| i.name == "en0").unwrap_or(0) + 1;
+    loop {
+        let answer = prompt("选择出站网卡（序号或名称）", &default.to_string())?;
+        if let Ok(n) = answer.parse::<usize>() {
+            if n >= 1 && n <= ifaces.len() { return Ok(ifaces[n - 1].name.clone()); }
+        } else if let Some(iface) = ifaces.iter().find(|i| i.name == answer) {
+            return Ok(iface.name.clone());
+        } else if prompt_yes_no(&format!("网卡 {} 当前不可用，仍然使用", answer), false)? {
+            return Ok(answer);
+        }
+        println!("无效选择: {}", answer);
+    }
+}
+
+struct Answers {
+    iface: String,
+    listen: String,
+    socks5: Option<String>,
+    auth: Option<(String, String)>,
+}
+
+fn render_config(a: &Answers) -> String {
+    let mut out = String::new();
+    out.push_str("# iface-proxy 配置文件（由 `iface-proxy init` 生成）\n");
+    out.push_str("# 用法: iface-proxy --config <本文件路径>\n");
+    out.push_str("# 每行 `key = value` 等价于命令行参数 `--key value`，只有 key 的行等价于开关参数。\n");
+    out.push_str("# 命令行参数优先于本文件。\n\n");
+    out.push_str("# 出站网卡，所有外发连接绑定到该网卡\n");
+    out.push_str(&format!("iface = {}\n\n", a.iface));
+    out.push_str("# HTTP 代理监听地址（HTTP/1.x 与 CONNECT）\n");
+    out.push_str(&format!("listen = {}\n\n", a.listen));
+    out.push_str("# SOCKS5 代理（默认关闭）\n");
+    match &a.socks5 {
+        Some(addr) => {
+            out.push_str("socks5\n");
+            out.push_str(&format!("socks5-listen = {}\n", addr));
+        }
+        None => {
+            out.push_str("# socks5\n");
+            out.push_str("# socks5-listen = 127.0.0.1:7080\n");
+        }
+    }
+    match &a.auth {
+        Some((user, pass)) => {
+            out.push_str("\n# SOCKS5 用户名/密码认证\n");
+            out.push_str(&format!("socks5-user = {}\n", user));
+            out.push_str(&format!("socks5-pass = {}\n", pass));
+        }
+        None => {
+            out.push_str("\n# SOCKS5 用户名/密码认证（不设置则无需认证）\n");
+            out.push_str("# socks5-user = user\n");
+            out.push_str("# socks5-pass = pass\n");
+        }
+    }
+    out.push_str("\n# 并发与超时\n");
+    out.push_str("# max-conns = 10000\n");
+    out.push_str("# max-conns-wait-ms = 0\n");
+    out.push_str("# read-timeout-ms = 10000\n");
+    out.push_str("# session-timeout-ms = 600000\n");
+    out
+}
+
+pub(crate) fn write_private_file(path: &std::path::Path, contents: &str) -> Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+    if let Some(dir) = path.parent() { std::fs::create_dir_all(dir)?; }
+    let mut f = std::fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    f.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+pub(crate) fn run_init_wizard() -> Result<()> {
+    println!("iface-proxy 初始化向导（直接回车使用方括号中的默认值）\n");
+    let iface = prompt_iface()?;
+    let listen = prompt_addr("HTTP 代理监听地址", "127.0.0.1:7890")?;
+    let socks5 = if prompt_yes_no("启用 SOCKS5 代理", false)? {
+        Some(prompt_addr("SOCKS5 监听地址", "127.0.0.1:7080")?)
+    } else {
+        None
+    };
+    let auth = if socks5.is_some() && prompt_yes_no("SOCKS5 需要用户名/密码认证", false)? {
+        let user = loop {
+            let u = prompt("用户名", "")?;
+            if !u.is_empty() && u.len() <= 255 { break u; }
+            println!("用户名长度需为 1-255");
+        };
+        let pass = loop {
+            let p = prompt("密码", "")?;
+            if !p.is_empty() && p.len() <= 255 { break p; }
+            println!("密码长度需为 1-255");
+        };
+        Some((user, pass))
+    } else {
+        None
+    };
+
+    let default_path = match std::env::var("HOME") {
+        Ok(home) => format!("{}/.config/iface-proxy/iface-proxy.conf", home),
+        Err(_) => String::from("iface-proxy.conf"),
+    };
+    let path = prompt("配置文件路径", &default_path)?;
+    let path_buf = std::path::PathBuf::from(&path);
+    if path_buf.exists() && !prompt_yes_no(&format!("{} 已存在，是否覆盖", path), false)? {
+        println!("已取消，未写入任何文件。");
+        return Ok(());
+    }
+    let answers = Answers { iface, listen, socks5, auth };
+    write_private_file(&path_buf, &render_config(&answers))?;
+    println!("配置已写入 {}", path);
+
+    if cfg!(any(target_os = "macos", target_os = "linux")) && prompt_yes_no("注册为自动启动的服务 (macOS launchd / Linux systemd)", false)? {
+        let abs = std::fs::canonicalize(&path_buf)?;
+        if let Err(e) = crate::service::install(&abs.to_string_lossy()) {
+            println!("服务注册失败: {}", e);
+        }
+    }
+    println!("\n启动命令: iface-proxy --config {}", path);
+    Ok(())
+}
@@ -0,0 +1,472 @@
+// iface-proxy 也可作为库嵌入（如 Android/termux 应用）：`run_cli` 即命令行入口，
+// `set_prepare_socket` 可替换出站 socket 的默认网卡绑定（如调用 VpnService.protect）
+use anyhow::Result;
+
+mod util;
+mod error;
+mod http_proxy;
+mod socks5;
+mod addr;
+mod access_log;
+mod logsink;
+mod socks4;
+mod mixed;
+mod config;
+mod init;
+mod alloc;
+mod admin;
+mod listener;
+mod shadowsocks;
+mod websocket;
+mod tls;
+mod ssh;
+mod upstream;
+mod dialer;
+mod auth;
+mod router;
+mod script;
+mod rules;
+mod ruleset;
+mod privdrop;
+mod cli;
+mod service;
+mod check;
+mod build_info;
+mod metrics;
+mod statsd;
+mod stats;
+mod quota;
+mod ban;
+mod handshake;
+mod token;
+mod cache;
+mod capture;
+mod chaos;
+mod record;
+mod decompress;
+mod dump;
+mod rewrite;
+mod loopguard;
+mod acl;
+mod dns;
+mod hosts;
+mod nat64;
+mod fakeip;
+mod forward;
+mod head;
+mod egress;
+mod ifwatch;
+mod balance;
+mod session;
+mod selftest;
+mod bench;
+mod probe;
+mod process;
+mod mdns;
+mod portmap;
+mod uri;
+mod response;
+// cargo-fuzz 目标的入口（fuzz/），正常构建不包含
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
+
+use listener::ListenerContext;
+
+pub use dialer::{set_dialer, DialFuture, DialRequest, Dialer, DirectDialer, OutboundStream, ProxyStream};
+pub use record::{Frame, Recording};
+pub use router::{set_router, Decision, Query, Route, Router};
+pub use upstream::UpstreamDialer;
+pub use util::{set_prepare_socket, SocketInfo};
+
+// 按命令行参数运行（`args[0]` 为程序名），与可执行文件行为一致；需在 tokio 多线程运行时中调用
+pub async fn run_cli(args: Vec<String>) -> Result<()> {
+    let cli = cli::parse(args)?;
+    match cli.command {
+        None => run(cli.run).await,
+        Some(cli::Command::Run(args)) => run(args).await,
+        Some(cli::Command::CheckConfig(args)) => check::run_check(args).await,
+        Some(cli::Command::ListIfaces) => list_ifaces(),
+        Some(cli::Command::Service(cmd)) => service::run(cmd),
+        Some(cli::Command::Init) => init::run_init_wizard(),
+        Some(cli::Command::SelfTest(args)) => selftest::run_self_test(args).await,
+        Some(cli::Command::Bench(args)) => bench::run_bench(args).await,
+    }
+}
+
+// --mdns：每种服务类型发布第一个对应的监听，mixed 同时算作 HTTP 与 SOCKS5；只监听回环地址的不发布
+fn mdns_config(bound: &listener::BoundSet, name: Option<String>, ifaces: Vec<String>, iface: &str) -> mdns::MdnsConfig {
+    use listener::ListenerKind;
+    let mut services: Vec<mdns::Service> = Vec::new();
+    for (kind, types) in [
+        (ListenerKind::Http, &["_http-proxy._tcp"][..]),
+        (ListenerKind::Socks5, &["_socks._tcp"][..]),
+        (ListenerKind::Mixed, &["_http-proxy._tcp", "_socks._tcp"][..]),
+    ] {
+        let Some(addr) = bound.addr(kind) else { continue };
+        let bind = match addr.ip() {
+            ip if ip.is_unspecified() => None,
+            std::net::IpAddr::V4(v4) if !v4.is_loopback() => Some(v4),
+            _ => continue,
+        };
+        for t in types {
+            if !services.iter().any(|s| s.kind == *t) { services.push(mdns::Service { kind: t, port: addr.port(), bind }); }
+        }
+    }
+    mdns::MdnsConfig {
+        instance: name.unwrap_or_else(|| format!("iface-proxy on {}", mdns::hostname())),
+        services,
+        ifaces,
+        txt: vec![format!("iface={}", iface)],
+    }
+}
+
+// --port-map：管理接口与只监听回环地址的不映射，外部端口与监听端口相同
+fn port_mappings(bound: &listener::BoundSet) -> Vec<portmap::Mapping> {
+    let mut out: Vec<portmap::Mapping> = Vec::new();
+    for (kind, addr) in bound.inet_addrs() {
+        if kind == listener::ListenerKind::Admin { continue; }
+        let bind = match addr.ip() {
+            ip if ip.is_unspecified() => None,
+            std::net::IpAddr::V4(v4) if !v4.is_loopback() => Some(v4),
+            _ => continue,
+        };
+        let proto = if kind.is_udp() { portmap::Proto::Udp } else { portmap::Proto::Tcp };
+        if out.iter().any(|m| m.proto == proto && m.port == addr.port()) { continue; }
+        out.push(portmap::Mapping { proto, port: addr.port(), bind, label: kind.name() });
+    }
+    out
+}
+
+fn list_ifaces() -> Result<()> {
+    for info in crate::util::list_interfaces()? {
+        let addrs: Vec<String> = info.addrs.iter().map(|a| a.to_string()).collect();
+        println!(
+            "{:<12} {:<4}{} {}",
+            info.name,
+            if info.is_up { "up" } else { "down" },
+            if info.is_loopback { " loopback" } else { "" },
+            addrs.join(", ")
+        );
+    }
+    Ok(())
+}
+
+async fn run(args: cli::RunArgs) -> Result<()> {
+    // 最先接上日志输出，启动阶段的日志也送到同一处
+    if let Some(spec) = &args.log_sink {
+        logsink::install(logsink::Stream::App, spec)?;
+        crate::util::log_info(format!("log sink: {}", spec.describe()));
+    }
+    // 尝试提高 NOFILE 软/硬限制（不保证成功）
+    crate::util::try_raise_nofile_limit(65536);
+    let specs = args.listener_specs()?;
+    let ss = args.ss_config(&specs)?;
+    let dns_upstreams = args.dns_upstreams(&specs)?;
+    let fake_ip = args.fake_ip_pool(&specs)?;
+    let upstream_table = args.upstream_table()?;
+    let upstream_names: Vec<String> = args.upstreams.iter().map(|u| u.name.clone()).collect();
+    let rule_set_fetch = args.rule_set_fetch();
+    let url_test = args.url_test()?;
+    let deny_dest = args.deny_dest();
+    let probe_ifaces = args.probe_ifaces(&specs);
+    let auth = args.auth.as_ref().map(|b| b.build()).transpose()?;
+    let cli::RunArgs { config: config_path, iface, socks5_user, socks5_pass, max_conns, read_timeout_ms, session_timeout_ms, .. } = args;
+
+    // 启动摘要：集中打印生效配置，便于反馈问题时附带完整上下文
+    crate::util::log_info(format!(
+        "iface-proxy {} starting (config: {})",
+        build_info::LONG_VERSION,
+        config_path.as_deref().unwrap_or("none")
+    ));
+    let listener_summary: Vec<String> = specs.iter().map(|s| s.describe()).collect();
+    crate::util::log_info(format!(
+        "listeners: {} (socks auth: {})",
+        listener_summary.join(" "),
+        if socks5_user.is_some() || socks5_pass.is_some() { "on" } else { "off" }
+    ));
+    if let Some(auth) = &auth {
+        crate::util::log_info(format!("auth backend: {} (http, socks5 and mixed listeners without their own user=/pass=)", auth.describe()));
+    }
+    if specs.iter().any(|s| !s.is_local_only()) {
+        let show = |list: &[crate::util::Cidr]| if list.is_empty() { String::from("any") } else { list.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(",") };
+        crate::util::log_info(format!(
+            "exposed listeners: allow-client={} deny-dest={}",
+            show(&args.allow_clients),
+            if deny_dest.is_empty() { String::from("none") } else { show(&deny_dest) }
+        ));
+    }
+    if !args.egress_groups.is_empty() {
+        let groups: Vec<String> = args.egress_groups.iter().map(|g| g.describe()).collect();
+        crate::util::log_info(format!("egress groups: {}", groups.join(" ")));
+    }
+    balance::install(args.egress_groups.clone());
+    crate::util::log_info(format!("egress: {} {}", crate::util::describe_iface(&iface), upstream_table.summary()));
+    upstream::install(upstream_table);
+    if !args.hosts.is_empty() || !args.hosts_files.is_empty() {
+        crate::util::log_info(format!("static hosts: {}", hosts::install(&args.hosts, &args.hosts_files)?));
+    }
+    if let Some(cfg) = args.nat64_prefix {
+        match cfg {
+            nat64::Nat64::Prefix(p) => crate::util::log_info(format!("nat64: prefix {} for IPv4 targets on IPv6-only egress interfaces", p)),
+            nat64::Nat64::Auto => crate::util::log_info("nat64: discovering the prefix via ipv4only.arpa"),
+        }
+        nat64::install(cfg);
+    }
+    if let Some(pool) = fake_ip {
+        crate::util::log_info(format!("fake-ip: {}", pool.describe()));
+        fakeip::install(pool);
+    }
+    if !args.rules.is_empty() || args.rules_file.is_some() || !args.rule_sets.is_empty() {
+        if args.script.is_some() { anyhow::bail!("--rule/--rules-file/--rule-set and --script cannot be combined"); }
+        let imports = ruleset::Imports::load(args.rule_sets.clone(), args.geoip.clone(), rule_set_fetch).await?;
+        let rules = rules::Rules::load(args.rules.clone(), args.rules_file.clone(), imports, upstream_names)?;
+        crate::util::log_info(format!("rules: {}", rules.summary()));
+        rules::install(rules)?;
+        if let Some(secs) = args.drain_on_reload { rules::set_drain(std::time::Duration::from_secs(secs)); }
+        if args.rules_file.is_some() || !args.rule_sets.is_empty() { tokio::spawn(rules::reload_on_sighup()); }
+        tokio::spawn(rules::refresh_rule_sets());
+    }
+    if args.log_process {
+        process::enable();
+        process::enable_uid();
+    }
+    if let Some(path) = &args.script {
+        router::set_router(script::Engine::load(path)?)?;
+        crate::util::log_info(format!("route script: {}", path));
+    }
+    quota::install(args.user_quotas.clone());
+    ban::install(ban::BanConfig {
+        threshold: args.auth_ban_threshold,
+        window: std::time::Duration::from_secs(args.auth_ban_window_secs),
+        ban: std::time::Duration::from_secs(args.auth_ban_secs),
+    });
+    if let Some(path) = &args.token_file {
+        let n = token::load(Some(path.clone()))?;
+        crate::util::log_info(format!("tokens: {} active from {}", n, path));
+    }
+    if let Some(mode) = args.log_redact { addr::set_redact(mode); }
+    http_proxy::set_lenient(args.lenient);
+    http_proxy::set_retry(!args.no_retry);
+    if !args.tcp_mss.is_empty() {
+        let desc: Vec<String> = args.tcp_mss.iter().map(|m| format!("{}={}", m.iface.as_deref().unwrap_or("*"), m.mss)).collect();
+        crate::util::log_info(format!("tcp mss clamp: {}", desc.join(", ")));
+    }
+    crate::util::set_tcp_mss(args.tcp_mss.clone());
+    if !args.source_ports.is_empty() {
+        let desc: Vec<String> = args.source_ports.iter().map(|r| format!("{}={}-{}", r.iface.as_deref().unwrap_or("*"), r.start, r.end)).collect();
+        crate::util::log_info(format!("source ports: {}", desc.join(", ")));
+    }
+    crate::util::set_source_ports(args.source_ports.clone());
+    if let Some(vrf) = &args.vrf {
+        match crate::util::check_vrf(vrf, &iface) {
+            Ok(desc) => crate::util::log_info(format!("vrf: {}", desc)),
+            // 与出口网卡不存在时一样只记录，连接时再报错
+            Err(e) if cfg!(target_os = "linux") => crate::util::log_error(format!("vrf: {}", e)),
+            Err(e) => return Err(e),
+        }
+    }
+    crate::util::set_vrf(args.vrf.clone());
+    if let Some(mark) = args.fwmark { crate::util::log_info(format!("fwmark: {:#x}", mark)); }
+    crate::util::set_fwmark(args.fwmark);
+    if let Some(dscp) = args.dscp { crate::util::log_info(format!("dscp: {} (tos {:#04x})", dscp, dscp << 2)); }
+    if !args.sockopts.is_empty() {
+        crate::util::log_info(format!("socket options: {}", args.sockopts.iter().map(|o| o.describe()).collect::<Vec<_>>().join(", ")));
+    }
+    crate::util::set_outbound_marks(args.dscp, args.sockopts.clone());
+    let tcp_opts = crate::util::SockOpts { keepalive: args.tcp_keepalive, fast_open: args.tcp_fast_open.then_some(true) };
+    if !tcp_opts.describe().is_empty() || !args.tcp_rules.is_empty() {
+        let mut desc = tcp_opts.describe();
+        desc.extend(args.tcp_rules.iter().map(|r| format!("{}: {}", r.suffix, r.opts.describe().join(" "))));
+        crate::util::log_info(format!("tcp options: {}", desc.join(", ")));
+    }
+    crate::util::set_tcp_opts(tcp_opts, args.tcp_rules.clone());
+    http_proxy::set_connect_ports(args.connect_ports.clone());
+    let agent = if args.no_proxy_agent { None } else { Some(args.proxy_agent.clone().unwrap_or_else(|| String::from(build_info::AGENT))) };
+    http_proxy::set_response_headers(agent, args.connect_headers.clone());
+    acl::set_audit(args.acl_audit);
+    if !args.egress_allow.is_empty() {
+        crate::util::log_info(format!("egress override: clients may select {} via {} header or SOCKS5 user@IFACE", args.egress_allow.join(","), egress::HEADER));
+    }
+    egress::install(args.egress_allow.clone());
+    if args.acl_audit {
+        crate::util::log_info("acl: audit mode, rule matches are logged and counted but not enforced");
+    }
+    if !args.header_rules.is_empty() || args.add_via || args.add_forwarded {
+        crate::util::log_info(format!(
+            "http headers: rules={} via={} forwarded={}",
+            args.header_rules.len(),
+            if args.add_via { "on" } else { "off" },
+            if args.add_forwarded { "on" } else { "off" }
+        ));
+        rewrite::install(rewrite::RewriteConfig { rules: args.header_rules.clone(), via: args.add_via, forwarded: args.add_forwarded });
+    }
+    if !args.decompress.is_empty() {
+        crate::util::log_info(format!("http decompress: {} rule(s)", args.decompress.len()));
+        decompress::install(args.decompress.clone());
+    }
+    if let Some(memory) = args.cache_size {
+        crate::util::log_info(format!(
+            "http cache: memory={} bytes, max object={} bytes, disk={}",
+            memory,
+            args.cache_max_object,
+            args.cache_dir.as_deref().unwrap_or("off")
+        ));
+        cache::install(cache::CacheConfig {
+            memory,
+            max_object: args.cache_max_object,
+            dir: args.cache_dir.as_ref().map(std::path::PathBuf::from),
+            disk: args.cache_disk_size,
+        });
+    }
+    if let Some(dir) = &args.capture_dir {
+        capture::install(capture::CaptureConfig {
+            dir: dir.clone(),
+            hosts: args.capture_hosts.iter().map(|h| h.trim_start_matches('.').to_ascii_lowercase()).collect(),
+            tunnels: args.capture_tunnels,
+        });
+        crate::util::log_info(format!("capture: writing pcap files to {} (tunnels: {})", dir, if args.capture_tunnels { "on" } else { "off" }));
+    }
+    if !args.chaos.is_empty() {
+        let desc: Vec<String> = args.chaos.iter().map(|r| r.describe()).collect();
+        crate::util::log_info(format!("chaos: injecting faults for {} (testing only)", desc.join("; ")));
+        chaos::install(args.chaos.clone());
+    }
+    if let Some(dir) = &args.record_dir {
+        record::install(record::RecordConfig {
+            dir: dir.clone(),
+            hosts: args.record_hosts.iter().map(|h| h.trim_start_matches('.').to_ascii_lowercase()).collect(),
+            max_bytes: args.record_max_bytes,
+        });
+        crate::util::log_info(format!("record: writing failed http/socks5 sessions to {} (up to {} bytes each)", dir, args.record_max_bytes));
+    }
+    if let Some(path) = &args.access_log {
+        access_log::install(access_log::AccessLogConfig {
+            path: path.clone(),
+            max_days: args.access_log_max_days,
+            max_size: args.access_log_max_size.unwrap_or(0),
+        })?;
+        crate::util::log_info(format!("access log: {} (keep {} day(s), max size {})", path, args.access_log_max_days, args.access_log_max_size.map_or_else(|| String::from("unlimited"), |n| n.to_string())));
+    }
+    if let Some(spec) = &args.access_log_sink {
+        logsink::install(logsink::Stream::Access, spec)?;
+        crate::util::log_info(format!("access log sink: {}", spec.describe()));
+    }
+    if let Some(level) = args.dump_http {
+        dump::install(level, &args.dump_http_file, args.dump_http_body_max)?;
+        crate::util::log_info(format!("http dump: {} -> {}", level.name(), args.dump_http_file));
+    }
+    crate::util::log_info(format!(
+        "limits: max-conns={} max-conns-wait-ms={} max-handshakes={} max-handshakes-per-ip={} header-min-rate={} read-timeout-ms={} session-timeout-ms={} session-idle-secs={}",
+        max_conns, args.max_conns_wait_ms, args.max_handshakes, args.max_handshakes_per_ip, args.header_min_rate, read_timeout_ms, session_timeout_ms, args.session_idle_secs
+    ));
+    handshake::configure(args.max_handshakes_per_ip, args.header_min_rate);
+    session::spawn_idle_eviction(args.session_idle_secs);
+    head::set_limits(args.max_header_line, args.max_headers);
+    crate::util::log_info(format!(
+        "features: allocator={} alloc-stats={}",
+        crate::alloc::allocator_name(),
+        if crate::alloc::alloc_stats_enabled() { "on" } else { "off" }
+    ));
+
+    let ctx = std::sync::Arc::new(ListenerContext {
+        iface,
+        socks5_user,
+        socks5_pass,
+        auth,
+        ss,
+        dns_upstreams,
+        udp_idle_secs: args.udp_idle_secs,
+        conns: std::sync::Arc::new(tokio::sync::Semaphore::new(max_conns)),
+        conns_wait_ms: args.max_conns_wait_ms,
+        handshakes: std::sync::Arc::new(tokio::sync::Semaphore::new(args.max_handshakes)),
+        read_timeout_ms,
+        session_timeout_ms,
+        allow_client: args.allow_clients.clone(),
+        deny_dest,
+    });
+    let bound = listener::BoundSet::bind(specs).await?;
+    loopguard::install(bound.tcp_addrs());
+    // 与监听一样须在降权前 bind
+    let mdns = if args.mdns { Some(mdns::bind(mdns_config(&bound, args.mdns_name.clone(), args.mdns_ifaces.clone(), &ctx.iface))?) } else { None };
+    // 所有监听已 bind（可能是特权端口），此时再降权
+    if let Some(user) = &args.user {
+        privdrop::drop_privileges(user, args.group.as_deref(), args.keep_caps)?;
+    }
+    if let Some(path) = &args.stats_file {
+        stats::load(path)?;
+    }
+    crate::util::log_info(format!("ready: {}", bound.describe().join(", ")));
+    if let Some(path) = &args.ready_file {
+        bound.write_ready_file(path)?;
+    }
+    if args.stats_interval_secs > 0 {
+        tokio::spawn(stats::run_reporter(args.stats_interval_secs, args.stats_top));
+    }
+    if let Some(addr) = &args.statsd {
+        let mut tags = args.statsd_tags.clone();
+        if !tags.iter().any(|(k, _)| k == "iface") { tags.insert(0, (String::from("iface"), ctx.iface.clone())); }
+        crate::util::log_info(format!("statsd: pushing metrics to {} every {}s ({}, prefix {:?})", addr, args.statsd_interval_secs, args.statsd_format.name(), args.statsd_prefix));
+        tokio::spawn(statsd::run(statsd::StatsdConfig {
+            addr: addr.clone(),
+            prefix: args.statsd_prefix.clone(),
+            format: args.statsd_format,
+            tags,
+            interval: std::time::Duration::from_secs(args.statsd_interval_secs),
+        }));
+    }
+    if !args.probe_targets.is_empty() {
+        crate::util::log_info(format!(
+            "probes: {} via {} every {}s",
+            args.probe_targets.join(","),
+            probe_ifaces.join(","),
+            args.probe_interval_secs
+        ));
+        tokio::spawn(probe::run_probes(probe::ProbeConfig {
+            targets: args.probe_targets.clone(),
+            ifaces: probe_ifaces,
+            interval_secs: args.probe_interval_secs,
+            window: args.probe_window as usize,
+        }));
+    }
+    if args.summary_interval_secs > 0 {
+        tokio::spawn(stats::run_summary(args.summary_interval_secs));
+    }
+    if let Some(policy) = args.on_iface_change {
+        crate::util::log_info(format!("iface watch: every {}s, on address change: {}", args.iface_watch_secs.max(1), policy.name()));
+        tokio::spawn(ifwatch::watch(policy, std::time::Duration::from_secs(args.iface_watch_secs.max(1)), std::time::Duration::from_secs(args.iface_drain_secs)));
+    }
+    if balance::has_url_test() {
+        crate::util::log_info(format!("url-test: {} every {}s, tolerance {}ms", url_test.url, url_test.interval.as_secs(), url_test.tolerance_ms));
+        tokio::spawn(balance::run_url_test(url_test));
+    }
+    if let Some(m) = &mdns { m.spawn(); }
+    let port_mapper = if args.port_map {
+        let mappings = port_mappings(&bound);
+        if mappings.is_empty() { crate::util::log_error("port map: no listener on a non-loopback IPv4 address to map"); }
+        let cfg = portmap::PortMapConfig { method: args.port_map_method, lifetime: args.port_map_lifetime, gateway: args.port_map_gateway };
+        Some(portmap::spawn(cfg, mappings))
+    } else {
+        None
+    };
+    let tasks = bound.spawn(&ctx);
+    let listeners_done = async {
+        for task in tasks {
+            if let Err(e) = task.await {
+                crate::util::log_error(format!("listener task panicked: {}", e));
+            }
+        }
+    };
+    tokio::select! {
+        _ = listeners_done => {}
+        _ = crate::util::shutdown_signal() => crate::util::log_info("shutdown signal received"),
+    }
+    if let Some(m) = &mdns { m.goodbye().await; }
+    if let Some(p) = &port_mapper { p.release().await; }
+    if let Some(path) = &args.stats_file {
+        match stats::save(path) {
+            Ok(()) => crate::util::log_info(format!("traffic stats saved to {}", path)),
+            Err(e) => crate::util::log_error(format!("failed to save traffic stats to {}: {}", path, e)),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,789 @@
+use anyhow::Result;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream, UdpSocket, UnixListener, UnixStream};
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+
+use crate::acl::AclRule;
+use crate::shadowsocks::SsConfig;
+use crate::auth::{Authenticator, StaticAuth};
+use crate::dialer::{DialContext, Dialer};
+use crate::tls::{TlsOptions, TlsServer};
+use crate::util::{log_error, log_info, log_throttled, Cidr, SockOpts};
+
+// 新增监听类型时：在此添加枚举值，并在 parse/name/spawn_listener 中各补一个分支
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ListenerKind {
+    Http,
+    Socks5,
+    Mixed,
+    Admin,
+    Shadowsocks,
+    WsTunnel,
+    Dns,
+    UdpForward,
+    TcpForward,
+}
+
+impl ListenerKind {
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "http" => Some(Self::Http),
+            "socks5" | "socks" => Some(Self::Socks5),
+            "mixed" => Some(Self::Mixed),
+            "admin" => Some(Self::Admin),
+            "ss" | "shadowsocks" => Some(Self::Shadowsocks),
+            "ws" | "ws-tunnel" => Some(Self::WsTunnel),
+            "dns" => Some(Self::Dns),
+            "udp-forward" => Some(Self::UdpForward),
+            "tcp-forward" => Some(Self::TcpForward),
+            _ => None,
+        }
+    }
+
+    // 转发类监听需要固定的目标 (target=HOST:PORT)
+    pub(crate) fn is_forward(self) -> bool {
+        matches!(self, Self::UdpForward | Self::TcpForward)
+    }
+
+    // 使用 UDP socket 的监听
+    pub(crate) fn is_udp(self) -> bool {
+        matches!(self, Self::Dns | Self::UdpForward)
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Http => "http",
+            Self::Socks5 => "socks5",
+            Self::Mixed => "mixed",
+            Self::Admin => "admin",
+            Self::Shadowsocks => "ss",
+            Self::WsTunnel => "ws",
+            Self::Dns => "dns",
+            Self::UdpForward => "udp-forward",
+            Self::TcpForward => "tcp-forward",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct ListenerSpec {
+    pub(crate) kind: ListenerKind,
+    pub(crate) listen: String,
+    // 以下为单个监听的覆盖项，未设置时使用全局参数
+    pub(crate) iface: Option<String>,
+    pub(crate) user: Option<String>,
+    pub(crate) pass: Option<String>,
+    pub(crate) allow: Vec<Cidr>,
+    // unix: 监听的 socket 文件权限
+    pub(crate) mode: Option<u32>,
+    // 转发类监听的目标 HOST:PORT
+    pub(crate) target: Option<String>,
+    // 出站 TCP 的 keepalive=/tfo=，未设置的项用全局参数
+    pub(crate) sockopts: SockOpts,
+    // 本监听的并发连接上限，在全局 --max-conns 之内再加一层
+    pub(crate) max_conns: Option<usize>,
+    // http 监听的 TLS 证书与客户端证书认证
+    pub(crate) tls: TlsOptions,
+}
+
+impl ListenerSpec {
+    pub(crate) fn new(kind: ListenerKind, listen: impl Into<String>) -> Self {
+        Self { kind, listen: listen.into(), iface: None, user: None, pass: None, allow: Vec::new(), mode: None, target: None, sockopts: SockOpts::default(), max_conns: None, tls: TlsOptions::default() }
+    }
+
+    pub(crate) fn unix_path(&self) -> Option<&str> {
+        self.listen.strip_prefix("unix:")
+    }
+
+    // 只有本机能连上的监听：回环地址、localhost 与 unix socket
+    pub(crate) fn is_local_only(&self) -> bool {
+        if self.unix_path().is_some() { return true; }
+        match self.listen.parse::<std::net::SocketAddr>() {
+            Ok(sa) => sa.ip().is_loopback(),
+            Err(_) => self.listen.rsplit_once(':').is_some_and(|(h, _)| h.eq_ignore_ascii_case("localhost")),
+        }
+    }
+
+    // `--listener <kind>=<ADDR:PORT>[?iface=IF&user=U&pass=P&allow=CIDR,CIDR]`,
+    // e.g. `socks5=192.168.1.10:1080?user=lan&pass=secret&allow=192.168.1.0/24`
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (kind, rest) = s.split_once('=').ok_or_else(|| anyhow::anyhow!("invalid listener spec {:?} (expected kind=ADDR:PORT)", s))?;
+        let kind = ListenerKind::parse(kind.trim()).ok_or_else(|| anyhow::anyhow!("unknown listener kind {:?}", kind))?;
+        let (listen, query) = match rest.split_once('?') { Some((l, q)) => (l, Some(q)), None => (rest, None) };
+        let listen = parse_listen_addr(listen.trim()).map_err(|e| anyhow::anyhow!("listener spec {:?}: {}", s, e))?;
+        let mut spec = Self::new(kind, listen);
+        spec.apply_options(query.unwrap_or(""), s)?;
+        Ok(spec)
+    }
+
+    // `--udp-forward/--tcp-forward LISTEN=HOST:PORT[?iface=IF&allow=CIDR]`，
+    // 等价于 `--listener udp-forward=LISTEN?target=HOST:PORT&...`
+    pub(crate) fn parse_forward(kind: ListenerKind, s: &str) -> Result<Self> {
+        let (listen, rest) = s.split_once('=').ok_or_else(|| anyhow::anyhow!("invalid forward {:?} (expected LISTEN=HOST:PORT)", s))?;
+        let (target, query) = match rest.split_once('?') { Some((t, q)) => (t, Some(q)), None => (rest, None) };
+        let listen = parse_listen_addr(listen.trim()).map_err(|e| anyhow::anyhow!("forward {:?}: {}", s, e))?;
+        let mut spec = Self::new(kind, listen);
+        spec.target = Some(parse_target(target.trim()).map_err(|e| anyhow::anyhow!("forward {:?}: {}", s, e))?);
+        spec.apply_options(query.unwrap_or(""), s)?;
+        Ok(spec)
+    }
+
+    fn apply_options(&mut self, query: &str, s: &str) -> Result<()> {
+        let spec = self;
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            match pair.split_once('=') {
+                Some(("iface", v)) => spec.iface = Some(v.to_string()),
+                Some(("user", v)) => spec.user = Some(v.to_string()),
+                Some(("pass", v)) => spec.pass = Some(v.to_string()),
+                Some(("allow", v)) => {
+                    for c in v.split(',').filter(|c| !c.is_empty()) { spec.allow.push(Cidr::parse(c)?); }
+                }
+                Some(("mode", v)) => spec.mode = Some(parse_socket_mode(v)?),
+                Some(("target", v)) => spec.target = Some(parse_target(v)?),
+                Some(("max-conns", v)) => {
+                    let n = v.parse::<usize>().ok().filter(|n| *n > 0);
+                    spec.max_conns = Some(n.ok_or_else(|| anyhow::anyhow!("invalid max-conns {:?} in {:?} (expected a positive number)", v, s))?);
+                }
+                Some((k, v)) if spec.sockopts.apply_option(k, v)? => {}
+                Some((k, v)) if spec.tls.apply_option(k, v)? => {}
+                _ => anyhow::bail!("unknown listener option {:?} in {:?}", pair, s),
+            }
+        }
+        if spec.user.is_some() != spec.pass.is_some() {
+            anyhow::bail!("listener spec {:?}: user and pass must be set together", s);
+        }
+        if spec.kind.is_forward() != spec.target.is_some() {
+            anyhow::bail!("listener spec {:?}: target= is required for forward listeners and not allowed otherwise", s);
+        }
+        spec.tls.validate(s)?;
+        if spec.tls.enabled() && spec.kind != ListenerKind::Http {
+            anyhow::bail!("listener spec {:?}: tls-cert is supported for http listeners only", s);
+        }
+        Ok(())
+    }
+
+    // 启动摘要中的一项，如 `socks5=0.0.0.0:1080(iface=eth1,auth,allow=192.168.1.0/24)`
+    pub(crate) fn describe(&self) -> String {
+        let mut opts = Vec::new();
+        if let Some(iface) = &self.iface { opts.push(format!("iface={}", iface)); }
+        if self.user.is_some() { opts.push(String::from("auth")); }
+        if !self.allow.is_empty() {
+            let allow: Vec<String> = self.allow.iter().map(|c| c.to_string()).collect();
+            opts.push(format!("allow={}", allow.join(",")));
+        }
+        if let Some(mode) = self.mode { opts.push(format!("mode={:04o}", mode)); }
+        if let Some(n) = self.max_conns { opts.push(format!("max-conns={}", n)); }
+        opts.extend(self.sockopts.describe());
+        opts.extend(self.tls.describe());
+        let listen = match &self.target {
+            Some(target) => format!("{}->{}", self.listen, target),
+            None => self.listen.clone(),
+        };
+        if opts.is_empty() {
+            format!("{}={}", self.kind.name(), listen)
+        } else {
+            format!("{}={}({})", self.kind.name(), listen, opts.join(","))
+        }
+    }
+}
+
+// ADDR:PORT，ADDR 可为 IP（IPv6 需加方括号）或主机名；或 unix:/path/to.sock
+pub(crate) fn parse_listen_addr(s: &str) -> Result<String> {
+    if let Some(path) = s.strip_prefix("unix:") {
+        if path.is_empty() { anyhow::bail!("invalid address {:?} (expected unix:/path/to.sock)", s); }
+        return Ok(s.to_string());
+    }
+    if s.parse::<std::net::SocketAddr>().is_ok() { return Ok(s.to_string()); }
+    let (host, port) = s.rsplit_once(':').ok_or_else(|| anyhow::anyhow!("invalid address {:?} (expected ADDR:PORT)", s))?;
+    if host.is_empty() || host.contains(':') { anyhow::bail!("invalid address {:?} (expected ADDR:PORT, IPv6 as [::1]:PORT)", s); }
+    port.parse::<u16>().map_err(|_| anyhow::anyhow!("invalid port in {:?}", s))?;
+    Ok(s.to_string())
+}
+
+// 转发目标 HOST:PORT（不支持 unix:）
+fn parse_target(s: &str) -> Result<String> {
+    if s.starts_with("unix:") { anyhow::bail!("invalid target {:?} (expected HOST:PORT)", s); }
+    parse_listen_addr(s)
+}
+
+pub(crate) fn parse_udp_forward(s: &str) -> Result<ListenerSpec> {
+    ListenerSpec::parse_forward(ListenerKind::UdpForward, s)
+}
+
+pub(crate) fn parse_tcp_forward(s: &str) -> Result<ListenerSpec> {
+    ListenerSpec::parse_forward(ListenerKind::TcpForward, s)
+}
+
+// 八进制权限，如 660 / 0660 / 0o660
+pub(crate) fn parse_socket_mode(s: &str) -> Result<u32> {
+    let digits = s.trim().trim_start_matches("0o");
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|m| *m <= 0o777)
+        .ok_or_else(|| anyhow::anyhow!("invalid socket mode {:?} (expected octal, e.g. 0660)", s))
+}
+
+// 同一地址只能被一个监听占用，启动前统一检查，避免后启动的任务才报 bind 失败；端口 0 由系统分配，不会冲突
+pub(crate) fn validate_specs(specs: &[ListenerSpec]) -> Result<()> {
+    if specs.is_empty() { anyhow::bail!("no listeners enabled"); }
+    for (i, a) in specs.iter().enumerate() {
+        if let Some(b) = specs[..i].iter().find(|b| b.listen == a.listen && !a.listen.ends_with(":0")) {
+            anyhow::bail!("listen address {} is used by both {} and {} listeners", a.listen, b.kind.name(), a.kind.name());
+        }
+        if a.unix_path().is_some() {
+            if !matches!(a.kind, ListenerKind::Http | ListenerKind::Admin) {
+                anyhow::bail!("{} listener cannot listen on {} (unix sockets are supported for http and admin)", a.kind.name(), a.listen);
+            }
+            if !a.allow.is_empty() {
+                anyhow::bail!("listener {}: allow is not supported on unix sockets", a.listen);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) struct ListenerContext {
+    pub(crate) iface: String,
+    pub(crate) socks5_user: Option<String>,
+    pub(crate) socks5_pass: Option<String>,
+    // --auth 指定的认证后端
+    pub(crate) auth: Option<Arc<dyn Authenticator>>,
+    pub(crate) ss: Option<Arc<SsConfig>>,
+    pub(crate) dns_upstreams: Arc<Vec<SocketAddr>>,
+    pub(crate) udp_idle_secs: u64,
+    // --max-conns：所有监听共享同一组名额
+    pub(crate) conns: Arc<Semaphore>,
+    // --max-conns-wait-ms：名额用尽时新连接最多排队等待的时间，0 为立即丢弃
+    pub(crate) conns_wait_ms: u64,
+    // --max-handshakes：所有监听共享，已接入但还没读完请求的连接占用
+    pub(crate) handshakes: Arc<Semaphore>,
+    pub(crate) read_timeout_ms: u64,
+    pub(crate) session_timeout_ms: u64,
+    // 以下两项只作用于非回环地址上的监听
+    pub(crate) allow_client: Vec<Cidr>,
+    pub(crate) deny_dest: Vec<Cidr>,
+}
+
+// 单个监听生效的设置：覆盖项与全局参数合并后的结果，由 accept 循环及其会话共享
+pub(crate) struct ListenerSettings {
+    pub(crate) iface: String,
+    // 未设置时不要求认证
+    pub(crate) auth: Option<Arc<dyn Authenticator>>,
+    pub(crate) allow: Vec<Cidr>,
+    // 会话不允许访问的目标地址，空表示不限制
+    pub(crate) deny_dest: Vec<Cidr>,
+    pub(crate) limit: Limiter,
+    pub(crate) read_timeout_ms: u64,
+    pub(crate) session_timeout_ms: u64,
+    pub(crate) sockopts: SockOpts,
+    pub(crate) dialer: Arc<dyn Dialer>,
+    // 设置了 tls-cert= 的 http 监听，证书在 bind 时加载
+    pub(crate) tls: Option<Arc<TlsServer>>,
+}
+
+impl ListenerSettings {
+    fn resolve(spec: &ListenerSpec, ctx: &ListenerContext, tls: Option<Arc<TlsServer>>) -> Self {
+        // 监听自身的 user=/pass= 优先；其次 --auth 后端，作用于 HTTP、SOCKS5 与混合端口；
+        // 再次全局 --socks5-user/--socks5-pass，只作用于 SOCKS5 与混合端口
+        let proxy = matches!(spec.kind, ListenerKind::Http | ListenerKind::Socks5 | ListenerKind::Mixed | ListenerKind::WsTunnel);
+        let socks = matches!(spec.kind, ListenerKind::Socks5 | ListenerKind::Mixed);
+        let auth: Option<Arc<dyn Authenticator>> = if spec.user.is_some() {
+            Some(Arc::new(StaticAuth::new(spec.user.clone(), spec.pass.clone())))
+        } else if let Some(backend) = ctx.auth.as_ref().filter(|_| proxy) {
+            Some(backend.clone())
+        } else if socks && (ctx.socks5_user.is_some() || ctx.socks5_pass.is_some()) {
+            Some(Arc::new(StaticAuth::new(ctx.socks5_user.clone(), ctx.socks5_pass.clone())))
+        } else {
+            None
+        };
+        let exposed = !spec.is_local_only();
+        Self {
+            iface: spec.iface.clone().unwrap_or_else(|| ctx.iface.clone()),
+            auth,
+            allow: if spec.allow.is_empty() && exposed { ctx.allow_client.clone() } else { spec.allow.clone() },
+            deny_dest: if exposed { ctx.deny_dest.clone() } else { Vec::new() },
+            limit: Limiter::new(spec, ctx),
+            read_timeout_ms: ctx.read_timeout_ms,
+            session_timeout_ms: ctx.session_timeout_ms,
+            sockopts: spec.sockopts,
+            dialer: crate::dialer::installed(),
+            tls,
+        }
+    }
+
+    pub(crate) fn dial_context(&self) -> DialContext<'_> {
+        DialContext { dialer: &*self.dialer, iface: &self.iface, deny: &self.deny_dest }
+    }
+
+    // 未配置 allow 时不限制来源；审计模式下只记录不拒绝
+    pub(crate) fn allows(&self, peer: IpAddr) -> bool {
+        self.allow.is_empty() || self.allow.iter().any(|c| c.contains(peer)) || !crate::acl::hit(AclRule::ClientAllow, peer)
+    }
+}
+
+// 并发连接名额：全局名额与本监听的 max-conns= 名额都拿到才接入；
+// 两者按固定顺序获取（先本监听后全局），排队中的连接不会互相占着对方需要的名额
+pub(crate) struct Limiter {
+    global: Arc<Semaphore>,
+    local: Option<Arc<Semaphore>>,
+    handshakes: Arc<Semaphore>,
+    wait: Duration,
+    stats: Arc<LimitStats>,
+}
+
+// 会话持有期间占用名额，drop 时归还
+pub(crate) struct ConnPermit {
+    _local: Option<OwnedSemaphorePermit>,
+    _global: OwnedSemaphorePermit,
+}
+
+struct LimitStats {
+    listener: String,
+    rejected: AtomicU64,
+    queued: AtomicU64,
+}
+
+fn limit_stats() -> &'static Mutex<Vec<Arc<LimitStats>>> {
+    static STATS: OnceLock<Mutex<Vec<Arc<LimitStats>>>> = OnceLock::new();
+    STATS.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+impl Limiter {
+    fn new(spec: &ListenerSpec, ctx: &ListenerContext) -> Self {
+        let stats = Arc::new(LimitStats { listener: format!("{}={}", spec.kind.name(), spec.listen), rejected: AtomicU64::new(0), queued: AtomicU64::new(0) });
+        // 管理接口不占名额，不出现在指标中
+        if spec.kind != ListenerKind::Admin { limit_stats().lock().unwrap_or_else(|e| e.into_inner()).push(stats.clone()); }
+        Self {
+            global: ctx.conns.clone(),
+            local: spec.max_conns.map(|n| Arc::new(Semaphore::new(n))),
+            handshakes: ctx.handshakes.clone(),
+            wait: Duration::from_millis(ctx.conns_wait_ms),
+            stats,
+        }
+    }
+
+    fn try_take(&self) -> Option<ConnPermit> {
+        let local = match &self.local {
+            Some(sem) => Some(sem.clone().try_acquire_owned().ok()?),
+            None => None,
+        };
+        Some(ConnPermit { _local: local, _global: self.global.clone().try_acquire_owned().ok()? })
+    }
+
+    async fn take(&self) -> Option<ConnPermit> {
+        let local = match &self.local {
+            Some(sem) => Some(sem.clone().acquire_owned().await.ok()?),
+            None => None,
+        };
+        Some(ConnPermit { _local: local, _global: self.global.clone().acquire_owned().await.ok()? })
+    }
+
+    // 不等待，用于 UDP 等不能阻塞收包循环的场合；拿不到时计入 rejected
+    pub(crate) fn try_acquire(&self) -> Option<ConnPermit> {
+        let permit = self.try_take();
+        if permit.is_none() { self.stats.rejected.fetch_add(1, Ordering::Relaxed); }
+        permit
+    }
+
+    // 握手名额：HTTP 请求头、SOCKS 请求读完前占用（见 session::handshake_done），不等待，
+    // 拿不到时计入 rejected。空闲连接虽然会在 --read-timeout-ms 后断开，到期前仍占着文件描述符
+    pub(crate) fn handshake(&self, peer: Option<IpAddr>) -> Option<crate::handshake::Permit> {
+        let permit = crate::handshake::acquire(&self.handshakes, peer);
+        if permit.is_none() { self.stats.rejected.fetch_add(1, Ordering::Relaxed); }
+        permit
+    }
+
+    // accept 循环用：名额用尽时最多等待 --max-conns-wait-ms，期间不再 accept 新连接，
+    // 积压的连接留在内核队列里；超时仍拿不到则计入 rejected
+    pub(crate) async fn acquire(&self) -> Option<ConnPermit> {
+        if let Some(permit) = self.try_take() { return Some(permit); }
+        if self.wait.is_zero() {
+            self.stats.rejected.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        self.stats.queued.fetch_add(1, Ordering::Relaxed);
+        let permit = tokio::time::timeout(self.wait, self.take()).await.ok().flatten();
+        if permit.is_none() { self.stats.rejected.fetch_add(1, Ordering::Relaxed); }
+        permit
+    }
+}
+
+pub(crate) fn render_metrics() -> String {
+    let stats = limit_stats().lock().unwrap_or_else(|e| e.into_inner());
+    if stats.is_empty() { return String::new(); }
+    let mut out = String::from("# HELP iface_proxy_conn_rejected_total Connections dropped because the concurrency limit was reached.\n");
+    out.push_str("# TYPE iface_proxy_conn_rejected_total counter\n");
+    for st in stats.iter() {
+        out.push_str(&format!("iface_proxy_conn_rejected_total{{listener=\"{}\"}} {}\n", st.listener, st.rejected.load(Ordering::Relaxed)));
+    }
+    out.push_str("# HELP iface_proxy_conn_queued_total Connections that waited for a free slot under the concurrency limit.\n");
+    out.push_str("# TYPE iface_proxy_conn_queued_total counter\n");
+    for st in stats.iter() {
+        out.push_str(&format!("iface_proxy_conn_queued_total{{listener=\"{}\"}} {}\n", st.listener, st.queued.load(Ordering::Relaxed)));
+    }
+    out
+}
+
+pub(crate) enum BoundListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+    // 仅 DNS 转发使用
+    Udp(UdpSocket),
+}
+
+pub(crate) enum Accepted {
+    Tcp(TcpStream, std::net::SocketAddr),
+    Unix(UnixStream),
+}
+
+impl BoundListener {
+    pub(crate) fn local_desc(&self) -> String {
+        match self {
+            Self::Tcp(l) => l.local_addr().map(|a| a.to_string()).unwrap_or_default(),
+            Self::Udp(s) => s.local_addr().map(|a| format!("udp:{}", a)).unwrap_or_default(),
+            Self::Unix(l) => match l.local_addr().ok().and_then(|a| a.as_pathname().map(|p| p.display().to_string())) {
+                Some(p) => format!("unix:{}", p),
+                None => String::from("unix:?"),
+            },
+        }
+    }
+
+    pub(crate) fn tcp_addr(&self) -> Option<std::net::SocketAddr> {
+        match self {
+            Self::Tcp(l) => l.local_addr().ok(),
+            Self::Unix(_) | Self::Udp(_) => None,
+        }
+    }
+
+    fn inet_addr(&self) -> Option<std::net::SocketAddr> {
+        match self {
+            Self::Tcp(l) => l.local_addr().ok(),
+            Self::Udp(s) => s.local_addr().ok(),
+            Self::Unix(_) => None,
+        }
+    }
+
+    pub(crate) async fn accept(&self) -> std::io::Result<Accepted> {
+        match self {
+            Self::Tcp(l) => l.accept().await.map(|(s, peer)| Accepted::Tcp(s, peer)),
+            Self::Unix(l) => l.accept().await.map(|(s, _)| Accepted::Unix(s)),
+            Self::Udp(_) => Err(std::io::Error::other("udp listener does not accept connections")),
+        }
+    }
+}
+
+const ACCEPT_BACKOFF_MIN_MS: u64 = 50;
+const ACCEPT_BACKOFF_MAX_MS: u64 = 1000;
+// 描述符耗尽时暂停 accept 的时长：待接入的连接留在内核 backlog 中，等现有会话结束释放描述符
+const FD_EXHAUSTED_PAUSE_MS: u64 = 500;
+
+// 管理接口可暂停的监听（TCP 与 Unix socket，管理接口自身除外），按实际地址登记。
+// 暂停期间 accept 循环不再接入，已建立的会话不受影响；新连接留在内核 backlog 中，恢复后依次接入
+struct Gate {
+    // KIND=ADDR
+    name: String,
+    addr: String,
+    paused: watch::Sender<bool>,
+}
+
+fn gates() -> &'static Mutex<Vec<Arc<Gate>>> {
+    static GATES: OnceLock<Mutex<Vec<Arc<Gate>>>> = OnceLock::new();
+    GATES.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+fn register_gate(kind: ListenerKind, addr: String) {
+    let gate = Gate { name: format!("{}={}", kind.name(), addr), addr, paused: watch::Sender::new(false) };
+    gates().lock().unwrap_or_else(|e| e.into_inner()).push(Arc::new(gate));
+}
+
+fn gate(addr: &str) -> Option<Arc<Gate>> {
+    gates().lock().unwrap_or_else(|e| e.into_inner()).iter().find(|g| g.addr == addr).cloned()
+}
+
+// `which` 为 KIND=ADDR、ADDR 或 all；返回状态有变化的监听
+pub(crate) fn set_paused(which: &str, paused: bool) -> Result<Vec<String>> {
+    let gates = gates().lock().unwrap_or_else(|e| e.into_inner());
+    let hits: Vec<&Arc<Gate>> = gates.iter().filter(|g| which == "all" || g.name == which || g.addr == which).collect();
+    if hits.is_empty() { anyhow::bail!("no pausable listener {:?}", which); }
+    Ok(hits.into_iter().filter(|g| g.paused.send_replace(paused) != paused).map(|g| g.name.clone()).collect())
+}
+
+pub(crate) fn render_listeners() -> String {
+    let gates = gates().lock().unwrap_or_else(|e| e.into_inner());
+    let mut out = format!("{:<48} {}\n", "listener", "state");
+    for g in gates.iter() {
+        out.push_str(&format!("{:<48} {}\n", g.name, if *g.paused.borrow() { "paused" } else { "accepting" }));
+    }
+    out
+}
+
+// 各监听 accept 循环共用：出错时不退出循环，一般错误指数退避，EMFILE/ENFILE 时暂停接入并告警，
+// 避免描述符耗尽时在同一个错误上空转，也不会因一次错误停掉整个监听
+pub(crate) struct AcceptBackoff {
+    delay_ms: u64,
+    // 首次 accept 时按地址查找
+    gate: Option<Option<Arc<Gate>>>,
+}
+
+impl AcceptBackoff {
+    pub(crate) fn new() -> Self {
+        Self { delay_ms: ACCEPT_BACKOFF_MIN_MS, gate: None }
+    }
+
+    // 反复调用 `accept` 直到成功；监听被暂停时等到恢复，正在等待的 accept 也随即取消
+    pub(crate) async fn accept<T, F: std::future::Future<Output = std::io::Result<T>>>(&mut self, listen: &str, mut accept: impl FnMut() -> F) -> T {
+        let gate = self.gate.get_or_insert_with(|| gate(listen)).clone();
+        loop {
+            let res = match &gate {
+                Some(g) => {
+                    let mut rx = g.paused.subscribe();
+                    if *rx.borrow_and_update() {
+                        log_info(format!("{}: accept paused", listen));
+                        let _ = rx.wait_for(|p| !*p).await;
+                        log_info(format!("{}: accept resumed", listen));
+                    }
+                    tokio::select! {
+                        res = accept() => res,
+                        _ = rx.wait_for(|p| *p) => continue,
+                    }
+                }
+                None => accept().await,
+            };
+            match res {
+                Ok(v) => {
+                    self.delay_ms = ACCEPT_BACKOFF_MIN_MS;
+                    return v;
+                }
+                Err(e) if is_fd_exhausted(&e) => {
+                    log_throttled(|| log_error(format!(
+                        "{}: out of file descriptors ({}), pausing accept for {}ms ({} active sessions; raise the NOFILE limit or lower --max-conns)",
+                        listen, e, FD_EXHAUSTED_PAUSE_MS, crate::session::active()
+                    )));
+                    tokio::time::sleep(std::time::Duration::from_millis(FD_EXHAUSTED_PAUSE_MS)).await;
+                }
+                Err(e) => {
+                    log_error(format!("{}: accept error: {}", listen, e));
+                    tokio::time::sleep(std::time::Duration::from_millis(self.delay_ms)).await;
+                    self.delay_ms = self.delay_ms.saturating_mul(2).min(ACCEPT_BACKOFF_MAX_MS);
+                }
+            }
+        }
+    }
+}
+
+fn is_fd_exhausted(e: &std::io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(code) if code == nix::errno::Errno::EMFILE as i32 || code == nix::errno::Errno::ENFILE as i32)
+}
+
+// 一组已 bind、尚未开始 accept 的监听。bind 完成即可获知实际地址（端口 0 时为系统分配的端口），
+// 调用方据此做就绪通知，再调用 spawn 启动各监听。TLS 证书也在此时读取，私钥文件可以只对 root 可读
+pub(crate) struct BoundSet(Vec<(ListenerSpec, BoundListener, Option<Arc<TlsServer>>)>);
+
+impl BoundSet {
+    pub(crate) async fn bind(specs: Vec<ListenerSpec>) -> Result<Self> {
+        let mut bound = Vec::with_capacity(specs.len());
+        for spec in specs {
+            let l = bind_listener(&spec).await?;
+            let tls = match spec.tls.enabled() {
+                true => Some(Arc::new(TlsServer::load(&spec.tls).map_err(|e| anyhow::anyhow!("{} listener on {}: {}", spec.kind.name(), spec.listen, e))?)),
+                false => None,
+            };
+            bound.push((spec, l, tls));
+        }
+        Ok(Self(bound))
+    }
+
+    pub(crate) fn tcp_addrs(&self) -> Vec<std::net::SocketAddr> {
+        self.0.iter().filter_map(|(_, l, _)| l.tcp_addr()).collect()
+    }
+
+    // 各 TCP/UDP 监听的类型与实际地址
+    pub(crate) fn inet_addrs(&self) -> Vec<(ListenerKind, std::net::SocketAddr)> {
+        self.0.iter().filter_map(|(spec, l, _)| Some((spec.kind, l.inet_addr()?))).collect()
+    }
+
+    // 该类型第一个 TCP 监听的实际地址
+    pub(crate) fn addr(&self, kind: ListenerKind) -> Option<std::net::SocketAddr> {
+        self.0.iter().find(|(spec, ..)| spec.kind == kind).and_then(|(_, l, _)| l.tcp_addr())
+    }
+
+    // `KIND 地址` 列表，用于就绪日志与 --ready-file
+    pub(crate) fn describe(&self) -> Vec<String> {
+        self.0.iter().map(|(spec, l, _)| format!("{} {}", spec.kind.name(), l.local_desc())).collect()
+    }
+
+    // --ready-file：每行 `KIND 地址`，先写临时文件再 rename，读取方不会看到写了一半的内容
+    pub(crate) fn write_ready_file(&self, path: &str) -> Result<()> {
+        let body: String = self.describe().iter().map(|l| format!("{}\n", l)).collect();
+        let tmp = format!("{}.tmp", path);
+        std::fs::write(&tmp, body).map_err(|e| anyhow::anyhow!("failed to write ready file {}: {}", tmp, e))?;
+        std::fs::rename(&tmp, path).map_err(|e| anyhow::anyhow!("failed to write ready file {}: {}", path, e))?;
+        Ok(())
+    }
+
+    pub(crate) fn spawn(self, ctx: &Arc<ListenerContext>) -> Vec<JoinHandle<()>> {
+        self.0.into_iter().map(|(spec, l, tls)| spawn_listener(spec, l, tls, ctx.clone())).collect()
+    }
+}
+
+// 残留的 socket 文件（上次未清理）且无人监听时先删除，避免 bind 报 EADDRINUSE
+fn remove_stale_socket(path: &str) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    let Ok(meta) = std::fs::symlink_metadata(path) else { return Ok(()) };
+    if !meta.file_type().is_socket() { anyhow::bail!("{} exists and is not a socket", path); }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() { anyhow::bail!("{} is in use by another process", path); }
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+async fn bind_unix(path: &str, mode: u32) -> Result<UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+    remove_stale_socket(path)?;
+    let l = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(l)
+}
+
+// 先统一 bind，再（可选）降权，最后才启动各监听的 accept 循环
+async fn bind_listener(spec: &ListenerSpec) -> Result<BoundListener> {
+    let res = match spec.unix_path() {
+        Some(path) => bind_unix(path, spec.mode.unwrap_or(0o660)).await.map(BoundListener::Unix),
+        None if spec.kind.is_udp() => UdpSocket::bind(&spec.listen).await.map(BoundListener::Udp).map_err(Into::into),
+        None => TcpListener::bind(&spec.listen).await.map(BoundListener::Tcp).map_err(Into::into),
+    };
+    res.map_err(|e| anyhow::anyhow!("failed to bind {} listener on {}: {}", spec.kind.name(), spec.listen, e))
+}
+
+fn spawn_listener(spec: ListenerSpec, listener: BoundListener, tls: Option<Arc<TlsServer>>, ctx: Arc<ListenerContext>) -> JoinHandle<()> {
+    if spec.kind != ListenerKind::Admin && !matches!(listener, BoundListener::Udp(_)) { register_gate(spec.kind, listener.local_desc()); }
+    tokio::spawn(async move {
+        let s = Arc::new(ListenerSettings::resolve(&spec, &ctx, tls));
+        let res = match (spec.kind, listener) {
+            (ListenerKind::Dns, BoundListener::Udp(sock)) => crate::dns::run_dns(sock, ctx.dns_upstreams.clone(), s).await,
+            (ListenerKind::UdpForward, BoundListener::Udp(sock)) => {
+                let target = spec.target.clone().unwrap_or_default();
+                crate::forward::run_udp_forward(sock, target, ctx.udp_idle_secs, s).await
+            }
+            (_, BoundListener::Udp(_)) | (ListenerKind::Dns | ListenerKind::UdpForward, _) => Err(anyhow::anyhow!("udp sockets are supported for dns and udp-forward listeners only")),
+            (ListenerKind::Http, l) => crate::http_proxy::run_http_proxy(l, s).await,
+            (ListenerKind::Admin, l) => crate::admin::run_admin(l, s).await,
+            (_, BoundListener::Unix(_)) => Err(anyhow::anyhow!("unix sockets are supported for http and admin listeners only")),
+            (ListenerKind::TcpForward, BoundListener::Tcp(l)) => {
+                let target = spec.target.clone().unwrap_or_default();
+                crate::forward::run_tcp_forward(l, target, s).await
+            }
+            (ListenerKind::Socks5, BoundListener::Tcp(l)) => crate::socks5::run_socks5_proxy_auth(l, s).await,
+            (ListenerKind::Mixed, BoundListener::Tcp(l)) => crate::mixed::run_mixed_proxy(l, s).await,
+            (ListenerKind::Shadowsocks, BoundListener::Tcp(l)) => match ctx.ss.clone() {
+                Some(cfg) => crate::shadowsocks::run_shadowsocks_proxy(l, cfg, s).await,
+                None => Err(anyhow::anyhow!("shadowsocks listener requires --ss-password")),
+            },
+            (ListenerKind::WsTunnel, BoundListener::Tcp(l)) => crate::websocket::run_ws_tunnel(l, s).await,
+        };
+        if let Err(e) = res {
+            log_error(format!("{} listener on {} fatal error: {}", spec.kind.name(), spec.listen, e));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn accept_backoff_survives_errors() {
+        let mut errors = vec![
+            std::io::Error::from_raw_os_error(nix::errno::Errno::EMFILE as i32),
+            std::io::Error::from(std::io::ErrorKind::ConnectionAborted),
+        ];
+        assert!(is_fd_exhausted(&errors[0]) && !is_fd_exhausted(&errors[1]));
+        let mut backoff = AcceptBackoff::new();
+        let mut calls = 0;
+        let v = backoff.accept("test", || {
+            calls += 1;
+            let r = errors.pop().map_or(Ok(7), Err);
+            async move { r }
+        }).await;
+        assert_eq!((v, calls), (7, 3));
+        assert_eq!(backoff.delay_ms, ACCEPT_BACKOFF_MIN_MS);
+    }
+    #[tokio::test]
+    async fn paused_listener_stops_accepting() {
+        register_gate(ListenerKind::Socks5, String::from("192.0.2.1:1080"));
+        assert!(set_paused("socks5=192.0.2.9:1080", true).is_err());
+        assert_eq!(set_paused("192.0.2.1:1080", true).unwrap(), ["socks5=192.0.2.1:1080"]);
+        assert!(set_paused("socks5=192.0.2.1:1080", true).unwrap().is_empty());
+        assert!(render_listeners().contains("socks5=192.0.2.1:1080"));
+
+        let accepted = Arc::new(AtomicU64::new(0));
+        let counter = accepted.clone();
+        let task = tokio::spawn(async move {
+            AcceptBackoff::new().accept("192.0.2.1:1080", || {
+                counter.fetch_add(1, Ordering::Relaxed);
+                async { Ok::<_, std::io::Error>(7) }
+            }).await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(accepted.load(Ordering::Relaxed), 0);
+        set_paused("192.0.2.1:1080", false).unwrap();
+        assert_eq!(tokio::time::timeout(Duration::from_secs(2), task).await.unwrap().unwrap(), 7);
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+    }
+
+    fn context(conns: &Arc<Semaphore>, conns_wait_ms: u64) -> ListenerContext {
+        ListenerContext {
+            iface: String::from("lo"),
+            socks5_user: None,
+            socks5_pass: None,
+            auth: None,
+            ss: None,
+            dns_upstreams: Arc::new(Vec::new()),
+            udp_idle_secs: 60,
+            conns: conns.clone(),
+            conns_wait_ms,
+            handshakes: Arc::new(Semaphore::new(1)),
+            read_timeout_ms: 1000,
+            session_timeout_ms: 1000,
+            allow_client: Vec::new(),
+            deny_dest: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn limits_are_shared_and_per_listener() {
+        assert!(ListenerSpec::parse("http=127.0.0.1:0?max-conns=0").is_err());
+        let spec = ListenerSpec::parse("http=127.0.0.1:0?max-conns=1").unwrap();
+        assert_eq!(spec.describe(), "http=127.0.0.1:0(max-conns=1)");
+        let conns = Arc::new(Semaphore::new(2));
+        let http = Limiter::new(&spec, &context(&conns, 0));
+        let socks = Limiter::new(&ListenerSpec::new(ListenerKind::Socks5, "127.0.0.1:0"), &context(&conns, 0));
+
+        // 本监听的上限先到：全局还有名额也拒绝
+        let _a = http.acquire().await.unwrap();
+        assert!(http.acquire().await.is_none());
+        assert_eq!(http.stats.rejected.load(Ordering::Relaxed), 1);
+        // 全局名额被两个监听共同占满
+        let b = socks.acquire().await.unwrap();
+        assert!(socks.try_acquire().is_none());
+
+        // 允许排队时等到名额释放即可接入
+        let queued = Limiter::new(&ListenerSpec::new(ListenerKind::Mixed, "127.0.0.1:0"), &context(&conns, 2000));
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(b);
+        });
+        assert!(queued.acquire().await.is_some());
+        assert_eq!((queued.stats.queued.load(Ordering::Relaxed), queued.stats.rejected.load(Ordering::Relaxed)), (1, 0));
+        assert!(render_metrics().contains("iface_proxy_conn_rejected_total{listener=\"http=127.0.0.1:0\"} 1"));
+
+        // 握手名额不排队，归还后才能接入下一个
+        let pending = queued.handshake(None).unwrap();
+        assert!(queued.handshake(None).is_none());
+        drop(pending);
+        assert!(queued.handshake(None).is_some());
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::Result;
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+// --log-sink / --access-log-sink：应用日志与访问日志各自可以送到终端、syslog（unix 套接字或 UDP）或 journald。
+// 发送失败（如 syslogd 重启）时先重连一次，仍失败则这一条退回终端输出，不会丢失也不会递归写日志
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum SinkSpec {
+    Console,
+    SyslogUnix(PathBuf),
+    SyslogUdp(String),
+    Journald,
+}
+
+impl SinkSpec {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        match s {
+            "console" => return Ok(Self::Console),
+            "journald" => return Ok(Self::Journald),
+            "syslog" => return Ok(Self::SyslogUnix(PathBuf::from(default_syslog_socket()))),
+            _ => {}
+        }
+        if let Some(path) = s.strip_prefix("syslog:unix:") {
+            anyhow::ensure!(!path.is_empty(), "invalid log sink {:?}: empty socket path", s);
+            return Ok(Self::SyslogUnix(PathBuf::from(path)));
+        }
+        if let Some(addr) = s.strip_prefix("syslog:udp:").or_else(|| s.strip_prefix("syslog:")) {
+            let addr = if addr.rsplit_once(':').is_some_and(|(_, p)| p.parse::<u16>().is_ok()) { addr.to_string() } else { format!("{}:514", addr) };
+            anyhow::ensure!(!addr.starts_with(':'), "invalid log sink {:?}: empty host", s);
+            return Ok(Self::SyslogUdp(addr));
+        }
+        anyhow::bail!("invalid log sink {:?} (expected console, syslog, syslog:unix:PATH, syslog:udp:HOST[:PORT] or journald)", s)
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            Self::Console => String::from("console"),
+            Self::SyslogUnix(p) => format!("syslog:unix:{}", p.display()),
+            Self::SyslogUdp(a) => format!("syslog:udp:{}", a),
+            Self::Journald => String::from("journald"),
+        }
+    }
+}
+
+fn default_syslog_socket() -> &'static str {
+    if cfg!(target_os = "macos") { "/var/run/syslog" } else { "/dev/log" }
+}
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+// syslog 严重级别（RFC 5424），journald 的 PRIORITY 取同样的值
+pub(crate) const ERR: u8 = 3;
+pub(crate) const WARNING: u8 = 4;
+pub(crate) const NOTICE: u8 = 5;
+pub(crate) const INFO: u8 = 6;
+
+// facility daemon
+const FACILITY: u8 = 3;
+
+#[derive(Clone, Copy)]
+pub(crate) enum Stream {
+    App,
+    Access,
+}
+
+enum Conn {
+    Console,
+    Unix(UnixDatagram, PathBuf),
+    Udp(UdpSocket),
+    Journald(UnixDatagram),
+}
+
+struct Sink {
+    conn: Conn,
+    // syslog 的 TAG / journald 的 SYSLOG_IDENTIFIER，两类日志分开便于在收集端过滤
+    tag: &'static str,
+    host: String,
+}
+
+static APP: OnceLock<Sink> = OnceLock::new();
+static ACCESS: OnceLock<Sink> = OnceLock::new();
+
+fn slot(stream: Stream) -> &'static OnceLock<Sink> {
+    match stream {
+        Stream::App => &APP,
+        Stream::Access => &ACCESS,
+    }
+}
+
+impl Sink {
+    fn open(spec: &SinkSpec, tag: &'static str) -> Result<Self> {
+        let conn = match spec {
+            SinkSpec::Console => Conn::Console,
+            SinkSpec::SyslogUnix(path) => {
+                let sock = UnixDatagram::unbound()?;
+                sock.connect(path).map_err(|e| anyhow::anyhow!("connect syslog socket {}: {}", path.display(), e))?;
+                Conn::Unix(sock, path.clone())
+            }
+            SinkSpec::SyslogUdp(addr) => {
+                let target = std::net::ToSocketAddrs::to_socket_addrs(addr.as_str())
+                    .map_err(|e| anyhow::anyhow!("resolve syslog server {}: {}", addr, e))?
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("resolve syslog server {}: no address", addr))?;
+                let sock = UdpSocket::bind(if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+                sock.connect(target)?;
+                Conn::Udp(sock)
+            }
+            SinkSpec::Journald => {
+                let sock = UnixDatagram::unbound()?;
+                sock.connect(JOURNALD_SOCKET).map_err(|e| anyhow::anyhow!("connect journald socket {}: {}", JOURNALD_SOCKET, e))?;
+                Conn::Journald(sock)
+            }
+        };
+        Ok(Self { conn, tag, host: crate::mdns::hostname() })
+    }
+
+    // 返回 false 表示没送出去，由调用方退回终端
+    fn send(&self, severity: u8, msg: &str) -> bool {
+        match &self.conn {
+            Conn::Console => false,
+            Conn::Unix(sock, path) => {
+                let line = syslog_local(severity, self.tag, msg);
+                sock.send(line.as_bytes()).is_ok() || (sock.connect(path).is_ok() && sock.send(line.as_bytes()).is_ok())
+            }
+            Conn::Udp(sock) => sock.send(syslog_remote(severity, self.tag, &self.host, msg).as_bytes()).is_ok(),
+            Conn::Journald(sock) => {
+                let data = journald_datagram(severity, self.tag, msg);
+                sock.send(&data).is_ok() || (sock.connect(JOURNALD_SOCKET).is_ok() && sock.send(&data).is_ok())
+            }
+        }
+    }
+}
+
+pub(crate) fn install(stream: Stream, spec: &SinkSpec) -> Result<()> {
+    let tag = match stream {
+        Stream::App => "iface-proxy",
+        Stream::Access => "iface-proxy-access",
+    };
+    let _ = slot(stream).set(Sink::open(spec, tag)?);
+    Ok(())
+}
+
+// 是否为该类日志配置了输出（含 console）
+pub(crate) fn configured(stream: Stream) -> bool {
+    slot(stream).get().is_some()
+}
+
+// 送到该类日志的输出；未配置、配置为 console 或发送失败时返回 false
+pub(crate) fn send(stream: Stream, severity: u8, msg: &str) -> bool {
+    slot(stream).get().is_some_and(|s| s.send(severity, msg))
+}
+
+// 本机 syslog 套接字：RFC 3164 风格，时间戳由 syslogd 补上
+fn syslog_local(severity: u8, tag: &str, msg: &str) -> String {
+    format!("<{}>{}[{}]: {}", FACILITY * 8 + severity, tag, std::process::id(), msg)
+}
+
+// 远端 syslog：RFC 5424，带 UTC 时间戳与主机名
+fn syslog_remote(severity: u8, tag: &str, host: &str, msg: &str) -> String {
+    format!("<{}>1 {} {} {} {} - - {}", FACILITY * 8 + severity, utc_timestamp(), host, tag, std::process::id(), msg)
+}
+
+fn utc_timestamp() -> String {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    let t: nix::libc::time_t = now.as_secs() as nix::libc::time_t;
+    let mut tm: nix::libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { let _ = nix::libc::gmtime_r(&t, &mut tm); }
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        now.subsec_millis()
+    )
+}
+
+// journald 原生协议：每行 KEY=VALUE，值里有换行时改用 KEY\n<u64 小端长度><值>\n
+fn journald_datagram(severity: u8, tag: &str, msg: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    let priority = severity.to_string();
+    let facility = FACILITY.to_string();
+    for (key, value) in [("PRIORITY", priority.as_str()), ("SYSLOG_FACILITY", facility.as_str()), ("SYSLOG_IDENTIFIER", tag), ("MESSAGE", msg)] {
+        out.extend_from_slice(key.as_bytes());
+        if value.contains('\n') {
+            out.push(b'\n');
+            out.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            out.push(b'=');
+        }
+        out.extend_from_slice(value.as_bytes());
+        out.push(b'\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sinks() {
+        assert_eq!(SinkSpec::parse("journald").unwrap(), SinkSpec::Journald);
+        assert_eq!(SinkSpec::parse("syslog:unix:/tmp/log").unwrap(), SinkSpec::SyslogUnix(PathBuf::from("/tmp/log")));
+        assert_eq!(SinkSpec::parse("syslog:udp:logs.lan").unwrap(), SinkSpec::SyslogUdp(String::from("logs.lan:514")));
+        assert_eq!(SinkSpec::parse("syslog:10.0.0.1:5514").unwrap(), SinkSpec::SyslogUdp(String::from("10.0.0.1:5514")));
+        assert_eq!(SinkSpec::parse("syslog:udp:[::1]:5514").unwrap(), SinkSpec::SyslogUdp(String::from("[::1]:5514")));
+        assert!(SinkSpec::parse("file:/tmp/x").is_err());
+        assert!(SinkSpec::parse("syslog:unix:").is_err());
+    }
+
+    #[test]
+    fn sends_with_priority() {
+        let dir = std::env::temp_dir().join(format!("iface-proxy-logsink-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("log.sock");
+        let server = UnixDatagram::bind(&path).unwrap();
+        let sink = Sink::open(&SinkSpec::SyslogUnix(path.clone()), "iface-proxy").unwrap();
+        assert!(sink.send(ERR, "boom"));
+        let mut buf = [0u8; 512];
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(std::str::from_utf8(&buf[..n]).unwrap(), format!("<27>iface-proxy[{}]: boom", std::process::id()));
+
+        let data = journald_datagram(INFO, "iface-proxy-access", "a\nb");
+        let mut want = b"PRIORITY=6\nSYSLOG_FACILITY=3\nSYSLOG_IDENTIFIER=iface-proxy-access\nMESSAGE\n".to_vec();
+        want.extend_from_slice(&3u64.to_le_bytes());
+        want.extend_from_slice(b"a\nb\n");
+        assert_eq!(data, want);
+
+        // 收端消失后发送失败，调用方退回终端
+        drop(server);
+        std::fs::remove_file(&path).unwrap();
+        assert!(!sink.send(INFO, "lost"));
+        assert!(!Sink::open(&SinkSpec::Console, "iface-proxy").unwrap().send(INFO, "x"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
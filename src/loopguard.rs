@@ -0,0 +1,51 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+
+// 防止代理回环：目标解析到本进程自己的监听地址时拒绝连接；
+// 明文 HTTP 请求中带有本实例的 Via 标识时同样拒绝（经其他代理绕回来的情况）
+struct Guard {
+    listens: Vec<SocketAddr>,
+    // 本机网卡地址，用于匹配监听在 0.0.0.0 / :: 上的情况
+    local: Vec<IpAddr>,
+    token: String,
+}
+
+static GUARD: OnceLock<Guard> = OnceLock::new();
+
+// 所有 TCP 监听 bind 完成后调用
+pub(crate) fn install(listens: Vec<SocketAddr>) {
+    let local = crate::util::list_interfaces()
+        .map(|list| list.into_iter().flat_map(|i| i.addrs).collect())
+        .unwrap_or_default();
+    let mut id = [0u8; 4];
+    let _ = getrandom::getrandom(&mut id);
+    let token = format!("iface-proxy-{}", id.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+    let _ = GUARD.set(Guard { listens, local, token });
+}
+
+// 本实例在 Via 中使用的名称，如 `iface-proxy-1a2b3c4d`
+pub(crate) fn via_pseudonym() -> &'static str {
+    GUARD.get().map(|g| g.token.as_str()).unwrap_or("iface-proxy")
+}
+
+pub(crate) fn is_self(dest: SocketAddr) -> bool {
+    let Some(g) = GUARD.get() else { return false };
+    let ip = dest.ip().to_canonical();
+    g.listens.iter().filter(|l| l.port() == dest.port()).any(|l| {
+        let lip = l.ip().to_canonical();
+        if lip.is_unspecified() {
+            ip.is_loopback() || ip.is_unspecified() || g.local.contains(&ip)
+        } else {
+            lip == ip || (lip.is_loopback() && ip.is_unspecified())
+        }
+    })
+}
+
+// 请求头中出现本实例的 Via 标识，或本程序的 Proxy-Agent
+pub(crate) fn seen_in_headers(head: &crate::head::RequestHead) -> bool {
+    let Some(g) = GUARD.get() else { return false };
+    head.fields().any(|(name, value)| {
+        (name.eq_ignore_ascii_case("via") && value.split(',').any(|hop| hop.split_whitespace().nth(1) == Some(g.token.as_str())))
+            || (name.eq_ignore_ascii_case("proxy-agent") && value.starts_with("iface-proxy/"))
+    })
+}
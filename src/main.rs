@@ -1,95 +1,4 @@
-use anyhow::Result;
-
-mod util;
-mod http_proxy;
-mod socks5;
-
-fn print_help() {
-    println!("iface-proxy - 本地 HTTP/HTTPS 与 SOCKS5 代理 (仅 HTTP/1.x)\n\n用法:\n  iface-proxy [OPTIONS]\n\n常用参数:\n  -i, --iface <NAME>              指定外发网卡名称 (默认: en0)\n  -l, --listen <ADDR:PORT>        HTTP 代理监听地址 (默认: 127.0.0.1:7890，HTTP/1.x)\n      --socks5                    启用 SOCKS5 代理（默认关闭）\n  -S, --socks5-listen <ADDR:PORT> SOCKS5 监听地址 (默认: 127.0.0.1:7080，与 --socks5 配合使用)\n  -v, --version                   显示版本并退出\n  -h, --help                      显示本帮助并退出\n\n说明:\n- 默认仅启动 HTTP(127.0.0.1:7890，HTTP/1.x)。使用 --socks5 才会启用 SOCKS5(默认 127.0.0.1:7080)。\n- 出站连接将绑定到指定网卡 (--iface)。\n示例:\n  iface-proxy --iface en0\n  iface-proxy --iface en0 --socks5\n  iface-proxy --iface en0 --socks5 --socks5-listen 127.0.0.1:1081\n  iface-proxy --iface en0 --listen 127.0.0.1:8080\n");
-}
-
 #[tokio::main]
-async fn main() -> Result<()> {
-    // 尝试提高 NOFILE 软/硬限制（不保证成功）
-    crate::util::try_raise_nofile_limit(65536);
-    // 解析命令行参数中的 --iface/-i，默认 en0
-    let mut iface = String::from("en0");
-    let mut listen = String::from("127.0.0.1:7890");
-    let mut socks5_listen: Option<String> = Some(String::from("127.0.0.1:7080"));
-    let mut socks5_user: Option<String> = None;
-    let mut socks5_pass: Option<String> = None;
-    let mut enable_socks5 = false;
-    let mut max_conns: usize = 10000;
-    let mut read_timeout_ms: u64 = 10000;
-    let mut session_timeout_ms: u64 = 600_000; // 10min
-    let mut args = std::env::args().skip(1);
-    while let Some(arg) = args.next() {
-        if arg == "--help" || arg == "-h" { print_help(); return Ok(()); }
-        if arg == "--version" || arg == "-v" { println!("{}", env!("IFACE_PROXY_VERSION")); return Ok(()); }
-        if arg == "--iface" || arg == "-i" {
-            if let Some(val) = args.next() { iface = val; }
-        } else if let Some(val) = arg.strip_prefix("--iface=") {
-            iface = val.to_string();
-        } else if arg == "--listen" || arg == "-l" {
-            if let Some(val) = args.next() { listen = val; }
-        } else if let Some(val) = arg.strip_prefix("--listen=") {
-            listen = val.to_string();
-        } else if arg == "--socks5" {
-            enable_socks5 = true;
-        } else if arg == "--socks5-listen" || arg == "-S" {
-            if let Some(val) = args.next() { socks5_listen = Some(val); }
-        } else if let Some(val) = arg.strip_prefix("--socks5-listen=") {
-            socks5_listen = Some(val.to_string());
-        } else if arg == "--socks5-user" {
-            if let Some(val) = args.next() { socks5_user = Some(val); }
-        } else if let Some(val) = arg.strip_prefix("--socks5-user=") {
-            socks5_user = Some(val.to_string());
-        } else if arg == "--socks5-pass" {
-            if let Some(val) = args.next() { socks5_pass = Some(val); }
-        } else if let Some(val) = arg.strip_prefix("--socks5-pass=") {
-            socks5_pass = Some(val.to_string());
-        } else if arg == "--max-conns" {
-            if let Some(val) = args.next() { max_conns = val.parse().unwrap_or(max_conns); }
-        } else if let Some(val) = arg.strip_prefix("--max-conns=") {
-            max_conns = val.parse().unwrap_or(max_conns);
-        } else if arg == "--read-timeout-ms" {
-            if let Some(val) = args.next() { read_timeout_ms = val.parse().unwrap_or(read_timeout_ms); }
-        } else if let Some(val) = arg.strip_prefix("--read-timeout-ms=") {
-            read_timeout_ms = val.parse().unwrap_or(read_timeout_ms);
-        } else if arg == "--session-timeout-ms" {
-            if let Some(val) = args.next() { session_timeout_ms = val.parse().unwrap_or(session_timeout_ms); }
-        } else if let Some(val) = arg.strip_prefix("--session-timeout-ms=") {
-            session_timeout_ms = val.parse().unwrap_or(session_timeout_ms);
-        }
-    }
-
-    let http_iface = iface.clone();
-    let http_listen = listen.clone();
-    // 主端口固定 HTTP/1.x 代理
-    let http_sem = std::sync::Arc::new(tokio::sync::Semaphore::new(max_conns));
-    let http_sem_clone = http_sem.clone();
-    let http_task = tokio::spawn(async move {
-        if let Err(e) = http_proxy::run_http_proxy(&http_iface, &http_listen, http_sem_clone, read_timeout_ms, session_timeout_ms).await {
-            crate::util::log_error(format!("HTTP proxy fatal error: {}", e));
-        }
-    });
-
-    if enable_socks5 {
-        if let Some(s5_addr) = socks5_listen {
-            let s5_iface = iface.clone();
-            let s5_user_cloned = socks5_user.clone();
-            let s5_pass_cloned = socks5_pass.clone();
-            let s5_sem = std::sync::Arc::new(tokio::sync::Semaphore::new(max_conns));
-            tokio::spawn(async move {
-                if let Err(e) = socks5::run_socks5_proxy_auth(&s5_iface, &s5_addr, s5_user_cloned.as_deref(), s5_pass_cloned.as_deref(), s5_sem, read_timeout_ms, session_timeout_ms).await {
-                    crate::util::log_error(format!("SOCKS5 proxy fatal error: {}", e));
-                }
-            });
-        }
-    }
-
-    if let Err(e) = http_task.await {
-        crate::util::log_error(format!("HTTP proxy task panicked: {}", e));
-    }
-    Ok(())
-}
\ No newline at end of file
+async fn main() -> anyhow::Result<()> {
+    iface_proxy::run_cli(std::env::args().collect()).await
+}
@@ -0,0 +1,396 @@
+use anyhow::Result;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+use crate::util::{log_error, log_info};
+
+// mDNS/DNS-SD 通告：在局域网上以 `_http-proxy._tcp`、`_socks._tcp` 发布 HTTP 与 SOCKS5 监听，手机等设备可自动发现代理。
+// 只实现应答方所需的最小子集：每块网卡一个绑定到该网卡的 socket，启动时主动通告两次，收到匹配的查询时应答，
+// 退出时发送 TTL 为 0 的告别包；不做名字冲突探测
+const GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const PORT: u16 = 5353;
+// RFC 6762 建议：与主机地址相关的记录 120 秒，其余 75 分钟
+const HOST_TTL: u32 = 120;
+const OTHER_TTL: u32 = 4500;
+// 旧式单播查询（源端口不是 5353）应答的 TTL 上限
+const LEGACY_TTL: u32 = 10;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+
+const MAX_LABEL: usize = 63;
+
+// 一个要发布的监听
+pub(crate) struct Service {
+    // 服务类型，如 `_http-proxy._tcp`
+    pub(crate) kind: &'static str,
+    pub(crate) port: u16,
+    // 监听地址，None 表示所有地址
+    pub(crate) bind: Option<Ipv4Addr>,
+}
+
+pub(crate) struct MdnsConfig {
+    // 服务实例名，显示在发现列表中
+    pub(crate) instance: String,
+    pub(crate) services: Vec<Service>,
+    // 只在这些网卡上通告，空表示所有已启用的非回环网卡
+    pub(crate) ifaces: Vec<String>,
+    pub(crate) txt: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Record {
+    name: Vec<String>,
+    rtype: u16,
+    // 只由本机发布的记录，应答时带 cache-flush 位
+    unique: bool,
+    ttl: u32,
+    rdata: Vec<u8>,
+    // PTR/SRV 指向的名字，用于附加记录
+    target: Option<Vec<String>>,
+}
+
+fn labels(name: &str) -> Vec<String> {
+    name.split('.').map(String::from).collect()
+}
+
+fn put_name(out: &mut Vec<u8>, name: &[String]) {
+    for l in name {
+        out.push(l.len() as u8);
+        out.extend_from_slice(l.as_bytes());
+    }
+    out.push(0);
+}
+
+fn same_name(a: &[String], b: &[String]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x.eq_ignore_ascii_case(y))
+}
+
+// 截断到一个 DNS 标签的长度上限（按字符边界）
+fn truncate_label(s: &str) -> String {
+    let mut end = s.len().min(MAX_LABEL);
+    while !s.is_char_boundary(end) { end -= 1; }
+    s[..end].to_string()
+}
+
+// 本机名的第一段，只保留主机名允许的字符
+pub(crate) fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let ret = unsafe { nix::libc::gethostname(buf.as_mut_ptr() as *mut nix::libc::c_char, buf.len()) };
+    let raw = if ret == 0 { String::from_utf8_lossy(&buf[..buf.iter().position(|&b| b == 0).unwrap_or(buf.len())]).into_owned() } else { String::new() };
+    let name: String = raw.split('.').next().unwrap_or("").chars().filter(|c| c.is_ascii_alphanumeric() || *c == '-').collect();
+    if name.is_empty() { String::from("iface-proxy") } else { truncate_label(&name) }
+}
+
+// 某块网卡（地址为 `addr`）上要发布的全部记录
+fn records(cfg: &MdnsConfig, host: &[String], addr: Ipv4Addr) -> Vec<Record> {
+    let mut out = vec![Record { name: host.to_vec(), rtype: TYPE_A, unique: true, ttl: HOST_TTL, rdata: addr.octets().to_vec(), target: None }];
+    for svc in cfg.services.iter().filter(|s| s.bind.is_none_or(|b| b == addr)) {
+        let kind = labels(&format!("{}.local", svc.kind));
+        let mut instance = vec![truncate_label(&cfg.instance)];
+        instance.extend(kind.iter().cloned());
+        let mut ptr = Vec::new();
+        put_name(&mut ptr, &kind);
+        out.push(Record { name: labels("_services._dns-sd._udp.local"), rtype: TYPE_PTR, unique: false, ttl: OTHER_TTL, rdata: ptr, target: None });
+        let mut ptr = Vec::new();
+        put_name(&mut ptr, &instance);
+        out.push(Record { name: kind, rtype: TYPE_PTR, unique: false, ttl: OTHER_TTL, rdata: ptr, target: Some(instance.clone()) });
+        // 优先级 0、权重 0
+        let mut srv = vec![0, 0, 0, 0];
+        srv.extend_from_slice(&svc.port.to_be_bytes());
+        put_name(&mut srv, host);
+        out.push(Record { name: instance.clone(), rtype: TYPE_SRV, unique: true, ttl: HOST_TTL, rdata: srv, target: Some(host.to_vec()) });
+        let mut txt = Vec::new();
+        for s in cfg.txt.iter().filter(|s| s.len() <= 255) {
+            txt.push(s.len() as u8);
+            txt.extend_from_slice(s.as_bytes());
+        }
+        // 空 TXT 也须有一个零长度字符串
+        if txt.is_empty() { txt.push(0); }
+        out.push(Record { name: instance, rtype: TYPE_TXT, unique: true, ttl: OTHER_TTL, rdata: txt, target: None });
+    }
+    out
+}
+
+#[derive(Debug)]
+struct Question {
+    name: Vec<String>,
+    qtype: u16,
+    // QU 位：请求单播应答
+    unicast: bool,
+}
+
+struct Query {
+    id: u16,
+    questions: Vec<Question>,
+    // 问题段的问题数与结束位置，旧式单播应答需原样带回问题段
+    qdcount: u16,
+    end: usize,
+}
+
+// 读名字，支持压缩指针；返回名字和名字之后的位置
+fn read_name(pkt: &[u8], mut pos: usize) -> Option<(Vec<String>, usize)> {
+    let mut name = Vec::new();
+    let mut end = None;
+    for _ in 0..128 {
+        let len = *pkt.get(pos)? as usize;
+        match len {
+            0 => return Some((name, end.unwrap_or(pos + 1))),
+            l if l & 0xc0 == 0xc0 => {
+                end.get_or_insert(pos + 2);
+                pos = ((l & 0x3f) << 8) | *pkt.get(pos + 1)? as usize;
+            }
+            l if l <= MAX_LABEL => {
+                name.push(String::from_utf8_lossy(pkt.get(pos + 1..pos + 1 + l)?).into_owned());
+                pos += 1 + l;
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+fn parse_query(pkt: &[u8]) -> Option<Query> {
+    // 只处理标准查询，忽略应答（包括自己发出的通告）
+    if pkt.len() < 12 || pkt[2] & 0xf8 != 0 { return None; }
+    let qdcount = u16::from_be_bytes([pkt[4], pkt[5]]);
+    let mut pos = 12;
+    let mut questions = Vec::with_capacity(qdcount as usize);
+    for _ in 0..qdcount {
+        let (name, next) = read_name(pkt, pos)?;
+        let qtype = u16::from_be_bytes([*pkt.get(next)?, *pkt.get(next + 1)?]);
+        let qclass = u16::from_be_bytes([*pkt.get(next + 2)?, *pkt.get(next + 3)?]);
+        pos = next + 4;
+        if qclass & 0x7fff == 1 || qclass & 0x7fff == 255 { questions.push(Question { name, qtype, unicast: qclass & 0x8000 != 0 }); }
+    }
+    Some(Query { id: u16::from_be_bytes([pkt[0], pkt[1]]), questions, qdcount, end: pos })
+}
+
+// 匹配问题的记录，以及随之附带的 SRV/TXT/A 附加记录
+fn answer<'a>(records: &'a [Record], questions: &[Question]) -> (Vec<&'a Record>, Vec<&'a Record>) {
+    let answers: Vec<&Record> = records
+        .iter()
+        .filter(|r| questions.iter().any(|q| (q.qtype == r.rtype || q.qtype == TYPE_ANY) && same_name(&q.name, &r.name)))
+        .collect();
+    let mut additional: Vec<&Record> = Vec::new();
+    let mut targets: Vec<&Vec<String>> = answers.iter().filter_map(|r| r.target.as_ref()).collect();
+    while let Some(t) = targets.pop() {
+        for r in records.iter().filter(|r| same_name(&r.name, t) && r.rtype != TYPE_PTR) {
+            if answers.iter().chain(additional.iter()).any(|a| std::ptr::eq(*a, r)) { continue; }
+            additional.push(r);
+            if let Some(next) = &r.target { targets.push(next); }
+        }
+    }
+    (answers, additional)
+}
+
+// `question` 为旧式单播应答带回的问题段及问题数；`ttl_cap` 为 0 时即告别包
+fn encode(id: u16, question: Option<(&[u8], u16)>, answers: &[&Record], additional: &[&Record], ttl_cap: u32, legacy: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(512);
+    out.extend_from_slice(&id.to_be_bytes());
+    // QR=1、AA=1
+    out.extend_from_slice(&[0x84, 0x00]);
+    out.extend_from_slice(&question.map_or(0, |(_, n)| n).to_be_bytes());
+    out.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(&(additional.len() as u16).to_be_bytes());
+    if let Some((q, _)) = question { out.extend_from_slice(q); }
+    for r in answers.iter().chain(additional) {
+        put_name(&mut out, &r.name);
+        out.extend_from_slice(&r.rtype.to_be_bytes());
+        let class: u16 = if r.unique && !legacy { 0x8001 } else { 1 };
+        out.extend_from_slice(&class.to_be_bytes());
+        out.extend_from_slice(&r.ttl.min(ttl_cap).to_be_bytes());
+        out.extend_from_slice(&(r.rdata.len() as u16).to_be_bytes());
+        out.extend_from_slice(&r.rdata);
+    }
+    out
+}
+
+fn set_multicast_if(fd: i32, addr: Ipv4Addr) -> Result<()> {
+    let a = nix::libc::in_addr { s_addr: u32::from(addr).to_be() };
+    let ret = unsafe {
+        nix::libc::setsockopt(
+            fd,
+            nix::libc::IPPROTO_IP,
+            nix::libc::IP_MULTICAST_IF,
+            &a as *const _ as *const nix::libc::c_void,
+            std::mem::size_of::<nix::libc::in_addr>() as nix::libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        anyhow::bail!("setsockopt(IP_MULTICAST_IF) failed: {}", std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// 绑定到网卡的 0.0.0.0:5353，与系统自带的 mDNS 服务（avahi、mDNSResponder）共用端口
+fn socket_for(iface: &str, addr: Ipv4Addr) -> Result<UdpSocket> {
+    use nix::sys::socket::{bind, setsockopt, socket, sockopt, AddressFamily, SockFlag, SockType, SockaddrIn};
+    let fd = socket(AddressFamily::Inet, SockType::Datagram, SockFlag::empty(), None)?;
+    setsockopt(&fd, sockopt::ReuseAddr, &true)?;
+    setsockopt(&fd, sockopt::ReusePort, &true)?;
+    crate::util::bind_iface_v4(fd.as_raw_fd(), iface)?;
+    bind(fd.as_raw_fd(), &SockaddrIn::from(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, PORT)))?;
+    let sock = std::net::UdpSocket::from(fd);
+    sock.join_multicast_v4(&GROUP, &addr)?;
+    sock.set_multicast_ttl_v4(255)?;
+    set_multicast_if(sock.as_raw_fd(), addr)?;
+    sock.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(sock)?)
+}
+
+#[derive(Clone)]
+struct Link {
+    iface: String,
+    sock: Arc<UdpSocket>,
+    records: Arc<Vec<Record>>,
+}
+
+impl Link {
+    async fn announce(&self, ttl_cap: u32) {
+        let all: Vec<&Record> = self.records.iter().collect();
+        let pkt = encode(0, None, &all, &[], ttl_cap, false);
+        if let Err(e) = self.sock.send_to(&pkt, SocketAddr::from((GROUP, PORT))).await {
+            log_error(format!("mdns: announce on {} failed: {}", self.iface, e));
+        }
+    }
+}
+
+pub(crate) struct Responder {
+    links: Vec<Link>,
+}
+
+// 在降权前调用：SO_BINDTODEVICE 需要 root 或 CAP_NET_RAW
+pub(crate) fn bind(cfg: MdnsConfig) -> Result<Responder> {
+    let host = vec![format!("{}-proxy", truncate_label(&hostname()).trim_end_matches('-')), String::from("local")];
+    let ifaces = crate::util::list_interfaces()?;
+    for name in &cfg.ifaces {
+        if !ifaces.iter().any(|i| &i.name == name) { anyhow::bail!("--mdns-iface {}: no such interface", name); }
+    }
+    let mut links = Vec::new();
+    for i in ifaces {
+        let wanted = if cfg.ifaces.is_empty() { i.is_up && !i.is_loopback } else { cfg.ifaces.contains(&i.name) };
+        let Some(addr) = i.addrs.iter().find_map(|a| match a { std::net::IpAddr::V4(v4) => Some(*v4), _ => None }) else { continue };
+        if !wanted { continue; }
+        let records = records(&cfg, &host, addr);
+        // 只有 A 记录：该网卡上没有可达的监听
+        if records.len() <= 1 { continue; }
+        let sock = socket_for(&i.name, addr).map_err(|e| anyhow::anyhow!("mdns: cannot listen on {}: {}", i.name, e))?;
+        log_info(format!("mdns: advertising {:?} on {} ({}.local, {})", cfg.instance, i.name, host[0], addr));
+        links.push(Link { iface: i.name, sock: Arc::new(sock), records: Arc::new(records) });
+    }
+    if links.is_empty() {
+        log_error("mdns: nothing to advertise (no HTTP/SOCKS5 listener reachable on an up, non-loopback IPv4 interface)");
+    }
+    Ok(Responder { links })
+}
+
+async fn serve(sock: Arc<UdpSocket>, iface: String, records: Arc<Vec<Record>>) {
+    let mut buf = vec![0u8; 9000];
+    loop {
+        let (n, from) = match sock.recv_from(&mut buf).await {
+            Ok(v) => v,
+            Err(e) => {
+                log_error(format!("mdns: recv on {} failed: {}", iface, e));
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let Some(q) = parse_query(&buf[..n]) else { continue };
+        let (answers, additional) = answer(&records, &q.questions);
+        if answers.is_empty() { continue; }
+        // 旧式单播查询（如 dig -p 5353）：沿用查询 ID 与问题段直接回给发送方
+        let legacy = from.port() != PORT;
+        let pkt = if legacy {
+            encode(q.id, Some((&buf[12..q.end], q.qdcount)), &answers, &additional, LEGACY_TTL, true)
+        } else {
+            encode(0, None, &answers, &additional, u32::MAX, false)
+        };
+        let dest = if legacy || q.questions.iter().all(|q| q.unicast) { from } else { SocketAddr::from((GROUP, PORT)) };
+        if let Err(e) = sock.send_to(&pkt, dest).await {
+            log_error(format!("mdns: reply to {} on {} failed: {}", from, iface, e));
+        }
+    }
+}
+
+impl Responder {
+    pub(crate) fn spawn(&self) {
+        for l in &self.links {
+            tokio::spawn(serve(l.sock.clone(), l.iface.clone(), l.records.clone()));
+        }
+        let links = self.links.clone();
+        // RFC 6762 8.3：启动时至少通告两次，间隔一秒
+        tokio::spawn(async move {
+            for _ in 0..2 {
+                for l in &links { l.announce(u32::MAX).await; }
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        });
+    }
+
+    // 退出前发送告别包，其他设备立即移除缓存的记录
+    pub(crate) async fn goodbye(&self) {
+        for l in &self.links { l.announce(0).await; }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_browse_queries_with_srv_and_address() {
+        let cfg = MdnsConfig {
+            instance: String::from("iface-proxy on box"),
+            services: vec![
+                Service { kind: "_http-proxy._tcp", port: 7890, bind: None },
+                Service { kind: "_socks._tcp", port: 1080, bind: Some(Ipv4Addr::new(10, 0, 0, 9)) },
+            ],
+            ifaces: Vec::new(),
+            txt: vec![String::from("iface=eth0")],
+        };
+        let host = labels("box-proxy.local");
+        let recs = records(&cfg, &host, Ipv4Addr::new(192, 168, 1, 5));
+        // SOCKS5 只监听在别的地址上
+        assert!(!recs.iter().any(|r| r.name[0] == "_socks"));
+
+        // 带压缩指针的 PTR 查询：_http-proxy._tcp.local，再问一次 _services 且请求单播
+        let mut q = vec![0x12, 0x34, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0];
+        put_name(&mut q, &labels("_http-proxy._tcp.local"));
+        q.extend_from_slice(&[0, 12, 0, 1]);
+        q.extend_from_slice(&[9]);
+        q.extend_from_slice(b"_services");
+        q.extend_from_slice(&[7]);
+        q.extend_from_slice(b"_dns-sd");
+        q.extend_from_slice(&[4]);
+        q.extend_from_slice(b"_udp");
+        q.extend_from_slice(&[0xc0, 29, 0, 12, 0x80, 1]);
+        let query = parse_query(&q).unwrap();
+        assert_eq!(query.end, q.len());
+        assert_eq!(query.questions[1].name, labels("_services._dns-sd._udp.local"));
+        assert!(query.questions[1].unicast && !query.questions[0].unicast);
+
+        let (answers, additional) = answer(&recs, &query.questions);
+        assert_eq!(answers.iter().map(|r| r.rtype).collect::<Vec<_>>(), vec![TYPE_PTR, TYPE_PTR]);
+        assert_eq!(additional.iter().map(|r| r.rtype).collect::<Vec<_>>(), vec![TYPE_SRV, TYPE_TXT, TYPE_A]);
+        let srv = additional[0];
+        assert_eq!(srv.name[0], "iface-proxy on box");
+        assert_eq!(&srv.rdata[4..6], &7890u16.to_be_bytes());
+        assert_eq!(additional[2].rdata, vec![192, 168, 1, 5]);
+
+        let pkt = encode(query.id, Some((&q[12..query.end], 2)), &answers, &additional, LEGACY_TTL, true);
+        assert_eq!(&pkt[..12], &[0x12, 0x34, 0x84, 0, 0, 2, 0, 2, 0, 0, 0, 3]);
+        assert_eq!(read_name(&pkt, 12).unwrap().0, labels("_http-proxy._tcp.local"));
+
+        // 自己发出的通告（QR=1）不当作查询
+        assert!(parse_query(&pkt).is_none());
+        assert!(answer(&recs, &[Question { name: labels("other.local"), qtype: TYPE_ANY, unicast: false }]).0.is_empty());
+    }
+}
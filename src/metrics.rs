@@ -0,0 +1,27 @@
+use crate::build_info;
+
+// Prometheus 文本格式 (text/plain; version=0.0.4)
+pub(crate) fn render() -> String {
+    let mut out = String::new();
+    out.push_str("# HELP iface_proxy_build_info Build information; the value is always 1.\n");
+    out.push_str("# TYPE iface_proxy_build_info gauge\n");
+    out.push_str(&format!(
+        "iface_proxy_build_info{{version=\"{}\",git_hash=\"{}\",build_date=\"{}\",allocator=\"{}\"}} 1\n",
+        build_info::VERSION,
+        build_info::GIT_HASH,
+        build_info::BUILD_DATE,
+        crate::alloc::allocator_name()
+    ));
+    out.push_str("# HELP iface_proxy_active_sessions Sessions currently in progress.\n");
+    out.push_str("# TYPE iface_proxy_active_sessions gauge\n");
+    out.push_str(&format!("iface_proxy_active_sessions {}\n", crate::session::active()));
+    out.push_str(&crate::listener::render_metrics());
+    out.push_str(&crate::handshake::render_metrics());
+    out.push_str(&crate::error::render_metrics());
+    out.push_str(&crate::acl::render_metrics());
+    out.push_str(&crate::probe::render_metrics());
+    out.push_str(&crate::response::render_metrics());
+    out.push_str(&crate::cache::render_metrics());
+    out.push_str(&crate::chaos::render_metrics());
+    out
+}
@@ -0,0 +1,72 @@
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+
+use crate::http_proxy::handle_http_proxy;
+use crate::socks5::handle_socks5;
+use crate::listener::{AcceptBackoff, ListenerSettings};
+use crate::util::{log_throttled, log_info};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Sniffed { Http, Socks }
+
+// SOCKS 握手以版本号字节（0x05 / 0x04）开头，HTTP 请求以 ASCII 的方法名开头
+fn sniff(first: u8) -> Option<Sniffed> {
+    match first {
+        0x04 | 0x05 => Some(Sniffed::Socks),
+        b'A'..=b'Z' => Some(Sniffed::Http),
+        _ => None,
+    }
+}
+
+async fn handle_mixed(inbound: TcpStream, peer: SocketAddr, s: &ListenerSettings) -> Result<()> {
+    // peek 不取走这个字节，选中的处理函数仍能读到完整请求
+    let mut first = [0u8; 1];
+    let n = timeout(Duration::from_millis(s.read_timeout_ms), inbound.peek(&mut first))
+        .await
+        .map_err(|_| anyhow::anyhow!("read timeout"))??;
+    if n == 0 { anyhow::bail!("client closed before sending data"); }
+    match sniff(first[0]) {
+        Some(Sniffed::Socks) => handle_socks5(inbound, s.dial_context(), s.auth.as_deref(), s.read_timeout_ms, s.session_timeout_ms).await,
+        Some(Sniffed::Http) => handle_http_proxy(inbound, Some(peer), s.dial_context(), s.auth.as_deref(), None, s.read_timeout_ms, s.session_timeout_ms).await,
+        None => anyhow::bail!("unrecognized protocol (first byte 0x{:02x})", first[0]),
+    }
+}
+
+pub async fn run_mixed_proxy(listener: TcpListener, s: Arc<ListenerSettings>) -> Result<()> {
+    let listen = listener.local_addr()?.to_string();
+    log_info(format!("Mixed (HTTP/SOCKS5/SOCKS4) proxy listening on {}, bound to {}", listen, s.iface));
+    let mut backoff = AcceptBackoff::new();
+    loop {
+        let (inbound, peer_addr) = backoff.accept(&listen, || listener.accept()).await;
+        if !s.allows(peer_addr.ip()) {
+            log_throttled(|| log_info(format!("denied connection from {} to {} (not in allow list)", peer_addr, listen)));
+            continue;
+        }
+        if crate::ban::banned(peer_addr.ip(), &listen) { continue; }
+        let Some(pending) = s.limit.handshake(Some(peer_addr.ip())) else {
+            log_throttled(|| log_info("too many connections still in handshake; dropping new mixed connection"));
+            continue;
+        };
+        match s.limit.acquire().await {
+            Some(permit) => {
+                let (s, listen) = (s.clone(), listen.clone());
+                let who = crate::process::Peer::tcp(&inbound);
+                tokio::spawn(crate::session::run("mixed", peer_addr.to_string(), s.sockopts, async move {
+                    let _permit = permit;
+                    crate::session::hold_handshake(pending);
+                    log_throttled(|| log_info(format!("Incoming TCP connection from {} -> listening on {} (iface: {})", peer_addr, listen, s.iface)));
+                    crate::process::record(who).await;
+                    if let Err(e) = handle_mixed(inbound, peer_addr, &s).await {
+                        crate::error::log_session_error("Mixed handler", &e);
+                    }
+                }));
+            }
+            None => {
+                log_throttled(|| log_info("too many concurrent connections; dropping new mixed connection"));
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::util::{list_interfaces, log_error, log_info};
+
+// --nat64-prefix：出口网卡只有 IPv6（如蜂窝网络）时，把 IPv4 目标（客户端给的 IPv4 字面量、只有 A 记录的域名）
+// 按 RFC 6052 嵌入 NAT64 前缀，改连合成的 IPv6 地址。`auto` 时按 RFC 7050 解析 ipv4only.arpa 的 AAAA 找出前缀
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Prefix {
+    net: Ipv6Addr,
+    len: u8,
+}
+
+// RFC 6052 允许的前缀长度
+const LENGTHS: [u8; 6] = [96, 64, 56, 48, 40, 32];
+
+impl Prefix {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (addr, len) = s.trim().split_once('/').unwrap_or((s.trim(), "96"));
+        let net: Ipv6Addr = addr.parse().map_err(|_| anyhow::anyhow!("invalid NAT64 prefix {:?}", s))?;
+        let len: u8 = len.parse().ok().filter(|l| LENGTHS.contains(l)).ok_or_else(|| anyhow::anyhow!("invalid NAT64 prefix length in {:?} (expected 32, 40, 48, 56, 64 or 96)", s))?;
+        let mask = u128::MAX << (128 - len as u32);
+        Ok(Self { net: Ipv6Addr::from(u128::from(net) & mask), len })
+    }
+
+    // 前缀之后依次放入 IPv4 的 4 个字节，跳过第 64-71 位（u 字节）
+    fn positions(self) -> impl Iterator<Item = usize> {
+        (self.len as usize / 8..16).filter(|&p| p != 8).take(4)
+    }
+
+    pub(crate) fn synthesize(self, v4: Ipv4Addr) -> Ipv6Addr {
+        let mut b = self.net.octets();
+        for (p, byte) in self.positions().zip(v4.octets()) { b[p] = byte; }
+        Ipv6Addr::from(b)
+    }
+
+    // 属于该前缀的地址中嵌入的 IPv4
+    pub(crate) fn extract(self, v6: Ipv6Addr) -> Option<Ipv4Addr> {
+        let mask = u128::MAX << (128 - self.len as u32);
+        if u128::from(v6) & mask != u128::from(self.net) { return None; }
+        let b = v6.octets();
+        let mut v4 = [0u8; 4];
+        for (i, p) in self.positions().enumerate() { v4[i] = b[p]; }
+        Some(Ipv4Addr::from(v4))
+    }
+}
+
+impl std::fmt::Display for Prefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.net, self.len)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Nat64 {
+    Prefix(Prefix),
+    Auto,
+}
+
+impl Nat64 {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        if s.trim().eq_ignore_ascii_case("auto") { return Ok(Self::Auto); }
+        Prefix::parse(s).map(Self::Prefix)
+    }
+}
+
+static PREFIX: RwLock<Option<Prefix>> = RwLock::new(None);
+// 各网卡是否只有 IPv6，短时间缓存，避免每个连接都枚举网卡
+static V6_ONLY: OnceLock<Mutex<HashMap<String, (Instant, bool)>>> = OnceLock::new();
+const V6_ONLY_TTL: Duration = Duration::from_secs(10);
+// auto 时的重新探测间隔：找到前缀后较长（换网络时前缀可能变化），没找到时较短
+const REDISCOVER: Duration = Duration::from_secs(3600);
+const RETRY: Duration = Duration::from_secs(60);
+
+fn prefix() -> Option<Prefix> {
+    *PREFIX.read().unwrap_or_else(|e| e.into_inner())
+}
+
+fn set_prefix(p: Option<Prefix>) {
+    *PREFIX.write().unwrap_or_else(|e| e.into_inner()) = p;
+}
+
+pub(crate) fn install(cfg: Nat64) {
+    match cfg {
+        Nat64::Prefix(p) => set_prefix(Some(p)),
+        Nat64::Auto => { tokio::spawn(run_discovery()); }
+    }
+}
+
+async fn run_discovery() {
+    loop {
+        let found = match discover().await {
+            Ok(p) => {
+                if prefix() != Some(p) { log_info(format!("nat64: discovered prefix {} via ipv4only.arpa", p)); }
+                Some(p)
+            }
+            Err(e) => {
+                if prefix().is_some() { log_error(format!("nat64: rediscovery failed, keeping the previous prefix: {}", e)); }
+                prefix()
+            }
+        };
+        set_prefix(found);
+        tokio::time::sleep(if found.is_some() { REDISCOVER } else { RETRY }).await;
+    }
+}
+
+// RFC 7050：ipv4only.arpa 只有 A 记录 192.0.0.170/171，DNS64 合成的 AAAA 中嵌入了它们，据此得出前缀与长度
+async fn discover() -> Result<Prefix> {
+    let addrs: Vec<Ipv6Addr> = tokio::net::lookup_host(("ipv4only.arpa", 0))
+        .await?
+        .filter_map(|sa| match sa.ip() {
+            IpAddr::V6(v6) => Some(v6),
+            IpAddr::V4(_) => None,
+        })
+        .collect();
+    addrs.iter().find_map(|&v6| prefix_of(v6)).ok_or_else(|| anyhow::anyhow!("ipv4only.arpa has no synthesized AAAA record (no DNS64 on this network)"))
+}
+
+fn prefix_of(v6: Ipv6Addr) -> Option<Prefix> {
+    let known = [Ipv4Addr::new(192, 0, 0, 170), Ipv4Addr::new(192, 0, 0, 171)];
+    LENGTHS.iter().find_map(|&len| {
+        let p = Prefix::parse(&format!("{}/{}", v6, len)).ok()?;
+        p.extract(v6).filter(|v4| known.contains(v4)).map(|_| p)
+    })
+}
+
+// 网卡存在、有 IPv6 全局地址而没有可用的 IPv4（链路本地不算）
+fn v6_only(iface: &str) -> bool {
+    let cache = V6_ONLY.get_or_init(Mutex::default);
+    if let Some(&(at, v)) = cache.lock().unwrap_or_else(|e| e.into_inner()).get(iface) {
+        if at.elapsed() < V6_ONLY_TTL { return v; }
+    }
+    let v = list_interfaces().ok().and_then(|l| l.into_iter().find(|i| i.name == iface)).is_some_and(|i| {
+        let v4 = i.addrs.iter().any(|a| matches!(a, IpAddr::V4(v4) if !v4.is_link_local() && !v4.is_loopback()));
+        let v6 = i.addrs.iter().any(|a| matches!(a, IpAddr::V6(v6) if !v6.is_loopback() && (v6.segments()[0] & 0xffc0) != 0xfe80));
+        v6 && !v4
+    });
+    cache.lock().unwrap_or_else(|e| e.into_inner()).insert(iface.to_string(), (Instant::now(), v));
+    v
+}
+
+// 出口网卡只有 IPv6 时把解析结果中的 IPv4 换成合成地址（原生 IPv6 在前）
+pub(crate) fn map(addrs: Vec<SocketAddr>, iface: &str) -> Vec<SocketAddr> {
+    let Some(p) = prefix() else { return addrs };
+    if !addrs.iter().any(|a| a.is_ipv4()) || !v6_only(iface) { return addrs; }
+    let (v6, v4): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs.into_iter().partition(|a| a.is_ipv6());
+    let synthesized = v4.into_iter().filter_map(|a| match a.ip() {
+        IpAddr::V4(ip) => Some(SocketAddr::new(IpAddr::V6(p.synthesize(ip)), a.port())),
+        IpAddr::V6(_) => None,
+    });
+    let mut out = v6;
+    for a in synthesized {
+        if !out.contains(&a) { out.push(a); }
+    }
+    out
+}
+
+// 合成地址按其中的 IPv4 做目标黑名单检查，其余原样返回
+pub(crate) fn unmap(ip: IpAddr) -> IpAddr {
+    match (ip, prefix()) {
+        (IpAddr::V6(v6), Some(p)) => p.extract(v6).map_or(ip, IpAddr::V4),
+        _ => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc6052_examples() {
+        let v4 = Ipv4Addr::new(192, 0, 2, 33);
+        for (prefix, want) in [
+            ("2001:db8::/32", "2001:db8:c000:221::"),
+            ("2001:db8:100::/40", "2001:db8:1c0:2:21::"),
+            ("2001:db8:122::/48", "2001:db8:122:c000:2:2100::"),
+            ("2001:db8:122:300::/56", "2001:db8:122:3c0:0:221::"),
+            ("2001:db8:122:344::/64", "2001:db8:122:344:c0:2:2100:0"),
+            ("2001:db8:122:344::/96", "2001:db8:122:344::c000:221"),
+        ] {
+            let p = Prefix::parse(prefix).unwrap();
+            let got = p.synthesize(v4);
+            assert_eq!(got, want.parse::<Ipv6Addr>().unwrap(), "{}", prefix);
+            assert_eq!(p.extract(got), Some(v4));
+        }
+        assert_eq!(Prefix::parse("64:ff9b::").unwrap().to_string(), "64:ff9b::/96");
+        assert!(Prefix::parse("64:ff9b::/80").is_err());
+        assert_eq!(Prefix::parse("64:ff9b::/96").unwrap().extract("2001:db8::1".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn discovers_prefix_from_ipv4only_arpa() {
+        assert_eq!(prefix_of("64:ff9b::c000:aa".parse().unwrap()), Some(Prefix::parse("64:ff9b::/96").unwrap()));
+        assert_eq!(prefix_of("2001:db8:122:3c0:0:aa::".parse().unwrap()), Some(Prefix::parse("2001:db8:122:300::/56").unwrap()));
+        assert_eq!(prefix_of("2001:db8::1".parse().unwrap()), None);
+    }
+}
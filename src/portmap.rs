@@ -0,0 +1,498 @@
+use anyhow::Result;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+use crate::util::{log_error, log_info};
+
+// 端口映射：监听在局域网地址上时，经 NAT-PMP (RFC 6886) 或 UPnP IGD 请求网关把同一外部端口映射到本机，
+// 租期过半时续期，退出时删除；取得的外部地址与端口写入日志
+const NATPMP_PORT: u16 = 5351;
+const SSDP_ADDR: (Ipv4Addr, u16) = (Ipv4Addr::new(239, 255, 255, 250), 1900);
+const SSDP_WAIT_MS: u64 = 2000;
+const HTTP_TIMEOUT_SECS: u64 = 5;
+// 出错后重试的间隔
+const RETRY_SECS: u64 = 60;
+const DESCRIPTION: &str = "iface-proxy";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Method {
+    // 先试 NAT-PMP，网关不支持时改用 UPnP
+    Auto,
+    NatPmp,
+    Upnp,
+}
+
+impl Method {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "natpmp" | "nat-pmp" => Ok(Self::NatPmp),
+            "upnp" => Ok(Self::Upnp),
+            _ => anyhow::bail!("invalid port map method {:?} (expected auto, natpmp or upnp)", s),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Proto {
+    Tcp,
+    Udp,
+}
+
+impl Proto {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+        }
+    }
+}
+
+// 一个要映射的监听端口，外部端口与之相同
+#[derive(Clone, Debug)]
+pub(crate) struct Mapping {
+    pub(crate) proto: Proto,
+    pub(crate) port: u16,
+    // 监听地址，None 表示所有地址
+    pub(crate) bind: Option<Ipv4Addr>,
+    // 监听类型，用于日志
+    pub(crate) label: &'static str,
+}
+
+pub(crate) struct PortMapConfig {
+    pub(crate) method: Method,
+    pub(crate) lifetime: u32,
+    // 不指定时取默认路由（或监听地址所在网卡）的网关
+    pub(crate) gateway: Option<Ipv4Addr>,
+}
+
+// /proc/net/route 中的默认路由网关；给出网卡名时只看该网卡，多条时取 metric 最小的
+fn parse_proc_route(text: &str, iface: Option<&str>) -> Option<Ipv4Addr> {
+    const RTF_GATEWAY: u32 = 0x2;
+    let hex = |s: &str| u32::from_str_radix(s, 16).ok();
+    text.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let f: Vec<&str> = line.split_whitespace().collect();
+            if f.len() < 7 || iface.is_some_and(|i| i != f[0]) { return None; }
+            let (dest, gw, flags, metric) = (hex(f[1])?, hex(f[2])?, hex(f[3])?, f[6].parse::<u32>().ok()?);
+            // 按网络字节序输出的 u32
+            (dest == 0 && flags & RTF_GATEWAY != 0).then(|| (metric, Ipv4Addr::from(gw.to_ne_bytes())))
+        })
+        .min_by_key(|(metric, _)| *metric)
+        .map(|(_, gw)| gw)
+}
+
+fn iface_of(addr: Ipv4Addr) -> Option<String> {
+    crate::util::list_interfaces().ok()?.into_iter().find(|i| i.addrs.contains(&addr.into())).map(|i| i.name)
+}
+
+#[cfg(target_os = "linux")]
+fn default_gateway(bind: Option<Ipv4Addr>) -> Result<Ipv4Addr> {
+    let iface = bind.and_then(iface_of);
+    let text = std::fs::read_to_string("/proc/net/route")?;
+    parse_proc_route(&text, iface.as_deref()).ok_or_else(|| anyhow::anyhow!("no default IPv4 gateway{} (use --port-map-gateway)", iface.map(|i| format!(" on {}", i)).unwrap_or_default()))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn default_gateway(bind: Option<Ipv4Addr>) -> Result<Ipv4Addr> {
+    let mut cmd = std::process::Command::new("route");
+    cmd.args(["-n", "get"]);
+    if let Some(iface) = bind.and_then(iface_of) { cmd.args(["-ifscope", &iface]); }
+    let out = cmd.arg("default").output()?;
+    String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("gateway:").and_then(|g| g.trim().parse().ok()))
+        .ok_or_else(|| anyhow::anyhow!("no default IPv4 gateway (use --port-map-gateway)"))
+}
+
+// 发往网关时使用的本机地址
+fn local_toward(gateway: Ipv4Addr, bind: Option<Ipv4Addr>) -> Result<Ipv4Addr> {
+    let sock = std::net::UdpSocket::bind((bind.unwrap_or(Ipv4Addr::UNSPECIFIED), 0))?;
+    sock.connect((gateway, NATPMP_PORT))?;
+    match sock.local_addr()? {
+        SocketAddr::V4(a) => Ok(*a.ip()),
+        SocketAddr::V6(_) => anyhow::bail!("no IPv4 address toward gateway {}", gateway),
+    }
+}
+
+fn natpmp_result(code: u16) -> &'static str {
+    match code {
+        1 => "unsupported version",
+        2 => "not authorized/refused",
+        3 => "network failure",
+        4 => "out of resources",
+        5 => "unsupported opcode",
+        _ => "unknown error",
+    }
+}
+
+fn natpmp_map_request(proto: Proto, internal: u16, external: u16, lifetime: u32) -> Vec<u8> {
+    let op = match proto {
+        Proto::Udp => 1,
+        Proto::Tcp => 2,
+    };
+    let mut req = vec![0, op, 0, 0];
+    req.extend_from_slice(&internal.to_be_bytes());
+    req.extend_from_slice(&external.to_be_bytes());
+    req.extend_from_slice(&lifetime.to_be_bytes());
+    req
+}
+
+// 校验应答的操作码与结果码，返回结果码之后的内容（从 epoch 开始）
+fn natpmp_parse<'a>(req: &[u8], resp: &'a [u8]) -> Result<Option<&'a [u8]>> {
+    if resp.len() < 8 || resp[0] != 0 || resp[1] != req[1] | 0x80 { return Ok(None); }
+    let code = u16::from_be_bytes([resp[2], resp[3]]);
+    if code != 0 { anyhow::bail!("error {} ({})", code, natpmp_result(code)); }
+    Ok(Some(&resp[4..]))
+}
+
+// 按 RFC 6886 从 250ms 起倍增重发，这里最多 4 次
+async fn natpmp_request(gateway: Ipv4Addr, local: Ipv4Addr, req: &[u8]) -> Result<Vec<u8>> {
+    let sock = UdpSocket::bind((local, 0)).await?;
+    sock.connect((gateway, NATPMP_PORT)).await?;
+    let mut wait = Duration::from_millis(250);
+    let mut buf = [0u8; 64];
+    for _ in 0..4 {
+        sock.send(req).await?;
+        let deadline = tokio::time::Instant::now() + wait;
+        while let Ok(r) = tokio::time::timeout_at(deadline, sock.recv(&mut buf)).await {
+            let n = r?;
+            if let Some(body) = natpmp_parse(req, &buf[..n])? { return Ok(body.to_vec()); }
+        }
+        wait *= 2;
+    }
+    anyhow::bail!("no response")
+}
+
+async fn natpmp_map(gateway: Ipv4Addr, local: Ipv4Addr, m: &Mapping, lifetime: u32) -> Result<(Ipv4Addr, u16, u32)> {
+    let ext = natpmp_request(gateway, local, &[0, 0]).await?;
+    let ip = Ipv4Addr::new(*ext.get(4).unwrap_or(&0), *ext.get(5).unwrap_or(&0), *ext.get(6).unwrap_or(&0), *ext.get(7).unwrap_or(&0));
+    let body = natpmp_request(gateway, local, &natpmp_map_request(m.proto, m.port, m.port, lifetime)).await?;
+    if body.len() < 12 { anyhow::bail!("short NAT-PMP mapping response"); }
+    let external = u16::from_be_bytes([body[6], body[7]]);
+    let granted = u32::from_be_bytes([body[8], body[9], body[10], body[11]]);
+    Ok((ip, external, granted))
+}
+
+// UPnP IGD 上的 WANIPConnection/WANPPPConnection 服务
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Igd {
+    control: String,
+    service: String,
+}
+
+fn header<'a>(resp: &'a str, name: &str) -> Option<&'a str> {
+    resp.lines().find_map(|l| {
+        let (k, v) = l.split_once(':')?;
+        k.trim().eq_ignore_ascii_case(name).then(|| v.trim())
+    })
+}
+
+// 第一个 `<tag>`（忽略命名空间前缀）的文本内容
+fn xml_value<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = rest.find('>')?;
+        let name = rest[..end].split_whitespace().next().unwrap_or("");
+        let local = name.rsplit(':').next().unwrap_or(name);
+        if local == tag {
+            let body = &rest[end + 1..];
+            return Some(body[..body.find("</")?].trim());
+        }
+        rest = &rest[end + 1..];
+    }
+    None
+}
+
+fn split_url(url: &str) -> Result<(&str, &str)> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| anyhow::anyhow!("unsupported UPnP URL {:?}", url))?;
+    Ok(match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    })
+}
+
+// 设备描述中的连接服务；controlURL 为相对地址时按 URLBase 或描述文件地址补全
+fn find_igd(xml: &str, location: &str) -> Option<Igd> {
+    let services: Vec<&str> = xml.split("<service>").skip(1).collect();
+    let pick = |kind: &str| services.iter().find_map(|s| {
+        let service = xml_value(s, "serviceType")?;
+        if !service.contains(kind) { return None; }
+        Some((service.to_string(), xml_value(s, "controlURL")?.to_string()))
+    });
+    let (service, control) = pick(":WANIPConnection:").or_else(|| pick(":WANPPPConnection:"))?;
+    let control = if control.starts_with("http://") {
+        control
+    } else {
+        let base = xml_value(xml, "URLBase").filter(|b| !b.is_empty()).unwrap_or(location);
+        let host = split_url(base).ok()?.0;
+        format!("http://{}/{}", host, control.trim_start_matches('/'))
+    };
+    Some(Igd { control, service })
+}
+
+async fn http(url: &str, soap: Option<(&str, String)>) -> Result<(u16, String)> {
+    let (host, path) = split_url(url)?;
+    let req = match &soap {
+        Some((action, body)) => format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/xml; charset=\"utf-8\"\r\nSOAPAction: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path, host, action, body.len(), body
+        ),
+        None => format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host),
+    };
+    let resp = timeout(Duration::from_secs(HTTP_TIMEOUT_SECS), async {
+        let mut s = TcpStream::connect(host).await?;
+        s.write_all(req.as_bytes()).await?;
+        let mut resp = Vec::new();
+        s.read_to_end(&mut resp).await?;
+        Ok::<_, anyhow::Error>(resp)
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("{} timed out", url))??;
+    let resp = String::from_utf8_lossy(&resp).into_owned();
+    let status = resp.split_whitespace().nth(1).and_then(|s| s.parse().ok()).ok_or_else(|| anyhow::anyhow!("bad HTTP response from {}", url))?;
+    let body = resp.split_once("\r\n\r\n").map(|(_, b)| b.to_string()).unwrap_or_default();
+    Ok((status, body))
+}
+
+async fn upnp_discover(gateway: Ipv4Addr, local: Ipv4Addr) -> Result<Igd> {
+    let sock = UdpSocket::bind((local, 0)).await?;
+    let search = "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n";
+    sock.send_to(search.as_bytes(), SSDP_ADDR).await?;
+    // 部分网关不应答组播搜索，同时直接问一次网关
+    let _ = sock.send_to(search.as_bytes(), (gateway, SSDP_ADDR.1)).await;
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(SSDP_WAIT_MS);
+    let mut buf = [0u8; 2048];
+    let mut location = None;
+    while let Ok(r) = tokio::time::timeout_at(deadline, sock.recv_from(&mut buf)).await {
+        let (n, from) = r?;
+        let Some(loc) = header(&String::from_utf8_lossy(&buf[..n]), "location").map(String::from) else { continue };
+        let from_gateway = from.ip() == std::net::IpAddr::V4(gateway);
+        location = Some(loc);
+        if from_gateway { break; }
+    }
+    let location = location.ok_or_else(|| anyhow::anyhow!("no UPnP gateway answered"))?;
+    let (status, xml) = http(&location, None).await?;
+    if status != 200 { anyhow::bail!("UPnP description {} returned {}", location, status); }
+    find_igd(&xml, &location).ok_or_else(|| anyhow::anyhow!("UPnP device at {} has no WAN connection service", location))
+}
+
+async fn soap(igd: &Igd, action: &str, args: &[(&str, String)]) -> Result<String> {
+    let args: String = args.iter().map(|(k, v)| format!("<{}>{}</{}>", k, v, k)).collect();
+    let body = format!(
+        "<?xml version=\"1.0\"?>\r\n<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body><u:{} xmlns:u=\"{}\">{}</u:{}></s:Body></s:Envelope>\r\n",
+        action, igd.service, args, action
+    );
+    let (status, resp) = http(&igd.control, Some((&format!("\"{}#{}\"", igd.service, action), body))).await?;
+    if status != 200 {
+        anyhow::bail!("UPnP {} failed: {} {}", action, xml_value(&resp, "errorCode").unwrap_or("?"), xml_value(&resp, "errorDescription").unwrap_or(""));
+    }
+    Ok(resp)
+}
+
+// 返回实际使用的租期
+async fn upnp_add(igd: &Igd, local: Ipv4Addr, m: &Mapping, lifetime: u32) -> Result<u32> {
+    let args = |lease: u32| {
+        vec![
+            ("NewRemoteHost", String::new()),
+            ("NewExternalPort", m.port.to_string()),
+            ("NewProtocol", m.proto.name().to_ascii_uppercase()),
+            ("NewInternalPort", m.port.to_string()),
+            ("NewInternalClient", local.to_string()),
+            ("NewEnabled", String::from("1")),
+            ("NewPortMappingDescription", String::from(DESCRIPTION)),
+            ("NewLeaseDuration", lease.to_string()),
+        ]
+    };
+    match soap(igd, "AddPortMapping", &args(lifetime)).await {
+        // 725 OnlyPermanentLeasesSupported：改为不限期，退出时照样删除
+        Err(e) if lifetime != 0 && e.to_string().contains(" 725 ") => soap(igd, "AddPortMapping", &args(0)).await.map(|_| 0),
+        r => r.map(|_| lifetime),
+    }
+}
+
+async fn upnp_map(gateway: Ipv4Addr, local: Ipv4Addr, m: &Mapping, lifetime: u32) -> Result<(Ipv4Addr, Igd, u32)> {
+    let igd = upnp_discover(gateway, local).await?;
+    let granted = upnp_add(&igd, local, m, lifetime).await?;
+    let resp = soap(&igd, "GetExternalIPAddress", &[]).await?;
+    let ip = xml_value(&resp, "NewExternalIPAddress").and_then(|v| v.parse().ok()).unwrap_or(Ipv4Addr::UNSPECIFIED);
+    Ok((ip, igd, granted))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Backend {
+    NatPmp,
+    Upnp(Igd),
+}
+
+// 已建立的映射
+#[derive(Clone, Debug)]
+struct Active {
+    mapping: Mapping,
+    gateway: Ipv4Addr,
+    local: Ipv4Addr,
+    external: SocketAddr,
+    backend: Backend,
+}
+
+async fn map_one(cfg: &PortMapConfig, m: &Mapping) -> Result<(Active, u32)> {
+    let gateway = match cfg.gateway {
+        Some(g) => g,
+        None => default_gateway(m.bind)?,
+    };
+    let local = local_toward(gateway, m.bind)?;
+    let natpmp_err = if cfg.method == Method::Upnp {
+        None
+    } else {
+        match natpmp_map(gateway, local, m, cfg.lifetime).await.map_err(|e| anyhow::anyhow!("NAT-PMP via {}: {}", gateway, e)) {
+            Ok((ip, port, granted)) => {
+                let external = SocketAddr::from((ip, port));
+                return Ok((Active { mapping: m.clone(), gateway, local, external, backend: Backend::NatPmp }, granted));
+            }
+            Err(e) if cfg.method == Method::NatPmp => return Err(e),
+            Err(e) => Some(e),
+        }
+    };
+    match upnp_map(gateway, local, m, cfg.lifetime).await.map_err(|e| anyhow::anyhow!("UPnP via {}: {}", gateway, e)) {
+        Ok((ip, igd, granted)) => Ok((Active { mapping: m.clone(), gateway, local, external: SocketAddr::from((ip, m.port)), backend: Backend::Upnp(igd) }, granted)),
+        Err(e) => match natpmp_err {
+            Some(n) => anyhow::bail!("{}; {}", n, e),
+            None => Err(e),
+        },
+    }
+}
+
+async fn unmap(a: &Active) -> Result<()> {
+    match &a.backend {
+        Backend::NatPmp => natpmp_request(a.gateway, a.local, &natpmp_map_request(a.mapping.proto, a.mapping.port, 0, 0)).await.map(|_| ()),
+        Backend::Upnp(igd) => soap(
+            igd,
+            "DeletePortMapping",
+            &[("NewRemoteHost", String::new()), ("NewExternalPort", a.mapping.port.to_string()), ("NewProtocol", a.mapping.proto.name().to_ascii_uppercase())],
+        )
+        .await
+        .map(|_| ()),
+    }
+}
+
+fn describe(a: &Active) -> String {
+    let via = match &a.backend {
+        Backend::NatPmp => String::from("NAT-PMP"),
+        Backend::Upnp(igd) => format!("UPnP {}", igd.control),
+    };
+    let double_nat = match a.external.ip() {
+        // 含运营商级 NAT 的 100.64.0.0/10
+        std::net::IpAddr::V4(ip) if ip.is_private() || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64) => " (private address: the gateway is itself behind NAT)",
+        std::net::IpAddr::V4(ip) if ip.is_unspecified() => " (gateway did not report its external address)",
+        _ => "",
+    };
+    format!(
+        "{} {}:{} ({}) -> {}{} via {}, gateway {}",
+        a.mapping.proto.name(),
+        a.local,
+        a.mapping.port,
+        a.mapping.label,
+        a.external,
+        double_nat,
+        via,
+        a.gateway
+    )
+}
+
+pub(crate) struct PortMapper {
+    active: Arc<tokio::sync::Mutex<Vec<Option<Active>>>>,
+}
+
+// 逐个建立映射，在最短租期过半时全部续期；失败的映射每 RETRY_SECS 重试
+pub(crate) fn spawn(cfg: PortMapConfig, mappings: Vec<Mapping>) -> PortMapper {
+    let active = Arc::new(tokio::sync::Mutex::new(vec![None; mappings.len()]));
+    let state = active.clone();
+    tokio::spawn(async move {
+        loop {
+            let mut next = Duration::from_secs(RETRY_SECS.max(cfg.lifetime as u64 / 2));
+            for (i, m) in mappings.iter().enumerate() {
+                match map_one(&cfg, m).await {
+                    Ok((a, granted)) => {
+                        let mut state = state.lock().await;
+                        let changed = state[i].as_ref().is_none_or(|old: &Active| old.external != a.external || old.backend != a.backend);
+                        if changed {
+                            let lease = if granted == 0 { String::from("permanent") } else { format!("lifetime {}s", granted) };
+                            log_info(format!("port map: {} ({})", describe(&a), lease));
+                        }
+                        // 租期为 0 表示不限期，仍按 --port-map-lifetime 的节奏确认映射还在
+                        if granted > 0 { next = next.min(Duration::from_secs((granted as u64 / 2).max(1))); }
+                        state[i] = Some(a);
+                    }
+                    Err(e) => {
+                        log_error(format!("port map: {} port {} ({}): {}", m.proto.name(), m.port, m.label, e));
+                        next = next.min(Duration::from_secs(RETRY_SECS));
+                    }
+                }
+            }
+            tokio::time::sleep(next).await;
+        }
+    });
+    PortMapper { active }
+}
+
+impl PortMapper {
+    // 退出前删除已建立的映射
+    pub(crate) async fn release(&self) {
+        let active: Vec<Active> = self.active.lock().await.iter().flatten().cloned().collect();
+        for a in active {
+            match unmap(&a).await {
+                Ok(()) => log_info(format!("port map: removed {} {}", a.mapping.proto.name(), a.external)),
+                Err(e) => log_error(format!("port map: failed to remove {} {}: {}", a.mapping.proto.name(), a.external, e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_gateway_and_natpmp_replies() {
+        let route = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+                     eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0\n\
+                     wlan0\t00000000\t01000A0A\t0003\t0\t0\t600\t00000000\t0\t0\t0\n\
+                     eth0\t0001A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0\n";
+        if cfg!(target_endian = "little") {
+            assert_eq!(parse_proc_route(route, None), Some(Ipv4Addr::new(192, 168, 1, 1)));
+            assert_eq!(parse_proc_route(route, Some("wlan0")), Some(Ipv4Addr::new(10, 10, 0, 1)));
+        }
+        assert_eq!(parse_proc_route(route, Some("lo")), None);
+
+        let req = natpmp_map_request(Proto::Tcp, 7890, 7890, 3600);
+        assert_eq!(req, vec![0, 2, 0, 0, 0x1e, 0xd2, 0x1e, 0xd2, 0, 0, 0x0e, 0x10]);
+        let ok = [0, 130, 0, 0, 0, 0, 0, 9, 0x1e, 0xd2, 0x1f, 0x00, 0, 0, 0x0e, 0x10];
+        assert_eq!(natpmp_parse(&req, &ok).unwrap().unwrap().len(), 12);
+        let refused = [0, 130, 0, 2, 0, 0, 0, 9, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(natpmp_parse(&req, &refused).unwrap_err().to_string().contains("refused"));
+        // 其他请求的应答
+        assert!(natpmp_parse(&req, &[0, 128, 0, 0, 0, 0, 0, 9, 1, 2, 3, 4]).unwrap().is_none());
+    }
+
+    #[test]
+    fn finds_the_upnp_control_url() {
+        let ssdp = "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\nLocation: http://192.168.1.1:5000/rootDesc.xml\r\nST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n";
+        assert_eq!(header(ssdp, "location"), Some("http://192.168.1.1:5000/rootDesc.xml"));
+        let xml = r#"<root><device><serviceList>
+            <service><serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType><controlURL>/ctl/L3F</controlURL></service>
+            <service><serviceType>urn:schemas-upnp-org:service:WANPPPConnection:1</serviceType><controlURL>/ctl/PPP</controlURL></service>
+            <service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType><controlURL>ctl/IPConn</controlURL></service>
+        </serviceList></device></root>"#;
+        let igd = find_igd(xml, "http://192.168.1.1:5000/rootDesc.xml").unwrap();
+        assert_eq!(igd, Igd { control: String::from("http://192.168.1.1:5000/ctl/IPConn"), service: String::from("urn:schemas-upnp-org:service:WANIPConnection:1") });
+        let resp = "<s:Envelope><s:Body><u:GetExternalIPAddressResponse xmlns:u=\"x\"><NewExternalIPAddress>203.0.113.7</NewExternalIPAddress></u:GetExternalIPAddressResponse></s:Body></s:Envelope>";
+        assert_eq!(xml_value(resp, "NewExternalIPAddress"), Some("203.0.113.7"));
+        assert_eq!(xml_value("<s:Fault><errorCode>725</errorCode></s:Fault>", "errorCode"), Some("725"));
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::Result;
+use nix::unistd::{Gid, Group, Uid, User};
+
+use crate::util::log_info;
+
+// Linux capability 位（linux/capability.h）
+#[cfg(target_os = "linux")]
+const CAP_NET_ADMIN: u32 = 12;
+#[cfg(target_os = "linux")]
+const CAP_NET_RAW: u32 = 13;
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: i32,
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+// PR_SET_KEEPCAPS 与 capset 只作用于调用它的线程，而 glibc 的 setuid 会切换所有线程的 UID。
+// 降权时 tokio 的工作线程已经存在，所以用实时信号让每个线程各自执行一遍，否则只有当前线程保留了 capability，
+// 其他线程上创建的出站 socket 设置 SO_MARK（5.7 之前的内核还有 SO_BINDTODEVICE）会失败
+#[cfg(target_os = "linux")]
+mod threads {
+    use anyhow::Result;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    use nix::libc;
+
+    pub(super) const KEEPCAPS_ON: u8 = 1;
+    // 只保留 CAP_NET_RAW + CAP_NET_ADMIN，并关闭 keepcaps
+    pub(super) const RESTRICT: u8 = 2;
+
+    static STEP: AtomicU8 = AtomicU8::new(0);
+    static DONE: AtomicUsize = AtomicUsize::new(0);
+    static FAILED: AtomicBool = AtomicBool::new(false);
+
+    // 只调用 prctl/capset 系统调用，可以在信号处理函数中执行
+    pub(super) fn apply(step: u8) -> bool {
+        match step {
+            KEEPCAPS_ON => unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1 as libc::c_ulong, 0, 0, 0) == 0 },
+            RESTRICT => {
+                const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+                let mask = (1u32 << super::CAP_NET_RAW) | (1u32 << super::CAP_NET_ADMIN);
+                let mut header = super::CapHeader { version: LINUX_CAPABILITY_VERSION_3, pid: 0 };
+                let data = [super::CapData { effective: mask, permitted: mask, inheritable: 0 }, super::CapData { effective: 0, permitted: 0, inheritable: 0 }];
+                let capset = unsafe { libc::syscall(libc::SYS_capset, &mut header as *mut super::CapHeader, data.as_ptr()) };
+                capset == 0 && unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 0 as libc::c_ulong, 0, 0, 0) == 0 }
+            }
+            _ => true,
+        }
+    }
+
+    extern "C" fn on_signal(_: libc::c_int) {
+        if !apply(STEP.load(Ordering::SeqCst)) { FAILED.store(true, Ordering::SeqCst); }
+        DONE.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn tids() -> Result<Vec<i32>> {
+        let mut out = Vec::new();
+        for e in std::fs::read_dir("/proc/self/task")? {
+            if let Some(tid) = e?.file_name().to_str().and_then(|s| s.parse().ok()) { out.push(tid); }
+        }
+        Ok(out)
+    }
+
+    // 在当前线程和其他所有线程上执行 `step`；期间新建的线程继承创建者的状态，再扫一遍直到没有遗漏
+    pub(super) fn on_all(step: u8) -> Result<()> {
+        if !apply(step) { anyhow::bail!("{}", std::io::Error::last_os_error()); }
+        let sig = libc::SIGRTMIN();
+        STEP.store(step, Ordering::SeqCst);
+        FAILED.store(false, Ordering::SeqCst);
+        let action = libc::sigaction { sa_sigaction: on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t, sa_mask: unsafe { std::mem::zeroed() }, sa_flags: libc::SA_RESTART, sa_restorer: None };
+        let mut old: libc::sigaction = unsafe { std::mem::zeroed() };
+        if unsafe { libc::sigaction(sig, &action, &mut old) } != 0 { anyhow::bail!("sigaction failed: {}", std::io::Error::last_os_error()); }
+        let result = signal_all(sig);
+        unsafe { libc::sigaction(sig, &old, std::ptr::null_mut()) };
+        result?;
+        if FAILED.load(Ordering::SeqCst) { anyhow::bail!("failed on some threads"); }
+        Ok(())
+    }
+
+    fn signal_all(sig: libc::c_int) -> Result<()> {
+        let (pid, me) = (std::process::id() as libc::pid_t, unsafe { libc::gettid() });
+        let mut seen: HashSet<i32> = HashSet::from([me]);
+        loop {
+            let new: Vec<i32> = tids()?.into_iter().filter(|t| !seen.contains(t)).collect();
+            if new.is_empty() { return Ok(()); }
+            DONE.store(0, Ordering::SeqCst);
+            let mut sent = 0;
+            for &tid in &new {
+                seen.insert(tid);
+                // 已退出的线程返回 ESRCH，不用等它
+                if unsafe { libc::syscall(libc::SYS_tgkill, pid, tid, sig) } == 0 { sent += 1; }
+            }
+            let deadline = Instant::now() + Duration::from_secs(2);
+            while DONE.load(Ordering::SeqCst) < sent {
+                if Instant::now() > deadline { anyhow::bail!("{} thread(s) did not respond", sent - DONE.load(Ordering::SeqCst)); }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+}
+
+fn set_keepcaps() -> Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        threads::on_all(threads::KEEPCAPS_ON).map_err(|e| anyhow::anyhow!("prctl(PR_SET_KEEPCAPS) failed: {}", e))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        anyhow::bail!("--keep-caps is only supported on Linux")
+    }
+}
+
+// 已 setuid 的进程只保留 SO_BINDTODEVICE / SO_MARK 所需的 CAP_NET_RAW + CAP_NET_ADMIN
+#[cfg(target_os = "linux")]
+fn keep_net_caps() -> Result<()> {
+    threads::on_all(threads::RESTRICT).map_err(|e| anyhow::anyhow!("capset failed: {}", e))
+}
+
+pub(crate) fn drop_privileges(user: &str, group: Option<&str>, keep_caps: bool) -> Result<()> {
+    if !Uid::effective().is_root() {
+        anyhow::bail!("--user requires starting as root");
+    }
+    let u = User::from_name(user)?.ok_or_else(|| anyhow::anyhow!("unknown user {:?}", user))?;
+    let gid: Gid = match group {
+        Some(name) => Group::from_name(name)?.ok_or_else(|| anyhow::anyhow!("unknown group {:?}", name))?.gid,
+        None => u.gid,
+    };
+
+    // 先附加组（需要 root），再 gid，最后 uid
+    nix::unistd::setgroups(&[gid]).map_err(|e| anyhow::anyhow!("setgroups failed: {}", e))?;
+    nix::unistd::setgid(gid)?;
+    if keep_caps { set_keepcaps()?; }
+    nix::unistd::setuid(u.uid)?;
+    #[cfg(target_os = "linux")]
+    if keep_caps { keep_net_caps()?; }
+
+    if nix::unistd::setuid(Uid::from_raw(0)).is_ok() {
+        anyhow::bail!("privilege drop failed: able to regain root");
+    }
+    log_info(format!(
+        "dropped privileges to user={} uid={} gid={}{}",
+        user,
+        u.uid,
+        gid,
+        if keep_caps { " (kept CAP_NET_RAW, CAP_NET_ADMIN)" } else { "" }
+    ));
+    Ok(())
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    const CHILD_ENV: &str = "IFACE_PROXY_PRIVDROP_CHILD";
+
+    // 当前线程的有效 capability
+    fn effective_caps() -> u64 {
+        let status = std::fs::read_to_string("/proc/thread-self/status").unwrap();
+        let line = status.lines().find_map(|l| l.strip_prefix("CapEff:")).unwrap();
+        u64::from_str_radix(line.trim(), 16).unwrap()
+    }
+
+    // 降权作用于整个进程，放到子进程里做；非 root 时跳过
+    #[test]
+    fn keep_caps_reach_worker_threads() {
+        if !Uid::effective().is_root() || User::from_name("nobody").ok().flatten().is_none() { return; }
+        if std::env::var_os(CHILD_ENV).is_none() {
+            let out = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "privdrop::tests::keep_caps_reach_worker_threads", "--test-threads=1", "--nocapture"])
+                .env(CHILD_ENV, "1")
+                .output()
+                .unwrap();
+            assert!(out.status.success(), "{}{}", String::from_utf8_lossy(&out.stdout), String::from_utf8_lossy(&out.stderr));
+            return;
+        }
+        let rt = tokio::runtime::Builder::new_multi_thread().worker_threads(4).enable_all().build().unwrap();
+        rt.block_on(async {
+            // 降权前就已存在的阻塞线程与工作线程
+            tokio::task::spawn_blocking(|| ()).await.unwrap();
+            drop_privileges("nobody", None, true).unwrap();
+            assert!(!Uid::effective().is_root());
+            let net_admin = 1u64 << CAP_NET_ADMIN;
+            let workers = worker_caps(8).await;
+            assert!(workers.iter().all(|c| c & net_admin != 0), "{:x?}", workers);
+            let blocking = tokio::task::spawn_blocking(effective_caps).await.unwrap();
+            assert_eq!(blocking, net_admin | (1u64 << CAP_NET_RAW));
+        });
+    }
+
+    async fn worker_caps(n: usize) -> Vec<u64> {
+        let tasks: Vec<_> = (0..n).map(|_| tokio::spawn(async { effective_caps() })).collect();
+        let mut out = Vec::new();
+        for t in tasks { out.push(t.await.unwrap()); }
+        out
+    }
+}
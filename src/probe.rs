@@ -0,0 +1,152 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use tokio::time::{timeout, Duration};
+
+use crate::util::{connect_outbound, log_info, log_throttled};
+
+// 出口探测：定时经每个配置的网卡向探测目标发起 TCP 连接，记录建连耗时与失败比例（最近 N 次），
+// 通过 /metrics 与管理接口 /probes 查看
+const CONNECT_TIMEOUT_MS: u64 = 3000;
+
+pub(crate) struct ProbeConfig {
+    pub(crate) targets: Vec<String>,
+    pub(crate) ifaces: Vec<String>,
+    pub(crate) interval_secs: u64,
+    pub(crate) window: usize,
+}
+
+#[derive(Default)]
+struct Samples {
+    // None 表示该次失败
+    recent: VecDeque<Option<u64>>,
+    last_error: Option<String>,
+}
+
+impl Samples {
+    fn push(&mut self, sample: Option<u64>, window: usize) {
+        if self.recent.len() >= window { self.recent.pop_front(); }
+        self.recent.push_back(sample);
+    }
+
+    fn loss(&self) -> f64 {
+        if self.recent.is_empty() { return 0.0; }
+        self.recent.iter().filter(|s| s.is_none()).count() as f64 / self.recent.len() as f64
+    }
+
+    // 成功样本的平均建连耗时
+    fn avg_ms(&self) -> Option<u64> {
+        let ok: Vec<u64> = self.recent.iter().flatten().copied().collect();
+        if ok.is_empty() { None } else { Some(ok.iter().sum::<u64>() / ok.len() as u64) }
+    }
+
+    fn last_ms(&self) -> Option<u64> {
+        self.recent.back().copied().flatten()
+    }
+}
+
+// (网卡, 目标) -> 样本
+fn table() -> &'static Mutex<HashMap<(String, String), Samples>> {
+    static TABLE: OnceLock<Mutex<HashMap<(String, String), Samples>>> = OnceLock::new();
+    TABLE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// 先解析再计时，耗时只包含 TCP 建连
+async fn probe_once(target: &str, iface: &str) -> Result<u64, String> {
+    let addr = crate::hosts::resolve_target(target)
+        .await
+        .map_err(|e| format!("resolve: {}", e))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| String::from("resolve: no addresses"))?;
+    let started = Instant::now();
+    match timeout(Duration::from_millis(CONNECT_TIMEOUT_MS), connect_outbound(&addr.ip().to_string(), addr.port(), iface, &[])).await {
+        Ok(Ok(_)) => Ok(started.elapsed().as_millis() as u64),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!("connect timed out after {}ms", CONNECT_TIMEOUT_MS)),
+    }
+}
+
+pub(crate) async fn run_probes(cfg: ProbeConfig) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(cfg.interval_secs.max(1)));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        // 同一轮的探测并发进行，避免一个超时的网卡拖慢其他网卡的采样
+        let mut round = Vec::new();
+        for iface in &cfg.ifaces {
+            for target in &cfg.targets {
+                let (iface, target) = (iface.clone(), target.clone());
+                round.push(tokio::spawn(async move {
+                    let res = probe_once(&target, &iface).await;
+                    (iface, target, res)
+                }));
+            }
+        }
+        let mut results = Vec::with_capacity(round.len());
+        for h in round {
+            if let Ok(v) = h.await { results.push(v); }
+        }
+        let mut t = table().lock().unwrap_or_else(|e| e.into_inner());
+        for (iface, target, res) in results {
+            let s = t.entry((iface.clone(), target.clone())).or_default();
+            match res {
+                Ok(ms) => {
+                    s.push(Some(ms), cfg.window);
+                    s.last_error = None;
+                }
+                Err(e) => {
+                    log_throttled(|| log_info(format!("probe {} via {} failed: {}", target, iface, e)));
+                    s.push(None, cfg.window);
+                    s.last_error = Some(e);
+                }
+            }
+        }
+    }
+}
+
+fn sorted_keys(t: &HashMap<(String, String), Samples>) -> Vec<(String, String)> {
+    let mut keys: Vec<(String, String)> = t.keys().cloned().collect();
+    keys.sort();
+    keys
+}
+
+pub(crate) fn render() -> String {
+    let t = table().lock().unwrap_or_else(|e| e.into_inner());
+    let mut out = format!("{:<12} {:<32} {:>8} {:>8} {:>7} {:>8}  {}\n", "iface", "target", "last_ms", "avg_ms", "loss", "samples", "last_error");
+    let ms = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_else(|| String::from("-"));
+    for key in sorted_keys(&t) {
+        let s = &t[&key];
+        out.push_str(&format!(
+            "{:<12} {:<32} {:>8} {:>8} {:>6.1}% {:>8}  {}\n",
+            key.0,
+            key.1,
+            ms(s.last_ms()),
+            ms(s.avg_ms()),
+            s.loss() * 100.0,
+            s.recent.len(),
+            s.last_error.as_deref().unwrap_or("-")
+        ));
+    }
+    out
+}
+
+pub(crate) fn render_metrics() -> String {
+    let t = table().lock().unwrap_or_else(|e| e.into_inner());
+    if t.is_empty() { return String::new(); }
+    let keys = sorted_keys(&t);
+    let label = |k: &(String, String)| format!("iface=\"{}\",target=\"{}\"", k.0, k.1);
+    let mut out = String::from("# HELP iface_proxy_probe_connect_ms Average TCP connect time to the probe target over the recent window.\n");
+    out.push_str("# TYPE iface_proxy_probe_connect_ms gauge\n");
+    for k in &keys {
+        if let Some(avg) = t[k].avg_ms() {
+            out.push_str(&format!("iface_proxy_probe_connect_ms{{{}}} {}\n", label(k), avg));
+        }
+    }
+    out.push_str("# HELP iface_proxy_probe_loss_ratio Share of failed probes over the recent window.\n");
+    out.push_str("# TYPE iface_proxy_probe_loss_ratio gauge\n");
+    for k in &keys {
+        out.push_str(&format!("iface_proxy_probe_loss_ratio{{{}}} {:.3}\n", label(k), t[k].loss()));
+    }
+    out
+}
@@ -0,0 +1,278 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::net::{TcpStream, UnixStream};
+
+use crate::util::{log_info, log_throttled};
+
+// 本机客户端所属的进程与用户：规则的 process:/uid: 条件与 --log-process 用。TCP 连接按两端地址在系统的 socket 表中
+// 找到客户端那一侧的 socket（Linux 的 /proc/net/tcp 同时给出属主 UID），再找持有它的进程（Linux 扫描 /proc，macOS 用 libproc）；
+// unix socket 直接取对端凭据。查进程要遍历进程表，只在启用时、且只对本机发起的连接查找
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static UIDS: AtomicBool = AtomicBool::new(false);
+
+// --log-process，或加载了带 process: 条件的规则
+pub(crate) fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub(crate) fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// --log-process，或加载了带 uid: 条件的规则；只读 socket 表，开销远小于查进程
+pub(crate) fn enable_uid() {
+    UIDS.store(true, Ordering::Relaxed);
+}
+
+pub(crate) fn uid_enabled() -> bool {
+    UIDS.load(Ordering::Relaxed)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Process {
+    pub(crate) pid: u32,
+    pub(crate) name: String,
+    // 可执行文件路径，取不到（如无权限）时为空
+    pub(crate) path: String,
+}
+
+// 会话开始时在 accept 循环里取得，进入会话后再查找
+pub(crate) enum Peer {
+    // (客户端地址, 监听地址)
+    Tcp(SocketAddr, SocketAddr),
+    // unix socket 对端的 (pid, uid)，pid 不是所有平台都能取到
+    Unix(Option<u32>, u32),
+    Unknown,
+}
+
+impl Peer {
+    pub(crate) fn tcp(s: &TcpStream) -> Self {
+        if !enabled() && !uid_enabled() { return Self::Unknown; }
+        match (s.peer_addr(), s.local_addr()) {
+            (Ok(peer), Ok(local)) if is_same_host(peer.ip(), local.ip()) => Self::Tcp(peer, local),
+            _ => Self::Unknown,
+        }
+    }
+
+    pub(crate) fn unix(s: &UnixStream) -> Self {
+        if !enabled() && !uid_enabled() { return Self::Unknown; }
+        match s.peer_cred() {
+            Ok(c) => Self::Unix(c.pid().filter(|pid| *pid > 0).map(|pid| pid as u32), c.uid()),
+            Err(_) => Self::Unknown,
+        }
+    }
+}
+
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        v4 => v4,
+    }
+}
+
+// 回环地址，或连到本机某个地址时源地址与目的地址相同
+fn is_same_host(peer: IpAddr, local: IpAddr) -> bool {
+    let peer = canonical(peer);
+    peer.is_loopback() || peer == canonical(local)
+}
+
+// 查找客户端的 UID 与进程并记入当前会话；查不到的不记录
+pub(crate) async fn record(peer: Peer) {
+    let want = enabled();
+    let found = match peer {
+        Peer::Unknown => return,
+        Peer::Unix(pid, uid) => tokio::task::spawn_blocking(move || (Some(uid), pid.filter(|_| want).and_then(describe))).await,
+        Peer::Tcp(peer, local) => tokio::task::spawn_blocking(move || owner(peer, local, want)).await,
+    };
+    let Ok((uid, process)) = found else { return };
+    let mut parts = Vec::new();
+    if let Some(uid) = uid { parts.push(format!("uid {}", uid)); }
+    if let Some(p) = &process { parts.push(format!("process {} (pid {}{}{})", p.name, p.pid, if p.path.is_empty() { "" } else { ", " }, p.path)); }
+    if parts.is_empty() { return; }
+    log_throttled(|| log_info(format!("client {}", parts.join(", "))));
+    crate::session::set_owner(uid, process);
+}
+
+// 客户端一侧 socket（本端为 `peer`、对端为 `local`）的属主 UID，以及 `want_process` 时持有它的进程
+#[cfg(target_os = "linux")]
+fn owner(peer: SocketAddr, local: SocketAddr, want_process: bool) -> (Option<u32>, Option<Process>) {
+    let Some((inode, uid)) = linux::socket_entry(peer, local) else { return (None, None) };
+    (Some(uid), linux::pid_holding(inode).filter(|_| want_process).and_then(describe))
+}
+
+#[cfg(target_os = "linux")]
+fn describe(pid: u32) -> Option<Process> {
+    let name = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?.trim_end().to_string();
+    let path = std::fs::read_link(format!("/proc/{}/exe", pid)).map(|p| p.to_string_lossy().into_owned()).unwrap_or_default();
+    Some(Process { pid, name, path })
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+    // /proc/net/tcp 中的地址：IPv4 为一个、IPv6 为四个按本机字节序打印的 32 位字，后接 `:` 与十六进制端口
+    pub(super) fn parse_addr(s: &str) -> Option<SocketAddr> {
+        let (ip, port) = s.split_once(':')?;
+        let port = u16::from_str_radix(port, 16).ok()?;
+        let word = |i: usize| ip.get(i * 8..i * 8 + 8).and_then(|w| u32::from_str_radix(w, 16).ok()).map(u32::to_ne_bytes);
+        let ip = match ip.len() {
+            8 => IpAddr::V4(Ipv4Addr::from(word(0)?)),
+            32 => {
+                let mut b = [0u8; 16];
+                for i in 0..4 { b[i * 4..i * 4 + 4].copy_from_slice(&word(i)?); }
+                IpAddr::V6(Ipv6Addr::from(b))
+            }
+            _ => return None,
+        };
+        Some(SocketAddr::new(ip, port))
+    }
+
+    fn same(a: SocketAddr, b: SocketAddr) -> bool {
+        a.port() == b.port() && super::canonical(a.ip()) == super::canonical(b.ip())
+    }
+
+    // 本端为 `peer`、对端为 `local` 的 TCP socket 的 (inode, 属主 UID)；IPv4 客户端连到双栈监听时也在 tcp 表中
+    pub(super) fn socket_entry(peer: SocketAddr, local: SocketAddr) -> Option<(u64, u32)> {
+        ["/proc/net/tcp", "/proc/net/tcp6"].iter().find_map(|path| {
+            let table = std::fs::read_to_string(path).ok()?;
+            table.lines().skip(1).find_map(|line| {
+                let f: Vec<&str> = line.split_whitespace().collect();
+                let (l, r) = (parse_addr(f.get(1)?)?, parse_addr(f.get(2)?)?);
+                if !(same(l, peer) && same(r, local)) { return None; }
+                let inode = f.get(9)?.parse().ok().filter(|inode| *inode != 0)?;
+                Some((inode, f.get(7)?.parse().ok()?))
+            })
+        })
+    }
+
+    // 只能看到有权限读取 /proc/PID/fd 的进程（非 root 时为同一用户的进程）
+    pub(super) fn pid_holding(inode: u64) -> Option<u32> {
+        let target = format!("socket:[{}]", inode);
+        std::fs::read_dir("/proc").ok()?.flatten().find_map(|entry| {
+            let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+            let fds = std::fs::read_dir(entry.path().join("fd")).ok()?;
+            fds.flatten().any(|fd| std::fs::read_link(fd.path()).is_ok_and(|l| l.as_os_str() == target.as_str())).then_some(pid)
+        })
+    }
+}
+
+// socket 属主只能经进程查到，macOS 上不提供 UID
+#[cfg(target_os = "macos")]
+fn owner(peer: SocketAddr, local: SocketAddr, want_process: bool) -> (Option<u32>, Option<Process>) {
+    if !want_process { return (None, None); }
+    (None, macos::pid_holding(peer, local).and_then(describe))
+}
+
+#[cfg(target_os = "macos")]
+fn describe(pid: u32) -> Option<Process> {
+    macos::describe(pid)
+}
+
+#[cfg(target_os = "macos")]
+mod macos {
+    use nix::libc::{c_int, c_void, proc_fdinfo, proc_listallpids, proc_name, proc_pidfdinfo, proc_pidinfo, proc_pidpath, PROC_PIDLISTFDS, PROC_PIDPATHINFO_MAXSIZE, PROX_FDTYPE_SOCKET};
+    use std::net::SocketAddr;
+
+    use super::Process;
+
+    // <sys/proc_info.h>：PROC_PIDFDSOCKETINFO 返回的 struct socket_fdinfo 中用到的字段偏移，
+    // 即 proc_fileinfo（24 字节）之后 socket_info 的 soi_family、soi_kind 与 soi_proto.pri_in 的前两个字段
+    const PROC_PIDFDSOCKETINFO: c_int = 3;
+    const SOI_FAMILY: usize = 24 + 160;
+    const SOI_KIND: usize = 24 + 232;
+    const INSI_FPORT: usize = 24 + 240;
+    const INSI_LPORT: usize = 24 + 244;
+    const SOCKINFO_IN: i32 = 1;
+    const SOCKINFO_TCP: i32 = 2;
+
+    fn read_i32(buf: &[u64], offset: usize) -> i32 {
+        let bytes: &[u8] = unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u8, buf.len() * 8) };
+        i32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap_or_default())
+    }
+
+    // 端口以网络字节序存放在 int 的低 16 位
+    fn port(v: i32) -> u16 {
+        u16::from_be(v as u16)
+    }
+
+    fn fds(pid: c_int) -> Vec<proc_fdinfo> {
+        let size = std::mem::size_of::<proc_fdinfo>();
+        let mut buf: Vec<proc_fdinfo> = Vec::with_capacity(256);
+        loop {
+            let cap = buf.capacity();
+            let n = unsafe { proc_pidinfo(pid, PROC_PIDLISTFDS, 0, buf.as_mut_ptr() as *mut c_void, (cap * size) as c_int) };
+            if n <= 0 { return Vec::new(); }
+            let count = n as usize / size;
+            // 写满时可能还有更多，扩大后重取
+            if count < cap {
+                unsafe { buf.set_len(count) };
+                return buf;
+            }
+            buf.reserve(cap * 2);
+        }
+    }
+
+    pub(super) fn pid_holding(peer: SocketAddr, local: SocketAddr) -> Option<u32> {
+        let mut pids = vec![0 as c_int; 4096];
+        let n = unsafe { proc_listallpids(pids.as_mut_ptr() as *mut c_void, (pids.len() * std::mem::size_of::<c_int>()) as c_int) };
+        if n <= 0 { return None; }
+        let family = if peer.is_ipv4() { nix::libc::AF_INET } else { nix::libc::AF_INET6 };
+        // struct socket_fdinfo 约 800 字节，给足空间即可
+        let mut info = [0u64; 256];
+        pids.truncate(n as usize);
+        pids.into_iter().filter(|pid| *pid > 0).find_map(|pid| {
+            fds(pid).into_iter().filter(|fd| fd.proc_fdtype as c_int == PROX_FDTYPE_SOCKET).find_map(|fd| {
+                let got = unsafe { proc_pidfdinfo(pid, fd.proc_fd, PROC_PIDFDSOCKETINFO, info.as_mut_ptr() as *mut c_void, std::mem::size_of_val(&info) as c_int) };
+                if got <= INSI_LPORT as c_int { return None; }
+                let kind = read_i32(&info, SOI_KIND);
+                let tcp = (kind == SOCKINFO_IN || kind == SOCKINFO_TCP) && read_i32(&info, SOI_FAMILY) == family;
+                (tcp && port(read_i32(&info, INSI_LPORT)) == peer.port() && port(read_i32(&info, INSI_FPORT)) == local.port()).then_some(pid as u32)
+            })
+        })
+    }
+
+    pub(super) fn describe(pid: u32) -> Option<Process> {
+        let mut name = [0u8; 256];
+        let n = unsafe { proc_name(pid as c_int, name.as_mut_ptr() as *mut c_void, name.len() as u32) };
+        if n <= 0 { return None; }
+        let mut path = vec![0u8; PROC_PIDPATHINFO_MAXSIZE as usize];
+        let p = unsafe { proc_pidpath(pid as c_int, path.as_mut_ptr() as *mut c_void, path.len() as u32) };
+        path.truncate(p.max(0) as usize);
+        Some(Process { pid, name: String::from_utf8_lossy(&name[..n as usize]).into_owned(), path: String::from_utf8_lossy(&path).into_owned() })
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn owner(_peer: SocketAddr, _local: SocketAddr, _want_process: bool) -> (Option<u32>, Option<Process>) {
+    (None, None)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn describe(_pid: u32) -> Option<Process> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn finds_the_process_behind_a_loopback_connection() {
+        assert_eq!(linux::parse_addr("0100007F:1F90"), Some("127.0.0.1:8080".parse().unwrap()));
+        assert_eq!(linux::parse_addr("00000000000000000000000001000000:0050"), Some("[::1]:80".parse().unwrap()));
+        assert!(is_same_host("::ffff:127.0.0.1".parse().unwrap(), "::1".parse().unwrap()));
+        assert!(!is_same_host("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap()));
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (_server, peer) = listener.accept().unwrap();
+        assert_eq!(peer, client.local_addr().unwrap());
+        let (uid, p) = owner(peer, listener.local_addr().unwrap(), true);
+        assert_eq!(uid, Some(nix::unistd::getuid().as_raw()));
+        let p = p.unwrap();
+        assert_eq!(p.pid, std::process::id());
+        assert!(!p.name.is_empty() && p.path.ends_with(&*std::env::current_exe().unwrap().file_name().unwrap().to_string_lossy()));
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use crate::util::local_date;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum QuotaPeriod {
+    Day,
+    Month,
+}
+
+impl QuotaPeriod {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Month => "month",
+        }
+    }
+
+    // 周期标识（本地时区）：日为 YYYYMMDD，月为 YYYYMM；变化即进入新周期
+    fn current_key(self) -> u32 {
+        let (y, m, d) = local_date();
+        match self {
+            Self::Day => y as u32 * 10000 + m * 100 + d,
+            Self::Month => y as u32 * 100 + m,
+        }
+    }
+}
+
+// USER=SIZE/PERIOD，如 `alice=10GiB/month`、`*=500MiB/day`；`*` 匹配没有单独配额的用户
+#[derive(Clone)]
+pub(crate) struct UserQuota {
+    pub(crate) user: String,
+    pub(crate) limit: u64,
+    pub(crate) period: QuotaPeriod,
+}
+
+// 1024 进制：B, K/KB/KiB, M/MB/MiB, G/GB/GiB, T/TB/TiB
+pub(crate) fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let n: u64 = num.parse().map_err(|_| anyhow::anyhow!("invalid size {:?}", s))?;
+    let mul: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        _ => anyhow::bail!("invalid size unit in {:?}", s),
+    };
+    n.checked_mul(mul).ok_or_else(|| anyhow::anyhow!("size {:?} is too large", s))
+}
+
+impl UserQuota {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (user, rest) = s.split_once('=').ok_or_else(|| anyhow::anyhow!("invalid user quota {:?} (expected USER=SIZE/day|month)", s))?;
+        let (size, period) = rest.split_once('/').ok_or_else(|| anyhow::anyhow!("user quota {:?} is missing /day or /month", s))?;
+        let period = match period.trim().to_ascii_lowercase().as_str() {
+            "day" | "daily" => QuotaPeriod::Day,
+            "month" | "monthly" => QuotaPeriod::Month,
+            _ => anyhow::bail!("unknown quota period {:?} (expected day or month)", period),
+        };
+        let user = user.trim();
+        if user.is_empty() { anyhow::bail!("user quota {:?} has an empty user", s); }
+        Ok(Self { user: user.to_string(), limit: parse_size(size)?, period })
+    }
+}
+
+#[derive(Default)]
+struct Usage {
+    connections: u64,
+    bytes_up: u64,
+    bytes_down: u64,
+    period_key: u32,
+    period_bytes: u64,
+}
+
+static QUOTAS: OnceLock<Vec<UserQuota>> = OnceLock::new();
+
+fn usage() -> &'static Mutex<HashMap<String, Usage>> {
+    static USAGE: OnceLock<Mutex<HashMap<String, Usage>>> = OnceLock::new();
+    USAGE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub(crate) fn install(quotas: Vec<UserQuota>) {
+    let _ = QUOTAS.set(quotas);
+}
+
+fn quota_for(user: &str) -> Option<&'static UserQuota> {
+    let quotas = QUOTAS.get()?;
+    quotas.iter().find(|q| q.user == user).or_else(|| quotas.iter().find(|q| q.user == "*"))
+}
+
+// 当前周期已用字节；周期切换时清零
+fn period_used(u: &mut Usage, q: &UserQuota) -> u64 {
+    let key = q.period.current_key();
+    if u.period_key != key {
+        u.period_key = key;
+        u.period_bytes = 0;
+    }
+    u.period_bytes
+}
+
+// 认证通过后、建立出站连接前调用；超出配额时返回错误，由调用方回 403 / REP 失败
+pub(crate) fn check(user: &str) -> Result<()> {
+    let Some(q) = quota_for(user) else { return Ok(()) };
+    let mut map = usage().lock().unwrap_or_else(|e| e.into_inner());
+    let used = period_used(map.entry(user.to_string()).or_default(), q);
+    if used >= q.limit {
+        anyhow::bail!("user {} exceeded the {} quota ({} of {} bytes)", user, q.period.name(), used, q.limit);
+    }
+    Ok(())
+}
+
+pub(crate) fn record(user: &str, bytes_up: u64, bytes_down: u64) {
+    let mut map = usage().lock().unwrap_or_else(|e| e.into_inner());
+    let u = map.entry(user.to_string()).or_default();
+    if let Some(q) = quota_for(user) { period_used(u, q); }
+    u.connections += 1;
+    u.bytes_up += bytes_up;
+    u.bytes_down += bytes_down;
+    u.period_bytes += bytes_up + bytes_down;
+}
+
+pub(crate) fn render() -> String {
+    let mut map = usage().lock().unwrap_or_else(|e| e.into_inner());
+    let mut users: Vec<String> = map.keys().cloned().collect();
+    users.sort();
+    let mut out = format!("{:<24} {:>8} {:>14} {:>14} {}\n", "user", "conns", "bytes_up", "bytes_down", "quota");
+    for user in users {
+        let u = map.get_mut(&user).expect("user present");
+        let quota = match quota_for(&user) {
+            Some(q) => format!("{}/{} per {}", period_used(u, q), q.limit, q.period.name()),
+            None => String::from("-"),
+        };
+        out.push_str(&format!("{:<24} {:>8} {:>14} {:>14} {}\n", user, u.connections, u.bytes_up, u.bytes_down, quota));
+    }
+    out
+}
+
+// 清零指定用户（None 为全部）的用量，返回受影响的用户数
+pub(crate) fn reset(user: Option<&str>) -> usize {
+    let mut map = usage().lock().unwrap_or_else(|e| e.into_inner());
+    match user {
+        Some(u) => map.remove(u).map(|_| 1).unwrap_or(0),
+        None => {
+            let n = map.len();
+            map.clear();
+            n
+        }
+    }
+}
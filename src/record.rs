@@ -0,0 +1,221 @@
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::util::{log_error, log_info};
+
+// 会话录制：在内存中记下客户端连接上双向的原始字节（含代理握手），会话出错结束时写入文件，
+// 再用 replay 工具把客户端一侧重新发给代理，离线复现协议问题。与 capture 不同，录的是入站一侧
+pub(crate) struct RecordConfig {
+    pub(crate) dir: String,
+    // 目标主机后缀过滤，空表示全部（含未得到目标就失败的会话）
+    pub(crate) hosts: Vec<String>,
+    // 每个会话最多保留的字节数，超出部分丢弃并在文件中标记
+    pub(crate) max_bytes: usize,
+}
+
+static CONFIG: OnceLock<RecordConfig> = OnceLock::new();
+
+pub(crate) fn install(cfg: RecordConfig) {
+    let _ = CONFIG.set(cfg);
+}
+
+const MAGIC: &str = "iface-proxy recording 1";
+
+// 一段连续收到或发出的数据
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    // true 为客户端发给代理，false 为代理发给客户端
+    pub from_client: bool,
+    // 距会话开始的毫秒数
+    pub at_ms: u32,
+    pub data: Vec<u8>,
+}
+
+// 录制文件：文本头（首行为 MAGIC，之后每行 `key: value`，空行结束），随后为帧序列，
+// 每帧为方向（`C`/`S`）、u32 毫秒偏移与 u32 长度（大端）加数据
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Recording {
+    // 监听类型（http、socks5）
+    pub kind: String,
+    pub peer: String,
+    // 会话的目标 host:port，未得到目标时为空
+    pub target: String,
+    // 超出 --record-max-bytes，后面的数据没有录下
+    pub truncated: bool,
+    pub frames: Vec<Frame>,
+}
+
+impl Recording {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = format!(
+            "{}\nkind: {}\npeer: {}\ntarget: {}\ntruncated: {}\n\n",
+            MAGIC, self.kind, self.peer, self.target, if self.truncated { "yes" } else { "no" }
+        )
+        .into_bytes();
+        for f in &self.frames {
+            out.push(if f.from_client { b'C' } else { b'S' });
+            out.extend_from_slice(&f.at_ms.to_be_bytes());
+            out.extend_from_slice(&(f.data.len() as u32).to_be_bytes());
+            out.extend_from_slice(&f.data);
+        }
+        out
+    }
+
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        let end = bytes.windows(2).position(|w| w == b"\n\n").ok_or_else(|| anyhow::anyhow!("not a recording (missing header)"))?;
+        let header = std::str::from_utf8(&bytes[..end]).map_err(|_| anyhow::anyhow!("not a recording (header is not UTF-8)"))?;
+        let mut lines = header.lines();
+        if lines.next() != Some(MAGIC) { anyhow::bail!("not a recording (expected {:?})", MAGIC); }
+        let mut rec = Recording::default();
+        for line in lines {
+            let (key, value) = line.split_once(": ").unwrap_or((line, ""));
+            match key {
+                "kind" => rec.kind = value.to_string(),
+                "peer" => rec.peer = value.to_string(),
+                "target" => rec.target = value.to_string(),
+                "truncated" => rec.truncated = value == "yes",
+                // 新版本可能增加的字段
+                _ => {}
+            }
+        }
+        let mut rest = &bytes[end + 2..];
+        while !rest.is_empty() {
+            if rest.len() < 9 { anyhow::bail!("truncated frame header"); }
+            let from_client = match rest[0] {
+                b'C' => true,
+                b'S' => false,
+                b => anyhow::bail!("bad frame direction 0x{:02x}", b),
+            };
+            let at_ms = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]);
+            let len = u32::from_be_bytes([rest[5], rest[6], rest[7], rest[8]]) as usize;
+            let data = rest.get(9..9 + len).ok_or_else(|| anyhow::anyhow!("truncated frame data"))?;
+            rec.frames.push(Frame { from_client, at_ms, data: data.to_vec() });
+            rest = &rest[9 + len..];
+        }
+        Ok(rec)
+    }
+
+    // 某一方向的全部字节
+    pub fn stream(&self, from_client: bool) -> Vec<u8> {
+        self.frames.iter().filter(|f| f.from_client == from_client).flat_map(|f| f.data.iter().copied()).collect()
+    }
+}
+
+// 录制中的会话
+pub(crate) struct Tape {
+    started: Instant,
+    frames: Vec<Frame>,
+    bytes: usize,
+    truncated: bool,
+}
+
+impl Tape {
+    fn push(&mut self, from_client: bool, data: &[u8], max: usize) {
+        if self.truncated || data.is_empty() { return; }
+        let take = data.len().min(max.saturating_sub(self.bytes));
+        self.truncated = take < data.len();
+        if take == 0 { return; }
+        self.bytes += take;
+        let at_ms = self.started.elapsed().as_millis().min(u32::MAX as u128) as u32;
+        // 同方向、同一毫秒内的连续数据合为一帧
+        match self.frames.last_mut() {
+            Some(last) if last.from_client == from_client && last.at_ms == at_ms => last.data.extend_from_slice(&data[..take]),
+            _ => self.frames.push(Frame { from_client, at_ms, data: data[..take].to_vec() }),
+        }
+    }
+}
+
+// 会话开始时包住客户端连接；未开启录制时原样透传
+pub(crate) fn wrap<S>(inner: S) -> Recorded<S> {
+    let tape = CONFIG.get().map(|_| Arc::new(Mutex::new(Tape { started: Instant::now(), frames: Vec::new(), bytes: 0, truncated: false })));
+    if let Some(t) = &tape { crate::session::set_tape(t.clone()); }
+    Recorded { inner, tape }
+}
+
+fn host_matches(hosts: &[String], target: &str) -> bool {
+    if hosts.is_empty() { return true; }
+    let host = target.rsplit_once(':').map_or(target, |(h, _)| h).trim_start_matches('[').trim_end_matches(']');
+    !host.is_empty() && hosts.iter().any(|s| crate::util::host_has_suffix(host, s))
+}
+
+// 会话出错结束时由 session 调用
+pub(crate) fn save(id: u64, kind: &str, peer: &str, target: &str, tape: &Mutex<Tape>) {
+    let Some(cfg) = CONFIG.get() else { return };
+    if !host_matches(&cfg.hosts, target) { return; }
+    let tape = tape.lock().unwrap_or_else(|e| e.into_inner());
+    let rec = Recording { kind: kind.to_string(), peer: peer.to_string(), target: target.to_string(), truncated: tape.truncated, frames: tape.frames.clone() };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let name = if target.is_empty() { String::from("unknown") } else { target.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' }).collect() };
+    let path = std::path::Path::new(&cfg.dir).join(format!("{}-{}-{}.rec", now.as_millis(), id, name));
+    let res = std::fs::create_dir_all(&cfg.dir).and_then(|_| std::fs::File::create(&path)).and_then(|mut f| f.write_all(&rec.encode()));
+    match res {
+        Ok(()) => log_info(format!("record: session #{} ({} bytes) -> {}", id, tape.bytes, path.display())),
+        Err(e) => log_error(format!("record: failed to write {}: {}", path.display(), e)),
+    }
+}
+
+pub(crate) struct Recorded<S> {
+    inner: S,
+    tape: Option<Arc<Mutex<Tape>>>,
+}
+
+impl<S> Recorded<S> {
+    fn record(&self, from_client: bool, data: &[u8]) {
+        if let (Some(tape), Some(cfg)) = (&self.tape, CONFIG.get()) {
+            tape.lock().unwrap_or_else(|e| e.into_inner()).push(from_client, data, cfg.max_bytes);
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Recorded<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if buf.filled().len() > before { self.record(true, &buf.filled()[before..]); }
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Recorded<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res { self.record(false, &buf[..n]); }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tape_round_trips_through_a_file() {
+        let mut tape = Tape { started: Instant::now(), frames: Vec::new(), bytes: 0, truncated: false };
+        tape.push(true, b"CONNECT a:443 HTTP/1.1\r\n", 40);
+        tape.push(true, b"\r\n", 40);
+        tape.push(false, b"HTTP/1.1 502 Bad Gateway\r\n", 40);
+        assert!(tape.truncated);
+        let rec = Recording { kind: String::from("http"), peer: String::from("127.0.0.1:5000"), target: String::from("a:443"), truncated: true, frames: tape.frames };
+        let parsed = Recording::parse(&rec.encode()).unwrap();
+        assert_eq!(parsed, rec);
+        assert_eq!(parsed.stream(true), b"CONNECT a:443 HTTP/1.1\r\n\r\n");
+        assert_eq!(parsed.stream(false), b"HTTP/1.1 502 B");
+        assert!(Recording::parse(b"GET / HTTP/1.1\r\n\r\n").is_err());
+
+        assert!(host_matches(&[String::from("example.com")], "www.example.com:443"));
+        assert!(!host_matches(&[String::from("example.com")], ""));
+        assert!(host_matches(&[], ""));
+    }
+}
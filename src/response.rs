@@ -0,0 +1,339 @@
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// 明文 HTTP 路径上解析上游发给客户端的应答：状态行、头部与 body 边界
+// （Content-Length / chunked / 读到关闭）。Framer 只切分、不改写；
+// ResponseWatch 据此在每个应答结束时回调一次，供访问日志使用，keep-alive 复用也据此判断应答何时结束
+const MAX_HEAD: usize = 64 * 1024;
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Transaction {
+    pub(crate) status: u16,
+    pub(crate) head_bytes: u64,
+    pub(crate) body_bytes: u64,
+    // false：body 未按声明的长度/分块收完连接就断了
+    pub(crate) complete: bool,
+}
+
+#[derive(Debug)]
+enum State {
+    Head(Vec<u8>),
+    Length(u64),
+    ChunkSize(Vec<u8>),
+    ChunkData(u64),
+    // 每块数据后的 CRLF，值为还需跳过的字节数
+    ChunkEnd(u8),
+    Trailer(Vec<u8>),
+    UntilClose,
+    // 101 切换协议或无法解析：之后不再是 HTTP，只透传
+    Opaque,
+}
+
+// Framer::feed 按到达顺序产生的片段；各片段拼起来即为原始字节流
+pub(crate) enum Event<'a> {
+    // 完整的应答头（含结尾空行），has_body 为 false 时没有 body（HEAD、204、304、1xx）
+    Head { head: &'a [u8], has_body: bool },
+    // body 中的实际内容（已去掉分块格式）
+    Data(&'a [u8]),
+    // 分块格式本身：块大小行、块尾 CRLF、trailer
+    Framing(&'a [u8]),
+    // 不属于任何可解析应答的字节
+    Raw(&'a [u8]),
+    // 一个应答结束
+    End(&'a Transaction),
+}
+
+pub(crate) struct Framer {
+    state: State,
+    cur: Transaction,
+    // 对应请求是否为 HEAD（应答没有 body），按发出顺序排队；未知时按非 HEAD 处理
+    head_requests: std::collections::VecDeque<bool>,
+}
+
+// 1xx..5xx 应答计数（/metrics）
+static RESPONSES: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+
+impl Framer {
+    pub(crate) fn new(first_method: &str) -> Self {
+        Self {
+            state: State::Head(Vec::new()),
+            cur: Transaction::default(),
+            head_requests: [first_method.eq_ignore_ascii_case("HEAD")].into(),
+        }
+    }
+
+    // 连接结束时调用：读到关闭的 body 在此结束，其余未完成的应答记为不完整
+    pub(crate) fn finish(&mut self, emit: &mut impl FnMut(Event)) {
+        match std::mem::replace(&mut self.state, State::Opaque) {
+            State::UntilClose => self.complete(true, emit),
+            State::Head(buf) if buf.is_empty() => {}
+            State::Head(buf) if self.cur.status == 0 => emit(Event::Raw(&buf)),
+            State::Opaque => {}
+            _ => self.complete(false, emit),
+        }
+        self.state = State::Opaque;
+    }
+
+    fn complete(&mut self, complete: bool, emit: &mut impl FnMut(Event)) {
+        self.cur.complete = complete;
+        if (100..600).contains(&self.cur.status) {
+            RESPONSES[(self.cur.status / 100 - 1) as usize].fetch_add(1, Ordering::Relaxed);
+        }
+        emit(Event::End(&self.cur));
+        self.cur = Transaction::default();
+        self.head_requests.pop_front();
+        self.state = State::Head(Vec::new());
+    }
+
+    pub(crate) fn feed(&mut self, mut data: &[u8], emit: &mut impl FnMut(Event)) {
+        while !data.is_empty() {
+            match &mut self.state {
+                State::Opaque => {
+                    emit(Event::Raw(data));
+                    return;
+                }
+                State::Head(buf) => {
+                    let start = buf.len().saturating_sub(3);
+                    buf.extend_from_slice(data);
+                    let Some(end) = buf[start..].windows(4).position(|w| w == b"\r\n\r\n").map(|i| start + i + 4) else {
+                        if buf.len() > MAX_HEAD {
+                            emit(Event::Raw(buf));
+                            self.state = State::Opaque;
+                        }
+                        return;
+                    };
+                    let used = end - (buf.len() - data.len());
+                    let head = std::mem::take(buf);
+                    data = &data[used..];
+                    self.cur.head_bytes += end as u64;
+                    self.on_head(&head[..end], emit);
+                }
+                State::Length(left) => {
+                    let n = (*left).min(data.len() as u64);
+                    *left -= n;
+                    self.cur.body_bytes += n;
+                    emit(Event::Data(&data[..n as usize]));
+                    data = &data[n as usize..];
+                    if *left == 0 { self.complete(true, emit); }
+                }
+                State::UntilClose => {
+                    self.cur.body_bytes += data.len() as u64;
+                    emit(Event::Data(data));
+                    return;
+                }
+                State::ChunkSize(line) => {
+                    self.cur.body_bytes += data.len().min(data.iter().position(|&b| b == b'\n').map_or(usize::MAX, |p| p + 1)) as u64;
+                    let Some(pos) = data.iter().position(|&b| b == b'\n') else {
+                        line.extend_from_slice(data);
+                        emit(Event::Framing(data));
+                        if line.len() > 1024 { self.state = State::Opaque; }
+                        return;
+                    };
+                    line.extend_from_slice(&data[..=pos]);
+                    emit(Event::Framing(&data[..=pos]));
+                    data = &data[pos + 1..];
+                    let text = String::from_utf8_lossy(line);
+                    let size = text.trim().split(';').next().and_then(|h| u64::from_str_radix(h.trim(), 16).ok());
+                    self.state = match size {
+                        Some(0) => State::Trailer(Vec::new()),
+                        Some(n) => State::ChunkData(n),
+                        None => State::Opaque,
+                    };
+                }
+                State::ChunkData(left) => {
+                    let n = (*left).min(data.len() as u64);
+                    *left -= n;
+                    self.cur.body_bytes += n;
+                    emit(Event::Data(&data[..n as usize]));
+                    data = &data[n as usize..];
+                    if *left == 0 { self.state = State::ChunkEnd(2); }
+                }
+                State::ChunkEnd(left) => {
+                    let n = (*left as usize).min(data.len());
+                    *left -= n as u8;
+                    self.cur.body_bytes += n as u64;
+                    emit(Event::Framing(&data[..n]));
+                    data = &data[n..];
+                    if *left == 0 { self.state = State::ChunkSize(Vec::new()); }
+                }
+                State::Trailer(line) => {
+                    let Some(pos) = data.iter().position(|&b| b == b'\n') else {
+                        line.extend_from_slice(data);
+                        self.cur.body_bytes += data.len() as u64;
+                        emit(Event::Framing(data));
+                        return;
+                    };
+                    let empty = line.iter().chain(&data[..pos]).all(|&b| b == b'\r');
+                    line.clear();
+                    self.cur.body_bytes += pos as u64 + 1;
+                    emit(Event::Framing(&data[..=pos]));
+                    data = &data[pos + 1..];
+                    // 空行结束整个分块 body
+                    if empty { self.complete(true, emit); }
+                }
+            }
+        }
+    }
+
+    fn on_head(&mut self, head: &[u8], emit: &mut impl FnMut(Event)) {
+        let text = String::from_utf8_lossy(head);
+        let mut lines = text.split("\r\n");
+        let status = lines.next().and_then(|l| {
+            let mut parts = l.split_whitespace();
+            parts.next().filter(|v| v.starts_with("HTTP/"))?;
+            parts.next()?.parse::<u16>().ok()
+        });
+        let Some(status) = status else {
+            emit(Event::Raw(head));
+            self.state = State::Opaque;
+            return;
+        };
+        self.cur.status = status;
+        let mut length = None;
+        let mut chunked = false;
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else { continue };
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<u64>().ok();
+            } else if name.trim().eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.rsplit(',').next().is_some_and(|v| v.trim().eq_ignore_ascii_case("chunked"));
+            }
+        }
+        let head_request = self.head_requests.front().copied().unwrap_or(false);
+        self.state = match status {
+            101 => State::Opaque,
+            // 100 Continue 等临时应答：最终应答还在后面
+            100..=199 => State::Head(Vec::new()),
+            204 | 304 => State::Length(0),
+            _ if head_request => State::Length(0),
+            _ if chunked => State::ChunkSize(Vec::new()),
+            _ => match length {
+                Some(n) => State::Length(n),
+                None => State::UntilClose,
+            },
+        };
+        let has_body = !matches!(self.state, State::Opaque | State::Head(_) | State::Length(0));
+        emit(Event::Head { head, has_body });
+        match self.state {
+            State::Opaque => {
+                self.complete(true, emit);
+                self.state = State::Opaque;
+            }
+            State::Length(0) => self.complete(true, emit),
+            _ => {}
+        }
+    }
+}
+
+pub(crate) struct ResponseWatch<S, F: FnMut(&Transaction)> {
+    inner: S,
+    framer: Framer,
+    on_done: F,
+}
+
+impl<S, F: FnMut(&Transaction)> ResponseWatch<S, F> {
+    pub(crate) fn new(inner: S, first_method: &str, on_done: F) -> Self {
+        Self { inner, framer: Framer::new(first_method), on_done }
+    }
+
+    pub(crate) fn finish(&mut self) {
+        let on_done = &mut self.on_done;
+        self.framer.finish(&mut |e| if let Event::End(t) = e { on_done(t) });
+    }
+
+    fn observe(&mut self, data: &[u8]) {
+        let on_done = &mut self.on_done;
+        self.framer.feed(data, &mut |e| if let Event::End(t) = e { on_done(t) });
+    }
+}
+
+impl<S: AsyncRead + Unpin, F: FnMut(&Transaction) + Unpin> AsyncRead for ResponseWatch<S, F> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin, F: FnMut(&Transaction) + Unpin> AsyncWrite for ResponseWatch<S, F> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            self.observe(&buf[..n]);
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+pub(crate) fn render_metrics() -> String {
+    let mut out = String::from("# HELP iface_proxy_http_responses_total Plain HTTP responses relayed to clients, by status class.\n");
+    out.push_str("# TYPE iface_proxy_http_responses_total counter\n");
+    for (i, n) in RESPONSES.iter().enumerate() {
+        out.push_str(&format!("iface_proxy_http_responses_total{{class=\"{}xx\"}} {}\n", i + 1, n.load(Ordering::Relaxed)));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 逐字节喂入，覆盖任意切分位置；返回 (状态码, body 字节数, 是否完整)
+    fn watch(method: &str, data: &[u8], close: bool) -> Vec<(u16, u64, bool)> {
+        let mut seen = Vec::new();
+        let mut w = ResponseWatch::new((), method, |t: &Transaction| seen.push((t.status, t.body_bytes, t.complete)));
+        for b in data.chunks(1) {
+            w.observe(b);
+        }
+        if close { w.finish(); }
+        drop(w);
+        seen
+    }
+
+    #[test]
+    fn content_length_responses_back_to_back() {
+        let data = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhelloHTTP/1.1 404 Not Found\r\ncontent-length: 2\r\n\r\nno";
+        assert_eq!(watch("GET", data, false), vec![(200, 5, true), (404, 2, true)]);
+    }
+
+    #[test]
+    fn chunked_body_with_trailer() {
+        let data = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n0\r\nX-Sum: 1\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n";
+        let got = watch("GET", data, false);
+        assert_eq!(got.len(), 2);
+        assert_eq!((got[0].0, got[0].2), (200, true));
+        assert_eq!(got[0].1, (b"5;ext=1\r\nhello\r\n0\r\nX-Sum: 1\r\n\r\n").len() as u64);
+        assert_eq!(got[1], (204, 0, true));
+    }
+
+    #[test]
+    fn responses_without_body() {
+        assert_eq!(watch("HEAD", b"HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\n", false), vec![(200, 0, true)]);
+        assert_eq!(watch("GET", b"HTTP/1.1 304 Not Modified\r\nContent-Length: 10\r\n\r\n", false), vec![(304, 0, true)]);
+        // 100 Continue 不单独计一个应答
+        let data = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok";
+        assert_eq!(watch("POST", data, false), vec![(201, 2, true)]);
+    }
+
+    #[test]
+    fn body_until_close_and_truncated_bodies() {
+        assert_eq!(watch("GET", b"HTTP/1.0 200 OK\r\n\r\nabc", true), vec![(200, 3, true)]);
+        assert_eq!(watch("GET", b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nabc", true), vec![(200, 3, false)]);
+        assert_eq!(watch("GET", b"", true), vec![]);
+    }
+
+    #[test]
+    fn switching_protocols_stops_parsing() {
+        let data = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n\x81\x05hello";
+        assert_eq!(watch("GET", data, true), vec![(101, 0, true)]);
+        assert_eq!(watch("GET", b"SSH-2.0-OpenSSH\r\n\r\n", true), vec![]);
+    }
+}
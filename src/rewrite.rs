@@ -0,0 +1,120 @@
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+
+// 明文 HTTP 请求头改写，按声明顺序依次应用于转发给目标的请求
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum HeaderAction {
+    // 追加一行，不影响已有的同名头
+    Add,
+    // 替换所有同名头（没有则追加）
+    Set,
+    // 删除同名头；NAME 以 `*` 结尾时按前缀匹配
+    Remove,
+}
+
+// SUFFIX=ACTION:NAME[=VALUE]，如 `*=remove:X-Forwarded-For`、`api.example.com=set:X-Api-Key=abc`；
+// SUFFIX 匹配目标主机及其子域名，`*` 匹配全部
+#[derive(Clone, Debug)]
+pub(crate) struct HeaderRule {
+    pub(crate) suffix: String,
+    pub(crate) action: HeaderAction,
+    pub(crate) name: String,
+    pub(crate) value: String,
+}
+
+impl HeaderRule {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (suffix, rest) = s.split_once('=').ok_or_else(|| anyhow::anyhow!("invalid header rule {:?} (expected SUFFIX=ACTION:NAME[=VALUE])", s))?;
+        let (action, header) = rest.split_once(':').ok_or_else(|| anyhow::anyhow!("header rule {:?} is missing ACTION:", s))?;
+        let action = match action.trim().to_ascii_lowercase().as_str() {
+            "add" => HeaderAction::Add,
+            "set" => HeaderAction::Set,
+            "remove" | "del" => HeaderAction::Remove,
+            _ => anyhow::bail!("unknown header action {:?} (expected add, set or remove)", action),
+        };
+        let (name, value) = match header.split_once('=') {
+            Some((n, v)) => (n.trim(), Some(v.trim())),
+            None => (header.trim(), None),
+        };
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_*".contains(&b)) {
+            anyhow::bail!("header rule {:?} has an invalid header name", s);
+        }
+        if name.contains('*') && (action != HeaderAction::Remove || !name.ends_with('*') || name.matches('*').count() > 1) {
+            anyhow::bail!("header rule {:?}: `*` is only allowed at the end of a remove rule", s);
+        }
+        let value = match (action, value) {
+            (HeaderAction::Remove, None) => "",
+            (HeaderAction::Remove, Some(_)) => anyhow::bail!("header rule {:?}: remove takes no value", s),
+            (_, Some(v)) => v,
+            (_, None) => anyhow::bail!("header rule {:?} is missing =VALUE", s),
+        };
+        if value.contains(['\r', '\n']) { anyhow::bail!("header rule {:?}: value must not contain CR/LF", s); }
+        Ok(Self {
+            suffix: suffix.trim().trim_start_matches('.').to_ascii_lowercase(),
+            action,
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    fn matches_host(&self, host: &str) -> bool {
+        crate::util::host_has_suffix(host, &self.suffix)
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        match self.name.strip_suffix('*') {
+            Some(prefix) => name.len() >= prefix.len() && name[..prefix.len()].eq_ignore_ascii_case(prefix),
+            None => name.eq_ignore_ascii_case(&self.name),
+        }
+    }
+}
+
+pub(crate) struct RewriteConfig {
+    pub(crate) rules: Vec<HeaderRule>,
+    // 追加 `Via: 1.1 iface-proxy-<实例标识>`
+    pub(crate) via: bool,
+    // 追加 RFC 7239 `Forwarded: for=...;host=...;proto=http`
+    pub(crate) forwarded: bool,
+}
+
+static CONFIG: OnceLock<RewriteConfig> = OnceLock::new();
+
+pub(crate) fn install(cfg: RewriteConfig) {
+    let _ = CONFIG.set(cfg);
+}
+
+// RFC 7239 node：IPv6 需加方括号并整体加引号；unix socket 等没有地址时为 unknown
+fn forwarded_node(peer: Option<SocketAddr>) -> String {
+    match peer.map(|p| p.ip().to_canonical()) {
+        Some(std::net::IpAddr::V4(ip)) => ip.to_string(),
+        Some(std::net::IpAddr::V6(ip)) => format!("\"[{}]\"", ip),
+        None => String::from("unknown"),
+    }
+}
+
+// `headers` 为去掉请求行后的 (名称, 值) 列表，`peer` 为客户端地址
+pub(crate) fn apply(headers: &mut Vec<(String, String)>, host: &str, port: u16, peer: Option<SocketAddr>) {
+    let Some(cfg) = CONFIG.get() else { return };
+    for rule in cfg.rules.iter().filter(|r| r.matches_host(host)) {
+        match rule.action {
+            HeaderAction::Add => headers.push((rule.name.clone(), rule.value.clone())),
+            HeaderAction::Set => {
+                let mut seen = false;
+                headers.retain(|(n, _)| !rule.matches_name(n) || !std::mem::replace(&mut seen, true));
+                match headers.iter_mut().find(|(n, _)| rule.matches_name(n)) {
+                    Some(h) => h.1 = rule.value.clone(),
+                    None => headers.push((rule.name.clone(), rule.value.clone())),
+                }
+            }
+            HeaderAction::Remove => headers.retain(|(n, _)| !rule.matches_name(n)),
+        }
+    }
+    if cfg.via {
+        headers.push((String::from("Via"), format!("1.1 {}", crate::loopguard::via_pseudonym())));
+    }
+    if cfg.forwarded {
+        let authority = crate::uri::format_authority(host, port, 80);
+        headers.push((String::from("Forwarded"), format!("for={};host=\"{}\";proto=http", forwarded_node(peer), authority)));
+    }
+}
@@ -0,0 +1,270 @@
+use anyhow::Result;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+
+use crate::dialer::{DialFuture, DialRequest, Dialer, DirectDialer};
+use crate::addr::redact;
+use crate::error::{Denied, ProxyError};
+use crate::util::{log_error, log_info, log_throttled};
+
+// 路由钩子：为每个出站连接选择去向（经某网卡直连、经某上游、拦截、改写目标），
+// 不必把每种规则都做成命令行参数。内置实现为 --rule/--rules-file 规则与 --script Lua 脚本，
+// 嵌入方也可用 set_router 直接提供 Rust 实现
+
+// 待决定的连接
+pub struct Query<'a> {
+    // 客户端地址，unix socket 为 "unix"
+    pub client: &'a str,
+    // http、connect、socks5、socks4、ss、ws、tcp-forward
+    pub protocol: &'a str,
+    pub host: &'a str,
+    pub port: u16,
+    // CONNECT 时客户端随请求一起发来的 ClientHello 中的 SNI
+    pub sni: Option<&'a str>,
+    // 通过认证的用户名
+    pub user: Option<&'a str>,
+    // 本机客户端的属主 UID（Linux 的 TCP 与 unix socket，启用 uid 查找且查到时）
+    pub uid: Option<u32>,
+    // 本机客户端所属进程的名称与可执行文件路径（启用 process 查找且查到时）
+    pub process: Option<&'a str>,
+    pub process_path: Option<&'a str>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Route {
+    // 照常按 --upstream-rule 与会话的出口网卡处理
+    Default,
+    // 直连（不看 --upstream-rule），可指定出口网卡
+    Direct(Option<String>),
+    Upstream(String),
+    Block,
+}
+
+// 直连目标时的解析方式（经上游时由上游解析，不受影响）
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Resolve {
+    // 不解析，直接连这个地址
+    Addr(IpAddr),
+    // 向这台 DNS 服务器查询 A/AAAA；查询从指定网卡发出，未指定时用连接的出口网卡
+    Server { addr: SocketAddr, iface: Option<String> },
+}
+
+impl Resolve {
+    // SERVER[:PORT][@IFACE]，如 10.0.0.53@utun2、[fd00::53]:5353
+    pub(crate) fn parse_server(s: &str) -> Result<Self> {
+        let (server, iface) = match s.rsplit_once('@') {
+            Some((server, iface)) if !iface.is_empty() => (server, Some(iface.to_string())),
+            Some(_) => anyhow::bail!("invalid DNS server {:?}: empty interface after @", s),
+            None => (s, None),
+        };
+        Ok(Self::Server { addr: crate::dns::parse_dns_upstream(server)?, iface })
+    }
+}
+
+impl std::fmt::Display for Resolve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Addr(ip) => write!(f, "{}", ip),
+            Self::Server { addr, iface: None } => write!(f, "dns {}", addr),
+            Self::Server { addr, iface: Some(iface) } => write!(f, "dns {} via {}", addr, iface),
+        }
+    }
+}
+
+// 经上游连接时目标域名的解析位置；直连总在本机解析
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    // 在本机解析（静态映射、规则的 resolve:/dns: 与系统解析），把 IP 交给上游
+    Local,
+    // 把域名原样交给上游解析
+    Remote,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Decision {
+    pub route: Route,
+    // 改写后的目标主机与端口
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    // 覆盖（改写后的）目标主机的解析
+    pub resolve: Option<Resolve>,
+    // 经上游时目标域名在哪里解析，None 为交给上游
+    pub resolution: Option<Resolution>,
+}
+
+impl Decision {
+    pub fn new(route: Route) -> Self {
+        Self { route, host: None, port: None, resolve: None, resolution: None }
+    }
+}
+
+// 在协议处理器的线程上同步调用，应尽快返回；出错时记录日志并按 Route::Default 处理
+pub trait Router: Send + Sync {
+    fn route(&self, q: &Query<'_>) -> Result<Decision>;
+}
+
+static ROUTER: OnceLock<Box<dyn Router>> = OnceLock::new();
+
+// 设置路由钩子；须在 run_cli 之前设置，只能设置一次（与 --rule、--script 互斥）
+pub fn set_router(router: impl Router + 'static) -> Result<()> {
+    ROUTER.set(Box::new(router)).map_err(|_| anyhow::anyhow!("router is already set"))
+}
+
+pub(crate) fn enabled() -> bool {
+    ROUTER.get().is_some()
+}
+
+// 未设置路由钩子时原样返回
+pub(crate) fn wrap(inner: Arc<dyn Dialer>) -> Arc<dyn Dialer> {
+    if enabled() { Arc::new(RouteDialer { inner }) } else { inner }
+}
+
+// 在 Dialer 链最外层询问路由钩子
+struct RouteDialer {
+    inner: Arc<dyn Dialer>,
+}
+
+impl Dialer for RouteDialer {
+    fn dial<'a>(&'a self, req: DialRequest<'a>) -> DialFuture<'a> {
+        Box::pin(async move {
+            let Some(decision) = decide(req.host, req.port) else { return self.inner.dial(req).await };
+            let decision = match decision {
+                Ok(d) => d,
+                Err(e) => {
+                    log_throttled(|| log_error(format!("route {} failed, using default routing: {}", redact(req.host, req.port), e)));
+                    return self.inner.dial(req).await;
+                }
+            };
+            crate::session::set_routed(req.host, req.port, &decision);
+            let host = decision.host.as_deref().unwrap_or(req.host);
+            let port = decision.port.unwrap_or(req.port);
+            if decision.route != Route::Block && (host != req.host || port != req.port) {
+                log_throttled(|| log_info(format!("route: rewrite {} -> {}", redact(req.host, req.port), redact(host, port))));
+            }
+            if let (Some(resolve), false) = (&decision.resolve, decision.route == Route::Block) {
+                log_throttled(|| log_info(format!("route: resolve {} with {}", redact(host, port), resolve)));
+            }
+            match &decision.route {
+                Route::Default => scoped(&decision, host, req.iface, self.inner.dial(req.retarget(host, port, req.iface))).await,
+                Route::Direct(iface) => {
+                    let iface = iface.as_deref().unwrap_or(req.iface);
+                    log_throttled(|| log_info(format!("route: {} direct via {}", redact(host, port), iface)));
+                    scoped(&decision, host, iface, DirectDialer.dial(req.retarget(host, port, iface))).await
+                }
+                Route::Upstream(name) => scoped(&decision, host, req.iface, crate::upstream::dial_named(name, req.retarget(host, port, req.iface))).await,
+                Route::Block => Err(ProxyError::PolicyDenied(Denied::Route(redact(req.host, req.port))).into()),
+            }
+        })
+    }
+}
+
+// 以当前会话的客户端信息询问路由钩子；未设置时返回 None
+fn decide(host: &str, port: u16) -> Option<Result<Decision>> {
+    let router = ROUTER.get()?;
+    let client = crate::session::client();
+    let q = Query {
+        client: client.as_ref().map(|c| c.peer.as_str()).unwrap_or(""),
+        protocol: client.as_ref().map(|c| c.protocol).unwrap_or(""),
+        host,
+        port,
+        sni: client.as_ref().and_then(|c| c.sni.as_deref()),
+        user: client.as_ref().and_then(|c| c.user.as_deref()),
+        uid: client.as_ref().and_then(|c| c.uid),
+        process: client.as_ref().and_then(|c| c.process.as_ref()).map(|p| p.name.as_str()),
+        process_path: client.as_ref().and_then(|c| c.process.as_ref()).map(|p| p.path.as_str()),
+    };
+    Some(router.route(&q))
+}
+
+// 明文 HTTP 缓存命中时不会走到 dial：路由拦截或改写目标的请求既不用缓存应答也不写入缓存，
+// 否则放行时（或其他用户、进程）取回的内容会绕过 block 与 rewrite:
+pub(crate) fn cacheable(host: &str, port: u16) -> bool {
+    match decide(host, port) {
+        Some(Ok(d)) => d.route != Route::Block && d.host.as_deref().is_none_or(|h| h == host) && d.port.is_none_or(|p| p == port),
+        _ => true,
+    }
+}
+
+// 规则给出的解析方式与经上游时的解析位置，作用于 `fut` 内对 `host` 的解析
+async fn scoped<F: std::future::Future>(d: &Decision, host: &str, iface: &str, fut: F) -> F::Output {
+    crate::upstream::with_resolution(d.resolution, crate::hosts::with_override(host, d.resolve.clone(), iface, fut)).await
+}
+
+// ClientHello 中与路由有关的部分
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct ClientHello {
+    pub(crate) sni: Option<String>,
+    // 带 encrypted_client_hello 扩展时，明文 SNI 只是 ECH 的公开名字（如 CDN 的通用域名），不是真正的目标
+    pub(crate) ech: bool,
+}
+
+const EXT_SERVER_NAME: usize = 0x0000;
+const EXT_ECH: usize = 0xfe0d;
+
+// `buf` 须从记录头开始，不完整时返回 None
+pub(crate) fn parse_client_hello(buf: &[u8]) -> Option<ClientHello> {
+    fn take<'b>(buf: &mut &'b [u8], n: usize) -> Option<&'b [u8]> {
+        if buf.len() < n { return None; }
+        let (head, rest) = buf.split_at(n);
+        *buf = rest;
+        Some(head)
+    }
+    fn u16_at(buf: &mut &[u8]) -> Option<usize> {
+        take(buf, 2).map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+    }
+    let mut p = buf;
+    // 记录头：ContentType=handshake，握手类型=ClientHello
+    if take(&mut p, 5)?[0] != 0x16 || take(&mut p, 4)?[0] != 0x01 { return None; }
+    take(&mut p, 2 + 32)?;
+    let n = take(&mut p, 1)?[0] as usize;
+    take(&mut p, n)?;
+    let n = u16_at(&mut p)?;
+    take(&mut p, n)?;
+    let n = take(&mut p, 1)?[0] as usize;
+    take(&mut p, n)?;
+    let n = u16_at(&mut p)?;
+    let mut exts = take(&mut p, n)?;
+    let mut hello = ClientHello::default();
+    while !exts.is_empty() {
+        let kind = u16_at(&mut exts)?;
+        let n = u16_at(&mut exts)?;
+        let mut ext = take(&mut exts, n)?;
+        match kind {
+            EXT_ECH => hello.ech = true,
+            EXT_SERVER_NAME => {
+                u16_at(&mut ext)?;
+                if take(&mut ext, 1)?[0] != 0 { continue; }
+                let n = u16_at(&mut ext)?;
+                hello.sni = std::str::from_utf8(take(&mut ext, n)?).ok().map(|s| s.to_ascii_lowercase());
+            }
+            _ => {}
+        }
+    }
+    Some(hello)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sni_is_read_from_client_hello() {
+        let hello = crate::selftest::client_hello("Example.COM");
+        assert_eq!(parse_client_hello(&hello), Some(ClientHello { sni: Some(String::from("example.com")), ech: false }));
+        assert_eq!(parse_client_hello(&hello[..hello.len() - 10]), None);
+        assert_eq!(parse_client_hello(b"GET / HTTP/1.1\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn ech_extension_is_detected() {
+        // 在扩展末尾追加 encrypted_client_hello（outer，内容截短），并修正记录、握手与扩展块的长度
+        let mut hello = crate::selftest::client_hello("public.cdn.example");
+        let ech = [0xfe, 0x0d, 0x00, 0x01, 0x00];
+        hello.extend_from_slice(&ech);
+        let grow = |b: &mut [u8]| { let n = u16::from_be_bytes([b[0], b[1]]) + ech.len() as u16; b.copy_from_slice(&n.to_be_bytes()); };
+        grow(&mut hello[3..5]);
+        grow(&mut hello[7..9]);
+        grow(&mut hello[64..66]);
+        assert_eq!(parse_client_hello(&hello), Some(ClientHello { sni: Some(String::from("public.cdn.example")), ech: true }));
+    }
+}
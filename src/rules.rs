@@ -0,0 +1,710 @@
+use anyhow::Result;
+use regex::Regex;
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+
+use crate::router::{Decision, Query, Resolution, Resolve, Route, Router};
+use crate::ruleset::Imports;
+use crate::util::{log_error, log_info, log_throttled, Cidr};
+
+// --rule / --rules-file：按优先级排列的路由规则，每条为 `[priority=N] 条件... => 动作[,动作]`。
+// 同一规则的条件须全部满足，条件内逗号分隔的取值满足其一即可；优先级高的在前，相同时按声明顺序。
+// 去向（iface/direct/upstream/block/default）、改写（rewrite）、解析（resolve/dns）与经上游时的解析位置（local-dns/remote-dns）
+// 各取第一条给出它的命中规则，
+// 因此低优先级规则可以为高优先级规则补上另一项，如 `suffix:corp.example => rewrite:gw.corp.example` 与 `* => iface:en0`
+
+#[derive(Clone, Debug)]
+enum Matcher {
+    Domain(Vec<String>),
+    Suffix(Vec<String>),
+    Keyword(Vec<String>),
+    Regex(Regex),
+    Cidr(Vec<Cidr>),
+    Port(Vec<(u16, u16)>),
+    Protocol(Vec<String>),
+    User(Vec<String>),
+    // 本机客户端的属主 UID，用户名在解析规则时换成 UID
+    Uid(Vec<u32>),
+    // 客户端进程名（不区分大小写），含 `/` 的取值为可执行文件路径前缀
+    Process(Vec<String>),
+    // 本地时间的时段：days 为星期位图（bit0 为周一），ranges 为分钟区间 [start, end)，
+    // end 不大于 start 时跨午夜；ranges 为空表示全天
+    Time { days: u8, ranges: Vec<(u32, u32)> },
+}
+
+const ALL_DAYS: u8 = 0x7f;
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+// `mon-fri,sun`，区间可跨周末（如 fri-mon）；另有 weekdays、weekend
+fn parse_days(v: &str) -> Result<u8> {
+    let day = |s: &str| DAY_NAMES.iter().position(|d| s.eq_ignore_ascii_case(d)).ok_or_else(|| anyhow::anyhow!("invalid day {:?} (expected mon..sun, weekdays or weekend)", s));
+    let mut mask = 0u8;
+    for part in v.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        mask |= match part.to_ascii_lowercase().as_str() {
+            "weekdays" => 0x1f,
+            "weekend" => 0x60,
+            _ => {
+                let (lo, hi) = part.split_once('-').unwrap_or((part, part));
+                let (lo, hi) = (day(lo.trim())?, day(hi.trim())?);
+                (0..7).map(|i| (lo + i) % 7).take((hi + 7 - lo) % 7 + 1).fold(0, |m, d| m | 1 << d)
+            }
+        };
+    }
+    Ok(mask)
+}
+
+// 跨午夜区间在午夜之后的部分算作前一天的时段，如周五 22:00-02:00 包含周六凌晨 1 点
+fn in_schedule(days: u8, ranges: &[(u32, u32)], weekday: u32, minute: u32) -> bool {
+    let on = |d: u32| days & (1 << d) != 0;
+    if ranges.is_empty() { return on(weekday); }
+    ranges.iter().any(|&(start, end)| {
+        if start < end {
+            on(weekday) && (start..end).contains(&minute)
+        } else {
+            minute >= start && on(weekday) || minute < end && on((weekday + 6) % 7)
+        }
+    })
+}
+
+// 一次匹配用到的目标信息；时间只在有规则用到时才取
+struct Target<'a> {
+    q: &'a Query<'a>,
+    // 用于域名条件的名字：目标是 IP 字面量且有 SNI 时用 SNI
+    name: String,
+    ip: Option<IpAddr>,
+    clock: OnceCell<(u32, u32)>,
+}
+
+// 数字 UID 或本机用户名
+fn parse_uid(v: &str) -> Result<u32> {
+    if let Ok(uid) = v.parse() { return Ok(uid); }
+    let user = nix::unistd::User::from_name(v).map_err(|e| anyhow::anyhow!("cannot look up user {:?}: {}", v, e))?;
+    Ok(user.ok_or_else(|| anyhow::anyhow!("unknown user {:?}", v))?.uid.as_raw())
+}
+
+fn list(v: &str) -> Vec<String> {
+    v.split(',').map(|s| s.trim().trim_end_matches('.').to_ascii_lowercase()).filter(|s| !s.is_empty()).collect()
+}
+
+fn parse_minute(s: &str) -> Option<u32> {
+    let (h, m) = s.split_once(':')?;
+    let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+    (h < 24 && m < 60 || h == 24 && m == 0).then_some(h * 60 + m)
+}
+
+impl Matcher {
+    fn parse(token: &str) -> Result<Self> {
+        let (kind, v) = token.split_once(':').ok_or_else(|| anyhow::anyhow!("invalid condition {:?} (expected KIND:VALUE)", token))?;
+        let m = match kind {
+            "domain" => Matcher::Domain(list(v)),
+            "suffix" => Matcher::Suffix(list(v).into_iter().map(|s| s.trim_start_matches('.').to_string()).collect()),
+            "keyword" => Matcher::Keyword(list(v)),
+            // 正则里可能有逗号，整体作为一个表达式
+            "regex" => Matcher::Regex(Regex::new(v).map_err(|e| anyhow::anyhow!("invalid regex {:?}: {}", v, e))?),
+            "cidr" => Matcher::Cidr(v.split(',').map(Cidr::parse).collect::<Result<_>>()?),
+            "port" => Matcher::Port(v.split(',').map(|p| {
+                let (lo, hi) = p.split_once('-').unwrap_or((p, p));
+                match (lo.trim().parse::<u16>(), hi.trim().parse::<u16>()) {
+                    (Ok(lo), Ok(hi)) if lo <= hi => Ok((lo, hi)),
+                    _ => Err(anyhow::anyhow!("invalid port range {:?}", p)),
+                }
+            }).collect::<Result<_>>()?),
+            "protocol" => Matcher::Protocol(list(v)),
+            "user" => Matcher::User(v.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect()),
+            "time" => Matcher::Time { days: ALL_DAYS, ranges: v.split(',').map(|r| {
+                r.split_once('-')
+                    .and_then(|(a, b)| Some((parse_minute(a.trim())?, parse_minute(b.trim())?)))
+                    .ok_or_else(|| anyhow::anyhow!("invalid time range {:?} (expected HH:MM-HH:MM)", r))
+            }).collect::<Result<_>>()? },
+            "uid" => Matcher::Uid(v.split(',').map(str::trim).filter(|u| !u.is_empty()).map(parse_uid).collect::<Result<_>>()?),
+            // 规则按空白分词，路径中的空格写作 %20
+            "process" => Matcher::Process(v.split(',').filter(|p| !p.trim().is_empty()).map(|p| {
+                crate::uri::percent_decode(p.trim()).map(|p| p.to_lowercase()).map_err(|e| anyhow::anyhow!("invalid process {:?}: {}", p, e))
+            }).collect::<Result<_>>()?),
+            "day" => Matcher::Time { days: parse_days(v)?, ranges: Vec::new() },
+            _ => anyhow::bail!("unknown condition {:?} (expected domain, suffix, keyword, regex, cidr, port, protocol, user, uid, process, time or day)", kind),
+        };
+        let empty = match &m {
+            Matcher::Domain(v) | Matcher::Suffix(v) | Matcher::Keyword(v) | Matcher::Protocol(v) | Matcher::User(v) | Matcher::Process(v) => v.is_empty(),
+            Matcher::Uid(v) => v.is_empty(),
+            Matcher::Time { days, .. } => *days == 0,
+            _ => false,
+        };
+        if empty { anyhow::bail!("condition {:?} has no values", token); }
+        Ok(m)
+    }
+
+    fn matches(&self, t: &Target<'_>) -> bool {
+        let name = t.name.as_str();
+        match self {
+            Matcher::Domain(v) => v.iter().any(|d| d == name),
+            Matcher::Suffix(v) => v.iter().any(|s| name == s || name.strip_suffix(s.as_str()).is_some_and(|p| p.ends_with('.'))),
+            Matcher::Keyword(v) => v.iter().any(|k| name.contains(k.as_str())),
+            Matcher::Regex(re) => re.is_match(name),
+            Matcher::Cidr(v) => t.ip.is_some_and(|ip| v.iter().any(|c| c.contains(ip))),
+            Matcher::Port(v) => v.iter().any(|(lo, hi)| (*lo..=*hi).contains(&t.q.port)),
+            Matcher::Protocol(v) => v.iter().any(|p| p == t.q.protocol),
+            Matcher::User(v) => t.q.user.is_some_and(|u| v.iter().any(|x| x == u)),
+            Matcher::Uid(v) => t.q.uid.is_some_and(|u| v.contains(&u)),
+            Matcher::Process(v) => v.iter().any(|x| match x.contains('/') {
+                true => t.q.process_path.is_some_and(|p| p.to_lowercase().starts_with(x.as_str())),
+                false => t.q.process.is_some_and(|n| n.to_lowercase() == *x),
+            }),
+            Matcher::Time { days, ranges } => {
+                let (weekday, minute) = *t.clock.get_or_init(crate::util::local_clock);
+                in_schedule(*days, ranges, weekday, minute)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Rule {
+    priority: i32,
+    matchers: Vec<Matcher>,
+    route: Option<Route>,
+    rewrite: Option<(String, Option<u16>)>,
+    resolve: Option<Resolve>,
+    resolution: Option<Resolution>,
+    text: String,
+}
+
+impl Rule {
+    // 如 `priority=10 suffix:example.com port:443 => upstream:remote`
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let text = s.trim().to_string();
+        let (lhs, rhs) = text.split_once("=>").ok_or_else(|| anyhow::anyhow!("invalid rule {:?} (expected CONDITIONS => ACTIONS)", s))?;
+        let mut priority = 0;
+        let mut matchers = Vec::new();
+        for tok in lhs.split_whitespace() {
+            if let Some(p) = tok.strip_prefix("priority=") {
+                priority = p.parse().map_err(|_| anyhow::anyhow!("invalid priority {:?} in rule {:?}", p, s))?;
+            } else if tok != "*" {
+                matchers.push(Matcher::parse(tok).map_err(|e| anyhow::anyhow!("rule {:?}: {}", s, e))?);
+            }
+        }
+        let (mut route, mut rewrite, mut resolve, mut resolution) = (None, None, None, None);
+        for action in rhs.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+            let (slot_taken, r) = match action.split_once(':') {
+                None if action == "direct" => (route.is_some(), Some(Route::Direct(None))),
+                None if action == "block" => (route.is_some(), Some(Route::Block)),
+                None if action == "default" => (route.is_some(), Some(Route::Default)),
+                Some(("iface", v)) if !v.is_empty() => (route.is_some(), Some(Route::Direct(Some(v.to_string())))),
+                Some(("upstream", v)) if !v.is_empty() => (route.is_some(), Some(Route::Upstream(v.to_string()))),
+                Some(("rewrite", v)) => {
+                    if rewrite.is_some() { anyhow::bail!("rule {:?} has more than one rewrite", s); }
+                    let (host, port) = crate::uri::parse_authority(v, 0).map_err(|e| anyhow::anyhow!("rule {:?}: bad rewrite target: {}", s, e))?;
+                    rewrite = Some((host, (port != 0).then_some(port)));
+                    continue;
+                }
+                None if action == "local-dns" || action == "remote-dns" => {
+                    if resolution.is_some() { anyhow::bail!("rule {:?} has more than one of local-dns and remote-dns", s); }
+                    resolution = Some(if action == "local-dns" { Resolution::Local } else { Resolution::Remote });
+                    continue;
+                }
+                Some((kind @ ("resolve" | "dns"), v)) => {
+                    if resolve.is_some() { anyhow::bail!("rule {:?} has more than one of resolve and dns", s); }
+                    resolve = Some(match kind {
+                        "resolve" => Resolve::Addr(v.trim_start_matches('[').trim_end_matches(']').parse().map_err(|_| anyhow::anyhow!("rule {:?}: invalid address {:?} in resolve", s, v))?),
+                        _ => Resolve::parse_server(v).map_err(|e| anyhow::anyhow!("rule {:?}: {}", s, e))?,
+                    });
+                    continue;
+                }
+                _ => anyhow::bail!("rule {:?}: unknown action {:?} (expected iface:NAME, direct, upstream:NAME, block, default, rewrite:HOST[:PORT], resolve:IP, dns:SERVER[:PORT][@IFACE], local-dns or remote-dns)", s, action),
+            };
+            if slot_taken { anyhow::bail!("rule {:?} has more than one of iface, direct, upstream, block and default", s); }
+            route = r;
+        }
+        if route.is_none() && rewrite.is_none() && resolve.is_none() && resolution.is_none() { anyhow::bail!("rule {:?} has no action", s); }
+        Ok(Self { priority, matchers: merge_days(matchers), route, rewrite, resolve, resolution, text })
+    }
+
+    // 替换日志与 /rules 中显示的规则文本（导入的规则集合并后的规则用来源描述代替原文）
+    pub(crate) fn labeled(self, text: String) -> Self {
+        Self { text, ..self }
+    }
+}
+
+// 同一规则里的 day: 并入各 time: 条件，跨午夜时段才能按开始的那一天计算
+fn merge_days(mut matchers: Vec<Matcher>) -> Vec<Matcher> {
+    let has_ranges = |m: &Matcher| matches!(m, Matcher::Time { ranges, .. } if !ranges.is_empty());
+    if !matchers.iter().any(has_ranges) { return matchers; }
+    let mut days = ALL_DAYS;
+    matchers.retain(|m| match m {
+        Matcher::Time { days: d, ranges } if ranges.is_empty() => { days &= d; false }
+        _ => true,
+    });
+    for m in &mut matchers {
+        if let Matcher::Time { days: d, .. } = m { *d &= days; }
+    }
+    matchers
+}
+
+// 反向逐级的域名 trie（com -> example -> www），节点上记录以此结尾的 domain:/suffix: 规则
+#[derive(Default)]
+struct DomainTrie {
+    children: HashMap<String, DomainTrie>,
+    exact: Vec<usize>,
+    suffix: Vec<usize>,
+}
+
+impl DomainTrie {
+    fn insert(&mut self, domain: &str, rule: usize, suffix: bool) {
+        let node = domain.rsplit('.').fold(self, |node, label| node.children.entry(label.to_string()).or_default());
+        if suffix { node.suffix.push(rule) } else { node.exact.push(rule) }
+    }
+
+    fn lookup(&self, name: &str, out: &mut Vec<usize>) {
+        let mut node = self;
+        let mut labels = name.rsplit('.').peekable();
+        while let Some(label) = labels.next() {
+            let Some(next) = node.children.get(label) else { return };
+            node = next;
+            out.extend_from_slice(&node.suffix);
+            if labels.peek().is_none() { out.extend_from_slice(&node.exact); }
+        }
+    }
+}
+
+// 静态区间树：区间按起点排序后作为隐式平衡二叉树，max_end 为各子树的最大终点，
+// 查询某地址落在哪些 CIDR 中时可以跳过整棵不可能包含它的子树
+#[derive(Default)]
+struct IntervalTree {
+    items: Vec<(u128, u128, usize)>,
+    max_end: Vec<u128>,
+}
+
+impl IntervalTree {
+    fn build(mut items: Vec<(u128, u128, usize)>) -> Self {
+        items.sort_by_key(|i| i.0);
+        let mut tree = Self { max_end: vec![0; items.len()], items };
+        tree.fill(0, tree.items.len());
+        tree
+    }
+
+    fn fill(&mut self, lo: usize, hi: usize) -> u128 {
+        if lo >= hi { return 0; }
+        let mid = (lo + hi) / 2;
+        let m = self.items[mid].1.max(self.fill(lo, mid)).max(self.fill(mid + 1, hi));
+        self.max_end[mid] = m;
+        m
+    }
+
+    fn stab(&self, lo: usize, hi: usize, point: u128, out: &mut Vec<usize>) {
+        if lo >= hi { return; }
+        let mid = (lo + hi) / 2;
+        if self.max_end[mid] < point { return; }
+        self.stab(lo, mid, point, out);
+        let (start, end, rule) = self.items[mid];
+        if start <= point {
+            if point <= end { out.push(rule); }
+            self.stab(mid + 1, hi, point, out);
+        }
+    }
+}
+
+// 编译后的规则表：有域名或 CIDR 条件的规则经 trie/区间树预筛，其余规则总是逐条检查
+#[derive(Default)]
+pub(crate) struct RuleSet {
+    rules: Vec<Rule>,
+    domains: DomainTrie,
+    cidrs: IntervalTree,
+    unindexed: Vec<usize>,
+}
+
+impl RuleSet {
+    pub(crate) fn new(mut rules: Vec<Rule>, upstreams: &[String]) -> Result<Self> {
+        for r in &rules {
+            if let Some(Route::Upstream(name)) = &r.route {
+                if !upstreams.contains(name) { anyhow::bail!("rule {:?} refers to unknown upstream {:?}", r.text, name); }
+            }
+        }
+        // 稳定排序：同优先级保持声明顺序
+        rules.sort_by_key(|r| std::cmp::Reverse(r.priority));
+        if rules.iter().any(|r| r.matchers.iter().any(|m| matches!(m, Matcher::Process(_)))) { crate::process::enable(); }
+        if rules.iter().any(|r| r.matchers.iter().any(|m| matches!(m, Matcher::Uid(_)))) { crate::process::enable_uid(); }
+        let (mut domains, mut ranges, mut unindexed) = (DomainTrie::default(), Vec::new(), Vec::new());
+        for (i, r) in rules.iter().enumerate() {
+            // 只按第一个可索引的条件预筛，命中后仍检查全部条件
+            match r.matchers.iter().find(|m| matches!(m, Matcher::Domain(_) | Matcher::Suffix(_) | Matcher::Cidr(_))) {
+                Some(Matcher::Domain(v)) => v.iter().for_each(|d| domains.insert(d, i, false)),
+                Some(Matcher::Suffix(v)) => v.iter().for_each(|d| domains.insert(d, i, true)),
+                Some(Matcher::Cidr(v)) => ranges.extend(v.iter().map(|c| { let (lo, hi) = c.range(); (lo, hi, i) })),
+                _ => unindexed.push(i),
+            }
+        }
+        Ok(Self { rules, domains, cidrs: IntervalTree::build(ranges), unindexed })
+    }
+
+    pub(crate) fn evaluate(&self, q: &Query<'_>) -> (Decision, Vec<&str>) {
+        let host = q.host.trim_start_matches('[').trim_end_matches(']');
+        let ip = host.parse::<IpAddr>().ok();
+        let name = match (ip, q.sni) {
+            (Some(_), Some(sni)) => sni,
+            _ => host,
+        };
+        let t = Target { q, name: name.trim_end_matches('.').to_ascii_lowercase(), ip, clock: OnceCell::new() };
+        let mut candidates = self.unindexed.clone();
+        self.domains.lookup(&t.name, &mut candidates);
+        if let Some(ip) = ip {
+            self.cidrs.stab(0, self.cidrs.items.len(), crate::util::Cidr::key(ip), &mut candidates);
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        let (mut route, mut rewrite, mut resolve, mut resolution, mut matched) = (None, None, None, None, Vec::new());
+        for rule in candidates.into_iter().map(|i| &self.rules[i]) {
+            if route.is_some() && rewrite.is_some() && resolve.is_some() && resolution.is_some() { break; }
+            let fills = route.is_none() && rule.route.is_some()
+                || rewrite.is_none() && rule.rewrite.is_some()
+                || resolve.is_none() && rule.resolve.is_some()
+                || resolution.is_none() && rule.resolution.is_some();
+            if !fills || !rule.matchers.iter().all(|m| m.matches(&t)) { continue; }
+            if route.is_none() { route = rule.route.clone(); }
+            if rewrite.is_none() { rewrite = rule.rewrite.clone(); }
+            if resolve.is_none() { resolve = rule.resolve.clone(); }
+            if resolution.is_none() { resolution = rule.resolution; }
+            matched.push(rule.text.as_str());
+        }
+        let (host, port) = match rewrite {
+            Some((h, p)) => (Some(h), p),
+            None => (None, None),
+        };
+        (Decision { route: route.unwrap_or(Route::Default), host, port, resolve, resolution }, matched)
+    }
+}
+
+// 命令行规则固定，规则文件与本地规则集在 SIGHUP 或管理接口 POST /rules/reload 时重新读取；读取失败沿用旧规则。
+// 生效顺序（同优先级时）：--rule、--rules-file、--rule-set
+pub(crate) struct Rules {
+    inline: Vec<Rule>,
+    file: Option<String>,
+    imports: Imports,
+    upstreams: Vec<String>,
+    set: RwLock<Arc<RuleSet>>,
+    // 各规则集的转换摘要
+    imported: RwLock<Vec<String>>,
+    // 唤醒远程规则集的拉取任务
+    refresh: tokio::sync::Notify,
+}
+
+fn read_rules_file(path: &str) -> Result<Vec<Rule>> {
+    let text = std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("read {}: {}", path, e))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+        .map(|(n, l)| Rule::parse(l).map_err(|e| anyhow::anyhow!("{}:{}: {}", path, n + 1, e)))
+        .collect()
+}
+
+impl Rules {
+    pub(crate) fn load(inline: Vec<Rule>, file: Option<String>, imports: Imports, upstreams: Vec<String>) -> Result<Self> {
+        let rules = Self { inline, file, imports, upstreams, set: RwLock::default(), imported: RwLock::default(), refresh: tokio::sync::Notify::new() };
+        rules.compile()?;
+        Ok(rules)
+    }
+
+    fn compile(&self) -> Result<()> {
+        let mut rules = self.inline.clone();
+        if let Some(path) = &self.file { rules.extend(read_rules_file(path)?); }
+        let (imported, summary) = self.imports.rules()?;
+        rules.extend(imported);
+        let set = RuleSet::new(rules, &self.upstreams)?;
+        *self.set.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(set);
+        *self.imported.write().unwrap_or_else(|e| e.into_inner()) = summary;
+        Ok(())
+    }
+
+    fn current(&self) -> Arc<RuleSet> {
+        self.set.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub(crate) fn summary(&self) -> String {
+        let n = self.current().rules.len();
+        let mut s = match &self.file {
+            Some(path) => format!("{} rule(s), {} from command line, file {}", n, self.inline.len(), path),
+            None => format!("{} rule(s)", n),
+        };
+        let imported = self.imported.read().unwrap_or_else(|e| e.into_inner());
+        if !imported.is_empty() { s.push_str(&format!("; rule sets: {}", imported.join("; "))); }
+        s
+    }
+
+    pub(crate) fn reload(&self) -> Result<String> {
+        self.imports.reload_local()?;
+        self.compile()?;
+        Ok(self.summary())
+    }
+
+    // 管理接口 /rules：按生效顺序列出
+    pub(crate) fn render(&self) -> String {
+        let set = self.current();
+        set.rules.iter().map(|r| format!("{:>6}  {}\n", r.priority, r.text)).collect()
+    }
+}
+
+impl Router for Rules {
+    fn route(&self, q: &Query<'_>) -> Result<Decision> {
+        let set = self.current();
+        let (decision, matched) = set.evaluate(q);
+        if !matched.is_empty() {
+            log_throttled(|| log_info(format!("rules: {} matched {}", crate::addr::redact(q.host, q.port), matched.join(" | "))));
+        }
+        Ok(decision)
+    }
+}
+
+static RULES: OnceLock<Arc<Rules>> = OnceLock::new();
+
+struct Installed(Arc<Rules>);
+
+impl Router for Installed {
+    fn route(&self, q: &Query<'_>) -> Result<Decision> {
+        self.0.route(q)
+    }
+}
+
+pub(crate) fn install(rules: Rules) -> Result<()> {
+    let rules = Arc::new(rules);
+    let _ = RULES.set(rules.clone());
+    crate::router::set_router(Installed(rules))
+}
+
+pub(crate) fn installed() -> Option<&'static Rules> {
+    RULES.get().map(|r| r.as_ref())
+}
+
+// --drain-on-reload：规则重新加载后，去向变了（如改为拦截、所走的规则被删除）的会话在宽限期后结束；
+// 未设置时只在日志与 /rules/reload 的应答中报告受影响的会话数
+static DRAIN: OnceLock<Duration> = OnceLock::new();
+
+pub(crate) fn set_drain(grace: Duration) {
+    let _ = DRAIN.set(grace);
+}
+
+// 返回受影响会话的说明，附在重新加载的摘要后
+fn drain_rerouted(rules: &'static Rules) -> String {
+    let set = rules.current();
+    let ids = crate::session::rerouted(|q| set.evaluate(q).0);
+    if ids.is_empty() { return String::from("no active session affected"); }
+    let n = ids.len();
+    let Some(grace) = DRAIN.get().copied() else { return format!("{} active session(s) now routed differently, kept open", n) };
+    tokio::spawn(async move {
+        tokio::time::sleep(grace).await;
+        // 宽限期内规则可能又被改回去，只结束仍受影响的
+        let set = rules.current();
+        let still: Vec<u64> = crate::session::rerouted(|q| set.evaluate(q).0).into_iter().filter(|id| ids.contains(id)).collect();
+        let closed = crate::session::close_ids(&still, "routing rules changed");
+        if closed > 0 { log_info(format!("rules: closed {} session(s) routed differently after reload", closed)); }
+    });
+    format!("{} active session(s) now routed differently, closing in {}s", n, grace.as_secs())
+}
+
+fn reload_and_drain(rules: &'static Rules) -> Result<String> {
+    let summary = rules.reload()?;
+    Ok(format!("{}; {}", summary, drain_rerouted(rules)))
+}
+
+fn log_reload(res: &Result<String>) {
+    match res {
+        Ok(summary) => log_info(format!("rules reloaded: {}", summary)),
+        Err(e) => log_error(format!("rules reload failed, keeping the previous rules: {}", e)),
+    }
+}
+
+// 重新读取规则文件与本地规则集，远程规则集随后在后台重新拉取
+pub(crate) fn reload() -> Result<String> {
+    let rules = installed().ok_or_else(|| anyhow::anyhow!("no rules configured"))?;
+    let res = reload_and_drain(rules);
+    log_reload(&res);
+    if rules.imports.has_remote() { rules.refresh.notify_one(); }
+    res
+}
+
+// 按各远程规则集的间隔重新拉取，内容有变化时重新编译；reload() 时立即拉取全部
+pub(crate) async fn refresh_rule_sets() {
+    let Some(rules) = installed() else { return };
+    while let Some(due) = rules.imports.next_due() {
+        let force = tokio::select! {
+            _ = tokio::time::sleep_until(due) => false,
+            _ = rules.refresh.notified() => true,
+        };
+        if rules.imports.refresh_remote(force).await { log_reload(&reload_and_drain(rules)); }
+    }
+}
+
+pub(crate) async fn reload_on_sighup() {
+    let mut hup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            log_error(format!("failed to install SIGHUP handler: {}", e));
+            return;
+        }
+    };
+    while hup.recv().await.is_some() {
+        let _ = reload();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(rules: &[&str]) -> RuleSet {
+        RuleSet::new(rules.iter().map(|r| Rule::parse(r).unwrap()).collect(), &[String::from("remote")]).unwrap()
+    }
+
+    fn query<'a>(host: &'a str, port: u16, protocol: &'a str, user: Option<&'a str>) -> Query<'a> {
+        Query { client: "127.0.0.1:5000", protocol, host, port, sni: None, user, uid: None, process: None, process_path: None }
+    }
+
+    #[test]
+    fn priority_and_merge() {
+        let rules = set(&[
+            "suffix:example.com port:443 => upstream:remote",
+            "priority=10 domain:ads.example.com => block",
+            "priority=5 suffix:corp.example.com => rewrite:gw.corp.example.com:8443",
+            "keyword:track,beacon => block",
+            r"regex:^api\d+\.svc$ => iface:en7",
+            "* => iface:en0",
+        ]);
+        let route = |host, port| rules.evaluate(&query(host, port, "connect", None)).0;
+        assert_eq!(route("ads.example.com", 443).route, Route::Block);
+        assert_eq!(route("www.example.com", 443).route, Route::Upstream(String::from("remote")));
+        assert_eq!(route("example.com", 443).route, Route::Upstream(String::from("remote")));
+        assert_eq!(route("notexample.com", 443).route, Route::Direct(Some(String::from("en0"))));
+        assert_eq!(route("www.example.com", 80).route, Route::Direct(Some(String::from("en0"))));
+        assert_eq!(route("tracker.net", 80).route, Route::Block);
+        assert_eq!(route("api12.svc", 80).route, Route::Direct(Some(String::from("en7"))));
+        assert_eq!(route("api12.svc.x", 80).route, Route::Direct(Some(String::from("en0"))));
+        // 改写来自高优先级规则，去向由低优先级规则补上
+        let d = route("db.corp.example.com", 443);
+        assert_eq!(d.route, Route::Upstream(String::from("remote")));
+        assert_eq!((d.host.as_deref(), d.port), (Some("gw.corp.example.com"), Some(8443)));
+    }
+
+    #[test]
+    fn cidr_port_protocol_and_user() {
+        let rules = set(&[
+            "cidr:10.0.0.0/8,fd00::/8 => iface:eth1",
+            "cidr:10.1.0.0/16 port:22 => block",
+            "protocol:socks5 user:alice,bob => iface:en7",
+            "port:8000-8999 => direct",
+        ]);
+        let route = |host, port, protocol, user| rules.evaluate(&query(host, port, protocol, user)).0.route;
+        assert_eq!(route("10.1.2.3", 22, "http", None), Route::Direct(Some(String::from("eth1"))));
+        assert_eq!(route("10.2.0.1", 80, "http", None), Route::Direct(Some(String::from("eth1"))));
+        assert_eq!(route("[fd00::1]", 443, "connect", None), Route::Direct(Some(String::from("eth1"))));
+        assert_eq!(route("11.0.0.1", 80, "socks5", Some("alice")), Route::Direct(Some(String::from("en7"))));
+        assert_eq!(route("11.0.0.1", 80, "http", Some("alice")), Route::Default);
+        assert_eq!(route("example.com", 8080, "http", None), Route::Direct(None));
+
+        let priority = set(&["cidr:10.0.0.0/8 => iface:eth1", "priority=1 cidr:10.1.0.0/16 port:22 => block"]);
+        assert_eq!(priority.evaluate(&query("10.1.2.3", 22, "http", None)).0.route, Route::Block);
+    }
+
+    #[test]
+    fn resolve_and_dns_actions() {
+        let rules = set(&[
+            "priority=1 domain:pinned.internal => resolve:10.0.0.5",
+            "suffix:internal => dns:10.0.0.53@utun2",
+            "suffix:internal => iface:utun2",
+            "suffix:v6.example => dns:[fd00::53]:5353, direct",
+        ]);
+        let route = |host| rules.evaluate(&query(host, 443, "connect", None)).0;
+        let d = route("pinned.internal");
+        assert_eq!(d.resolve, Some(Resolve::Addr("10.0.0.5".parse().unwrap())));
+        assert_eq!(d.route, Route::Direct(Some(String::from("utun2"))));
+        assert_eq!(route("git.internal").resolve, Some(Resolve::Server { addr: "10.0.0.53:53".parse().unwrap(), iface: Some(String::from("utun2")) }));
+        assert_eq!(route("a.v6.example").resolve, Some(Resolve::Server { addr: "[fd00::53]:5353".parse().unwrap(), iface: None }));
+        assert_eq!(route("example.com").resolve, None);
+        // 高优先级的 remote-dns 把个别域名排除在全局 local-dns 之外
+        let dns = set(&["priority=1 suffix:cdn.example => remote-dns", "* => local-dns", "* => upstream:remote"]);
+        let d = dns.evaluate(&query("www.example.com", 443, "connect", None)).0;
+        assert_eq!((d.route, d.resolution), (Route::Upstream(String::from("remote")), Some(Resolution::Local)));
+        assert_eq!(dns.evaluate(&query("a.cdn.example", 443, "connect", None)).0.resolution, Some(Resolution::Remote));
+        for bad in ["* => resolve:not-an-ip", "* => dns:10.0.0.53@", "* => dns:ns.example", "* => resolve:10.0.0.1,dns:10.0.0.53", "* => local-dns,remote-dns"] {
+            assert!(Rule::parse(bad).is_err(), "{:?} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn interval_tree_finds_all_overlapping_ranges() {
+        let cidrs = ["0.0.0.0/0", "10.0.0.0/8", "10.1.0.0/16", "10.1.2.0/24", "192.168.0.0/16", "10.1.2.3/32", "172.16.0.0/12"];
+        let tree = IntervalTree::build(cidrs.iter().enumerate().map(|(i, c)| { let (lo, hi) = Cidr::parse(c).unwrap().range(); (lo, hi, i) }).collect());
+        let hit = |ip: &str| {
+            let mut out = Vec::new();
+            tree.stab(0, tree.items.len(), Cidr::key(ip.parse().unwrap()), &mut out);
+            out.sort();
+            out
+        };
+        assert_eq!(hit("10.1.2.3"), [0, 1, 2, 3, 5]);
+        assert_eq!(hit("10.9.0.1"), [0, 1]);
+        assert_eq!(hit("172.20.0.1"), [0, 6]);
+        assert_eq!(hit("8.8.8.8"), [0]);
+    }
+
+    #[test]
+    fn schedules_at_boundaries() {
+        let at = |h: u32, m: u32| h * 60 + m;
+        let (mon, fri, sat, sun) = (0, 4, 5, 6);
+        assert_eq!(parse_days("fri-mon").unwrap(), 0b111_0001);
+        assert_eq!(parse_days("weekdays,Sun").unwrap(), 0b101_1111);
+        assert!(parse_days("funday").is_err());
+
+        // 工作日 09:00-18:00：起点包含、终点不含
+        let office = [(at(9, 0), at(18, 0))];
+        assert!(!in_schedule(0x1f, &office, mon, at(8, 59)));
+        assert!(in_schedule(0x1f, &office, mon, at(9, 0)));
+        assert!(in_schedule(0x1f, &office, fri, at(17, 59)));
+        assert!(!in_schedule(0x1f, &office, fri, at(18, 0)));
+        assert!(!in_schedule(0x1f, &office, sat, at(10, 0)));
+
+        // 工作日夜间 22:00-06:00：周五夜里延续到周六早上，周日夜里不算
+        let night = [(at(22, 0), at(6, 0))];
+        assert!(in_schedule(0x1f, &night, fri, at(23, 59)));
+        assert!(in_schedule(0x1f, &night, sat, at(0, 0)));
+        assert!(in_schedule(0x1f, &night, sat, at(5, 59)));
+        assert!(!in_schedule(0x1f, &night, sat, at(6, 0)));
+        assert!(!in_schedule(0x1f, &night, sun, at(22, 0)));
+        assert!(!in_schedule(0x1f, &night, mon, at(1, 0)));
+        assert!(in_schedule(ALL_DAYS, &[(at(18, 0), at(24, 0))], sun, at(23, 59)));
+        assert!(in_schedule(0x60, &[], sun, at(12, 0)) && !in_schedule(0x60, &[], mon, at(12, 0)));
+
+        let rule = Rule::parse("day:weekdays suffix:video.example time:09:00-18:00 => block").unwrap();
+        assert!(matches!(&rule.matchers[..], [Matcher::Suffix(_), Matcher::Time { days: 0x1f, ranges }] if ranges.len() == 1));
+        assert!(matches!(&Rule::parse("day:sat,sun => iface:en7").unwrap().matchers[..], [Matcher::Time { days: 0x60, ranges }] if ranges.is_empty()));
+    }
+
+    #[test]
+    fn matches_process_name_or_path() {
+        let rules = set(&["process:Slack => iface:en0", "process:/Applications/Google%20Chrome.app/ => iface:en7", "* => iface:utun2"]);
+        let route = |name, path| rules.evaluate(&Query { process: name, process_path: path, ..query("example.com", 443, "connect", None) }).0.route;
+        assert_eq!(route(Some("slack"), Some("/Applications/Slack.app/Contents/MacOS/Slack")), Route::Direct(Some(String::from("en0"))));
+        assert_eq!(route(Some("Google Chrome"), Some("/Applications/Google Chrome.app/Contents/MacOS/Google Chrome")), Route::Direct(Some(String::from("en7"))));
+        assert_eq!(route(Some("Slack Helper"), None), Route::Direct(Some(String::from("utun2"))));
+        assert_eq!(route(None, None), Route::Direct(Some(String::from("utun2"))));
+        assert!(crate::process::enabled());
+    }
+
+    #[test]
+    fn matches_uid_or_user_name() {
+        let rules = set(&["uid:1000,root => iface:eth1", "uid:1001 => block"]);
+        let route = |uid| rules.evaluate(&Query { uid, ..query("example.com", 443, "connect", None) }).0.route;
+        assert_eq!(route(Some(0)), Route::Direct(Some(String::from("eth1"))));
+        assert_eq!(route(Some(1000)), Route::Direct(Some(String::from("eth1"))));
+        assert_eq!(route(Some(1001)), Route::Block);
+        assert_eq!(route(None), Route::Default);
+        assert!(crate::process::uid_enabled());
+    }
+
+    #[test]
+    fn rejects_bad_rules() {
+        for bad in ["suffix:x.com", "suffix:x.com => fly", "port:9-1 => block", "time:25:00-26:00 => block", "day:someday => block", "day:, => block", "process:%zz => block", "uid:no-such-user-here => block", "x => block", "* => block,direct", "* => upstream:nope"] {
+            let res = Rule::parse(bad).and_then(|r| RuleSet::new(vec![r], &[]));
+            assert!(res.is_err(), "{:?} should be rejected", bad);
+        }
+    }
+}
@@ -0,0 +1,573 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+
+use crate::rules::Rule;
+use crate::util::log_error;
+
+// --rule-set / --geoip：导入 Clash（配置中的 rules:、rule-provider 的 payload:）与 Surge（.list、[Rule] 段）规则，
+// 转换成 --rule 规则。SOURCE 为本地路径或 http(s):// 地址，远程来源按各自的间隔带 If-None-Match/If-Modified-Since 重新拉取，
+// 设置 --rule-set-cache-dir 时内容与校验头落盘，重启后拉取失败也能先用上次的内容
+
+// 拉取远程规则集的超时与大小上限
+const FETCH_TIMEOUT_SECS: u64 = 30;
+const FETCH_MAX_BYTES: usize = 32 << 20;
+// 拉取失败后的重试间隔（不超过该来源的拉取间隔）
+const RETRY_SECS: u64 = 60;
+const CACHE_MAGIC: &str = "iface-proxy-ruleset/1";
+
+// [priority=N] [interval=SECS] [iface=NAME] SOURCE [=> ACTION]；不带动作时使用每行的策略（DIRECT、REJECT 或上游名）
+#[derive(Clone, Debug)]
+pub(crate) struct RuleSetSpec {
+    priority: i32,
+    pub(crate) source: String,
+    action: Option<String>,
+    // 远程来源的拉取间隔与出口网卡，缺省用 --rule-set-interval-secs 与 --rule-set-iface
+    interval: Option<u64>,
+    iface: Option<String>,
+}
+
+impl RuleSetSpec {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (lhs, action) = match s.split_once("=>") {
+            Some((l, a)) => (l, Some(a.trim().to_string())),
+            None => (s, None),
+        };
+        let (mut priority, mut source, mut interval, mut iface) = (0, None, None, None);
+        for tok in lhs.split_whitespace() {
+            match tok.split_once('=') {
+                Some(("priority", p)) => priority = p.parse().map_err(|_| anyhow::anyhow!("invalid priority {:?} in rule set {:?}", p, s))?,
+                Some(("interval", v)) => match v.parse::<u64>() {
+                    Ok(secs) if secs > 0 => interval = Some(secs),
+                    _ => anyhow::bail!("invalid interval {:?} in rule set {:?}", v, s),
+                },
+                Some(("iface", v)) if !v.is_empty() => iface = Some(v.to_string()),
+                _ if source.is_none() && (is_remote(tok) || !tok.contains('=')) => source = Some(tok.to_string()),
+                _ => anyhow::bail!("invalid rule set {:?} (expected [priority=N] [interval=SECS] [iface=NAME] SOURCE [=> ACTION])", s),
+            }
+        }
+        let source = source.ok_or_else(|| anyhow::anyhow!("rule set {:?} has no source", s))?;
+        if let Some(a) = &action {
+            Rule::parse(&format!("* => {}", a)).map_err(|e| anyhow::anyhow!("rule set {:?}: {}", s, e))?;
+        }
+        Ok(Self { priority, source, action, interval, iface })
+    }
+}
+
+// CC=SOURCE：国家/地区代码对应的 CIDR 列表（每行一个），供 GEOIP,CC 使用
+#[derive(Clone, Debug)]
+pub(crate) struct GeoIpSpec {
+    country: String,
+    pub(crate) source: String,
+}
+
+impl GeoIpSpec {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (cc, source) = s.split_once('=').ok_or_else(|| anyhow::anyhow!("invalid geoip list {:?} (expected CC=SOURCE)", s))?;
+        if cc.trim().is_empty() || source.trim().is_empty() { anyhow::bail!("invalid geoip list {:?} (expected CC=SOURCE)", s); }
+        Ok(Self { country: cc.trim().to_ascii_uppercase(), source: source.trim().to_string() })
+    }
+}
+
+pub(crate) fn is_remote(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+pub(crate) fn tls_config() -> Arc<tokio_rustls::rustls::ClientConfig> {
+    static CONFIG: OnceLock<Arc<tokio_rustls::rustls::ClientConfig>> = OnceLock::new();
+    CONFIG.get_or_init(|| {
+        let mut roots = tokio_rustls::rustls::RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        Arc::new(tokio_rustls::rustls::ClientConfig::builder().with_root_certificates(roots).with_no_client_auth())
+    }).clone()
+}
+
+// 条件请求用的校验头，来自上次的 200 应答
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+pub(crate) enum Fetched {
+    NotModified,
+    Body(String, Validators),
+}
+
+// HTTP/1.0 请求，应答不会是 chunked；只接受 200 与 304
+async fn get<S: AsyncRead + AsyncWrite + Unpin>(mut s: S, host: &str, path: &str, v: &Validators) -> Result<Fetched> {
+    let mut req = format!("GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: {}\r\nAccept: */*\r\nConnection: close\r\n", path, host, crate::build_info::AGENT);
+    if let Some(etag) = &v.etag { req.push_str(&format!("If-None-Match: {}\r\n", etag)); }
+    if let Some(lm) = &v.last_modified { req.push_str(&format!("If-Modified-Since: {}\r\n", lm)); }
+    req.push_str("\r\n");
+    s.write_all(req.as_bytes()).await?;
+    let mut buf = Vec::new();
+    (&mut s).take(FETCH_MAX_BYTES as u64 + 1).read_to_end(&mut buf).await?;
+    if buf.len() > FETCH_MAX_BYTES { anyhow::bail!("larger than {} bytes", FETCH_MAX_BYTES); }
+    let end = buf.windows(4).position(|w| w == b"\r\n\r\n").ok_or_else(|| anyhow::anyhow!("malformed response"))?;
+    let head = String::from_utf8_lossy(&buf[..end]);
+    let header = |name: &str| {
+        head.lines().skip(1).find_map(|l| l.split_once(':').filter(|(k, _)| k.trim().eq_ignore_ascii_case(name)).map(|(_, v)| v.trim().to_string()))
+    };
+    match head.split_whitespace().nth(1).unwrap_or("") {
+        "304" => Ok(Fetched::NotModified),
+        "200" => {
+            let v = Validators { etag: header("etag"), last_modified: header("last-modified") };
+            Ok(Fetched::Body(String::from_utf8_lossy(&buf[end + 4..]).into_owned(), v))
+        }
+        _ => anyhow::bail!("unexpected response {:?}", head.lines().next().unwrap_or("")),
+    }
+}
+
+pub(crate) struct Url {
+    tls: bool,
+    pub(crate) host: String,
+    port: u16,
+    pub(crate) path: String,
+}
+
+impl Url {
+    pub(crate) fn parse(url: &str) -> Result<Self> {
+        let (tls, rest) = match (url.strip_prefix("https://"), url.strip_prefix("http://")) {
+            (Some(r), _) => (true, r),
+            (None, Some(r)) => (false, r),
+            _ => anyhow::bail!("unsupported URL {:?} (expected http:// or https://)", url),
+        };
+        let end = rest.find(['/', '?']).unwrap_or(rest.len());
+        let (authority, path) = rest.split_at(end);
+        let (host, port) = crate::uri::parse_authority(authority, if tls { 443 } else { 80 }).map_err(|e| anyhow::anyhow!("{}: {}", url, e))?;
+        let path = if path.starts_with('/') { path.to_string() } else { format!("/{}", path) };
+        Ok(Self { tls, host, port, path })
+    }
+
+    // 经出口网卡建立到该地址的连接，https 时完成 TLS 握手
+    pub(crate) async fn connect(&self, iface: &str) -> Result<crate::dialer::OutboundStream> {
+        let stream = crate::util::connect_outbound(&self.host, self.port, iface, &[]).await?;
+        if !self.tls { return Ok(Box::new(stream)); }
+        let name = tokio_rustls::rustls::pki_types::ServerName::try_from(self.host.clone())?;
+        Ok(Box::new(tokio_rustls::TlsConnector::from(tls_config()).connect(name, stream).await?))
+    }
+}
+
+// 经出口网卡拉取 http(s):// 地址的内容；带上校验头时内容未变的应答为 NotModified
+pub(crate) async fn fetch(url: &str, iface: &str, v: &Validators) -> Result<Fetched> {
+    let u = Url::parse(url)?;
+    let fut = async { get(u.connect(iface).await?, &u.host, &u.path, v).await };
+    tokio::time::timeout(Duration::from_secs(FETCH_TIMEOUT_SECS), fut)
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out after {}s", FETCH_TIMEOUT_SECS))?
+        .map_err(|e| anyhow::anyhow!("fetch {}: {}", url, e))
+}
+
+fn read_local(path: &str) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("read {}: {}", path, e))
+}
+
+// 一条规则集条目，value 已规范化
+enum Entry {
+    Domain(String),
+    Suffix(String),
+    Keyword(String),
+    Regex(String),
+    Cidr(String),
+    Port(String),
+    GeoIp(String),
+    Match,
+}
+
+fn policy_action(policy: &str) -> String {
+    match policy.to_ascii_uppercase().as_str() {
+        "DIRECT" => String::from("direct"),
+        p if p.starts_with("REJECT") => String::from("block"),
+        _ => format!("upstream:{}", policy),
+    }
+}
+
+// 返回 (条目, 该行自带的策略)；不支持的类型返回 Err(类型名)
+fn parse_entry(item: &str) -> Result<(Entry, Option<&str>), String> {
+    let fields: Vec<&str> = item.split(',').map(str::trim).collect();
+    if fields.len() == 1 {
+        // domain/ipcidr 形式的 rule-provider 与 Surge domain-set：+.x、.x 为后缀，*.x 近似为后缀
+        let v = fields[0].trim_end_matches('.').to_ascii_lowercase();
+        if crate::util::Cidr::parse(&v).is_ok() { return Ok((Entry::Cidr(v), None)); }
+        return Ok(match v.strip_prefix("+.").or_else(|| v.strip_prefix("*.")).or_else(|| v.strip_prefix('.')) {
+            Some(s) => (Entry::Suffix(s.to_string()), None),
+            None => (Entry::Domain(v), None),
+        });
+    }
+    let kind = fields[0].to_ascii_uppercase();
+    if kind == "MATCH" || kind == "FINAL" { return Ok((Entry::Match, Some(fields[1]))); }
+    let value = fields[1];
+    let policy = fields.get(2).copied().filter(|p| !p.eq_ignore_ascii_case("no-resolve") && !p.is_empty());
+    let lower = || value.trim_end_matches('.').to_ascii_lowercase();
+    let entry = match kind.as_str() {
+        "DOMAIN" => Entry::Domain(lower()),
+        "DOMAIN-SUFFIX" => Entry::Suffix(lower().trim_start_matches('.').to_string()),
+        "DOMAIN-KEYWORD" => Entry::Keyword(lower()),
+        "DOMAIN-REGEX" => Entry::Regex(value.to_string()),
+        "IP-CIDR" | "IP-CIDR6" => Entry::Cidr(value.to_string()),
+        "DST-PORT" | "DEST-PORT" => Entry::Port(value.to_string()),
+        "GEOIP" => Entry::GeoIp(value.to_ascii_uppercase()),
+        _ => return Err(kind),
+    };
+    Ok((entry, policy))
+}
+
+// 只取规则所在的段：Clash YAML 的 rules:/payload:，Surge 的 [Rule]；都没有时整个文件都是规则
+fn rule_lines(text: &str) -> impl Iterator<Item = &str> {
+    let mut section: Option<String> = None;
+    text.lines().filter_map(move |raw| {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") || line.starts_with(';') { return None; }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = Some(line[1..line.len() - 1].trim().to_ascii_lowercase());
+            return None;
+        }
+        let item = line.strip_prefix("- ");
+        if item.is_none() && raw.starts_with(char::is_whitespace) { return None; }
+        if item.is_none() && (line.ends_with(':') || line.contains(": ")) {
+            section = Some(line.split(':').next().unwrap_or("").trim().to_ascii_lowercase());
+            return None;
+        }
+        if !matches!(section.as_deref(), None | Some("rules" | "payload" | "rule")) { return None; }
+        let item = item.unwrap_or(line).trim();
+        let item = item.split(" #").next().unwrap_or(item).trim();
+        Some(item.trim_matches(|c| c == '\'' || c == '"'))
+    })
+}
+
+// 同一动作的连续条目归为一组，组内按类型合并成少数几条规则（域名与 CIDR 条目仍经 trie/区间树索引），
+// 组与组之间保持原有顺序，因此带不同策略的 Clash rules: 仍是先匹配先生效
+#[derive(Default)]
+struct Group {
+    action: String,
+    domain: Vec<String>,
+    suffix: Vec<String>,
+    keyword: Vec<String>,
+    regex: Vec<String>,
+    cidr: Vec<String>,
+    port: Vec<String>,
+    all: bool,
+}
+
+pub(crate) struct Converted {
+    pub(crate) rules: Vec<Rule>,
+    pub(crate) entries: usize,
+    // 跳过的条目，按原因计数
+    pub(crate) skipped: BTreeMap<String, usize>,
+}
+
+pub(crate) fn convert(spec: &RuleSetSpec, text: &str, geoip: &HashMap<String, Vec<String>>) -> Result<Converted> {
+    let mut groups: Vec<Group> = Vec::new();
+    let mut skipped: BTreeMap<String, usize> = BTreeMap::new();
+    let mut entries = 0;
+    for item in rule_lines(text) {
+        let (entry, policy) = match parse_entry(item) {
+            Ok(v) => v,
+            Err(kind) => {
+                *skipped.entry(kind).or_default() += 1;
+                continue;
+            }
+        };
+        let Some(action) = spec.action.clone().or_else(|| policy.map(policy_action)) else {
+            *skipped.entry(String::from("no policy")).or_default() += 1;
+            continue;
+        };
+        if groups.last().is_none_or(|g| g.action != action) {
+            groups.push(Group { action, ..Group::default() });
+        }
+        let g = groups.last_mut().expect("group pushed above");
+        let valid = match entry {
+            Entry::Domain(v) => { g.domain.push(v); true }
+            Entry::Suffix(v) => { g.suffix.push(v); true }
+            Entry::Keyword(v) => { g.keyword.push(v); true }
+            Entry::Regex(v) => regex::Regex::new(&v).is_ok() && { g.regex.push(v); true },
+            Entry::Cidr(v) => crate::util::Cidr::parse(&v).is_ok() && { g.cidr.push(v); true },
+            Entry::Port(v) => Rule::parse(&format!("port:{} => direct", v)).is_ok() && { g.port.push(v); true },
+            Entry::GeoIp(cc) if cc == "LAN" => { g.cidr.extend(crate::util::default_deny_dest().iter().map(|c| c.to_string())); true }
+            Entry::GeoIp(cc) => match geoip.get(&cc) {
+                Some(list) => { g.cidr.extend(list.iter().cloned()); true }
+                None => {
+                    *skipped.entry(format!("GEOIP,{} (no --geoip {}=...)", cc, cc)).or_default() += 1;
+                    continue;
+                }
+            },
+            Entry::Match => { g.all = true; true }
+        };
+        if valid { entries += 1 } else { *skipped.entry(String::from("invalid")).or_default() += 1 }
+    }
+    let mut rules = Vec::new();
+    for g in groups {
+        let mut push = |cond: String, what: String| -> Result<()> {
+            let rule = Rule::parse(&format!("priority={} {} => {}", spec.priority, cond, g.action))?;
+            rules.push(rule.labeled(format!("rule-set {}: {} => {}", spec.source, what, g.action)));
+            Ok(())
+        };
+        for (kind, values) in [("domain", &g.domain), ("suffix", &g.suffix), ("keyword", &g.keyword), ("cidr", &g.cidr), ("port", &g.port)] {
+            if !values.is_empty() { push(format!("{}:{}", kind, values.join(",")), format!("{} {}", values.len(), kind))?; }
+        }
+        for re in &g.regex { push(format!("regex:{}", re), format!("regex {}", re))?; }
+        if g.all { push(String::from("*"), String::from("MATCH"))?; }
+    }
+    Ok(Converted { rules, entries, skipped })
+}
+
+fn parse_cidr_list(source: &str, text: &str) -> Vec<String> {
+    let list: Vec<String> = text.lines()
+        .map(|l| l.split('#').next().unwrap_or("").trim().trim_start_matches("- ").trim_matches(|c| c == '\'' || c == '"'))
+        .filter(|l| crate::util::Cidr::parse(l).is_ok())
+        .map(str::to_string)
+        .collect();
+    if list.is_empty() { log_error(format!("geoip list {} has no CIDRs", source)); }
+    list
+}
+
+// 远程来源的拉取参数
+pub(crate) struct FetchOptions {
+    pub(crate) iface: String,
+    pub(crate) interval: Duration,
+    pub(crate) cache_dir: Option<PathBuf>,
+}
+
+// 一个规则集或 GeoIP 列表来源的最近一次成功读取的内容，未读到过时为 None
+#[derive(Default)]
+struct Loaded {
+    text: Option<Arc<str>>,
+    validators: Validators,
+}
+
+struct Source {
+    path: String,
+    iface: String,
+    interval: Duration,
+    loaded: Mutex<Loaded>,
+    // 下次拉取的时间，只用于远程来源
+    due: Mutex<Instant>,
+}
+
+impl Source {
+    fn new(path: &str, iface: Option<&str>, interval: Option<u64>, opts: &FetchOptions) -> Self {
+        Self {
+            path: path.to_string(),
+            iface: iface.unwrap_or(&opts.iface).to_string(),
+            interval: interval.map(Duration::from_secs).unwrap_or(opts.interval),
+            loaded: Mutex::default(),
+            due: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn remote(&self) -> bool {
+        is_remote(&self.path)
+    }
+
+    fn text(&self) -> Option<Arc<str>> {
+        self.loaded.lock().unwrap_or_else(|e| e.into_inner()).text.clone()
+    }
+
+    // 内容有变化时返回 true
+    fn store(&self, text: String, validators: Validators) -> bool {
+        let mut loaded = self.loaded.lock().unwrap_or_else(|e| e.into_inner());
+        loaded.validators = validators;
+        if loaded.text.as_deref() == Some(text.as_str()) { return false; }
+        loaded.text = Some(Arc::from(text));
+        true
+    }
+
+    fn cache_path(&self, dir: &std::path::Path) -> PathBuf {
+        dir.join(format!("{}.ruleset", blake3::hash(self.path.as_bytes()).to_hex()))
+    }
+
+    // 缓存文件：魔数、URL、ETag、Last-Modified 各一行（没有时为空行），之后是内容
+    fn load_cache(&self, dir: &std::path::Path) {
+        let Ok(data) = std::fs::read_to_string(self.cache_path(dir)) else { return };
+        let mut parts = data.splitn(5, '\n');
+        let (magic, url, etag, lm, body) = (parts.next(), parts.next(), parts.next(), parts.next(), parts.next());
+        if magic != Some(CACHE_MAGIC) || url != Some(self.path.as_str()) { return; }
+        let opt = |s: Option<&str>| s.filter(|s| !s.is_empty()).map(str::to_string);
+        self.store(body.unwrap_or("").to_string(), Validators { etag: opt(etag), last_modified: opt(lm) });
+    }
+
+    fn save_cache(&self, dir: &std::path::Path, text: &str, v: &Validators) {
+        let data = format!(
+            "{}\n{}\n{}\n{}\n{}",
+            CACHE_MAGIC,
+            self.path,
+            v.etag.as_deref().unwrap_or(""),
+            v.last_modified.as_deref().unwrap_or(""),
+            text
+        );
+        if let Err(e) = std::fs::write(self.cache_path(dir), data) {
+            log_error(format!("rule set: cannot write cache for {}: {}", self.path, e));
+        }
+    }
+}
+
+// 规则集与 GeoIP 列表的来源；远程读取失败时沿用上次的内容（启动时失败且没有磁盘缓存则为空，等下次拉取）
+pub(crate) struct Imports {
+    sets: Vec<(RuleSetSpec, Source)>,
+    geoip: Vec<(GeoIpSpec, Source)>,
+    cache_dir: Option<PathBuf>,
+}
+
+impl Imports {
+    pub(crate) async fn load(sets: Vec<RuleSetSpec>, geoip: Vec<GeoIpSpec>, opts: FetchOptions) -> Result<Self> {
+        if let Some(dir) = &opts.cache_dir {
+            std::fs::create_dir_all(dir).map_err(|e| anyhow::anyhow!("cannot create {}: {}", dir.display(), e))?;
+        }
+        let imports = Self {
+            sets: sets.into_iter().map(|s| { let src = Source::new(&s.source, s.iface.as_deref(), s.interval, &opts); (s, src) }).collect(),
+            geoip: geoip.into_iter().map(|g| { let src = Source::new(&g.source, None, None, &opts); (g, src) }).collect(),
+            cache_dir: opts.cache_dir,
+        };
+        imports.reload_local()?;
+        if let Some(dir) = &imports.cache_dir {
+            imports.sources().filter(|s| s.remote()).for_each(|s| s.load_cache(dir));
+        }
+        imports.refresh_remote(true).await;
+        Ok(imports)
+    }
+
+    fn sources(&self) -> impl Iterator<Item = &Source> {
+        self.sets.iter().map(|(_, s)| s).chain(self.geoip.iter().map(|(_, s)| s))
+    }
+
+    pub(crate) fn has_remote(&self) -> bool {
+        self.sources().any(Source::remote)
+    }
+
+    // 重新读取本地文件；出错时不改动已有内容
+    pub(crate) fn reload_local(&self) -> Result<()> {
+        let mut loaded = Vec::new();
+        for src in self.sources().filter(|s| !s.remote()) {
+            loaded.push((src, read_local(&src.path)?));
+        }
+        for (src, text) in loaded { src.store(text, Validators::default()); }
+        Ok(())
+    }
+
+    // 最早到期的远程来源的拉取时间
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.sources().filter(|s| s.remote()).map(|s| *s.due.lock().unwrap_or_else(|e| e.into_inner())).min()
+    }
+
+    // 拉取到期（force 时为全部）的远程来源，返回是否有内容变化
+    pub(crate) async fn refresh_remote(&self, force: bool) -> bool {
+        let mut changed = false;
+        for src in self.sources().filter(|s| s.remote()) {
+            let now = Instant::now();
+            if !force && *src.due.lock().unwrap_or_else(|e| e.into_inner()) > now { continue; }
+            let validators = src.loaded.lock().unwrap_or_else(|e| e.into_inner()).validators.clone();
+            let next = match fetch(&src.path, &src.iface, &validators).await {
+                Ok(Fetched::NotModified) => src.interval,
+                Ok(Fetched::Body(text, v)) => {
+                    if let Some(dir) = &self.cache_dir { src.save_cache(dir, &text, &v); }
+                    changed |= src.store(text, v);
+                    src.interval
+                }
+                Err(e) => {
+                    log_error(format!("rule set: {}", e));
+                    src.interval.min(Duration::from_secs(RETRY_SECS))
+                }
+            };
+            *src.due.lock().unwrap_or_else(|e| e.into_inner()) = now + next;
+        }
+        changed
+    }
+
+    // 转换成规则，第二项为每个来源一行的摘要
+    pub(crate) fn rules(&self) -> Result<(Vec<Rule>, Vec<String>)> {
+        let mut countries: HashMap<String, Vec<String>> = HashMap::new();
+        for (spec, src) in &self.geoip {
+            if let Some(text) = src.text() { countries.entry(spec.country.clone()).or_default().extend(parse_cidr_list(&spec.source, &text)); }
+        }
+        let (mut rules, mut summary) = (Vec::new(), Vec::new());
+        for (spec, src) in &self.sets {
+            let Some(text) = src.text() else {
+                summary.push(format!("{}: not loaded yet", spec.source));
+                continue;
+            };
+            let c = convert(spec, &text, &countries).map_err(|e| anyhow::anyhow!("rule set {}: {}", spec.source, e))?;
+            let skipped: Vec<String> = c.skipped.iter().map(|(k, n)| format!("{} x{}", k, n)).collect();
+            summary.push(format!(
+                "{}: {} entries -> {} rule(s){}",
+                spec.source,
+                c.entries,
+                c.rules.len(),
+                if skipped.is_empty() { String::new() } else { format!(", skipped {}", skipped.join(", ")) }
+            ));
+            rules.extend(c.rules);
+        }
+        Ok((rules, summary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::Route;
+
+    fn route(set: &crate::rules::RuleSet, host: &str, port: u16) -> Route {
+        set.evaluate(&crate::router::Query { client: "127.0.0.1:5000", protocol: "connect", host, port, sni: None, user: None, uid: None, process: None, process_path: None }).0.route
+    }
+
+    fn rules(spec: &str, text: &str) -> Converted {
+        let geoip = HashMap::from([(String::from("CN"), vec![String::from("1.0.1.0/24"), String::from("36.0.0.0/10")])]);
+        convert(&RuleSetSpec::parse(spec).unwrap(), text, &geoip).unwrap()
+    }
+
+    #[test]
+    fn clash_config_keeps_policy_order() {
+        let text = "port: 7890\nproxies:\n  - name: remote\n    type: ss\nrules:\n  - DOMAIN-SUFFIX,google.com,remote\n  - DOMAIN-KEYWORD,ads,REJECT\n  - DOMAIN,ads.example.com,REJECT\n  - IP-CIDR,10.0.0.0/8,DIRECT,no-resolve\n  - GEOIP,CN,DIRECT\n  - PROCESS-NAME,curl,DIRECT\n  - MATCH,remote\n";
+        let c = rules("rules.yaml", text);
+        assert_eq!(c.entries, 6);
+        assert_eq!(c.skipped.get("PROCESS-NAME"), Some(&1));
+        let set = crate::rules::RuleSet::new(c.rules, &[String::from("remote")]).unwrap();
+        let hit = |host: &str| route(&set, host, 443);
+        assert_eq!(hit("www.google.com"), Route::Upstream(String::from("remote")));
+        assert_eq!(hit("badads.net"), Route::Block);
+        assert_eq!(hit("10.1.1.1"), Route::Direct(None));
+        assert_eq!(hit("36.1.2.3"), Route::Direct(None));
+        assert_eq!(hit("8.8.8.8"), Route::Upstream(String::from("remote")));
+    }
+
+    #[test]
+    fn surge_and_provider_lists_take_the_given_action() {
+        let text = "# surge list\nDOMAIN-SUFFIX,example.com\nIP-CIDR6,2001:db8::/32,no-resolve\nGEOIP,US\nUSER-AGENT,foo*\n";
+        let c = rules("priority=3 ads.list => block", text);
+        assert_eq!(c.entries, 2);
+        assert_eq!(c.skipped.len(), 2);
+        let provider = "payload:\n  - '+.example.org'\n  - 'exact.example.net'\n  - '192.168.0.0/16'\n";
+        let c = rules("provider.yaml => iface:en7", provider);
+        assert_eq!(c.entries, 3);
+        let set = crate::rules::RuleSet::new(c.rules, &[]).unwrap();
+        assert_eq!(route(&set, "a.example.org", 80), Route::Direct(Some(String::from("en7"))));
+        assert_eq!(route(&set, "sub.exact.example.net", 80), Route::Default);
+        assert_eq!(route(&set, "192.168.1.1", 80), Route::Direct(Some(String::from("en7"))));
+    }
+
+    #[test]
+    fn spec_options_and_disk_cache() {
+        let spec = RuleSetSpec::parse("priority=2 interval=600 iface=en7 https://example.com/a.list?x=1 => block").unwrap();
+        assert_eq!((spec.priority, spec.interval, spec.iface.as_deref()), (2, Some(600), Some("en7")));
+        assert_eq!(spec.source, "https://example.com/a.list?x=1");
+        assert!(RuleSetSpec::parse("interval=0 a.list").is_err());
+        assert!(RuleSetSpec::parse("a.list b.list").is_err());
+
+        let dir = std::env::temp_dir().join(format!("iface-proxy-ruleset-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let opts = FetchOptions { iface: String::from("en0"), interval: Duration::from_secs(60), cache_dir: Some(dir.clone()) };
+        let src = Source::new(&spec.source, spec.iface.as_deref(), spec.interval, &opts);
+        let v = Validators { etag: Some(String::from("\"abc\"")), last_modified: None };
+        src.save_cache(&dir, "DOMAIN,a.test\n", &v);
+        let restored = Source::new(&spec.source, None, None, &opts);
+        restored.load_cache(&dir);
+        assert_eq!(restored.text().as_deref(), Some("DOMAIN,a.test\n"));
+        assert_eq!(restored.loaded.lock().unwrap().validators, v);
+        assert_eq!((restored.iface.as_str(), restored.interval), ("en0", Duration::from_secs(60)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
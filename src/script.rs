@@ -0,0 +1,170 @@
+use anyhow::Result;
+
+use crate::router::{Decision, Query, Router};
+#[cfg(feature = "lua")]
+use crate::router::Route;
+#[cfg(feature = "lua")]
+use crate::util::{log_error, log_info};
+
+// --script：内嵌 Lua 5.4 的路由钩子（需以 `--features lua` 编译），脚本中的 route(conn) 为每个出站连接返回去向
+
+// 单次调用的执行时间上限，超出按脚本出错处理
+#[cfg(feature = "lua")]
+const BUDGET_MS: u64 = 50;
+
+#[cfg(feature = "lua")]
+pub(crate) struct Engine {
+    path: String,
+    // 脚本文件修改后下次调用时重新加载；加载失败时沿用旧脚本
+    state: std::sync::Mutex<(mlua::Lua, Option<std::time::SystemTime>)>,
+}
+
+#[cfg(feature = "lua")]
+impl Engine {
+    pub(crate) fn load(path: &str) -> Result<Self> {
+        let mtime = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let lua = Self::compile(path)?;
+        Ok(Self { path: path.to_string(), state: std::sync::Mutex::new((lua, mtime)) })
+    }
+
+    fn compile(path: &str) -> Result<mlua::Lua> {
+        use mlua::{Function, HookTriggers, Lua};
+        let src = std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("read {}: {}", path, e))?;
+        let lua = Lua::new();
+        let lua_err = |e: mlua::Error| anyhow::anyhow!("{}: {}", path, e);
+        let log = lua.create_function(|_, msg: String| {
+            log_info(format!("script: {}", msg));
+            Ok(())
+        }).map_err(lua_err)?;
+        lua.globals().set("log", log).map_err(lua_err)?;
+        lua.load(src.as_str()).set_name(path).exec().map_err(lua_err)?;
+        lua.globals().get::<_, Function>("route").map_err(|_| anyhow::anyhow!("{} does not define a route(conn) function", path))?;
+        lua.set_hook(HookTriggers::new().every_nth_instruction(1000), |lua, _| match lua.app_data_ref::<std::time::Instant>() {
+            Some(deadline) if std::time::Instant::now() > *deadline => Err(mlua::Error::runtime(format!("exceeded {}ms", BUDGET_MS))),
+            _ => Ok(()),
+        });
+        Ok(lua)
+    }
+
+    fn reload_if_changed(&self, state: &mut (mlua::Lua, Option<std::time::SystemTime>)) {
+        let mtime = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if mtime.is_none() || mtime == state.1 { return; }
+        state.1 = mtime;
+        match Self::compile(&self.path) {
+            Ok(lua) => {
+                state.0 = lua;
+                log_info(format!("script {} reloaded", self.path));
+            }
+            Err(e) => log_error(format!("script reload failed, keeping the previous version: {}", e)),
+        }
+    }
+
+    // route(conn) 的返回值：nil、"default"/"direct"/"block"，或 {iface=, upstream=, block=, direct=, host=, port=}
+    fn decision(ret: mlua::Value<'_>) -> Result<Decision> {
+        use mlua::Value;
+        match ret {
+            Value::Nil => Ok(Decision::new(Route::Default)),
+            Value::String(s) => {
+                let route = match s.to_str()? {
+                    "default" => Route::Default,
+                    "direct" => Route::Direct(None),
+                    "block" => Route::Block,
+                    other => anyhow::bail!("unknown action {:?} (expected default, direct, block or a table)", other),
+                };
+                Ok(Decision::new(route))
+            }
+            Value::Table(t) => {
+                let iface: Option<String> = t.get("iface")?;
+                let upstream: Option<String> = t.get("upstream")?;
+                let block: Option<bool> = t.get("block")?;
+                let direct: Option<bool> = t.get("direct")?;
+                let route = match (block.unwrap_or(false), upstream, iface) {
+                    (true, None, None) => Route::Block,
+                    (false, Some(name), None) => Route::Upstream(name),
+                    (false, None, Some(iface)) => Route::Direct(Some(iface)),
+                    (false, None, None) if direct.unwrap_or(false) => Route::Direct(None),
+                    (false, None, None) => Route::Default,
+                    _ => anyhow::bail!("block, upstream and iface are mutually exclusive"),
+                };
+                Ok(Decision { route, host: t.get("host")?, port: t.get("port")?, resolve: None, resolution: None })
+            }
+            other => anyhow::bail!("route() returned a {}, expected nil, a string or a table", other.type_name()),
+        }
+    }
+}
+
+#[cfg(feature = "lua")]
+impl Router for Engine {
+    fn route(&self, q: &Query<'_>) -> Result<Decision> {
+        use mlua::{Function, Value};
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        self.reload_if_changed(&mut state);
+        let lua = &state.0;
+        let conn = lua.create_table()?;
+        conn.set("client", q.client)?;
+        conn.set("protocol", q.protocol)?;
+        conn.set("host", q.host)?;
+        conn.set("port", q.port)?;
+        conn.set("sni", q.sni)?;
+        conn.set("user", q.user)?;
+        conn.set("uid", q.uid)?;
+        conn.set("process", q.process)?;
+        conn.set("process_path", q.process_path)?;
+        lua.set_app_data(std::time::Instant::now() + std::time::Duration::from_millis(BUDGET_MS));
+        let route: Function = lua.globals().get("route")?;
+        let ret = route.call::<_, Value>(conn);
+        lua.remove_app_data::<std::time::Instant>();
+        // 返回值借用着 Lua 状态，须在释放锁之前转换完
+        let decision = Self::decision(ret?)?;
+        Ok(decision)
+    }
+}
+
+#[cfg(not(feature = "lua"))]
+pub(crate) enum Engine {}
+
+#[cfg(not(feature = "lua"))]
+impl Engine {
+    pub(crate) fn load(_path: &str) -> Result<Self> {
+        anyhow::bail!("--script needs the lua feature (cargo build --features lua)")
+    }
+}
+
+#[cfg(not(feature = "lua"))]
+impl Router for Engine {
+    fn route(&self, _q: &Query<'_>) -> Result<Decision> {
+        match *self {}
+    }
+}
+
+#[cfg(all(test, feature = "lua"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lua_route_returns_decisions() {
+        let path = std::env::temp_dir().join(format!("iface-proxy-script-{}.lua", std::process::id()));
+        std::fs::write(&path, r#"
+            function route(c)
+              if c.host == "ads.example.com" then return "block" end
+              if c.sni == "video.example.com" then return { upstream = "remote" } end
+              if c.user == "alice" then return { iface = "en7", host = "alt.example.com", port = 8443 } end
+              if c.port == 22 then return "direct" end
+              if c.host == "loop" then while true do end end
+            end
+        "#).unwrap();
+        let engine = Engine::load(path.to_str().unwrap()).unwrap();
+        let q = |host, port, sni, user| Query { client: "127.0.0.1:5000", protocol: "connect", host, port, sni, user, uid: None, process: None, process_path: None };
+        let d = Decision::new;
+        assert_eq!(engine.route(&q("ads.example.com", 443, None, None)).unwrap(), d(Route::Block));
+        assert_eq!(engine.route(&q("1.2.3.4", 443, Some("video.example.com"), None)).unwrap(), d(Route::Upstream(String::from("remote"))));
+        assert_eq!(
+            engine.route(&q("example.com", 443, None, Some("alice"))).unwrap(),
+            Decision { route: Route::Direct(Some(String::from("en7"))), host: Some(String::from("alt.example.com")), port: Some(8443), resolve: None, resolution: None }
+        );
+        assert_eq!(engine.route(&q("example.com", 22, None, None)).unwrap(), d(Route::Direct(None)));
+        assert_eq!(engine.route(&q("example.com", 80, None, None)).unwrap(), d(Route::Default));
+        assert!(engine.route(&q("loop", 80, None, None)).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
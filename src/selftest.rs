@@ -0,0 +1,237 @@
+use anyhow::Result;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+
+use crate::check::Report;
+use crate::cli::SelfTestArgs;
+use crate::listener::{BoundSet, ListenerContext, ListenerKind, ListenerSpec};
+
+// self-test：在 127.0.0.1 的临时端口上启动 HTTP 与 SOCKS5 监听，经自身分别做一次明文 GET、
+// 一次到 TLS 主机的 CONNECT 和一次 SOCKS5 CONNECT，报告回显服务看到的出口 IP
+const STEP_TIMEOUT_MS: u64 = 10_000;
+
+// http://HOST[:PORT][/PATH]
+fn parse_echo_url(url: &str) -> Result<(String, u16, String)> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| anyhow::anyhow!("echo url {:?} must start with http://", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((h, p)) if !h.ends_with(':') => (h, p.parse().map_err(|_| anyhow::anyhow!("invalid port in echo url {:?}", url))?),
+        _ => (authority, 80),
+    };
+    if host.is_empty() { anyhow::bail!("echo url {:?} has no host", url); }
+    Ok((host.trim_start_matches('[').trim_end_matches(']').to_string(), port, path.to_string()))
+}
+
+fn split_host_port(s: &str, default_port: u16) -> (String, u16) {
+    match s.rsplit_once(':') {
+        Some((h, p)) if p.parse::<u16>().is_ok() => (h.trim_start_matches('[').trim_end_matches(']').to_string(), p.parse().unwrap_or(default_port)),
+        _ => (s.to_string(), default_port),
+    }
+}
+
+fn authority(host: &str, port: u16) -> String {
+    if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) }
+}
+
+async fn read_to_end<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    let mut tmp = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut tmp).await?;
+        if n == 0 || buf.len() > 64 * 1024 { return Ok(buf); }
+        buf.extend_from_slice(&tmp[..n]);
+    }
+}
+
+// 读到响应头结束，返回状态码
+async fn read_status<S: AsyncRead + Unpin>(stream: &mut S) -> Result<String> {
+    let mut buf = Vec::new();
+    let mut b = [0u8; 1];
+    while !buf.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut b).await? == 0 { anyhow::bail!("connection closed before response headers"); }
+        buf.push(b[0]);
+        if buf.len() > 16 * 1024 { anyhow::bail!("response headers too large"); }
+    }
+    Ok(String::from_utf8_lossy(&buf).split_whitespace().nth(1).unwrap_or("").to_string())
+}
+
+// 用 HTTP/1.0 请求回显服务，避免 chunked；返回响应正文（即出口 IP）
+async fn fetch_echo<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, target: &str, host: &str, port: u16, extra_headers: &str) -> Result<String> {
+    let req = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: {}\r\n{}Connection: close\r\n\r\n",
+        target,
+        authority(host, port),
+        crate::build_info::AGENT,
+        extra_headers
+    );
+    stream.write_all(req.as_bytes()).await?;
+    let resp = read_to_end(stream).await?;
+    let text = String::from_utf8_lossy(&resp);
+    let (head, body) = text.split_once("\r\n\r\n").ok_or_else(|| anyhow::anyhow!("malformed response"))?;
+    if head.split_whitespace().nth(1) != Some("200") { anyhow::bail!("got {}", head.lines().next().unwrap_or("")); }
+    let body = body.trim();
+    if body.is_empty() { anyhow::bail!("echo service returned an empty body"); }
+    Ok(body.chars().take(100).collect())
+}
+
+fn proxy_auth_header(auth: Option<(&str, &str)>) -> String {
+    use base64::Engine;
+    match auth {
+        Some((u, p)) => format!("Proxy-Authorization: Basic {}\r\n", base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", u, p))),
+        None => String::new(),
+    }
+}
+
+// 最小的 TLS 1.2 ClientHello（带 SNI），只用来确认隧道另一端确实是 TLS 服务
+pub(crate) fn client_hello(sni: &str) -> Vec<u8> {
+    let mut random = [0u8; 32];
+    let _ = getrandom::getrandom(&mut random);
+    let suites: [u16; 8] = [0xc02f, 0xc030, 0xc02b, 0xc02c, 0x009c, 0x009d, 0x002f, 0x0035];
+    let mut ext = Vec::new();
+    let name = sni.as_bytes();
+    ext.extend_from_slice(&[0x00, 0x00]);
+    ext.extend_from_slice(&((name.len() + 5) as u16).to_be_bytes());
+    ext.extend_from_slice(&((name.len() + 3) as u16).to_be_bytes());
+    ext.push(0);
+    ext.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    ext.extend_from_slice(name);
+    // supported_groups: x25519, secp256r1, secp384r1
+    ext.extend_from_slice(&[0x00, 0x0a, 0x00, 0x08, 0x00, 0x06, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x18]);
+    // ec_point_formats: uncompressed
+    ext.extend_from_slice(&[0x00, 0x0b, 0x00, 0x02, 0x01, 0x00]);
+    // signature_algorithms
+    ext.extend_from_slice(&[0x00, 0x0d, 0x00, 0x0a, 0x00, 0x08, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01, 0x05, 0x01]);
+    let mut body = vec![0x03, 0x03];
+    body.extend_from_slice(&random);
+    body.push(0);
+    body.extend_from_slice(&((suites.len() * 2) as u16).to_be_bytes());
+    for s in suites { body.extend_from_slice(&s.to_be_bytes()); }
+    body.extend_from_slice(&[0x01, 0x00]);
+    body.extend_from_slice(&(ext.len() as u16).to_be_bytes());
+    body.extend_from_slice(&ext);
+    let mut hs = vec![0x01];
+    hs.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    hs.extend_from_slice(&body);
+    let mut rec = vec![0x16, 0x03, 0x01];
+    rec.extend_from_slice(&(hs.len() as u16).to_be_bytes());
+    rec.extend_from_slice(&hs);
+    rec
+}
+
+async fn step<T>(fut: impl std::future::Future<Output = Result<T>>) -> Result<T> {
+    timeout(Duration::from_millis(STEP_TIMEOUT_MS), fut).await.map_err(|_| anyhow::anyhow!("timed out after {}s", STEP_TIMEOUT_MS / 1000))?
+}
+
+async fn test_http_get(proxy: SocketAddr, auth: Option<(&str, &str)>, echo: &(String, u16, String)) -> Result<String> {
+    let (host, port, path) = echo;
+    let mut s = TcpStream::connect(proxy).await?;
+    let url = format!("http://{}{}", authority(host, *port), path);
+    fetch_echo(&mut s, &url, host, *port, &proxy_auth_header(auth)).await
+}
+
+async fn test_connect_tls(proxy: SocketAddr, auth: Option<(&str, &str)>, tls_host: &str) -> Result<String> {
+    let (host, port) = split_host_port(tls_host, 443);
+    let mut s = TcpStream::connect(proxy).await?;
+    let target = authority(&host, port);
+    s.write_all(format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n{}\r\n", target, target, proxy_auth_header(auth)).as_bytes()).await?;
+    let status = read_status(&mut s).await?;
+    if status != "200" { anyhow::bail!("proxy answered {} to CONNECT", status); }
+    s.write_all(&client_hello(&host)).await?;
+    let mut rec = [0u8; 5];
+    s.read_exact(&mut rec).await?;
+    match rec[0] {
+        0x16 => Ok(format!("tunnel to {} established, TLS handshake answered", target)),
+        0x15 => Ok(format!("tunnel to {} established, TLS alert received (server reachable)", target)),
+        b => anyhow::bail!("tunnel established but peer did not answer with TLS (first byte 0x{:02x})", b),
+    }
+}
+
+async fn test_socks5(proxy: SocketAddr, auth: Option<(&str, &str)>, echo: &(String, u16, String)) -> Result<String> {
+    let (host, port, path) = echo;
+    let mut s = TcpStream::connect(proxy).await?;
+    s.write_all(&[0x05, 0x01, if auth.is_some() { 0x02 } else { 0x00 }]).await?;
+    let mut rep = [0u8; 2];
+    s.read_exact(&mut rep).await?;
+    if rep[1] == 0xFF { anyhow::bail!("no acceptable SOCKS5 auth method"); }
+    if let Some((u, p)) = auth {
+        let mut msg = vec![0x01, u.len() as u8];
+        msg.extend_from_slice(u.as_bytes());
+        msg.push(p.len() as u8);
+        msg.extend_from_slice(p.as_bytes());
+        s.write_all(&msg).await?;
+        s.read_exact(&mut rep).await?;
+        if rep[1] != 0x00 { anyhow::bail!("SOCKS5 authentication failed"); }
+    }
+    let mut req = vec![0x05, 0x01, 0x00, 0x03, host.len() as u8];
+    req.extend_from_slice(host.as_bytes());
+    req.extend_from_slice(&port.to_be_bytes());
+    s.write_all(&req).await?;
+    let mut head = [0u8; 10];
+    s.read_exact(&mut head).await?;
+    if head[1] != 0x00 { anyhow::bail!("SOCKS5 CONNECT failed (REP=0x{:02x})", head[1]); }
+    fetch_echo(&mut s, path, host, *port, "").await
+}
+
+// 出口 IP 是否为所选网卡自身的地址（否则多半经过了 NAT）
+fn describe_ip(ip: &str, iface: &str) -> String {
+    let Ok(addr) = ip.parse::<IpAddr>() else { return format!("egress reported {:?}", ip) };
+    let local = crate::util::list_interfaces().ok().and_then(|l| l.into_iter().find(|i| i.name == iface)).map(|i| i.addrs).unwrap_or_default();
+    if local.contains(&addr) {
+        format!("egress IP {} (address of {})", addr, iface)
+    } else {
+        format!("egress IP {} (not an address of {}, likely NAT)", addr, iface)
+    }
+}
+
+pub(crate) async fn run_self_test(args: SelfTestArgs) -> Result<()> {
+    let mut report = Report::new();
+    let run = &args.run;
+    let echo = parse_echo_url(&args.echo_url)?;
+    report.item(&format!("iface {}", run.iface), crate::check::check_iface(&run.iface));
+    let table = run.upstream_table()?;
+    report.item("upstreams", Ok(table.summary()));
+    crate::upstream::install(table);
+
+    let ctx = Arc::new(ListenerContext {
+        iface: run.iface.clone(),
+        socks5_user: run.socks5_user.clone(),
+        socks5_pass: run.socks5_pass.clone(),
+        // 自检只验证出口，不经 --auth 后端
+        auth: None,
+        ss: None,
+        dns_upstreams: Arc::new(Vec::new()),
+        udp_idle_secs: run.udp_idle_secs,
+        conns: Arc::new(tokio::sync::Semaphore::new(16)),
+        conns_wait_ms: 0,
+        handshakes: Arc::new(tokio::sync::Semaphore::new(16)),
+        read_timeout_ms: run.read_timeout_ms,
+        session_timeout_ms: STEP_TIMEOUT_MS,
+        allow_client: Vec::new(),
+        deny_dest: Vec::new(),
+    });
+    let bound = BoundSet::bind(vec![ListenerSpec::new(ListenerKind::Http, "127.0.0.1:0"), ListenerSpec::new(ListenerKind::Socks5, "127.0.0.1:0")]).await?;
+    let addrs = [ListenerKind::Http, ListenerKind::Socks5].map(|k| bound.addr(k).expect("tcp listener"));
+    bound.spawn(&ctx);
+    let auth = run.socks5_user.as_deref().zip(run.socks5_pass.as_deref());
+
+    let http_ip = step(test_http_get(addrs[0], auth, &echo)).await;
+    report.item(&format!("http GET {}", args.echo_url), http_ip.as_deref().map(|ip| describe_ip(ip, &run.iface)).map_err(|e| anyhow::anyhow!("{}", e)));
+    report.item(&format!("http CONNECT {}", args.tls_host), step(test_connect_tls(addrs[0], auth, &args.tls_host)).await);
+    let socks_ip = step(test_socks5(addrs[1], auth, &echo)).await;
+    report.item(&format!("socks5 CONNECT {}", authority(&echo.0, echo.1)), socks_ip.as_deref().map(|ip| describe_ip(ip, &run.iface)).map_err(|e| anyhow::anyhow!("{}", e)));
+    if let (Ok(a), Ok(b)) = (&http_ip, &socks_ip) {
+        if a != b { report.item("egress consistency", Err(anyhow::anyhow!("http saw {} but socks5 saw {}", a, b))); }
+    }
+
+    if report.failures > 0 {
+        anyhow::bail!("{} check(s) failed", report.failures);
+    }
+    println!("self-test OK");
+    Ok(())
+}
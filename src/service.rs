@@ -0,0 +1,113 @@
+use anyhow::Result;
+
+use crate::cli::ServiceCommand;
+
+pub(crate) fn run(cmd: ServiceCommand) -> Result<()> {
+    match cmd {
+        ServiceCommand::Install { config } => {
+            let abs = std::fs::canonicalize(&config).map_err(|e| anyhow::anyhow!("config {}: {}", config, e))?;
+            install(&abs.to_string_lossy())
+        }
+        ServiceCommand::Uninstall => uninstall(),
+        ServiceCommand::Status => status(),
+    }
+}
+
+fn run_cmd(program: &str, args: &[&str]) -> Result<()> {
+    let status = std::process::Command::new(program).args(args).status()
+        .map_err(|e| anyhow::anyhow!("failed to run {}: {}", program, e))?;
+    if !status.success() { anyhow::bail!("{} {} failed ({})", program, args.join(" "), status); }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+const LAUNCHD_LABEL: &str = "io.github.notfound945.iface-proxy";
+
+#[cfg(target_os = "macos")]
+fn plist_path() -> Result<std::path::PathBuf> {
+    let home = std::env::var("HOME")?;
+    Ok(std::path::PathBuf::from(home).join(format!("Library/LaunchAgents/{}.plist", LAUNCHD_LABEL)))
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn install(config_path: &str) -> Result<()> {
+    let home = std::env::var("HOME")?;
+    let exe = std::env::current_exe()?;
+    let plist_path = plist_path()?;
+    let plist = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n<plist version=\"1.0\">\n<dict>\n  <key>Label</key>\n  <string>{label}</string>\n  <key>ProgramArguments</key>\n  <array>\n    <string>{exe}</string>\n    <string>run</string>\n    <string>--config</string>\n    <string>{config}</string>\n  </array>\n  <key>RunAtLoad</key>\n  <true/>\n  <key>KeepAlive</key>\n  <true/>\n  <key>StandardOutPath</key>\n  <string>{home}/Library/Logs/iface-proxy.log</string>\n  <key>StandardErrorPath</key>\n  <string>{home}/Library/Logs/iface-proxy.log</string>\n</dict>\n</plist>\n",
+        label = LAUNCHD_LABEL,
+        exe = exe.display(),
+        config = config_path,
+        home = home,
+    );
+    crate::init::write_private_file(&plist_path, &plist)?;
+    println!("已写入 {}", plist_path.display());
+    run_cmd("launchctl", &["load", "-w", &plist_path.to_string_lossy()])?;
+    println!("已通过 launchd 注册并启动服务，日志: {}/Library/Logs/iface-proxy.log", home);
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn uninstall() -> Result<()> {
+    let plist_path = plist_path()?;
+    if !plist_path.exists() { anyhow::bail!("service is not installed ({} not found)", plist_path.display()); }
+    run_cmd("launchctl", &["unload", "-w", &plist_path.to_string_lossy()])?;
+    std::fs::remove_file(&plist_path)?;
+    println!("已停止并移除服务 {}", LAUNCHD_LABEL);
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn status() -> Result<()> {
+    run_cmd("launchctl", &["list", LAUNCHD_LABEL])
+}
+
+#[cfg(target_os = "linux")]
+const SYSTEMD_UNIT: &str = "/etc/systemd/system/iface-proxy.service";
+
+#[cfg(target_os = "linux")]
+pub(crate) fn install(config_path: &str) -> Result<()> {
+    let exe = std::env::current_exe()?;
+    let unit = format!(
+        "[Unit]\nDescription=iface-proxy\nAfter=network-online.target\nWants=network-online.target\n\n[Service]\nExecStart={exe} run --config {config}\nRestart=on-failure\nLimitNOFILE=65536\n\n[Install]\nWantedBy=multi-user.target\n",
+        exe = exe.display(),
+        config = config_path,
+    );
+    crate::init::write_private_file(std::path::Path::new(SYSTEMD_UNIT), &unit)?;
+    println!("已写入 {}", SYSTEMD_UNIT);
+    run_cmd("systemctl", &["daemon-reload"])?;
+    run_cmd("systemctl", &["enable", "--now", "iface-proxy"])?;
+    println!("已通过 systemd 注册并启动服务，日志: journalctl -u iface-proxy");
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn uninstall() -> Result<()> {
+    if !std::path::Path::new(SYSTEMD_UNIT).exists() { anyhow::bail!("service is not installed ({} not found)", SYSTEMD_UNIT); }
+    run_cmd("systemctl", &["disable", "--now", "iface-proxy"])?;
+    std::fs::remove_file(SYSTEMD_UNIT)?;
+    run_cmd("systemctl", &["daemon-reload"])?;
+    println!("已停止并移除服务 iface-proxy");
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn status() -> Result<()> {
+    run_cmd("systemctl", &["status", "--no-pager", "iface-proxy"])
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+pub(crate) fn install(_config_path: &str) -> Result<()> {
+    anyhow::bail!("service registration is only supported on macOS and Linux")
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn uninstall() -> Result<()> {
+    anyhow::bail!("service registration is only supported on macOS and Linux")
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn status() -> Result<()> {
+    anyhow::bail!("service registration is only supported on macOS and Linux")
+}
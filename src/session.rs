@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+// 每个接受的连接分配一个递增 ID；会话内（含握手、出站连接、结束与错误）打印的日志都带 `[#ID]`，
+// 便于在并发会话交错的日志中按会话筛选。活动会话可通过管理接口 /sessions 查看
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+// 已结束的会话数，及其中打印过错误日志的会话数
+static FINISHED: AtomicU64 = AtomicU64::new(0);
+static FAILED: AtomicU64 = AtomicU64::new(0);
+
+tokio::task_local! {
+    static CURRENT: u64;
+    // 所属监听上设置的出站 TCP 选项
+    static SOCKOPTS: crate::util::SockOpts;
+}
+
+struct Info {
+    kind: &'static str,
+    peer: String,
+    target: String,
+    iface: String,
+    // 路由脚本用：实际协议（混合端口识别后才知道）、认证用户名、CONNECT 时随请求带来的 TLS SNI
+    protocol: &'static str,
+    user: Option<String>,
+    sni: Option<String>,
+    // 本机客户端的属主 UID 与所属进程（启用查找且查到时）
+    uid: Option<u32>,
+    process: Option<crate::process::Process>,
+    // --record-dir 时客户端连接上的原始字节，会话出错结束时写入文件
+    tape: Option<Arc<Mutex<crate::record::Tape>>>,
+    // 最近一次出站连接的本地地址，出口地址变化时据此找出受影响的会话
+    local: Option<SocketAddr>,
+    // 最近一次出站连接的原始目标与路由决定，规则重新加载后据此找出去向已变化的会话
+    routed: Option<(String, u16, crate::router::Decision)>,
+    // 握手完成前占用的 --max-handshakes 名额
+    handshake: Option<crate::handshake::Permit>,
+    // 通知后会话立即结束（关闭两端连接）
+    cancel: Arc<Cancel>,
+    activity: Arc<Activity>,
+    started: Instant,
+    failed: bool,
+}
+
+struct Cancel {
+    notify: Notify,
+    // 结束原因，打印在日志里
+    reason: OnceLock<String>,
+}
+
+impl Cancel {
+    fn close(&self, reason: impl Into<String>) {
+        let _ = self.reason.set(reason.into());
+        self.notify.notify_one();
+    }
+}
+
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+// 会话经出站连接转发的字节数与最近一次收发的时间（epoch 起的毫秒），由 stats::Metered 更新
+#[derive(Default)]
+pub(crate) struct Activity {
+    up: AtomicU64,
+    down: AtomicU64,
+    last_ms: AtomicU64,
+}
+
+impl Activity {
+    pub(crate) fn add(&self, up: u64, down: u64) {
+        if up > 0 { self.up.fetch_add(up, Ordering::Relaxed); }
+        if down > 0 { self.down.fetch_add(down, Ordering::Relaxed); }
+        self.touch();
+    }
+
+    fn touch(&self) {
+        self.last_ms.store(epoch().elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn idle(&self) -> Duration {
+        epoch().elapsed().saturating_sub(Duration::from_millis(self.last_ms.load(Ordering::Relaxed)))
+    }
+}
+
+fn table() -> &'static Mutex<HashMap<u64, Info>> {
+    static TABLE: OnceLock<Mutex<HashMap<u64, Info>>> = OnceLock::new();
+    TABLE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// 会话结束（含 panic、被取消）时移出活动表
+struct Guard(u64);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let info = table().lock().unwrap_or_else(|e| e.into_inner()).remove(&self.0);
+        FINISHED.fetch_add(1, Ordering::Relaxed);
+        if let Some(i) = &info {
+            crate::access_log::record(&crate::access_log::Entry {
+                id: self.0,
+                kind: i.kind,
+                protocol: i.protocol,
+                peer: &i.peer,
+                user: i.user.as_deref(),
+                target: &i.target,
+                iface: &i.iface,
+                up: i.activity.up.load(Ordering::Relaxed),
+                down: i.activity.down.load(Ordering::Relaxed),
+                duration: i.started.elapsed(),
+                failed: i.failed,
+            });
+        }
+        let Some(i) = info.filter(|i| i.failed) else { return };
+        FAILED.fetch_add(1, Ordering::Relaxed);
+        if let Some(tape) = &i.tape { crate::record::save(self.0, i.kind, &i.peer, &i.target, tape); }
+    }
+}
+
+// 以新的会话 ID 运行 `fut`，`peer` 为客户端地址（unix socket 为 "unix"），`sockopts` 为所属监听的出站 TCP 选项
+pub(crate) async fn run<F: Future<Output = ()>>(kind: &'static str, peer: String, sockopts: crate::util::SockOpts, fut: F) {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let cancel = Arc::new(Cancel { notify: Notify::new(), reason: OnceLock::new() });
+    let activity = Arc::new(Activity::default());
+    activity.touch();
+    let info = Info {
+        kind,
+        peer,
+        target: String::new(),
+        iface: String::new(),
+        protocol: kind,
+        user: None,
+        sni: None,
+        uid: None,
+        process: None,
+        tape: None,
+        local: None,
+        routed: None,
+        handshake: None,
+        cancel: cancel.clone(),
+        activity,
+        started: Instant::now(),
+        failed: false,
+    };
+    table().lock().unwrap_or_else(|e| e.into_inner()).insert(id, info);
+    let _guard = Guard(id);
+    CURRENT.scope(id, async move {
+        tokio::select! {
+            _ = SOCKOPTS.scope(sockopts, fut) => {}
+            _ = cancel.notify.notified() => crate::util::log_info(format!("session closed: {}", cancel.reason.get().map_or("cancelled", |r| r.as_str()))),
+        }
+    }).await
+}
+
+pub(crate) fn current() -> Option<u64> {
+    CURRENT.try_with(|id| *id).ok()
+}
+
+pub(crate) fn sockopts() -> crate::util::SockOpts {
+    SOCKOPTS.try_with(|o| *o).unwrap_or_default()
+}
+
+// 日志前缀，会话外为空
+pub(crate) fn tag() -> String {
+    current().map(|id| format!("[#{}] ", id)).unwrap_or_default()
+}
+
+fn update(f: impl FnOnce(&mut Info)) {
+    let Some(id) = current() else { return };
+    if let Some(info) = table().lock().unwrap_or_else(|e| e.into_inner()).get_mut(&id) {
+        f(info);
+    }
+}
+
+// 记录当前会话的出站目标与网卡
+pub(crate) fn set_target(host: &str, port: u16, iface: &str) {
+    update(|i| {
+        i.target = format!("{}:{}", host, port);
+        i.iface = iface.to_string();
+    });
+}
+
+pub(crate) fn set_local(addr: SocketAddr) {
+    update(|i| i.local = Some(addr));
+}
+
+pub(crate) fn set_routed(host: &str, port: u16, decision: &crate::router::Decision) {
+    update(|i| i.routed = Some((host.to_string(), port, decision.clone())));
+}
+
+// 用 `route`（重新加载后的规则）重新判断各会话最近一次出站连接，返回去向或改写目标已变化的会话
+pub(crate) fn rerouted(route: impl Fn(&crate::router::Query<'_>) -> crate::router::Decision) -> Vec<u64> {
+    let t = table().lock().unwrap_or_else(|e| e.into_inner());
+    t.iter()
+        .filter_map(|(id, i)| {
+            let (host, port, old) = i.routed.as_ref()?;
+            let process = i.process.as_ref();
+            let q = crate::router::Query {
+                client: &i.peer,
+                protocol: i.protocol,
+                host,
+                port: *port,
+                sni: i.sni.as_deref(),
+                user: i.user.as_deref(),
+                uid: i.uid,
+                process: process.map(|p| p.name.as_str()),
+                process_path: process.map(|p| p.path.as_str()),
+            };
+            (route(&q) != *old).then_some(*id)
+        })
+        .collect()
+}
+
+// 结束 `ids` 中仍在的会话，返回结束的个数
+pub(crate) fn close_ids(ids: &[u64], reason: &str) -> usize {
+    let t = table().lock().unwrap_or_else(|e| e.into_inner());
+    ids.iter().filter_map(|id| t.get(id)).map(|i| i.cancel.close(reason)).count()
+}
+
+// 结束出站本地地址满足 `pred` 的会话，返回结束的个数
+pub(crate) fn close_where(pred: impl Fn(IpAddr) -> bool) -> usize {
+    let t = table().lock().unwrap_or_else(|e| e.into_inner());
+    let hits: Vec<&Info> = t.values().filter(|i| i.local.is_some_and(|a| pred(a.ip()))).collect();
+    hits.iter().for_each(|i| i.cancel.close("its outbound address is no longer on the interface"));
+    hits.len()
+}
+
+// 管理接口 POST /sessions/kill；会话不存在时返回 false
+pub(crate) fn kill(id: u64) -> bool {
+    let t = table().lock().unwrap_or_else(|e| e.into_inner());
+    let Some(i) = t.get(&id) else { return false };
+    i.cancel.close("killed via admin API");
+    true
+}
+
+// 超过 max_idle 没有收发数据的会话
+fn idle_sessions(max_idle: Duration) -> Vec<(u64, Duration)> {
+    let t = table().lock().unwrap_or_else(|e| e.into_inner());
+    t.iter().map(|(id, i)| (*id, i.activity.idle())).filter(|(_, idle)| *idle >= max_idle).collect()
+}
+
+fn evict_idle(max_idle: Duration) -> usize {
+    let idle = idle_sessions(max_idle);
+    let t = table().lock().unwrap_or_else(|e| e.into_inner());
+    for (id, d) in &idle {
+        if let Some(i) = t.get(id) { i.cancel.close(format!("idle for {}s", d.as_secs())); }
+    }
+    idle.len()
+}
+
+// --session-idle-secs：后台定期清理空闲会话，0 为不启用；须在 tokio 运行时中调用
+pub(crate) fn spawn_idle_eviction(idle_secs: u64) {
+    if idle_secs == 0 { return; }
+    let max_idle = Duration::from_secs(idle_secs);
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval((max_idle / 4).clamp(Duration::from_secs(1), Duration::from_secs(30)));
+        loop {
+            tick.tick().await;
+            let n = evict_idle(max_idle);
+            if n > 0 { crate::util::log_info(format!("closed {} session(s) idle for more than {}s", n, idle_secs)); }
+        }
+    });
+}
+
+// 当前会话的活动计数，会话外为 None
+pub(crate) fn activity() -> Option<Arc<Activity>> {
+    let id = current()?;
+    table().lock().unwrap_or_else(|e| e.into_inner()).get(&id).map(|i| i.activity.clone())
+}
+
+// 出站本地地址满足 `pred` 的会话数
+pub(crate) fn count_where(pred: impl Fn(IpAddr) -> bool) -> usize {
+    table().lock().unwrap_or_else(|e| e.into_inner()).values().filter(|i| i.local.is_some_and(|a| pred(a.ip()))).count()
+}
+
+// 当前会话占用握手名额，直到 handshake_done 或会话结束
+pub(crate) fn hold_handshake(permit: crate::handshake::Permit) {
+    update(|i| i.handshake = Some(permit));
+}
+
+// 已读完客户端请求，归还握手名额
+pub(crate) fn handshake_done() {
+    update(|i| i.handshake = None);
+}
+
+pub(crate) fn set_protocol(protocol: &'static str) {
+    update(|i| i.protocol = protocol);
+}
+
+pub(crate) fn set_user(user: &str) {
+    update(|i| i.user = Some(user.to_string()));
+}
+
+pub(crate) fn set_sni(sni: String) {
+    update(|i| i.sni = Some(sni));
+}
+
+pub(crate) fn set_tape(tape: Arc<Mutex<crate::record::Tape>>) {
+    update(|i| i.tape = Some(tape));
+}
+
+pub(crate) fn set_owner(uid: Option<u32>, process: Option<crate::process::Process>) {
+    update(|i| {
+        i.uid = uid;
+        i.process = process;
+    });
+}
+
+// 当前会话的客户端信息，供路由脚本使用
+pub(crate) struct Client {
+    pub(crate) peer: String,
+    pub(crate) protocol: &'static str,
+    pub(crate) user: Option<String>,
+    pub(crate) sni: Option<String>,
+    pub(crate) uid: Option<u32>,
+    pub(crate) process: Option<crate::process::Process>,
+}
+
+pub(crate) fn client() -> Option<Client> {
+    let id = current()?;
+    let t = table().lock().unwrap_or_else(|e| e.into_inner());
+    let i = t.get(&id)?;
+    Some(Client { peer: i.peer.clone(), protocol: i.protocol, user: i.user.clone(), sni: i.sni.clone(), uid: i.uid, process: i.process.clone() })
+}
+
+// 当前会话的客户端 IP（Unix socket 上的会话没有）
+pub(crate) fn peer_ip() -> Option<IpAddr> {
+    let id = current()?;
+    let t = table().lock().unwrap_or_else(|e| e.into_inner());
+    t.get(&id)?.peer.parse::<SocketAddr>().ok().map(|a| a.ip())
+}
+
+// 由 log_error 调用：当前会话记为出错
+pub(crate) fn mark_failed() {
+    update(|i| i.failed = true);
+}
+
+// (已接受, 已结束, 出错) 的累计会话数
+pub(crate) fn counters() -> (u64, u64, u64) {
+    let accepted = NEXT_ID.load(Ordering::Relaxed) - 1;
+    (accepted, FINISHED.load(Ordering::Relaxed), FAILED.load(Ordering::Relaxed))
+}
+
+pub(crate) fn active() -> usize {
+    table().lock().unwrap_or_else(|e| e.into_inner()).len()
+}
+
+// 按 ID 升序
+pub(crate) fn render() -> String {
+    let t = table().lock().unwrap_or_else(|e| e.into_inner());
+    let mut ids: Vec<&u64> = t.keys().collect();
+    ids.sort();
+    let mut out = format!("{:<8} {:<12} {:<24} {:<40} {:<10} {:>8} {:>8} {:>12} {:>12} {:>6}  {}\n", "id", "kind", "peer", "target", "iface", "age_s", "idle_s", "up", "down", "uid", "process");
+    for id in ids {
+        let i = &t[id];
+        let target = if i.target.is_empty() { "-" } else { i.target.as_str() };
+        let iface = if i.iface.is_empty() { "-" } else { i.iface.as_str() };
+        let uid = i.uid.map_or_else(|| String::from("-"), |u| u.to_string());
+        let process = i.process.as_ref().map_or("-", |p| p.name.as_str());
+        let a = &i.activity;
+        out.push_str(&format!(
+            "{:<8} {:<12} {:<24} {:<40} {:<10} {:>8} {:>8} {:>12} {:>12} {:>6}  {}\n",
+            id, i.kind, i.peer, target, iface, i.started.elapsed().as_secs(), a.idle().as_secs(), a.up.load(Ordering::Relaxed), a.down.load(Ordering::Relaxed), uid, process
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn close_where_ends_sessions_on_a_vanished_address() {
+        let local: SocketAddr = "192.0.2.77:40000".parse().unwrap();
+        let task = tokio::spawn(run("test", String::from("peer"), Default::default(), async move {
+            set_local(local);
+            std::future::pending::<()>().await
+        }));
+        while count_where(|ip| ip == local.ip()) == 0 { tokio::task::yield_now().await; }
+        assert_eq!(close_where(|ip| ip.to_string() == "192.0.2.1"), 0);
+        assert_eq!(close_where(|ip| ip == local.ip()), 1);
+        tokio::time::timeout(std::time::Duration::from_secs(2), task).await.unwrap().unwrap();
+        assert_eq!(count_where(|ip| ip == local.ip()), 0);
+    }
+
+    #[tokio::test]
+    async fn kill_and_idle_eviction() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let spawn = |tx: tokio::sync::mpsc::UnboundedSender<u64>| tokio::spawn(run("test", String::from("peer"), Default::default(), async move {
+            tx.send(current().unwrap()).unwrap();
+            std::future::pending::<()>().await
+        }));
+        let killed = spawn(tx.clone());
+        let id = rx.recv().await.unwrap();
+        assert!(kill(id) && !kill(u64::MAX));
+        tokio::time::timeout(Duration::from_secs(2), killed).await.unwrap().unwrap();
+        assert!(!kill(id));
+
+        let idle = spawn(tx);
+        let id = rx.recv().await.unwrap();
+        let a = table().lock().unwrap().get(&id).map(|i| i.activity.clone()).unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(idle_sessions(Duration::from_millis(20)).iter().any(|(i, _)| *i == id));
+        // 有流量后重新计时
+        a.add(10, 20);
+        assert!(!idle_sessions(Duration::from_millis(20)).iter().any(|(i, _)| *i == id));
+        assert!(render().lines().any(|l| l.starts_with(&id.to_string()) && l.contains(" 10 ") && l.contains(" 20 ")));
+        assert!(kill(id));
+        tokio::time::timeout(Duration::from_secs(2), idle).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn rerouted_sessions_after_rule_change() {
+        use crate::router::{Decision, Route};
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let task = tokio::spawn(run("test", String::from("192.0.2.5:4000"), Default::default(), async move {
+            set_routed("ads.example", 443, &Decision::new(Route::Upstream(String::from("remote"))));
+            tx.send(current().unwrap()).unwrap();
+            std::future::pending::<()>().await
+        }));
+        let id = rx.recv().await.unwrap();
+        let block = |q: &crate::router::Query<'_>| Decision::new(if q.host == "ads.example" && q.port == 443 { Route::Block } else { Route::Default });
+        assert!(!rerouted(|_| Decision::new(Route::Upstream(String::from("remote")))).contains(&id));
+        assert!(rerouted(block).contains(&id));
+        assert_eq!(close_ids(&[id, u64::MAX], "routing rules changed"), 1);
+        tokio::time::timeout(Duration::from_secs(2), task).await.unwrap().unwrap();
+        assert!(!rerouted(block).contains(&id));
+    }
+}
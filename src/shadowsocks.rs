@@ -0,0 +1,537 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes128Gcm, Aes256Gcm};
+use base64::Engine;
+use chacha20poly1305::ChaCha20Poly1305;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::{timeout, Duration, Instant};
+
+use crate::dialer::{DialContext, DialRequest};
+use crate::listener::{AcceptBackoff, ListenerSettings};
+use crate::util::{log_throttled, log_info};
+
+// Shadowsocks 2022（SIP022）AEAD 流协议，只支持 TCP
+const TAG_LEN: usize = 16;
+const MAX_CHUNK: usize = 0xFFFF;
+const MAX_TIME_DIFF_SECS: u64 = 30;
+const SALT_TTL_SECS: u64 = 60;
+const HEADER_TYPE_REQUEST: u8 = 0;
+const HEADER_TYPE_RESPONSE: u8 = 1;
+const SUBKEY_CONTEXT: &str = "shadowsocks 2022 session subkey";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SsMethod {
+    Aes128Gcm,
+    Aes256Gcm,
+    Chacha20Poly1305,
+}
+
+impl SsMethod {
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        match s {
+            "2022-blake3-aes-128-gcm" => Some(Self::Aes128Gcm),
+            "2022-blake3-aes-256-gcm" => Some(Self::Aes256Gcm),
+            "2022-blake3-chacha20-poly1305" => Some(Self::Chacha20Poly1305),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Aes128Gcm => "2022-blake3-aes-128-gcm",
+            Self::Aes256Gcm => "2022-blake3-aes-256-gcm",
+            Self::Chacha20Poly1305 => "2022-blake3-chacha20-poly1305",
+        }
+    }
+
+    fn key_len(self) -> usize {
+        match self {
+            Self::Aes128Gcm => 16,
+            Self::Aes256Gcm | Self::Chacha20Poly1305 => 32,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct SsConfig {
+    pub(crate) method: SsMethod,
+    key: Vec<u8>,
+}
+
+impl SsConfig {
+    // password 为 base64 编码的 PSK，如 `openssl rand -base64 32`
+    pub(crate) fn new(method: &str, password: &str) -> Result<Self> {
+        let method = SsMethod::parse(method).ok_or_else(|| anyhow::anyhow!("unsupported shadowsocks method {:?}", method))?;
+        let key = base64::engine::general_purpose::STANDARD
+            .decode(password.trim())
+            .map_err(|e| anyhow::anyhow!("shadowsocks password is not valid base64: {}", e))?;
+        if key.len() != method.key_len() {
+            anyhow::bail!("{} needs a {}-byte key, got {} bytes", method.name(), method.key_len(), key.len());
+        }
+        Ok(Self { method, key })
+    }
+
+    fn salt_len(&self) -> usize {
+        self.method.key_len()
+    }
+}
+
+enum CipherInner {
+    Aes128(Box<Aes128Gcm>),
+    Aes256(Box<Aes256Gcm>),
+    Chacha(Box<ChaCha20Poly1305>),
+}
+
+// 会话的一个方向：子密钥由 PSK + salt 派生，nonce 为小端计数器
+pub(crate) struct SsCipher {
+    inner: CipherInner,
+    counter: u64,
+}
+
+impl SsCipher {
+    pub(crate) fn new(cfg: &SsConfig, salt: &[u8]) -> Self {
+        let mut material = cfg.key.clone();
+        material.extend_from_slice(salt);
+        let subkey = blake3::derive_key(SUBKEY_CONTEXT, &material);
+        let key = &subkey[..cfg.method.key_len()];
+        let inner = match cfg.method {
+            SsMethod::Aes128Gcm => CipherInner::Aes128(Box::new(Aes128Gcm::new_from_slice(key).expect("key length"))),
+            SsMethod::Aes256Gcm => CipherInner::Aes256(Box::new(Aes256Gcm::new_from_slice(key).expect("key length"))),
+            SsMethod::Chacha20Poly1305 => CipherInner::Chacha(Box::new(ChaCha20Poly1305::new_from_slice(key).expect("key length"))),
+        };
+        Self { inner, counter: 0 }
+    }
+
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[..8].copy_from_slice(&self.counter.to_le_bytes());
+        self.counter += 1;
+        nonce
+    }
+
+    pub(crate) fn seal(&mut self, plain: &[u8]) -> Vec<u8> {
+        let nonce = self.next_nonce();
+        let res = match &self.inner {
+            CipherInner::Aes128(c) => c.encrypt(&nonce.into(), plain),
+            CipherInner::Aes256(c) => c.encrypt(&nonce.into(), plain),
+            CipherInner::Chacha(c) => c.encrypt(&nonce.into(), plain),
+        };
+        res.expect("AEAD encryption cannot fail for in-range chunk sizes")
+    }
+
+    pub(crate) fn open(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let nonce = self.next_nonce();
+        let res = match &self.inner {
+            CipherInner::Aes128(c) => c.decrypt(&nonce.into(), data),
+            CipherInner::Aes256(c) => c.decrypt(&nonce.into(), data),
+            CipherInner::Chacha(c) => c.decrypt(&nonce.into(), data),
+        };
+        res.map_err(|_| anyhow::anyhow!("shadowsocks AEAD authentication failed"))
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+pub(crate) fn random_salt(len: usize) -> Result<Vec<u8>> {
+    let mut salt = vec![0u8; len];
+    getrandom::getrandom(&mut salt).map_err(|e| anyhow::anyhow!("getrandom failed: {}", e))?;
+    Ok(salt)
+}
+
+pub(crate) fn check_timestamp(ts: u64) -> Result<()> {
+    let now = unix_now();
+    if now.abs_diff(ts) > MAX_TIME_DIFF_SECS {
+        anyhow::bail!("shadowsocks header timestamp off by {}s", now.abs_diff(ts));
+    }
+    Ok(())
+}
+
+// 防重放：请求的 salt 在 SALT_TTL_SECS 内只能出现一次
+fn check_and_store_salt(salt: &[u8]) -> Result<()> {
+    static SEEN: OnceLock<Mutex<HashMap<Vec<u8>, u64>>> = OnceLock::new();
+    let now = unix_now();
+    let mut seen = SEEN.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap_or_else(|e| e.into_inner());
+    seen.retain(|_, expires| *expires > now);
+    if seen.contains_key(salt) { anyhow::bail!("shadowsocks salt replayed"); }
+    seen.insert(salt.to_vec(), now + SALT_TTL_SECS);
+    Ok(())
+}
+
+// 把 `data` 编码为一个或多个（长度块，负载块）对
+pub(crate) fn encode_chunks(cipher: &mut SsCipher, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 2 * (2 + TAG_LEN));
+    for part in data.chunks(MAX_CHUNK) {
+        out.extend(cipher.seal(&(part.len() as u16).to_be_bytes()));
+        out.extend(cipher.seal(part));
+    }
+    out
+}
+
+// 在块边界上正常 EOF 时返回 None
+pub(crate) async fn read_chunk<R: AsyncRead + Unpin>(r: &mut R, cipher: &mut SsCipher) -> Result<Option<Vec<u8>>> {
+    let mut len_buf = [0u8; 2 + TAG_LEN];
+    match r.read_exact(&mut len_buf).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = cipher.open(&len_buf)?;
+    let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+    let mut payload = vec![0u8; len + TAG_LEN];
+    r.read_exact(&mut payload).await?;
+    Ok(Some(cipher.open(&payload)?))
+}
+
+async fn read_exact_timeout<S: AsyncRead + Unpin>(stream: &mut S, buf: &mut [u8], read_timeout_ms: u64) -> Result<()> {
+    timeout(Duration::from_millis(read_timeout_ms), stream.read_exact(buf))
+        .await
+        .map_err(|_| anyhow::anyhow!("read timeout"))??;
+    Ok(())
+}
+
+async fn relay_client_to_target<R, W>(mut from: R, mut to: W, mut dec: SsCipher) -> Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut total = 0u64;
+    while let Some(data) = read_chunk(&mut from, &mut dec).await? {
+        to.write_all(&data).await?;
+        total += data.len() as u64;
+    }
+    to.shutdown().await?;
+    Ok(total)
+}
+
+async fn relay_target_to_client<R, W>(mut from: R, mut to: W, cfg: &SsConfig, request_salt: &[u8]) -> Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; 16 * 1024];
+    let n = from.read(&mut buf).await?;
+    if n == 0 { to.shutdown().await?; return Ok(0); }
+    // 响应头中带有第一个负载块的长度，所以要等目标发出数据后才发送
+    let salt = random_salt(cfg.salt_len())?;
+    let mut enc = SsCipher::new(cfg, &salt);
+    let mut header = Vec::with_capacity(1 + 8 + request_salt.len() + 2);
+    header.push(HEADER_TYPE_RESPONSE);
+    header.extend_from_slice(&unix_now().to_be_bytes());
+    header.extend_from_slice(request_salt);
+    header.extend_from_slice(&(n as u16).to_be_bytes());
+    let mut first = salt;
+    first.extend(enc.seal(&header));
+    first.extend(enc.seal(&buf[..n]));
+    to.write_all(&first).await?;
+    let mut total = n as u64;
+    loop {
+        let n = from.read(&mut buf).await?;
+        if n == 0 { break; }
+        to.write_all(&encode_chunks(&mut enc, &buf[..n])).await?;
+        total += n as u64;
+    }
+    to.shutdown().await?;
+    Ok(total)
+}
+
+async fn handle_shadowsocks<S: AsyncRead + AsyncWrite + Unpin>(mut inbound: S, ctx: DialContext<'_>, cfg: &SsConfig, read_timeout_ms: u64, session_timeout_ms: u64) -> Result<()> {
+    let DialContext { dialer, iface, deny: deny_dest } = ctx;
+    let mut salt = vec![0u8; cfg.salt_len()];
+    read_exact_timeout(&mut inbound, &mut salt, read_timeout_ms).await?;
+    let mut dec = SsCipher::new(cfg, &salt);
+
+    // 定长头：类型(1) 时间戳(8) 变长头长度(2)
+    let mut fixed = [0u8; 11 + TAG_LEN];
+    read_exact_timeout(&mut inbound, &mut fixed, read_timeout_ms).await?;
+    // 校验失败时直接断开，不给探测者任何信息
+    let fixed = dec.open(&fixed)?;
+    if fixed[0] != HEADER_TYPE_REQUEST { anyhow::bail!("shadowsocks: unexpected header type {}", fixed[0]); }
+    check_timestamp(u64::from_be_bytes(fixed[1..9].try_into()?))?;
+    check_and_store_salt(&salt)?;
+    let var_len = u16::from_be_bytes([fixed[9], fixed[10]]) as usize;
+
+    // 变长头：地址、端口、填充长度、填充、初始负载
+    let mut var = vec![0u8; var_len + TAG_LEN];
+    read_exact_timeout(&mut inbound, &mut var, read_timeout_ms).await?;
+    let var = dec.open(&var)?;
+    let (target, used) = crate::addr::TargetAddr::decode(&var)?;
+    let (host, port) = (target.host(), target.port());
+    let pad = var.get(used..used + 2).ok_or_else(|| anyhow::anyhow!("truncated shadowsocks header"))?;
+    let payload_start = used + 2 + u16::from_be_bytes([pad[0], pad[1]]) as usize;
+    let initial = var.get(payload_start..).ok_or_else(|| anyhow::anyhow!("truncated shadowsocks padding"))?;
+
+    log_throttled(|| log_info(format!("Shadowsocks CONNECT -> {} (iface: {})", target.redacted(), iface)));
+    let outbound = dialer.dial(DialRequest::new(&host, port, iface, deny_dest)).await?;
+    let mut outbound = crate::capture::maybe_wrap(outbound, &host, port, false);
+    if !initial.is_empty() { outbound.write_all(initial).await?; }
+
+    let (ir, iw) = tokio::io::split(inbound);
+    let (or, ow) = tokio::io::split(outbound);
+    let started = Instant::now();
+    let (c2s, s2c) = timeout(
+        Duration::from_millis(session_timeout_ms),
+        async { tokio::try_join!(relay_client_to_target(ir, ow, dec), relay_target_to_client(or, iw, cfg, &salt)) },
+    )
+    .await??;
+    let c2s = c2s + initial.len() as u64;
+    crate::stats::record(&host, c2s, s2c, started.elapsed());
+    log_throttled(|| log_info(format!("Shadowsocks finished {} (c->s: {} bytes, s->c: {} bytes)", target.redacted(), c2s, s2c)));
+    Ok(())
+}
+
+pub async fn run_shadowsocks_proxy(listener: TcpListener, cfg: Arc<SsConfig>, s: Arc<ListenerSettings>) -> Result<()> {
+    let listen = listener.local_addr()?.to_string();
+    log_info(format!("Shadowsocks ({}) listening on {}, bound to {}", cfg.method.name(), listen, s.iface));
+    let mut backoff = AcceptBackoff::new();
+    loop {
+        let (inbound, peer_addr) = backoff.accept(&listen, || listener.accept()).await;
+        if !s.allows(peer_addr.ip()) {
+            log_throttled(|| log_info(format!("denied connection from {} to {} (not in allow list)", peer_addr, listen)));
+            continue;
+        }
+        let cfg_clone = cfg.clone();
+        match s.limit.acquire().await {
+            Some(permit) => {
+                let (s, listen) = (s.clone(), listen.clone());
+                let who = crate::process::Peer::tcp(&inbound);
+                tokio::spawn(crate::session::run("ss", peer_addr.to_string(), s.sockopts, async move {
+                    let _permit = permit;
+                    log_throttled(|| log_info(format!("Incoming TCP connection from {} -> listening on {} (iface: {})", peer_addr, listen, s.iface)));
+                    crate::process::record(who).await;
+                    if let Err(e) = handle_shadowsocks(inbound, s.dial_context(), &cfg_clone, s.read_timeout_ms, s.session_timeout_ms).await {
+                        crate::error::log_session_error("Shadowsocks handler", &e);
+                    }
+                }));
+            }
+            None => {
+                log_throttled(|| log_info("too many concurrent connections; dropping new Shadowsocks connection"));
+            }
+        }
+    }
+}
+
+// ---- 客户端：把 Shadowsocks 服务器配置为上游时使用 ----
+
+enum ReadState {
+    Salt,
+    ResponseHeader,
+    Length,
+    Payload(usize),
+    Eof,
+}
+
+// 包装到远端 Shadowsocks 2022 服务器的连接，对外是到目标的明文流，可以像直连 socket 一样转发
+pub(crate) struct SsClientStream<S> {
+    inner: S,
+    cfg: Arc<SsConfig>,
+    request_salt: Vec<u8>,
+    enc: SsCipher,
+    dec: Option<SsCipher>,
+    state: ReadState,
+    raw: Vec<u8>,
+    plain: Vec<u8>,
+    plain_pos: usize,
+    pending: Vec<u8>,
+    pending_pos: usize,
+}
+
+fn invalid_data(e: impl std::fmt::Display) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SsClientStream<S> {
+    // 立即发出请求头（用随机填充代替初始负载），服务器先发言的协议也能用
+    pub(crate) async fn connect(mut inner: S, cfg: Arc<SsConfig>, host: &str, port: u16) -> Result<Self> {
+        let salt = random_salt(cfg.salt_len())?;
+        let mut enc = SsCipher::new(&cfg, &salt);
+        let mut pad_len = [0u8; 1];
+        getrandom::getrandom(&mut pad_len).map_err(|e| anyhow::anyhow!("getrandom failed: {}", e))?;
+        let pad_len = 1 + (pad_len[0] as usize % 64);
+        let mut var = crate::addr::TargetAddr::new(host, port).encode()?;
+        var.extend_from_slice(&(pad_len as u16).to_be_bytes());
+        var.extend(random_salt(pad_len)?);
+        let mut fixed = Vec::with_capacity(11);
+        fixed.push(HEADER_TYPE_REQUEST);
+        fixed.extend_from_slice(&unix_now().to_be_bytes());
+        fixed.extend_from_slice(&(var.len() as u16).to_be_bytes());
+        let mut header = salt.clone();
+        header.extend(enc.seal(&fixed));
+        header.extend(enc.seal(&var));
+        inner.write_all(&header).await?;
+        Ok(Self {
+            inner,
+            cfg,
+            request_salt: salt,
+            enc,
+            dec: None,
+            state: ReadState::Salt,
+            raw: Vec::new(),
+            plain: Vec::new(),
+            plain_pos: 0,
+            pending: Vec::new(),
+            pending_pos: 0,
+        })
+    }
+
+    fn poll_drain_pending(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        use std::task::Poll;
+        while self.pending_pos < self.pending.len() {
+            match std::pin::Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.pending_pos..]) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(n)) => self.pending_pos += n,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        self.pending.clear();
+        self.pending_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    fn process_frame(&mut self, frame: Vec<u8>) -> std::io::Result<()> {
+        match self.state {
+            ReadState::Salt => {
+                self.dec = Some(SsCipher::new(&self.cfg, &frame));
+                self.state = ReadState::ResponseHeader;
+            }
+            ReadState::ResponseHeader => {
+                let dec = self.dec.as_mut().expect("cipher set after salt");
+                let h = dec.open(&frame).map_err(invalid_data)?;
+                let salt_len = self.request_salt.len();
+                if h[0] != HEADER_TYPE_RESPONSE { return Err(invalid_data("shadowsocks: unexpected response header type")); }
+                let ts = u64::from_be_bytes(h[1..9].try_into().expect("8 bytes"));
+                check_timestamp(ts).map_err(invalid_data)?;
+                if h[9..9 + salt_len] != self.request_salt[..] { return Err(invalid_data("shadowsocks: response salt mismatch")); }
+                let len = u16::from_be_bytes([h[9 + salt_len], h[10 + salt_len]]) as usize;
+                self.state = ReadState::Payload(len);
+            }
+            ReadState::Length => {
+                let dec = self.dec.as_mut().expect("cipher set after salt");
+                let l = dec.open(&frame).map_err(invalid_data)?;
+                self.state = ReadState::Payload(u16::from_be_bytes([l[0], l[1]]) as usize);
+            }
+            ReadState::Payload(_) => {
+                let dec = self.dec.as_mut().expect("cipher set after salt");
+                self.plain = dec.open(&frame).map_err(invalid_data)?;
+                self.plain_pos = 0;
+                self.state = ReadState::Length;
+            }
+            ReadState::Eof => {}
+        }
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for SsClientStream<S> {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        out: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        use std::task::Poll;
+        let this = self.get_mut();
+        loop {
+            if this.plain_pos < this.plain.len() {
+                let n = out.remaining().min(this.plain.len() - this.plain_pos);
+                out.put_slice(&this.plain[this.plain_pos..this.plain_pos + n]);
+                this.plain_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            let salt_len = this.request_salt.len();
+            let need = match this.state {
+                ReadState::Salt => salt_len,
+                ReadState::ResponseHeader => 1 + 8 + salt_len + 2 + TAG_LEN,
+                ReadState::Length => 2 + TAG_LEN,
+                ReadState::Payload(n) => n + TAG_LEN,
+                ReadState::Eof => return Poll::Ready(Ok(())),
+            };
+            while this.raw.len() < need {
+                let mut tmp = [0u8; 8192];
+                let mut rb = tokio::io::ReadBuf::new(&mut tmp);
+                match std::pin::Pin::new(&mut this.inner).poll_read(cx, &mut rb) {
+                    Poll::Ready(Ok(())) => {}
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                }
+                if rb.filled().is_empty() {
+                    // 只有在块之间（或服务器还没应答）时 EOF 才算正常结束
+                    if this.raw.is_empty() && matches!(this.state, ReadState::Length | ReadState::Salt) {
+                        this.state = ReadState::Eof;
+                        return Poll::Ready(Ok(()));
+                    }
+                    return Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into()));
+                }
+                this.raw.extend_from_slice(rb.filled());
+            }
+            let frame: Vec<u8> = this.raw.drain(..need).collect();
+            this.process_frame(frame)?;
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for SsClientStream<S> {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<std::io::Result<usize>> {
+        use std::task::Poll;
+        let this = self.get_mut();
+        match this.poll_drain_pending(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other.map(|r| r.map(|_| 0)),
+        }
+        if buf.is_empty() { return Poll::Ready(Ok(0)); }
+        let n = buf.len().min(MAX_CHUNK);
+        this.pending = encode_chunks(&mut this.enc, &buf[..n]);
+        // 加密后即视为已接收；这里的 Pending 由下一次 write/flush 推进
+        if let Poll::Ready(Err(e)) = this.poll_drain_pending(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        std::task::ready!(this.poll_drain_pending(cx))?;
+        std::pin::Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        std::task::ready!(this.poll_drain_pending(cx))?;
+        std::pin::Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialer::MemoryDialer;
+
+    #[tokio::test]
+    async fn client_and_server_relay_over_memory_streams() {
+        let cfg = Arc::new(SsConfig::new("2022-blake3-aes-128-gcm", "AAECAwQFBgcICQoLDA0ODw==").unwrap());
+        let dialer = MemoryDialer::default();
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let session = handle_shadowsocks(server, DialContext { dialer: &dialer, iface: "lo", deny: &[] }, &cfg, 1000, 5000);
+        let client = async {
+            let mut s = SsClientStream::connect(client, cfg.clone(), "example.com", 80).await.unwrap();
+            s.write_all(b"ping").await.unwrap();
+            let mut echo = [0u8; 4];
+            s.read_exact(&mut echo).await.unwrap();
+            assert_eq!(&echo, b"ping");
+            s.shutdown().await.unwrap();
+        };
+        let (res, ()) = tokio::join!(session, client);
+        res.unwrap();
+        assert_eq!(*dialer.dialed.lock().unwrap(), ["example.com:80"]);
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::dialer::{DialContext, DialRequest};
+use crate::socks5::read_exact_into;
+use crate::stats::Metered;
+use crate::util::{log_throttled, log_info};
+
+const REP_GRANTED: u8 = 0x5A;
+const REP_REJECTED: u8 = 0x5B;
+
+async fn read_cstring<S: AsyncRead + Unpin>(stream: &mut S, read_timeout_ms: u64) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let mut b = [0u8; 1];
+        read_exact_into(stream, &mut b, read_timeout_ms).await?;
+        if b[0] == 0 { return Ok(out); }
+        if out.len() >= 255 { anyhow::bail!("SOCKS4 field too long"); }
+        out.push(b[0]);
+    }
+}
+
+async fn reply<S: AsyncWrite + Unpin>(stream: &mut S, rep: u8) -> Result<()> {
+    stream.write_all(&[0x00, rep, 0, 0, 0, 0, 0, 0]).await?;
+    Ok(())
+}
+
+// SOCKS 监听读到 VER=0x04 与 CMD 后调用。
+// SOCKS4 没有密码，配置了 SOCKS5 认证时一律拒绝
+pub(crate) async fn handle_socks4<S: AsyncRead + AsyncWrite + Unpin>(
+    mut inbound: S,
+    cmd: u8,
+    ctx: DialContext<'_>,
+    need_auth: bool,
+    read_timeout_ms: u64,
+    session_timeout_ms: u64,
+) -> Result<()> {
+    let DialContext { dialer, iface, deny: deny_dest } = ctx;
+    let mut hdr = [0u8; 6];
+    read_exact_into(&mut inbound, &mut hdr, read_timeout_ms).await?;
+    let port = u16::from_be_bytes([hdr[0], hdr[1]]);
+    let ip = std::net::Ipv4Addr::new(hdr[2], hdr[3], hdr[4], hdr[5]);
+    let _userid = read_cstring(&mut inbound, read_timeout_ms).await?;
+    // SOCKS4a：地址为 0.0.0.x（x != 0）时用户 ID 之后跟着域名
+    let o = ip.octets();
+    let target = if o[0] == 0 && o[1] == 0 && o[2] == 0 && o[3] != 0 {
+        let name = read_cstring(&mut inbound, read_timeout_ms).await?;
+        match crate::addr::TargetAddr::domain(&name, port) {
+            Ok(t) => t,
+            Err(e) => {
+                reply(&mut inbound, REP_REJECTED).await?;
+                anyhow::bail!("SOCKS4a: {}", e);
+            }
+        }
+    } else {
+        crate::addr::TargetAddr::Ip((ip, port).into())
+    };
+    let host = target.host();
+
+    if need_auth {
+        reply(&mut inbound, REP_REJECTED).await?;
+        anyhow::bail!("SOCKS4 rejected: authentication required");
+    }
+    if cmd != 0x01 {
+        reply(&mut inbound, REP_REJECTED).await?;
+        anyhow::bail!("SOCKS4 unsupported CMD");
+    }
+
+    crate::session::set_protocol("socks4");
+    crate::session::handshake_done();
+    log_throttled(|| log_info(format!("SOCKS4 CONNECT -> {} (iface: {})", target.redacted(), iface)));
+    let outbound = match dialer.dial(DialRequest::new(&host, port, iface, deny_dest)).await {
+        Ok(s) => Metered::new(crate::capture::maybe_wrap(s, &host, port, false)),
+        Err(e) => {
+            let _ = reply(&mut inbound, REP_REJECTED).await;
+            return Err(e);
+        }
+    };
+    reply(&mut inbound, REP_GRANTED).await?;
+    let (c2s, s2c) = crate::stats::relay(&mut inbound, outbound, &host, None, session_timeout_ms).await?;
+    log_throttled(|| log_info(format!("SOCKS4 finished {} (c->s: {} bytes, s->c: {} bytes)", target.redacted(), c2s, s2c)));
+    Ok(())
+}
@@ -1,68 +1,138 @@
 use anyhow::Result;
-use tokio::io::{copy_bidirectional, AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Semaphore;
-use tokio::time::{sleep, timeout, Duration};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::{timeout, Duration};
 use std::sync::Arc;
 
-use crate::util::{connect_outbound, log_throttled, log_info, log_error, is_transient_anyhow_error};
+use crate::auth::Authenticator;
+use crate::dialer::{DialContext, DialRequest};
+use crate::socks4::handle_socks4;
+use crate::listener::{AcceptBackoff, ListenerSettings};
+use crate::stats::Metered;
+use crate::error::ProxyError;
+use crate::util::{log_throttled, log_info};
 
-async fn read_exact_into(stream: &mut TcpStream, buf: &mut [u8], read_timeout_ms: u64) -> Result<()> {
+pub(crate) async fn read_exact_into<S: AsyncRead + Unpin>(stream: &mut S, buf: &mut [u8], read_timeout_ms: u64) -> Result<()> {
     timeout(Duration::from_millis(read_timeout_ms), stream.read_exact(buf))
         .await
-        .map_err(|_| anyhow::anyhow!("read timeout"))??;
+        .map_err(|_| ProxyError::Handshake(String::from("read timeout")))??;
     Ok(())
 }
 
-async fn handle_socks5(
-    mut inbound: TcpStream,
-    iface: &str,
-    user: Option<&str>,
-    pass: Option<&str>,
+// 建连失败的 REP：0x02 规则不允许、0x03 网络不可达、0x04 主机不可达、0x05 拒绝连接、0x06 超时，其余 0x01
+fn reply_code(e: &anyhow::Error) -> u8 {
+    match ProxyError::find(e) {
+        Some(ProxyError::PolicyDenied(_)) => 0x02,
+        Some(ProxyError::IfaceBind { .. }) => 0x03,
+        Some(ProxyError::Io(ioe)) if ioe.kind() == std::io::ErrorKind::NetworkUnreachable => 0x03,
+        Some(ProxyError::Dns { .. }) => 0x04,
+        Some(ProxyError::Io(ioe)) if ioe.kind() == std::io::ErrorKind::HostUnreachable => 0x04,
+        Some(ProxyError::ConnectRefused(_)) => 0x05,
+        Some(ProxyError::ConnectTimeout(_)) => 0x06,
+        _ => 0x01,
+    }
+}
+
+pub(crate) async fn handle_socks5<S: AsyncRead + AsyncWrite + Unpin>(
+    mut inbound: S,
+    ctx: DialContext<'_>,
+    auth: Option<&dyn Authenticator>,
     read_timeout_ms: u64,
     session_timeout_ms: u64,
 ) -> Result<()> {
+    let DialContext { dialer, iface, deny: deny_dest } = ctx;
     // Greeting
     let mut g = [0u8; 2];
     read_exact_into(&mut inbound, &mut g, read_timeout_ms).await?;
-    if g[0] != 5 { anyhow::bail!("Invalid SOCKS5 version in greeting"); }
+    let need_auth = auth.is_some();
+    if g[0] == 4 { return handle_socks4(inbound, g[1], ctx, need_auth, read_timeout_ms, session_timeout_ms).await; }
+    if g[0] != 5 { anyhow::bail!(ProxyError::Handshake(String::from("Invalid SOCKS5 version in greeting"))); }
     let nmethods = g[1] as usize;
     let mut methods = vec![0u8; nmethods];
     if nmethods > 0 { read_exact_into(&mut inbound, &mut methods, read_timeout_ms).await?; }
-    let need_auth = user.is_some() || pass.is_some();
-    if need_auth {
-        let use_userpass = methods.iter().any(|m| *m == 0x02);
-        if use_userpass { inbound.write_all(&[0x05, 0x02]).await?; } else { inbound.write_all(&[0x05, 0xFF]).await?; anyhow::bail!("client doesn't support username/password auth"); }
+    // 未开认证但允许指定出口网卡时，也接受用户名/密码方式，以便从用户名中取网卡
+    let mut egress = None;
+    // 通过认证的用户名，用于配额与按用户统计
+    let mut user: Option<String> = None;
+    if need_auth || (crate::egress::enabled() && methods.contains(&0x02)) {
+        let use_userpass = methods.contains(&0x02);
+        if use_userpass { inbound.write_all(&[0x05, 0x02]).await?; } else { inbound.write_all(&[0x05, 0xFF]).await?; anyhow::bail!(ProxyError::Handshake(String::from("client doesn't support username/password auth"))); }
         // subnegotiation
-        let mut sb_ver = [0u8;1]; read_exact_into(&mut inbound, &mut sb_ver, read_timeout_ms).await?; if sb_ver[0] != 0x01 { anyhow::bail!("invalid auth subnegotiation version"); }
+        let mut sb_ver = [0u8;1]; read_exact_into(&mut inbound, &mut sb_ver, read_timeout_ms).await?; if sb_ver[0] != 0x01 { anyhow::bail!(ProxyError::Handshake(String::from("invalid auth subnegotiation version"))); }
         let mut ulen_b = [0u8;1]; read_exact_into(&mut inbound, &mut ulen_b, read_timeout_ms).await?; let ulen = ulen_b[0] as usize;
         let mut ubytes = vec![0u8; ulen]; if ulen>0 { read_exact_into(&mut inbound, &mut ubytes, read_timeout_ms).await?; }
         let mut plen_b = [0u8;1]; read_exact_into(&mut inbound, &mut plen_b, read_timeout_ms).await?; let plen = plen_b[0] as usize;
         let mut pbytes = vec![0u8; plen]; if plen>0 { read_exact_into(&mut inbound, &mut pbytes, read_timeout_ms).await?; }
-        let ok = user.map(|u| u.as_bytes().to_vec()).as_deref()==Some(&ubytes[..]) && pass.map(|p| p.as_bytes().to_vec()).as_deref()==Some(&pbytes[..]);
-        if ok { inbound.write_all(&[0x01, 0x00]).await?; } else { inbound.write_all(&[0x01, 0x01]).await?; anyhow::bail!("invalid username/password"); }
+        let pass = String::from_utf8_lossy(&pbytes).into_owned();
+        // 用户名本身可能含 `@`，先按完整用户名匹配，再尝试 user@IFACE
+        let full = String::from_utf8_lossy(&ubytes).into_owned();
+        let ok = match auth {
+            Some(a) if crate::auth::verify(a, &full, &pass).await => {
+                user = Some(full);
+                true
+            }
+            _ => {
+                let (base, name) = crate::egress::split_username(&ubytes);
+                egress = name;
+                let base = String::from_utf8_lossy(base).into_owned();
+                match auth {
+                    Some(a) if crate::auth::verify(a, &base, &pass).await => {
+                        user = Some(base);
+                        true
+                    }
+                    Some(_) => false,
+                    None => true,
+                }
+            }
+        };
+        if !ok {
+            crate::ban::record_failure();
+            inbound.write_all(&[0x01, 0x01]).await?;
+            anyhow::bail!("invalid username/password");
+        }
+        if let Some(name) = &egress {
+            if let Err(e) = crate::egress::check(name) { inbound.write_all(&[0x01, 0x01]).await?; return Err(e); }
+        }
+        inbound.write_all(&[0x01, 0x00]).await?;
     } else {
         inbound.write_all(&[0x05, 0x00]).await?;
     }
 
+    let iface = egress.as_deref().unwrap_or(iface);
+    crate::session::set_protocol("socks5");
+    if let Some(u) = &user { crate::session::set_user(u); }
+
     // Request
     let mut h = [0u8; 4]; read_exact_into(&mut inbound, &mut h, read_timeout_ms).await?;
-    if h[0] != 5 { anyhow::bail!("Invalid SOCKS5 version in request"); }
+    if h[0] != 5 { anyhow::bail!(ProxyError::Handshake(String::from("Invalid SOCKS5 version in request"))); }
     let cmd = h[1]; let atyp = h[3];
-    let (target_host, target_port) = match atyp {
-        0x01 => { let mut v4=[0u8;4]; read_exact_into(&mut inbound,&mut v4, read_timeout_ms).await?; let ip=std::net::Ipv4Addr::new(v4[0],v4[1],v4[2],v4[3]); let mut p=[0u8;2]; read_exact_into(&mut inbound,&mut p, read_timeout_ms).await?; (ip.to_string(), u16::from_be_bytes(p)) }
-        0x03 => { let mut l=[0u8;1]; read_exact_into(&mut inbound,&mut l, read_timeout_ms).await?; let len=l[0] as usize; let mut hb=vec![0u8;len]; if len>0 { read_exact_into(&mut inbound,&mut hb, read_timeout_ms).await?; } let host=String::from_utf8_lossy(&hb).to_string(); let mut p=[0u8;2]; read_exact_into(&mut inbound,&mut p, read_timeout_ms).await?; (host, u16::from_be_bytes(p)) }
-        0x04 => { let mut v6=[0u8;16]; read_exact_into(&mut inbound,&mut v6, read_timeout_ms).await?; let ip=std::net::Ipv6Addr::from(v6); let mut p=[0u8;2]; read_exact_into(&mut inbound,&mut p, read_timeout_ms).await?; (ip.to_string(), u16::from_be_bytes(p)) }
-        _ => anyhow::bail!("Unsupported ATYP"),
-    };
+    // REP 0x08：不支持的地址类型
+    if ![0x01, 0x03, 0x04].contains(&atyp) { inbound.write_all(&[0x05, 0x08, 0x00, 0x01, 0,0,0,0, 0,0]).await?; }
+    let target = crate::addr::TargetAddr::read(&mut inbound, atyp, read_timeout_ms).await?;
+    let (target_host, target_port) = (target.host(), target.port());
+    crate::session::handshake_done();
 
     match cmd {
         0x01 => {
-            log_throttled(|| log_info(format!("SOCKS5 CONNECT -> {}:{} (iface: {})", target_host, target_port, iface)));
-            let mut outbound = connect_outbound(&target_host, target_port, iface).await?;
+            log_throttled(|| log_info(format!("SOCKS5 CONNECT -> {} (iface: {})", target.redacted(), iface)));
+            if let Some(u) = user.as_deref() {
+                if let Err(e) = crate::quota::check(u) {
+                    // REP 0x02: connection not allowed by ruleset
+                    inbound.write_all(&[0x05, 0x02, 0x00, 0x01, 0,0,0,0, 0,0]).await?;
+                    return Err(e);
+                }
+            }
+            let outbound = match dialer.dial(DialRequest::new(&target_host, target_port, iface, deny_dest)).await {
+                Ok(s) => s,
+                Err(e) => {
+                    inbound.write_all(&[0x05, reply_code(&e), 0x00, 0x01, 0,0,0,0, 0,0]).await?;
+                    return Err(e);
+                }
+            };
+            let outbound = Metered::new(crate::capture::maybe_wrap(outbound, &target_host, target_port, false));
             inbound.write_all(&[0x05, 0x00, 0x00, 0x01, 0,0,0,0, 0,0]).await?;
-            let (c2s, s2c) = timeout(Duration::from_millis(session_timeout_ms), copy_bidirectional(&mut inbound, &mut outbound)).await??;
-            log_throttled(|| log_info(format!("SOCKS5 finished {}:{} (c->s: {} bytes, s->c: {} bytes)", target_host, target_port, c2s, s2c)));
+            let (c2s, s2c) = crate::stats::relay(&mut inbound, outbound, &target_host, user.as_deref(), session_timeout_ms).await?;
+            log_throttled(|| log_info(format!("SOCKS5 finished {} (c->s: {} bytes, s->c: {} bytes)", target.redacted(), c2s, s2c)));
             Ok(())
         }
         0x03 => { anyhow::bail!("UDP ASSOC not supported") }
@@ -70,40 +140,32 @@ async fn handle_socks5(
     }
 }
 
-pub async fn run_socks5_proxy_auth(iface: &str, listen: &str, user: Option<&str>, pass: Option<&str>, sem: Arc<Semaphore>, read_timeout_ms: u64, session_timeout_ms: u64) -> Result<()> {
-    let listener = TcpListener::bind(listen).await?;
-    log_info(format!("SOCKS5 proxy listening on {}, bound to {}", listen, iface));
-    let mut backoff_ms: u64 = 50;
+pub async fn run_socks5_proxy_auth(listener: TcpListener, s: Arc<ListenerSettings>) -> Result<()> {
+    let listen = listener.local_addr()?.to_string();
+    log_info(format!("SOCKS5 proxy listening on {}, bound to {}", listen, s.iface));
+    let mut backoff = AcceptBackoff::new();
     loop {
-        let (inbound, peer_addr) = match listener.accept().await {
-            Ok(v) => { backoff_ms = 50; v }
-            Err(e) => {
-                log_error(format!("accept error: {}", e));
-                sleep(Duration::from_millis(backoff_ms)).await;
-                backoff_ms = (backoff_ms.saturating_mul(2)).min(1000);
-                continue;
-            }
-        };
-        let listen_for_log = listen.to_string();
-        log_throttled(|| log_info(format!("Incoming TCP connection from {} -> listening on {} (iface: {})", peer_addr, listen_for_log, iface)));
-        let iface_for_task = iface.to_string();
-        let u = user.map(|s| s.to_string());
-        let p = pass.map(|s| s.to_string());
-        let sem_clone = sem.clone();
-        match sem_clone.try_acquire_owned() {
-            Ok(permit) => {
-                tokio::spawn(async move {
+        let (inbound, peer_addr) = backoff.accept(&listen, || listener.accept()).await;
+        if !s.allows(peer_addr.ip()) {
+            log_throttled(|| log_info(format!("denied connection from {} to {} (not in allow list)", peer_addr, listen)));
+            continue;
+        }
+        if crate::ban::banned(peer_addr.ip(), &listen) { continue; }
+        match s.limit.acquire().await {
+            Some(permit) => {
+                let (s, listen) = (s.clone(), listen.clone());
+                let who = crate::process::Peer::tcp(&inbound);
+                tokio::spawn(crate::session::run("socks5", peer_addr.to_string(), s.sockopts, async move {
                     let _permit = permit;
-                    if let Err(e) = handle_socks5(inbound, &iface_for_task, u.as_deref(), p.as_deref(), read_timeout_ms, session_timeout_ms).await {
-                        if is_transient_anyhow_error(&e) {
-                            log_info(format!("SOCKS5 handler transient: {}", e));
-                        } else {
-                            log_error(format!("SOCKS5 handler error: {}", e));
-                        }
+                    log_throttled(|| log_info(format!("Incoming TCP connection from {} -> listening on {} (iface: {})", peer_addr, listen, s.iface)));
+                    crate::process::record(who).await;
+                    let inbound = crate::record::wrap(inbound);
+                    if let Err(e) = handle_socks5(inbound, s.dial_context(), s.auth.as_deref(), s.read_timeout_ms, s.session_timeout_ms).await {
+                        crate::error::log_session_error("SOCKS5 handler", &e);
                     }
-                });
+                }));
             }
-            Err(_) => {
+            None => {
                 log_throttled(|| log_info("too many concurrent connections; dropping new SOCKS5 connection"));
             }
         }
@@ -111,3 +173,53 @@ pub async fn run_socks5_proxy_auth(iface: &str, listen: &str, user: Option<&str>
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialer::MemoryDialer;
+    use crate::util::Cidr;
+
+    #[tokio::test]
+    async fn connect_by_domain_goes_through_dialer() {
+        let dialer = MemoryDialer::default();
+        let (mut client, server) = tokio::io::duplex(4096);
+        let session = handle_socks5(server, DialContext { dialer: &dialer, iface: "lo", deny: &[] }, None, 1000, 5000);
+        let client = async {
+            client.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+            let mut b = [0u8; 2];
+            client.read_exact(&mut b).await.unwrap();
+            assert_eq!(b, [0x05, 0x00]);
+            client.write_all(&[0x05, 0x01, 0x00, 0x03, 11]).await.unwrap();
+            client.write_all(b"example.com\x01\xbb").await.unwrap();
+            let mut rep = [0u8; 10];
+            client.read_exact(&mut rep).await.unwrap();
+            assert_eq!(rep[1], 0x00);
+            client.write_all(b"ping").await.unwrap();
+            let mut echo = [0u8; 4];
+            client.read_exact(&mut echo).await.unwrap();
+            assert_eq!(&echo, b"ping");
+            drop(client);
+        };
+        let (res, ()) = tokio::join!(session, client);
+        res.unwrap();
+        assert_eq!(*dialer.dialed.lock().unwrap(), ["example.com:443"]);
+    }
+
+    #[tokio::test]
+    async fn denied_destination_is_refused_with_rep_2() {
+        let dialer = MemoryDialer::default();
+        let deny = [Cidr::parse("10.0.0.0/8").unwrap()];
+        let (mut client, server) = tokio::io::duplex(4096);
+        let session = handle_socks5(server, DialContext { dialer: &dialer, iface: "lo", deny: &deny }, None, 1000, 5000);
+        let client = async {
+            client.write_all(&[0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x01, 10, 1, 2, 3, 0, 80]).await.unwrap();
+            let mut b = [0u8; 12];
+            client.read_exact(&mut b).await.unwrap();
+            assert_eq!(b[3], 0x02);
+        };
+        let (res, ()) = tokio::join!(session, client);
+        assert!(matches!(ProxyError::find(&res.unwrap_err()), Some(ProxyError::PolicyDenied(crate::error::Denied::Dest(_)))));
+        assert!(dialer.dialed.lock().unwrap().is_empty());
+    }
+}
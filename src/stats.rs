@@ -0,0 +1,218 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{copy_bidirectional, AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{timeout, Instant};
+
+use crate::util::log_info;
+
+// 按目标主机聚合的流量统计；会话结束时记录一次
+#[derive(Clone, Copy, Default)]
+pub(crate) struct HostStats {
+    pub(crate) connections: u64,
+    pub(crate) bytes_up: u64,
+    pub(crate) bytes_down: u64,
+    pub(crate) total_ms: u64,
+}
+
+impl HostStats {
+    fn total_bytes(&self) -> u64 {
+        self.bytes_up + self.bytes_down
+    }
+
+    fn avg_ms(&self) -> u64 {
+        self.total_ms.checked_div(self.connections).unwrap_or(0)
+    }
+}
+
+fn table() -> &'static Mutex<HashMap<String, HostStats>> {
+    static TABLE: OnceLock<Mutex<HashMap<String, HostStats>>> = OnceLock::new();
+    TABLE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+pub(crate) fn record(host: &str, bytes_up: u64, bytes_down: u64, elapsed: Duration) {
+    let mut t = table().lock().unwrap_or_else(|e| e.into_inner());
+    let e = t.entry(normalize(host)).or_default();
+    e.connections += 1;
+    e.bytes_up += bytes_up;
+    e.bytes_down += bytes_down;
+    e.total_ms += elapsed.as_millis() as u64;
+}
+
+// 按总字节数降序
+pub(crate) fn top(n: usize) -> Vec<(String, HostStats)> {
+    let t = table().lock().unwrap_or_else(|e| e.into_inner());
+    let mut v: Vec<(String, HostStats)> = t.iter().map(|(k, s)| (k.clone(), *s)).collect();
+    v.sort_by(|a, b| b.1.total_bytes().cmp(&a.1.total_bytes()).then_with(|| a.0.cmp(&b.0)));
+    v.truncate(n);
+    v
+}
+
+pub(crate) fn render_top(n: usize) -> String {
+    let mut out = format!("{:<40} {:>8} {:>14} {:>14} {:>10}\n", "host", "conns", "bytes_up", "bytes_down", "avg_ms");
+    for (host, s) in top(n) {
+        out.push_str(&format!("{:<40} {:>8} {:>14} {:>14} {:>10}\n", host, s.connections, s.bytes_up, s.bytes_down, s.avg_ms()));
+    }
+    out
+}
+
+// 持久化格式：每行 `host\tconnections\tbytes_up\tbytes_down\ttotal_ms`
+pub(crate) fn load(path: &str) -> Result<()> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => anyhow::bail!("failed to read stats file {}: {}", path, e),
+    };
+    let mut t = table().lock().unwrap_or_else(|e| e.into_inner());
+    for (idx, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') { continue; }
+        let f: Vec<&str> = line.split('\t').collect();
+        let num = |i: usize| f.get(i).and_then(|v| v.parse::<u64>().ok()).ok_or_else(|| anyhow::anyhow!("stats file {} line {}: malformed", path, idx + 1));
+        let e = t.entry(normalize(f[0])).or_default();
+        e.connections += num(1)?;
+        e.bytes_up += num(2)?;
+        e.bytes_down += num(3)?;
+        e.total_ms += num(4)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn save(path: &str) -> Result<()> {
+    let mut out = String::from("# host\tconnections\tbytes_up\tbytes_down\ttotal_ms\n");
+    for (host, s) in top(usize::MAX) {
+        out.push_str(&format!("{}\t{}\t{}\t{}\t{}\n", host, s.connections, s.bytes_up, s.bytes_down, s.total_ms));
+    }
+    // 先写临时文件再 rename，避免中途退出留下半个文件
+    let tmp = format!("{}.tmp", path);
+    std::fs::write(&tmp, out)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+pub(crate) async fn run_reporter(interval_secs: u64, top_n: usize) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let rows = top(top_n);
+        if rows.is_empty() { continue; }
+        let parts: Vec<String> = rows
+            .iter()
+            .map(|(h, s)| format!("{} conns={} up={} down={} avg_ms={}", crate::addr::redact_host(h), s.connections, s.bytes_up, s.bytes_down, s.avg_ms()))
+            .collect();
+        log_info(format!("top hosts: {}", parts.join("; ")));
+    }
+}
+
+// 进程累计转发字节数（上行, 下行），用于定时摘要中的速率
+static LIVE_UP: AtomicU64 = AtomicU64::new(0);
+static LIVE_DOWN: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn count_bytes(up: u64, down: u64) {
+    if up > 0 { LIVE_UP.fetch_add(up, Ordering::Relaxed); }
+    if down > 0 { LIVE_DOWN.fetch_add(down, Ordering::Relaxed); }
+}
+
+// 每隔 interval_secs 输出一行运行概况：新建会话速率、活动会话、上/下行速率及该时段内结束会话的出错比例
+pub(crate) async fn run_summary(interval_secs: u64) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+    ticker.tick().await;
+    let mut last = Instant::now();
+    let (mut prev_acc, mut prev_fin, mut prev_fail) = crate::session::counters();
+    let (mut prev_up, mut prev_down) = (LIVE_UP.load(Ordering::Relaxed), LIVE_DOWN.load(Ordering::Relaxed));
+    loop {
+        ticker.tick().await;
+        let secs = last.elapsed().as_secs_f64().max(0.001);
+        last = Instant::now();
+        let (acc, fin, fail) = crate::session::counters();
+        let (up, down) = (LIVE_UP.load(Ordering::Relaxed), LIVE_DOWN.load(Ordering::Relaxed));
+        let (d_fin, d_fail) = (fin - prev_fin, fail - prev_fail);
+        let err_pct = if d_fin == 0 { 0.0 } else { d_fail as f64 * 100.0 / d_fin as f64 };
+        log_info(format!(
+            "summary: accepted/s={:.1} active={} up={}B/s down={}B/s errors={}/{} ({:.1}%)",
+            (acc - prev_acc) as f64 / secs,
+            crate::session::active(),
+            ((up - prev_up) as f64 / secs) as u64,
+            ((down - prev_down) as f64 / secs) as u64,
+            d_fail,
+            d_fin,
+            err_pct
+        ));
+        (prev_acc, prev_fin, prev_fail, prev_up, prev_down) = (acc, fin, fail, up, down);
+    }
+}
+
+// 统计经过出站连接的字节数：写入为上行，读出为下行。会话因超时或出错中断时计数依然有效
+pub(crate) struct Metered<S> {
+    inner: S,
+    up: u64,
+    down: u64,
+    // 所属会话的流量与活动时间（/sessions 与空闲清理用）
+    session: Option<Arc<crate::session::Activity>>,
+}
+
+impl<S> Metered<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        Self { inner, up: 0, down: 0, session: crate::session::activity() }
+    }
+
+    // (上行, 下行)
+    pub(crate) fn totals(&self) -> (u64, u64) {
+        (self.up, self.down)
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Metered<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let n = (buf.filled().len() - before) as u64;
+        self.down += n;
+        count_bytes(0, n);
+        if let Some(a) = self.session.as_ref().filter(|_| n > 0) { a.add(0, n); }
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Metered<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            self.up += n as u64;
+            count_bytes(n as u64, 0);
+            if let Some(a) = &self.session { a.add(n as u64, 0); }
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// 双向转发并记账（目标主机，及认证用户的用量）；超时或出错时也按已转发的字节记录
+pub(crate) async fn relay<A, B>(inbound: &mut A, mut outbound: Metered<B>, host: &str, user: Option<&str>, session_timeout_ms: u64) -> Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    let started = Instant::now();
+    let res = timeout(Duration::from_millis(session_timeout_ms), copy_bidirectional(inbound, &mut outbound)).await;
+    let (up, down) = outbound.totals();
+    record(host, up, down, started.elapsed());
+    if let Some(user) = user { crate::quota::record(user, up, down); }
+    res??;
+    Ok((up, down))
+}
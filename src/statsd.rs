@@ -0,0 +1,205 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::util::{log_error, log_throttled};
+
+// --statsd：没有 Prometheus 抓取时，定时把 /metrics 中的全部指标以 StatsD/DogStatsD 格式经 UDP 推出去。
+// counter 推送与上次的差值 (|c)，gauge 推送当前值 (|g)；指标上的标签 (listener、kind 等) 在 DogStatsD 中
+// 成为标签，普通 StatsD 不支持标签，按顺序拼进指标名
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    Statsd,
+    Dogstatsd,
+}
+
+impl Format {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "statsd" => Ok(Self::Statsd),
+            "dogstatsd" | "datadog" => Ok(Self::Dogstatsd),
+            _ => anyhow::bail!("invalid statsd format {:?} (expected statsd or dogstatsd)", s),
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Statsd => "statsd",
+            Self::Dogstatsd => "dogstatsd",
+        }
+    }
+}
+
+pub(crate) struct StatsdConfig {
+    pub(crate) addr: String,
+    pub(crate) prefix: String,
+    pub(crate) format: Format,
+    // 附加到每个指标上的标签 (仅 DogStatsD)
+    pub(crate) tags: Vec<(String, String)>,
+    pub(crate) interval: Duration,
+}
+
+// KEY:VALUE
+pub(crate) fn parse_tag(s: &str) -> Result<(String, String)> {
+    let (k, v) = s.split_once(':').ok_or_else(|| anyhow::anyhow!("invalid statsd tag {:?} (expected KEY:VALUE)", s))?;
+    anyhow::ensure!(!k.trim().is_empty(), "invalid statsd tag {:?}: empty key", s);
+    Ok((k.trim().to_string(), v.trim().to_string()))
+}
+
+// 单个 UDP 包的上限，留足以太网 MTU 下的余量
+const MAX_PACKET: usize = 1400;
+
+pub(crate) async fn run(cfg: StatsdConfig) {
+    let target = match tokio::net::lookup_host(cfg.addr.as_str()).await.map(|mut a| a.next()) {
+        Ok(Some(t)) => t,
+        Ok(None) => return log_error(format!("statsd: {} has no address, not pushing metrics", cfg.addr)),
+        Err(e) => return log_error(format!("statsd: resolve {} failed, not pushing metrics: {}", cfg.addr, e)),
+    };
+    let sock = match tokio::net::UdpSocket::bind(if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }).await {
+        Ok(s) => s,
+        Err(e) => return log_error(format!("statsd: bind udp socket failed: {}", e)),
+    };
+    let mut last = HashMap::new();
+    let mut ticker = tokio::time::interval(cfg.interval);
+    loop {
+        ticker.tick().await;
+        let lines = lines(&crate::metrics::render(), &cfg, &mut last);
+        for packet in packets(&lines) {
+            if let Err(e) = sock.send_to(packet.as_bytes(), target).await {
+                log_throttled(|| log_error(format!("statsd: send to {} failed: {}", target, e)));
+            }
+        }
+    }
+}
+
+type Labels = Vec<(String, String)>;
+
+// Prometheus 文本中的一条样本：指标名、标签、值
+fn parse_sample(line: &str) -> Option<(&str, Labels, f64)> {
+    let (series, value) = line.rsplit_once(' ')?;
+    let value: f64 = value.parse().ok()?;
+    let Some((name, rest)) = series.split_once('{') else { return Some((series, Vec::new(), value)) };
+    let mut labels = Vec::new();
+    let mut chars = rest.strip_suffix('}')?.chars().peekable();
+    loop {
+        let key: String = chars.by_ref().take_while(|c| *c != '=').collect();
+        if key.is_empty() { break; }
+        if chars.next() != Some('"') { return None; }
+        let mut val = String::new();
+        loop {
+            match chars.next()? {
+                '\\' => match chars.next()? {
+                    'n' => val.push('\n'),
+                    c => val.push(c),
+                },
+                '"' => break,
+                c => val.push(c),
+            }
+        }
+        labels.push((key, val));
+        if chars.peek() == Some(&',') { chars.next(); }
+    }
+    Some((name, labels, value))
+}
+
+// 指标名与标签值中只保留 StatsD 安全的字符
+fn clean(s: &str) -> String {
+    s.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' { c } else { '_' }).collect()
+}
+
+fn clean_tag(s: &str) -> String {
+    s.chars().map(|c| if matches!(c, ',' | '|' | '#' | '\n') { '_' } else { c }).collect()
+}
+
+// 把一次 /metrics 输出转换成 StatsD 行；`last` 记录各 counter 上次的值，计数归零 (重启、重置) 时按当前值推送
+fn lines(text: &str, cfg: &StatsdConfig, last: &mut HashMap<String, f64>) -> Vec<String> {
+    let mut counters: Vec<&str> = Vec::new();
+    let mut out = Vec::new();
+    for line in text.lines() {
+        if let Some(rest) = line.strip_prefix("# TYPE ") {
+            if let Some((name, "counter")) = rest.split_once(' ') { counters.push(name); }
+            continue;
+        }
+        if line.starts_with('#') || line.is_empty() { continue; }
+        let Some((name, labels, value)) = parse_sample(line) else { continue };
+        let short = name.strip_prefix("iface_proxy_").unwrap_or(name);
+        let mut metric = if cfg.prefix.is_empty() { clean(short) } else { format!("{}.{}", cfg.prefix, clean(short)) };
+        let mut tags: Vec<String> = Vec::new();
+        match cfg.format {
+            Format::Statsd => labels.iter().for_each(|(_, v)| { metric.push('.'); metric.push_str(&clean(v)); }),
+            Format::Dogstatsd => {
+                // 指标自带的同名标签 (如探测的 iface) 优先
+                let own = |k: &String| labels.iter().any(|(l, _)| l == k);
+                tags.extend(cfg.tags.iter().filter(|(k, _)| !own(k)).map(|(k, v)| format!("{}:{}", clean_tag(k), clean_tag(v))));
+                tags.extend(labels.iter().map(|(k, v)| format!("{}:{}", clean_tag(k), clean_tag(v))));
+            }
+        }
+        let suffix = if tags.is_empty() { String::new() } else { format!("|#{}", tags.join(",")) };
+        if counters.contains(&name) {
+            let prev = last.insert(line.rsplit_once(' ').map_or(line, |(s, _)| s).to_string(), value).unwrap_or(0.0);
+            let delta = if value >= prev { value - prev } else { value };
+            if delta > 0.0 { out.push(format!("{}:{}|c{}", metric, delta, suffix)); }
+        } else {
+            // StatsD 中带符号的 gauge 值表示增减，负数先归零再设置
+            if value < 0.0 { out.push(format!("{}:0|g{}", metric, suffix)); }
+            out.push(format!("{}:{}|g{}", metric, value, suffix));
+        }
+    }
+    out
+}
+
+// 按行拼成不超过 MAX_PACKET 的包
+fn packets(lines: &[String]) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    for line in lines {
+        match out.last_mut() {
+            Some(p) if p.len() + 1 + line.len() <= MAX_PACKET => {
+                p.push('\n');
+                p.push_str(line);
+            }
+            _ => out.push(line.clone()),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "# TYPE iface_proxy_active_sessions gauge\niface_proxy_active_sessions 3\n# TYPE iface_proxy_conn_rejected_total counter\niface_proxy_conn_rejected_total{listener=\"http=127.0.0.1:7890\"} 5\niface_proxy_conn_rejected_total{listener=\"socks5=[::1]:1080\"} 0\n# TYPE iface_proxy_probe_connect_ms gauge\niface_proxy_probe_connect_ms{target=\"a\\\"b\",iface=\"eth0\"} 12.5\n";
+
+    fn cfg(format: Format) -> StatsdConfig {
+        StatsdConfig { addr: String::new(), prefix: String::from("proxy"), format, tags: vec![(String::from("iface"), String::from("eth0"))], interval: Duration::from_secs(10) }
+    }
+
+    #[test]
+    fn dogstatsd_tags_and_counter_deltas() {
+        let cfg = cfg(Format::Dogstatsd);
+        let mut last = HashMap::new();
+        assert_eq!(
+            lines(TEXT, &cfg, &mut last),
+            [
+                "proxy.active_sessions:3|g|#iface:eth0",
+                "proxy.conn_rejected_total:5|c|#iface:eth0,listener:http=127.0.0.1:7890",
+                "proxy.probe_connect_ms:12.5|g|#target:a\"b,iface:eth0",
+            ]
+        );
+        let next = TEXT.replace("7890\"} 5", "7890\"} 8").replace("1080\"} 0", "1080\"} 2");
+        let out = lines(&next, &cfg, &mut last);
+        assert_eq!(out[1], "proxy.conn_rejected_total:3|c|#iface:eth0,listener:http=127.0.0.1:7890");
+        assert_eq!(out[2], "proxy.conn_rejected_total:2|c|#iface:eth0,listener:socks5=[::1]:1080");
+    }
+
+    #[test]
+    fn plain_statsd_folds_labels_and_packs() {
+        let out = lines(TEXT, &cfg(Format::Statsd), &mut HashMap::new());
+        assert_eq!(out[1], "proxy.conn_rejected_total.http_127.0.0.1_7890:5|c");
+        assert_eq!(out[2], "proxy.probe_connect_ms.a_b.eth0:12.5|g");
+        let many: Vec<String> = (0..100).map(|i| format!("proxy.metric_{:03}:1|g", i)).collect();
+        let packed = packets(&many);
+        assert!(packed.len() > 1 && packed.iter().all(|p| p.len() <= MAX_PACKET));
+        assert_eq!(packed.join("\n").lines().count(), 100);
+        assert!(parse_tag("novalue").is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use anyhow::Result;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 管理接口签发的 Bearer 令牌，供脚本代替长期密码做 HTTP 代理认证。
+// 只保存令牌的 blake3 哈希，明文只在创建时返回一次；id 取哈希前 4 字节，用于列出与吊销
+pub(crate) const DEFAULT_TTL_SECS: u64 = 86400;
+// 轮换时旧令牌默认再保留的时间，给正在切换的脚本留出余量
+pub(crate) const DEFAULT_GRACE_SECS: u64 = 60;
+const PREFIX: &str = "ifp_";
+
+#[derive(Clone, Debug, PartialEq)]
+struct Token {
+    hash: [u8; 32],
+    user: String,
+    // 签发时的有效期，轮换时沿用
+    ttl: u64,
+    // unix 秒
+    expires: u64,
+}
+
+impl Token {
+    fn id(&self) -> String {
+        self.hash[..8].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+pub(crate) struct Issued {
+    pub(crate) token: String,
+    pub(crate) id: String,
+    pub(crate) user: String,
+    pub(crate) expires: u64,
+}
+
+#[derive(Default)]
+struct Store {
+    tokens: Vec<Token>,
+}
+
+impl Store {
+    fn issue(&mut self, user: &str, ttl: u64, now: u64) -> Result<Issued> {
+        if user.is_empty() || user.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            anyhow::bail!("invalid user {:?}", user);
+        }
+        if ttl == 0 { anyhow::bail!("ttl must be positive"); }
+        let mut secret = [0u8; 24];
+        getrandom::getrandom(&mut secret).map_err(|e| anyhow::anyhow!("failed to generate token: {}", e))?;
+        use base64::Engine;
+        let token = format!("{}{}", PREFIX, base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(secret));
+        let t = Token { hash: *blake3::hash(token.as_bytes()).as_bytes(), user: user.to_string(), ttl, expires: now.saturating_add(ttl) };
+        let issued = Issued { token, id: t.id(), user: t.user.clone(), expires: t.expires };
+        self.tokens.retain(|t| t.expires > now);
+        self.tokens.push(t);
+        Ok(issued)
+    }
+
+    fn user(&self, token: &str, now: u64) -> Option<String> {
+        let hash = blake3::hash(token.as_bytes());
+        self.tokens.iter().find(|t| t.hash == *hash.as_bytes() && t.expires > now).map(|t| t.user.clone())
+    }
+
+    fn revoke(&mut self, id: &str) -> bool {
+        let before = self.tokens.len();
+        self.tokens.retain(|t| t.id() != id);
+        self.tokens.len() != before
+    }
+
+    // 为同一用户签发有效期相同的新令牌，旧令牌在 grace 秒后失效（不晚于原来的到期时间）
+    fn rotate(&mut self, id: &str, grace: u64, now: u64) -> Result<Issued> {
+        let old = self.tokens.iter_mut().find(|t| t.id() == id && t.expires > now).ok_or_else(|| anyhow::anyhow!("no active token {:?}", id))?;
+        old.expires = old.expires.min(now.saturating_add(grace));
+        let (user, ttl) = (old.user.clone(), old.ttl);
+        self.issue(&user, ttl, now)
+    }
+
+    // 每行 `HASH USER TTL EXPIRES`
+    fn serialize(&self, now: u64) -> String {
+        self.tokens.iter().filter(|t| t.expires > now).map(|t| format!("{} {} {} {}\n", blake3::Hash::from(t.hash).to_hex(), t.user, t.ttl, t.expires)).collect()
+    }
+
+    fn parse(body: &str) -> Result<Self> {
+        let mut tokens = Vec::new();
+        for (i, line) in body.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            let invalid = || anyhow::anyhow!("line {}: expected HASH USER TTL EXPIRES", i + 1);
+            let f: Vec<&str> = line.split_whitespace().collect();
+            let [hash, user, ttl, expires] = f[..] else { return Err(invalid()) };
+            tokens.push(Token {
+                hash: *blake3::Hash::from_hex(hash).map_err(|_| invalid())?.as_bytes(),
+                user: user.to_string(),
+                ttl: ttl.parse().map_err(|_| invalid())?,
+                expires: expires.parse().map_err(|_| invalid())?,
+            });
+        }
+        Ok(Self { tokens })
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn store() -> &'static Mutex<Store> {
+    static STORE: OnceLock<Mutex<Store>> = OnceLock::new();
+    STORE.get_or_init(|| Mutex::new(Store::default()))
+}
+
+// --token-file：令牌哈希落盘，重启后仍有效
+static FILE: OnceLock<String> = OnceLock::new();
+
+// 启动时调用；文件不存在时从空开始，首次签发时创建。返回读到的有效令牌数
+pub(crate) fn load(path: Option<String>) -> Result<usize> {
+    let Some(path) = path else { return Ok(0) };
+    let loaded = match std::fs::read_to_string(&path) {
+        Ok(body) => Store::parse(&body).map_err(|e| anyhow::anyhow!("token file {}: {}", path, e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Store::default(),
+        Err(e) => anyhow::bail!("token file {}: {}", path, e),
+    };
+    let now = now();
+    let n = loaded.tokens.iter().filter(|t| t.expires > now).count();
+    *store().lock().unwrap_or_else(|e| e.into_inner()) = loaded;
+    let _ = FILE.set(path);
+    Ok(n)
+}
+
+// 先写临时文件再 rename，权限 0600
+fn save(store: &Store) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    let Some(path) = FILE.get() else { return Ok(()) };
+    let tmp = format!("{}.tmp", path);
+    let mut f = std::fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&tmp)
+        .map_err(|e| anyhow::anyhow!("failed to write token file {}: {}", tmp, e))?;
+    f.write_all(store.serialize(now()).as_bytes()).and_then(|_| f.sync_all()).map_err(|e| anyhow::anyhow!("failed to write token file {}: {}", tmp, e))?;
+    std::fs::rename(&tmp, path).map_err(|e| anyhow::anyhow!("failed to write token file {}: {}", path, e))?;
+    Ok(())
+}
+
+// `Proxy-Authorization: Bearer TOKEN` 中有效令牌对应的用户名
+pub(crate) fn bearer_user(value: &str) -> Option<String> {
+    let mut parts = value.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") && token.starts_with(PREFIX) => {
+            store().lock().unwrap_or_else(|e| e.into_inner()).user(token, now())
+        }
+        _ => None,
+    }
+}
+
+// 落盘失败时撤销本次修改，内存与文件保持一致
+fn update<T>(f: impl FnOnce(&mut Store) -> Result<T>) -> Result<T> {
+    let mut store = store().lock().unwrap_or_else(|e| e.into_inner());
+    let before = store.tokens.clone();
+    let res = f(&mut store)?;
+    if let Err(e) = save(&store) {
+        store.tokens = before;
+        return Err(e);
+    }
+    Ok(res)
+}
+
+pub(crate) fn issue(user: &str, ttl: u64) -> Result<Issued> {
+    update(|s| s.issue(user, ttl, now()))
+}
+
+pub(crate) fn revoke(id: &str) -> Result<bool> {
+    update(|s| Ok(s.revoke(id)))
+}
+
+pub(crate) fn rotate(id: &str, grace: u64) -> Result<Issued> {
+    update(|s| s.rotate(id, grace, now()))
+}
+
+pub(crate) fn render() -> String {
+    let now = now();
+    let store = store().lock().unwrap_or_else(|e| e.into_inner());
+    let mut out = format!("{:<16} {:<24} {:>10} {}\n", "id", "user", "ttl", "expires_in");
+    for t in store.tokens.iter().filter(|t| t.expires > now) {
+        out.push_str(&format!("{:<16} {:<24} {:>10} {}\n", t.id(), t.user, t.ttl, t.expires - now));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn issue_rotate_revoke_and_persist() {
+        let mut s = Store::default();
+        let a = s.issue("ci", 100, 1000).unwrap();
+        assert!(a.token.starts_with(PREFIX) && a.expires == 1100);
+        assert!(s.issue("bad user", 100, 1000).is_err() && s.issue("ci", 0, 1000).is_err());
+        assert_eq!(s.user(&a.token, 1099).as_deref(), Some("ci"));
+        assert!(s.user(&a.token, 1100).is_none() && s.user("ifp_wrong", 1000).is_none());
+
+        // 轮换后旧令牌只在宽限期内有效，新令牌沿用原有效期
+        let b = s.rotate(&a.id, 10, 1050).unwrap();
+        assert_eq!((b.user.as_str(), b.expires), ("ci", 1150));
+        assert!(s.user(&a.token, 1059).is_some() && s.user(&a.token, 1060).is_none());
+        assert!(s.rotate(&a.id, 10, 1060).is_err());
+
+        let restored = Store::parse(&s.serialize(1060)).unwrap();
+        assert_eq!(restored.tokens.len(), 1);
+        assert_eq!(restored.user(&b.token, 1100).as_deref(), Some("ci"));
+        assert!(Store::parse("zz ci 1 2\n").is_err());
+
+        assert!(s.revoke(&b.id) && !s.revoke(&b.id));
+        assert!(s.user(&b.token, 1100).is_none());
+    }
+}
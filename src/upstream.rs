@@ -0,0 +1,334 @@
+use anyhow::Result;
+use std::sync::{Arc, OnceLock};
+
+use crate::acl::AclRule;
+use crate::dialer::{DialFuture, DialRequest, Dialer, OutboundStream};
+use crate::shadowsocks::{SsClientStream, SsConfig};
+use crate::ssh::SshConfig;
+use crate::error::{Denied, ProxyError};
+use crate::router::Resolution;
+use crate::util::{connect_outbound, log_throttled, log_info};
+use crate::websocket::ClientHandshake;
+
+#[derive(Clone)]
+pub(crate) enum UpstreamKind {
+    Shadowsocks(Arc<SsConfig>),
+    // 另一台 iface-proxy 的 `ws` 监听，由它发起出站连接
+    WebSocket(Arc<WsConfig>),
+    // SSH 跳板机：各目标在同一条共享连接上以 direct-tcpip 通道打开
+    Ssh(Arc<SshConfig>),
+}
+
+pub(crate) struct WsConfig {
+    pub(crate) tls: bool,
+    pub(crate) path: String,
+    pub(crate) auth: Option<(String, String)>,
+    // 请求远端使用的出口网卡（须在远端的 --egress-allow 中）
+    pub(crate) egress: Option<String>,
+    // 仅 wss：TLS ClientHello 中代替上游主机名发送的 SNI
+    pub(crate) sni: Option<Sni>,
+}
+
+// `sni=HOST` 发送 HOST 并按它校验证书（按 IP 连接、靠 SNI 分流的 CDN 节点）；
+// `sni=none` 不发送 SNI，仍按上游主机名校验
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Sni {
+    Host(String),
+    Omit,
+}
+
+// 关闭 SNI 的共享客户端配置
+fn no_sni_tls_config() -> Arc<tokio_rustls::rustls::ClientConfig> {
+    static CONFIG: OnceLock<Arc<tokio_rustls::rustls::ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let mut cfg = (*crate::ruleset::tls_config()).clone();
+            cfg.enable_sni = false;
+            Arc::new(cfg)
+        })
+        .clone()
+}
+
+// WebSocket 握手的时限，不含 TCP 建连
+const WS_HANDSHAKE_TIMEOUT_MS: u64 = 10_000;
+
+#[derive(Clone)]
+pub(crate) struct Upstream {
+    pub(crate) name: String,
+    pub(crate) kind: UpstreamKind,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    // 连接上游服务器所用的出口网卡，缺省为 --iface
+    pub(crate) iface: Option<String>,
+}
+
+impl Upstream {
+    // NAME=ss://METHOD:PASSWORD@HOST:PORT[?iface=utun2]
+    // NAME=ws://[USER:PASS@]HOST:PORT[/PATH][?iface=utun2&egress=eth1]（TLS 用 wss://，另可带 sni=HOST|none）
+    // NAME=ssh://USER@HOST[:PORT][?iface=utun2&key=PATH&known-hosts=PATH&host-key=SHA256:...]
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (name, url) = s.split_once('=').ok_or_else(|| anyhow::anyhow!("invalid upstream {:?} (expected NAME=URL)", s))?;
+        let name = name.trim();
+        if name.is_empty() { anyhow::bail!("upstream {:?} has an empty name", s); }
+        let url = url.trim();
+        let (scheme, rest) = url.split_once("://").ok_or_else(|| anyhow::anyhow!("unsupported upstream scheme in {:?} (supported: ss://, ws://, wss://, ssh://)", url))?;
+        let (rest, query) = match rest.split_once('?') { Some((r, q)) => (r, Some(q)), None => (rest, None) };
+        let (rest, path) = match scheme {
+            "ws" | "wss" => match rest.find('/') { Some(i) => (&rest[..i], &rest[i..]), None => (rest, "/") },
+            _ => (rest, ""),
+        };
+        let (userinfo, hostport) = match rest.rsplit_once('@') { Some((u, h)) => (Some(u), h), None => (None, rest) };
+        let (host, port) = if scheme == "ssh" {
+            crate::uri::parse_authority(hostport, 22).map_err(|e| anyhow::anyhow!("upstream {:?}: {}", name, e))?
+        } else {
+            let (host, port) = hostport.rsplit_once(':').ok_or_else(|| anyhow::anyhow!("upstream {:?} is missing a port", name))?;
+            let port: u16 = port.parse().map_err(|_| anyhow::anyhow!("upstream {:?} has an invalid port", name))?;
+            (host.trim_start_matches('[').trim_end_matches(']').to_string(), port)
+        };
+        let mut iface = None;
+        let mut egress = None;
+        let mut sni = None;
+        let (mut key, mut known_hosts, mut host_key) = (None, None, None);
+        for pair in query.unwrap_or("").split('&').filter(|p| !p.is_empty()) {
+            match (scheme, pair.split_once('=')) {
+                (_, Some(("iface", v))) => iface = Some(v.to_string()),
+                ("ws" | "wss", Some(("egress", v))) => egress = Some(v.to_string()),
+                ("wss", Some(("sni", "none"))) => sni = Some(Sni::Omit),
+                ("wss", Some(("sni", v))) if !v.is_empty() => sni = Some(Sni::Host(v.to_ascii_lowercase())),
+                ("ssh", Some(("key", v))) => key = Some(crate::uri::percent_decode(v).map_err(anyhow::Error::msg)?),
+                ("ssh", Some(("known-hosts", v))) => known_hosts = Some(crate::uri::percent_decode(v).map_err(anyhow::Error::msg)?),
+                ("ssh", Some(("host-key", v))) => host_key = Some(v.to_string()),
+                _ => anyhow::bail!("unknown upstream option {:?}", pair),
+            }
+        }
+        let kind = match scheme {
+            "ss" => {
+                let userinfo = userinfo.ok_or_else(|| anyhow::anyhow!("upstream {:?} is missing METHOD:PASSWORD@", name))?;
+                let (method, password) = userinfo.split_once(':').ok_or_else(|| anyhow::anyhow!("upstream {:?} is missing METHOD:PASSWORD", name))?;
+                UpstreamKind::Shadowsocks(Arc::new(SsConfig::new(method, &crate::uri::percent_decode(password).map_err(anyhow::Error::msg)?)?))
+            }
+            "ws" | "wss" => {
+                let auth = match userinfo {
+                    Some(u) => {
+                        let (user, pass) = u.split_once(':').ok_or_else(|| anyhow::anyhow!("upstream {:?} is missing USER:PASS", name))?;
+                        let decode = |v: &str| crate::uri::percent_decode(v).map_err(anyhow::Error::msg);
+                        Some((decode(user)?, decode(pass)?))
+                    }
+                    None => None,
+                };
+                UpstreamKind::WebSocket(Arc::new(WsConfig { tls: scheme == "wss", path: path.to_string(), auth, egress, sni }))
+            }
+            "ssh" => {
+                let user = userinfo.ok_or_else(|| anyhow::anyhow!("upstream {:?} is missing USER@", name))?;
+                if user.contains(':') { anyhow::bail!("upstream {:?}: ssh password authentication is not supported; use key=PATH", name); }
+                let cfg = SshConfig::load(&crate::uri::percent_decode(user).map_err(anyhow::Error::msg)?, &host, port, key.as_deref(), known_hosts.as_deref(), host_key.as_deref())
+                    .map_err(|e| anyhow::anyhow!("upstream {:?}: {}", name, e))?;
+                UpstreamKind::Ssh(Arc::new(cfg))
+            }
+            _ => anyhow::bail!("unsupported upstream scheme in {:?} (supported: ss://, ws://, wss://, ssh://)", url),
+        };
+        Ok(Self { name: name.to_string(), kind, host, port, iface })
+    }
+
+    async fn connect(&self, host: &str, port: u16, default_iface: &str) -> Result<OutboundStream> {
+        let iface = self.iface.as_deref().unwrap_or(default_iface);
+        let server = || connect_outbound(&self.host, self.port, iface, &[]);
+        match &self.kind {
+            UpstreamKind::Shadowsocks(cfg) => Ok(Box::new(SsClientStream::connect(server().await?, cfg.clone(), host, port).await?)),
+            UpstreamKind::Ssh(cfg) => cfg.open(&self.host, self.port, iface, host, port).await,
+            UpstreamKind::WebSocket(cfg) => {
+                let server = server().await?;
+                let bracket = |h: &str| if h.contains(':') { format!("[{}]", h) } else { h.to_string() };
+                let target = format!("{}:{}", bracket(host), port);
+                // 按 IP 连接并指定 sni=HOST 时，Host 头也须是这个名字
+                let authority = match &cfg.sni {
+                    Some(Sni::Host(h)) if self.host.parse::<std::net::IpAddr>().is_ok() => h.clone(),
+                    _ => bracket(&self.host),
+                };
+                let hs = ClientHandshake {
+                    host: &format!("{}:{}", authority, self.port),
+                    path: &cfg.path,
+                    auth: cfg.auth.as_ref().map(|(u, p)| (u.as_str(), p.as_str())),
+                    target: &target,
+                    egress: cfg.egress.as_deref(),
+                };
+                if !cfg.tls { return Ok(Box::new(crate::websocket::connect(server, hs, WS_HANDSHAKE_TIMEOUT_MS).await?)); }
+                let (name, config) = match &cfg.sni {
+                    Some(Sni::Host(h)) => (h.clone(), crate::ruleset::tls_config()),
+                    Some(Sni::Omit) => (self.host.clone(), no_sni_tls_config()),
+                    None => (self.host.clone(), crate::ruleset::tls_config()),
+                };
+                let name = tokio_rustls::rustls::pki_types::ServerName::try_from(name)?;
+                let tls = tokio_rustls::TlsConnector::from(config).connect(name, server).await?;
+                Ok(Box::new(crate::websocket::connect(tls, hs, WS_HANDSHAKE_TIMEOUT_MS).await?))
+            }
+        }
+    }
+}
+
+// --upstream-rule：目标主机匹配 SUFFIX（含子域名，见 host_has_suffix）时经名为 `upstream` 的上游连接
+#[derive(Clone)]
+pub(crate) struct UpstreamRule {
+    pub(crate) suffix: String,
+    pub(crate) upstream: String,
+}
+
+impl UpstreamRule {
+    // SUFFIX=NAME，如 `example.com=remote`
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (suffix, upstream) = s.split_once('=').ok_or_else(|| anyhow::anyhow!("invalid upstream rule {:?} (expected SUFFIX=NAME)", s))?;
+        Ok(Self { suffix: suffix.trim().trim_start_matches('.').to_ascii_lowercase(), upstream: upstream.trim().to_string() })
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        crate::util::host_has_suffix(host, &self.suffix)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct UpstreamTable {
+    upstreams: Vec<Upstream>,
+    rules: Vec<UpstreamRule>,
+}
+
+impl UpstreamTable {
+    pub(crate) fn new(upstreams: Vec<Upstream>, rules: Vec<UpstreamRule>) -> Result<Self> {
+        for r in &rules {
+            if !upstreams.iter().any(|u| u.name == r.upstream) {
+                anyhow::bail!("upstream rule {} refers to unknown upstream {:?}", r.suffix, r.upstream);
+            }
+        }
+        Ok(Self { upstreams, rules })
+    }
+
+    pub(crate) fn summary(&self) -> String {
+        format!("upstreams={} upstream-rules={}", self.upstreams.len(), self.rules.len())
+    }
+
+    // 按声明顺序取第一条命中的规则
+    fn select(&self, host: &str) -> Option<&Upstream> {
+        let rule = self.rules.iter().find(|r| r.matches(host))?;
+        self.upstreams.iter().find(|u| u.name == rule.upstream)
+    }
+}
+
+static TABLE: OnceLock<UpstreamTable> = OnceLock::new();
+
+pub(crate) fn install(table: UpstreamTable) {
+    let _ = TABLE.set(table);
+}
+
+// 为 host:port 建立出站连接：命中 --upstream-rule 时经该上游，否则交给 `direct`。
+// 请求中的黑名单为本会话不可访问的目标范围；经上游的域名默认由远端解析，只检查 IP 字面量
+// （local-dns 时在本机解析后检查）。重试（`attempt`）只对直连有意义
+pub struct UpstreamDialer {
+    direct: Box<dyn Dialer>,
+}
+
+impl UpstreamDialer {
+    pub fn new(direct: impl Dialer + 'static) -> Self {
+        Self { direct: Box::new(direct) }
+    }
+}
+
+impl Dialer for UpstreamDialer {
+    fn dial<'a>(&'a self, req: DialRequest<'a>) -> DialFuture<'a> {
+        Box::pin(async move {
+            match TABLE.get().and_then(|t| t.select(req.host)) {
+                Some(up) => dial_via(up, req).await,
+                None => self.direct.dial(req).await,
+            }
+        })
+    }
+}
+
+tokio::task_local! {
+    // 路由规则的 local-dns / remote-dns
+    static RESOLUTION: Resolution;
+}
+
+pub(crate) async fn with_resolution<F: std::future::Future>(resolution: Option<Resolution>, fut: F) -> F::Output {
+    match resolution {
+        Some(r) => RESOLUTION.scope(r, fut).await,
+        None => fut.await,
+    }
+}
+
+// local-dns 时在本机解析域名，按禁止范围过滤后从第 attempt 个地址起取一个交给上游
+async fn resolve_locally(req: &DialRequest<'_>) -> Result<Option<String>> {
+    let local = RESOLUTION.try_with(|r| *r == Resolution::Local).unwrap_or(false);
+    if !local || req.host.trim_start_matches('[').trim_end_matches(']').parse::<std::net::IpAddr>().is_ok() { return Ok(None); }
+    let addrs = crate::hosts::resolve(req.host, req.port).await.map_err(|e| ProxyError::Dns { host: req.host.to_string(), reason: e.to_string() })?;
+    let mut denied = None;
+    let allowed: Vec<std::net::IpAddr> = addrs
+        .iter()
+        .map(|a| a.ip())
+        .filter(|&ip| {
+            let deny = req.denies(ip) && crate::acl::hit(AclRule::DestDeny, ip);
+            if deny { denied = Some(ip); }
+            !deny
+        })
+        .collect();
+    match (allowed.is_empty(), denied) {
+        (true, Some(ip)) => Err(ProxyError::PolicyDenied(Denied::Dest(ip)).into()),
+        (true, None) => Err(ProxyError::Dns { host: req.host.to_string(), reason: String::from("no address") }.into()),
+        (false, _) => Ok(Some(allowed[req.attempt % allowed.len()].to_string())),
+    }
+}
+
+async fn dial_via(up: &Upstream, req: DialRequest<'_>) -> Result<OutboundStream> {
+    let resolved = resolve_locally(&req).await?;
+    let (host, port, iface) = (resolved.as_deref().unwrap_or(req.host), req.port, req.iface);
+    crate::session::set_target(req.host, port, up.iface.as_deref().unwrap_or(iface));
+    if let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse::<std::net::IpAddr>() {
+        if req.denies(ip) && crate::acl::hit(AclRule::DestDeny, ip) { return Err(ProxyError::PolicyDenied(Denied::Dest(ip)).into()); }
+    }
+    let shown = match &resolved {
+        Some(ip) => format!("{} ({} resolved locally)", crate::addr::redact(req.host, port), crate::addr::redact_host(ip)),
+        None => crate::addr::redact(host, port),
+    };
+    log_throttled(|| log_info(format!("route {} via upstream {} ({}:{}, iface: {})", shown, up.name, up.host, up.port, up.iface.as_deref().unwrap_or(iface))));
+    up.connect(host, port, iface).await
+}
+
+// 经指定名称的上游连接（路由脚本选定上游时用），不看 --upstream-rule
+pub(crate) async fn dial_named(name: &str, req: DialRequest<'_>) -> Result<OutboundStream> {
+    let up = TABLE.get().and_then(|t| t.upstreams.iter().find(|u| u.name == name)).ok_or_else(|| anyhow::anyhow!("unknown upstream {:?}", name))?;
+    dial_via(up, req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn local_dns_resolves_before_the_upstream() {
+        use crate::router::Resolve;
+        let deny = [crate::util::Cidr::parse("10.0.0.0/8").unwrap()];
+        let host = "db.upstream-test.example";
+        // 本机解析的结果由规则的 resolve: 给出，不依赖系统解析
+        let local = |ip: &str, req| {
+            let pinned = Some(Resolve::Addr(ip.parse().unwrap()));
+            with_resolution(Some(Resolution::Local), crate::hosts::with_override(host, pinned, "lo", async move { resolve_locally(&req).await }))
+        };
+        assert_eq!(local("192.0.2.7", DialRequest::new(host, 443, "lo", &deny)).await.unwrap().as_deref(), Some("192.0.2.7"));
+        assert!(local("10.0.0.7", DialRequest::new(host, 443, "lo", &deny)).await.is_err());
+        assert_eq!(local("192.0.2.7", DialRequest::new("192.0.2.9", 443, "lo", &deny)).await.unwrap(), None);
+        let req = DialRequest::new(host, 443, "lo", &deny);
+        assert_eq!(resolve_locally(&req).await.unwrap(), None);
+        assert_eq!(with_resolution(Some(Resolution::Remote), resolve_locally(&req)).await.unwrap(), None);
+    }
+
+    #[test]
+    fn wss_sni_option() {
+        let sni = |url: &str| match Upstream::parse(url).unwrap().kind {
+            UpstreamKind::WebSocket(cfg) => cfg.sni.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(sni("cdn=wss://203.0.113.10:443/tunnel?sni=Edge.Example.com"), Some(Sni::Host(String::from("edge.example.com"))));
+        assert_eq!(sni("cdn=wss://proxy.example.com:443?sni=none"), Some(Sni::Omit));
+        assert_eq!(sni("cdn=wss://proxy.example.com:443"), None);
+        assert!(Upstream::parse("cdn=ws://203.0.113.10:80?sni=edge.example.com").is_err());
+        assert!(Upstream::parse("cdn=wss://203.0.113.10:443?sni=").is_err());
+    }
+}
@@ -1,11 +1,14 @@
+#[cfg(target_os = "macos")]
 use std::ffi::CString;
 use std::os::fd::AsRawFd;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::io;
 
 use anyhow::Result;
-use tokio::net::{lookup_host, TcpSocket, TcpStream};
+use crate::error::{Denied, ProxyError};
+use tokio::net::{TcpSocket, TcpStream};
 
 #[cfg(target_os = "macos")]
 use nix::libc::{if_nametoindex, IPPROTO_IP, IP_BOUND_IF, IPPROTO_IPV6, IPV6_BOUND_IF};
@@ -56,6 +59,613 @@ pub(crate) fn bind_iface_v6(fd: i32, iface: &str) -> Result<()> {
     Ok(())
 }
 
+// Linux: SO_BINDTODEVICE, which needs root or CAP_NET_RAW
+#[cfg(target_os = "linux")]
+fn bind_to_device(fd: i32, iface: &str) -> Result<()> {
+    let ret = unsafe {
+        nix::libc::setsockopt(
+            fd,
+            nix::libc::SOL_SOCKET,
+            nix::libc::SO_BINDTODEVICE,
+            iface.as_ptr() as *const nix::libc::c_void,
+            iface.len() as nix::libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        anyhow::bail!("setsockopt(SO_BINDTODEVICE, {}) failed: {}", iface, io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn bind_iface_v4(fd: i32, iface: &str) -> Result<()> {
+    bind_to_device(fd, iface)
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn bind_iface_v6(fd: i32, iface: &str) -> Result<()> {
+    bind_to_device(fd, iface)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+pub(crate) fn bind_iface_v4(_fd: i32, _iface: &str) -> Result<()> {
+    anyhow::bail!("binding to an interface is not supported on this platform")
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+pub(crate) fn bind_iface_v6(_fd: i32, _iface: &str) -> Result<()> {
+    anyhow::bail!("binding to an interface is not supported on this platform")
+}
+
+// --vrf：出站 socket 用 SO_BINDTODEVICE 绑到 VRF 设备（按 VRF 的路由表选路），
+// 再 bind 到出口网卡的地址选定物理网卡；SO_BINDTODEVICE 同一时间只能绑一个设备，两者不能都用它
+static VRF: OnceLock<String> = OnceLock::new();
+
+pub(crate) fn set_vrf(vrf: Option<String>) {
+    if let Some(vrf) = vrf { let _ = VRF.set(vrf); }
+}
+
+// 启动时检查：VRF 设备存在，出口网卡已加入该 VRF（未加入只告警，路由可能仍然可达）
+#[cfg(target_os = "linux")]
+pub(crate) fn check_vrf(vrf: &str, iface: &str) -> Result<String> {
+    if !std::path::Path::new(&format!("/sys/class/net/{}/flags", vrf)).exists() {
+        anyhow::bail!("VRF device {:?} not found in /sys/class/net", vrf);
+    }
+    if iface == vrf { return Ok(format!("{} (no source binding)", vrf)); }
+    let master = std::fs::read_link(format!("/sys/class/net/{}/master", iface))
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()));
+    match master {
+        Some(m) if m == vrf => Ok(format!("{} (enslaves {})", vrf, iface)),
+        Some(m) => anyhow::bail!("iface {} belongs to {}, not VRF {}", iface, m, vrf),
+        None => anyhow::bail!("iface {} is not enslaved to VRF {} (ip link set {} master {})", iface, vrf, iface, vrf),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn check_vrf(_vrf: &str, _iface: &str) -> Result<String> {
+    anyhow::bail!("--vrf is only supported on Linux")
+}
+
+// 交给嵌入方准备回调的出站 socket 信息
+pub struct SocketInfo<'a> {
+    // 尚未 connect 的 socket
+    pub fd: std::os::fd::RawFd,
+    // 本次连接选用的出口网卡（--iface、上游的 iface= 或按请求指定的网卡）
+    pub iface: &'a str,
+    pub target: std::net::SocketAddr,
+    pub udp: bool,
+}
+
+type PrepareSocket = Box<dyn Fn(&SocketInfo<'_>) -> io::Result<()> + Send + Sync>;
+
+static PREPARE_SOCKET: OnceLock<PrepareSocket> = OnceLock::new();
+
+// 嵌入方提供的出站 socket 准备回调，在 connect 前以原始 fd 调用，取代默认的网卡/VRF 绑定，
+// 如 Android 上调用 VpnService.protect(fd) 避免流量回到自己的 VPN；返回错误时跳过该地址。
+// 须在 run_cli 之前设置，只能设置一次
+pub fn set_prepare_socket(f: impl Fn(&SocketInfo<'_>) -> io::Result<()> + Send + Sync + 'static) -> Result<()> {
+    PREPARE_SOCKET.set(Box::new(f)).map_err(|_| anyhow::anyhow!("prepare-socket callback is already set"))
+}
+
+// 绑定出口：默认同 bind_iface_v4/v6；设置 VRF 时绑 VRF 设备；嵌入方设置了回调时交给回调
+fn bind_outbound(fd: i32, iface: &str, target: std::net::SocketAddr, udp: bool) -> Result<()> {
+    if let Some(prepare) = PREPARE_SOCKET.get() {
+        return prepare(&SocketInfo { fd, iface, target, udp })
+            .map_err(|e| anyhow::anyhow!("prepare-socket callback failed for {}: {}", target, e));
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(vrf) = VRF.get() {
+        return bind_to_device(fd, vrf);
+    }
+    if target.is_ipv6() { bind_iface_v6(fd, iface) } else { bind_iface_v4(fd, iface) }
+}
+
+// VRF 模式下的源地址：出口网卡上同地址族的第一个非链路本地地址；网卡即 VRF 设备或没有该族地址时
+// 不绑定源地址，由 VRF 路由表选择出口
+fn vrf_source(iface: &str, v6: bool) -> Option<std::net::SocketAddr> {
+    let vrf = VRF.get()?;
+    if iface == vrf { return None; }
+    let info = list_interfaces().ok()?.into_iter().find(|i| i.name == iface)?;
+    let ip = info.addrs.into_iter().find(|a| match a {
+        std::net::IpAddr::V4(v4) => !v6 && !v4.is_link_local(),
+        std::net::IpAddr::V6(a6) => v6 && (a6.segments()[0] & 0xffc0) != 0xfe80,
+    })?;
+    Some((ip, 0).into())
+}
+
+// --fwmark：出站 socket 的 SO_MARK，配合 `ip rule add fwmark ...` 做策略路由（如 VRF 下单靠 SO_BINDTODEVICE 选不对路由表）
+static FWMARK: OnceLock<u32> = OnceLock::new();
+
+pub(crate) fn set_fwmark(mark: Option<u32>) {
+    if let Some(mark) = mark { let _ = FWMARK.set(mark); }
+}
+
+// 十进制或 0x 开头的十六进制，如 100、0x1f
+pub(crate) fn parse_fwmark(s: &str) -> Result<u32> {
+    let s = s.trim();
+    let v = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse::<u32>(),
+    };
+    v.map_err(|_| anyhow::anyhow!("invalid fwmark {:?} (expected a decimal or 0x-prefixed hex u32)", s))
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn set_so_mark(fd: i32, mark: u32) -> Result<()> {
+    let ret = unsafe {
+        nix::libc::setsockopt(
+            fd,
+            nix::libc::SOL_SOCKET,
+            nix::libc::SO_MARK,
+            &mark as *const _ as *const nix::libc::c_void,
+            std::mem::size_of::<u32>() as u32,
+        )
+    };
+    if ret != 0 {
+        anyhow::bail!("setsockopt(SO_MARK={:#x}) failed (needs root or CAP_NET_ADMIN): {}", mark, io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_so_mark(_fd: i32, _mark: u32) -> Result<()> {
+    anyhow::bail!("--fwmark is only supported on Linux")
+}
+
+// --dscp：出站 socket 的 DSCP 标记（IPv4 的 IP_TOS、IPv6 的 IPV6_TCLASS 高 6 位），供上游 QoS 设备分类
+static DSCP: OnceLock<u8> = OnceLock::new();
+
+// 0-63 或 RFC 4594 的名称：CS0-CS7、AF11-AF43、EF、VOICE-ADMIT
+pub(crate) fn parse_dscp(s: &str) -> Result<u8> {
+    let name = s.trim().to_ascii_uppercase();
+    let v = match name.as_str() {
+        "EF" => Some(46),
+        "VOICE-ADMIT" | "VA" => Some(44),
+        n => match (n.strip_prefix("CS"), n.strip_prefix("AF")) {
+            (Some(c), _) => c.parse::<u8>().ok().filter(|c| *c <= 7).map(|c| c << 3),
+            (_, Some(af)) => match af.as_bytes() {
+                [c @ b'1'..=b'4', d @ b'1'..=b'3'] => Some(((c - b'0') << 3) | ((d - b'0') << 1)),
+                _ => None,
+            },
+            _ => n.parse::<u8>().ok().filter(|v| *v <= 63),
+        },
+    };
+    v.ok_or_else(|| anyhow::anyhow!("invalid DSCP {:?} (expected 0-63, CS0-CS7, AF11-AF43 or EF)", s))
+}
+
+// --sockopt [LEVEL:]NAME=VALUE：出站 socket 上额外的整数 setsockopt，LEVEL 与 NAME 可写常量名或数字，
+// 常用的常量名可省略 LEVEL；IPPROTO_IP/IPPROTO_IPV6 级只用于对应地址族，IPPROTO_TCP/IPPROTO_UDP 级只用于对应协议
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RawSockOpt {
+    pub(crate) level: nix::libc::c_int,
+    pub(crate) name: nix::libc::c_int,
+    pub(crate) value: nix::libc::c_int,
+    label: String,
+}
+
+fn sockopt_names() -> Vec<(&'static str, nix::libc::c_int, nix::libc::c_int)> {
+    use nix::libc::*;
+    let mut names = vec![
+        ("SO_SNDBUF", SOL_SOCKET, SO_SNDBUF),
+        ("SO_RCVBUF", SOL_SOCKET, SO_RCVBUF),
+        ("SO_KEEPALIVE", SOL_SOCKET, SO_KEEPALIVE),
+        ("IP_TOS", IPPROTO_IP, IP_TOS),
+        ("IP_TTL", IPPROTO_IP, IP_TTL),
+        ("IPV6_TCLASS", IPPROTO_IPV6, IPV6_TCLASS),
+        ("IPV6_UNICAST_HOPS", IPPROTO_IPV6, IPV6_UNICAST_HOPS),
+        ("TCP_NODELAY", IPPROTO_TCP, TCP_NODELAY),
+        ("TCP_MAXSEG", IPPROTO_TCP, TCP_MAXSEG),
+        ("TCP_NOTSENT_LOWAT", IPPROTO_TCP, TCP_NOTSENT_LOWAT),
+    ];
+    #[cfg(target_os = "linux")]
+    names.extend([("SO_PRIORITY", SOL_SOCKET, SO_PRIORITY), ("TCP_USER_TIMEOUT", IPPROTO_TCP, TCP_USER_TIMEOUT), ("TCP_WINDOW_CLAMP", IPPROTO_TCP, TCP_WINDOW_CLAMP)]);
+    names
+}
+
+impl RawSockOpt {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        use nix::libc::{IPPROTO_IP, IPPROTO_IPV6, IPPROTO_TCP, IPPROTO_UDP, SOL_SOCKET};
+        let invalid = || anyhow::anyhow!("invalid socket option {:?} (expected [LEVEL:]NAME=VALUE, e.g. SO_PRIORITY=6 or 6:24=1)", s);
+        let (key, value) = s.split_once('=').ok_or_else(invalid)?;
+        let value: nix::libc::c_int = value.trim().parse().map_err(|_| invalid())?;
+        let (level, name) = match key.split_once(':') {
+            Some((l, n)) => (Some(l.trim()), n.trim()),
+            None => (None, key.trim()),
+        };
+        let level = match level.map(|l| l.to_ascii_uppercase()) {
+            None => None,
+            Some(l) => Some(match l.as_str() {
+                "SOL_SOCKET" | "SOCKET" => SOL_SOCKET,
+                "IPPROTO_IP" | "IP" => IPPROTO_IP,
+                "IPPROTO_IPV6" | "IPV6" => IPPROTO_IPV6,
+                "IPPROTO_TCP" | "TCP" => IPPROTO_TCP,
+                "IPPROTO_UDP" | "UDP" => IPPROTO_UDP,
+                n => n.parse().map_err(|_| anyhow::anyhow!("unknown socket option level {:?} in {:?}", n, s))?,
+            }),
+        };
+        let known = sockopt_names().into_iter().find(|(n, ..)| n.eq_ignore_ascii_case(name));
+        let (level, name) = match (level, known, name.parse::<nix::libc::c_int>()) {
+            (Some(level), _, Ok(name)) => (level, name),
+            (Some(level), Some((_, l, n)), _) if level == l => (l, n),
+            (Some(_), Some(_), _) => anyhow::bail!("socket option {} does not belong to the given level in {:?}", name, s),
+            (None, Some((_, l, n)), _) => (l, n),
+            (None, None, Ok(_)) => anyhow::bail!("numeric socket option {:?} needs a LEVEL (LEVEL:NAME=VALUE)", s),
+            (_, None, Err(_)) => anyhow::bail!("unknown socket option {:?} (use LEVEL:NUMBER=VALUE for options without a built-in name)", name),
+        };
+        Ok(Self { level, name, value, label: key.trim().to_string() })
+    }
+
+    fn applies_to(&self, v6: bool, tcp: bool) -> bool {
+        match self.level {
+            nix::libc::IPPROTO_IP => !v6,
+            nix::libc::IPPROTO_IPV6 => v6,
+            nix::libc::IPPROTO_TCP => tcp,
+            nix::libc::IPPROTO_UDP => !tcp,
+            _ => true,
+        }
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        format!("{}={}", self.label, self.value)
+    }
+}
+
+static SOCKOPTS: OnceLock<Vec<RawSockOpt>> = OnceLock::new();
+
+pub(crate) fn set_outbound_marks(dscp: Option<u8>, opts: Vec<RawSockOpt>) {
+    if let Some(d) = dscp { let _ = DSCP.set(d); }
+    let _ = SOCKOPTS.set(opts);
+}
+
+// DSCP 与 --sockopt 按顺序设置，--sockopt 中的 IP_TOS/IPV6_TCLASS 会覆盖 --dscp
+pub(crate) fn set_socket_marks(fd: i32, v6: bool, tcp: bool, dscp: Option<u8>, opts: &[RawSockOpt]) -> Result<()> {
+    if let Some(d) = dscp {
+        let tos = (d as nix::libc::c_int) << 2;
+        if v6 {
+            setsockopt_int(fd, nix::libc::IPPROTO_IPV6, nix::libc::IPV6_TCLASS, tos, "IPV6_TCLASS")?;
+        } else {
+            setsockopt_int(fd, nix::libc::IPPROTO_IP, nix::libc::IP_TOS, tos, "IP_TOS")?;
+        }
+    }
+    for o in opts.iter().filter(|o| o.applies_to(v6, tcp)) {
+        setsockopt_int(fd, o.level, o.name, o.value, &o.label)?;
+    }
+    Ok(())
+}
+
+// 与绑定网卡一样，设置失败时不发出连接，避免流量绕开策略路由或 QoS 分类
+fn apply_marks(fd: i32, v6: bool, tcp: bool) -> Result<()> {
+    if let Some(&mark) = FWMARK.get() { set_so_mark(fd, mark)?; }
+    set_socket_marks(fd, v6, tcp, DSCP.get().copied(), SOCKOPTS.get().map(Vec::as_slice).unwrap_or_default())
+}
+
+// [IFACE=]MSS：出站 TCP 连接的 MSS 上限，SYN 中即按此通告，用于 PPPoE/VPN 等路径 MTU 偏小、
+// 大包被静默丢弃（CONNECT 隧道握手后卡住）的网卡；不带网卡名的值用于其余网卡
+#[derive(Clone, Debug)]
+pub(crate) struct TcpMss {
+    pub(crate) iface: Option<String>,
+    pub(crate) mss: u32,
+}
+
+impl TcpMss {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (iface, mss) = match s.split_once('=') {
+            Some((i, m)) if !i.trim().is_empty() => (Some(i.trim().to_string()), m),
+            Some(_) => anyhow::bail!("tcp-mss {:?} has an empty interface name", s),
+            None => (None, s),
+        };
+        // Linux 接受的范围：TCP_MIN_MSS..=MAX_TCP_WINDOW
+        let mss = mss.trim().parse::<u32>().ok().filter(|m| (88..=32767).contains(m)).ok_or_else(|| anyhow::anyhow!("invalid MSS in {:?} (expected 88-32767)", s))?;
+        Ok(Self { iface, mss })
+    }
+}
+
+static TCP_MSS: OnceLock<Vec<TcpMss>> = OnceLock::new();
+
+pub(crate) fn set_tcp_mss(rules: Vec<TcpMss>) {
+    let _ = TCP_MSS.set(rules);
+}
+
+fn tcp_mss_for(iface: &str) -> Option<u32> {
+    let rules = TCP_MSS.get()?;
+    rules.iter().find(|r| r.iface.as_deref() == Some(iface)).or_else(|| rules.iter().find(|r| r.iface.is_none())).map(|r| r.mss)
+}
+
+// [IFACE=]START-END：出站连接（TCP 与 UDP 转发）的本地端口限定在该范围内，connect 前按顺序 bind，
+// 用于上游防火墙只放行特定源端口的网卡；不带网卡名的范围用于其余网卡。范围大小即该网卡同时可用的出站连接数
+#[derive(Clone, Debug)]
+pub(crate) struct PortRange {
+    pub(crate) iface: Option<String>,
+    pub(crate) start: u16,
+    pub(crate) end: u16,
+}
+
+impl PortRange {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (iface, range) = match s.split_once('=') {
+            Some((i, r)) if !i.trim().is_empty() => (Some(i.trim().to_string()), r),
+            Some(_) => anyhow::bail!("source-ports {:?} has an empty interface name", s),
+            None => (None, s),
+        };
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let port = |p: &str| p.trim().parse::<u16>().ok().filter(|p| *p > 0);
+        match (port(start), port(end)) {
+            (Some(start), Some(end)) if start <= end => Ok(Self { iface, start, end }),
+            _ => anyhow::bail!("invalid port range in {:?} (expected START-END within 1-65535)", s),
+        }
+    }
+}
+
+static SOURCE_PORTS: OnceLock<Vec<PortRange>> = OnceLock::new();
+// 下次从范围内第几个端口开始尝试，使相继的连接分散到不同端口
+static PORT_CURSOR: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn set_source_ports(ranges: Vec<PortRange>) {
+    let _ = SOURCE_PORTS.set(ranges);
+}
+
+fn source_ports_for(iface: &str) -> Option<(u16, u16)> {
+    let ranges = SOURCE_PORTS.get()?;
+    ranges.iter().find(|r| r.iface.as_deref() == Some(iface)).or_else(|| ranges.iter().find(|r| r.iface.is_none())).map(|r| (r.start, r.end))
+}
+
+// 依次尝试范围内的端口直到 `bind` 成功，返回绑定的地址
+fn bind_in_range<T>(ip: std::net::IpAddr, (start, end): (u16, u16), mut bind: impl FnMut(std::net::SocketAddr) -> io::Result<T>) -> Result<T> {
+    let n = (end - start) as u64 + 1;
+    let first = PORT_CURSOR.fetch_add(1, Ordering::Relaxed) % n;
+    for i in 0..n {
+        let port = start + ((first + i) % n) as u16;
+        match bind((ip, port).into()) {
+            Ok(v) => return Ok(v),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+            Err(e) => anyhow::bail!("bind to {}: {}", std::net::SocketAddr::from((ip, port)), e),
+        }
+    }
+    anyhow::bail!("no free source port in {}-{} on {}", start, end, ip)
+}
+
+// connect 前绑定源地址：VRF 模式下为出口网卡的地址，设置了 --source-ports 时再限定端口
+fn bind_source(socket: &TcpSocket, iface: &str, v6: bool) -> Result<()> {
+    let src = vrf_source(iface, v6);
+    let Some(range) = source_ports_for(iface) else {
+        return match src {
+            Some(src) => socket.bind(src).map_err(|e| anyhow::anyhow!("bind to {}: {}", src, e)),
+            None => Ok(()),
+        };
+    };
+    let ip = src.map(|a| a.ip()).unwrap_or(if v6 { std::net::Ipv6Addr::UNSPECIFIED.into() } else { std::net::Ipv4Addr::UNSPECIFIED.into() });
+    bind_in_range(ip, range, |a| socket.bind(a))
+}
+
+fn set_tcp_maxseg(fd: i32, mss: u32) -> Result<()> {
+    let val = mss as nix::libc::c_int;
+    let ret = unsafe {
+        nix::libc::setsockopt(
+            fd,
+            nix::libc::IPPROTO_TCP,
+            nix::libc::TCP_MAXSEG,
+            &val as *const _ as *const nix::libc::c_void,
+            std::mem::size_of::<nix::libc::c_int>() as u32,
+        )
+    };
+    if ret != 0 {
+        anyhow::bail!("setsockopt(TCP_MAXSEG={}) failed: {}", mss, io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// 连接前设置，失败只记录，不影响连接本身
+fn apply_tcp_mss(fd: i32, iface: &str) {
+    if let Some(mss) = tcp_mss_for(iface) {
+        if let Err(e) = set_tcp_maxseg(fd, mss) {
+            log_throttled(|| log_error(format!("{} (iface: {})", e, iface)));
+        }
+    }
+}
+
+// 出站 TCP 保活，时间单位为秒；经 NAT 的长连接隧道空闲时靠它维持映射并及时发现对端已失联
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Keepalive {
+    Off,
+    On { idle: u32, interval: u32, count: u32 },
+}
+
+impl Keepalive {
+    // `off` 或 IDLE[,INTERVAL[,COUNT]]，如 `60,10,6`；INTERVAL 默认 15，COUNT 默认 4
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        if s.trim().eq_ignore_ascii_case("off") { return Ok(Self::Off); }
+        let parts: Vec<u32> = s
+            .split(',')
+            .map(|p| p.trim().parse::<u32>().ok().filter(|v| (1..=32767).contains(v)))
+            .collect::<Option<_>>()
+            .ok_or_else(|| anyhow::anyhow!("invalid keepalive {:?} (expected off or IDLE[,INTERVAL[,COUNT]] in seconds)", s))?;
+        match parts[..] {
+            [idle] => Ok(Self::On { idle, interval: 15, count: 4 }),
+            [idle, interval] => Ok(Self::On { idle, interval, count: 4 }),
+            [idle, interval, count] if count <= 127 => Ok(Self::On { idle, interval, count }),
+            _ => anyhow::bail!("invalid keepalive {:?} (expected off or IDLE[,INTERVAL[,COUNT]] in seconds)", s),
+        }
+    }
+}
+
+impl std::fmt::Display for Keepalive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Off => write!(f, "off"),
+            Self::On { idle, interval, count } => write!(f, "{},{},{}", idle, interval, count),
+        }
+    }
+}
+
+// 出站 TCP 的保活与 Fast Open；可全局设置、在单个监听上覆盖（`?keepalive=...&tfo=on`），
+// 或经 --tcp-rule 按目标主机覆盖，优先级依次升高。None 表示沿用上一级
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SockOpts {
+    pub(crate) keepalive: Option<Keepalive>,
+    pub(crate) fast_open: Option<bool>,
+}
+
+impl SockOpts {
+    pub(crate) fn or(self, base: SockOpts) -> SockOpts {
+        SockOpts { keepalive: self.keepalive.or(base.keepalive), fast_open: self.fast_open.or(base.fast_open) }
+    }
+
+    // 处理 `keepalive=` 与 `tfo=`，不认识的键返回 false
+    pub(crate) fn apply_option(&mut self, key: &str, value: &str) -> Result<bool> {
+        match key {
+            "keepalive" => self.keepalive = Some(Keepalive::parse(value)?),
+            "tfo" => {
+                self.fast_open = Some(match value.trim().to_ascii_lowercase().as_str() {
+                    "on" | "1" | "true" => true,
+                    "off" | "0" | "false" => false,
+                    _ => anyhow::bail!("invalid tfo {:?} (expected on or off)", value),
+                })
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // 启动摘要用，如 ["keepalive=60,15,4", "tfo=on"]
+    pub(crate) fn describe(&self) -> Vec<String> {
+        let mut out = Vec::new();
+        if let Some(k) = self.keepalive { out.push(format!("keepalive={}", k)); }
+        if let Some(t) = self.fast_open { out.push(format!("tfo={}", if t { "on" } else { "off" })); }
+        out
+    }
+}
+
+// 按域名后缀匹配主机，各类 SUFFIX= 规则共用：`*` 匹配全部，否则主机须等于后缀或以 `.后缀` 结尾；
+// 不区分大小写，忽略主机末尾的点。`suffix` 应已去掉前导点
+pub(crate) fn host_has_suffix(host: &str, suffix: &str) -> bool {
+    if suffix == "*" { return true; }
+    let host = host.trim_end_matches('.');
+    let Some(start) = host.len().checked_sub(suffix.len()) else { return false };
+    host.is_char_boundary(start) && host[start..].eq_ignore_ascii_case(suffix) && (start == 0 || host.as_bytes()[start - 1] == b'.')
+}
+
+// SUFFIX=OPTS，OPTS 同监听选项，如 `ssh.example.com=keepalive=30,10,3`、`example.com=tfo=on&keepalive=off`；
+// SUFFIX 匹配目标主机及其子域名，`*` 匹配全部，按声明顺序取第一条
+#[derive(Clone, Debug)]
+pub(crate) struct TcpRule {
+    pub(crate) suffix: String,
+    pub(crate) opts: SockOpts,
+}
+
+impl TcpRule {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (suffix, opts) = s.split_once('=').ok_or_else(|| anyhow::anyhow!("invalid tcp rule {:?} (expected SUFFIX=keepalive=...&tfo=...)", s))?;
+        let suffix = suffix.trim().trim_start_matches('.').to_ascii_lowercase();
+        if suffix.is_empty() { anyhow::bail!("tcp rule {:?} is missing a host suffix", s); }
+        let mut rule = Self { suffix, opts: SockOpts::default() };
+        for pair in opts.split('&').filter(|p| !p.is_empty()) {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            if !rule.opts.apply_option(k.trim(), v)? { anyhow::bail!("unknown tcp rule option {:?} in {:?}", pair, s); }
+        }
+        Ok(rule)
+    }
+
+    fn matches_host(&self, host: &str) -> bool {
+        host_has_suffix(host, &self.suffix)
+    }
+}
+
+struct TcpOptsConfig {
+    global: SockOpts,
+    rules: Vec<TcpRule>,
+}
+
+static TCP_OPTS: OnceLock<TcpOptsConfig> = OnceLock::new();
+
+pub(crate) fn set_tcp_opts(global: SockOpts, rules: Vec<TcpRule>) {
+    let _ = TCP_OPTS.set(TcpOptsConfig { global, rules });
+}
+
+fn sockopts_for(host: &str) -> SockOpts {
+    let session = crate::session::sockopts();
+    let Some(cfg) = TCP_OPTS.get() else { return session };
+    let rule = cfg.rules.iter().find(|r| r.matches_host(host)).map(|r| r.opts).unwrap_or_default();
+    rule.or(session).or(cfg.global)
+}
+
+fn setsockopt_int(fd: i32, level: nix::libc::c_int, name: nix::libc::c_int, value: nix::libc::c_int, label: &str) -> Result<()> {
+    let ret = unsafe {
+        nix::libc::setsockopt(
+            fd,
+            level,
+            name,
+            &value as *const _ as *const nix::libc::c_void,
+            std::mem::size_of::<nix::libc::c_int>() as u32,
+        )
+    };
+    if ret != 0 {
+        anyhow::bail!("setsockopt({}={}) failed: {}", label, value, io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+const TCP_KEEPIDLE: (nix::libc::c_int, &str) = (nix::libc::TCP_KEEPIDLE, "TCP_KEEPIDLE");
+#[cfg(target_os = "macos")]
+const TCP_KEEPIDLE: (nix::libc::c_int, &str) = (nix::libc::TCP_KEEPALIVE, "TCP_KEEPALIVE");
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn set_keepalive(fd: i32, idle: u32, interval: u32, count: u32) -> Result<()> {
+    use nix::libc::{IPPROTO_TCP, SOL_SOCKET, SO_KEEPALIVE, TCP_KEEPCNT, TCP_KEEPINTVL};
+    setsockopt_int(fd, SOL_SOCKET, SO_KEEPALIVE, 1, "SO_KEEPALIVE")?;
+    setsockopt_int(fd, IPPROTO_TCP, TCP_KEEPIDLE.0, idle as i32, TCP_KEEPIDLE.1)?;
+    setsockopt_int(fd, IPPROTO_TCP, TCP_KEEPINTVL, interval as i32, "TCP_KEEPINTVL")?;
+    setsockopt_int(fd, IPPROTO_TCP, TCP_KEEPCNT, count as i32, "TCP_KEEPCNT")
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn set_keepalive(fd: i32, _idle: u32, _interval: u32, _count: u32) -> Result<()> {
+    setsockopt_int(fd, nix::libc::SOL_SOCKET, nix::libc::SO_KEEPALIVE, 1, "SO_KEEPALIVE")
+}
+
+// Linux 的 TCP_FASTOPEN_CONNECT：connect 立即返回，首次写入的数据随 SYN 发出（有 cookie 时）
+#[cfg(target_os = "linux")]
+fn set_fast_open(fd: i32) -> Result<()> {
+    setsockopt_int(fd, nix::libc::IPPROTO_TCP, nix::libc::TCP_FASTOPEN_CONNECT, 1, "TCP_FASTOPEN_CONNECT")
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_fast_open(_fd: i32) -> Result<()> {
+    anyhow::bail!("TCP Fast Open for outbound connections is only supported on Linux")
+}
+
+// 连接前设置，失败只记录，不影响连接本身
+fn apply_sockopts(fd: i32, host: &str) {
+    let opts = sockopts_for(host);
+    if let Some(Keepalive::On { idle, interval, count }) = opts.keepalive {
+        if let Err(e) = set_keepalive(fd, idle, interval, count) {
+            log_throttled(|| log_error(format!("{} (target: {})", e, crate::addr::redact_host(host))));
+        }
+    }
+    if opts.fast_open == Some(true) {
+        if let Err(e) = set_fast_open(fd) {
+            log_throttled(|| log_error(format!("{} (target: {})", e, crate::addr::redact_host(host))));
+        }
+    }
+}
+
+// SIGINT / SIGTERM 任一到达即返回
+pub(crate) async fn shutdown_signal() {
+    let mut term = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        Ok(s) => s,
+        Err(e) => {
+            log_error(format!("failed to install SIGTERM handler: {}", e));
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = term.recv() => {}
+    }
+}
+
 // 全局日志限频
 const LOGS_PER_SEC: u64 = 50;
 static LOG_WINDOW_SEC: AtomicU64 = AtomicU64::new(0);
@@ -69,23 +679,19 @@ fn now_sec() -> u64 {
         .as_secs()
 }
 
-pub(crate) fn log_throttled<F: FnOnce()>(f: F)
-where
-    F: FnOnce(),
-{
+pub(crate) fn log_throttled<F: FnOnce()>(f: F) {
     let now = now_sec();
     let window = LOG_WINDOW_SEC.load(Ordering::Relaxed);
-    if now != window {
-        if LOG_WINDOW_SEC
+    if now != window
+        && LOG_WINDOW_SEC
             .compare_exchange(window, now, Ordering::SeqCst, Ordering::SeqCst)
             .is_ok()
-        {
-            let suppressed = LOG_SUPPRESSED.swap(0, Ordering::SeqCst);
-            if suppressed > 0 {
-                log_log(format!("suppressed {} messages in last 1s", suppressed));
-            }
-            LOG_COUNT.store(0, Ordering::SeqCst);
+    {
+        let suppressed = LOG_SUPPRESSED.swap(0, Ordering::SeqCst);
+        if suppressed > 0 {
+            log_log(format!("suppressed {} messages in last 1s", suppressed));
         }
+        LOG_COUNT.store(0, Ordering::SeqCst);
     }
     let c = LOG_COUNT.fetch_add(1, Ordering::SeqCst);
     if c < LOGS_PER_SEC {
@@ -95,6 +701,42 @@ where
     }
 }
 
+// JSON 字符串字面量（含引号）
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// 本地时区的当前日期 (year, month, day)
+pub(crate) fn local_date() -> (i32, u32, u32) {
+    local_date_at(now_sec())
+}
+
+// 本地时区中 Unix 时间 `secs` 所在的日期
+pub(crate) fn local_date_at(secs: u64) -> (i32, u32, u32) {
+    let t: nix::libc::time_t = secs as nix::libc::time_t;
+    let mut tm: nix::libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { let _ = nix::libc::localtime_r(&t, &mut tm); }
+    (tm.tm_year + 1900, (tm.tm_mon + 1) as u32, tm.tm_mday as u32)
+}
+
+// 本地时区的当前时刻：(星期几，周一为 0；一天中的分钟数)
+pub(crate) fn local_clock() -> (u32, u32) {
+    let t: nix::libc::time_t = now_sec() as nix::libc::time_t;
+    let mut tm: nix::libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { let _ = nix::libc::localtime_r(&t, &mut tm); }
+    (((tm.tm_wday + 6) % 7) as u32, (tm.tm_hour * 60 + tm.tm_min) as u32)
+}
+
 pub(crate) fn current_timestamp_prefix() -> String {
     let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
     let secs = now.as_secs() as i64;
@@ -112,48 +754,36 @@ pub(crate) fn current_timestamp_prefix() -> String {
 }
 
 pub(crate) fn log_info(message: impl AsRef<str>) {
+    if crate::logsink::send(crate::logsink::Stream::App, crate::logsink::INFO, &format!("{}{}", crate::session::tag(), message.as_ref())) { return; }
     println!(
-        "{} \x1b[32mINFO\x1b[0m {}",
+        "{} \x1b[32mINFO\x1b[0m {}{}",
         current_timestamp_prefix(),
+        crate::session::tag(),
         message.as_ref()
     );
 }
 
 pub(crate) fn log_log(message: impl AsRef<str>) {
+    if crate::logsink::send(crate::logsink::Stream::App, crate::logsink::NOTICE, &format!("{}{}", crate::session::tag(), message.as_ref())) { return; }
     println!(
-        "{} \x1b[36mLOG\x1b[0m {}",
+        "{} \x1b[36mLOG\x1b[0m {}{}",
         current_timestamp_prefix(),
+        crate::session::tag(),
         message.as_ref()
     );
 }
 
 pub(crate) fn log_error(message: impl AsRef<str>) {
+    crate::session::mark_failed();
+    if crate::logsink::send(crate::logsink::Stream::App, crate::logsink::ERR, &format!("{}{}", crate::session::tag(), message.as_ref())) { return; }
     eprintln!(
-        "{} \x1b[31mERROR\x1b[0m {}",
+        "{} \x1b[31mERROR\x1b[0m {}{}",
         current_timestamp_prefix(),
+        crate::session::tag(),
         message.as_ref()
     );
 }
 
-pub(crate) fn is_transient_anyhow_error(err: &anyhow::Error) -> bool {
-    if let Some(ioe) = err.downcast_ref::<io::Error>() {
-        return matches!(
-            ioe.kind(),
-            io::ErrorKind::BrokenPipe
-                | io::ErrorKind::ConnectionReset
-                | io::ErrorKind::ConnectionAborted
-                | io::ErrorKind::TimedOut
-                | io::ErrorKind::UnexpectedEof
-        );
-    }
-    let s = err.to_string().to_lowercase();
-    s.contains("broken pipe")
-        || s.contains("connection reset")
-        || s.contains("timed out")
-        || s.contains("connection aborted")
-        || s.contains("unexpected eof")
-}
-
 #[cfg(target_os = "macos")]
 pub(crate) fn try_raise_nofile_limit(min_soft: u64) {
     unsafe {
@@ -190,22 +820,133 @@ pub(crate) fn try_raise_nofile_limit(_min_soft: u64) {
     // No-op on unsupported targets
 }
 
-pub(crate) async fn connect_outbound(host: &str, port: u16, iface: &str) -> Result<TcpStream> {
-    let addrs = lookup_host((host, port)).await?;
+pub(crate) struct IfaceInfo {
+    pub(crate) name: String,
+    pub(crate) addrs: Vec<std::net::IpAddr>,
+    pub(crate) is_up: bool,
+    pub(crate) is_loopback: bool,
+}
+
+pub(crate) fn list_interfaces() -> Result<Vec<IfaceInfo>> {
+    use nix::net::if_::InterfaceFlags;
+    let mut out: Vec<IfaceInfo> = Vec::new();
+    for ifa in nix::ifaddrs::getifaddrs()? {
+        let pos = match out.iter().position(|i| i.name == ifa.interface_name) {
+            Some(p) => p,
+            None => {
+                out.push(IfaceInfo {
+                    name: ifa.interface_name.clone(),
+                    addrs: Vec::new(),
+                    is_up: ifa.flags.contains(InterfaceFlags::IFF_UP),
+                    is_loopback: ifa.flags.contains(InterfaceFlags::IFF_LOOPBACK),
+                });
+                out.len() - 1
+            }
+        };
+        if let Some(addr) = ifa.address.as_ref() {
+            if let Some(v4) = addr.as_sockaddr_in() {
+                out[pos].addrs.push(std::net::IpAddr::V4(v4.ip()));
+            } else if let Some(v6) = addr.as_sockaddr_in6() {
+                out[pos].addrs.push(std::net::IpAddr::V6(v6.ip()));
+            }
+        }
+    }
+    Ok(out)
+}
+
+// e.g. "en0 up [192.168.1.2, fe80::1]" or "en0 (not found)"
+pub(crate) fn describe_iface(iface: &str) -> String {
+    if let Some(members) = crate::balance::members(iface) {
+        let list: Vec<String> = members.iter().map(|m| describe_iface(m)).collect();
+        return format!("{} group [{}]", iface, list.join("; "));
+    }
+    match list_interfaces() {
+        Ok(list) => match list.into_iter().find(|i| i.name == iface) {
+            Some(info) => {
+                let addrs: Vec<String> = info.addrs.iter().map(|a| a.to_string()).collect();
+                format!("{} {} [{}]", iface, if info.is_up { "up" } else { "down" }, addrs.join(", "))
+            }
+            None => format!("{} (not found)", iface),
+        },
+        Err(e) => format!("{} (lookup failed: {})", iface, e),
+    }
+}
+
+// 绑定网卡的出站 UDP socket（本地端口随机），地址族与 `target` 一致
+pub(crate) fn udp_socket_for(target: std::net::SocketAddr, iface: &str) -> Result<tokio::net::UdpSocket> {
+    let iface = crate::balance::resolve(iface, &target.ip().to_string(), 0);
+    let local: std::net::SocketAddr = match vrf_source(iface, target.is_ipv6()) {
+        Some(src) => src,
+        None if target.is_ipv4() => (std::net::Ipv4Addr::UNSPECIFIED, 0).into(),
+        None => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let sock = match source_ports_for(iface) {
+        Some(range) => bind_in_range(local.ip(), range, std::net::UdpSocket::bind)?,
+        None => std::net::UdpSocket::bind(local)?,
+    };
+    bind_outbound(sock.as_raw_fd(), iface, target, true)?;
+    apply_marks(sock.as_raw_fd(), target.is_ipv6(), false)?;
+    sock.set_nonblocking(true)?;
+    Ok(tokio::net::UdpSocket::from_std(sock)?)
+}
+
+// 默认禁止经非回环监听访问的目标：本机、RFC1918 内网、链路本地与 IPv6 ULA
+pub(crate) fn default_deny_dest() -> Vec<Cidr> {
+    ["0.0.0.0/8", "127.0.0.0/8", "10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "169.254.0.0/16", "::1/128", "::/128", "fe80::/10", "fc00::/7"]
+        .iter()
+        .map(|c| Cidr::parse(c).expect("valid builtin CIDR"))
+        .collect()
+}
+
+// 记下会话出站连接的本地地址，--on-iface-change 据此找出出口地址变化后受影响的会话
+fn record_local(s: TcpStream) -> TcpStream {
+    if let Ok(addr) = s.local_addr() { crate::session::set_local(addr); }
+    s
+}
+
+// `deny` 按解析后的地址检查，因此指向内网的域名同样会被拒绝
+pub(crate) async fn connect_outbound(host: &str, port: u16, iface: &str, deny: &[Cidr]) -> Result<TcpStream> {
+    connect_outbound_attempt(host, port, iface, deny, 0).await
+}
+
+// 第 N 次尝试从解析结果的第 N 个地址开始，重试时先换一个地址（如双栈目标的 IPv6 不通时先试 IPv4）
+pub(crate) async fn connect_outbound_attempt(host: &str, port: u16, iface: &str, deny: &[Cidr], attempt: usize) -> Result<TcpStream> {
+    let iface = crate::balance::resolve(iface, host, attempt);
+    let addrs = crate::hosts::resolve(host, port).await.map_err(|e| ProxyError::Dns { host: host.to_string(), reason: e.to_string() })?;
+    let mut addrs = crate::nat64::map(addrs, iface);
+    if !addrs.is_empty() {
+        let n = attempt % addrs.len();
+        addrs.rotate_left(n);
+    }
     let mut last_err: Option<anyhow::Error> = None;
     for sa in addrs {
+        let dest = crate::nat64::unmap(sa.ip());
+        if deny.iter().any(|c| c.contains(dest)) && crate::acl::hit(crate::acl::AclRule::DestDeny, dest) {
+            last_err = Some(ProxyError::PolicyDenied(Denied::Dest(dest)).into());
+            continue;
+        }
+        if crate::loopguard::is_self(sa) {
+            last_err = Some(ProxyError::PolicyDenied(Denied::Loop(sa)).into());
+            continue;
+        }
         match sa {
             std::net::SocketAddr::V4(v4) => {
                 let socket = TcpSocket::new_v4()?;
                 let fd = socket.as_raw_fd();
-                if let Err(e) = bind_iface_v4(fd, iface) {
-                    last_err = Some(e);
+                if let Err(e) = bind_outbound(fd, iface, sa, false).and_then(|_| apply_marks(fd, false, true)) {
+                    last_err = Some(ProxyError::IfaceBind { iface: iface.to_string(), reason: e.to_string() }.into());
+                    continue;
+                }
+                if let Err(e) = bind_source(&socket, iface, false) {
+                    last_err = Some(ProxyError::IfaceBind { iface: iface.to_string(), reason: e.to_string() }.into());
                     continue;
                 }
+                apply_tcp_mss(fd, iface);
+                apply_sockopts(fd, host);
                 match socket.connect(std::net::SocketAddr::V4(v4)).await {
-                    Ok(s) => return Ok(s),
+                    Ok(s) => return Ok(record_local(s)),
                     Err(e) => {
-                        last_err = Some(anyhow::Error::new(e));
+                        last_err = Some(ProxyError::connect(sa, e).into());
                         continue;
                     }
                 }
@@ -213,14 +954,20 @@ pub(crate) async fn connect_outbound(host: &str, port: u16, iface: &str) -> Resu
             std::net::SocketAddr::V6(v6) => {
                 let socket = TcpSocket::new_v6()?;
                 let fd = socket.as_raw_fd();
-                if let Err(e) = bind_iface_v6(fd, iface) {
-                    last_err = Some(e);
+                if let Err(e) = bind_outbound(fd, iface, sa, false).and_then(|_| apply_marks(fd, true, true)) {
+                    last_err = Some(ProxyError::IfaceBind { iface: iface.to_string(), reason: e.to_string() }.into());
+                    continue;
+                }
+                if let Err(e) = bind_source(&socket, iface, true) {
+                    last_err = Some(ProxyError::IfaceBind { iface: iface.to_string(), reason: e.to_string() }.into());
                     continue;
                 }
+                apply_tcp_mss(fd, iface);
+                apply_sockopts(fd, host);
                 match socket.connect(std::net::SocketAddr::V6(v6)).await {
-                    Ok(s) => return Ok(s),
+                    Ok(s) => return Ok(record_local(s)),
                     Err(e) => {
-                        last_err = Some(anyhow::Error::new(e));
+                        last_err = Some(ProxyError::connect(sa, e).into());
                         continue;
                     }
                 }
@@ -230,8 +977,135 @@ pub(crate) async fn connect_outbound(host: &str, port: u16, iface: &str) -> Resu
     if let Some(e) = last_err {
         Err(e)
     } else {
-        anyhow::bail!("no address")
+        Err(ProxyError::Dns { host: host.to_string(), reason: String::from("no address") }.into())
+    }
+}
+
+
+// ADDR/PREFIX，如 192.168.1.0/24、fd00::/8；不带前缀时表示单个地址
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Cidr {
+    net: std::net::IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((a, p)) => (a, Some(p)),
+            None => (s, None),
+        };
+        let net: std::net::IpAddr = addr.trim().parse().map_err(|_| anyhow::anyhow!("invalid CIDR {:?}", s))?;
+        let max = if net.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.trim().parse::<u8>().ok().filter(|p| *p <= max).ok_or_else(|| anyhow::anyhow!("invalid prefix length in {:?}", s))?,
+            None => max,
+        };
+        Ok(Self { net, prefix })
+    }
+
+    pub(crate) fn contains(&self, ip: std::net::IpAddr) -> bool {
+        // IPv4-mapped IPv6 peers (dual-stack listeners) match IPv4 ranges
+        match (self.net, ip.to_canonical()) {
+            (std::net::IpAddr::V4(net), std::net::IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (std::net::IpAddr::V6(net), std::net::IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+
+    // 在 IPv4 映射的 IPv6 地址空间中的闭区间，IPv4 与 IPv6 范围可以放在一起比较
+    pub(crate) fn range(&self) -> (u128, u128) {
+        let (start, bits) = match self.net {
+            std::net::IpAddr::V4(v4) => (u128::from(v4.to_ipv6_mapped()), 32 - self.prefix as u32),
+            std::net::IpAddr::V6(v6) => (u128::from(v6), 128 - self.prefix as u32),
+        };
+        let host = u128::MAX.checked_shr(128 - bits).unwrap_or(0);
+        (start & !host, start | host)
+    }
+
+    pub(crate) fn key(ip: std::net::IpAddr) -> u128 {
+        match ip.to_canonical() {
+            std::net::IpAddr::V4(v4) => u128::from(v4.to_ipv6_mapped()),
+            std::net::IpAddr::V6(v6) => u128::from(v6),
+        }
     }
 }
 
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.net, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_suffix_matching() {
+        assert!(host_has_suffix("anything.example", "*"));
+        assert!(host_has_suffix("example.com", "example.com"));
+        assert!(host_has_suffix("WWW.Example.COM.", "example.com"));
+        assert!(!host_has_suffix("badexample.com", "example.com"));
+        assert!(!host_has_suffix("com", "example.com"));
+        assert!(!host_has_suffix("例子.example.co", "example.com"));
+        // 各类 SUFFIX= 规则都经这里匹配
+        let rule = TcpRule::parse(".Example.com=tfo=on").unwrap();
+        assert!(rule.matches_host("a.example.com") && !rule.matches_host("example.org"));
+    }
 
+    #[test]
+    fn dscp_and_raw_sockopts() {
+        assert_eq!(parse_dscp("ef").unwrap(), 46);
+        assert_eq!(parse_dscp("AF41").unwrap(), 34);
+        assert_eq!(parse_dscp("cs1").unwrap(), 8);
+        assert_eq!(parse_dscp("63").unwrap(), 63);
+        assert!(parse_dscp("64").is_err() && parse_dscp("AF44").is_err() && parse_dscp("CS8").is_err());
+
+        let ttl = RawSockOpt::parse("IP_TTL=9").unwrap();
+        assert_eq!((ttl.level, ttl.name, ttl.value), (nix::libc::IPPROTO_IP, nix::libc::IP_TTL, 9));
+        assert_eq!(RawSockOpt::parse("tcp:TCP_NODELAY=1").unwrap().name, nix::libc::TCP_NODELAY);
+        assert_eq!(RawSockOpt::parse("6:1=1").unwrap().describe(), "6:1=1");
+        assert!(RawSockOpt::parse("42=1").is_err() && RawSockOpt::parse("SO_BOGUS=1").is_err());
+        assert!(RawSockOpt::parse("IPPROTO_TCP:IP_TTL=1").is_err() && RawSockOpt::parse("IP_TTL").is_err());
+
+        // --sockopt 里的 IP_TOS 晚于 --dscp 设置，IPv6 级与 TCP 级选项不用于 IPv4 UDP socket
+        let get = |fd, level, name| {
+            let mut v: nix::libc::c_int = 0;
+            let mut len = std::mem::size_of::<nix::libc::c_int>() as nix::libc::socklen_t;
+            assert_eq!(unsafe { nix::libc::getsockopt(fd, level, name, &mut v as *mut _ as *mut nix::libc::c_void, &mut len) }, 0);
+            v
+        };
+        let sock = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let fd = sock.as_raw_fd();
+        let opts = [ttl, RawSockOpt::parse("IPV6_UNICAST_HOPS=3").unwrap(), RawSockOpt::parse("TCP_NODELAY=1").unwrap()];
+        set_socket_marks(fd, false, false, Some(46), &opts).unwrap();
+        assert_eq!(get(fd, nix::libc::IPPROTO_IP, nix::libc::IP_TOS), 46 << 2);
+        assert_eq!(get(fd, nix::libc::IPPROTO_IP, nix::libc::IP_TTL), 9);
+        set_socket_marks(fd, false, false, Some(46), &[RawSockOpt::parse("IP_TOS=32").unwrap()]).unwrap();
+        assert_eq!(get(fd, nix::libc::IPPROTO_IP, nix::libc::IP_TOS), 32);
+    }
+
+    #[test]
+    fn source_port_ranges() {
+        let r = PortRange::parse("ppp0=50000-50099").unwrap();
+        assert_eq!((r.iface.as_deref(), r.start, r.end), (Some("ppp0"), 50000, 50099));
+        assert_eq!(PortRange::parse("40000").map(|r| (r.start, r.end)).unwrap(), (40000, 40000));
+        for bad in ["=1-2", "2-1", "0-10", "1-65536", "a-b"] {
+            assert!(PortRange::parse(bad).is_err(), "{}", bad);
+        }
+
+        let ip: std::net::IpAddr = "127.0.0.1".parse().unwrap();
+        let port = std::net::TcpListener::bind((ip, 0)).unwrap().local_addr().unwrap().port();
+        let held = bind_in_range(ip, (port, port), std::net::TcpListener::bind).unwrap();
+        assert_eq!(held.local_addr().unwrap().port(), port);
+        let e = bind_in_range(ip, (port, port), std::net::TcpListener::bind).unwrap_err();
+        assert!(e.to_string().contains("no free source port"), "{}", e);
+    }
+}
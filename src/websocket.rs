@@ -0,0 +1,460 @@
+use anyhow::Result;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpListener;
+use tokio::time::{timeout, Duration};
+
+use crate::error::{Denied, ProxyError};
+use crate::head::RequestHead;
+use crate::listener::{AcceptBackoff, ListenerSettings};
+use crate::stats::Metered;
+use crate::util::{log_info, log_throttled};
+
+// WebSocket 隧道：两台 iface-proxy 配对，本地实例把会话经 WebSocket（可经 TLS）交给远端实例，由远端经它的网卡外发。
+// 远端以 `--listener ws=ADDR:PORT` 接受隧道，本地以 `--upstream NAME=ws(s)://USER:PASS@HOST:PORT/PATH` 作为上游；
+// 握手请求用 X-Iface-Proxy-Target 头给出目标，远端建连成功后才回 101，之后每个隧道承载一条 TCP 连接
+pub(crate) const TARGET_HEADER: &str = "X-Iface-Proxy-Target";
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// 单帧最大负载，大块写入拆成多帧
+const MAX_FRAME: usize = 16 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+pub(crate) fn accept_key(key: &str) -> String {
+    use base64::Engine;
+    let mut sha = sha1_smol::Sha1::new();
+    sha.update(key.as_bytes());
+    sha.update(GUID.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(sha.digest().bytes())
+}
+
+fn encode_frame(out: &mut Vec<u8>, opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) {
+    out.push(0x80 | opcode);
+    let bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        n if n < 126 => out.push(bit | n as u8),
+        n if n <= u16::MAX as usize => {
+            out.push(bit | 126);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        n => {
+            out.push(bit | 127);
+            out.extend_from_slice(&(n as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(key) => {
+            out.extend_from_slice(&key);
+            out.extend(payload.iter().enumerate().map(|(i, b)| b ^ key[i % 4]));
+        }
+        None => out.extend_from_slice(payload),
+    }
+}
+
+// 帧头：(头部长度, 操作码, 负载长度, 掩码)；数据不够时为 None
+fn parse_header(buf: &[u8]) -> Option<(usize, u8, u64, Option<[u8; 4]>)> {
+    let (b0, b1) = (*buf.first()?, *buf.get(1)?);
+    let (mut pos, len) = match b1 & 0x7f {
+        126 => (4, u16::from_be_bytes([*buf.get(2)?, *buf.get(3)?]) as u64),
+        127 => (10, u64::from_be_bytes(buf.get(2..10)?.try_into().ok()?)),
+        n => (2, n as u64),
+    };
+    let mask = if b1 & 0x80 != 0 {
+        let key: [u8; 4] = buf.get(pos..pos + 4)?.try_into().ok()?;
+        pos += 4;
+        Some(key)
+    } else {
+        None
+    };
+    Some((pos, b0 & 0x0f, len, mask))
+}
+
+// 在底层连接上收发二进制帧的字节流；客户端发出的帧加掩码，收到 close 帧即视为 EOF，ping 自动回 pong
+pub(crate) struct WsStream<S> {
+    inner: S,
+    client: bool,
+    // 已读入、尚未处理的原始字节
+    rbuf: Vec<u8>,
+    // 当前数据帧还没交给调用方的负载字节数，及其掩码与偏移
+    left: u64,
+    mask: Option<([u8; 4], usize)>,
+    eof: bool,
+    // 已编码、尚未写出的帧
+    wbuf: Vec<u8>,
+    closed: bool,
+    // 掩码用的伪随机数状态
+    seed: u64,
+}
+
+impl<S> WsStream<S> {
+    // `leftover` 为读握手时多读到的帧数据
+    pub(crate) fn new(inner: S, client: bool, leftover: &[u8]) -> Self {
+        let mut seed = [0u8; 8];
+        let _ = getrandom::getrandom(&mut seed);
+        Self { inner, client, rbuf: leftover.to_vec(), left: 0, mask: None, eof: false, wbuf: Vec::new(), closed: false, seed: u64::from_le_bytes(seed) | 1 }
+    }
+
+    fn next_mask(&mut self) -> Option<[u8; 4]> {
+        if !self.client { return None; }
+        // xorshift64
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        Some((self.seed as u32).to_le_bytes())
+    }
+
+    fn queue(&mut self, opcode: u8, payload: &[u8]) {
+        let mask = self.next_mask();
+        encode_frame(&mut self.wbuf, opcode, payload, mask);
+    }
+}
+
+impl<S: AsyncWrite + Unpin> WsStream<S> {
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while !self.wbuf.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.wbuf))?;
+            if n == 0 { return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into())); }
+            self.wbuf.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("websocket: {}", msg))
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if this.eof { return Poll::Ready(Ok(())); }
+            if this.left > 0 && !this.rbuf.is_empty() {
+                let n = (this.left.min(this.rbuf.len() as u64) as usize).min(buf.remaining());
+                let mut data: Vec<u8> = this.rbuf.drain(..n).collect();
+                if let Some((key, pos)) = &mut this.mask {
+                    for b in data.iter_mut() {
+                        *b ^= key[*pos % 4];
+                        *pos += 1;
+                    }
+                }
+                this.left -= n as u64;
+                buf.put_slice(&data);
+                return Poll::Ready(Ok(()));
+            }
+            if this.left == 0 {
+                if let Some((hlen, opcode, len, mask)) = parse_header(&this.rbuf) {
+                    match opcode {
+                        OP_CONTINUATION | OP_TEXT | OP_BINARY => {
+                            // 客户端发来的帧须加掩码，服务端发来的不得加
+                            if this.client == mask.is_some() { return Poll::Ready(Err(invalid("bad frame masking"))); }
+                            this.rbuf.drain(..hlen);
+                            this.left = len;
+                            this.mask = mask.map(|k| (k, 0));
+                            continue;
+                        }
+                        OP_CLOSE | OP_PING | OP_PONG => {
+                            // 控制帧不分片，负载不超过 125 字节，整帧到齐再处理
+                            if len > 125 { return Poll::Ready(Err(invalid("oversized control frame"))); }
+                            let end = hlen + len as usize;
+                            if this.rbuf.len() >= end {
+                                let mut payload: Vec<u8> = this.rbuf[hlen..end].to_vec();
+                                if let Some(key) = mask {
+                                    for (i, b) in payload.iter_mut().enumerate() { *b ^= key[i % 4]; }
+                                }
+                                this.rbuf.drain(..end);
+                                match opcode {
+                                    OP_CLOSE => this.eof = true,
+                                    OP_PING => {
+                                        this.queue(OP_PONG, &payload);
+                                        // 尽力立即写出，写不完的留给下一次写或 flush
+                                        if let Poll::Ready(Err(e)) = this.poll_drain(cx) { return Poll::Ready(Err(e)); }
+                                    }
+                                    _ => {}
+                                }
+                                continue;
+                            }
+                        }
+                        _ => return Poll::Ready(Err(invalid("unknown opcode"))),
+                    }
+                }
+            }
+            let mut tmp = [0u8; MAX_FRAME];
+            let mut rb = ReadBuf::new(&mut tmp);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut rb))?;
+            if rb.filled().is_empty() {
+                if this.left > 0 || !this.rbuf.is_empty() { return Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into())); }
+                this.eof = true;
+                continue;
+            }
+            this.rbuf.extend_from_slice(rb.filled());
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = &mut *self;
+        ready!(this.poll_drain(cx))?;
+        if buf.is_empty() { return Poll::Ready(Ok(0)); }
+        let n = buf.len().min(MAX_FRAME);
+        this.queue(OP_BINARY, &buf[..n]);
+        // 数据已编入 wbuf，写不完的由下一次写或 flush 继续
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) { return Poll::Ready(Err(e)); }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    // 先发 close 帧再关闭写方向
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        if !self.closed {
+            self.closed = true;
+            // 1000 正常关闭
+            self.queue(OP_CLOSE, &1000u16.to_be_bytes());
+        }
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// 读到空行为止的应答头；返回 (头部文本, 之后多读到的字节)
+async fn read_response_head<S: AsyncRead + Unpin>(s: &mut S) -> Result<(String, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut tmp = [0u8; 4096];
+    loop {
+        let n = s.read(&mut tmp).await?;
+        if n == 0 { anyhow::bail!("connection closed during websocket handshake"); }
+        buf.extend_from_slice(&tmp[..n]);
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = buf.split_off(end + 4);
+            return Ok((String::from_utf8_lossy(&buf[..end]).into_owned(), rest));
+        }
+        if buf.len() > 16 * 1024 { anyhow::bail!("websocket handshake response too large"); }
+    }
+}
+
+// 客户端握手：请求隧道到 `target`（host:port），远端建连成功时返回 101
+pub(crate) struct ClientHandshake<'a> {
+    // Host 头
+    pub(crate) host: &'a str,
+    pub(crate) path: &'a str,
+    pub(crate) auth: Option<(&'a str, &'a str)>,
+    pub(crate) target: &'a str,
+    // 请远端使用的出口网卡，须在远端的 --egress-allow 中
+    pub(crate) egress: Option<&'a str>,
+}
+
+pub(crate) async fn connect<S: AsyncRead + AsyncWrite + Unpin>(mut s: S, hs: ClientHandshake<'_>, handshake_timeout_ms: u64) -> Result<WsStream<S>> {
+    use base64::Engine;
+    let mut key = [0u8; 16];
+    getrandom::getrandom(&mut key)?;
+    let key = base64::engine::general_purpose::STANDARD.encode(key);
+    let mut req = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\nUser-Agent: {}\r\n{}: {}\r\n",
+        hs.path, hs.host, key, crate::build_info::AGENT, TARGET_HEADER, hs.target
+    );
+    if let Some((user, pass)) = hs.auth {
+        req.push_str(&format!("Authorization: Basic {}\r\n", base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, pass))));
+    }
+    if let Some(iface) = hs.egress { req.push_str(&format!("{}: {}\r\n", crate::egress::HEADER, iface)); }
+    req.push_str("\r\n");
+    let (head, rest) = timeout(Duration::from_millis(handshake_timeout_ms), async {
+        s.write_all(req.as_bytes()).await?;
+        read_response_head(&mut s).await
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("websocket handshake timed out"))??;
+    let status = head.lines().next().unwrap_or("");
+    let code = status.split_whitespace().nth(1).unwrap_or("");
+    if code != "101" {
+        // 远端的错误应答正文为一行说明
+        let reason = String::from_utf8_lossy(&rest).trim().to_string();
+        let reason = if reason.is_empty() { status.to_string() } else { format!("{} ({})", status, reason) };
+        if code == "403" { anyhow::bail!(ProxyError::PolicyDenied(Denied::Route(format!("{} refused by tunnel server: {}", crate::addr::redact_authority(hs.target), reason)))); }
+        anyhow::bail!("tunnel server answered {}", reason);
+    }
+    let accept = head.lines().find_map(|l| l.split_once(':').filter(|(n, _)| n.trim().eq_ignore_ascii_case("sec-websocket-accept")).map(|(_, v)| v.trim()));
+    if accept != Some(accept_key(&key).as_str()) { anyhow::bail!("websocket handshake: bad Sec-WebSocket-Accept"); }
+    Ok(WsStream::new(s, true, &rest))
+}
+
+fn reply(status: &str, extra: &str, reason: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nServer: {}\r\n{}Content-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+        status,
+        crate::build_info::AGENT,
+        extra,
+        reason.len() + 1,
+        reason
+    )
+}
+
+async fn handle_tunnel<S: AsyncRead + AsyncWrite + Unpin>(mut inbound: S, s: &ListenerSettings) -> Result<()> {
+    let raw = match crate::handshake::read_headers(&mut inbound, s.read_timeout_ms).await {
+        Ok(raw) => raw,
+        Err(e) => {
+            if let Some(ProxyError::HeadersTooLarge(reason)) = ProxyError::find(&e) {
+                inbound.write_all(reply("431 Request Header Fields Too Large", "", reason).as_bytes()).await?;
+            }
+            return Err(e);
+        }
+    };
+    crate::session::handshake_done();
+    let (header_end, leftover) = crate::http_proxy::split_headers_body(&raw).ok_or_else(|| ProxyError::Handshake(String::from("bad headers")))?;
+    let text = String::from_utf8_lossy(&raw[..header_end]);
+    let head = RequestHead::parse(&text).map_err(|e| ProxyError::Handshake(e.to_string()))?;
+    let key = match head.get("sec-websocket-key") {
+        Some(k) if head.method.eq_ignore_ascii_case("GET") && head.is_websocket_upgrade() => k,
+        _ => {
+            inbound.write_all(reply("400 Bad Request", "", "expected a WebSocket tunnel handshake").as_bytes()).await?;
+            anyhow::bail!(ProxyError::Handshake(String::from("not a websocket handshake")));
+        }
+    };
+
+    let mut user = None;
+    if let Some(auth) = &s.auth {
+        let creds = head.get("authorization").and_then(crate::http_proxy::basic_credentials);
+        let ok = match &creds {
+            Some((u, p)) => crate::auth::verify(auth.as_ref(), u, p).await,
+            None => false,
+        };
+        if !ok {
+            if creds.is_some() { crate::ban::record_failure(); }
+            inbound.write_all(reply("401 Unauthorized", "WWW-Authenticate: Basic realm=\"iface-proxy\"\r\n", "authentication required").as_bytes()).await?;
+            anyhow::bail!("websocket tunnel authentication failed");
+        }
+        let name = creds.map(|c| c.0).unwrap_or_default();
+        if let Err(e) = crate::quota::check(&name) {
+            inbound.write_all(reply("403 Forbidden", "", &e.to_string()).as_bytes()).await?;
+            return Err(e);
+        }
+        crate::session::set_user(&name);
+        user = Some(name);
+    }
+
+    let target = head.get(TARGET_HEADER).unwrap_or("");
+    let target = match crate::addr::TargetAddr::parse_authority(target, 0) {
+        Ok(t) if t.port() != 0 => t,
+        _ => {
+            inbound.write_all(reply("400 Bad Request", "", &format!("missing or invalid {} header", TARGET_HEADER)).as_bytes()).await?;
+            anyhow::bail!(ProxyError::Handshake(format!("invalid tunnel target {:?}", target)));
+        }
+    };
+    let egress = crate::egress::from_headers(&head);
+    if let Some(name) = &egress {
+        if let Err(e) = crate::egress::check(name) {
+            inbound.write_all(reply("403 Forbidden", "", &e.to_string()).as_bytes()).await?;
+            return Err(e);
+        }
+    }
+    let iface = egress.as_deref().unwrap_or(&s.iface);
+    let (host, port) = (target.host(), target.port());
+
+    log_throttled(|| log_info(format!("WebSocket tunnel -> {} (iface: {})", target.redacted(), iface)));
+    // 建连失败时按 HTTP 代理的规则回错误状态，客户端据此报错
+    let outbound = crate::http_proxy::dial(&mut inbound, s.dialer.as_ref(), &host, port, iface, &s.deny_dest).await?;
+    let resp = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept_key(key));
+    inbound.write_all(resp.as_bytes()).await?;
+    let mut ws = WsStream::new(inbound, false, leftover);
+    let outbound = Metered::new(crate::capture::maybe_wrap(outbound, &host, port, false));
+    let (c2s, s2c) = crate::stats::relay(&mut ws, outbound, &host, user.as_deref(), s.session_timeout_ms).await?;
+    log_throttled(|| log_info(format!("WebSocket tunnel finished {} (c->s: {} bytes, s->c: {} bytes)", target.redacted(), c2s, s2c)));
+    Ok(())
+}
+
+pub async fn run_ws_tunnel(listener: TcpListener, s: Arc<ListenerSettings>) -> Result<()> {
+    let listen = listener.local_addr()?.to_string();
+    log_info(format!("WebSocket tunnel listening on {}, bound to {}{}", listen, s.iface, if s.auth.is_some() { ", auth enabled" } else { "" }));
+    let mut backoff = AcceptBackoff::new();
+    loop {
+        let (inbound, peer_addr) = backoff.accept(&listen, || listener.accept()).await;
+        if !s.allows(peer_addr.ip()) {
+            log_throttled(|| log_info(format!("denied connection from {} to {} (not in allow list)", peer_addr, listen)));
+            continue;
+        }
+        if crate::ban::banned(peer_addr.ip(), &listen) { continue; }
+        let Some(pending) = s.limit.handshake(Some(peer_addr.ip())) else {
+            log_throttled(|| log_info("too many connections still in handshake; dropping new WebSocket tunnel connection"));
+            continue;
+        };
+        match s.limit.acquire().await {
+            Some(permit) => {
+                let s = s.clone();
+                tokio::spawn(crate::session::run("ws", peer_addr.to_string(), s.sockopts, async move {
+                    let _permit = permit;
+                    crate::session::hold_handshake(pending);
+                    if let Err(e) = handle_tunnel(inbound, &s).await {
+                        crate::error::log_session_error("WebSocket tunnel", &e);
+                    }
+                }));
+            }
+            None => {
+                log_throttled(|| log_info("too many concurrent connections; dropping new WebSocket tunnel connection"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_the_rfc_accept_key() {
+        // RFC 6455 1.3 中的例子
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        let mut frame = Vec::new();
+        encode_frame(&mut frame, OP_BINARY, &[1u8; 300], Some([1, 2, 3, 4]));
+        assert_eq!(parse_header(&frame), Some((8, OP_BINARY, 300, Some([1, 2, 3, 4]))));
+        assert_eq!(parse_header(&frame[..3]), None);
+    }
+
+    #[tokio::test]
+    async fn carries_bytes_both_ways_and_answers_pings() {
+        let (a, b) = tokio::io::duplex(1024);
+        let mut client = WsStream::new(a, true, &[]);
+        let (mut raw_server, mut raw_w) = tokio::io::split(b);
+
+        // 大于单帧的写入拆成多帧，服务端按掩码还原
+        let data: Vec<u8> = (0..40_000u32).map(|i| i as u8).collect();
+        let sent = data.clone();
+        let writer = tokio::spawn(async move {
+            client.write_all(&sent).await.unwrap();
+            client.flush().await.unwrap();
+            client
+        });
+        let mut server_side = Vec::new();
+        let mut tmp = [0u8; 4096];
+        let mut server = WsStream::new(tokio::io::join(&mut raw_server, tokio::io::sink()), false, &[]);
+        while server_side.len() < data.len() {
+            let n = server.read(&mut tmp).await.unwrap();
+            server_side.extend_from_slice(&tmp[..n]);
+        }
+        assert_eq!(server_side, data);
+        let mut client = writer.await.unwrap();
+
+        // 服务端发 ping 与数据，再发 close：客户端读到数据与 EOF，并回 pong
+        let mut frames = Vec::new();
+        encode_frame(&mut frames, OP_PING, b"hi", None);
+        encode_frame(&mut frames, OP_BINARY, b"pong?", None);
+        encode_frame(&mut frames, OP_CLOSE, &1000u16.to_be_bytes(), None);
+        raw_w.write_all(&frames).await.unwrap();
+        let mut got = Vec::new();
+        client.read_to_end(&mut got).await.unwrap();
+        assert_eq!(got, b"pong?");
+        let mut pong = [0u8; 8];
+        raw_server.read_exact(&mut pong).await.unwrap();
+        assert_eq!(parse_header(&pong).map(|h| (h.1, h.2)), Some((OP_PONG, 2)));
+    }
+}
@@ -0,0 +1,438 @@
+// 端到端测试：在临时端口启动代理进程与本地源站，经回环网卡验证转发行为
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const IFACE: &str = if cfg!(target_os = "macos") { "lo0" } else { "lo" };
+const ORIGIN_BODY: &str = "hello from origin";
+
+struct Proxy {
+    child: Child,
+    ready_file: PathBuf,
+    listeners: Vec<(String, SocketAddr)>,
+}
+
+impl Proxy {
+    // 额外参数追加在 `run -i lo -l 127.0.0.1:0 --ready-file ...` 之后
+    fn start(extra: &[&str]) -> Self {
+        let ready_file = std::env::temp_dir().join(format!("iface-proxy-e2e-{}-{:?}.ready", std::process::id(), std::thread::current().id()));
+        let _ = std::fs::remove_file(&ready_file);
+        let child = Command::new(env!("CARGO_BIN_EXE_iface-proxy"))
+            .args(["run", "-i", IFACE, "-l", "127.0.0.1:0", "--ready-file"])
+            .arg(&ready_file)
+            .args(extra)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("spawn iface-proxy");
+        let mut proxy = Proxy { child, ready_file, listeners: Vec::new() };
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            if let Ok(body) = std::fs::read_to_string(&proxy.ready_file) {
+                proxy.listeners = body
+                    .lines()
+                    .filter_map(|l| l.split_once(' '))
+                    .filter_map(|(kind, addr)| Some((kind.to_string(), addr.parse().ok()?)))
+                    .collect();
+                return proxy;
+            }
+            if let Ok(Some(status)) = proxy.child.try_wait() {
+                panic!("iface-proxy exited before becoming ready: {}", status);
+            }
+            assert!(Instant::now() < deadline, "iface-proxy did not become ready");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    fn addr(&self, kind: &str) -> SocketAddr {
+        self.listeners.iter().find(|(k, _)| k == kind).map(|(_, a)| *a).unwrap_or_else(|| panic!("no {} listener", kind))
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_file(&self.ready_file);
+    }
+}
+
+// GET 返回固定应答（`/headers` 返回收到的请求头），POST 把 body 原样作为应答 body，其余数据原样回显
+fn start_origin() -> SocketAddr {
+    start_origin_on("127.0.0.1:0")
+}
+
+fn start_origin_on(bind: &str) -> SocketAddr {
+    let listener = TcpListener::bind(bind).unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for conn in listener.incoming().flatten() {
+            std::thread::spawn(move || serve_origin(conn));
+        }
+    });
+    addr
+}
+
+fn serve_origin(mut conn: TcpStream) {
+    let mut buf = [0u8; 4096];
+    let mut data = Vec::new();
+    loop {
+        let n = match conn.read(&mut buf) { Ok(0) | Err(_) => return, Ok(n) => n };
+        data.extend_from_slice(&buf[..n]);
+        if !data.starts_with(b"GET ") && !data.starts_with(b"POST ") {
+            if conn.write_all(&data).is_err() { return; }
+            data.clear();
+            continue;
+        }
+        let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4) else { continue };
+        let head = String::from_utf8_lossy(&data[..end]).to_ascii_lowercase();
+        let body = match head.lines().find_map(|l| l.strip_prefix("content-length:")) {
+            Some(len) => {
+                let len: usize = len.trim().parse().unwrap();
+                if data.len() < end + len { continue; }
+                data[end..end + len].to_vec()
+            }
+            None if data.starts_with(b"GET /headers") => data[..end].to_vec(),
+            None => ORIGIN_BODY.as_bytes().to_vec(),
+        };
+        let mut resp = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).into_bytes();
+        resp.extend_from_slice(&body);
+        let _ = conn.write_all(&resp);
+        return;
+    }
+}
+
+fn connect(addr: SocketAddr) -> TcpStream {
+    let s = TcpStream::connect(addr).unwrap();
+    s.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    s
+}
+
+fn read_head(s: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut b = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if s.read(&mut b).unwrap() == 0 { break; }
+        head.push(b[0]);
+    }
+    String::from_utf8_lossy(&head).into_owned()
+}
+
+fn read_to_end(s: &mut TcpStream) -> String {
+    let mut out = Vec::new();
+    let _ = s.read_to_end(&mut out);
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn socks5_connect(proxy: SocketAddr, target: SocketAddr, auth: Option<(&str, &str)>) -> Result<TcpStream, String> {
+    let mut s = connect(proxy);
+    s.write_all(&[0x05, 0x01, if auth.is_some() { 0x02 } else { 0x00 }]).unwrap();
+    let mut rep = [0u8; 2];
+    s.read_exact(&mut rep).map_err(|e| e.to_string())?;
+    if rep[1] == 0xFF { return Err(String::from("no acceptable method")); }
+    if let Some((u, p)) = auth {
+        let mut msg = vec![0x01, u.len() as u8];
+        msg.extend_from_slice(u.as_bytes());
+        msg.push(p.len() as u8);
+        msg.extend_from_slice(p.as_bytes());
+        s.write_all(&msg).unwrap();
+        s.read_exact(&mut rep).map_err(|e| e.to_string())?;
+        if rep[1] != 0x00 { return Err(String::from("auth failed")); }
+    }
+    let SocketAddr::V4(v4) = target else { unreachable!() };
+    let mut req = vec![0x05, 0x01, 0x00, 0x01];
+    req.extend_from_slice(&v4.ip().octets());
+    req.extend_from_slice(&v4.port().to_be_bytes());
+    s.write_all(&req).unwrap();
+    let mut reply = [0u8; 10];
+    s.read_exact(&mut reply).map_err(|e| e.to_string())?;
+    if reply[1] != 0x00 { return Err(format!("CONNECT failed (REP={:#04x})", reply[1])); }
+    Ok(s)
+}
+
+#[test]
+fn connect_tunnel_echoes_through_origin() {
+    let origin = start_origin();
+    let proxy = Proxy::start(&["--connect-ports", "*"]);
+    let mut s = connect(proxy.addr("http"));
+    write!(s, "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", origin).unwrap();
+    let head = read_head(&mut s);
+    assert!(head.starts_with("HTTP/1.1 200"), "unexpected CONNECT response: {:?}", head);
+    let payload = vec![0x5Au8; 64 * 1024];
+    s.write_all(&payload).unwrap();
+    let mut back = vec![0u8; payload.len()];
+    s.read_exact(&mut back).unwrap();
+    assert_eq!(back, payload);
+}
+
+#[test]
+fn absolute_uri_get_is_forwarded() {
+    let origin = start_origin();
+    let proxy = Proxy::start(&[]);
+    let mut s = connect(proxy.addr("http"));
+    write!(s, "GET http://{0}/ HTTP/1.1\r\nHost: {0}\r\nConnection: close\r\n\r\n", origin).unwrap();
+    let resp = read_to_end(&mut s);
+    assert!(resp.starts_with("HTTP/1.1 200"), "unexpected response: {:?}", resp);
+    assert!(resp.ends_with(ORIGIN_BODY), "unexpected body: {:?}", resp);
+}
+
+#[test]
+fn absolute_uri_with_ipv6_literal_and_userinfo() {
+    let Ok(probe) = TcpListener::bind("[::1]:0") else { return }; // 环境不支持 IPv6 回环时跳过
+    drop(probe);
+    let origin = start_origin_on("[::1]:0");
+    let proxy = Proxy::start(&[]);
+    let mut s = connect(proxy.addr("http"));
+    write!(s, "GET http://alice:s%40cret@{0}/headers?x=1 HTTP/1.1\r\nHost: {0}\r\nConnection: close\r\n\r\n", origin).unwrap();
+    let resp = read_to_end(&mut s);
+    assert!(resp.starts_with("HTTP/1.1 200"), "unexpected response: {:?}", resp);
+    let body = &resp[resp.find("\r\n\r\n").unwrap() + 4..];
+    assert!(body.starts_with("GET /headers?x=1 HTTP/1.1\r\n"), "origin saw {:?}", body);
+    // alice:s@cret
+    assert!(body.contains("Authorization: Basic YWxpY2U6c0BjcmV0\r\n"), "origin saw {:?}", body);
+}
+
+#[test]
+fn post_body_sent_with_headers_reaches_origin() {
+    let origin = start_origin();
+    let proxy = Proxy::start(&[]);
+    let mut s = connect(proxy.addr("http"));
+    let body = "field=value&other=1";
+    // 头部与 body 在同一次写入中，代理读头部时会一并读到 body
+    let req = format!("POST http://{0}/form HTTP/1.1\r\nHost: {0}\r\nContent-Length: {1}\r\nConnection: close\r\n\r\n{2}", origin, body.len(), body);
+    s.write_all(req.as_bytes()).unwrap();
+    let resp = read_to_end(&mut s);
+    assert!(resp.starts_with("HTTP/1.1 200"), "unexpected response: {:?}", resp);
+    assert!(resp.ends_with(body), "origin did not receive the body: {:?}", resp);
+}
+
+#[test]
+fn bytes_pipelined_after_connect_are_forwarded() {
+    let origin = start_origin();
+    let proxy = Proxy::start(&["--connect-ports", "*"]);
+    let mut s = connect(proxy.addr("http"));
+    // 客户端不等 200 就发出隧道内的第一段数据（如 TLS ClientHello）
+    write!(s, "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\npipelined-hello", origin).unwrap();
+    let head = read_head(&mut s);
+    assert!(head.starts_with("HTTP/1.1 200"), "unexpected CONNECT response: {:?}", head);
+    let mut back = [0u8; 15];
+    s.read_exact(&mut back).unwrap();
+    assert_eq!(&back, b"pipelined-hello");
+}
+
+#[test]
+fn client_hello_pipelined_after_connect_on_mixed_port() {
+    let origin = start_origin();
+    let proxy = Proxy::start(&["-M", "127.0.0.1:0", "--connect-ports", "*"]);
+    // 形如 TLS 握手记录、超过代理读请求头时单次读取大小的首段数据，与 CONNECT 在同一次写入中发出
+    let mut hello = vec![0x16, 0x03, 0x01, 0x07, 0xFC];
+    hello.extend((0..2044u32).map(|i| (i % 251) as u8));
+    let mut req = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", origin).into_bytes();
+    req.extend_from_slice(&hello);
+    let mut s = connect(proxy.addr("mixed"));
+    s.write_all(&req).unwrap();
+    let head = read_head(&mut s);
+    assert!(head.starts_with("HTTP/1.1 200"), "unexpected CONNECT response: {:?}", head);
+    let mut back = vec![0u8; hello.len()];
+    s.read_exact(&mut back).unwrap();
+    assert_eq!(back, hello);
+}
+
+#[test]
+fn connect_targets_are_validated() {
+    let proxy = Proxy::start(&[]);
+    let status = |target: &str| {
+        let mut s = connect(proxy.addr("http"));
+        write!(s, "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", target).unwrap();
+        read_head(&mut s).split_whitespace().nth(1).unwrap_or("").to_string()
+    };
+    assert_eq!(status(":443"), "400");
+    assert_eq!(status("[]:443"), "400");
+    assert_eq!(status("2001:db8::1:443"), "400");
+    assert_eq!(status("example.com:0"), "400");
+    assert_eq!(status("example.com:https"), "400");
+    // 默认只允许 443 与 8443
+    assert_eq!(status("127.0.0.1:22"), "403");
+    assert_eq!(status("[::1]:25"), "403");
+}
+
+#[test]
+fn connect_to_bracketed_ipv6_literal() {
+    let Ok(probe) = TcpListener::bind("[::1]:0") else { return }; // 环境不支持 IPv6 回环时跳过
+    drop(probe);
+    let origin = start_origin_on("[::1]:0");
+    let proxy = Proxy::start(&["--connect-ports", "*"]);
+    let mut s = connect(proxy.addr("http"));
+    write!(s, "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", origin).unwrap();
+    let head = read_head(&mut s);
+    assert!(head.starts_with("HTTP/1.1 200"), "unexpected CONNECT response: {:?}", head);
+    s.write_all(b"v6").unwrap();
+    let mut back = [0u8; 2];
+    s.read_exact(&mut back).unwrap();
+    assert_eq!(&back, b"v6");
+}
+
+#[test]
+fn socks5_auth_success_and_failure() {
+    let origin = start_origin();
+    let proxy = Proxy::start(&["--socks5", "-S", "127.0.0.1:0", "--socks5-user", "alice", "--socks5-pass", "secret"]);
+    let socks = proxy.addr("socks5");
+
+    let mut s = socks5_connect(socks, origin, Some(("alice", "secret"))).expect("valid credentials");
+    s.write_all(b"ping").unwrap();
+    let mut back = [0u8; 4];
+    s.read_exact(&mut back).unwrap();
+    assert_eq!(&back, b"ping");
+
+    assert_eq!(socks5_connect(socks, origin, Some(("alice", "wrong"))).unwrap_err(), "auth failed");
+    assert!(socks5_connect(socks, origin, None).is_err(), "unauthenticated CONNECT must be refused");
+}
+
+#[test]
+fn htpasswd_auth_backend_guards_http_and_socks5() {
+    let origin = start_origin();
+    let htpasswd = std::env::temp_dir().join(format!("iface-proxy-e2e-{}.htpasswd", std::process::id()));
+    std::fs::write(&htpasswd, "alice:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=\n").unwrap();
+    let backend = format!("htpasswd:{}", htpasswd.display());
+    let proxy = Proxy::start(&["--socks5", "-S", "127.0.0.1:0", "--auth", &backend]);
+
+    let get = |auth: &str| {
+        let mut s = connect(proxy.addr("http"));
+        write!(s, "GET http://{}/ HTTP/1.1\r\nHost: {}\r\n{}Connection: close\r\n\r\n", origin, origin, auth).unwrap();
+        read_head(&mut s)
+    };
+    // alice:secret / alice:wrong
+    assert!(get("Proxy-Authorization: Basic YWxpY2U6c2VjcmV0\r\n").starts_with("HTTP/1.1 200"));
+    assert!(get("Proxy-Authorization: Basic YWxpY2U6d3Jvbmc=\r\n").starts_with("HTTP/1.1 407"));
+    assert!(get("").starts_with("HTTP/1.1 407"));
+
+    assert!(socks5_connect(proxy.addr("socks5"), origin, Some(("alice", "secret"))).is_ok());
+    assert_eq!(socks5_connect(proxy.addr("socks5"), origin, Some(("alice", "wrong"))).unwrap_err(), "auth failed");
+    let _ = std::fs::remove_file(&htpasswd);
+}
+
+#[test]
+fn idle_client_is_closed_after_read_timeout() {
+    let proxy = Proxy::start(&["--read-timeout-ms", "300"]);
+    let mut s = connect(proxy.addr("http"));
+    let started = Instant::now();
+    let mut buf = [0u8; 64];
+    // 超时后代理关闭连接：读到 EOF 或连接被重置
+    let n = s.read(&mut buf).unwrap_or(0);
+    assert_eq!(n, 0);
+    assert!(started.elapsed() < Duration::from_secs(4), "connection was not closed by the read timeout");
+}
+
+#[test]
+fn malformed_requests_are_rejected() {
+    let proxy = Proxy::start(&[]);
+    // 无法解析的请求行：直接断开
+    let mut s = connect(proxy.addr("http"));
+    s.write_all(b"NOT-A-REQUEST\r\n\r\n").unwrap();
+    let resp = read_to_end(&mut s);
+    assert!(resp.is_empty() || resp.starts_with("HTTP/1.1 400"), "garbage request got {:?}", resp);
+
+    // 可能导致请求走私的头部组合：400
+    let mut s = connect(proxy.addr("http"));
+    s.write_all(b"GET http://127.0.0.1:1/ HTTP/1.1\r\nHost: a\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n").unwrap();
+    let resp = read_to_end(&mut s);
+    assert!(resp.starts_with("HTTP/1.1 400"), "ambiguous framing got {:?}", resp);
+}
+
+// 缓存测试用源站：`/fresh` 可缓存 60 秒，`/etag` 每次都要验证（带 If-None-Match 时返回 304）；
+// body 带上当前是第几次请求
+fn start_cache_origin() -> (SocketAddr, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let log = seen.clone();
+    std::thread::spawn(move || {
+        for mut conn in listener.incoming().flatten() {
+            let head = read_head(&mut conn);
+            let n = {
+                let mut log = log.lock().unwrap();
+                log.push(head.clone());
+                log.len()
+            };
+            let resp = if head.starts_with("GET /fresh") {
+                let body = format!("fresh {}", n);
+                format!("HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+            } else if head.contains("If-None-Match: \"v1\"\r\n") {
+                String::from("HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n")
+            } else {
+                let body = format!("etag {}", n);
+                format!("HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nCache-Control: no-cache\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+            };
+            let _ = conn.write_all(resp.as_bytes());
+        }
+    });
+    (addr, seen)
+}
+
+#[test]
+fn responses_are_cached_and_revalidated() {
+    let (origin, seen) = start_cache_origin();
+    let proxy = Proxy::start(&["--cache-size", "1MiB"]);
+    let get = |path: &str| {
+        let mut s = connect(proxy.addr("http"));
+        write!(s, "GET http://{0}{1} HTTP/1.1\r\nHost: {0}\r\n\r\n", origin, path).unwrap();
+        read_to_end(&mut s)
+    };
+
+    let first = get("/fresh");
+    assert!(first.starts_with("HTTP/1.1 200") && first.ends_with("fresh 1"), "unexpected response: {:?}", first);
+    let second = get("/fresh");
+    assert!(second.starts_with("HTTP/1.1 200") && second.ends_with("fresh 1"), "not served from cache: {:?}", second);
+    assert!(second.contains("\r\nAge: "), "cached response lacks Age: {:?}", second);
+    assert_eq!(seen.lock().unwrap().len(), 1);
+
+    let first = get("/etag");
+    assert!(first.ends_with("etag 2"), "unexpected response: {:?}", first);
+    // 源站返回 304，客户端收到缓存中的完整应答
+    let second = get("/etag");
+    assert!(second.starts_with("HTTP/1.1 200") && second.ends_with("etag 2"), "revalidation failed: {:?}", second);
+    let seen = seen.lock().unwrap();
+    assert_eq!(seen.len(), 3);
+    assert!(seen[2].contains("If-None-Match: \"v1\"\r\n"), "origin saw {:?}", seen[2]);
+}
+
+// 前 `drops` 个连接读完请求头后不应答直接关闭，之后正常应答
+fn start_flaky_origin(drops: usize) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for (i, mut conn) in listener.incoming().flatten().enumerate() {
+            read_head(&mut conn);
+            if i < drops { continue; }
+            let _ = write!(conn, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", ORIGIN_BODY.len(), ORIGIN_BODY);
+        }
+    });
+    addr
+}
+
+#[test]
+fn idempotent_requests_are_retried_once() {
+    let get = |proxy: &Proxy, origin: SocketAddr, method: &str| {
+        let mut s = connect(proxy.addr("http"));
+        write!(s, "{1} http://{0}/ HTTP/1.1\r\nHost: {0}\r\nConnection: close\r\n\r\n", origin, method).unwrap();
+        read_to_end(&mut s)
+    };
+    let proxy = Proxy::start(&[]);
+    let resp = get(&proxy, start_flaky_origin(1), "GET");
+    assert!(resp.starts_with("HTTP/1.1 200") && resp.ends_with(ORIGIN_BODY), "retry failed: {:?}", resp);
+    // 只重试一次
+    let resp = get(&proxy, start_flaky_origin(2), "GET");
+    assert!(resp.starts_with("HTTP/1.1 502"), "expected 502: {:?}", resp);
+    // 非幂等请求不重试
+    let resp = get(&proxy, start_flaky_origin(1), "DELETE");
+    assert!(resp.is_empty(), "DELETE must not be retried: {:?}", resp);
+    // 无法连接的目标：502
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    assert!(get(&proxy, closed, "GET").starts_with("HTTP/1.1 502"));
+
+    let proxy = Proxy::start(&["--no-retry"]);
+    let resp = get(&proxy, start_flaky_origin(1), "GET");
+    assert!(resp.is_empty(), "retried despite --no-retry: {:?}", resp);
+}